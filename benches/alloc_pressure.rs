@@ -0,0 +1,111 @@
+//! Allocator pressure of the packet path.
+//!
+//! Counts heap allocations with a wrapping global allocator:
+//! - per packet buffer, fresh `Vec` against a [`BufferPool`],
+//! - per packet of a full loopback test (client and server threads).
+//!
+//! Run with `cargo bench --bench alloc_pressure`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use udpopt::{BufferPool, ClientBuilder, ClientCommand, ServerBuilder, ServerCommand};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const PACKETS: u64 = 100_000;
+
+fn allocations<F: FnOnce() -> u64>(f: F) -> (u64, u64) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let packets = f();
+    (ALLOCATIONS.load(Ordering::Relaxed) - before, packets)
+}
+
+fn report(name: &str, (allocs, packets): (u64, u64)) {
+    println!(
+        "{name:<24} {allocs:>8} allocations for {packets:>7} packets ({:.4} per packet)",
+        allocs as f64 / packets.max(1) as f64
+    );
+}
+
+fn main() {
+    report(
+        "fresh Vec per packet",
+        allocations(|| {
+            for i in 0..PACKETS {
+                let mut buf = vec![0u8; 1500];
+                buf[0] = i as u8;
+                black_box(&buf);
+            }
+            PACKETS
+        }),
+    );
+
+    let pool = BufferPool::default();
+    report(
+        "BufferPool per packet",
+        allocations(|| {
+            for i in 0..PACKETS {
+                let mut buf = pool.get(1500);
+                buf[0] = i as u8;
+                black_box(&buf);
+            }
+            PACKETS
+        }),
+    );
+
+    report("loopback test", allocations(loopback));
+}
+
+/// One second of traffic through a sync client and server, returns the packets sent
+fn loopback() -> u64 {
+    let mut server_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server_sock.local_addr().unwrap();
+    let mut client_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client_sock.connect(server_addr).unwrap();
+
+    let (server_tx, server_rx) = mpsc::channel();
+    let mut server = ServerBuilder::new(Duration::from_secs(1)).build(server_rx);
+    let server_thread = thread::spawn(move || server.run(&mut server_sock));
+
+    let (client_tx, client_rx) = mpsc::channel();
+    let mut client =
+        ClientBuilder::new(200_000_000.0, 1200, Duration::from_secs(1)).build(client_rx);
+    server_tx.send(ServerCommand::Start).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    client_tx.send(ClientCommand::Start).unwrap();
+
+    let report = client.run(&mut client_sock).unwrap();
+    server_thread.join().unwrap().unwrap();
+    report.packets_sent
+}
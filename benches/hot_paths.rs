@@ -0,0 +1,154 @@
+//! Criterion benchmarks of the packet path.
+//!
+//! - `header`: encode / decode of the packet header,
+//! - `process_packet`: server-side accounting of one datagram,
+//! - `pacer`: time per pacing slot, compare with the configured slot length,
+//! - `loopback`: packets per second through a sync client and server.
+//!
+//! Run with `cargo bench --features bench --bench hot_paths`.
+
+use std::{
+    hint::black_box,
+    net::UdpSocket,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use udpopt::{
+    ClientBuilder, ClientCommand, ServerBuilder, ServerCommand,
+    bench::{HEADER_LEN, Receiver, decode_header, encode_header, packet_interval, wait_for_slot},
+};
+
+const PAYLOAD: usize = 1200;
+
+fn header(c: &mut Criterion) {
+    let mut group = c.benchmark_group("header");
+    group.throughput(Throughput::Elements(1));
+    let mut buf = [0u8; HEADER_LEN];
+    let mut seq = 0u64;
+
+    group.bench_function("encode", |b| {
+        b.iter(|| {
+            seq += 1;
+            encode_header(black_box(&mut buf), seq, 1_700_000_000, 500_000);
+        })
+    });
+    group.bench_function("decode", |b| b.iter(|| decode_header(black_box(&buf))));
+    group.finish();
+}
+
+/// Datagrams `0..count`, every `swap_every`-th pair swapped and every
+/// `drop_every`-th one missing (0 disables either)
+fn stream(count: u64, swap_every: u64, drop_every: u64) -> Vec<Vec<u8>> {
+    let mut seqs: Vec<u64> = (0..count)
+        .filter(|s| drop_every == 0 || s % drop_every != drop_every - 1)
+        .collect();
+    if swap_every > 0 {
+        for i in (0..seqs.len() - 1).step_by(swap_every as usize) {
+            seqs.swap(i, i + 1);
+        }
+    }
+    seqs.into_iter()
+        .map(|seq| {
+            let mut packet = vec![0u8; PAYLOAD];
+            encode_header(&mut packet, seq, seq / 1000, (seq % 1000 * 1000) as u32);
+            packet
+        })
+        .collect()
+}
+
+fn process_packet(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_packet");
+    let cases = [
+        ("in_order", stream(10_000, 0, 0)),
+        ("reordered_1pct", stream(10_000, 100, 0)),
+        ("lossy_1pct", stream(10_000, 0, 100)),
+    ];
+    for (name, packets) in &cases {
+        group.throughput(Throughput::Elements(packets.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), packets, |b, packets| {
+            b.iter(|| {
+                let mut receiver = Receiver::default();
+                for (i, packet) in packets.iter().enumerate() {
+                    receiver.process(packet, Duration::from_micros(i as u64 * 1000));
+                }
+                receiver.interval(Duration::from_secs(1))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn pacer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pacer");
+    // one iteration is one slot, the reported time should match the slot length
+    for bitrate_bps in [10_000_000.0, 100_000_000.0] {
+        let ipp = packet_interval(PAYLOAD, bitrate_bps);
+        group.bench_with_input(
+            BenchmarkId::new("slot", format!("{}us", ipp.as_micros())),
+            &ipp,
+            |b, &ipp| {
+                b.iter_custom(|iters| {
+                    let start = Instant::now();
+                    for tick in 1..=iters {
+                        wait_for_slot(tick, ipp, start);
+                    }
+                    start.elapsed()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Runs a short loopback test, returns the packets the server received and the test time
+fn loopback_run(duration: Duration) -> (u64, Duration) {
+    let mut server_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server_sock.local_addr().unwrap();
+    let mut client_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client_sock.connect(server_addr).unwrap();
+
+    let (server_tx, server_rx) = mpsc::channel();
+    let mut server = ServerBuilder::new(Duration::from_secs(10)).build(server_rx);
+    let server_thread = thread::spawn(move || server.run(&mut server_sock));
+
+    let (client_tx, client_rx) = mpsc::channel();
+    // far above what loopback can carry, so pacing never waits
+    let mut client = ClientBuilder::new(100e9, PAYLOAD, duration).build(client_rx);
+    server_tx.send(ServerCommand::Start).unwrap();
+    thread::sleep(Duration::from_millis(20));
+    client_tx.send(ClientCommand::Start).unwrap();
+
+    let report = client.run(&mut client_sock).unwrap();
+    // the FIN may be dropped with the rest of the overflow
+    let _ = server_tx.send(ServerCommand::Stop);
+    let received = server_thread
+        .join()
+        .unwrap()
+        .unwrap()
+        .iter()
+        .map(|r| r.received)
+        .sum();
+    (received, report.duration)
+}
+
+fn loopback(c: &mut Criterion) {
+    let mut group = c.benchmark_group("loopback");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(1));
+    // one iteration is one received packet
+    group.bench_function("packets", |b| {
+        b.iter_custom(|iters| {
+            let (received, elapsed) = loopback_run(Duration::from_millis(200));
+            elapsed
+                .div_f64(received.max(1) as f64)
+                .mul_f64(iters as f64)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, header, process_packet, pacer, loopback);
+criterion_main!(benches);
@@ -0,0 +1,306 @@
+//! # Access control
+//!
+//! A public server should only measure the clients it expects. A client
+//! built with `ClientBuilder::access_token` carries its token right after
+//! the header of every data packet; a server built with
+//! `ServerBuilder::access_control` starts measuring only once a packet with
+//! an accepted token arrives, and from then on ignores the packets of every
+//! other source instead of counting them.
+//!
+//! The token is checked again when the client's address changes mid-test
+//! (NAT rebinding), so the test follows an authorized client only. Tokens
+//! travel in clear, combine them with the `auth` feature against spoofing.
+//!
+//! `ServerBuilder::allow_sources` filters on the source address instead: the
+//! datagrams from outside the given [`IpNet`] prefixes, e.g. scanners hitting
+//! the open port, are counted in `IntervalResult::foreign` and left out of
+//! the statistics.
+//!
+//! ```
+//! use std::sync::mpsc;
+//! use std::time::Duration;
+//! use udpopt::{ClientBuilder, ServerBuilder, access::AccessControl};
+//!
+//! let (_tx, rx) = mpsc::channel();
+//! let server = ServerBuilder::new(Duration::from_secs(1))
+//!     .access_control(AccessControl::allowlist(["lab-42", "lab-43"]))
+//!     .build(rx);
+//! let client = ClientBuilder::new(10e6, 1200, Duration::from_secs(10)).access_token("lab-42");
+//! ```
+
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use crate::{errors::UdpOptError, utils::udp_data::HEADER_SIZE};
+
+/// Longest token a packet can carry
+pub const MAX_TOKEN_LEN: usize = 255;
+
+/// Decides whether the token of a packet from `SocketAddr` may run a test.
+pub type TokenCallback = Box<dyn FnMut(&[u8], SocketAddr) -> bool + Send>;
+
+/// Which clients a server measures, see the [module docs](self).
+pub enum AccessControl {
+    /// The tokens accepted from any source.
+    Allowlist(Vec<Vec<u8>>),
+    /// Asked for every token to check, with the source it came from.
+    Callback(TokenCallback),
+}
+
+impl AccessControl {
+    /// Accepts any of `tokens`.
+    pub fn allowlist<T: Into<Vec<u8>>>(tokens: impl IntoIterator<Item = T>) -> Self {
+        Self::Allowlist(tokens.into_iter().map(Into::into).collect())
+    }
+
+    /// Accepts the tokens `callback` returns `true` for.
+    pub fn callback(callback: impl FnMut(&[u8], SocketAddr) -> bool + Send + 'static) -> Self {
+        Self::Callback(Box::new(callback))
+    }
+
+    /// Whether `packet`, from `from`, carries an accepted token
+    pub(crate) fn authorize(&mut self, packet: &[u8], from: SocketAddr) -> bool {
+        read_token(packet).is_some_and(|token| self.accepts(token, from))
+    }
+
+    /// Whether `token`, from `from`, is accepted
+    pub(crate) fn accepts(&mut self, token: &[u8], from: SocketAddr) -> bool {
+        match self {
+            Self::Allowlist(tokens) => tokens.iter().any(|t| t == token),
+            Self::Callback(callback) => callback(token, from),
+        }
+    }
+}
+
+impl std::fmt::Debug for AccessControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // the tokens are secrets, keep them out of the logs
+            Self::Allowlist(tokens) => write!(f, "Allowlist({} tokens)", tokens.len()),
+            Self::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+/// An IP prefix such as `10.0.0.0/8` or `2001:db8::/32`; a bare address is
+/// a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// The addresses sharing the first `prefix_len` bits of `addr`.
+    ///
+    /// # Errors
+    /// [`UdpOptError::InvalidPrefix`] if `prefix_len` is longer than the address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, UdpOptError> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max {
+            return Err(UdpOptError::InvalidPrefix(format!("{addr}/{prefix_len}")));
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    /// Address of the prefix.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Length of the prefix in bits.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Whether `ip` is in the prefix; IPv4-mapped IPv6 addresses, as a
+    /// dual-stack socket reports them, match IPv4 prefixes.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // bits that differ from the prefix, none may be left after the shift
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net) ^ u32::from(ip))
+                .checked_shr(32 - u32::from(self.prefix_len))
+                .is_none_or(|diff| diff == 0),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net) ^ u128::from(ip))
+                .checked_shr(128 - u32::from(self.prefix_len))
+                .is_none_or(|diff| diff == 0),
+            _ => false,
+        }
+    }
+}
+
+/// Whether `ip` is in one of the `sources` prefixes, any address when there
+/// are none, as `ServerBuilder::allow_sources` filters.
+pub fn source_allowed(sources: &[IpNet], ip: IpAddr) -> bool {
+    sources.is_empty() || sources.iter().any(|net| net.contains(ip))
+}
+
+impl FromStr for IpNet {
+    type Err = UdpOptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((addr, len)) => {
+                let len = len
+                    .parse()
+                    .map_err(|_| UdpOptError::InvalidPrefix(s.to_string()))?;
+                Self::new(addr.parse()?, len)
+            }
+            None => {
+                let addr: IpAddr = s.parse()?;
+                Self::new(addr, if addr.is_ipv4() { 32 } else { 128 })
+            }
+        }
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Room the token takes after the header: its length byte and itself
+pub(crate) fn token_space(token: &[u8]) -> usize {
+    1 + token.len()
+}
+
+/// Writes `token` after the header of `packet`, which has room for it
+pub(crate) fn write_token(packet: &mut [u8], token: &[u8]) {
+    packet[HEADER_SIZE] = token.len() as u8;
+    packet[HEADER_SIZE + 1..HEADER_SIZE + 1 + token.len()].copy_from_slice(token);
+}
+
+/// The token after the header of `packet`, `None` if it does not fit
+pub(crate) fn read_token(packet: &[u8]) -> Option<&[u8]> {
+    let len = usize::from(*packet.get(HEADER_SIZE)?);
+    packet.get(HEADER_SIZE + 1..HEADER_SIZE + 1 + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::udp_data::{FLAG_DATA, FLAG_FIN, UdpHeader};
+    use crate::{ClientBuilder, ClientCommand, MockSocket, ServerBuilder, ServerCommand};
+    use std::{sync::mpsc, time::Duration};
+
+    #[test]
+    fn test_tokens() {
+        let from: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+        let mut packet = [0u8; 64];
+        write_token(&mut packet, b"lab-42");
+        assert_eq!(read_token(&packet), Some(&b"lab-42"[..]));
+        assert_eq!(read_token(&packet[..HEADER_SIZE + 3]), None);
+
+        let mut allow = AccessControl::allowlist(["lab-41", "lab-42"]);
+        assert!(allow.authorize(&packet, from));
+        assert!(!AccessControl::allowlist(["lab-4"]).authorize(&packet, from));
+        let mut by_source = AccessControl::callback(move |_, src| src == from);
+        assert!(by_source.authorize(&packet, from));
+        assert!(!by_source.authorize(&packet, "192.0.2.9:4000".parse().unwrap()));
+        assert_eq!(format!("{allow:?}"), "Allowlist(2 tokens)");
+    }
+
+    #[test]
+    fn test_server_ignores_unauthorized_sources() {
+        let (tx, rx) = mpsc::channel();
+        let mut client = ClientBuilder::new(50e6, 200, Duration::from_secs(10))
+            .stop_after_packets(10)
+            .access_token("lab-42")
+            .build(rx);
+        let mut client_sock = MockSocket::new();
+        client_sock.connect("192.0.2.1:5201".parse().unwrap());
+        tx.send(ClientCommand::Start).unwrap();
+        client.run(&mut client_sock).unwrap();
+
+        let peer: SocketAddr = "192.0.2.2:40000".parse().unwrap();
+        let intruder: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+        let mut foreign = vec![0u8; 200];
+        UdpHeader::new(0, 0, 0, FLAG_DATA).write_header(&mut foreign);
+        let mut sock = MockSocket::new();
+        // neither starts the test nor counts once it runs
+        sock.push(&foreign, intruder);
+        for (packet, _) in client_sock.take_sent() {
+            sock.push(&packet, peer);
+            sock.push(&foreign, intruder);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(10))
+            .access_control(AccessControl::allowlist(["lab-42"]))
+            .build(rx);
+        tx.send(ServerCommand::Start).unwrap();
+        let results = server.run(&mut sock).unwrap();
+        // the first packet only starts the measurement, the FIN counts
+        assert_eq!(results[0].received, 10);
+        assert_eq!(results[0].duplicates, 0);
+        assert_eq!(results[0].lost, 0);
+    }
+
+    #[test]
+    fn test_ip_net() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let lab: IpNet = "192.0.2.0/24".parse().unwrap();
+        assert!(lab.contains(ip("192.0.2.200")));
+        assert!(lab.contains(ip("::ffff:192.0.2.7")));
+        assert!(!lab.contains(ip("192.0.3.1")));
+        assert!(!lab.contains(ip("2001:db8::1")));
+        assert_eq!(lab.to_string(), "192.0.2.0/24");
+
+        let host: IpNet = "2001:db8::1".parse().unwrap();
+        assert_eq!(host.prefix_len(), 128);
+        assert!(host.contains(ip("2001:db8::1")) && !host.contains(ip("2001:db8::2")));
+        let any: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("203.0.113.9")));
+
+        assert!(source_allowed(&[], ip("203.0.113.9")));
+        assert!(source_allowed(&[host, lab], ip("192.0.2.7")));
+        assert!(!source_allowed(&[host, lab], ip("203.0.113.9")));
+
+        assert!(matches!(
+            "10.0.0.0/33".parse::<IpNet>(),
+            Err(UdpOptError::InvalidPrefix(_))
+        ));
+        assert!(matches!(
+            "10.0.0.0/x".parse::<IpNet>(),
+            Err(UdpOptError::InvalidPrefix(_))
+        ));
+        assert!(matches!(
+            "lab/8".parse::<IpNet>(),
+            Err(UdpOptError::InvalidAddress(_))
+        ));
+    }
+
+    #[test]
+    fn test_server_counts_foreign_sources() {
+        let packet = |seq, flags| {
+            let mut packet = vec![0u8; 200];
+            UdpHeader::new(seq, 0, 0, flags).write_header(&mut packet);
+            packet
+        };
+        let peer: SocketAddr = "192.0.2.2:40000".parse().unwrap();
+        let scanner: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+        let mut sock = MockSocket::new();
+        // neither starts the test nor skews its loss
+        sock.push(&packet(1000, FLAG_DATA), scanner);
+        for seq in 0..5 {
+            sock.push(&packet(seq, FLAG_DATA), peer);
+            sock.push(&packet(seq + 50, FLAG_DATA), scanner);
+        }
+        sock.push(&packet(5, FLAG_FIN), peer);
+
+        let (tx, rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(10))
+            .allow_sources(vec!["192.0.2.0/24".parse().unwrap()])
+            .build(rx);
+        tx.send(ServerCommand::Start).unwrap();
+        let results = server.run(&mut sock).unwrap();
+        assert_eq!(results[0].received, 5);
+        assert_eq!(results[0].lost, 0);
+        assert_eq!(results[0].out_of_order, 0);
+        assert_eq!(results[0].foreign, 5);
+    }
+}
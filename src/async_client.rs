@@ -1,31 +1,51 @@
 //! Asynchronous UDP Client for sending high-performance test packets to a UDP server.
 //!
 //! This module provides [`AsyncUdpClient`] — an async client that can send UDP packets
-//! at a specified bitrate using `tokio`, with precise timing, start/stop control,
+//! at a specified bitrate on any async runtime (see [`crate::runtime`]), with precise timing, start/stop control,
 //! and FIN signaling at the end of transmission.
 
-use std::time::{Duration, Instant};
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
-use tokio::{net::UdpSocket, sync::mpsc::Receiver};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{self, Receiver},
+};
 
 use crate::{
+    builder::ClientConfig,
     errors::UdpOptError,
+    handle::{AsyncClientHandle, CONTROL_CAPACITY},
+    result::ClientReport,
+    runtime::{AsyncDatagram, yield_now},
     utils::{
-        net_utils::{ClientCommand, interval_per_packet},
-        random_utils::AsyncRandomToSend,
-        udp_data::{FLAG_DATA, FLAG_FIN, UdpHeader, now_micros},
+        net_utils::{
+            ClientCommand, ClientProgress, PauseOutcome, RetryPolicy, START_POLL, instant_at,
+            is_too_big_error, is_transient_io_error, is_transient_send_error, is_unreachable_error,
+            pacing_target,
+        },
+        pacer::{PacingPolicy, Wait},
+        results_exchange::recv_results_async,
+        send_data::SendData,
+        udp_data::{
+            FLAG_DATA, FLAG_FIN, FLAG_HEARTBEAT, FLAG_STATS, UdpHeader, new_session_id, now_micros,
+        },
     },
 };
 
+/// Sleep threshold of [`PacingPolicy::Hybrid`] when none is set, the runtime
+/// timer is not calibrated
+const ASYNC_SLEEP_THRESHOLD: Duration = Duration::from_micros(200);
+
 /// Asynchronous UDP client for high-throughput packet sending.
 #[derive(Debug)]
 pub struct AsyncUdpClient {
-    /// Target sending bitrate in bits per second.
-    bitrate_bps: f64,
-    /// Size of each UDP packet payload, including header.
-    payload_size: usize,
-    /// Maximum duration for the transmission test.
-    timeout: Duration,
+    /// Rate, payload size, duration and observers
+    config: ClientConfig,
+
     /// Async receiver for control commands (`Start`, `Stop`) from another thread.
     control_rx: Receiver<ClientCommand>,
 }
@@ -36,7 +56,7 @@ impl AsyncUdpClient {
     /// # Parameters
     /// - `bitrate_bps`: Desired sending bitrate in bits per second.
     /// - `payload_size`: Number of bytes in each packet (typically 512–1500 bytes).
-    /// - `timeout`: Total duration to keep sending packets.
+    /// - `timeout`: Total duration to keep sending packets, `None` to send until `Stop`.
     /// - `control_rx`: Async channel to receive [`ClientCommand`] control signals.
     ///
     /// # Returns
@@ -44,103 +64,563 @@ impl AsyncUdpClient {
     pub async fn new(
         bitrate_bps: f64,
         payload_size: usize,
-        timeout: Duration,
+        timeout: impl Into<Option<Duration>>,
         control_rx: Receiver<ClientCommand>,
     ) -> Self {
-        Self {
-            bitrate_bps,
-            payload_size,
-            timeout,
+        Self::from_config(
+            ClientConfig::new(bitrate_bps, payload_size, timeout),
             control_rx,
-        }
+        )
+    }
+
+    pub(crate) fn from_config(config: ClientConfig, control_rx: Receiver<ClientCommand>) -> Self {
+        Self { config, control_rx }
     }
 
     /// Runs the UDP async client, sending packets to the specified destination.
     ///
-    /// - Waits for a `Start` command from the control channel before sending.
+    /// - Waits for a `Start` command from the control channel before sending,
+    ///   or with `StartAt` until the given wall-clock time.
     /// - Sends packets according to the configured bitrate and payload size.
-    /// - Stops after `timeout` duration or when the control channel sends `Stop`.
+    /// - Stops after `timeout` duration or when the control channel sends `Stop`;
+    ///   without a `timeout` it only stops on `Stop`.
+    /// - Also stops once the `stop_after_bytes` / `stop_after_packets` limit is reached.
+    /// - Also stops on Ctrl-C / SIGTERM if built with `stop_on_signal` (feature `signal`).
+    /// - `Pause` halts sending until `Resume`; the paused time does not count towards `timeout`.
     /// - Sends a FIN packet at the end to notify the server.
     ///
     /// # Parameters
-    /// - `sock`: A bound async [`UdpSocket`] that will be used to send packets.
+    /// - `sock`: A bound and connected async UDP socket, see [`crate::runtime`].
     ///
-    /// Returns:
+    /// Returns a [`ClientReport`] with the transmit statistics, or:
     /// - [`UdpOptError::SendFailed`] if sending fails.
-    /// - [`UdpOptError::FailToGetRandom`] if payload randomization fails.
+    /// - [`UdpOptError::PayloadFailed`] if the payload source fails.
+    /// - [`UdpOptError::SchedulingFailed`] if the core pinning or realtime priority cannot be applied.
     /// - [`UdpOptError::ChannelClosed`] if control channel disconnects before start.
     /// - [`UdpOptError::UnexpectedCommand`] if an unexpected command is received.
+    pub async fn run<S: AsyncDatagram>(
+        &mut self,
+        sock: &mut S,
+    ) -> Result<ClientReport, UdpOptError> {
+        #[cfg(feature = "tracing")]
+        let span = run_span!("udpopt_client", self.config.test_id);
+        let run = self.run_inner(sock);
+        #[cfg(feature = "tracing")]
+        let run = tracing::Instrument::instrument(run, span);
+        run.await
+    }
+
+    /// A tokio socket bound to the address and source ports given with
+    /// [`ClientBuilder::bind`](crate::ClientBuilder::bind) and
+    /// [`ClientBuilder::source_ports`](crate::ClientBuilder::source_ports),
+    /// connected to `server`, for [`run`](Self::run).
+    ///
+    /// # Errors
+    /// [`UdpOptError::BindFailed`] or [`UdpOptError::ConnectFailed`].
+    ///
+    /// # Panics
+    /// Outside of a tokio runtime.
+    pub fn connect(&self, server: SocketAddr) -> Result<UdpSocket, UdpOptError> {
+        let sock = self.config.connect(server)?;
+        sock.set_nonblocking(true)
+            .map_err(UdpOptError::NonBlockingFailed)?;
+        UdpSocket::from_std(sock).map_err(|source| UdpOptError::ConnectFailed {
+            peer: server,
+            source,
+        })
+    }
 
-    pub async fn run(&mut self, sock: &mut UdpSocket) -> Result<(), UdpOptError> {
-        let ipp = interval_per_packet(self.payload_size, self.bitrate_bps);
+    /// Runs the client on a tokio task, controlled through the returned
+    /// [`AsyncClientHandle`] instead of the channel it was built with;
+    /// [`ClientBuilder::spawn_async`](crate::ClientBuilder::spawn_async) needs no channel at all.
+    ///
+    /// # Panics
+    /// Outside of a tokio runtime.
+    pub fn spawn(mut self, mut sock: UdpSocket) -> AsyncClientHandle {
+        let (tx, rx) = mpsc::channel(CONTROL_CAPACITY);
+        self.control_rx = rx;
+        AsyncClientHandle::new(tx, tokio::spawn(async move { self.run(&mut sock).await }))
+    }
+
+    async fn run_inner<S: AsyncDatagram>(
+        &mut self,
+        sock: &mut S,
+    ) -> Result<ClientReport, UdpOptError> {
+        if let Ok(peer) = sock.peer_addr() {
+            record_peer!(peer);
+        }
+        // in probing mode a slot holds a whole train
+        let (mut ipp, mut per_slot) = self.config.pacing();
+        let train_len = self.config.train_len();
+        let session = new_session_id();
 
         let mut seq = 0;
-        let mut buf = vec![0u8; self.payload_size];
-        let mut random = AsyncRandomToSend::new()
-            .await
-            .map_err(|e| UdpOptError::FailToGetRandom(e))?;
+        let mut buf = self.config.buffers.get(self.config.max_packet_len());
+        self.config.watch_signals()?;
+        self.config
+            .tuning
+            .apply()
+            .map_err(UdpOptError::SchedulingFailed)?;
+        if let Some(device) = &self.config.device {
+            sock.bind_device(device)
+                .map_err(UdpOptError::DeviceFailed)?;
+        }
+        if let Some(tos) = self.config.tos() {
+            sock.set_tos(tos).map_err(UdpOptError::DscpFailed)?;
+        } else if self.config.ecn {
+            sock.set_ect0().map_err(UdpOptError::EcnFailed)?;
+        }
+        if let Some(on) = self.config.dont_fragment {
+            sock.set_dont_fragment(on)
+                .map_err(UdpOptError::FragmentationFailed)?;
+        }
 
+        // a command that arrived while waiting for the start or the slot
+        let mut pending: Option<ClientCommand> = None;
         // wait for the start udp packet to start the test and set the buf lenght
-        match self.control_rx.recv().await {
-            Some(ClientCommand::Stop) => return Err(UdpOptError::UnexpectedCommand),
-            Some(ClientCommand::Start) => {}
-            None => return Err(UdpOptError::ChannelClosed),
+        loop {
+            match self.control_rx.recv().await {
+                Some(ClientCommand::Start) => break,
+                Some(ClientCommand::StartAt(at)) => {
+                    event!(info, "waiting for the scheduled start");
+                    pending = self.wait_scheduled_start::<S>(instant_at(at)).await?;
+                    break;
+                }
+                // nothing sent yet
+                Some(ClientCommand::Status(reply)) => {
+                    let _ = reply.send(ClientProgress::default());
+                }
+                Some(_) => return Err(UdpOptError::UnexpectedCommand),
+                None => return Err(UdpOptError::ChannelClosed),
+            }
         }
+        event!(
+            info,
+            bitrate_bps = self.config.bitrate_bps(),
+            payload_size = self.config.payload_size,
+            "sending"
+        );
 
-        let start = Instant::now();
+        let mut unlimited = self.config.unlimited();
+        let mut start = Instant::now();
+        let mut next_progress = start + self.config.progress_interval;
+        let mut next_stats = self.config.stats_interval.map(|every| start + every);
+        let mut interval_start = start;
+        let mut stats = SendData::new().with_pacing(ipp, per_slot);
+        if let (Ok(mtu), Ok(peer)) = (sock.path_mtu(), sock.peer_addr()) {
+            stats = stats.with_path_mtu(mtu, peer.ip().to_canonical().is_ipv6());
+        }
+        let mut faults = self.config.faults.map(|f| f.start());
+        // pacing slot, unlike `seq` it also advances when a send is skipped
+        let mut tick: u64 = 0;
+        // the schedule restarts from this slot and instant on a rate change
+        let (mut pace_tick, mut pace_start) = (tick, start);
+        let mut control_open = true;
 
         loop {
-            if start.elapsed() >= self.timeout {
+            if self.config.timeout.is_some_and(|t| start.elapsed() >= t)
+                || self
+                    .config
+                    .limit_reached(stats.packets_sent(), stats.bytes_sent())
+                || self.config.shutdown_requested()
+            {
                 break;
             }
 
-            random
-                .fill(&mut buf)
-                .await
-                .map_err(|e| UdpOptError::FailToGetRandom(e))?;
+            // Check control messages
+            let command = match pending.take() {
+                Some(command) => Some(command),
+                None => self.control_rx.try_recv().ok(),
+            };
+            let rate = self.config.rate;
+            match command {
+                Some(ClientCommand::Stop) => {
+                    event!(info, "stop command received");
+                    break;
+                }
+                Some(ClientCommand::Pause) => {
+                    match self.wait_resume(stats.progress(start.elapsed())).await? {
+                        PauseOutcome::Resumed(paused) => {
+                            // shift the timeline so pacing and timeout ignore the pause
+                            start += paused;
+                            pace_start += paused;
+                            next_progress += paused;
+                            if let Some(at) = next_stats.as_mut() {
+                                *at += paused;
+                            }
+                            interval_start += paused;
+                        }
+                        PauseOutcome::Stopped => break,
+                    }
+                }
+                Some(ClientCommand::SetRate(rate)) => self.config.set_rate(rate),
+                // the caller may have given up waiting
+                Some(ClientCommand::Status(reply)) => {
+                    let _ = reply.send(stats.progress(start.elapsed()));
+                }
+                Some(_) => return Err(UdpOptError::UnexpectedCommand),
+                None => {}
+            }
+            if self.config.rate != rate {
+                (ipp, per_slot) = self.config.pacing();
+                unlimited = self.config.unlimited();
+                (pace_tick, pace_start) = (tick, Instant::now());
+                stats.set_pacing(ipp, per_slot, start.elapsed());
+            }
+
+            let packet = &mut buf[..self.config.packet_len(seq)];
+            self.config
+                .payload
+                .fill(packet)
+                .map_err(UdpOptError::PayloadFailed)?;
 
             let (sec, usec) = now_micros();
-            let mut header = UdpHeader::new(seq, sec, usec, FLAG_DATA);
-            header.write_header(&mut buf);
+            let mut header = UdpHeader::new(seq, sec, usec, FLAG_DATA)
+                .with_train(train_len)
+                .with_session(session)
+                .with_sender(seq + 1, self.config.header_rate_bps());
+            header.write_header(packet);
+            self.config.seal(packet);
 
-            sock.send(&buf)
-                .await
-                .map_err(|e| UdpOptError::SendFailed(e))?;
+            let late = if unlimited {
+                Duration::ZERO
+            } else {
+                let slot = (tick - pace_tick) / per_slot;
+                Instant::now().saturating_duration_since(pacing_target(slot, ipp, pace_start))
+            };
+            // a withheld packet counts as sent, as far as the server knows it left
+            let sent = if faults
+                .as_mut()
+                .is_none_or(|f| f.admit(packet, Instant::now()))
+            {
+                send_retrying(sock, packet, self.config.send_retry, &mut stats).await
+            } else {
+                Ok(packet.len())
+            };
+            match sent {
+                Ok(len) => {
+                    stats.record_sent(len, late);
+                    seq += 1;
+                }
+                Err(e) if is_transient_send_error(&e) => stats.record_failure(),
+                // longer than the path MTU with the don't-fragment bit set
+                Err(e) if self.config.dont_fragment == Some(true) && is_too_big_error(&e) => {
+                    stats.record_too_big();
+                    if let Ok(mtu) = sock.path_mtu() {
+                        stats.set_path_mtu(mtu);
+                    }
+                }
+                // out of retries
+                Err(e) if self.config.send_retry.is_some() && is_transient_io_error(&e) => {
+                    stats.record_failure();
+                }
+                // skipped while the grace lasts
+                Err(e)
+                    if is_unreachable_error(&e)
+                        && self.config.unreachable_grace.is_some_and(|grace| {
+                            stats.record_unreachable(start.elapsed(), grace)
+                        }) => {}
+                Err(e) => {
+                    return Err(UdpOptError::send_failed(
+                        sock.peer_addr().ok(),
+                        packet.len(),
+                    )(e));
+                }
+            }
+            tick += 1;
+            if let Some(f) = faults.as_mut() {
+                f.send_due_async(sock, false).await?;
+            }
 
-            seq += 1;
-            time_to_next_target_async(seq, ipp, start).await;
+            let now = Instant::now();
+            if now >= next_progress {
+                stats.close_interval(now - interval_start);
+                interval_start = now;
+                self.config.emit_progress(&stats.progress(start.elapsed()));
+                next_progress += self.config.progress_interval;
+            }
+            if let (Some(at), Some(every)) = (next_stats, self.config.stats_interval)
+                && now >= at
+            {
+                let packet = &mut buf[..self.config.packet_len(seq)];
+                self.config.write_control(packet, FLAG_STATS, seq, session);
+                send_control(sock, packet, self.config.unreachable_grace.is_some()).await?;
+                next_stats = Some(at + every);
+            }
+
+            if !unlimited {
+                // a command cuts the wait short, the next turn handles it
+                let target = pacing_target((tick - pace_tick) / per_slot, ipp, pace_start);
+                // a gap longer than the heartbeat interval is bridged with heartbeats
+                let mut beat = self
+                    .config
+                    .heartbeat
+                    .map(|every| (Instant::now() + every, every));
+                loop {
+                    let until = match beat {
+                        Some((at, _)) if at < target => at,
+                        _ => target,
+                    };
+                    let wait = wait_until_async::<S>(self.config.pacing_policy, until);
+                    tokio::select! {
+                        biased;
+                        command = self.control_rx.recv(), if control_open => match command {
+                            Some(command) => {
+                                pending = Some(command);
+                                break;
+                            }
+                            None => {
+                                control_open = false;
+                                continue;
+                            }
+                        },
+                        _ = wait => {}
+                    }
+                    let Some((at, every)) = beat.as_mut().filter(|_| until < target) else {
+                        break;
+                    };
+                    let packet = &mut buf[..self.config.heartbeat_len()];
+                    self.config
+                        .write_control(packet, FLAG_HEARTBEAT, seq, session);
+                    send_control(sock, packet, self.config.unreachable_grace.is_some()).await?;
+                    *at += *every;
+                }
+            }
+        }
+
+        if let Some(f) = faults.as_mut() {
+            f.send_due_async(sock, true).await?;
         }
 
         let (sec, usec) = now_micros();
-        let mut fin = UdpHeader::new(seq, sec, usec, FLAG_FIN);
-        fin.write_header(&mut buf);
+        // the FIN carries the final count of packets sent
+        let rate = self.config.header_rate_bps();
+        let fin = || {
+            UdpHeader::new(seq, sec, usec, FLAG_FIN)
+                .with_session(session)
+                .with_sender(seq + 1, rate)
+        };
+        fin().write_header(&mut buf);
+        self.config.seal(&mut buf);
+
+        let mut len = buf.len();
+        let mut sent = sock.send(&buf).await;
+        // refused as too long with the don't-fragment bit, a short one fits
+        if sent.as_ref().is_err_and(is_too_big_error) {
+            len = self.config.heartbeat_len();
+            fin().write_header(&mut buf[..len]);
+            self.config.seal(&mut buf[..len]);
+            sent = sock.send(&buf[..len]).await;
+        }
+        sent.map_err(UdpOptError::send_failed(sock.peer_addr().ok(), len))?;
+        event!(info, seq, "FIN sent");
+        self.config.emit_progress(&stats.progress(start.elapsed()));
 
-        sock.send(&buf)
-            .await
-            .map_err(|e| UdpOptError::SendFailed(e))?;
-        println!("Client done. Sent {} packets (+FIN)", seq);
+        let mut report = stats.into_report(start.elapsed(), interval_start.elapsed());
+        report.faults = faults.map(|f| f.stats()).unwrap_or_default();
+        report.session = session;
+        if let Some(fragmentation) = report.fragmentation.as_mut() {
+            fragmentation.path_mtu = sock.path_mtu().unwrap_or(fragmentation.path_mtu);
+            fragmentation.dont_fragment = self.config.dont_fragment;
+        }
+        if let Some(timeout) = self.config.remote_results {
+            (report.remote, report.public_endpoint) = recv_results_async(sock, timeout).await?;
+            if report.remote.is_none() {
+                event!(warn, "no results from the server");
+            }
+        }
+        event!(
+            info,
+            packets_sent = report.packets_sent,
+            send_failures = report.send_failures,
+            "test finished"
+        );
+        Ok(report)
+    }
+
+    /// Waits for `target`, the time given by `StartAt`, answering `Status`
+    /// and taking `SetRate`; returns the `Stop` that cut it short, if any.
+    async fn wait_scheduled_start<S: AsyncDatagram>(
+        &mut self,
+        target: Instant,
+    ) -> Result<Option<ClientCommand>, UdpOptError> {
+        let mut control_open = true;
+        loop {
+            let remaining = target.saturating_duration_since(Instant::now());
+            // a signal ends the send loop before the first packet
+            if self.config.shutdown_requested() {
+                return Ok(None);
+            }
+            if remaining <= START_POLL {
+                // the last slice is precise
+                wait_until_async::<S>(PacingPolicy::default(), target).await;
+                return Ok(None);
+            }
+            let command = tokio::select! {
+                biased;
+                command = self.control_rx.recv(), if control_open => command,
+                _ = S::sleep((remaining - START_POLL).min(START_POLL)) => continue,
+            };
+            match command {
+                Some(ClientCommand::Stop) => return Ok(Some(ClientCommand::Stop)),
+                Some(ClientCommand::SetRate(rate)) => self.config.set_rate(rate),
+                Some(ClientCommand::Status(reply)) => {
+                    let _ = reply.send(ClientProgress::default());
+                }
+                Some(_) => return Err(UdpOptError::UnexpectedCommand),
+                None => control_open = false,
+            }
+        }
+    }
 
-        Ok(())
+    /// Waits until `Resume` or `Stop` arrives on the control channel,
+    /// answering `Status` with the `progress` frozen by the pause.
+    async fn wait_resume(&mut self, progress: ClientProgress) -> Result<PauseOutcome, UdpOptError> {
+        let paused_at = Instant::now();
+        loop {
+            match self.control_rx.recv().await {
+                Some(ClientCommand::Resume) => {
+                    return Ok(PauseOutcome::Resumed(paused_at.elapsed()));
+                }
+                Some(ClientCommand::Stop) => return Ok(PauseOutcome::Stopped),
+                Some(ClientCommand::Pause) => {}
+                Some(ClientCommand::SetRate(rate)) => self.config.set_rate(rate),
+                Some(ClientCommand::Status(reply)) => {
+                    let _ = reply.send(progress);
+                }
+                Some(ClientCommand::Start | ClientCommand::StartAt(_)) => {
+                    return Err(UdpOptError::UnexpectedCommand);
+                }
+                None => return Err(UdpOptError::ChannelClosed),
+            }
+        }
     }
 }
 
 //helper function
 
-/// Asynchronous version of the precise send timing function.
-async fn time_to_next_target_async(seq: u64, ipp: Duration, start: Instant) {
-    let next_target = start + Duration::from_secs_f64(seq as f64 * ipp.as_secs_f64());
+/// Sends `packet`, retrying a transient failure as `retry` says
+async fn send_retrying<S: AsyncDatagram>(
+    sock: &S,
+    packet: &[u8],
+    retry: Option<RetryPolicy>,
+    stats: &mut SendData,
+) -> io::Result<usize> {
+    let mut sent = sock.send(packet).await;
+    let Some(retry) = retry else {
+        return sent;
+    };
+    for attempt in 0..retry.max_retries {
+        match &sent {
+            Err(e) if retry.retries(e) => {}
+            _ => break,
+        }
+        stats.record_retry();
+        S::sleep(retry.backoff(attempt)).await;
+        sent = sock.send(packet).await;
+    }
+    sent
+}
+
+/// Sends a STATS packet or a heartbeat, which lost or not is not worth
+/// failing the test for, nor a refusal by an unreachable peer with
+/// `outlast_unreachable`
+async fn send_control<S: AsyncDatagram>(
+    sock: &S,
+    packet: &[u8],
+    outlast_unreachable: bool,
+) -> Result<(), UdpOptError> {
+    match sock.send(packet).await {
+        Err(e) if outlast_unreachable && is_unreachable_error(&e) => Ok(()),
+        Err(e) if !is_transient_send_error(&e) => Err(UdpOptError::send_failed(
+            sock.peer_addr().ok(),
+            packet.len(),
+        )(e)),
+        _ => Ok(()),
+    }
+}
+
+/// Waits until `target` as `policy` says.
+async fn wait_until_async<S: AsyncDatagram>(policy: PacingPolicy, target: Instant) {
     loop {
         let now = Instant::now();
-        if now >= next_target {
+        if now >= target {
             break;
         }
 
-        let remaining = next_target - now;
+        match policy.next_wait(target - now, ASYNC_SLEEP_THRESHOLD) {
+            Wait::Sleep(duration) => S::sleep(duration).await,
+            Wait::Yield => yield_now().await,
+            Wait::Spin => std::hint::spin_loop(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::ClientBuilder;
+    use tokio::{net::UdpSocket, sync::mpsc::channel};
+
+    #[tokio::test]
+    async fn test_stop_cuts_the_pacing_wait_short() {
+        let sink = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sock.connect(sink.local_addr().unwrap()).await.unwrap();
+
+        // one packet every 10 s
+        let (tx, rx) = channel(4);
+        let mut client = ClientBuilder::new(400.0, 500, Duration::from_secs(60)).build_async(rx);
+        tx.send(ClientCommand::Start).await.unwrap();
+        let stop = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            tx.send(ClientCommand::Stop).await.unwrap();
+        });
 
-        if remaining > Duration::from_micros(200) {
-            tokio::time::sleep(remaining - Duration::from_micros(100)).await;
-        } else {
-            tokio::task::yield_now().await;
+        let started = Instant::now();
+        let report = client.run(&mut sock).await.unwrap();
+        stop.await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(report.packets_sent, 1);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_fill_the_pacing_wait() {
+        let sink = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sock.connect(sink.local_addr().unwrap()).await.unwrap();
+
+        // one packet every 10 s
+        let (tx, rx) = channel(4);
+        let mut client = ClientBuilder::new(400.0, 500, Duration::from_secs(60))
+            .heartbeat(Duration::from_millis(50))
+            .build_async(rx);
+        tx.send(ClientCommand::Start).await.unwrap();
+        let stop = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(230)).await;
+            tx.send(ClientCommand::Stop).await.unwrap();
+        });
+        let report = client.run(&mut sock).await.unwrap();
+        stop.await.unwrap();
+        assert_eq!(report.packets_sent, 1);
+
+        let mut buf = [0u8; 1500];
+        let mut flags = Vec::new();
+        loop {
+            let len = sink.recv(&mut buf).await.unwrap();
+            let header = UdpHeader::parse(&buf[..len]).unwrap();
+            flags.push(header.flags);
+            if header.flags == FLAG_HEARTBEAT {
+                // no payload, and the count of packets sent so far
+                assert_eq!(len, crate::utils::udp_data::HEADER_SIZE);
+                assert_eq!(header.sent, 1);
+            }
+            if header.flags == FLAG_FIN {
+                break;
+            }
         }
+        assert_eq!(flags[0], FLAG_DATA);
+        let beats = flags.iter().filter(|&&f| f == FLAG_HEARTBEAT).count();
+        assert!((3..=5).contains(&beats), "{flags:?}");
     }
 }
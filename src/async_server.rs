@@ -4,32 +4,69 @@
 //! that can receive UDP packets, calculate bitrate periodically, and store
 //! interval-based test results.
 
-use std::time::Duration;
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
 use tokio::{
     net::UdpSocket,
-    sync::mpsc::{Receiver, error::TryRecvError},
-    time::Instant,
+    sync::mpsc::{self, Receiver},
 };
 
 use crate::{
-    errors::UdpOptError,
+    builder::ServerConfig,
+    errors::{RunError, UdpOptError},
+    handle::{AsyncServerHandle, CONTROL_CAPACITY},
+    histogram::Histogram,
+    result::{BandwidthEstimate, LossStats, ReorderStats, SizeStats, TestResult},
+    runtime::{AsyncDatagram, timeout},
+    socket::RecvMeta,
+    trace::TraceWriter,
     utils::{
-        net_utils::{IntervalResult, ServerCommand},
-        udp_data::{FLAG_FIN, HEADER_SIZE, UdpData, UdpHeader},
-        ui::print_result,
+        interval_clock::IntervalClock,
+        net_utils::{
+            IntervalResult, PauseOutcome, START_POLL, ServerCommand, ServerStatus, instant_at,
+        },
+        results_exchange::send_results_async,
+        train::TrainTracker,
+        udp_data::{
+            FLAG_DATA, FLAG_FIN, FLAG_HEARTBEAT, FLAG_STATS, InterArrival, RECV_BUF_LEN, UdpData,
+            UdpHeader, now_micros,
+        },
     },
 };
 
+/// Shortest wait for a packet, so a boundary just reached does not spin the loop
+const MIN_TICK: Duration = Duration::from_millis(1);
+
 /// Asynchronous UDP Server for high-throughput packet receiving.
 #[derive(Debug)]
 pub struct AsyncUdpServer {
-    ///Time between each result to save
-    interval: Duration,
+    /// Interval length, observers and trace output
+    config: ServerConfig,
     /// Collecting the interval results
     udp_result: Vec<IntervalResult>,
     /// Async receiver for control commands (`Start`, `Stop`) from another thread.
     control_rx: Receiver<ServerCommand>,
+    /// Per-packet latency histogram of the last run
+    latency: Histogram,
+    /// Delay variations between consecutive packets of the last run
+    ipdv: Histogram,
+    /// Reordering statistics of the last run
+    reorder: ReorderStats,
+    sizes: SizeStats,
+    /// Loss bursts of the last run
+    loss_pattern: LossStats,
+    /// Inter-arrival times of the last run
+    interarrival: Histogram,
+    /// Available bandwidth from the packet trains of the last run
+    bandwidth: Option<BandwidthEstimate>,
+    /// Packets the client of the last run says it sent
+    client_sent: Option<u64>,
+    /// Client and session id of the last run, its late packets cannot
+    /// start the next one
+    last_session: Option<(SocketAddr, u32)>,
 }
 
 impl AsyncUdpServer {
@@ -38,19 +75,55 @@ impl AsyncUdpServer {
     /// - `interval`: The duration for each result interval.
     /// - `control_rx`: A channel receiver to control start/stop commands.
     pub async fn new(interval: Duration, control_rx: Receiver<ServerCommand>) -> Self {
+        Self::from_config(ServerConfig::new(interval), control_rx)
+    }
+
+    pub(crate) fn from_config(config: ServerConfig, control_rx: Receiver<ServerCommand>) -> Self {
         Self {
-            interval,
+            config,
             udp_result: Vec::with_capacity(100),
             control_rx,
+            latency: Histogram::new(),
+            ipdv: Histogram::new(),
+            reorder: ReorderStats::default(),
+            sizes: SizeStats::default(),
+            loss_pattern: LossStats::default(),
+            interarrival: Histogram::new(),
+            bandwidth: None,
+            client_sent: None,
+            last_session: None,
         }
     }
+
+    /// Logs every received packet (seq, timestamps, size, transit) to `writer`.
+    ///
+    /// See [`crate::trace`] for the bundled CSV and binary writers.
+    pub fn set_trace_writer<T: TraceWriter + 'static>(&mut self, writer: T) {
+        self.config.trace = Some(Box::new(writer));
+    }
     /// Runs the async UDP server loop.
     ///
     /// - Waits for a `Start` command on the control channel before starting.
+    ///   `StartAt` starts at a wall-clock time instead, the datagrams that
+    ///   arrived before it are discarded.
+    /// - `Pause` freezes the interval timers until `Resume`.
+    ///
     /// The loop terminates when:
     /// - A `Stop` command is received.
     /// - A packet with the `FLAG_FIN` flag is received.
     /// - The control channel disconnects.
+    /// - The token given to `ServerBuilder::cancel_token` is cancelled.
+    /// - Ctrl-C / SIGTERM arrives, if built with `stop_on_signal` (feature `signal`).
+    /// - No packet arrived for the idle timeout, the client is taken as gone.
+    ///
+    /// The socket need not be connected, e.g. bound to `0.0.0.0`: the test
+    /// locks onto the client of its first packet, see [`peer`](Self::peer),
+    /// `ServerBuilder::peer` and `ServerBuilder::lock_peer`.
+    ///
+    /// The socket, the control channel and the token are awaited together, so
+    /// `Stop` and the cancellation take effect at once, even without traffic.
+    /// A link quiet for less than that only produces zero-traffic intervals.
+    /// The last, partial interval is always included in the results.
     ///
     ///
     /// # Arguments
@@ -61,76 +134,714 @@ impl AsyncUdpServer {
     ///
     /// # Errors
     ///
-    /// Returns [`UdpOptError::RecvFailed`] if a UDP receive error occurs.
-    /// Returns [`UdpOptError::UnexpectedCommand`] if a UDP receive error occurs.
-    /// Returns [`UdpOptError::ChannelClosed`] if a UDP receive error occurs.
+    /// A [`RunError`] carrying the intervals completed before the failure and:
+    /// - [`UdpOptError::RecvFailed`] if a UDP receive error occurs.
+    /// - [`UdpOptError::UnexpectedCommand`] if a command other than the ones above arrives.
+    /// - [`UdpOptError::ChannelClosed`] if the control channel disconnects during a pause.
+    pub async fn run<S: AsyncDatagram>(
+        &mut self,
+        sock: &mut S,
+    ) -> Result<Vec<IntervalResult>, RunError> {
+        #[cfg(feature = "tracing")]
+        let span = run_span!("udpopt_server", self.config.test_id);
+        let run = self.run_inner(sock);
+        #[cfg(feature = "tracing")]
+        let run = tracing::Instrument::instrument(run, span);
+        match run.await {
+            Ok(intervals) => Ok(intervals),
+            Err(error) => Err(RunError {
+                error,
+                intervals: self.udp_result.clone(),
+            }),
+        }
+    }
 
-    pub async fn run(&mut self, sock: &mut UdpSocket) -> Result<Vec<IntervalResult>, UdpOptError> {
-        println!("server start");
+    /// Runs the server on a tokio task, controlled through the returned
+    /// [`AsyncServerHandle`] instead of the channel it was built with;
+    /// [`ServerBuilder::spawn_async`](crate::ServerBuilder::spawn_async) needs no channel at all.
+    ///
+    /// # Panics
+    /// Outside of a tokio runtime.
+    pub fn spawn(mut self, mut sock: UdpSocket) -> AsyncServerHandle {
+        let (tx, rx) = mpsc::channel(CONTROL_CAPACITY);
+        self.control_rx = rx;
+        AsyncServerHandle::new(tx, tokio::spawn(async move { self.run(&mut sock).await }))
+    }
 
-        let mut udp_data = UdpData::new();
-        let mut buf = vec![0u8; 2048];
+    async fn run_inner<S: AsyncDatagram>(
+        &mut self,
+        sock: &mut S,
+    ) -> Result<Vec<IntervalResult>, UdpOptError> {
+        let mut udp_data = UdpData::with_jitter(self.config.jitter)
+            .with_controller((self.config.congestion)())
+            .with_nic(self.config.nic_sampler()?);
+        let mut trains = TrainTracker::new();
+        let mut interarrival = InterArrival::new(self.config.interarrival_sampling);
+        let mut buf = self.config.buffers.get(RECV_BUF_LEN);
 
         // wait for the start udp packet to start the test and set the buf lenght
-        match self.control_rx.recv().await {
-            Some(ServerCommand::Stop) => return Err(UdpOptError::UnexpectedCommand),
-            Some(ServerCommand::Start) => {}
-            None => return Err(UdpOptError::ChannelClosed),
+        let start_at = loop {
+            let command = tokio::select! {
+                biased;
+                _ = self.config.cancelled() => {
+                    event!(info, "cancelled before the start");
+                    return Ok(Vec::new());
+                }
+                command = self.control_rx.recv() => command,
+            };
+            match command {
+                Some(ServerCommand::Start) => break None,
+                Some(ServerCommand::StartAt(at)) => break Some(at),
+                // nothing received yet
+                Some(ServerCommand::Status(reply)) => {
+                    let _ = reply.send(ServerStatus::default());
+                }
+                Some(_) => return Err(UdpOptError::UnexpectedCommand),
+                None => return Err(UdpOptError::ChannelClosed),
+            }
+        };
+
+        self.config.watch_signals()?;
+        self.config
+            .tuning
+            .apply()
+            .map_err(UdpOptError::SchedulingFailed)?;
+        if let Some(device) = &self.config.device {
+            sock.bind_device(device)
+                .map_err(UdpOptError::DeviceFailed)?;
+        }
+        if self.config.ecn {
+            sock.enable_ecn().map_err(UdpOptError::EcnFailed)?;
         }
+        if self.config.socket_drops {
+            sock.enable_drop_count()
+                .map_err(UdpOptError::DropCountFailed)?;
+        }
+        if let Some(at) = start_at {
+            event!(info, "waiting for the scheduled start");
+            if !self.wait_scheduled_start::<S>(instant_at(at)).await? {
+                event!(info, "stopped before the scheduled start");
+                return Ok(Vec::new());
+            }
+            // drop the datagrams that arrived early
+            while let Some(res) = timeout::<S, _>(Duration::ZERO, sock.recv_from(&mut buf)).await {
+                res.map_err(UdpOptError::RecvFailed)?;
+            }
+        }
+        event!(info, "test started, waiting for the first packet");
 
         // start measuring after reciving the first packt
-        let _ = sock
-            .recv(&mut buf)
-            .await
-            .map_err(|e| UdpOptError::RecvFailed(e))?;
+        let Some((mut peer, session)) = self.wait_first_packet(sock, &mut buf).await? else {
+            event!(info, "stopped before any packet arrived");
+            return Ok(Vec::new());
+        };
+        self.last_session = Some((peer, session));
+        record_peer!(peer);
+        event!(info, session, "first packet received");
+        let mut fin_received = false;
+        // arrivals are timed from here, the interval clock restarts
+        let epoch = Instant::now();
 
         let mut calc_instat = Instant::now();
         let calc_interval = Duration::from_millis(200);
-        let mut start = Instant::now();
+        let mut clock = IntervalClock::new(
+            udp_data.start_clock(),
+            self.config.interval,
+            self.config.alignment,
+        );
+        // the warm-up is received like the rest of the test and thrown away
+        let mut warming_up = !self.config.omit.is_zero();
+        let mut last_packet = Instant::now();
 
         loop {
-            // Check control messages
-            match self.control_rx.try_recv() {
-                Ok(ServerCommand::Stop) => break,
-                Ok(ServerCommand::Start) => return Err(UdpOptError::UnexpectedCommand),
-                Err(TryRecvError::Empty) => {}
-                Err(TryRecvError::Disconnected) => return Err(UdpOptError::ChannelClosed),
-            }
-            let len = sock
-                .recv(&mut buf)
-                .await
-                .map_err(|e| UdpOptError::RecvFailed(e))?;
-
-            if len < HEADER_SIZE {
-                continue;
+            if self.config.shutdown_requested() {
+                event!(info, "shutdown requested, stopping");
+                break;
+            }
+
+            // before the receive, the ignored datagrams must not hold the boundaries back
+            if warming_up && clock.elapsed() >= self.config.omit {
+                event!(info, omitted = ?clock.elapsed(), "warm-up over");
+                udp_data.discard_statistics();
+                trains = TrainTracker::new();
+                interarrival = InterArrival::new(self.config.interarrival_sampling);
+                warming_up = false;
+                clock = IntervalClock::new(
+                    udp_data.start_clock(),
+                    self.config.interval,
+                    self.config.alignment,
+                );
+                calc_instat = clock.start();
+            }
+
+            // every boundary passed closes an interval, a gap in the traffic
+            // shows up as intervals with nothing received
+            while !warming_up && clock.is_due() {
+                let res = udp_data.get_interval_result(clock.advance());
+                udp_data.set_interval_start(clock.start());
+                self.config.emit_interval_async(&res).await;
+                self.config.sink_interval(peer, &res);
+                self.udp_result.push(res);
             }
 
-            let header = UdpHeader::read_header(&mut buf);
+            // wait for a packet, a control message or the cancellation,
+            // whichever comes first, waking up for the next boundary (or the
+            // end of the warm-up) on a quiet link
+            let next_tick = if warming_up {
+                self.config.omit.saturating_sub(clock.elapsed())
+            } else {
+                clock.remaining()
+            };
+            let recv_timeout = self.config.recv_timeout.min(next_tick.max(MIN_TICK));
+            let received = tokio::select! {
+                biased;
+                _ = self.config.cancelled() => {
+                    event!(info, "cancelled, stopping");
+                    break;
+                }
+                command = self.control_rx.recv() => {
+                    match command {
+                        Some(ServerCommand::Stop) => {
+                            event!(info, "stop command received");
+                            break;
+                        }
+                        Some(ServerCommand::Pause) => {
+                            let status = ServerStatus::new(
+                                epoch.elapsed(),
+                                Some(peer),
+                                &self.udp_result,
+                                udp_data.current(),
+                            );
+                            match self.wait_resume(status).await? {
+                                PauseOutcome::Resumed(paused) => {
+                                    // freeze the interval timers for the paused time
+                                    clock.shift(paused);
+                                    // nor does the idle timeout
+                                    last_packet += paused;
+                                    calc_instat += paused;
+                                    interarrival.restart();
+                                }
+                                PauseOutcome::Stopped => break,
+                            }
+                        }
+                        // the caller may have given up waiting
+                        Some(ServerCommand::Status(reply)) => {
+                            let _ = reply.send(ServerStatus::new(
+                                epoch.elapsed(),
+                                Some(peer),
+                                &self.udp_result,
+                                udp_data.current(),
+                            ));
+                        }
+                        Some(_) => return Err(UdpOptError::UnexpectedCommand),
+                        None => return Err(UdpOptError::ChannelClosed),
+                    }
+                    continue;
+                }
+                received = timeout::<S, _>(recv_timeout, recv_datagram(sock, &mut buf, self.config.ancillary())) => received,
+            };
+            match received {
+                Some(res) => {
+                    let (len, from, meta) = res.map_err(UdpOptError::RecvFailed)?;
+                    if self.config.socket_drops {
+                        udp_data.record_socket_drops(meta.dropped.unwrap_or(0));
+                    }
+                    if !self.config.allowed_source(from) {
+                        event!(trace, %from, "ignoring a source outside the allowed prefixes");
+                        udp_data.record_foreign();
+                        continue;
+                    }
+                    // stray datagrams from other applications must not skew the statistics
+                    let header = match UdpHeader::parse_received(&buf, len) {
+                        Ok(header) => header,
+                        Err(_e) => {
+                            event!(trace, len, reason = %_e, "ignoring datagram");
+                            udp_data.record_malformed();
+                            continue;
+                        }
+                    };
+                    if !self.config.authentic(&buf[..len]) {
+                        event!(debug, %from, seq = header.seq, "authentication failed");
+                        udp_data.record_auth_failure();
+                        continue;
+                    }
+                    // e.g. the late packets of a previous run
+                    if header.session != session {
+                        event!(trace, %from, session = header.session, "ignoring another session");
+                        continue;
+                    }
+                    // only a genuine packet can move the test to another peer
+                    if from != peer {
+                        if self.config.lock_peer {
+                            event!(trace, %from, "ignoring another source, the peer is locked");
+                            continue;
+                        }
+                        if !self.config.accepts_peer(from)
+                            || !self.config.authorized(&buf[..len], from)
+                        {
+                            event!(trace, %from, "ignoring unauthorized source");
+                            continue;
+                        }
+                        event!(info, new_peer = %from, "peer changed");
+                    }
+                    peer = from;
+                    self.last_session = Some((peer, session));
+                    last_packet = Instant::now();
+
+                    match header.flags {
+                        // a report of the client, not a data packet
+                        FLAG_STATS => {
+                            event!(trace, sent = header.sent, "stats received");
+                            udp_data.record_sender_stats(&header);
+                            continue;
+                        }
+                        // only tells the client is still there
+                        FLAG_HEARTBEAT => {
+                            event!(trace, "heartbeat received");
+                            continue;
+                        }
+                        _ => {}
+                    }
+
+                    if let Some(trace) = self.config.trace.as_mut() {
+                        let (sec, usec) = now_micros();
+                        trace
+                            .write_record(&header.to_record(len, sec, usec))
+                            .map_err(UdpOptError::TraceFailed)?;
+                    }
 
-            udp_data.process_packet(len, &header, start.elapsed());
+                    udp_data.process_packet(&buf[..len], &header, epoch.elapsed());
+                    udp_data.record_ecn(meta.ecn);
+                    trains.record(&header, len, last_packet);
+                    interarrival.record(last_packet);
 
-            let time_to_calc_bitrate = calc_instat.elapsed();
-            if time_to_calc_bitrate >= calc_interval {
-                udp_data.calc_bitrate(time_to_calc_bitrate);
-                calc_instat = Instant::now();
+                    let time_to_calc_bitrate = calc_instat.elapsed();
+                    if time_to_calc_bitrate >= calc_interval {
+                        udp_data.calc_bitrate(time_to_calc_bitrate);
+                        calc_instat = Instant::now();
+                    }
+
+                    if header.flags == FLAG_FIN {
+                        event!(info, seq = header.seq, "FIN received");
+                        udp_data.finish();
+                        fin_received = true;
+                        break;
+                    }
+                }
+                // a quiet link is an idle interval, the control channel is
+                // checked again at the top of the loop
+                None => {
+                    if self
+                        .config
+                        .idle_timeout
+                        .is_some_and(|idle| last_packet.elapsed() >= idle)
+                    {
+                        event!(warn, idle = ?last_packet.elapsed(), "no traffic, client gone");
+                        break;
+                    }
+                }
             }
+        }
+        // the last interval ends with the FIN, not with the linger
+        let elapsed = clock.elapsed();
+        if fin_received && let Some(linger) = self.config.fin_linger {
+            let _tail = self
+                .linger(
+                    sock,
+                    &mut buf,
+                    linger,
+                    (peer, session),
+                    &mut udp_data,
+                    epoch,
+                )
+                .await?;
+            event!(debug, received = _tail, "linger after the FIN over");
+        }
 
-            if header.flags == FLAG_FIN {
-                break;
+        // flush the last partial interval, or the only one if the interval
+        // time is bigger than the total time the client sent
+        if !warming_up && (self.udp_result.is_empty() || udp_data.has_pending()) {
+            let res = udp_data.get_interval_result(elapsed);
+            self.config.emit_interval_async(&res).await;
+            self.config.sink_interval(peer, &res);
+            self.udp_result.push(res);
+        }
+        self.latency = udp_data.latency().clone();
+        self.ipdv = udp_data.ipdv().clone();
+        self.reorder = *udp_data.reorder();
+        self.sizes = *udp_data.sizes();
+        self.loss_pattern = udp_data.loss_pattern();
+        self.bandwidth = trains.estimate();
+        self.client_sent = udp_data.client_sent();
+        self.interarrival = interarrival.histogram().clone();
+        self.config.flush_outputs_async::<S>().await?;
+
+        // the client only waits for the results after its FIN
+        if self.config.send_results && fin_received {
+            let summary = TestResult::from_intervals(&self.udp_result)
+                .with_latency(&self.latency)
+                .with_ipdv(&self.ipdv)
+                .with_reorder(&self.reorder)
+                .with_sizes(&self.sizes)
+                .with_loss_pattern(&self.loss_pattern)
+                .with_interarrival(&self.interarrival)
+                .with_packets_sent(self.client_sent.unwrap_or(0));
+            if !send_results_async(sock, peer, &summary).await? {
+                event!(warn, "client never acknowledged the results");
             }
-            if start.elapsed() >= self.interval {
-                let res = udp_data.get_interval_result(start.elapsed());
-                print_result(&res);
-                self.udp_result.push(res);
-                start = Instant::now();
+        }
+        event!(info, intervals = self.udp_result.len(), "test finished");
+        Ok(self.udp_result.clone())
+    }
+
+    /// Waits until the first packet arrives.
+    ///
+    /// Returns the sender and its session id, or `None` if `Stop`, a
+    /// cancellation or a shutdown came before any packet arrived.
+    async fn wait_first_packet<S: AsyncDatagram>(
+        &mut self,
+        sock: &S,
+        buf: &mut [u8],
+    ) -> Result<Option<(SocketAddr, u32)>, UdpOptError> {
+        // poll so that a signal is noticed without traffic
+        let poll = if self.config.stops_on_signal() {
+            Duration::from_millis(200)
+        } else {
+            Duration::MAX
+        };
+        loop {
+            if self.config.shutdown_requested() {
+                return Ok(None);
+            }
+            let received = tokio::select! {
+                biased;
+                _ = self.config.cancelled() => return Ok(None),
+                command = self.control_rx.recv() => {
+                    match command {
+                        Some(ServerCommand::Stop) => return Ok(None),
+                        // nothing to freeze before the measurement starts
+                        Some(ServerCommand::Pause | ServerCommand::Resume) => {}
+                        Some(ServerCommand::Status(reply)) => {
+                            let _ = reply.send(ServerStatus::default());
+                        }
+                        Some(_) => return Err(UdpOptError::UnexpectedCommand),
+                        None => return Err(UdpOptError::ChannelClosed),
+                    }
+                    continue;
+                }
+                received = timeout::<S, _>(poll, sock.recv_from(buf)) => received,
+            };
+            if let Some(res) = received {
+                let (len, from) = res.map_err(UdpOptError::RecvFailed)?;
+                // skip datagrams that are not from a udpopt client
+                let Ok(header) = UdpHeader::parse(&buf[..len]) else {
+                    continue;
+                };
+                // the results ACKs of the last run carry no session
+                let test_packet = matches!(header.flags, FLAG_DATA | FLAG_FIN);
+                let stale =
+                    header.session != 0 && self.last_session == Some((from, header.session));
+                if test_packet
+                    && !stale
+                    && self.config.authentic(&buf[..len])
+                    && self.config.allowed_source(from)
+                    && self.config.accepts_peer(from)
+                    && self.config.authorized(&buf[..len], from)
+                {
+                    return Ok(Some((from, header.session)));
+                }
             }
         }
-        println!("test finished");
-        // if the interval time bigger than the total time the client send
-        if self.udp_result.len() == 0 {
-            self.udp_result
-                .push(udp_data.get_interval_result(start.elapsed()));
+    }
+
+    /// Receives the data packets of `session` from `peer` for `linger` after
+    /// the FIN, the tail of the test still in flight. `Stop` cuts it short.
+    ///
+    /// Returns how many arrived.
+    async fn linger<S: AsyncDatagram>(
+        &mut self,
+        sock: &S,
+        buf: &mut [u8],
+        linger: Duration,
+        (peer, session): (SocketAddr, u32),
+        udp_data: &mut UdpData,
+        epoch: Instant,
+    ) -> Result<u64, UdpOptError> {
+        let deadline = Instant::now() + linger;
+        let mut tail = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.config.shutdown_requested() {
+                return Ok(tail);
+            }
+            let received = tokio::select! {
+                biased;
+                _ = self.config.cancelled() => return Ok(tail),
+                command = self.control_rx.recv() => {
+                    match command {
+                        Some(ServerCommand::Stop) | None => return Ok(tail),
+                        Some(ServerCommand::Status(reply)) => {
+                            let _ = reply.send(ServerStatus::new(
+                                epoch.elapsed(),
+                                Some(peer),
+                                &self.udp_result,
+                                udp_data.current(),
+                            ));
+                        }
+                        Some(_) => {}
+                    }
+                    continue;
+                }
+                received = timeout::<S, _>(remaining, recv_datagram(sock, buf, self.config.ancillary())) => received,
+            };
+            let Some(res) = received else {
+                return Ok(tail);
+            };
+            let (len, from, meta) = res.map_err(UdpOptError::RecvFailed)?;
+            if self.config.socket_drops {
+                udp_data.record_socket_drops(meta.dropped.unwrap_or(0));
+            }
+            let Ok(header) = UdpHeader::parse_received(buf, len) else {
+                continue;
+            };
+            if from == peer
+                && header.session == session
+                && header.flags == FLAG_DATA
+                && self.config.authentic(&buf[..len])
+            {
+                if let Some(trace) = self.config.trace.as_mut() {
+                    let (sec, usec) = now_micros();
+                    trace
+                        .write_record(&header.to_record(len, sec, usec))
+                        .map_err(UdpOptError::TraceFailed)?;
+                }
+                udp_data.process_packet(&buf[..len], &header, epoch.elapsed());
+                udp_data.record_ecn(meta.ecn);
+                tail += 1;
+            }
+        }
+    }
+
+    /// Waits for `target`, the time given by `StartAt`, answering `Status`;
+    /// `false` if `Stop`, a cancellation or a signal came first.
+    async fn wait_scheduled_start<S: AsyncDatagram>(
+        &mut self,
+        target: Instant,
+    ) -> Result<bool, UdpOptError> {
+        let mut control_open = true;
+        loop {
+            let remaining = target.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(true);
+            }
+            if self.config.shutdown_requested() {
+                return Ok(false);
+            }
+            let command = tokio::select! {
+                biased;
+                _ = self.config.cancelled() => return Ok(false),
+                command = self.control_rx.recv(), if control_open => command,
+                // signals are only polled
+                _ = S::sleep(remaining.min(START_POLL)) => continue,
+            };
+            match command {
+                Some(ServerCommand::Stop) => return Ok(false),
+                Some(ServerCommand::Status(reply)) => {
+                    let _ = reply.send(ServerStatus::default());
+                }
+                Some(_) => return Err(UdpOptError::UnexpectedCommand),
+                None => control_open = false,
+            }
+        }
+    }
+
+    /// Waits until `Resume` or `Stop` arrives on the control channel,
+    /// answering `Status` with the `status` frozen by the pause.
+    async fn wait_resume(&mut self, status: ServerStatus) -> Result<PauseOutcome, UdpOptError> {
+        let paused_at = Instant::now();
+        loop {
+            let command = tokio::select! {
+                biased;
+                _ = self.config.cancelled() => return Ok(PauseOutcome::Stopped),
+                command = self.control_rx.recv() => command,
+            };
+            match command {
+                Some(ServerCommand::Resume) => {
+                    return Ok(PauseOutcome::Resumed(paused_at.elapsed()));
+                }
+                Some(ServerCommand::Stop) => return Ok(PauseOutcome::Stopped),
+                Some(ServerCommand::Pause) => {}
+                Some(ServerCommand::Status(reply)) => {
+                    let _ = reply.send(status);
+                }
+                Some(ServerCommand::Start | ServerCommand::StartAt(_)) => {
+                    return Err(UdpOptError::UnexpectedCommand);
+                }
+                None => return Err(UdpOptError::ChannelClosed),
+            }
+        }
+    }
+
+    /// Returns the client of the last [`AsyncUdpServer::run`], its last address if it
+    /// moved during the test; `None` before the first packet.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.last_session.map(|(peer, _)| peer)
+    }
+
+    /// Returns the per-packet latency histogram recorded during the last [`AsyncUdpServer::run`].
+    ///
+    /// Values are transit times in microseconds relative to the first received packet.
+    pub fn latency_histogram(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// Returns the delay variations between consecutive packets (IPDV, RFC 3393)
+    /// recorded during the last [`AsyncUdpServer::run`].
+    ///
+    /// Values are absolute differences of transit times in microseconds.
+    pub fn ipdv_histogram(&self) -> &Histogram {
+        &self.ipdv
+    }
+
+    /// Returns the reordering statistics recorded during the last [`AsyncUdpServer::run`].
+    pub fn reorder_stats(&self) -> &ReorderStats {
+        &self.reorder
+    }
+
+    /// Returns the packets and bytes per length bucket received during the last [`AsyncUdpServer::run`].
+    pub fn size_stats(&self) -> &SizeStats {
+        &self.sizes
+    }
+
+    /// Returns the loss bursts recorded during the last [`AsyncUdpServer::run`].
+    pub fn loss_pattern(&self) -> &LossStats {
+        &self.loss_pattern
+    }
+
+    /// Returns the inter-arrival times (µs) recorded during the last [`AsyncUdpServer::run`].
+    pub fn interarrival_histogram(&self) -> &Histogram {
+        &self.interarrival
+    }
+
+    /// Returns the available-bandwidth estimate of the last [`AsyncUdpServer::run`],
+    /// `None` unless the client sent packet trains (`ClientBuilder::packet_trains`).
+    pub fn bandwidth_estimate(&self) -> Option<BandwidthEstimate> {
+        self.bandwidth
+    }
+
+    /// Returns how many packets the client of the last [`AsyncUdpServer::run`] says it
+    /// sent, its FIN included, from the first packet measured (the one that
+    /// starts the test is not) or the end of the warm-up; `None` if its
+    /// packets do not carry the count.
+    ///
+    /// The final count the FIN carries, also `None` when the FIN was lost or
+    /// the test stopped before it: the count of the last packet received
+    /// would miss the packets lost after it.
+    pub fn client_sent(&self) -> Option<u64> {
+        self.client_sent
+    }
+}
+
+/// Receives a datagram, with its ECN codepoint and the drop count of the
+/// socket if `ancillary` is set
+async fn recv_datagram<S: AsyncDatagram>(
+    sock: &S,
+    buf: &mut [u8],
+    ancillary: bool,
+) -> std::io::Result<(usize, SocketAddr, RecvMeta)> {
+    if ancillary {
+        sock.recv_from_meta(buf).await
+    } else {
+        let (len, from) = sock.recv_from(buf).await?;
+        Ok((len, from, RecvMeta::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerBuilder;
+    use tokio::{net::UdpSocket, sync::mpsc::channel};
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn test_stop_without_traffic() {
+        let mut sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (tx, rx) = channel(4);
+        let mut server = ServerBuilder::new(Duration::from_secs(1)).build_async(rx);
+
+        tx.send(ServerCommand::Start).await.unwrap();
+        let stop = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            tx.send(ServerCommand::Stop).await.unwrap();
+        };
+        let started = Instant::now();
+        let (results, ()) = tokio::join!(server.run(&mut sock), stop);
+        assert!(results.unwrap().is_empty());
+        // the receive timeout is 2 seconds
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_token_stops_a_quiet_test() {
+        let mut sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(sock.local_addr().unwrap()).await.unwrap();
+        let token = CancellationToken::new();
+        let (tx, rx) = channel(4);
+        let mut server = ServerBuilder::new(Duration::from_secs(1))
+            .cancel_token(token.clone())
+            .build_async(rx);
+
+        tx.send(ServerCommand::Start).await.unwrap();
+        let traffic = async {
+            let mut packet = [0u8; 100];
+            UdpHeader::new(0, 0, 0, crate::utils::udp_data::FLAG_DATA).write_header(&mut packet);
+            client.send(&packet).await.unwrap();
+            // then nothing, until the cancellation
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            token.cancel();
+        };
+        let started = Instant::now();
+        let (results, ()) = tokio::join!(server.run(&mut sock), traffic);
+        assert_eq!(results.unwrap().len(), 1);
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_traffic_gap_gives_empty_intervals() {
+        use crate::utils::udp_data::{FLAG_DATA, FLAG_FIN};
+
+        let mut sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(sock.local_addr().unwrap()).await.unwrap();
+        let (tx, rx) = channel(4);
+        // the default receive timeout (2 s) must not hide the gap
+        let mut server = ServerBuilder::new(Duration::from_millis(100)).build_async(rx);
+
+        tx.send(ServerCommand::Start).await.unwrap();
+        let traffic = async {
+            let send = |seq, flags| {
+                let mut packet = [0u8; 100];
+                UdpHeader::new(seq, 0, 0, flags).write_header(&mut packet);
+                packet
+            };
+            client.send(&send(0, FLAG_DATA)).await.unwrap();
+            client.send(&send(1, FLAG_DATA)).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(450)).await;
+            client.send(&send(2, FLAG_DATA)).await.unwrap();
+            client.send(&send(3, FLAG_FIN)).await.unwrap();
+        };
+        let (results, ()) = tokio::join!(server.run(&mut sock), traffic);
+        let results = results.unwrap();
+
+        assert_eq!(results.iter().map(|r| r.received).sum::<u64>(), 3);
+        let empty = results.iter().filter(|r| r.received == 0).count();
+        assert!(empty >= 3, "{empty} empty intervals of {}", results.len());
+        assert!(results.iter().all(|r| r.lost == 0));
+        // no hole in the timeline
+        for pair in results.windows(2) {
+            assert_eq!(pair[1].offset, pair[0].offset + pair[0].time);
         }
-        Ok(self.udp_result.clone())
     }
 }
@@ -0,0 +1,159 @@
+//! # Packet authentication (feature `auth`)
+//!
+//! With an [`AuthKey`] shared by both ends, the client ends every data
+//! packet and the FIN with a tag, a BLAKE3 keyed hash of the rest of the
+//! packet, header included. The server drops the packets whose tag does not
+//! match, so spoofed traffic can neither start nor end a test nor skew its
+//! statistics, and counts them in [`IntervalResult::auth_failures`] instead
+//! of as received.
+//!
+//! The tag takes the last [`TAG_LEN`] bytes of the payload, the packet length
+//! does not change. The payload is not encrypted.
+//!
+//! ```
+//! use std::sync::mpsc;
+//! use std::time::Duration;
+//! use udpopt::{ClientBuilder, ServerBuilder, auth::AuthKey};
+//!
+//! let key = AuthKey::from_passphrase("correct horse battery staple");
+//! let (_tx, rx) = mpsc::channel();
+//! let server = ServerBuilder::new(Duration::from_secs(1)).auth(key.clone()).build(rx);
+//! let client = ClientBuilder::new(10e6, 1200, Duration::from_secs(10)).auth(key);
+//! ```
+//!
+//! [`IntervalResult::auth_failures`]: crate::IntervalResult::auth_failures
+
+use crate::utils::udp_data::HEADER_SIZE;
+
+/// Length of the tag at the end of every packet
+pub const TAG_LEN: usize = 16;
+
+/// Shortest packet that holds a header and a tag
+pub(crate) const MIN_PACKET_LEN: usize = HEADER_SIZE + TAG_LEN;
+
+/// Separates the keys derived here from other uses of the same passphrase
+const KEY_CONTEXT: &str = "udpopt 2025 packet authentication key";
+
+/// Pre-shared key the packets are authenticated with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthKey {
+    key: [u8; 32],
+}
+
+impl AuthKey {
+    /// A key of 32 random bytes.
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+
+    /// Derives the key from a passphrase, for keys typed on both ends.
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        Self::new(blake3::derive_key(KEY_CONTEXT, passphrase.as_bytes()))
+    }
+
+    /// Writes the tag over the last [`TAG_LEN`] bytes of `packet`.
+    ///
+    /// `packet` is at least [`MIN_PACKET_LEN`] long.
+    pub(crate) fn sign(&self, packet: &mut [u8]) {
+        let (body, tag) = packet.split_at_mut(packet.len() - TAG_LEN);
+        tag.copy_from_slice(&self.tag(body));
+    }
+
+    /// Whether `packet` ends with the right tag.
+    pub(crate) fn verify(&self, packet: &[u8]) -> bool {
+        packet.len() >= MIN_PACKET_LEN && self.verify_message(packet)
+    }
+
+    /// Whether `message`, a control message rather than a packet with a
+    /// header, ends with the right tag.
+    pub(crate) fn verify_message(&self, message: &[u8]) -> bool {
+        let Some(split) = message.len().checked_sub(TAG_LEN) else {
+            return false;
+        };
+        let (body, tag) = message.split_at(split);
+        // constant time, the mismatch position must not leak
+        self.tag(body)
+            .iter()
+            .zip(tag)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+
+    fn tag(&self, body: &[u8]) -> [u8; TAG_LEN] {
+        let hash = blake3::keyed_hash(&self.key, body);
+        let mut tag = [0u8; TAG_LEN];
+        tag.copy_from_slice(&hash.as_bytes()[..TAG_LEN]);
+        tag
+    }
+}
+
+impl std::fmt::Debug for AuthKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuthKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::udp_data::{FLAG_DATA, UdpHeader};
+    use crate::{ClientBuilder, ClientCommand, MockSocket, ServerBuilder, ServerCommand};
+    use std::{net::SocketAddr, sync::mpsc, time::Duration};
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = AuthKey::from_passphrase("secret");
+        let mut packet = [7u8; 100];
+        key.sign(&mut packet);
+        assert!(key.verify(&packet));
+
+        assert!(!AuthKey::from_passphrase("other").verify(&packet));
+        let mut tampered = packet;
+        tampered[10] ^= 1;
+        assert!(!key.verify(&tampered));
+        assert!(!key.verify(&packet[..MIN_PACKET_LEN - 1]));
+        assert_eq!(format!("{key:?}"), "AuthKey(..)");
+    }
+
+    #[test]
+    fn test_server_rejects_spoofed_packets() {
+        let key = AuthKey::from_passphrase("secret");
+        let (tx, rx) = mpsc::channel();
+        let mut client = ClientBuilder::new(50e6, 200, Duration::from_secs(10))
+            .stop_after_packets(10)
+            .auth(key.clone())
+            .build(rx);
+        let mut client_sock = MockSocket::new();
+        client_sock.connect("192.0.2.1:5201".parse().unwrap());
+        tx.send(ClientCommand::Start).unwrap();
+        client.run(&mut client_sock).unwrap();
+        let sent = client_sock.take_sent();
+
+        let peer: SocketAddr = "192.0.2.2:40000".parse().unwrap();
+        let spoofer: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+        let mut forged = vec![0u8; 200];
+        UdpHeader::new(3, 0, 0, FLAG_DATA).write_header(&mut forged);
+        let mut sock = MockSocket::new();
+        // cannot start the test either
+        sock.push(&forged, spoofer);
+        for (i, (packet, _)) in sent.iter().enumerate() {
+            sock.push(packet, peer);
+            if i % 3 == 1 {
+                sock.push(&forged, spoofer);
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(10))
+            .auth(key)
+            .build(rx);
+        tx.send(ServerCommand::Start).unwrap();
+        let results = server.run(&mut sock).unwrap();
+        // the first genuine packet only starts the measurement, the FIN counts
+        assert_eq!(results[0].received, 10);
+        // the forgery after the FIN is never read
+        assert_eq!(results[0].auth_failures, 3);
+        assert_eq!(results[0].lost, 0);
+        assert_eq!(results[0].duplicates, 0);
+    }
+}
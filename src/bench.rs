@@ -0,0 +1,62 @@
+//! Entry points into the packet path for the `benches/` suite (feature `bench`).
+//!
+//! Not part of the public API: the wrappers only exist so the criterion
+//! benchmarks can time crate-private code, and may change at any time.
+
+use std::time::{Duration, Instant};
+
+use crate::utils::{
+    net_utils::{IntervalResult, Rate, interval_per_packet},
+    pacer::{Pacer, PacingPolicy},
+    udp_data::{FLAG_DATA, HEADER_SIZE, UdpData, UdpHeader},
+};
+
+/// Size of the packet header in bytes.
+pub const HEADER_LEN: usize = HEADER_SIZE;
+
+/// Writes the header of data packet `seq` into `buf`.
+pub fn encode_header(buf: &mut [u8], seq: u64, sec: u64, usec: u32) {
+    UdpHeader::new(seq, sec, usec, FLAG_DATA).write_header(buf);
+}
+
+/// Reads the sequence number back from a header, `None` for foreign datagrams.
+pub fn decode_header(buf: &[u8]) -> Option<u64> {
+    UdpHeader::parse(buf).ok().map(|h| h.seq)
+}
+
+/// The server's per-packet statistics.
+#[derive(Debug)]
+pub struct Receiver(UdpData);
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self(UdpData::new())
+    }
+}
+
+impl Receiver {
+    /// Parses and accounts `packet` like the server receive loop.
+    pub fn process(&mut self, packet: &[u8], now_since_start: Duration) {
+        if let Ok(header) = UdpHeader::parse(packet) {
+            self.0.process_packet(packet, &header, now_since_start);
+        }
+    }
+
+    /// Closes the current interval.
+    pub fn interval(&mut self, time: Duration) -> IntervalResult {
+        self.0.get_interval_result(time)
+    }
+}
+
+/// Time between packets of `payload_size` bytes at `bitrate_bps`.
+pub fn packet_interval(payload_size: usize, bitrate_bps: f64) -> Duration {
+    interval_per_packet(payload_size as f64, Rate::Bps(bitrate_bps))
+}
+
+/// Waits for pacing slot `tick` like the sync client send loop.
+pub fn wait_for_slot(tick: u64, ipp: Duration, start: Instant) {
+    thread_local! {
+        static PACER: Pacer = Pacer::new(PacingPolicy::default());
+    }
+    PACER.with(|pacer| pacer.wait_until(crate::utils::net_utils::pacing_target(tick, ipp, start)));
+}
@@ -0,0 +1,128 @@
+//! Reusable packet buffers.
+//!
+//! This module provides [`BufferPool`] — a small free list of byte buffers the
+//! clients and servers take their send / receive buffer from. A run borrows one
+//! [`PooledBuffer`] for its whole duration and every packet is read into or
+//! written from that same memory, so the hot path never touches the allocator.
+//! Sharing one pool between the runs of a test campaign (see
+//! [`crate::ServerBuilder::buffer_pool`]) also reuses the buffers across runs.
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+/// Number of idle buffers a pool keeps by default.
+const DEFAULT_MAX_IDLE: usize = 16;
+
+/// Thread-safe pool of byte buffers, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    free: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_idle: usize,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_IDLE)
+    }
+}
+
+impl BufferPool {
+    /// Creates an empty pool that keeps at most `max_idle` returned buffers.
+    pub fn new(max_idle: usize) -> Self {
+        Self {
+            free: Arc::new(Mutex::new(Vec::with_capacity(max_idle))),
+            max_idle,
+        }
+    }
+
+    /// Takes a zeroed buffer of `len` bytes, reusing an idle one when possible.
+    ///
+    /// The buffer goes back to the pool when the [`PooledBuffer`] is dropped.
+    pub fn get(&self, len: usize) -> PooledBuffer {
+        let reused = self.free.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let buf = match reused {
+            Some(mut buf) => {
+                buf.clear();
+                buf.resize(len, 0);
+                buf
+            }
+            None => vec![0u8; len],
+        };
+        PooledBuffer {
+            buf,
+            pool: self.clone(),
+        }
+    }
+
+    /// Number of idle buffers ready to be reused.
+    pub fn idle(&self) -> usize {
+        self.free.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    fn put(&self, buf: Vec<u8>) {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < self.max_idle {
+            free.push(buf);
+        }
+    }
+}
+
+/// A buffer borrowed from a [`BufferPool`], returned to it on drop.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buf));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let pool = BufferPool::new(4);
+        let ptr = {
+            let mut buf = pool.get(1500);
+            buf[0] = 0xAA;
+            buf.as_ptr()
+        };
+        assert_eq!(pool.idle(), 1);
+
+        // same memory, zeroed again and sized to the request
+        let buf = pool.get(1000);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(buf.len(), 1000);
+        assert!(buf.iter().all(|&b| b == 0));
+        assert_eq!(pool.idle(), 0);
+    }
+
+    #[test]
+    fn test_idle_buffers_are_capped() {
+        let pool = BufferPool::new(2);
+        let bufs: Vec<_> = (0..5).map(|_| pool.get(64)).collect();
+        drop(bufs);
+        assert_eq!(pool.idle(), 2);
+    }
+}
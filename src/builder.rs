@@ -0,0 +1,1402 @@
+//! Builders for configuring UDP clients and servers.
+//!
+//! [`ServerBuilder`] and [`ClientBuilder`] collect the optional settings
+//! (observers, trace output, ...) and build either the sync or the async
+//! variant, so both share the same configuration surface.
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "auth")]
+use crate::auth::AuthKey;
+use crate::{
+    access::{AccessControl, IpNet, MAX_TOKEN_LEN, source_allowed, token_space, write_token},
+    async_client::AsyncUdpClient,
+    async_server::AsyncUdpServer,
+    buffer_pool::BufferPool,
+    client::UdpClient,
+    congestion::{self, CongestionController, ControllerFactory},
+    errors::UdpOptError,
+    fault::FaultInjector,
+    handle::{AsyncClientHandle, AsyncServerHandle, CONTROL_CAPACITY, ClientHandle, ServerHandle},
+    nic::NicSampler,
+    payload::{FastRandom, PayloadSource, SizeMix},
+    runtime::AsyncDatagram,
+    server::UdpServer,
+    sink::{ResultSink, SinkQueue},
+    socket::{self, DatagramSocket, Ecn, Interface, PortRange},
+    trace::TraceWriter,
+    utils::interval_clock::IntervalAlignment,
+    utils::jitter::JitterEstimator,
+    utils::net_utils::{
+        ClientCommand, ClientProgress, IntervalCallback, IntervalResult, ProgressCallback, Rate,
+        RetryPolicy, ServerCommand, TrainConfig, interval_per_packet,
+    },
+    utils::pacer::PacingPolicy,
+    utils::sched::ThreadTuning,
+    utils::udp_data::{HEADER_SIZE, UdpHeader, now_micros},
+};
+
+/// Default time a server receive blocks before checking the control channel.
+const DEFAULT_RECV_TIMEOUT: Duration = Duration::from_secs(2);
+/// Default time without packets after which the server ends the test.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Channel the server streams interval results into.
+#[derive(Debug)]
+pub(crate) enum ResultSender {
+    Std(std::sync::mpsc::Sender<IntervalResult>),
+    Tokio(tokio::sync::mpsc::Sender<IntervalResult>),
+}
+
+/// Settings shared by [`UdpServer`] and [`AsyncUdpServer`].
+pub(crate) struct ServerConfig {
+    /// Time between each result to save
+    pub(crate) interval: Duration,
+    /// Where the interval boundaries fall
+    pub(crate) alignment: IntervalAlignment,
+    /// How the jitter of an interval is computed
+    pub(crate) jitter: JitterEstimator,
+    /// Creates the controller recommending the rate of a session
+    pub(crate) congestion: ControllerFactory,
+    /// Called with every interval result as soon as it is produced
+    pub(crate) on_interval: Option<IntervalCallback>,
+    /// Optional per-packet trace log
+    pub(crate) trace: Option<Box<dyn TraceWriter>>,
+    /// Receives every interval result as soon as it is produced
+    pub(crate) result_tx: Option<ResultSender>,
+    /// External systems every interval result is pushed to
+    pub(crate) sinks: SinkQueue,
+    /// Send the final [`crate::TestResult`] back to the client after its FIN
+    pub(crate) send_results: bool,
+    /// How long a receive blocks before the loop checks the timers and commands
+    pub(crate) recv_timeout: Duration,
+    /// Time without packets after which the client is taken as gone
+    pub(crate) idle_timeout: Option<Duration>,
+    /// How long the tail of the test is still received after the FIN
+    pub(crate) fin_linger: Option<Duration>,
+    /// Record every n-th inter-arrival time
+    pub(crate) interarrival_sampling: u32,
+    /// Warm-up after the first packet that is left out of the results
+    pub(crate) omit: Duration,
+    /// Identifies the test in the logs
+    pub(crate) test_id: Option<String>,
+    /// Where the run takes its packet buffer from
+    pub(crate) buffers: BufferPool,
+    /// Core pinning and realtime priority of the thread running the test
+    pub(crate) tuning: ThreadTuning,
+    /// Stop like on `Stop` once cancelled
+    pub(crate) cancel: Option<CancellationToken>,
+    /// Read the ECN codepoint of every packet and count the CE marks
+    pub(crate) ecn: bool,
+    /// Interface the socket is bound to
+    pub(crate) device: Option<Interface>,
+    /// Interface whose counters are attached to every interval
+    pub(crate) nic: Option<Interface>,
+    /// Count the datagrams the socket dropped with a full receive buffer
+    pub(crate) socket_drops: bool,
+    /// Drop the packets not signed with this key
+    #[cfg(feature = "auth")]
+    pub(crate) auth: Option<AuthKey>,
+    /// Which client tokens may start a test
+    pub(crate) access: Option<AccessControl>,
+    /// Prefixes the datagrams must come from, any source when empty
+    pub(crate) allow_sources: Vec<IpNet>,
+    /// The only source measured, an unspecified IP or port 0 matching any
+    pub(crate) peer: Option<SocketAddr>,
+    /// Stay on the first peer instead of following its address changes
+    pub(crate) lock_peer: bool,
+    /// Stop like on `Stop` when Ctrl-C / SIGTERM arrives
+    #[cfg(feature = "signal")]
+    pub(crate) stop_on_signal: bool,
+    /// Shutdowns requested before the run started, see [`crate::shutdown`]
+    #[cfg(feature = "signal")]
+    pub(crate) signals_before: u64,
+}
+
+impl ServerConfig {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            alignment: IntervalAlignment::default(),
+            jitter: JitterEstimator::default(),
+            congestion: congestion::default_factory(),
+            on_interval: None,
+            trace: None,
+            result_tx: None,
+            sinks: SinkQueue::default(),
+            send_results: false,
+            recv_timeout: DEFAULT_RECV_TIMEOUT,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            fin_linger: None,
+            interarrival_sampling: 1,
+            omit: Duration::ZERO,
+            test_id: None,
+            buffers: BufferPool::default(),
+            tuning: ThreadTuning::default(),
+            cancel: None,
+            ecn: false,
+            device: None,
+            nic: None,
+            socket_drops: false,
+            #[cfg(feature = "auth")]
+            auth: None,
+            access: None,
+            allow_sources: Vec::new(),
+            peer: None,
+            lock_peer: false,
+            #[cfg(feature = "signal")]
+            stop_on_signal: false,
+            #[cfg(feature = "signal")]
+            signals_before: 0,
+        }
+    }
+
+    /// Whether `from` is in the allowed prefixes, always without any.
+    pub(crate) fn allowed_source(&self, from: SocketAddr) -> bool {
+        source_allowed(&self.allow_sources, from.ip())
+    }
+
+    /// Whether datagrams from `from` may be measured, always without a peer filter.
+    pub(crate) fn accepts_peer(&self, from: SocketAddr) -> bool {
+        self.peer.is_none_or(|peer| {
+            (peer.ip().is_unspecified() || peer.ip() == from.ip())
+                && (peer.port() == 0 || peer.port() == from.port())
+        })
+    }
+
+    /// Whether `packet` from `from` carries an accepted token, always
+    /// without access control.
+    pub(crate) fn authorized(&mut self, packet: &[u8], from: SocketAddr) -> bool {
+        self.access
+            .as_mut()
+            .is_none_or(|access| access.authorize(packet, from))
+    }
+
+    /// Whether `packet` passes the pre-shared key check, always without a key.
+    pub(crate) fn authentic(&self, packet: &[u8]) -> bool {
+        #[cfg(feature = "auth")]
+        if let Some(key) = &self.auth {
+            return key.verify(packet);
+        }
+        let _ = packet;
+        true
+    }
+
+    /// Whether Ctrl-C / SIGTERM ends the run, see [`crate::shutdown`].
+    pub(crate) fn stops_on_signal(&self) -> bool {
+        #[cfg(feature = "signal")]
+        {
+            self.stop_on_signal
+        }
+        #[cfg(not(feature = "signal"))]
+        false
+    }
+
+    /// Installs the signal handler if the run stops on signals, from the
+    /// signals arriving now on.
+    pub(crate) fn watch_signals(&mut self) -> Result<(), UdpOptError> {
+        #[cfg(feature = "signal")]
+        if self.stop_on_signal {
+            crate::shutdown::install()?;
+            self.signals_before = crate::shutdown::requests();
+        }
+        Ok(())
+    }
+
+    /// Whether the run must end because of Ctrl-C / SIGTERM or a cancelled token.
+    pub(crate) fn shutdown_requested(&self) -> bool {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return true;
+        }
+        #[cfg(feature = "signal")]
+        {
+            self.stop_on_signal && crate::shutdown::requests() > self.signals_before
+        }
+        #[cfg(not(feature = "signal"))]
+        false
+    }
+
+    /// Resolves once the cancellation token fires, never without one.
+    ///
+    /// Owns a clone of the token, so the server future stays `Send` while it
+    /// is pending.
+    pub(crate) fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let token = self.cancel.clone();
+        async move {
+            match token {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    /// Whether the datagrams are received with their ancillary data, the
+    /// ECN codepoint or the drop count of the socket
+    pub(crate) fn ancillary(&self) -> bool {
+        self.ecn || self.socket_drops
+    }
+
+    /// Samples the interface given to `nic_counters`, if any.
+    pub(crate) fn nic_sampler(&self) -> Result<Option<NicSampler>, UdpOptError> {
+        self.nic
+            .as_ref()
+            .map(NicSampler::new)
+            .transpose()
+            .map_err(UdpOptError::NicCountersFailed)
+    }
+
+    /// Notifies the interval observer and the result stream, if any.
+    ///
+    /// A full tokio channel drops the result instead of blocking the receive loop.
+    pub(crate) fn emit_interval(&mut self, result: &IntervalResult) {
+        event!(
+            debug,
+            seconds = result.time.as_secs_f64(),
+            received = result.received,
+            lost = result.lost,
+            out_of_order = result.out_of_order,
+            jitter_ms = result.jitter_ms,
+            "interval"
+        );
+        if let Some(cb) = self.on_interval.as_mut() {
+            cb(result);
+        }
+        let delivered = match &self.result_tx {
+            Some(ResultSender::Std(tx)) => tx.send(*result).is_ok(),
+            Some(ResultSender::Tokio(tx)) => !matches!(
+                tx.try_send(*result),
+                Err(tokio::sync::mpsc::error::TrySendError::Closed(_))
+            ),
+            None => true,
+        };
+        // stop streaming once the receiving side is gone
+        if !delivered {
+            self.result_tx = None;
+        }
+    }
+
+    /// Queues an interval result received from `peer` for the result sinks.
+    pub(crate) fn sink_interval(&mut self, peer: SocketAddr, result: &IntervalResult) {
+        self.sinks.send(peer, result);
+    }
+
+    /// Flushes the trace log and the result sinks at the end of a run.
+    pub(crate) fn flush_outputs(&mut self) -> Result<(), UdpOptError> {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush().map_err(UdpOptError::TraceFailed)?;
+        }
+        self.sinks.flush();
+        Ok(())
+    }
+
+    /// Async version of [`ServerConfig::flush_outputs`].
+    pub(crate) async fn flush_outputs_async<S: AsyncDatagram>(
+        &mut self,
+    ) -> Result<(), UdpOptError> {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush().map_err(UdpOptError::TraceFailed)?;
+        }
+        self.sinks.flush_async::<S>().await;
+        Ok(())
+    }
+
+    /// Async version of [`ServerConfig::emit_interval`] that waits for room in a tokio channel.
+    pub(crate) async fn emit_interval_async(&mut self, result: &IntervalResult) {
+        event!(
+            debug,
+            seconds = result.time.as_secs_f64(),
+            received = result.received,
+            lost = result.lost,
+            out_of_order = result.out_of_order,
+            jitter_ms = result.jitter_ms,
+            "interval"
+        );
+        if let Some(cb) = self.on_interval.as_mut() {
+            cb(result);
+        }
+        let delivered = match &self.result_tx {
+            Some(ResultSender::Std(tx)) => tx.send(*result).is_ok(),
+            Some(ResultSender::Tokio(tx)) => tx.send(*result).await.is_ok(),
+            None => true,
+        };
+        if !delivered {
+            self.result_tx = None;
+        }
+    }
+}
+
+impl std::fmt::Debug for ServerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerConfig")
+            .field("interval", &self.interval)
+            .field("alignment", &self.alignment)
+            .field("jitter", &self.jitter)
+            .field("congestion", &"dyn CongestionController")
+            .field("on_interval", &self.on_interval.is_some())
+            .field("trace", &self.trace.is_some())
+            .field("result_tx", &self.result_tx)
+            .field("sinks", &self.sinks.len())
+            .field("send_results", &self.send_results)
+            .field("recv_timeout", &self.recv_timeout)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("fin_linger", &self.fin_linger)
+            .field("interarrival_sampling", &self.interarrival_sampling)
+            .field("omit", &self.omit)
+            .field("test_id", &self.test_id)
+            .field("buffers", &self.buffers.idle())
+            .field("tuning", &self.tuning)
+            .field("cancel", &self.cancel.is_some())
+            .field("allow_sources", &self.allow_sources)
+            .field("peer", &self.peer)
+            .field("lock_peer", &self.lock_peer)
+            .field("device", &self.device)
+            .field("nic", &self.nic)
+            .field("socket_drops", &self.socket_drops)
+            .finish()
+    }
+}
+
+/// Settings shared by [`UdpClient`] and [`AsyncUdpClient`].
+pub(crate) struct ClientConfig {
+    /// Target sending rate
+    pub(crate) rate: Rate,
+    /// Size of each UDP packet payload, including header.
+    pub(crate) payload_size: usize,
+    /// Maximum duration for the transmission test, `None` to run until stopped.
+    pub(crate) timeout: Option<Duration>,
+    /// Stop once this many bytes have been sent
+    pub(crate) stop_after_bytes: Option<u64>,
+    /// Stop once this many packets have been sent
+    pub(crate) stop_after_packets: Option<u64>,
+    /// How often `on_progress` is called while sending
+    pub(crate) progress_interval: Duration,
+    /// How often the packets sent so far are reported to the server
+    pub(crate) stats_interval: Option<Duration>,
+    /// Longest silence between two packets, bridged with heartbeats
+    pub(crate) heartbeat: Option<Duration>,
+    /// Called periodically with the transmit progress
+    pub(crate) on_progress: Option<ProgressCallback>,
+    /// How long to wait for the server's results after the FIN, if at all
+    pub(crate) remote_results: Option<Duration>,
+    /// Send back-to-back trains instead of evenly paced packets
+    pub(crate) trains: Option<TrainConfig>,
+    /// How the send loop waits for the next packet
+    pub(crate) pacing_policy: PacingPolicy,
+    /// Send non-blocking, counting the sends refused by a full send buffer
+    pub(crate) nonblocking_send: bool,
+    /// How a send failing with a transient error is retried, if at all
+    pub(crate) send_retry: Option<RetryPolicy>,
+    /// How long sends refused by an ICMP unreachable are outlasted
+    pub(crate) unreachable_grace: Option<Duration>,
+    /// Identifies the test in the logs
+    pub(crate) test_id: Option<String>,
+    /// Where the run takes its packet buffer from
+    pub(crate) buffers: BufferPool,
+    /// Core pinning and realtime priority of the thread running the test
+    pub(crate) tuning: ThreadTuning,
+    /// Packets to drop, duplicate, delay or reorder on purpose
+    pub(crate) faults: Option<FaultInjector>,
+    /// Fills the data packets after the header
+    pub(crate) payload: Box<dyn PayloadSource>,
+    /// Mix of packet lengths replacing `payload_size`
+    pub(crate) sizes: Option<SizeMix>,
+    /// Mark the packets ECT(0)
+    pub(crate) ecn: bool,
+    /// DSCP the packets are marked with
+    pub(crate) dscp: Option<u8>,
+    /// Interface the socket is bound to
+    pub(crate) device: Option<Interface>,
+    /// Set or clear the don't-fragment bit, `None` leaves the host's default
+    pub(crate) dont_fragment: Option<bool>,
+    /// Local address `connect` binds
+    pub(crate) local: Option<SocketAddr>,
+    /// Source ports `connect` takes the first free one of
+    pub(crate) source_ports: Option<PortRange>,
+    /// Sign the packets with this key
+    #[cfg(feature = "auth")]
+    pub(crate) auth: Option<AuthKey>,
+    /// Token presented to a server with access control
+    pub(crate) token: Option<Vec<u8>>,
+    /// Stop like on `Stop` when Ctrl-C / SIGTERM arrives
+    #[cfg(feature = "signal")]
+    pub(crate) stop_on_signal: bool,
+    /// Shutdowns requested before the run started, see [`crate::shutdown`]
+    #[cfg(feature = "signal")]
+    pub(crate) signals_before: u64,
+}
+
+impl ClientConfig {
+    pub(crate) fn new(
+        bitrate_bps: f64,
+        payload_size: usize,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Self {
+        Self {
+            rate: Rate::Bps(bitrate_bps),
+            payload_size,
+            timeout: timeout.into(),
+            stop_after_bytes: None,
+            stop_after_packets: None,
+            progress_interval: Duration::from_secs(1),
+            stats_interval: None,
+            heartbeat: None,
+            on_progress: None,
+            remote_results: None,
+            trains: None,
+            pacing_policy: PacingPolicy::default(),
+            nonblocking_send: false,
+            send_retry: None,
+            unreachable_grace: None,
+            test_id: None,
+            buffers: BufferPool::default(),
+            tuning: ThreadTuning::default(),
+            faults: None,
+            payload: Box::new(FastRandom::default()),
+            sizes: None,
+            ecn: false,
+            dscp: None,
+            device: None,
+            dont_fragment: None,
+            local: None,
+            source_ports: None,
+            #[cfg(feature = "auth")]
+            auth: None,
+            token: None,
+            #[cfg(feature = "signal")]
+            stop_on_signal: false,
+            #[cfg(feature = "signal")]
+            signals_before: 0,
+        }
+    }
+
+    /// Installs the signal handler if `stop_on_signal` is set, from the
+    /// signals arriving now on.
+    pub(crate) fn watch_signals(&mut self) -> Result<(), UdpOptError> {
+        #[cfg(feature = "signal")]
+        if self.stop_on_signal {
+            crate::shutdown::install()?;
+            self.signals_before = crate::shutdown::requests();
+        }
+        Ok(())
+    }
+
+    /// Whether the run must end because of Ctrl-C / SIGTERM.
+    pub(crate) fn shutdown_requested(&self) -> bool {
+        #[cfg(feature = "signal")]
+        {
+            self.stop_on_signal && crate::shutdown::requests() > self.signals_before
+        }
+        #[cfg(not(feature = "signal"))]
+        false
+    }
+
+    /// Whether the next packet would go past `stop_after_packets` / `stop_after_bytes`.
+    pub(crate) fn limit_reached(&self, packets_sent: u64, bytes_sent: u64) -> bool {
+        self.stop_after_packets
+            .is_some_and(|max| packets_sent >= max)
+            || self
+                .stop_after_bytes
+                .is_some_and(|max| bytes_sent + self.packet_len(packets_sent) as u64 > max)
+    }
+
+    /// TOS byte of the packets when a DSCP is set, ECT(0) included with
+    /// [`ClientBuilder::ecn`].
+    pub(crate) fn tos(&self) -> Option<u8> {
+        let ecn = if self.ecn { Ecn::Ect0.bits() } else { 0 };
+        self.dscp.map(|dscp| dscp << 2 | ecn)
+    }
+
+    /// A socket bound to the local address and source ports, connected to
+    /// `server`.
+    pub(crate) fn connect(&self, server: SocketAddr) -> Result<UdpSocket, UdpOptError> {
+        socket::connect(self.local, self.source_ports, server)
+    }
+
+    /// Length of the data packet number `index`.
+    pub(crate) fn packet_len(&self, index: u64) -> usize {
+        self.sizes
+            .as_ref()
+            .map_or(self.payload_size, |mix| mix.len_at(index))
+            .max(self.min_packet_len())
+    }
+
+    /// Length of the longest data packet, the size of the packet buffer.
+    pub(crate) fn max_packet_len(&self) -> usize {
+        self.sizes
+            .as_ref()
+            .map_or(self.payload_size, SizeMix::max_len)
+            .max(self.min_packet_len())
+    }
+
+    /// Length of a heartbeat: the header and what sealing needs, no payload.
+    pub(crate) fn heartbeat_len(&self) -> usize {
+        self.min_packet_len()
+    }
+
+    /// Whether the packets are signed
+    fn has_auth(&self) -> bool {
+        #[cfg(feature = "auth")]
+        {
+            self.auth.is_some()
+        }
+        #[cfg(not(feature = "auth"))]
+        false
+    }
+
+    /// Room a packet needs for its header, token and tag, if any
+    fn min_packet_len(&self) -> usize {
+        let min = self
+            .token
+            .as_ref()
+            .map_or(HEADER_SIZE, |token| HEADER_SIZE + token_space(token));
+        #[cfg(feature = "auth")]
+        if self.has_auth() {
+            return min + crate::auth::TAG_LEN;
+        }
+        min
+    }
+
+    /// Writes the access token into `packet` and signs it, if configured.
+    pub(crate) fn seal(&self, packet: &mut [u8]) {
+        if let Some(token) = &self.token {
+            write_token(packet, token);
+        }
+        #[cfg(feature = "auth")]
+        if let Some(key) = &self.auth {
+            key.sign(packet);
+        }
+    }
+
+    /// Writes a STATS or HEARTBEAT packet reporting the `sent` data packets
+    /// into `packet`.
+    pub(crate) fn write_control(&self, packet: &mut [u8], flags: u32, sent: u64, session: u32) {
+        let (sec, usec) = now_micros();
+        UdpHeader::new(sent, sec, usec, flags)
+            .with_session(session)
+            .with_sender(sent, self.header_rate_bps())
+            .write_header(packet);
+        self.seal(packet);
+    }
+
+    /// Length of a pacing slot and the number of packets sent back-to-back in it.
+    pub(crate) fn pacing(&self) -> (Duration, u64) {
+        match self.trains {
+            Some(train) => (train.spacing, train.length as u64),
+            None => (interval_per_packet(self.mean_packet_len(), self.rate), 1),
+        }
+    }
+
+    /// Changes the target rate of a running test, not in probing mode.
+    pub(crate) fn set_rate(&mut self, rate: Rate) {
+        if self.trains.is_some() {
+            event!(warn, "rate change ignored in probing mode");
+        } else {
+            event!(info, ?rate, "rate changed");
+            self.rate = rate;
+        }
+    }
+
+    /// Whether the packets go out unpaced, see [`Rate::Unlimited`].
+    pub(crate) fn unlimited(&self) -> bool {
+        self.trains.is_none() && self.rate == Rate::Unlimited
+    }
+
+    /// Whether the sync client sends non-blocking, always when unpaced.
+    pub(crate) fn nonblocking(&self) -> bool {
+        self.nonblocking_send || self.unlimited()
+    }
+
+    /// Mean length of the data packets.
+    fn mean_packet_len(&self) -> f64 {
+        self.sizes
+            .as_ref()
+            .map_or(self.packet_len(0) as f64, SizeMix::mean_len)
+    }
+
+    /// Target rate in bits per second, whichever way it was set.
+    pub(crate) fn bitrate_bps(&self) -> f64 {
+        self.rate.bitrate_bps(self.mean_packet_len())
+    }
+
+    /// Target rate written into the packet headers, 0 when unlimited.
+    pub(crate) fn header_rate_bps(&self) -> u64 {
+        if self.rate == Rate::Unlimited {
+            0
+        } else {
+            self.bitrate_bps() as u64
+        }
+    }
+
+    /// Train length written into the packet headers, 0 outside probing mode.
+    pub(crate) fn train_len(&self) -> u16 {
+        self.trains.map_or(0, |train| train.length)
+    }
+
+    /// Notifies the progress observer, if any.
+    pub(crate) fn emit_progress(&mut self, progress: &ClientProgress) {
+        event!(
+            debug,
+            seconds = progress.elapsed.as_secs_f64(),
+            packets_sent = progress.packets_sent,
+            bitrate_bps = progress.bitrate_bps,
+            "progress"
+        );
+        if let Some(cb) = self.on_progress.as_mut() {
+            cb(progress);
+        }
+    }
+}
+
+impl std::fmt::Debug for ClientConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientConfig")
+            .field("rate", &self.rate)
+            .field("bitrate_bps", &self.bitrate_bps())
+            .field("payload_size", &self.payload_size)
+            .field("timeout", &self.timeout)
+            .field("stop_after_bytes", &self.stop_after_bytes)
+            .field("stop_after_packets", &self.stop_after_packets)
+            .field("progress_interval", &self.progress_interval)
+            .field("stats_interval", &self.stats_interval)
+            .field("heartbeat", &self.heartbeat)
+            .field("on_progress", &self.on_progress.is_some())
+            .field("remote_results", &self.remote_results)
+            .field("trains", &self.trains)
+            .field("pacing_policy", &self.pacing_policy)
+            .field("nonblocking_send", &self.nonblocking_send)
+            .field("send_retry", &self.send_retry)
+            .field("unreachable_grace", &self.unreachable_grace)
+            .field("test_id", &self.test_id)
+            .field("buffers", &self.buffers.idle())
+            .field("tuning", &self.tuning)
+            .field("faults", &self.faults)
+            .field("payload", &"dyn PayloadSource")
+            .field("sizes", &self.sizes)
+            .field("ecn", &self.ecn)
+            .field("dscp", &self.dscp)
+            .field("device", &self.device)
+            .field("dont_fragment", &self.dont_fragment)
+            .field("local", &self.local)
+            .field("source_ports", &self.source_ports)
+            .field("auth", &self.has_auth())
+            .field("token", &self.token.is_some())
+            .finish()
+    }
+}
+
+/// Builder for [`UdpServer`] and [`AsyncUdpServer`].
+///
+/// ```
+/// use std::sync::mpsc;
+/// use std::time::Duration;
+/// use udpopt::{ServerBuilder, ui};
+///
+/// let (_tx, rx) = mpsc::channel();
+/// let server = ServerBuilder::new(Duration::from_secs(1))
+///     .on_interval(Box::new(|r| ui::print_result(r)))
+///     .build(rx);
+/// ```
+#[derive(Debug)]
+pub struct ServerBuilder {
+    config: ServerConfig,
+}
+
+impl ServerBuilder {
+    /// Starts a server configuration.
+    ///
+    /// - `interval`: The duration for each result interval, 100 ms and up
+    ///   keep the boundaries within a few percent.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            config: ServerConfig::new(interval),
+        }
+    }
+
+    /// Registers an observer called with every [`IntervalResult`] as it is produced.
+    pub fn on_interval(mut self, callback: IntervalCallback) -> Self {
+        self.config.on_interval = Some(callback);
+        self
+    }
+
+    /// Logs every received packet to `writer`, see [`crate::trace`].
+    pub fn trace_writer<T: TraceWriter + 'static>(mut self, writer: T) -> Self {
+        self.config.trace = Some(Box::new(writer));
+        self
+    }
+
+    /// Streams every [`IntervalResult`] through `tx` as it is produced,
+    /// in addition to returning the full list from `run`.
+    pub fn stream_results(mut self, tx: std::sync::mpsc::Sender<IntervalResult>) -> Self {
+        self.config.result_tx = Some(ResultSender::Std(tx));
+        self
+    }
+
+    /// Like [`ServerBuilder::stream_results`] but with a tokio channel, for
+    /// consumers running inside an async runtime.
+    pub fn stream_results_async(mut self, tx: tokio::sync::mpsc::Sender<IntervalResult>) -> Self {
+        self.config.result_tx = Some(ResultSender::Tokio(tx));
+        self
+    }
+
+    /// Pushes every [`IntervalResult`] to `sink` as it is produced, see [`crate::sink`].
+    ///
+    /// Can be called several times to export to more than one system. The
+    /// sinks run on a thread of their own; their failures are logged, they
+    /// do not end the test.
+    pub fn result_sink<S: ResultSink + 'static>(mut self, sink: S) -> Self {
+        self.config.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Sets how long a receive waits before the server checks its interval
+    /// timer (default 2 seconds).
+    ///
+    /// Keep it below the interval length so quiet intervals are reported on time.
+    /// The control channel does not depend on it: [`AsyncUdpServer`] awaits it
+    /// together with the socket and [`UdpServer`] checks it at least every 10 ms.
+    pub fn recv_timeout(mut self, timeout: Duration) -> Self {
+        self.config.recv_timeout = timeout;
+        self
+    }
+
+    /// Ends the test when no packet arrived for `timeout` (default 10 seconds),
+    /// `None` waits for a FIN or `Stop` however long the link stays quiet.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.idle_timeout = timeout;
+        self
+    }
+
+    /// Keeps receiving for `linger` after the FIN before returning, so the
+    /// tail packets still in flight behind it, e.g. reordered by the path,
+    /// count as received instead of lost, and as
+    /// [`late`](crate::IntervalResult::late). They land in the last interval,
+    /// whose bitrate still covers the time up to the FIN.
+    ///
+    /// The results requested with [`send_results_to_client`](Self::send_results_to_client) leave
+    /// after the linger, keep it below the client's wait for them. Not
+    /// supported by the sharded server.
+    pub fn fin_linger(mut self, linger: Duration) -> Self {
+        self.config.fin_linger = Some(linger);
+        self
+    }
+
+    /// Lays the interval boundaries on the wall clock instead of the start of
+    /// the measurement, see [`IntervalAlignment`]. Not supported by the
+    /// sharded server.
+    pub fn interval_alignment(mut self, alignment: IntervalAlignment) -> Self {
+        self.config.alignment = alignment;
+        self
+    }
+
+    /// Computes the jitter of the intervals with `estimator` instead of the
+    /// RFC 3550 smoothed jitter, see [`JitterEstimator`]. The IPDV
+    /// percentiles of the test are recorded whatever the estimator.
+    pub fn jitter_estimator(mut self, estimator: JitterEstimator) -> Self {
+        self.config.jitter = estimator;
+        self
+    }
+
+    /// Recommends the rate of the intervals with `controller` instead of
+    /// [`LossThreshold`](crate::congestion::LossThreshold), see
+    /// [`congestion`](crate::congestion). Every session starts from a clone
+    /// of it. Not supported by the sharded server, which recommends no rate.
+    pub fn congestion_controller<C>(mut self, controller: C) -> Self
+    where
+        C: CongestionController + Clone + Sync + 'static,
+    {
+        self.config.congestion = Arc::new(move || Box::new(controller.clone()));
+        self
+    }
+
+    /// Leaves the first `omit` after the first packet out of the results
+    /// (like iperf3 `-O`), so slow-start and ARP / route warm-up do not skew them.
+    ///
+    /// The packets are still received and their sequence numbers tracked, but
+    /// no [`IntervalResult`] is produced for the warm-up and the latency,
+    /// reordering and loss statistics start after it. Intervals are counted
+    /// from the end of the warm-up. Not supported by the sharded server.
+    pub fn omit(mut self, omit: Duration) -> Self {
+        self.config.omit = omit;
+        self
+    }
+
+    /// Records only every `every`-th inter-arrival time (default every one),
+    /// see [`crate::TestResult::interarrival_histogram`].
+    pub fn interarrival_sampling(mut self, every: u32) -> Self {
+        self.config.interarrival_sampling = every.max(1);
+        self
+    }
+
+    /// Sends the final [`crate::TestResult`] back to the client once its FIN
+    /// arrives, retrying until the client acknowledges it.
+    ///
+    /// Pair with [`ClientBuilder::remote_results`] on the client.
+    pub fn send_results_to_client(mut self) -> Self {
+        self.config.send_results = true;
+        self
+    }
+
+    /// Names the test; it tags the `tracing` span of every run (feature `tracing`).
+    pub fn test_id(mut self, id: impl Into<String>) -> Self {
+        self.config.test_id = Some(id.into());
+        self
+    }
+
+    /// Takes the packet buffer from `pool`, so several runs can share their
+    /// buffers, see [`crate::buffer_pool`].
+    pub fn buffer_pool(mut self, pool: BufferPool) -> Self {
+        self.config.buffers = pool;
+        self
+    }
+
+    /// Pins the thread that calls `run` to CPU `core` for the whole test (Linux only).
+    ///
+    /// With the async variant this is the executor thread polling the run, so
+    /// it only makes sense on a current-thread runtime.
+    pub fn pin_to_core(mut self, core: usize) -> Self {
+        self.config.tuning.core = Some(core);
+        self
+    }
+
+    /// Runs the test thread under the `SCHED_FIFO` realtime class (Linux only),
+    /// which needs `CAP_SYS_NICE` or an `RLIMIT_RTPRIO` allowance.
+    ///
+    /// A realtime thread that never sleeps can starve everything else on its
+    /// core, combine it with [`Self::pin_to_core`] on a spare core.
+    pub fn realtime_priority(mut self, enabled: bool) -> Self {
+        self.config.tuning.realtime = enabled;
+        self
+    }
+
+    /// Ends the test gracefully, like `Stop`, once `token` is cancelled.
+    ///
+    /// [`AsyncUdpServer`] reacts at once, even without traffic or while
+    /// waiting for the first packet; [`UdpServer`] notices it within 10 ms.
+    pub fn cancel_token(mut self, token: CancellationToken) -> Self {
+        self.config.cancel = Some(token);
+        self
+    }
+
+    /// Measures only the clients whose token `access` accepts, see
+    /// [`crate::access`]; packets from other sources are ignored.
+    pub fn access_control(mut self, access: AccessControl) -> Self {
+        self.config.access = Some(access);
+        self
+    }
+
+    /// Measures only the datagrams from the `sources` prefixes: the others,
+    /// e.g. scanners hitting the open port, are counted in
+    /// [`IntervalResult::foreign`] and left out of the statistics, see
+    /// [`crate::access`].
+    pub fn allow_sources(mut self, sources: Vec<IpNet>) -> Self {
+        self.config.allow_sources = sources;
+        self
+    }
+
+    /// Measures only the datagrams from `peer`, for a server listening on
+    /// `0.0.0.0`; port 0 accepts any port of the address.
+    pub fn peer(mut self, peer: SocketAddr) -> Self {
+        self.config.peer = Some(peer);
+        self
+    }
+
+    /// Keeps the test on the address of its first packet: the datagrams of
+    /// the same test from another address are ignored instead of following
+    /// the client through a NAT rebinding.
+    pub fn lock_peer(mut self) -> Self {
+        self.config.lock_peer = true;
+        self
+    }
+
+    /// Drops the packets not signed with `key`, counting them in
+    /// [`IntervalResult::auth_failures`], see [`crate::auth`].
+    #[cfg(feature = "auth")]
+    pub fn auth(mut self, key: AuthKey) -> Self {
+        self.config.auth = Some(key);
+        self
+    }
+
+    /// Reads the ECN codepoint of every packet and counts the ones marked
+    /// Congestion Experienced in [`IntervalResult::ce_marked`], to see
+    /// whether an AQM on the path (fq_codel, PIE, ...) marks instead of
+    /// dropping. Pair it with [`ClientBuilder::ecn`].
+    ///
+    /// Supported on Linux; elsewhere `run` fails with [`UdpOptError::EcnFailed`].
+    pub fn ecn(mut self) -> Self {
+        self.config.ecn = true;
+        self
+    }
+
+    /// Binds the socket to `interface`, a name like `"eth1"` or an index,
+    /// so only the packets that arrived on it are measured, see
+    /// [`crate::DatagramSocket::bind_device`].
+    ///
+    /// Supported on Linux; elsewhere `run` fails with [`UdpOptError::DeviceFailed`].
+    pub fn bind_device(mut self, interface: impl Into<Interface>) -> Self {
+        self.config.device = Some(interface.into());
+        self
+    }
+
+    /// Attaches what `interface`, a name like `"eth0"` or an index, counted
+    /// during every interval to [`IntervalResult::nic`], to tell the packets
+    /// the host dropped from the ones the network lost, see [`crate::nic`].
+    ///
+    /// Supported on Linux; elsewhere `run` fails with
+    /// [`UdpOptError::NicCountersFailed`].
+    pub fn nic_counters(mut self, interface: impl Into<Interface>) -> Self {
+        self.config.nic = Some(interface.into());
+        self
+    }
+
+    /// Counts the datagrams the socket dropped because its receive buffer
+    /// was full in [`IntervalResult::socket_drops`], the part of the loss
+    /// the server caused by not reading fast enough rather than the path.
+    ///
+    /// Supported on Linux; elsewhere `run` fails with
+    /// [`UdpOptError::DropCountFailed`].
+    pub fn count_socket_drops(mut self) -> Self {
+        self.config.socket_drops = true;
+        self
+    }
+
+    /// Ends the test gracefully on Ctrl-C / SIGTERM, see [`crate::shutdown`].
+    #[cfg(feature = "signal")]
+    pub fn stop_on_signal(mut self) -> Self {
+        self.config.stop_on_signal = true;
+        self
+    }
+
+    /// Builds a blocking [`UdpServer`].
+    pub fn build(self, control_rx: std::sync::mpsc::Receiver<ServerCommand>) -> UdpServer {
+        UdpServer::from_config(self.config, control_rx)
+    }
+
+    /// Builds a tokio based [`AsyncUdpServer`].
+    pub fn build_async(
+        self,
+        control_rx: tokio::sync::mpsc::Receiver<ServerCommand>,
+    ) -> AsyncUdpServer {
+        AsyncUdpServer::from_config(self.config, control_rx)
+    }
+
+    /// Builds a [`UdpServer`] and runs it on `sock` on its own thread,
+    /// controlled through the returned [`ServerHandle`], see [`crate::handle`].
+    pub fn spawn<S: DatagramSocket + Send + 'static>(self, mut sock: S) -> ServerHandle {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut server = self.build(rx);
+        ServerHandle::new(tx, std::thread::spawn(move || server.run(&mut sock)))
+    }
+
+    /// Builds an [`AsyncUdpServer`] and runs it on `sock` on a tokio task,
+    /// controlled through the returned [`AsyncServerHandle`].
+    ///
+    /// # Panics
+    /// Outside of a tokio runtime.
+    pub fn spawn_async(self, mut sock: tokio::net::UdpSocket) -> AsyncServerHandle {
+        let (tx, rx) = tokio::sync::mpsc::channel(CONTROL_CAPACITY);
+        let mut server = self.build_async(rx);
+        AsyncServerHandle::new(tx, tokio::spawn(async move { server.run(&mut sock).await }))
+    }
+
+    /// Builds a [`crate::ShardedServer`] receiving on `shards` threads, see [`crate::sharded`].
+    ///
+    /// The trace writer and the in-band results are not supported in this
+    /// mode. The [`nic_counters`](Self::nic_counters) go to the merged
+    /// intervals, sampled as each one completes. The
+    /// [`idle_timeout`](Self::idle_timeout) ends the run once every shard
+    /// has been quiet for it.
+    ///
+    /// # Panics
+    /// Panics if [`omit`](Self::omit), [`fin_linger`](Self::fin_linger) or a
+    /// wall-clock [`interval_alignment`](Self::interval_alignment) is set,
+    /// which the shards do not implement.
+    #[cfg(all(feature = "reuseport", unix))]
+    pub fn build_sharded(
+        self,
+        shards: usize,
+        control_rx: std::sync::mpsc::Receiver<ServerCommand>,
+    ) -> crate::ShardedServer {
+        assert!(
+            self.config.omit.is_zero(),
+            "omit is not supported by the sharded server"
+        );
+        assert!(
+            self.config.fin_linger.is_none(),
+            "fin_linger is not supported by the sharded server"
+        );
+        assert_eq!(
+            self.config.alignment,
+            IntervalAlignment::default(),
+            "interval_alignment is not supported by the sharded server"
+        );
+        crate::ShardedServer::from_config(self.config, shards, control_rx)
+    }
+}
+
+/// Builder for [`UdpClient`] and [`AsyncUdpClient`].
+///
+/// ```
+/// use std::sync::mpsc;
+/// use std::time::Duration;
+/// use udpopt::{ClientBuilder, ui};
+///
+/// let (_tx, rx) = mpsc::channel();
+/// let client = ClientBuilder::new(1_000_000.0, 1200, Duration::from_secs(5))
+///     .on_progress(Box::new(|p| ui::print_progress(p)))
+///     .build(rx);
+/// ```
+#[derive(Debug)]
+pub struct ClientBuilder {
+    config: ClientConfig,
+}
+
+impl ClientBuilder {
+    /// Starts a client configuration.
+    ///
+    /// - `bitrate_bps`: Desired sending bitrate in bits per second.
+    /// - `payload_size`: Number of bytes in each packet (typically 512–1500
+    ///   bytes), header included; shorter than the header, the packets are
+    ///   lengthened to it.
+    /// - `timeout`: Total duration to keep sending packets, `None` to send until
+    ///   [`ClientCommand::Stop`] arrives.
+    pub fn new(
+        bitrate_bps: f64,
+        payload_size: usize,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Self {
+        Self {
+            config: ClientConfig::new(bitrate_bps, payload_size, timeout),
+        }
+    }
+
+    /// Sets the target rate, in bits or in packets per second, replacing the
+    /// `bitrate_bps` given to [`Self::new`].
+    ///
+    /// ```
+    /// use std::{sync::mpsc, time::Duration};
+    /// use udpopt::{ClientBuilder, Rate};
+    ///
+    /// let (_tx, rx) = mpsc::channel();
+    /// let client = ClientBuilder::new(0.0, 200, Duration::from_secs(5))
+    ///     .rate(Rate::Pps(50_000.0))
+    ///     .build(rx);
+    /// ```
+    pub fn rate(mut self, rate: Rate) -> Self {
+        self.config.rate = rate;
+        self
+    }
+
+    /// Registers an observer called periodically with the [`ClientProgress`].
+    pub fn on_progress(mut self, callback: ProgressCallback) -> Self {
+        self.config.on_progress = Some(callback);
+        self
+    }
+
+    /// Sets how often the progress observer is called (default 1 second).
+    pub fn progress_interval(mut self, interval: Duration) -> Self {
+        self.config.progress_interval = interval;
+        self
+    }
+
+    /// Reports the count of packets sent so far to the server every
+    /// `interval` in a STATS packet, best set to the server's interval.
+    ///
+    /// The server then takes the loss of the interval as the packets
+    /// reported sent minus the packets received, instead of inferring it from
+    /// the gaps in the sequence numbers, which heavy reordering blurs.
+    pub fn stats_interval(mut self, interval: Duration) -> Self {
+        self.config.stats_interval = Some(interval);
+        self
+    }
+
+    /// Sends a small heartbeat packet whenever the pacing leaves the link
+    /// quiet for longer than `interval`, e.g. between the trains of a probe
+    /// or at a very low rate, so the server's idle timeout does not end the
+    /// test. Best well below the server's idle timeout.
+    ///
+    /// Heartbeats are neither counted as sent nor as received data.
+    ///
+    /// # Panics
+    /// Panics if `interval` is zero.
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "heartbeat interval is zero");
+        self.config.heartbeat = Some(interval);
+        self
+    }
+
+    /// Ends the test once `bytes` have been sent, without ever going past them:
+    /// the last packet is the last one that fits. The FIN is not counted.
+    ///
+    /// Combined with a `timeout` the test ends on whichever comes first.
+    pub fn stop_after_bytes(mut self, bytes: u64) -> Self {
+        self.config.stop_after_bytes = Some(bytes);
+        self
+    }
+
+    /// Ends the test once `packets` data packets have been sent. The FIN is not counted.
+    ///
+    /// Combined with a `timeout` the test ends on whichever comes first.
+    pub fn stop_after_packets(mut self, packets: u64) -> Self {
+        self.config.stop_after_packets = Some(packets);
+        self
+    }
+
+    /// Drops, duplicates, delays or reorders some of the data packets on
+    /// purpose, see [`FaultInjector`].
+    ///
+    /// The impaired packets still count as sent in the report, which also
+    /// lists them in `faults`.
+    pub fn fault_injector(mut self, faults: FaultInjector) -> Self {
+        self.config.faults = Some(faults);
+        self
+    }
+
+    /// Sets what fills the data packets after the header, see [`crate::payload`].
+    ///
+    /// Defaults to [`FastRandom`] bytes, which no link can compress.
+    pub fn payload(mut self, source: impl PayloadSource + 'static) -> Self {
+        self.config.payload = Box::new(source);
+        self
+    }
+
+    /// Sends packets of the lengths of `mix` instead of `payload_size`, e.g.
+    /// [`SizeMix::imix`]. The bitrate is kept on average.
+    pub fn packet_sizes(mut self, mix: SizeMix) -> Self {
+        self.config.sizes = Some(mix);
+        self
+    }
+
+    /// Presents `token` to a server with access control, see [`crate::access`].
+    ///
+    /// # Panics
+    /// Panics if `token` is longer than [`MAX_TOKEN_LEN`].
+    pub fn access_token(mut self, token: impl Into<Vec<u8>>) -> Self {
+        let token = token.into();
+        assert!(token.len() <= MAX_TOKEN_LEN, "access token too long");
+        self.config.token = Some(token);
+        self
+    }
+
+    /// Signs every packet with `key` for a server that checks it, see
+    /// [`crate::auth`]. Packets shorter than header and tag are lengthened.
+    #[cfg(feature = "auth")]
+    pub fn auth(mut self, key: AuthKey) -> Self {
+        self.config.auth = Some(key);
+        self
+    }
+
+    /// Sends the packets ECN-capable, ECT(0), so routers with ECN-enabled
+    /// queues mark them instead of dropping them under congestion.
+    ///
+    /// Supported on Linux; elsewhere `run` fails with [`UdpOptError::EcnFailed`].
+    pub fn ecn(mut self) -> Self {
+        self.config.ecn = true;
+        self
+    }
+
+    /// Marks the packets with the DiffServ code point `dscp` (e.g. 46 for
+    /// EF, 10 for AF11), to measure how the network treats that class.
+    /// Combines with [`ecn`](Self::ecn).
+    ///
+    /// Supported on Linux; elsewhere `run` fails with [`UdpOptError::DscpFailed`].
+    ///
+    /// # Panics
+    /// Panics if `dscp` does not fit in six bits.
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        assert!(dscp < 64, "DSCP out of range");
+        self.config.dscp = Some(dscp);
+        self
+    }
+
+    /// Sends through `interface`, a name like `"eth1"` or an index, whatever
+    /// the routing table prefers, so a multi-homed host can test each of its
+    /// links; multicast and IPv6 link-local targets leave through it too.
+    /// See [`crate::DatagramSocket::bind_device`].
+    ///
+    /// Supported on Linux; elsewhere `run` fails with [`UdpOptError::DeviceFailed`].
+    pub fn bind_device(mut self, interface: impl Into<Interface>) -> Self {
+        self.config.device = Some(interface.into());
+        self
+    }
+
+    /// Sets (`true`) or clears the don't-fragment bit of the packets instead
+    /// of leaving the host's default. Set, a packet longer than the path MTU
+    /// is refused and skipped, counted in [`crate::ClientReport::too_big`];
+    /// cleared, it leaves in IP fragments, counted in
+    /// [`crate::ClientReport::fragmentation`].
+    ///
+    /// Supported on Linux; elsewhere `run` fails with
+    /// [`UdpOptError::FragmentationFailed`].
+    pub fn dont_fragment(mut self, on: bool) -> Self {
+        self.config.dont_fragment = Some(on);
+        self
+    }
+
+    /// Local address [`UdpClient::connect`] binds, the port 0 taking any
+    /// free one; by default any address of the server's family.
+    pub fn bind(mut self, local: SocketAddr) -> Self {
+        self.config.local = Some(local);
+        self
+    }
+
+    /// Makes [`UdpClient::connect`] send from the first free port of
+    /// `ports` instead of the port of [`bind`](Self::bind), to exercise a
+    /// firewall or NAT policy that matches on source ports.
+    pub fn source_ports(mut self, ports: PortRange) -> Self {
+        self.config.source_ports = Some(ports);
+        self
+    }
+
+    /// After the FIN, waits up to `timeout` for the server's results and
+    /// returns them in [`crate::ClientReport::remote`].
+    ///
+    /// The server must be built with [`ServerBuilder::send_results_to_client`].
+    pub fn remote_results(mut self, timeout: Duration) -> Self {
+        self.config.remote_results = Some(timeout);
+        self
+    }
+
+    /// Names the test; it tags the `tracing` span of every run (feature `tracing`).
+    pub fn test_id(mut self, id: impl Into<String>) -> Self {
+        self.config.test_id = Some(id.into());
+        self
+    }
+
+    /// Takes the packet buffer from `pool`, so several runs can share their
+    /// buffers, see [`crate::buffer_pool`].
+    pub fn buffer_pool(mut self, pool: BufferPool) -> Self {
+        self.config.buffers = pool;
+        self
+    }
+
+    /// Pins the thread that calls `run` to CPU `core` for the whole test (Linux only).
+    ///
+    /// With the async variant this is the executor thread polling the run, so
+    /// it only makes sense on a current-thread runtime.
+    pub fn pin_to_core(mut self, core: usize) -> Self {
+        self.config.tuning.core = Some(core);
+        self
+    }
+
+    /// Runs the test thread under the `SCHED_FIFO` realtime class (Linux only),
+    /// which needs `CAP_SYS_NICE` or an `RLIMIT_RTPRIO` allowance.
+    ///
+    /// A realtime thread that never sleeps can starve everything else on its
+    /// core, combine it with [`Self::pin_to_core`] on a spare core.
+    pub fn realtime_priority(mut self, enabled: bool) -> Self {
+        self.config.tuning.realtime = enabled;
+        self
+    }
+
+    /// Probing mode: sends trains of `length` back-to-back packets, a new one
+    /// every `spacing`, instead of pacing at the bitrate.
+    ///
+    /// The server turns the arrival spread of the trains into a
+    /// [`crate::BandwidthEstimate`] of the available bandwidth, see
+    /// [`crate::UdpServer::bandwidth_estimate`]. `length` is clamped to `2..=65535`.
+    pub fn packet_trains(mut self, length: usize, spacing: Duration) -> Self {
+        self.config.trains = Some(TrainConfig {
+            length: length.clamp(2, u16::MAX as usize) as u16,
+            spacing,
+        });
+        self
+    }
+
+    /// Sets how the client waits for the send time of every packet, see
+    /// [`PacingPolicy`]. Defaults to sleeping, then yielding the last
+    /// stretch; spinning keeps to the schedule more closely on a busy or
+    /// coarse-timer host at the cost of a whole core.
+    pub fn pacing_policy(mut self, policy: PacingPolicy) -> Self {
+        self.config.pacing_policy = policy;
+        self
+    }
+
+    /// Sends non-blocking: a packet the full send buffer refuses is skipped
+    /// and counted in [`crate::ClientReport::would_block`] instead of
+    /// stalling the send loop, so [`crate::ClientReport::limit`] can tell a
+    /// network-limited run from a sender-limited one. Always on with
+    /// [`Rate::Unlimited`].
+    ///
+    /// Only the sync client: async sends wait for the socket to be writable.
+    pub fn nonblocking_send(mut self) -> Self {
+        self.config.nonblocking_send = true;
+        self
+    }
+
+    /// Retries a send that failed with a transient error (`EINTR`,
+    /// `ENOBUFS`...) as `policy` says, counting the retries in
+    /// [`crate::ClientReport::send_retries`]. A packet still refused after
+    /// the last retry is skipped and counted in
+    /// [`crate::ClientReport::send_failures`] instead of ending the test;
+    /// other errors, e.g. a refused connection, still do.
+    ///
+    /// Without a policy only `EINTR` and a full send buffer skip the
+    /// packet, any other error ends the test.
+    pub fn send_retry(mut self, policy: RetryPolicy) -> Self {
+        self.config.send_retry = Some(policy);
+        self
+    }
+
+    /// Keeps sending while the peer refuses the packets with an ICMP
+    /// unreachable (`ECONNREFUSED` when the server is not listening yet,
+    /// or restarting), for up to `grace` from the first refusal. Refused
+    /// packets are skipped and counted in
+    /// [`crate::ClientReport::send_failures`], the episodes are listed in
+    /// [`crate::ClientReport::unreachable`].
+    ///
+    /// Without a grace, or once it is over, the test ends with
+    /// [`UdpOptError::PeerUnreachable`].
+    pub fn unreachable_grace(mut self, grace: Duration) -> Self {
+        self.config.unreachable_grace = Some(grace);
+        self
+    }
+
+    /// Ends the test gracefully on Ctrl-C / SIGTERM, see [`crate::shutdown`].
+    #[cfg(feature = "signal")]
+    pub fn stop_on_signal(mut self) -> Self {
+        self.config.stop_on_signal = true;
+        self
+    }
+
+    /// Builds a blocking [`UdpClient`].
+    pub fn build(self, control_rx: std::sync::mpsc::Receiver<ClientCommand>) -> UdpClient {
+        UdpClient::from_config(self.config, control_rx)
+    }
+
+    /// Builds a tokio based [`AsyncUdpClient`].
+    pub fn build_async(
+        self,
+        control_rx: tokio::sync::mpsc::Receiver<ClientCommand>,
+    ) -> AsyncUdpClient {
+        AsyncUdpClient::from_config(self.config, control_rx)
+    }
+
+    /// Builds a [`UdpClient`] and runs it on `sock` on its own thread,
+    /// controlled through the returned [`ClientHandle`], see [`crate::handle`].
+    pub fn spawn<S: DatagramSocket + Send + 'static>(self, mut sock: S) -> ClientHandle {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut client = self.build(rx);
+        ClientHandle::new(tx, std::thread::spawn(move || client.run(&mut sock)))
+    }
+
+    /// Builds an [`AsyncUdpClient`] and runs it on `sock` on a tokio task,
+    /// controlled through the returned [`AsyncClientHandle`].
+    ///
+    /// # Panics
+    /// Outside of a tokio runtime.
+    pub fn spawn_async(self, mut sock: tokio::net::UdpSocket) -> AsyncClientHandle {
+        let (tx, rx) = tokio::sync::mpsc::channel(CONTROL_CAPACITY);
+        let mut client = self.build_async(rx);
+        AsyncClientHandle::new(tx, tokio::spawn(async move { client.run(&mut sock).await }))
+    }
+}
@@ -5,33 +5,50 @@
 //! commands via an `mpsc` channel.
 
 use std::{
-    net::UdpSocket,
-    sync::mpsc::Receiver,
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
     time::{Duration, Instant},
 };
 
 use crate::{
+    builder::ClientConfig,
     errors::UdpOptError,
+    handle::ClientHandle,
+    result::ClientReport,
+    socket::DatagramSocket,
     utils::{
-        net_utils::{ClientCommand, interval_per_packet},
-        random_utils::RandomToSend,
-        udp_data::{FLAG_DATA, FLAG_FIN, UdpHeader, now_micros},
+        net_utils::{
+            ClientCommand, ClientProgress, NonBlocking, PauseOutcome, RetryPolicy, instant_at,
+            is_too_big_error, is_transient_io_error, is_transient_send_error, is_unreachable_error,
+            pacing_target, wait_until_unless,
+        },
+        pacer::Pacer,
+        results_exchange::recv_results,
+        send_data::SendData,
+        udp_data::{
+            FLAG_DATA, FLAG_FIN, FLAG_HEARTBEAT, FLAG_STATS, UdpHeader, WireFormat, new_session_id,
+            now_micros,
+        },
     },
 };
 
+/// Packets sent between two checks of the control channel at high rates
+const CONTROL_POLL_PACKETS: u64 = 64;
+/// Pacing slot from which the control channel is checked before every packet
+const CONTROL_POLL_SLOT: Duration = Duration::from_micros(100);
+
 #[derive(Debug)]
 pub struct UdpClient {
-    /// Target sending bitrate in bits per second.
-    bitrate_bps: f64,
-
-    /// Size of each UDP packet payload, including header.
-    payload_size: usize,
-
-    /// Maximum duration for the transmission test.
-    timeout: Duration,
+    /// Rate, payload size, duration and observers
+    pub(crate) config: ClientConfig,
 
     /// Receiver for control commands (`Start`, `Stop`) from another thread.
     control_rx: Receiver<ClientCommand>,
+
+    /// A command taken off the channel before the send loop got to it
+    pending: Option<ClientCommand>,
 }
 
 impl UdpClient {
@@ -40,7 +57,7 @@ impl UdpClient {
     /// # Parameters
     /// - `bitrate_bps`: Desired sending bitrate in bits per second.
     /// - `payload_size`: Number of bytes in each packet (typically 512–1500 bytes).
-    /// - `timeout`: Total duration to keep sending packets.
+    /// - `timeout`: Total duration to keep sending packets, `None` to send until `Stop`.
     /// - `control_rx`: Channel to receive [`ClientCommand`] control signals.
     ///
     /// # Returns
@@ -48,112 +65,477 @@ impl UdpClient {
     pub fn new(
         bitrate_bps: f64,
         payload_size: usize,
-        timeout: Duration,
+        timeout: impl Into<Option<Duration>>,
         control_rx: Receiver<ClientCommand>,
     ) -> Self {
+        Self::from_config(
+            ClientConfig::new(bitrate_bps, payload_size, timeout),
+            control_rx,
+        )
+    }
+
+    pub(crate) fn from_config(config: ClientConfig, control_rx: Receiver<ClientCommand>) -> Self {
         Self {
-            bitrate_bps,
-            payload_size,
-            timeout,
+            config,
             control_rx,
+            pending: None,
         }
     }
 
     /// Runs the UDP client, sending packets to the specified destination.
     ///
-    /// - Waits for a `Start` command from the control channel before sending.
+    /// - Waits for a `Start` command from the control channel before sending,
+    ///   or with `StartAt` until the given wall-clock time.
     /// - Sends packets according to the configured bitrate and payload size.
-    /// - Stops after `timeout` duration or when the control channel sends `Stop`.
+    /// - Stops after `timeout` duration or when the control channel sends `Stop`;
+    ///   without a `timeout` it only stops on `Stop`.
+    /// - Also stops once the `stop_after_bytes` / `stop_after_packets` limit is reached.
+    /// - Also stops on Ctrl-C / SIGTERM if built with `stop_on_signal` (feature `signal`).
+    /// - `Pause` halts sending until `Resume`; the paused time does not count towards `timeout`.
     /// - Sends a FIN packet at the end to notify the server.
     ///
     /// # Parameters
-    /// - `sock`: A bound [`UdpSocket`] that will be used to send packets.
+    /// - `sock`: A connected [`DatagramSocket`] that will be used to send packets.
     ///
-    /// Returns:
+    /// Returns a [`ClientReport`] with the transmit statistics, or:
     /// - [`UdpOptError::SendFailed`] if sending fails.
-    /// - [`UdpOptError::FailToGetRandom`] if payload randomization fails.
+    /// - [`UdpOptError::PayloadFailed`] if the payload source fails.
+    /// - [`UdpOptError::SchedulingFailed`] if the core pinning or realtime priority cannot be applied.
     /// - [`UdpOptError::ChannelClosed`] if control channel disconnects before start.
     /// - [`UdpOptError::UnexpectedCommand`] if an unexpected command is received.
+    pub fn run<S: DatagramSocket>(&mut self, sock: &mut S) -> Result<ClientReport, UdpOptError> {
+        // wait for the start udp packet to start the test and set the buf lenght
+        self.wait_start()?;
+        self.transmit(sock, WireFormat::Native)
+    }
 
-    pub fn run(&mut self, sock: &mut UdpSocket) -> Result<(), UdpOptError> {
-        let ipp = interval_per_packet(self.payload_size, self.bitrate_bps);
+    /// A socket bound to the address and source ports given with
+    /// [`ClientBuilder::bind`](crate::ClientBuilder::bind) and
+    /// [`ClientBuilder::source_ports`](crate::ClientBuilder::source_ports),
+    /// connected to `server`, for [`run`](Self::run).
+    ///
+    /// # Errors
+    /// [`UdpOptError::BindFailed`] or [`UdpOptError::ConnectFailed`].
+    pub fn connect(&self, server: SocketAddr) -> Result<UdpSocket, UdpOptError> {
+        self.config.connect(server)
+    }
 
-        let mut seq: u64 = 0;
+    /// Runs the client on its own thread, controlled through the returned
+    /// [`ClientHandle`] instead of the channel it was built with;
+    /// [`ClientBuilder::spawn`](crate::ClientBuilder::spawn) needs no channel at all.
+    pub fn spawn<S: DatagramSocket + Send + 'static>(mut self, mut sock: S) -> ClientHandle {
+        let (tx, rx) = mpsc::channel();
+        self.control_rx = rx;
+        ClientHandle::new(tx, thread::spawn(move || self.run(&mut sock)))
+    }
 
-        let mut buf = vec![0u8; self.payload_size];
+    /// Blocks until the `Start` command arrives on the control channel, or
+    /// until the time given by `StartAt`.
+    ///
+    /// A `Stop` or a signal during the wait for `StartAt` ends it, the send
+    /// loop then stops before the first packet.
+    pub(crate) fn wait_start(&mut self) -> Result<(), UdpOptError> {
+        self.config.watch_signals()?;
+        loop {
+            match self.control_rx.recv() {
+                Ok(ClientCommand::Start) => return Ok(()),
+                Ok(ClientCommand::StartAt(at)) => {
+                    event!(info, "waiting for the scheduled start");
+                    let mut unexpected = false;
+                    wait_until_unless(instant_at(at), || {
+                        match self.control_rx.try_recv() {
+                            Ok(ClientCommand::Stop) => self.pending = Some(ClientCommand::Stop),
+                            Ok(ClientCommand::SetRate(rate)) => self.config.set_rate(rate),
+                            Ok(ClientCommand::Status(reply)) => {
+                                let _ = reply.send(ClientProgress::default());
+                            }
+                            Ok(_) => unexpected = true,
+                            Err(_) => {}
+                        }
+                        unexpected || self.pending.is_some() || self.config.shutdown_requested()
+                    });
+                    if unexpected {
+                        return Err(UdpOptError::UnexpectedCommand);
+                    }
+                    return Ok(());
+                }
+                // nothing sent yet
+                Ok(ClientCommand::Status(reply)) => {
+                    let _ = reply.send(ClientProgress::default());
+                }
+                Ok(_) => return Err(UdpOptError::UnexpectedCommand),
+                Err(_) => return Err(UdpOptError::ChannelClosed),
+            }
+        }
+    }
 
-        let mut random = RandomToSend::new().map_err(|e| UdpOptError::FailToGetRandom(e))?;
+    /// Paced send loop shared by the native and the compatibility protocols.
+    pub(crate) fn transmit<S: DatagramSocket>(
+        &mut self,
+        sock: &S,
+        format: WireFormat,
+    ) -> Result<ClientReport, UdpOptError> {
+        #[cfg(feature = "tracing")]
+        let _span = run_span!("udpopt_client", self.config.test_id).entered();
+        if let Ok(peer) = sock.peer_addr() {
+            record_peer!(peer);
+        }
+        // in probing mode a slot holds a whole train
+        let (mut ipp, mut per_slot) = self.config.pacing();
+        let train_len = self.config.train_len();
+        let session = new_session_id();
 
-        // wait for the start udp packet to start the test and set the buf lenght
-        match self.control_rx.recv() {
-            Ok(ClientCommand::Stop) => return Err(UdpOptError::UnexpectedCommand),
-            Ok(ClientCommand::Start) => {}
-            Err(_) => return Err(UdpOptError::ChannelClosed),
+        let mut seq: u64 = 0;
+
+        let mut buf = self.config.buffers.get(self.config.max_packet_len());
+
+        self.config
+            .tuning
+            .apply()
+            .map_err(UdpOptError::SchedulingFailed)?;
+        if let Some(device) = &self.config.device {
+            sock.bind_device(device)
+                .map_err(UdpOptError::DeviceFailed)?;
+        }
+        if let Some(tos) = self.config.tos() {
+            sock.set_tos(tos).map_err(UdpOptError::DscpFailed)?;
+        } else if self.config.ecn {
+            sock.set_ect0().map_err(UdpOptError::EcnFailed)?;
         }
-        println!("client start");
+        if let Some(on) = self.config.dont_fragment {
+            sock.set_dont_fragment(on)
+                .map_err(UdpOptError::FragmentationFailed)?;
+        }
+        event!(
+            info,
+            bitrate_bps = self.config.bitrate_bps(),
+            payload_size = self.config.payload_size,
+            "sending"
+        );
 
-        let start = Instant::now();
+        let mut unlimited = self.config.unlimited();
+        let nonblocking = if self.config.nonblocking() {
+            Some(NonBlocking::new(sock).map_err(UdpOptError::NonBlockingFailed)?)
+        } else {
+            None
+        };
+        // calibrated before the clock starts
+        let pacer = Pacer::new(self.config.pacing_policy);
+        let mut start = Instant::now();
+        let mut next_progress = start + self.config.progress_interval;
+        let mut next_stats = self.config.stats_interval.map(|every| start + every);
+        let mut interval_start = start;
+        let mut stats = SendData::new()
+            .with_pacing(ipp, per_slot)
+            .with_calibration(pacer.calibration());
+        if let (Ok(mtu), Ok(peer)) = (sock.path_mtu(), sock.peer_addr()) {
+            stats = stats.with_path_mtu(mtu, peer.ip().to_canonical().is_ipv6());
+        }
+        let mut faults = self.config.faults.map(|f| f.start());
+        // pacing slot, unlike `seq` it also advances when a send is skipped
+        let mut tick: u64 = 0;
+        // the schedule restarts from this slot and instant on a rate change
+        let (mut pace_tick, mut pace_start) = (tick, start);
+        let mut poll_every = control_poll_every(ipp);
+        // a command that cut a wait short, handled before the next packet
+        let mut pending = self.pending.take();
 
         loop {
-            if start.elapsed() >= self.timeout {
+            if !unlimited {
+                let slot = (tick - pace_tick) / per_slot;
+                let target = pacing_target(slot, ipp, pace_start);
+                // a gap longer than the heartbeat interval is bridged with
+                // heartbeats, a command arriving meanwhile cuts the wait short
+                if let Some(every) = self.config.heartbeat {
+                    let mut beat = Instant::now() + every;
+                    while beat < target && pending.is_none() {
+                        pending = pacer.wait_until_recv(beat, &self.control_rx);
+                        if pending.is_some() {
+                            break;
+                        }
+                        let packet = &mut buf[..self.config.heartbeat_len()];
+                        self.config
+                            .write_control(packet, FLAG_HEARTBEAT, seq, session);
+                        send_control(sock, packet, self.config.unreachable_grace.is_some())?;
+                        beat += every;
+                    }
+                }
+                if pending.is_none() {
+                    pending = pacer.wait_until_recv(target, &self.control_rx);
+                }
+            }
+
+            if self.config.timeout.is_some_and(|t| start.elapsed() >= t)
+                || self
+                    .config
+                    .limit_reached(stats.packets_sent(), stats.bytes_sent())
+                || self.config.shutdown_requested()
+            {
                 break;
             }
 
-            random
-                .fill(&mut buf)
-                .map_err(|e| UdpOptError::FailToGetRandom(e))?; //  note you can use any random  base insted of using the unix_epoch
+            // Check control messages
+            let interrupted = pending.is_some();
+            let command = if let Some(command) = pending.take() {
+                Ok(command)
+            } else if tick.is_multiple_of(poll_every) {
+                self.control_rx.try_recv()
+            } else {
+                Err(TryRecvError::Empty)
+            };
+            let rate = self.config.rate;
+            match command {
+                Ok(ClientCommand::Stop) => {
+                    event!(info, "stop command received");
+                    break;
+                }
+                Ok(ClientCommand::Pause) => {
+                    match self.wait_resume(stats.progress(start.elapsed()))? {
+                        PauseOutcome::Resumed(paused) => {
+                            // shift the timeline so pacing and timeout ignore the pause
+                            start += paused;
+                            pace_start += paused;
+                            next_progress += paused;
+                            if let Some(at) = next_stats.as_mut() {
+                                *at += paused;
+                            }
+                            interval_start += paused;
+                        }
+                        PauseOutcome::Stopped => break,
+                    }
+                }
+                Ok(ClientCommand::SetRate(rate)) => self.config.set_rate(rate),
+                // the caller may have given up waiting
+                Ok(ClientCommand::Status(reply)) => {
+                    let _ = reply.send(stats.progress(start.elapsed()));
+                }
+                Ok(_) => return Err(UdpOptError::UnexpectedCommand),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {}
+            }
+            if self.config.rate != rate {
+                (ipp, per_slot) = self.config.pacing();
+                unlimited = self.config.unlimited();
+                poll_every = control_poll_every(ipp);
+                (pace_tick, pace_start) = (tick, Instant::now());
+                stats.set_pacing(ipp, per_slot, start.elapsed());
+            }
+            // the wait for the next packet is not over
+            if interrupted {
+                continue;
+            }
+
+            let packet = &mut buf[..self.config.packet_len(seq)];
+            self.config
+                .payload
+                .fill(packet)
+                .map_err(UdpOptError::PayloadFailed)?;
 
             let (sec, usec) = now_micros();
 
-            let mut header = UdpHeader::new(seq, sec, usec, FLAG_DATA);
-            header.write_header(&mut buf);
+            let header = UdpHeader::new(seq, sec, usec, FLAG_DATA)
+                .with_train(train_len)
+                .with_session(session)
+                .with_sender(seq + 1, self.config.header_rate_bps());
+            format.write_header(packet, header);
+            self.config.seal(packet);
+
+            let late = if unlimited {
+                Duration::ZERO
+            } else {
+                let slot = (tick - pace_tick) / per_slot;
+                Instant::now().saturating_duration_since(pacing_target(slot, ipp, pace_start))
+            };
+            // a withheld packet counts as sent, as far as the server knows it left
+            let sent = if faults
+                .as_mut()
+                .is_none_or(|f| f.admit(packet, Instant::now()))
+            {
+                send_retrying(sock, packet, self.config.send_retry, &mut stats)
+            } else {
+                Ok(packet.len())
+            };
+            match sent {
+                Ok(len) => {
+                    stats.record_sent(len, late);
+                    seq += 1;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => stats.record_would_block(),
+                Err(e) if is_transient_send_error(&e) => stats.record_failure(),
+                // longer than the path MTU with the don't-fragment bit set
+                Err(e) if self.config.dont_fragment == Some(true) && is_too_big_error(&e) => {
+                    stats.record_too_big();
+                    if let Ok(mtu) = sock.path_mtu() {
+                        stats.set_path_mtu(mtu);
+                    }
+                }
+                // out of retries
+                Err(e) if self.config.send_retry.is_some() && is_transient_io_error(&e) => {
+                    stats.record_failure();
+                }
+                // skipped while the grace lasts
+                Err(e)
+                    if is_unreachable_error(&e)
+                        && self.config.unreachable_grace.is_some_and(|grace| {
+                            stats.record_unreachable(start.elapsed(), grace)
+                        }) => {}
+                Err(e) => {
+                    return Err(UdpOptError::send_failed(
+                        sock.peer_addr().ok(),
+                        packet.len(),
+                    )(e));
+                }
+            }
+            tick += 1;
+            if let Some(f) = faults.as_mut() {
+                f.send_due(sock, false)?;
+            }
 
-            sock.send(&buf).map_err(|e| UdpOptError::SendFailed(e))?;
+            let now = Instant::now();
+            if now >= next_progress {
+                stats.close_interval(now - interval_start);
+                interval_start = now;
+                self.config.emit_progress(&stats.progress(start.elapsed()));
+                next_progress += self.config.progress_interval;
+            }
+            if let (Some(at), Some(every)) = (next_stats, self.config.stats_interval)
+                && now >= at
+            {
+                let packet = &mut buf[..self.config.packet_len(seq)];
+                self.config.write_control(packet, FLAG_STATS, seq, session);
+                send_control(sock, packet, self.config.unreachable_grace.is_some())?;
+                next_stats = Some(at + every);
+            }
+        }
+        drop(nonblocking);
 
-            seq += 1;
-            time_to_next_target(seq, ipp, start);
+        if let Some(f) = faults.as_mut() {
+            f.send_due(sock, true)?;
         }
 
         // Send a final packet (FIN flag) to notify completion.
-        let (sec, usec) = now_micros();
-        let mut fin = UdpHeader::new(seq, sec, usec, FLAG_FIN);
-        fin.write_header(&mut buf);
-
-        sock.send(&buf).map_err(|e| UdpOptError::SendFailed(e))?;
-        println!("Client done. Sent {} packets (+FIN)", seq);
+        if format.has_fin() {
+            let (sec, usec) = now_micros();
+            // the FIN carries the final count of packets sent
+            let fin = || {
+                UdpHeader::new(seq, sec, usec, FLAG_FIN)
+                    .with_session(session)
+                    .with_sender(seq + 1, self.config.header_rate_bps())
+            };
+            format.write_header(&mut buf, fin());
+            self.config.seal(&mut buf);
+            let mut len = buf.len();
+            let mut sent = sock.send(&buf);
+            // refused as too long with the don't-fragment bit, a short one fits
+            if sent.as_ref().is_err_and(is_too_big_error) {
+                len = self.config.heartbeat_len();
+                format.write_header(&mut buf[..len], fin());
+                self.config.seal(&mut buf[..len]);
+                sent = sock.send(&buf[..len]);
+            }
+            sent.map_err(UdpOptError::send_failed(sock.peer_addr().ok(), len))?;
+            event!(info, seq, "FIN sent");
+        }
+        self.config.emit_progress(&stats.progress(start.elapsed()));
+
+        let mut report = stats.into_report(start.elapsed(), interval_start.elapsed());
+        report.faults = faults.map(|f| f.stats()).unwrap_or_default();
+        report.session = session;
+        if let Some(fragmentation) = report.fragmentation.as_mut() {
+            fragmentation.path_mtu = sock.path_mtu().unwrap_or(fragmentation.path_mtu);
+            fragmentation.dont_fragment = self.config.dont_fragment;
+        }
+        if let Some(timeout) = self.config.remote_results.filter(|_| format.has_fin()) {
+            (report.remote, report.public_endpoint) = recv_results(sock, timeout)?;
+            if report.remote.is_none() {
+                event!(warn, "no results from the server");
+            }
+        }
+        event!(
+            info,
+            packets_sent = report.packets_sent,
+            send_failures = report.send_failures,
+            "test finished"
+        );
+        Ok(report)
+    }
 
-        Ok(())
+    /// Blocks until `Resume` or `Stop` arrives on the control channel,
+    /// answering `Status` with the `progress` frozen by the pause.
+    fn wait_resume(&mut self, progress: ClientProgress) -> Result<PauseOutcome, UdpOptError> {
+        let paused_at = Instant::now();
+        loop {
+            match self.control_rx.recv() {
+                Ok(ClientCommand::Resume) => return Ok(PauseOutcome::Resumed(paused_at.elapsed())),
+                Ok(ClientCommand::Stop) => return Ok(PauseOutcome::Stopped),
+                Ok(ClientCommand::Pause) => {}
+                Ok(ClientCommand::SetRate(rate)) => self.config.set_rate(rate),
+                Ok(ClientCommand::Status(reply)) => {
+                    let _ = reply.send(progress);
+                }
+                Ok(ClientCommand::Start | ClientCommand::StartAt(_)) => {
+                    return Err(UdpOptError::UnexpectedCommand);
+                }
+                Err(_) => return Err(UdpOptError::ChannelClosed),
+            }
+        }
     }
 }
 
 //helper function
 
-#[inline]
-fn time_to_next_target(seq: u64, ipp: Duration, start: Instant) {
-    // this section of code determine when the next packet must be sent depnds
-    let next_target = start + Duration::from_secs_f64(seq as f64 * ipp.as_secs_f64());
-    loop {
-        let now = Instant::now();
-        if now >= next_target {
-            break;
-        }
-
-        let remaining = next_target - now;
+/// Packets between two checks of the control channel with slots of `ipp`
+fn control_poll_every(ipp: Duration) -> u64 {
+    if ipp >= CONTROL_POLL_SLOT {
+        1
+    } else {
+        CONTROL_POLL_PACKETS
+    }
+}
 
-        if remaining > Duration::from_micros(200) {
-            // coarse sleep; subtract a small delta to avoid oversleep
-            std::thread::sleep(remaining - Duration::from_micros(100));
-        } else {
-            // using spin here is more acurate but is uses more cpu
-            // short spin / yield
-            std::thread::yield_now();
+/// Sends `packet`, retrying a transient failure as `retry` says
+fn send_retrying<S: DatagramSocket>(
+    sock: &S,
+    packet: &[u8],
+    retry: Option<RetryPolicy>,
+    stats: &mut SendData,
+) -> io::Result<usize> {
+    let mut sent = sock.send(packet);
+    let Some(retry) = retry else {
+        return sent;
+    };
+    for attempt in 0..retry.max_retries {
+        match &sent {
+            Err(e) if retry.retries(e) => {}
+            _ => break,
         }
+        stats.record_retry();
+        thread::sleep(retry.backoff(attempt));
+        sent = sock.send(packet);
+    }
+    sent
+}
+
+/// Sends a STATS packet or a heartbeat, which lost or not is not worth
+/// failing the test for, nor a refusal by an unreachable peer with
+/// `outlast_unreachable`
+fn send_control<S: DatagramSocket>(
+    sock: &S,
+    packet: &[u8],
+    outlast_unreachable: bool,
+) -> Result<(), UdpOptError> {
+    match sock.send(packet) {
+        Err(e) if outlast_unreachable && is_unreachable_error(&e) => Ok(()),
+        Err(e) if !is_transient_send_error(&e) && e.kind() != io::ErrorKind::WouldBlock => Err(
+            UdpOptError::send_failed(sock.peer_addr().ok(), packet.len())(e),
+        ),
+        _ => Ok(()),
     }
 }
 
 #[cfg(test)]
 mod udp_client_tests {
-    use crate::utils::udp_data::HEADER_SIZE;
+    use crate::socket::PortRange;
+    use crate::utils::udp_data::UdpHeader;
 
     use super::*;
     use std::net::UdpSocket;
@@ -187,20 +569,13 @@ mod udp_client_tests {
     }
 
     /// Parses UDP header to extract sequence number and flags
-    /// Adjust based on your actual UdpHeader structure
     fn parse_header(buf: &[u8]) -> Option<(u64, u32)> {
-        if buf.len() < HEADER_SIZE {
-            return None;
-        }
-
-        let seq = u64::from_be_bytes(buf[0..8].try_into().unwrap());
-
-        let flags = u32::from_be_bytes(buf[20..24].try_into().unwrap());
-
-        Some((seq, flags))
+        let header = UdpHeader::parse(buf).ok()?;
+        Some((header.seq, header.flags))
     }
 
     /// Receives packets until FIN or timeout
+    #[allow(clippy::while_let_loop)]
     fn receive_all_packets(sock: &mut UdpSocket, timeout: Duration) -> Vec<(u64, u32, usize)> {
         sock.set_read_timeout(Some(timeout)).unwrap();
         let mut packets = Vec::new();
@@ -245,6 +620,64 @@ mod udp_client_tests {
     }
 
     #[test]
+    fn test_client_start_at() {
+        let (mut client, tx) = create_test_client(1_000_000.0, 512, Duration::from_millis(50));
+        let (_server_sock, mut client_sock) = create_socket_pair();
+
+        let start_at = std::time::SystemTime::now() + Duration::from_millis(200);
+        tx.send(ClientCommand::StartAt(start_at)).unwrap();
+        let sent_at = Instant::now();
+        client.run(&mut client_sock).unwrap();
+        assert!(std::time::SystemTime::now() >= start_at + Duration::from_millis(50));
+        assert!(sent_at.elapsed() >= Duration::from_millis(240));
+    }
+
+    #[test]
+    fn test_stop_before_the_scheduled_start() {
+        let (mut client, tx) = create_test_client(1_000_000.0, 512, Duration::from_secs(1));
+        let (_server_sock, mut client_sock) = create_socket_pair();
+
+        let start_at = std::time::SystemTime::now() + Duration::from_secs(10);
+        tx.send(ClientCommand::StartAt(start_at)).unwrap();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            tx.send(ClientCommand::Stop).unwrap();
+        });
+        let sent_at = Instant::now();
+        let report = client.run(&mut client_sock).unwrap();
+        stopper.join().unwrap();
+        assert!(sent_at.elapsed() < Duration::from_secs(1));
+        assert_eq!(report.packets_sent, 0);
+    }
+
+    #[test]
+    fn test_connect_from_source_ports() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let (_tx, rx) = mpsc::channel();
+
+        let client = crate::ClientBuilder::new(1e6, 512, Duration::from_millis(50))
+            .bind("127.0.0.1:0".parse().unwrap())
+            .build(rx);
+        let sock = client.connect(server_addr).unwrap();
+        assert!(sock.local_addr().unwrap().ip().is_loopback());
+        assert_eq!(sock.peer_addr().unwrap(), server_addr);
+
+        // the range starts on a taken port, the client moves past it
+        let taken = sock.local_addr().unwrap().port();
+        let ports = PortRange::new(taken, taken.saturating_add(20));
+        let (_tx, rx) = mpsc::channel();
+        let client = crate::ClientBuilder::new(1e6, 512, Duration::from_millis(50))
+            .bind("127.0.0.1:0".parse().unwrap())
+            .source_ports(ports)
+            .build(rx);
+        let sock = client.connect(server_addr).unwrap();
+        let port = sock.local_addr().unwrap().port();
+        assert!(ports.contains(port) && port != taken);
+    }
+
+    #[test]
+    #[allow(clippy::len_zero)]
     fn test_client_sends_packets() {
         let bitrate = 5_000_000.0; // 5 Mbps
         let payload_size = 512;
@@ -323,6 +756,407 @@ mod udp_client_tests {
         }
     }
 
+    #[test]
+    fn test_stop_after_packets_and_bytes() {
+        use crate::builder::ClientBuilder;
+
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(50_000_000.0, 512, Duration::from_secs(10))
+            .stop_after_packets(50)
+            .build(rx);
+        let (_server_sock, mut client_sock) = create_socket_pair();
+        tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+        assert_eq!(report.packets_sent, 50);
+
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(50_000_000.0, 512, None)
+            .stop_after_bytes(10_000)
+            .build(rx);
+        tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+        // 19 packets fit in the quota, a 20th would pass it
+        assert_eq!(report.packets_sent, 19);
+        assert_eq!(report.bytes_sent, 19 * 512);
+    }
+
+    #[test]
+    fn test_packets_shorter_than_the_header() {
+        use crate::{builder::ClientBuilder, socket::MockSocket, utils::udp_data::HEADER_SIZE};
+
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(50_000_000.0, 40, Duration::from_secs(10))
+            .stop_after_packets(5)
+            .build(rx);
+        let mut sock = MockSocket::new();
+        sock.connect("192.0.2.1:5201".parse().unwrap());
+        tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut sock).unwrap();
+        assert_eq!(report.bytes_sent, 5 * HEADER_SIZE as u64);
+        assert!(
+            sock.take_sent()
+                .iter()
+                .all(|(packet, _)| packet.len() == HEADER_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_fault_injection_ground_truth() {
+        use crate::{FaultInjector, ServerBuilder, ServerCommand, builder::ClientBuilder};
+
+        let (server_sock, mut client_sock) = create_socket_pair();
+        let mut server_sock = server_sock;
+        let (server_tx, server_rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(5)).build(server_rx);
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        let faults = FaultInjector::new()
+            .drop(0.05)
+            .duplicate(0.05)
+            .reorder(0.05)
+            // leaves the first packet alone, the server does not count it
+            .seed(2);
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(4_000_000.0, 500, Duration::from_secs(10))
+            .stop_after_packets(400)
+            .fault_injector(faults)
+            .build(rx);
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+        let results = handle.join().unwrap().unwrap();
+
+        let f = report.faults;
+        assert!(
+            f.dropped > 0 && f.duplicated > 0 && f.reordered > 0,
+            "{f:?}"
+        );
+        assert_eq!(report.packets_sent, 400);
+        assert_eq!(results.iter().map(|r| r.lost).sum::<u64>(), f.dropped);
+        assert_eq!(
+            results.iter().map(|r| r.duplicates).sum::<u64>(),
+            f.duplicated
+        );
+        assert_eq!(
+            results.iter().map(|r| r.out_of_order).sum::<u64>(),
+            f.reordered
+        );
+    }
+
+    #[test]
+    fn test_client_over_mock_socket() {
+        use crate::{builder::ClientBuilder, socket::MockSocket};
+
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(50_000_000.0, 512, Duration::from_secs(10))
+            .stop_after_packets(20)
+            .build(rx);
+        let mut sock = MockSocket::new();
+        sock.connect("192.0.2.1:5201".parse().unwrap());
+        tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut sock).unwrap();
+
+        let sent: Vec<(u64, u32)> = sock
+            .take_sent()
+            .iter()
+            .map(|(packet, _)| parse_header(packet).unwrap())
+            .collect();
+        assert_eq!(report.packets_sent, 20);
+        assert_eq!(sent.len(), 21);
+        assert!(
+            sent[..20]
+                .iter()
+                .enumerate()
+                .all(|(i, &(seq, _))| seq == i as u64)
+        );
+        assert_eq!(sent[20].1, FLAG_FIN);
+    }
+
+    #[test]
+    fn test_send_retry() {
+        use crate::{RetryPolicy, builder::ClientBuilder, socket::MockSocket};
+        use std::io::{Error, ErrorKind};
+
+        let policy = RetryPolicy {
+            max_retries: 2,
+            backoff: Duration::from_micros(10),
+            max_backoff: Duration::from_micros(20),
+        };
+        let run = |errors: Vec<Error>| {
+            let (tx, rx) = channel();
+            let mut client = ClientBuilder::new(8_000_000.0, 500, Duration::from_secs(10))
+                .send_retry(policy)
+                .stop_after_packets(5)
+                .build(rx);
+            let mut sock = MockSocket::new();
+            sock.connect("192.0.2.1:5201".parse().unwrap());
+            sock.fail_sends(errors);
+            tx.send(ClientCommand::Start).unwrap();
+            client.run(&mut sock)
+        };
+
+        // the first packet is out of retries and skipped, the second goes
+        // out on its retry
+        let report = run(vec![
+            ErrorKind::TimedOut.into(),
+            ErrorKind::Interrupted.into(),
+            ErrorKind::TimedOut.into(),
+            ErrorKind::Interrupted.into(),
+        ])
+        .unwrap();
+        assert_eq!(report.packets_sent, 5);
+        assert_eq!(report.send_failures, 1);
+        assert_eq!(report.send_retries, 3);
+
+        // not retried
+        let err = run(vec![ErrorKind::PermissionDenied.into()]).unwrap_err();
+        assert!(matches!(err, UdpOptError::SendFailed { bytes: 500, .. }));
+        assert!(!err.is_transient());
+
+        // ENOBUFS, a full queue of the interface
+        #[cfg(target_os = "linux")]
+        {
+            let report = run(vec![Error::from_raw_os_error(105)]).unwrap();
+            assert_eq!((report.packets_sent, report.send_retries), (5, 1));
+        }
+    }
+
+    #[test]
+    fn test_unreachable_grace() {
+        use crate::{builder::ClientBuilder, socket::MockSocket};
+        use std::io::ErrorKind;
+
+        let peer = "192.0.2.1:5201".parse().unwrap();
+        let run = |grace: Option<Duration>| {
+            let (tx, rx) = channel();
+            let mut builder =
+                ClientBuilder::new(8_000_000.0, 500, Duration::from_secs(10)).stop_after_packets(5);
+            if let Some(grace) = grace {
+                builder = builder.unreachable_grace(grace);
+            }
+            let mut client = builder.build(rx);
+            let mut sock = MockSocket::new();
+            sock.connect(peer);
+            // the server is not listening yet
+            sock.fail_sends((0..3).map(|_| ErrorKind::ConnectionRefused.into()));
+            tx.send(ClientCommand::Start).unwrap();
+            client.run(&mut sock)
+        };
+
+        let err = run(None).unwrap_err();
+        assert!(matches!(err, UdpOptError::PeerUnreachable { .. }));
+        assert_eq!(err.peer(), Some(peer));
+        assert!(!err.is_transient());
+
+        let report = run(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(report.packets_sent, 5);
+        assert_eq!(report.send_failures, 3);
+        assert_eq!(report.unreachable.len(), 1);
+        assert_eq!(report.unreachable[0].refusals, 3);
+        assert!(report.unreachable[0].duration < Duration::from_secs(1));
+
+        // refused for longer than the grace
+        let err = run(Some(Duration::ZERO)).unwrap_err();
+        assert!(matches!(err, UdpOptError::PeerUnreachable { .. }));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_dont_fragment() {
+        use crate::{builder::ClientBuilder, socket::MockSocket};
+        use std::io::Error;
+
+        let run = |dont_fragment: Option<bool>| {
+            let (tx, rx) = channel();
+            let mut builder = ClientBuilder::new(8_000_000.0, 3000, Duration::from_secs(10))
+                .stop_after_packets(4);
+            if let Some(on) = dont_fragment {
+                builder = builder.dont_fragment(on);
+            }
+            let mut client = builder.build(rx);
+            let mut sock = MockSocket::new();
+            sock.connect("192.0.2.1:5201".parse().unwrap());
+            sock.set_path_mtu(1500);
+            // EMSGSIZE
+            sock.fail_sends((0..2).map(|_| Error::from_raw_os_error(90)));
+            tx.send(ClientCommand::Start).unwrap();
+            client.run(&mut sock).map(|report| (report, sock))
+        };
+
+        let (report, sock) = run(Some(true)).unwrap();
+        assert_eq!(sock.dont_fragment(), Some(true));
+        assert_eq!((report.packets_sent, report.too_big), (4, 2));
+        assert_eq!(report.send_failures, 2);
+        let fragmentation = report.fragmentation.unwrap();
+        assert_eq!(fragmentation.dont_fragment, Some(true));
+        assert_eq!(
+            (fragmentation.fragmented, fragmentation.max_fragments),
+            (4, 3)
+        );
+        assert_eq!(fragmentation.ip_packets, 12);
+        assert_eq!(sock.take_sent().len(), 5);
+
+        // the kernel fragments it, an EMSGSIZE is an error then
+        let err = run(None).unwrap_err();
+        assert!(matches!(err, UdpOptError::SendFailed { bytes: 3000, .. }));
+    }
+
+    #[test]
+    fn test_rate_in_packets_per_second() {
+        use crate::{Rate, builder::ClientBuilder, socket::MockSocket};
+
+        // 500 pps is 2 ms a packet whatever their length
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(50_000_000.0, 512, Duration::from_secs(10))
+            .rate(Rate::Pps(500.0))
+            .stop_after_packets(10)
+            .build(rx);
+        let mut sock = MockSocket::new();
+        sock.connect("192.0.2.1:5201".parse().unwrap());
+        tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut sock).unwrap();
+
+        assert_eq!(report.packets_sent, 10);
+        assert!(report.duration >= Duration::from_millis(18));
+        assert!(report.pps > 0.0 && report.pps <= 560.0, "{}", report.pps);
+        assert_eq!(report.compliance.intended_packets, 10);
+    }
+
+    #[test]
+    fn test_unlimited_rate() {
+        use crate::{Rate, builder::ClientBuilder, socket::MockSocket};
+
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(1_000.0, 512, Duration::from_secs(10))
+            .rate(Rate::Unlimited)
+            .stop_after_packets(2000)
+            .build(rx);
+        let mut sock = MockSocket::new();
+        sock.connect("192.0.2.1:5201".parse().unwrap());
+        tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut sock).unwrap();
+
+        // 1000 bps would take minutes, unpaced it is instant
+        assert_eq!(report.packets_sent, 2000);
+        assert!(report.duration < Duration::from_secs(5));
+        assert_eq!(report.would_block, 0);
+        assert_eq!(report.compliance.intended_packets, 0);
+        assert_eq!(report.pacing_error.max(), 0);
+        assert_eq!(sock.take_sent().len(), 2001);
+    }
+
+    #[test]
+    fn test_payload_source() {
+        use crate::utils::udp_data::HEADER_SIZE;
+        use crate::{builder::ClientBuilder, payload::Zeroes, socket::MockSocket};
+
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(50_000_000.0, 512, Duration::from_secs(10))
+            .stop_after_packets(5)
+            .payload(Zeroes)
+            .build(rx);
+        let mut sock = MockSocket::new();
+        sock.connect("192.0.2.1:5201".parse().unwrap());
+        tx.send(ClientCommand::Start).unwrap();
+        client.run(&mut sock).unwrap();
+
+        let sent = sock.take_sent();
+        assert_eq!(sent.len(), 6);
+        assert!(
+            sent.iter()
+                .all(|(packet, _)| packet[HEADER_SIZE..].iter().all(|&b| b == 0))
+        );
+    }
+
+    #[test]
+    fn test_progress_observer_is_called() {
+        use crate::builder::ClientBuilder;
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_cb = seen.clone();
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(5_000_000.0, 512, Duration::from_millis(200))
+            .progress_interval(Duration::from_millis(50))
+            .on_progress(Box::new(move |p| seen_cb.lock().unwrap().push(*p)))
+            .build(rx);
+        let (_server_sock, mut client_sock) = create_socket_pair();
+
+        tx.send(ClientCommand::Start).unwrap();
+        client.run(&mut client_sock).unwrap();
+
+        let seen = seen.lock().unwrap();
+        // periodic reports plus the final one
+        assert!(seen.len() >= 3, "got {} progress reports", seen.len());
+        let last = seen.last().unwrap();
+        assert!(last.packets_sent > 0);
+        assert_eq!(last.bytes_sent, last.packets_sent * 512);
+        assert!(
+            seen.windows(2)
+                .all(|w| w[0].packets_sent <= w[1].packets_sent)
+        );
+    }
+
+    #[test]
+    fn test_client_report() {
+        let payload_size = 512;
+        let (mut client, tx) =
+            create_test_client(5_000_000.0, payload_size, Duration::from_millis(200));
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+
+        let handle = thread::spawn(move || client.run(&mut client_sock));
+        tx.send(ClientCommand::Start).unwrap();
+
+        let packets = receive_all_packets(&mut server_sock, Duration::from_millis(500));
+        let report = handle.join().unwrap().unwrap();
+
+        // every data packet is accounted for, the FIN is not
+        assert_eq!(report.packets_sent as usize, packets.len() - 1);
+        assert_eq!(report.bytes_sent, report.packets_sent * payload_size as u64);
+        assert_eq!(report.send_failures, 0);
+        assert_eq!(report.pacing_error.count(), report.packets_sent);
+        assert_eq!(
+            report.intervals.iter().map(|i| i.packets_sent).sum::<u64>(),
+            report.packets_sent
+        );
+        assert!(report.bitrate_bps > 0.0);
+        assert!(report.remote.is_none());
+        assert!(report.public_endpoint.is_none());
+    }
+
+    #[test]
+    fn test_remote_results() {
+        use crate::builder::{ClientBuilder, ServerBuilder};
+        use crate::result::TestResult;
+        use crate::utils::net_utils::ServerCommand;
+
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+        let (server_tx, server_rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(1))
+            .send_results_to_client()
+            .build(server_rx);
+        let server_handle = thread::spawn(move || server.run(&mut server_sock));
+        server_tx.send(ServerCommand::Start).unwrap();
+
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(2_000_000.0, 512, Duration::from_millis(200))
+            .remote_results(Duration::from_secs(2))
+            .build(rx);
+        tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+        let intervals = server_handle.join().unwrap().unwrap();
+
+        let remote = report.remote.expect("the server should send its results");
+        let local = TestResult::from_intervals(&intervals);
+        assert_eq!(remote.total_packets, local.total_packets);
+        assert_eq!(remote.total_bytes, local.total_bytes);
+        // the first packet only starts the measurement, the FIN is counted
+        assert_eq!(remote.total_packets, report.packets_sent);
+        // no NAT on loopback, the server saw the socket's own address
+        assert_eq!(report.public_endpoint, client_sock.local_addr().ok());
+    }
+
     #[test]
     fn test_client_timeout() {
         let bitrate = 1_000_000.0;
@@ -349,6 +1183,114 @@ mod udp_client_tests {
         );
     }
 
+    #[test]
+    fn test_pause_freezes_timeline() {
+        let timeout = Duration::from_millis(150);
+        let (mut client, tx) = create_test_client(1_000_000.0, 512, timeout);
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+
+        let start = Instant::now();
+        let handle = thread::spawn(move || client.run(&mut client_sock));
+
+        tx.send(ClientCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        tx.send(ClientCommand::Pause).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        tx.send(ClientCommand::Resume).unwrap();
+
+        let packets = receive_all_packets(&mut server_sock, Duration::from_millis(500));
+        assert!(handle.join().unwrap().is_ok());
+
+        // the paused time does not count towards the timeout
+        assert!(start.elapsed() >= timeout + Duration::from_millis(150));
+        // and the sequence timeline continues without gaps
+        for (i, p) in packets.iter().enumerate() {
+            assert_eq!(p.0, i as u64);
+        }
+    }
+
+    #[test]
+    fn test_stop_ends_transmission_early() {
+        let (mut client, tx) = create_test_client(1_000_000.0, 512, Duration::from_secs(10));
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+
+        let handle = thread::spawn(move || client.run(&mut client_sock));
+
+        tx.send(ClientCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        tx.send(ClientCommand::Stop).unwrap();
+
+        let packets = receive_all_packets(&mut server_sock, Duration::from_millis(500));
+        assert!(handle.join().unwrap().is_ok());
+        assert_eq!(packets.last().unwrap().1, FLAG_FIN);
+    }
+
+    #[test]
+    fn test_stop_at_high_rate() {
+        // 64 byte packets at 1 Gbps, the channel is only polled every few packets
+        let (mut client, tx) = create_test_client(1e9, 64, Duration::from_secs(30));
+        let (_server_sock, mut client_sock) = create_socket_pair();
+
+        let start = Instant::now();
+        let handle = thread::spawn(move || client.run(&mut client_sock));
+        tx.send(ClientCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        tx.send(ClientCommand::Stop).unwrap();
+
+        let report = handle.join().unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(report.packets_sent > 0);
+        assert!(report.duration < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_stop_between_heartbeats() {
+        // one packet every 10 s, bridged with heartbeats
+        let (tx, rx) = channel();
+        let mut client = crate::ClientBuilder::new(400.0, 500, Duration::from_secs(60))
+            .heartbeat(Duration::from_millis(50))
+            .build(rx);
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+
+        let start = Instant::now();
+        let handle = thread::spawn(move || client.run(&mut client_sock));
+        tx.send(ClientCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(230));
+        tx.send(ClientCommand::Stop).unwrap();
+
+        let report = handle.join().unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(report.packets_sent, 1);
+        let packets = receive_all_packets(&mut server_sock, Duration::from_millis(100));
+        assert_eq!(packets.first().unwrap().1, FLAG_DATA);
+        assert!(packets.iter().any(|p| p.1 == FLAG_HEARTBEAT));
+        assert_eq!(packets.last().unwrap().1, FLAG_FIN);
+    }
+
+    #[test]
+    fn test_stop_cuts_the_pacing_wait_short() {
+        // one packet every 10 s
+        let (mut client, tx) = create_test_client(400.0, 500, Duration::from_secs(60));
+        let (_server_sock, mut client_sock) = create_socket_pair();
+
+        let handle = thread::spawn(move || client.run(&mut client_sock));
+        tx.send(ClientCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let stopped_at = Instant::now();
+        tx.send(ClientCommand::Stop).unwrap();
+
+        let report = handle.join().unwrap().unwrap();
+        assert!(stopped_at.elapsed() < Duration::from_millis(500));
+        assert_eq!(report.packets_sent, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "heartbeat interval is zero")]
+    fn test_zero_heartbeat() {
+        let _ =
+            crate::ClientBuilder::new(400.0, 500, Duration::from_secs(1)).heartbeat(Duration::ZERO);
+    }
+
     #[test]
     fn test_zero_timeout_sends_only_fin() {
         let bitrate = 1_000_000.0;
@@ -373,6 +1315,23 @@ mod udp_client_tests {
         assert_eq!(packets[0].1, FLAG_FIN, "Should be FIN packet");
     }
 
+    #[test]
+    fn test_without_timeout_runs_until_stopped() {
+        let (tx, rx) = channel();
+        let mut client = UdpClient::new(1_000_000.0, 512, None, rx);
+        let (_server_sock, mut client_sock) = create_socket_pair();
+
+        let start = Instant::now();
+        let handle = thread::spawn(move || client.run(&mut client_sock));
+        tx.send(ClientCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        tx.send(ClientCommand::Stop).unwrap();
+
+        let report = handle.join().unwrap().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(report.packets_sent > 0);
+    }
+
     #[test]
     fn test_no_duplicate_sequence_numbers() {
         let bitrate = 10_000_000.0;
@@ -0,0 +1,648 @@
+//! # Config-file driven tests
+//!
+//! A [`TestConfig`] describes a test in a file kept under version control
+//! next to the pipeline that runs it, so a parameter change is a reviewed
+//! diff instead of a code change:
+//!
+//! ```toml
+//! role = "client"
+//! test_id = "edge-42"
+//! name = "edge uplink"
+//! tags = { site = "ams1", circuit = "C-4711" }
+//!
+//! [client]
+//! server = "192.0.2.10:5201"
+//! device = "eth1"
+//! bitrate_bps = 20e6
+//! duration = 10
+//!
+//! [server]
+//! bind = "0.0.0.0:5201"
+//! jitter = "ipdv"
+//! fin_linger = 0.5
+//!
+//! [thresholds]
+//! max_loss_percent = 1.0
+//! min_throughput_bps = 15e6
+//!
+//! # udpopt run edge.toml --profile voice
+//! [profiles.voice]
+//! bitrate_bps = 64e3
+//! payload_size = 200
+//! dscp = 46
+//! thresholds = { max_loss_percent = 0.1, max_p99_jitter_ms = 5.0 }
+//!
+//! [[sinks]]
+//! kind = "influx_udp"
+//! endpoint = "127.0.0.1:8089"
+//! ```
+//!
+//! [`TestConfig::run`] plays the `role` of the file and
+//! [`TestConfig::run_as`] the role it is given, so the same file serves both
+//! ends: the client sends the test and checks the server's results, the
+//! server receives one test, pushes its intervals to the sinks and checks
+//! its own results. The client gets no intervals and leaves the sinks out.
+//! A profile replaces some client parameters and the thresholds.
+//!
+//! ```no_run
+//! use udpopt::TestConfig;
+//! use udpopt::config::Role;
+//!
+//! let config = TestConfig::from_toml("edge.toml").unwrap();
+//! let outcome = config.run(Some("voice")).unwrap();
+//! println!("{}", outcome.verdict);
+//! // the other end, e.g. `udpopt run edge.toml --role server`
+//! let outcome = config.run_as(Role::Server, Some("voice")).unwrap();
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::Write,
+    net::{SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    builder::{ClientBuilder, ServerBuilder},
+    errors::UdpOptError,
+    result::TestResult,
+    sink::{Direction, HttpLineWriter, InfluxSink, UdpLineWriter},
+    socket::{Interface, PortRange},
+    thresholds::{Thresholds, Verdict},
+    utils::jitter::JitterEstimator,
+    utils::net_utils::{ClientCommand, ServerCommand},
+};
+
+/// How long the client waits for the server's results after its FIN
+const RESULTS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Which end of the test [`TestConfig::run`] plays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Sends the test to `client.server`.
+    #[default]
+    Client,
+    /// Receives one test on `server.bind`.
+    Server,
+}
+
+/// A test described in a file, see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestConfig {
+    /// The end [`run`](Self::run) plays.
+    #[serde(default)]
+    pub role: Role,
+    /// Identifies the test in the logs and the sinks.
+    #[serde(default)]
+    pub test_id: Option<String>,
+    /// Name of the run, a tag of the sink lines.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Free text about the run.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Key/value labels of the run, tags of the sink lines.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Parameters of the sending end.
+    #[serde(default)]
+    pub client: Option<ClientSettings>,
+    /// Parameters of the receiving end.
+    #[serde(default)]
+    pub server: Option<ServerSettings>,
+    /// Limits the results are checked against.
+    #[serde(default)]
+    pub thresholds: Thresholds,
+    /// Named variants of the client parameters and thresholds.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Where the server pushes its interval results.
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+}
+
+/// The `[client]` table of a [`TestConfig`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientSettings {
+    /// Server to send to.
+    pub server: SocketAddr,
+    /// Local address, any of the server's family when not set.
+    #[serde(default)]
+    pub bind: Option<SocketAddr>,
+    /// Source ports, the first free one is taken instead of the port of
+    /// `bind`.
+    #[serde(default)]
+    pub source_ports: Option<PortRange>,
+    /// Interface to send through, a name or an index.
+    #[serde(default)]
+    pub device: Option<Interface>,
+    /// Bitrate (bits/sec).
+    pub bitrate_bps: f64,
+    /// Packet length (bytes), header included.
+    #[serde(default = "default_payload_size")]
+    pub payload_size: usize,
+    /// How long to send (seconds in the file).
+    #[serde(with = "crate::report::secs", default = "default_duration")]
+    pub duration: Duration,
+    /// DiffServ code point of the packets, 0 for best effort.
+    #[serde(default)]
+    pub dscp: u8,
+    /// Send the packets ECN-capable.
+    #[serde(default)]
+    pub ecn: bool,
+}
+
+/// The `[server]` table of a [`TestConfig`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerSettings {
+    /// Address to receive on.
+    pub bind: SocketAddr,
+    /// Interface to receive on, a name or an index.
+    #[serde(default)]
+    pub device: Option<Interface>,
+    /// Length of the result intervals (seconds in the file).
+    #[serde(with = "crate::report::secs", default = "default_interval")]
+    pub interval: Duration,
+    /// How the jitter of an interval is computed.
+    #[serde(default)]
+    pub jitter: JitterEstimator,
+    /// Warm-up left out of the results (seconds in the file).
+    #[serde(with = "crate::report::opt_secs", default)]
+    pub omit: Option<Duration>,
+    /// How long the tail of the test is still received after the FIN
+    /// (seconds in the file).
+    #[serde(with = "crate::report::opt_secs", default)]
+    pub fin_linger: Option<Duration>,
+}
+
+/// A `[profiles.<name>]` table, the values it sets replace the ones of
+/// `[client]` and `[thresholds]`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Replaces [`ClientSettings::bitrate_bps`].
+    #[serde(default)]
+    pub bitrate_bps: Option<f64>,
+    /// Replaces [`ClientSettings::payload_size`].
+    #[serde(default)]
+    pub payload_size: Option<usize>,
+    /// Replaces [`ClientSettings::duration`].
+    #[serde(with = "crate::report::opt_secs", default)]
+    pub duration: Option<Duration>,
+    /// Replaces [`ClientSettings::dscp`].
+    #[serde(default)]
+    pub dscp: Option<u8>,
+    /// Replaces [`ClientSettings::ecn`].
+    #[serde(default)]
+    pub ecn: Option<bool>,
+    /// Replaces the whole `[thresholds]` table.
+    #[serde(default)]
+    pub thresholds: Option<Thresholds>,
+}
+
+/// A `[[sinks]]` entry, an [`InfluxSink`] writing to `kind`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkConfig {
+    /// Appends the lines to a file.
+    InfluxFile {
+        /// File to append to.
+        path: PathBuf,
+    },
+    /// Sends the lines to a UDP listener, see [`UdpLineWriter`].
+    InfluxUdp {
+        /// `host:port` of the listener.
+        endpoint: String,
+    },
+    /// Posts the lines to the HTTP write API, see [`HttpLineWriter`].
+    InfluxHttp {
+        /// Write endpoint, `http://` only.
+        url: String,
+        /// API token.
+        #[serde(default)]
+        token: Option<String>,
+    },
+}
+
+fn default_payload_size() -> usize {
+    1200
+}
+
+fn default_duration() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+/// What [`TestConfig::run`] measured and how it compares to the thresholds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestOutcome {
+    /// The profile the test ran with.
+    pub profile: Option<String>,
+    /// The receiving end's results.
+    pub result: TestResult,
+    /// The results checked against the thresholds.
+    pub verdict: Verdict,
+}
+
+impl TestConfig {
+    /// Reads the TOML file at `path`.
+    ///
+    /// # Errors
+    /// - [`UdpOptError::ReportFailed`] if the file cannot be read.
+    /// - [`UdpOptError::ReportFormat`] if it is not a test configuration.
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, UdpOptError> {
+        let toml = std::fs::read_to_string(path).map_err(UdpOptError::ReportFailed)?;
+        Self::from_toml_str(&toml)
+    }
+
+    /// Reads a TOML document, see [`from_toml`](Self::from_toml).
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFormat`] if `toml` is not a test configuration.
+    pub fn from_toml_str(toml: &str) -> Result<Self, UdpOptError> {
+        toml::from_str(toml).map_err(|e| UdpOptError::ReportFormat(e.to_string()))
+    }
+
+    /// The client parameters and thresholds with `profile` applied.
+    ///
+    /// # Errors
+    /// [`UdpOptError::InvalidConfig`] without a `[client]` table or for an
+    /// unknown profile.
+    pub fn client_settings(
+        &self,
+        profile: Option<&str>,
+    ) -> Result<(ClientSettings, Thresholds), UdpOptError> {
+        let mut client = self
+            .client
+            .clone()
+            .ok_or_else(|| UdpOptError::InvalidConfig("no [client] table".to_string()))?;
+        let mut thresholds = self.thresholds;
+        if let Some(profile) = self.profile(profile)? {
+            client.bitrate_bps = profile.bitrate_bps.unwrap_or(client.bitrate_bps);
+            client.payload_size = profile.payload_size.unwrap_or(client.payload_size);
+            client.duration = profile.duration.unwrap_or(client.duration);
+            client.dscp = profile.dscp.unwrap_or(client.dscp);
+            client.ecn = profile.ecn.unwrap_or(client.ecn);
+            thresholds = profile.thresholds.unwrap_or(thresholds);
+        }
+        Ok((client, thresholds))
+    }
+
+    /// Runs the end of the test given by `role`, with `profile` applied.
+    ///
+    /// # Errors
+    /// See [`run_as`](Self::run_as).
+    pub fn run(&self, profile: Option<&str>) -> Result<TestOutcome, UdpOptError> {
+        self.run_as(self.role, profile)
+    }
+
+    /// Runs the `role` end of the test whatever the `role` of the file, with
+    /// `profile` applied. The sinks are left out in the client role, which
+    /// gets no intervals.
+    ///
+    /// # Errors
+    /// - [`UdpOptError::InvalidConfig`] without the table of the role, for
+    ///   an unknown profile or a DSCP that does not fit in six bits.
+    /// - [`UdpOptError::MissingResults`] if the server did not return its
+    ///   results to the client.
+    /// - any error of binding the socket or of the run.
+    pub fn run_as(&self, role: Role, profile: Option<&str>) -> Result<TestOutcome, UdpOptError> {
+        let (result, thresholds) = match role {
+            Role::Client => self.run_client(profile)?,
+            Role::Server => self.run_server(profile)?,
+        };
+        Ok(TestOutcome {
+            profile: profile.map(str::to_string),
+            verdict: result.evaluate(&thresholds),
+            result,
+        })
+    }
+
+    fn profile(&self, name: Option<&str>) -> Result<Option<&Profile>, UdpOptError> {
+        name.map(|name| {
+            self.profiles
+                .get(name)
+                .ok_or_else(|| UdpOptError::InvalidConfig(format!("unknown profile {name}")))
+        })
+        .transpose()
+    }
+
+    fn run_client(&self, profile: Option<&str>) -> Result<(TestResult, Thresholds), UdpOptError> {
+        let (client, thresholds) = self.client_settings(profile)?;
+        if client.dscp >= 64 {
+            return Err(UdpOptError::InvalidConfig(format!(
+                "DSCP {} out of range",
+                client.dscp
+            )));
+        }
+        if !self.sinks.is_empty() {
+            event!(warn, "the sinks are only fed in the server role");
+        }
+        let (tx, rx) = mpsc::channel();
+        let mut builder =
+            ClientBuilder::new(client.bitrate_bps, client.payload_size, client.duration)
+                .remote_results(RESULTS_TIMEOUT);
+        if client.dscp != 0 {
+            builder = builder.dscp(client.dscp);
+        }
+        if client.ecn {
+            builder = builder.ecn();
+        }
+        if let Some(local) = client.bind {
+            builder = builder.bind(local);
+        }
+        if let Some(ports) = client.source_ports {
+            builder = builder.source_ports(ports);
+        }
+        if let Some(device) = &client.device {
+            builder = builder.bind_device(device.clone());
+        }
+        if let Some(id) = &self.test_id {
+            builder = builder.test_id(id.as_str());
+        }
+        let mut sender = builder.build(rx);
+        let mut sock = sender.connect(client.server)?;
+        let _ = tx.send(ClientCommand::Start);
+        let result = sender
+            .run(&mut sock)?
+            .remote
+            .ok_or(UdpOptError::MissingResults(0))?;
+        Ok((result, thresholds))
+    }
+
+    fn run_server(&self, profile: Option<&str>) -> Result<(TestResult, Thresholds), UdpOptError> {
+        let server = self
+            .server
+            .as_ref()
+            .ok_or_else(|| UdpOptError::InvalidConfig("no [server] table".to_string()))?;
+        let thresholds = self
+            .profile(profile)?
+            .and_then(|profile| profile.thresholds)
+            .unwrap_or(self.thresholds);
+        let mut sock = UdpSocket::bind(server.bind).map_err(|source| UdpOptError::BindFailed {
+            addr: server.bind,
+            source,
+        })?;
+
+        let (tx, rx) = mpsc::channel();
+        let mut builder = ServerBuilder::new(server.interval)
+            .jitter_estimator(server.jitter)
+            .omit(server.omit.unwrap_or_default())
+            .send_results_to_client();
+        if let Some(linger) = server.fin_linger {
+            builder = builder.fin_linger(linger);
+        }
+        if let Some(device) = &server.device {
+            builder = builder.bind_device(device.clone());
+        }
+        if let Some(id) = &self.test_id {
+            builder = builder.test_id(id.as_str());
+        }
+        for sink in &self.sinks {
+            builder = self.add_sink(builder, sink)?;
+        }
+        let mut receiver = builder.build(rx);
+        let _ = tx.send(ServerCommand::Start);
+        let intervals = receiver.run(&mut sock).map_err(|e| e.error)?;
+        let result = TestResult::from_intervals(&intervals)
+            .with_latency(receiver.latency_histogram())
+            .with_ipdv(receiver.ipdv_histogram())
+            .with_reorder(receiver.reorder_stats())
+            .with_sizes(receiver.size_stats())
+            .with_loss_pattern(receiver.loss_pattern())
+            .with_interarrival(receiver.interarrival_histogram())
+            .with_packets_sent(receiver.client_sent().unwrap_or(0));
+        Ok((result, thresholds))
+    }
+
+    /// Adds the [`InfluxSink`] of `sink` to `builder`
+    fn add_sink(
+        &self,
+        builder: ServerBuilder,
+        sink: &SinkConfig,
+    ) -> Result<ServerBuilder, UdpOptError> {
+        Ok(match sink {
+            SinkConfig::InfluxFile { path } => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(UdpOptError::SinkFailed)?;
+                builder.result_sink(self.tagged(InfluxSink::new(file)))
+            }
+            SinkConfig::InfluxUdp { endpoint } => {
+                let writer =
+                    UdpLineWriter::new(endpoint.as_str()).map_err(UdpOptError::SinkFailed)?;
+                builder.result_sink(self.tagged(InfluxSink::new(writer)))
+            }
+            SinkConfig::InfluxHttp { url, token } => {
+                let writer =
+                    HttpLineWriter::new(url, token.clone()).map_err(UdpOptError::SinkFailed)?;
+                builder.result_sink(self.tagged(InfluxSink::new(writer)))
+            }
+        })
+    }
+
+    /// `sink` tagged with the test id, the name and the tags, the traffic
+    /// received being an upload
+    fn tagged<W: Write + Send>(&self, sink: InfluxSink<W>) -> InfluxSink<W> {
+        let mut sink = sink.direction(Direction::Upload);
+        if let Some(id) = &self.test_id {
+            sink = sink.test_id(id.as_str());
+        }
+        if let Some(name) = &self.name {
+            sink = sink.tag("name", name.as_str());
+        }
+        for (key, value) in &self.tags {
+            sink = sink.tag(key.as_str(), value.as_str());
+        }
+        sink
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const CONFIG: &str = r#"
+        role = "client"
+        test_id = "edge-42"
+        name = "edge uplink"
+        tags = { site = "ams1", circuit = "C-4711" }
+
+        [client]
+        server = "192.0.2.10:5201"
+        source_ports = "40000-40099"
+        device = "eth1"
+        bitrate_bps = 20e6
+        duration = 10
+
+        [server]
+        bind = "0.0.0.0:5201"
+        device = 3
+        jitter = "ipdv"
+        fin_linger = 0.5
+
+        [thresholds]
+        max_loss_percent = 1.0
+        min_throughput_bps = 15e6
+
+        [profiles.voice]
+        bitrate_bps = 64e3
+        payload_size = 200
+        dscp = 46
+        thresholds = { max_loss_percent = 0.1 }
+
+        [[sinks]]
+        kind = "influx_udp"
+        endpoint = "127.0.0.1:8089"
+    "#;
+
+    #[test]
+    fn test_from_toml_str() {
+        let config = TestConfig::from_toml_str(CONFIG).unwrap();
+        assert_eq!(config.role, Role::Client);
+        assert_eq!(config.name.as_deref(), Some("edge uplink"));
+        assert_eq!(config.tags["circuit"], "C-4711");
+        let server = config.server.as_ref().unwrap();
+        assert_eq!(server.jitter, JitterEstimator::Ipdv);
+        assert_eq!(server.interval, Duration::from_secs(1));
+        assert_eq!(server.fin_linger, Some(Duration::from_millis(500)));
+        assert_eq!(server.device, Some(Interface::Index(3)));
+        assert_eq!(
+            config.sinks,
+            [SinkConfig::InfluxUdp {
+                endpoint: "127.0.0.1:8089".to_string()
+            }]
+        );
+
+        let (client, thresholds) = config.client_settings(None).unwrap();
+        assert_eq!((client.bitrate_bps, client.payload_size), (20e6, 1200));
+        assert_eq!(client.device, Some(Interface::from("eth1")));
+        assert_eq!(client.source_ports, Some(PortRange::new(40000, 40099)));
+        assert_eq!(thresholds.min_throughput_bps, Some(15e6));
+
+        let (voice, thresholds) = config.client_settings(Some("voice")).unwrap();
+        assert_eq!(
+            (voice.bitrate_bps, voice.payload_size, voice.dscp),
+            (64e3, 200, 46)
+        );
+        assert_eq!(voice.duration, Duration::from_secs(10));
+        assert_eq!(thresholds.max_loss_percent, Some(0.1));
+        assert_eq!(thresholds.min_throughput_bps, None);
+
+        assert!(matches!(
+            config.client_settings(Some("video")),
+            Err(UdpOptError::InvalidConfig(_))
+        ));
+        // a typo is not silently ignored
+        assert!(
+            TestConfig::from_toml_str("[client]\nserver = \"192.0.2.10:5201\"\nbitrate = 1e6")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_run_client() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut sock = sock;
+            let (tx, rx) = mpsc::channel();
+            let mut server = ServerBuilder::new(Duration::from_millis(250))
+                .send_results_to_client()
+                .build(rx);
+            tx.send(ServerCommand::Start).unwrap();
+            server.run(&mut sock).unwrap()
+        });
+
+        let config = TestConfig {
+            client: Some(ClientSettings {
+                server: addr,
+                bind: None,
+                source_ports: None,
+                device: None,
+                bitrate_bps: 1e6,
+                payload_size: 1200,
+                duration: Duration::from_millis(500),
+                dscp: 0,
+                ecn: false,
+            }),
+            thresholds: Thresholds {
+                // more than was sent
+                min_throughput_bps: Some(10e6),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let outcome = config.run(None).unwrap();
+        assert!(!server.join().unwrap().is_empty());
+        assert!(outcome.result.total_packets > 0);
+        assert!(!outcome.verdict.passed());
+        assert!(
+            outcome
+                .verdict
+                .failed(crate::thresholds::Criterion::Throughput)
+        );
+    }
+
+    #[test]
+    fn test_one_file_for_both_ends() {
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let lines = std::env::temp_dir().join(format!("udpopt-config-{port}.lp"));
+        let _ = std::fs::remove_file(&lines);
+        let toml = format!(
+            r#"
+            role = "client"
+            name = "both ends"
+
+            [client]
+            server = "127.0.0.1:{port}"
+            bitrate_bps = 1e6
+            duration = 0.5
+
+            [server]
+            bind = "127.0.0.1:{port}"
+            interval = 0.25
+
+            [[sinks]]
+            kind = "influx_file"
+            path = "{}"
+            "#,
+            lines.display()
+        );
+        let config = TestConfig::from_toml_str(&toml).unwrap();
+
+        let server = {
+            let config = config.clone();
+            thread::spawn(move || config.run_as(Role::Server, None))
+        };
+        thread::sleep(Duration::from_millis(200));
+        // the sinks are the server's, the client leaves them out
+        let sent = config.run(None).unwrap();
+        let received = server.join().unwrap().unwrap();
+
+        assert!(sent.verdict.passed());
+        assert!(received.verdict.passed());
+        assert_eq!(sent.result.total_packets, received.result.total_packets);
+        let lines = std::fs::read_to_string(&lines).unwrap();
+        assert!(lines.contains("name=both\\ ends"), "{lines}");
+    }
+}
@@ -0,0 +1,259 @@
+//! # Rate recommendations
+//!
+//! While a test runs, the server turns the loss it sees into the packet rate
+//! it would recommend the client to send at, reported in the
+//! [`recommended_bitrate`](crate::IntervalResult::recommended_bitrate) of
+//! every interval. The rule is a [`CongestionController`], chosen with
+//! `ServerBuilder::congestion_controller` from:
+//!
+//! - [`LossThreshold`], small steps around a 99 % delivery target, the default,
+//! - [`Aimd`], additive increase and multiplicative decrease, changing the
+//!   rate only after several intervals agree,
+//!
+//! or any other [`CongestionController`].
+//!
+//! ```
+//! use std::time::Duration;
+//! use udpopt::ServerBuilder;
+//! use udpopt::congestion::Aimd;
+//!
+//! let builder = ServerBuilder::new(Duration::from_secs(1))
+//!     .congestion_controller(Aimd::new().increase(50.0).decrease(0.8).hysteresis(5));
+//! ```
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use crate::utils::ui::loss_percent;
+
+/// Decides the packet rate to recommend from the loss of the test.
+///
+/// Every session of the server starts from a clone of the controller given
+/// to `ServerBuilder::congestion_controller`.
+pub trait CongestionController: Send + fmt::Debug {
+    /// Packets per second to recommend after `received` packets arrived and
+    /// `lost` were lost over `period`, `None` to keep the previous
+    /// recommendation.
+    ///
+    /// Called every 200 ms while packets arrive, with the packets of the
+    /// `period` since the previous call (or the start of the interval),
+    /// `received > 0` and a nonzero `period`.
+    fn recommend(&mut self, received: u64, lost: u64, period: Duration) -> Option<f64>;
+
+    /// Packets per second to recommend for the interval closing now, `None`
+    /// to keep the last recommendation. Called after the periods of the
+    /// interval went through [`recommend`](Self::recommend); does nothing
+    /// unless overridden.
+    fn close_interval(&mut self) -> Option<f64> {
+        None
+    }
+}
+
+/// Creates the controller of a new session
+pub(crate) type ControllerFactory = Arc<dyn Fn() -> Box<dyn CongestionController> + Send + Sync>;
+
+/// The [`CongestionController`] used when none is given
+pub(crate) fn default_factory() -> ControllerFactory {
+    Arc::new(|| Box::new(LossThreshold))
+}
+
+/// Whole percent of the packets that must arrive for the rate not to drop
+const ACCEPTABLE: u32 = 99;
+/// Third and fourth decimals of the delivery percentage from which the
+/// rate rises
+const ACCEPTABLEDECIMAL: u32 = 98;
+
+/// Steps the rate around a 99 % delivery target, the default.
+///
+/// Below 99 % of the packets delivered the rate drops by 5 %. At 99 % and
+/// above it rises by 5 pps or drops by 10 pps depending on the decimals of
+/// the delivery ratio, so close ratios can move it in opposite directions.
+/// Without loss the recommendation stays. Stateless, so it follows every
+/// change of the loss; [`Aimd`] is steadier.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LossThreshold;
+
+impl CongestionController for LossThreshold {
+    fn recommend(&mut self, received: u64, lost: u64, period: Duration) -> Option<f64> {
+        if lost == 0 {
+            return None;
+        }
+        // Packets per second (pps)
+        let act_pps = received as f64 / period.as_secs_f64();
+
+        // Compute received ratio once
+        let received_ratio = (received.saturating_sub(lost) as f64 / received as f64) * 100.0;
+
+        // Split into integer + decimal parts
+        let int_part = received_ratio as u32; // truncates
+
+        let decimal_part = ((received_ratio * 10000.0) as u32) % 100;
+
+        // Decide recommended adjustment
+        Some(if int_part < ACCEPTABLE {
+            act_pps * 0.95 // reduce rate by 5%
+        } else if decimal_part >= ACCEPTABLEDECIMAL {
+            act_pps + 5.0 // small increase
+        } else {
+            act_pps - 10.0 // bigger decrease
+        })
+    }
+}
+
+/// Additive increase, multiplicative decrease.
+///
+/// Judges the loss of whole intervals, not of the 200 ms periods inside
+/// them. Starts from the rate of the first interval, then adds
+/// [`increase`](Self::increase) pps when the loss stays within
+/// [`max_loss_percent`](Self::max_loss_percent) and multiplies by
+/// [`decrease`](Self::decrease) when it goes above. A change needs
+/// [`hysteresis`](Self::hysteresis) consecutive intervals on the same side
+/// of the limit, so a single lossy burst does not move the rate.
+#[derive(Debug, Clone)]
+pub struct Aimd {
+    increase_pps: f64,
+    decrease: f64,
+    max_loss_percent: f64,
+    hysteresis: u32,
+    /// Current recommendation, `None` before the first interval
+    rate: Option<f64>,
+    /// Whether the last intervals were lossy, and how many in a row
+    streak: (bool, u32),
+    /// Packets received and lost, and the time, of the open interval
+    pending: (u64, u64, Duration),
+}
+
+impl Aimd {
+    /// 10 pps up, 10 % down, 1 % loss tolerated, 3 intervals to agree.
+    pub fn new() -> Self {
+        Self {
+            increase_pps: 10.0,
+            decrease: 0.9,
+            max_loss_percent: 1.0,
+            hysteresis: 3,
+            rate: None,
+            streak: (false, 0),
+            pending: (0, 0, Duration::ZERO),
+        }
+    }
+
+    /// Packets per second added when the loss stays within the limit.
+    pub fn increase(mut self, pps: f64) -> Self {
+        self.increase_pps = pps.max(0.0);
+        self
+    }
+
+    /// Factor the rate is multiplied by when the loss goes above the limit,
+    /// clamped to 0..=1.
+    pub fn decrease(mut self, factor: f64) -> Self {
+        self.decrease = factor.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Highest loss (%) still counted as no congestion.
+    pub fn max_loss_percent(mut self, percent: f64) -> Self {
+        self.max_loss_percent = percent;
+        self
+    }
+
+    /// Consecutive intervals on the same side of the loss limit needed to
+    /// change the rate, at least 1.
+    pub fn hysteresis(mut self, intervals: u32) -> Self {
+        self.hysteresis = intervals.max(1);
+        self
+    }
+}
+
+impl Default for Aimd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CongestionController for Aimd {
+    fn recommend(&mut self, received: u64, lost: u64, period: Duration) -> Option<f64> {
+        // decided once the interval closes
+        self.pending.0 += received;
+        self.pending.1 += lost;
+        self.pending.2 += period;
+        None
+    }
+
+    fn close_interval(&mut self) -> Option<f64> {
+        let (received, lost, period) = std::mem::take(&mut self.pending);
+        if received == 0 || period.is_zero() {
+            return None;
+        }
+        let lossy = loss_percent(received, lost) > self.max_loss_percent;
+        let intervals = if self.streak.0 == lossy {
+            self.streak.1 + 1
+        } else {
+            1
+        };
+        self.streak = (lossy, intervals);
+
+        let rate = self
+            .rate
+            .get_or_insert(received as f64 / period.as_secs_f64());
+        if intervals >= self.hysteresis {
+            *rate = if lossy {
+                *rate * self.decrease
+            } else {
+                *rate + self.increase_pps
+            };
+            self.streak.1 = 0;
+        }
+        Some(*rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One second interval of five 200 ms periods, `received` and `lost`
+    /// spread over them
+    fn interval(aimd: &mut Aimd, received: u64, lost: u64) -> Option<f64> {
+        for _ in 0..5 {
+            assert_eq!(
+                aimd.recommend(received / 5, lost / 5, Duration::from_millis(200)),
+                None
+            );
+        }
+        aimd.close_interval()
+    }
+
+    #[test]
+    fn test_aimd_hysteresis() {
+        let mut aimd = Aimd::new().increase(100.0).decrease(0.5).hysteresis(3);
+
+        // two clean intervals are not enough to move the rate, whatever the
+        // number of periods in them
+        assert_eq!(interval(&mut aimd, 1000, 0), Some(1000.0));
+        assert_eq!(interval(&mut aimd, 1000, 0), Some(1000.0));
+        assert_eq!(interval(&mut aimd, 1000, 0), Some(1100.0));
+
+        // a lossy burst between clean intervals restarts the count
+        assert_eq!(interval(&mut aimd, 1000, 50), Some(1100.0));
+        assert_eq!(interval(&mut aimd, 1000, 0), Some(1100.0));
+
+        // three lossy intervals in a row halve it
+        for _ in 0..2 {
+            assert_eq!(interval(&mut aimd, 1000, 50), Some(1100.0));
+        }
+        assert_eq!(interval(&mut aimd, 1000, 50), Some(550.0));
+
+        // an interval without packets decides nothing
+        assert_eq!(aimd.close_interval(), None);
+
+        // the loss limit is inclusive
+        let mut aimd = Aimd::new().hysteresis(1).max_loss_percent(1.0);
+        assert_eq!(interval(&mut aimd, 990, 10), Some(1000.0));
+    }
+
+    #[test]
+    fn test_loss_threshold() {
+        let second = Duration::from_secs(1);
+        assert_eq!(LossThreshold.recommend(1000, 0, second), None);
+        assert_eq!(LossThreshold.recommend(1000, 100, second), Some(950.0));
+    }
+}
@@ -0,0 +1,285 @@
+//! # Live dashboard (feature `tui`)
+//!
+//! A terminal dashboard of the interval stream, for interactive debugging in
+//! the field: sparklines of the throughput, the loss and the jitter of the
+//! last intervals, with the latest values and the totals so far.
+//!
+//! [`channel`] returns the [`IntervalFeed`] to register as the server's
+//! interval observer and the [`Dashboard`] to run on another thread. The
+//! dashboard ends when the feed is dropped with the server, or when the user
+//! presses `q`, `Esc` or `Ctrl-C`.
+//!
+//! ```no_run
+//! use std::{sync::mpsc, thread, time::Duration};
+//! use udpopt::{ServerBuilder, ServerCommand, dashboard};
+//!
+//! let (feed, dashboard) = dashboard::channel();
+//! let (tx, rx) = mpsc::channel();
+//! let server = ServerBuilder::new(Duration::from_secs(1))
+//!     .on_interval(feed.observer())
+//!     .build(rx);
+//! let ui = thread::spawn(move || {
+//!     if dashboard.run().unwrap() {
+//!         // the user quit, end the test too
+//!         let _ = tx.send(ServerCommand::Stop);
+//!     }
+//! });
+//! ```
+
+use std::{
+    collections::VecDeque,
+    io,
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    time::Duration,
+};
+
+use ratatui::{
+    DefaultTerminal,
+    buffer::Buffer,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    widgets::{Block, Paragraph, Sparkline, Widget},
+};
+
+use crate::utils::{
+    net_utils::{IntervalCallback, IntervalResult},
+    ui::{format_bitrate, loss_percent},
+};
+
+/// Intervals kept for the sparklines
+const HISTORY: usize = 512;
+/// How often the keyboard is checked and the screen redrawn
+const TICK: Duration = Duration::from_millis(100);
+
+/// A connected feed and dashboard.
+pub fn channel() -> (IntervalFeed, Dashboard) {
+    let (tx, rx) = mpsc::channel();
+    let feed = IntervalFeed { tx };
+    let dashboard = Dashboard {
+        rx,
+        history: History::default(),
+    };
+    (feed, dashboard)
+}
+
+/// Sending end of the interval stream shown by a [`Dashboard`].
+#[derive(Debug, Clone)]
+pub struct IntervalFeed {
+    tx: Sender<IntervalResult>,
+}
+
+impl IntervalFeed {
+    /// Shows `result`, ignored once the dashboard is closed.
+    pub fn send(&self, result: &IntervalResult) {
+        let _ = self.tx.send(*result);
+    }
+
+    /// An interval observer for `ServerBuilder::on_interval` feeding the dashboard.
+    pub fn observer(&self) -> IntervalCallback {
+        let feed = self.clone();
+        Box::new(move |result| feed.send(result))
+    }
+}
+
+/// The terminal dashboard, see the [module docs](self).
+#[derive(Debug)]
+pub struct Dashboard {
+    rx: Receiver<IntervalResult>,
+    history: History,
+}
+
+impl Dashboard {
+    /// Takes over the terminal until every feed is dropped or the user quits.
+    ///
+    /// Returns whether the user quit.
+    ///
+    /// # Errors
+    /// The error setting up or drawing on the terminal.
+    pub fn run(mut self) -> io::Result<bool> {
+        let mut terminal = ratatui::try_init()?;
+        let res = self.run_on(&mut terminal);
+        ratatui::restore();
+        res
+    }
+
+    fn run_on(&mut self, terminal: &mut DefaultTerminal) -> io::Result<bool> {
+        loop {
+            loop {
+                match self.rx.try_recv() {
+                    Ok(result) => self.history.push(&result),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(false),
+                }
+            }
+            terminal.draw(|frame| self.history.render(frame.area(), frame.buffer_mut()))?;
+
+            if event::poll(TICK)?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                // raw mode turns Ctrl-C into a key press
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+}
+
+/// The last intervals and the totals so far
+#[derive(Debug, Default)]
+struct History {
+    /// Bitrate per interval (Kbps)
+    throughput: VecDeque<u64>,
+    /// Loss per interval (hundredths of a percent)
+    loss: VecDeque<u64>,
+    /// Jitter per interval (µs)
+    jitter: VecDeque<u64>,
+    last: Option<IntervalResult>,
+    intervals: u64,
+    received: u64,
+    lost: u64,
+    ce_marked: u64,
+    auth_failures: u64,
+    foreign: u64,
+    malformed: u64,
+}
+
+impl History {
+    fn push(&mut self, r: &IntervalResult) {
+        let points = [
+            (&mut self.throughput, r.bitrate_bps / 1e3),
+            (&mut self.loss, loss_percent(r.received, r.lost) * 100.0),
+            (&mut self.jitter, r.jitter_ms * 1e3),
+        ];
+        for (series, value) in points {
+            if series.len() == HISTORY {
+                series.pop_front();
+            }
+            series.push_back(value.round() as u64);
+        }
+        self.last = Some(*r);
+        self.intervals += 1;
+        self.received += r.received;
+        self.lost += r.lost;
+        self.ce_marked += r.ce_marked;
+        self.auth_failures += r.auth_failures;
+        self.foreign += r.foreign;
+        self.malformed += r.malformed;
+    }
+
+    fn render(&self, area: Rect, buf: &mut Buffer) {
+        let [totals, throughput, loss, jitter] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+        ])
+        .areas(area);
+
+        let mut line = format!(
+            "Intervals {} | Received {} | Lost {} ({:.2} %)",
+            self.intervals,
+            self.received,
+            self.lost,
+            loss_percent(self.received, self.lost)
+        );
+        // only counted when the server reads ECN / checks a key
+        if self.ce_marked > 0 {
+            line.push_str(&format!(" | CE {}", self.ce_marked));
+        }
+        if self.auth_failures > 0 {
+            line.push_str(&format!(" | Auth failures {}", self.auth_failures));
+        }
+        if self.foreign > 0 {
+            line.push_str(&format!(" | Foreign {}", self.foreign));
+        }
+        if self.malformed > 0 {
+            line.push_str(&format!(" | Malformed {}", self.malformed));
+        }
+        Paragraph::new(line)
+            .block(Block::bordered().title(" udpopt, q to quit "))
+            .render(totals, buf);
+
+        let last = self.last.unwrap_or_default();
+        let titles = [
+            format!(" Throughput {} ", format_bitrate(last.bitrate_bps)),
+            format!(" Loss {:.2} % ", loss_percent(last.received, last.lost)),
+            format!(" Jitter {:.3} ms ", last.jitter_ms),
+        ];
+        let charts = [
+            (throughput, &self.throughput, Color::Green),
+            (loss, &self.loss, Color::Red),
+            (jitter, &self.jitter, Color::Yellow),
+        ];
+        for ((area, series, color), title) in charts.into_iter().zip(titles) {
+            // the newest points that fit, inside the borders
+            let width = usize::from(area.width.saturating_sub(2));
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .data(series.iter().skip(series.len().saturating_sub(width)))
+                .style(Style::default().fg(color))
+                .render(area, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(buf: &Buffer) -> String {
+        buf.content().iter().map(|cell| cell.symbol()).collect()
+    }
+
+    #[test]
+    fn test_history_and_render() {
+        let (feed, mut dashboard) = channel();
+        let mut observer = feed.observer();
+        for lost in [0, 1, 0] {
+            observer(
+                &IntervalResult {
+                    time: Duration::from_secs(1),
+                    received: 99,
+                    lost,
+                    bytes: 1_250_000,
+                    jitter_ms: 0.25,
+                    ..Default::default()
+                }
+                .with_bitrate(),
+            );
+        }
+        drop((feed, observer));
+        while let Ok(result) = dashboard.rx.try_recv() {
+            dashboard.history.push(&result);
+        }
+
+        let history = &dashboard.history;
+        assert_eq!(history.throughput, [10_000, 10_000, 10_000]);
+        assert_eq!(history.loss, [0, 100, 0]);
+        assert_eq!(history.jitter, [250, 250, 250]);
+        assert_eq!((history.received, history.lost), (297, 1));
+
+        let area = Rect::new(0, 0, 80, 24);
+        let mut buf = Buffer::empty(area);
+        history.render(area, &mut buf);
+        let screen = text(&buf);
+        assert!(screen.contains("Intervals 3 | Received 297 | Lost 1 (0.34 %)"));
+        assert!(screen.contains("Throughput 10.00 Mbps"));
+        assert!(screen.contains("Loss 0.00 %"));
+        assert!(screen.contains("Jitter 0.250 ms"));
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut history = History::default();
+        for _ in 0..HISTORY + 10 {
+            history.push(&IntervalResult::default());
+        }
+        assert_eq!(history.throughput.len(), HISTORY);
+        assert_eq!(history.intervals, (HISTORY + 10) as u64);
+    }
+}
@@ -0,0 +1,328 @@
+//! Throughput discovery.
+//!
+//! [`CapacitySearch`] answers the question most tests are run for, how fast
+//! can I send without loss, by binary-searching the bitrate: it runs short
+//! tests against one server, halving the range between the highest rate that
+//! kept the loss within the limit and the lowest one that did not, until the
+//! range is narrow enough. The range is halved on a log scale, so a search
+//! from 1 Mbps to 10 Gbps takes no more trials than one from 1 to 10 Mbps.
+//!
+//! The [`Discovery`] gives the capacity with its bounds: the true capacity
+//! lies between the highest passing and the lowest failing rate, as far as
+//! the loss of one trial tells. Run it again to see how stable they are.
+//!
+//! Like [`crate::runner::TestRunner`], the server must answer every trial:
+//! build it with [`crate::ServerBuilder::send_results_to_client`] and call
+//! `run` again after each test, like the `udpopt server` command does.
+//!
+//! ```no_run
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//! use udpopt::discovery::CapacitySearch;
+//!
+//! let mut sock = UdpSocket::bind("0.0.0.0:0").unwrap();
+//! sock.connect("192.0.2.10:5201").unwrap();
+//!
+//! let found = CapacitySearch::new(1e6, 1e9)
+//!     .max_loss_percent(0.1)
+//!     .trial_duration(Duration::from_secs(2))
+//!     .run(&mut sock)
+//!     .unwrap();
+//! println!(
+//!     "{:.1} bps, below {:.1} bps",
+//!     found.capacity_bps, found.upper_bps
+//! );
+//! ```
+
+use std::{sync::mpsc, thread, time::Duration};
+
+use crate::{
+    builder::ClientBuilder, errors::UdpOptError, result::TestResult, socket::DatagramSocket,
+    utils::net_utils::ClientCommand,
+};
+
+/// Pause between two trials, so the server is back in `run` for the next one
+const DEFAULT_GAP: Duration = Duration::from_millis(500);
+
+/// How long every trial waits for the server's results after its FIN
+const DEFAULT_RESULTS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Binary-searches the highest bitrate whose loss stays within a limit.
+#[derive(Debug, Clone)]
+pub struct CapacitySearch {
+    min_bps: f64,
+    max_bps: f64,
+    payload_size: usize,
+    trial: Duration,
+    max_loss_percent: f64,
+    precision: f64,
+    max_trials: usize,
+    gap: Duration,
+    results_timeout: Duration,
+}
+
+impl CapacitySearch {
+    /// Searches between `min_bps` and `max_bps` with 1200 byte packets,
+    /// 2 second trials, no loss allowed, down to 5 % of the rate and at most
+    /// 12 trials.
+    ///
+    /// # Panics
+    /// If `min_bps` is not positive or not below `max_bps`.
+    pub fn new(min_bps: f64, max_bps: f64) -> Self {
+        assert!(
+            min_bps > 0.0 && min_bps < max_bps,
+            "the search range must be 0 < min < max"
+        );
+        Self {
+            min_bps,
+            max_bps,
+            payload_size: 1200,
+            trial: Duration::from_secs(2),
+            max_loss_percent: 0.0,
+            precision: 0.05,
+            max_trials: 12,
+            gap: DEFAULT_GAP,
+            results_timeout: DEFAULT_RESULTS_TIMEOUT,
+        }
+    }
+
+    /// Sets the packet length (bytes), header included.
+    pub fn payload_size(mut self, payload_size: usize) -> Self {
+        self.payload_size = payload_size;
+        self
+    }
+
+    /// Sets how long every trial sends.
+    pub fn trial_duration(mut self, trial: Duration) -> Self {
+        self.trial = trial;
+        self
+    }
+
+    /// Highest loss (%) a trial may have and still pass.
+    pub fn max_loss_percent(mut self, percent: f64) -> Self {
+        self.max_loss_percent = percent;
+        self
+    }
+
+    /// Stops when the bounds are closer than this fraction of the upper
+    /// one, e.g. 0.05 for 5 %.
+    pub fn precision(mut self, fraction: f64) -> Self {
+        self.precision = fraction;
+        self
+    }
+
+    /// Stops after this many trials even if the bounds are still apart, at
+    /// least 1.
+    pub fn max_trials(mut self, trials: usize) -> Self {
+        self.max_trials = trials.max(1);
+        self
+    }
+
+    /// Sets the pause between two trials (default 500 ms).
+    pub fn gap(mut self, gap: Duration) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    /// Sets how long every trial waits for the server's results (default 2 seconds).
+    pub fn results_timeout(mut self, timeout: Duration) -> Self {
+        self.results_timeout = timeout;
+        self
+    }
+
+    /// Runs the search on the connected `sock`.
+    ///
+    /// # Errors
+    /// - [`UdpOptError::MissingResults`] if the server did not answer a trial.
+    /// - any error of [`crate::UdpClient::run`].
+    pub fn run<S: DatagramSocket>(&self, sock: &mut S) -> Result<Discovery, UdpOptError> {
+        self.search(|trial, bitrate_bps| {
+            if trial > 0 {
+                thread::sleep(self.gap);
+            }
+            let (tx, rx) = mpsc::channel();
+            let mut client = ClientBuilder::new(bitrate_bps, self.payload_size, self.trial)
+                .remote_results(self.results_timeout)
+                .build(rx);
+            let _ = tx.send(ClientCommand::Start);
+            client
+                .run(sock)?
+                .remote
+                .ok_or(UdpOptError::MissingResults(trial))
+        })
+    }
+
+    /// The search, `run_trial` measuring one bitrate
+    fn search(
+        &self,
+        mut run_trial: impl FnMut(usize, f64) -> Result<TestResult, UdpOptError>,
+    ) -> Result<Discovery, UdpOptError> {
+        let mut trials: Vec<Trial> = Vec::new();
+        let mut measure = |bitrate_bps: f64| -> Result<bool, UdpOptError> {
+            event!(info, trial = trials.len(), bitrate_bps, "starting trial");
+            let result = run_trial(trials.len(), bitrate_bps)?;
+            let passed = result.loss_percent <= self.max_loss_percent;
+            trials.push(Trial {
+                bitrate_bps,
+                passed,
+                result,
+            });
+            Ok(passed)
+        };
+
+        // the top of the range first, a link faster than asked ends the search
+        let (lower, upper) = if measure(self.max_bps)? {
+            (self.max_bps, self.max_bps)
+        } else if self.max_trials < 2 {
+            (0.0, self.max_bps)
+        } else if !measure(self.min_bps)? {
+            (0.0, self.min_bps)
+        } else {
+            let (mut lower, mut upper) = (self.min_bps, self.max_bps);
+            for _ in 2..self.max_trials {
+                if upper - lower <= upper * self.precision {
+                    break;
+                }
+                let mid = (lower * upper).sqrt();
+                if measure(mid)? {
+                    lower = mid;
+                } else {
+                    upper = mid;
+                }
+            }
+            (lower, upper)
+        };
+
+        let throughput_bps = trials
+            .iter()
+            .filter(|t| t.passed && t.bitrate_bps == lower)
+            .map(|t| t.result.mean_bitrate)
+            .next()
+            .unwrap_or(0.0);
+        Ok(Discovery {
+            capacity_bps: lower,
+            upper_bps: upper,
+            throughput_bps,
+            saturated: lower == self.max_bps,
+            trials,
+        })
+    }
+}
+
+/// Outcome of a [`CapacitySearch`].
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    /// Highest bitrate that passed (bits/sec), 0 if none did: the lower
+    /// bound of the capacity.
+    pub capacity_bps: f64,
+    /// Lowest bitrate that failed (bits/sec): the upper bound of the
+    /// capacity, equal to `capacity_bps` when even the top of the range
+    /// passed.
+    pub upper_bps: f64,
+    /// What the server received at the capacity (bits/sec).
+    pub throughput_bps: f64,
+    /// Whether the top of the range passed, the link may be faster.
+    pub saturated: bool,
+    /// Every trial, in the order they ran.
+    pub trials: Vec<Trial>,
+}
+
+/// One test of a [`CapacitySearch`].
+#[derive(Debug, Clone)]
+pub struct Trial {
+    /// Bitrate sent (bits/sec).
+    pub bitrate_bps: f64,
+    /// Whether the loss stayed within the limit.
+    pub passed: bool,
+    /// The server's results.
+    pub result: TestResult,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ServerBuilder, ServerCommand};
+    use std::net::UdpSocket;
+
+    /// A link of `capacity_bps` dropping whatever is sent above it
+    fn link(capacity_bps: f64) -> impl FnMut(usize, f64) -> Result<TestResult, UdpOptError> {
+        move |_, bitrate_bps| {
+            let mut result = TestResult::from_intervals(&[]);
+            result.mean_bitrate = bitrate_bps.min(capacity_bps);
+            result.loss_percent = (bitrate_bps - capacity_bps).max(0.0) / bitrate_bps * 100.0;
+            Ok(result)
+        }
+    }
+
+    #[test]
+    fn test_search_brackets_the_capacity() {
+        let search = CapacitySearch::new(1e6, 100e6).precision(0.01);
+        let found = search.search(link(37e6)).unwrap();
+        assert!(found.capacity_bps <= 37e6 && 37e6 < found.upper_bps);
+        assert!(found.upper_bps - found.capacity_bps <= found.upper_bps * 0.01);
+        assert_eq!(found.throughput_bps, found.capacity_bps);
+        assert!(!found.saturated);
+        assert_eq!(found.trials[0].bitrate_bps, 100e6);
+        assert!(found.trials.len() <= 12);
+
+        // 1 % of loss tolerated moves the capacity up
+        let tolerant = search.clone().max_loss_percent(1.0);
+        let found = tolerant.search(link(37e6)).unwrap();
+        assert!(found.capacity_bps > 37e6);
+        assert!(found.capacity_bps <= 37e6 / 0.99 && 37e6 / 0.99 < found.upper_bps);
+
+        // a trial budget stops early
+        let found = search.clone().max_trials(4).search(link(37e6)).unwrap();
+        assert_eq!(found.trials.len(), 4);
+        assert!(found.capacity_bps <= 37e6 && 37e6 < found.upper_bps);
+    }
+
+    #[test]
+    fn test_search_outside_the_range() {
+        let search = CapacitySearch::new(10e6, 100e6);
+
+        let fast = search.search(link(1e9)).unwrap();
+        assert!(fast.saturated);
+        assert_eq!((fast.capacity_bps, fast.upper_bps), (100e6, 100e6));
+        assert_eq!(fast.trials.len(), 1);
+
+        let slow = search.search(link(1e6)).unwrap();
+        assert_eq!((slow.capacity_bps, slow.upper_bps), (0.0, 10e6));
+        assert_eq!(slow.throughput_bps, 0.0);
+        assert_eq!(slow.trials.len(), 2);
+
+        let once = search.clone().max_trials(1).search(link(50e6)).unwrap();
+        assert_eq!((once.capacity_bps, once.upper_bps), (0.0, 100e6));
+    }
+
+    #[test]
+    fn test_run_against_one_server() {
+        let mut server_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_sock
+            .connect(server_sock.local_addr().unwrap())
+            .unwrap();
+
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(100))
+            .send_results_to_client()
+            .build(server_rx);
+        let server_thread = thread::spawn(move || {
+            server_tx.send(ServerCommand::Start).unwrap();
+            server.run(&mut server_sock).unwrap();
+        });
+
+        // loopback carries the whole range, the first trial ends the search
+        let found = CapacitySearch::new(100e3, 1e6)
+            .payload_size(500)
+            .trial_duration(Duration::from_millis(300))
+            .max_loss_percent(5.0)
+            .run(&mut client_sock)
+            .unwrap();
+        server_thread.join().unwrap();
+
+        assert!(found.saturated);
+        assert_eq!(found.trials.len(), 1);
+        assert!(found.throughput_bps > 0.0);
+    }
+}
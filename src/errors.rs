@@ -1,31 +1,226 @@
-use std::{io, net::AddrParseError, time::Duration};
+use std::{
+    fmt, io,
+    net::{AddrParseError, SocketAddr},
+    time::Duration,
+};
 
 use thiserror::Error;
 
+use crate::utils::net_utils::{IntervalResult, is_transient_io_error, is_unreachable_error};
+
+/// Errors of the library.
+///
+/// The variants wrapping an underlying error (an `io::Error`, a
+/// [`HeaderError`]...) name the operation that failed and keep the cause as
+/// their [`source`](std::error::Error::source), print the whole chain with
+/// [`ui::format_error`](crate::ui::format_error). New variants may be added
+/// in a minor release.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum UdpOptError {
-    #[error("Failed to pind socket address: {0}")]
-    BindFailed(io::Error),
-    #[error("Udp socket failed to send data: {0}")]
-    SendFailed(io::Error),
-
-    #[error(" Udp socket failed to receive data: {0}")]
-    RecvFailed(io::Error),
-    #[error("Client faild to connect : {0}")]
-    ConnectFailed(io::Error),
+    #[error("Failed to bind the socket to {addr}")]
+    BindFailed {
+        addr: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    /// `peer` is `None` when the socket is not connected and the address
+    /// is unknown.
+    #[error("Failed to send {bytes} bytes{}", to_peer(.peer))]
+    SendFailed {
+        peer: Option<SocketAddr>,
+        bytes: usize,
+        #[source]
+        source: io::Error,
+    },
+    /// A send was refused by an ICMP unreachable: nothing listens on the
+    /// peer's port (`ECONNREFUSED` on a connected socket), or no route
+    /// reaches it. The client outlasts it for `ClientBuilder::unreachable_grace`.
+    #[error("{} is unreachable", the_peer(.peer))]
+    PeerUnreachable {
+        peer: Option<SocketAddr>,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to receive a datagram")]
+    RecvFailed(#[source] io::Error),
+    #[error("Failed to connect to {peer}")]
+    ConnectFailed {
+        peer: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
 
     #[error("Connection timed out after {0:?}")]
     Timeout(Duration),
 
-    #[error("Invalid address: {0}")]
+    #[error("Invalid address")]
     InvalidAddress(#[from] AddrParseError),
-    #[error("Get random for the test  faild ")]
-    FailToGetRandom(io::Error),
-    #[error("Socket receive reaches timeout")]
+    #[error("Invalid IP prefix: {0}")]
+    InvalidPrefix(String),
+    #[error("Invalid port range: {0}, expected PORT or FIRST-LAST")]
+    InvalidPortRange(String),
+    #[error("Unknown jitter estimator: {0}, expected rfc3550, ipdv or mad")]
+    UnknownJitterEstimator(String),
+    #[error("Failed to get random bytes for the test")]
+    FailToGetRandom(#[source] io::Error),
+    #[error("Failed to generate the packet payload")]
+    PayloadFailed(#[source] io::Error),
+    #[error("Failed to set the socket timeout")]
     SocketTimeout,
 
-    #[error("Unexpected Stop  command ")]
+    #[error("Unexpected command in this state")]
     UnexpectedCommand,
-    #[error("channel error")]
+    #[error("The control channel is closed")]
     ChannelClosed,
+    #[error("Failed to write the packet trace")]
+    TraceFailed(#[source] io::Error),
+    #[error("Control connection failed")]
+    ControlFailed(#[source] io::Error),
+    #[error("Protocol violation: {0}")]
+    ProtocolViolation(String),
+    #[error("Malformed packet")]
+    MalformedPacket(#[from] HeaderError),
+    #[error("Failed to install the signal handler: {0}")]
+    SignalFailed(String),
+    #[error("Failed to export interval results")]
+    SinkFailed(#[source] io::Error),
+    #[error("Failed to set the thread scheduling")]
+    SchedulingFailed(#[source] io::Error),
+    #[error("Failed to set up ECN on the socket")]
+    EcnFailed(#[source] io::Error),
+    #[error("Failed to set the DSCP of the socket")]
+    DscpFailed(#[source] io::Error),
+    #[error("Failed to bind the socket to the interface")]
+    DeviceFailed(#[source] io::Error),
+    #[error("Failed to read the interface counters")]
+    NicCountersFailed(#[source] io::Error),
+    #[error("Reverse mode refused: {0}")]
+    ReverseRefused(String),
+    #[error("Failed to count the drops of the socket's receive buffer")]
+    DropCountFailed(#[source] io::Error),
+    #[error("Failed to set the don't-fragment bit")]
+    FragmentationFailed(#[source] io::Error),
+    #[error("Failed to make the socket non-blocking")]
+    NonBlockingFailed(#[source] io::Error),
+    #[error("Failed to take over the inherited socket")]
+    InheritedSocket(#[source] io::Error),
+    #[error("The server did not return the results of run {0}")]
+    MissingResults(usize),
+    #[error("Failed to encode or decode the test report: {0}")]
+    ReportFormat(String),
+    #[error("Failed to read or write the test report")]
+    ReportFailed(#[source] io::Error),
+    #[error("Invalid scenario: {0}")]
+    InvalidScenario(String),
+    #[error("Invalid test configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Flow {flow} failed")]
+    FlowFailed {
+        flow: String,
+        #[source]
+        source: Box<UdpOptError>,
+    },
+}
+
+impl UdpOptError {
+    /// Whether the error may go away by itself, so the operation is worth
+    /// retrying: a send or a receive that was interrupted, found the socket
+    /// buffer full (`ENOBUFS`, `WouldBlock`) or timed out. A refused
+    /// connection, an unreachable network or a bad packet are not.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::SendFailed { source, .. } | Self::RecvFailed(source) => {
+                is_transient_io_error(source)
+            }
+            Self::Timeout(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Maps the error of sending `bytes` to `peer` to [`UdpOptError::SendFailed`],
+    /// or [`UdpOptError::PeerUnreachable`] for an ICMP unreachable.
+    pub(crate) fn send_failed(
+        peer: Option<SocketAddr>,
+        bytes: usize,
+    ) -> impl FnOnce(io::Error) -> Self {
+        move |source| {
+            if is_unreachable_error(&source) {
+                Self::PeerUnreachable { peer, source }
+            } else {
+                Self::SendFailed {
+                    peer,
+                    bytes,
+                    source,
+                }
+            }
+        }
+    }
+
+    /// The peer the failed operation was addressed to, when known.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        match self {
+            Self::SendFailed { peer, .. } | Self::PeerUnreachable { peer, .. } => *peer,
+            Self::ConnectFailed { peer, .. } => Some(*peer),
+            _ => None,
+        }
+    }
+}
+
+/// " to `peer`", nothing when unknown
+fn to_peer(peer: &Option<SocketAddr>) -> String {
+    peer.map_or_else(String::new, |peer| format!(" to {peer}"))
+}
+
+/// "Peer `peer`", "The peer" when unknown
+fn the_peer(peer: &Option<SocketAddr>) -> String {
+    peer.map_or_else(|| "The peer".to_string(), |peer| format!("Peer {peer}"))
+}
+
+/// Why a datagram is not a valid udpopt packet, see [`UdpOptError::MalformedPacket`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum HeaderError {
+    #[error("{0} bytes, too short for the header")]
+    TooShort(usize),
+    #[error("bad magic")]
+    BadMagic,
+    #[error("unsupported version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unknown flags {0:#x}")]
+    UnknownFlags(u32),
+    #[error("{0} microseconds in the timestamp")]
+    BadTimestamp(u32),
+    #[error("longer than the {0}-byte receive buffer, truncated")]
+    Truncated(usize),
+}
+
+/// Error of a server run, with the intervals completed before it happened.
+///
+/// Converts into its [`UdpOptError`], so `?` still works in functions that
+/// do not care about the partial results. Displays as its error, the source
+/// chain continues with the error's cause.
+#[derive(Debug)]
+pub struct RunError {
+    /// What stopped the test.
+    pub error: UdpOptError,
+    /// Intervals completed before the error, empty if it came before the first packet.
+    pub intervals: Vec<IntervalResult>,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+impl From<RunError> for UdpOptError {
+    fn from(e: RunError) -> Self {
+        e.error
+    }
 }
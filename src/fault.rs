@@ -0,0 +1,316 @@
+//! # Impairment injection
+//!
+//! A [`FaultInjector`] given to `ClientBuilder::fault_injector` makes the
+//! client drop, duplicate, delay or reorder a fraction of its data packets on
+//! purpose. What it did is returned in [`ClientReport::faults`], the ground
+//! truth to check the server's loss, reordering and jitter accounting against.
+//!
+//! Every packet gets at most one fault. The FIN is never impaired, the packets
+//! still held back are sent before it.
+//!
+//! ```
+//! use std::time::Duration;
+//! use udpopt::{ClientBuilder, FaultInjector};
+//!
+//! let faults = FaultInjector::new()
+//!     .drop(0.01)
+//!     .reorder(0.005)
+//!     .delay(0.01, Duration::from_millis(5))
+//!     .seed(42);
+//! let builder = ClientBuilder::new(10e6, 1200, Duration::from_secs(10)).fault_injector(faults);
+//! ```
+//!
+//! [`ClientReport::faults`]: crate::ClientReport::faults
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    errors::UdpOptError, runtime::AsyncDatagram, socket::DatagramSocket,
+    utils::net_utils::is_transient_send_error,
+};
+
+/// Fractions of the data packets to impair, see the [module docs](self).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FaultInjector {
+    drop: f64,
+    duplicate: f64,
+    reorder: f64,
+    delay: f64,
+    delay_by: Duration,
+    seed: Option<u64>,
+}
+
+impl FaultInjector {
+    /// An injector that does nothing until fractions are set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops `fraction` (0.0..=1.0) of the packets.
+    pub fn drop(mut self, fraction: f64) -> Self {
+        self.drop = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sends `fraction` of the packets twice.
+    pub fn duplicate(mut self, fraction: f64) -> Self {
+        self.duplicate = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sends `fraction` of the packets after the next packet.
+    pub fn reorder(mut self, fraction: f64) -> Self {
+        self.reorder = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Holds `fraction` of the packets back for `by` before sending them.
+    ///
+    /// The timestamp in the header is not updated, so the server sees the
+    /// extra transit time as jitter.
+    pub fn delay(mut self, fraction: f64, by: Duration) -> Self {
+        self.delay = fraction.clamp(0.0, 1.0);
+        self.delay_by = by;
+        self
+    }
+
+    /// Seeds the random draws, so every run impairs the same packets.
+    ///
+    /// Without a seed every run draws differently.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// State for one run
+    pub(crate) fn start(&self) -> Impairer {
+        let seed = self.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64
+        });
+        Impairer {
+            faults: *self,
+            rng: seed,
+            held: VecDeque::new(),
+            sent: 0,
+            stats: FaultStats::default(),
+        }
+    }
+}
+
+/// Packets impaired by a [`FaultInjector`] during a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    /// Packets never sent.
+    pub dropped: u64,
+    /// Packets sent twice.
+    pub duplicated: u64,
+    /// Packets sent after the next one.
+    pub reordered: u64,
+    /// Packets held back for the configured delay.
+    pub delayed: u64,
+}
+
+/// A packet kept for later
+#[derive(Debug)]
+struct Held {
+    packet: Vec<u8>,
+    release: Release,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Release {
+    /// Once the time has come
+    At(Instant),
+    /// Once more packets than this have been sent
+    AfterSent(u64),
+}
+
+/// Applies a [`FaultInjector`] to the packets of one run
+#[derive(Debug)]
+pub(crate) struct Impairer {
+    faults: FaultInjector,
+    /// splitmix64 state
+    rng: u64,
+    held: VecDeque<Held>,
+    /// Packets sent right away so far
+    sent: u64,
+    stats: FaultStats,
+}
+
+impl Impairer {
+    /// Decides the fate of `packet`, returns whether to send it now.
+    ///
+    /// Delayed, reordered and duplicated packets are copied and sent later by
+    /// [`send_due`](Self::send_due).
+    pub(crate) fn admit(&mut self, packet: &[u8], now: Instant) -> bool {
+        let f = self.faults;
+        let draw = self.next_f64();
+        let mut bound = f.drop;
+        if draw < bound {
+            self.stats.dropped += 1;
+            return false;
+        }
+        bound += f.duplicate;
+        if draw < bound {
+            self.stats.duplicated += 1;
+            self.hold(packet, Release::At(now));
+            self.sent += 1;
+            return true;
+        }
+        bound += f.reorder;
+        if draw < bound {
+            self.stats.reordered += 1;
+            self.hold(packet, Release::AfterSent(self.sent));
+            return false;
+        }
+        bound += f.delay;
+        if draw < bound {
+            self.stats.delayed += 1;
+            self.hold(packet, Release::At(now + f.delay_by));
+            return false;
+        }
+        self.sent += 1;
+        true
+    }
+
+    /// Sends the held packets whose time has come, all of them with `all`.
+    pub(crate) fn send_due<S: DatagramSocket>(
+        &mut self,
+        sock: &S,
+        all: bool,
+    ) -> Result<(), UdpOptError> {
+        while let Some(packet) = self.next_due(Instant::now(), all) {
+            match sock.send(&packet) {
+                Ok(_) => {}
+                Err(e) if is_transient_send_error(&e) => {}
+                Err(e) => {
+                    return Err(UdpOptError::send_failed(
+                        sock.peer_addr().ok(),
+                        packet.len(),
+                    )(e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Async version of [`send_due`](Self::send_due).
+    pub(crate) async fn send_due_async<S: AsyncDatagram>(
+        &mut self,
+        sock: &S,
+        all: bool,
+    ) -> Result<(), UdpOptError> {
+        while let Some(packet) = self.next_due(Instant::now(), all) {
+            match sock.send(&packet).await {
+                Ok(_) => {}
+                Err(e) if is_transient_send_error(&e) => {}
+                Err(e) => {
+                    return Err(UdpOptError::send_failed(
+                        sock.peer_addr().ok(),
+                        packet.len(),
+                    )(e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn stats(&self) -> FaultStats {
+        self.stats
+    }
+
+    fn hold(&mut self, packet: &[u8], release: Release) {
+        self.held.push_back(Held {
+            packet: packet.to_vec(),
+            release,
+        });
+    }
+
+    /// Takes the oldest held packet that may go
+    fn next_due(&mut self, now: Instant, all: bool) -> Option<Vec<u8>> {
+        let sent = self.sent;
+        let pos = self.held.iter().position(|h| {
+            all || match h.release {
+                Release::At(at) => now >= at,
+                Release::AfterSent(n) => sent > n,
+            }
+        })?;
+        self.held.remove(pos).map(|h| h.packet)
+    }
+
+    /// Uniform in `0.0..1.0`
+    fn next_f64(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::MockSocket;
+
+    /// Runs `packets` one-byte packets through `faults`, returns what left the socket
+    fn impair(faults: FaultInjector, packets: u8) -> (Vec<u8>, FaultStats) {
+        let mut sock = MockSocket::new();
+        sock.connect("192.0.2.1:5201".parse().unwrap());
+        let mut impairer = faults.start();
+        for p in 0..packets {
+            if impairer.admit(&[p], Instant::now()) {
+                sock.send(&[p]).unwrap();
+            }
+            impairer.send_due(&sock, false).unwrap();
+        }
+        impairer.send_due(&sock, true).unwrap();
+        let sent = sock.take_sent().into_iter().map(|(p, _)| p[0]).collect();
+        (sent, impairer.stats())
+    }
+
+    #[test]
+    fn test_no_faults_by_default() {
+        let (sent, stats) = impair(FaultInjector::new(), 100);
+        assert_eq!(sent, (0..100).collect::<Vec<u8>>());
+        assert_eq!(stats, FaultStats::default());
+    }
+
+    #[test]
+    fn test_each_fault() {
+        let (sent, stats) = impair(FaultInjector::new().drop(1.0), 10);
+        assert!(sent.is_empty());
+        assert_eq!(stats.dropped, 10);
+
+        let (sent, stats) = impair(FaultInjector::new().duplicate(1.0), 3);
+        assert_eq!(sent, [0, 0, 1, 1, 2, 2]);
+        assert_eq!(stats.duplicated, 3);
+
+        // every other packet swaps with the one after it
+        let (sent, _) = impair(FaultInjector::new().reorder(0.5).seed(7), 200);
+        let mut sorted = sent.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..200).collect::<Vec<u8>>());
+        assert_ne!(sent, sorted);
+
+        // held longer than the run lasts, they all go out at the end in order
+        let (sent, stats) = impair(FaultInjector::new().delay(1.0, Duration::from_secs(60)), 5);
+        assert_eq!(sent, [0, 1, 2, 3, 4]);
+        assert_eq!(stats.delayed, 5);
+    }
+
+    #[test]
+    fn test_seed_is_reproducible() {
+        let faults = FaultInjector::new().drop(0.2).duplicate(0.1).seed(42);
+        let (first, stats) = impair(faults, 200);
+        assert_eq!(impair(faults, 200), (first, stats));
+        assert!(stats.dropped > 20 && stats.dropped < 60, "{stats:?}");
+    }
+}
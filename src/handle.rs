@@ -0,0 +1,475 @@
+//! # Spawned tests
+//!
+//! [`ClientBuilder::spawn`] and [`ServerBuilder::spawn`] build a test and
+//! run it on its own thread, returning a handle that owns the control
+//! channel and the join handle, so there is no channel to wire by hand:
+//!
+//! ```no_run
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//! use udpopt::ClientBuilder;
+//!
+//! let sock = UdpSocket::bind("0.0.0.0:0")?;
+//! sock.connect("192.0.2.1:5000")?;
+//! let client = ClientBuilder::new(1_000_000.0, 1200, Duration::from_secs(10)).spawn(sock);
+//!
+//! client.start()?;
+//! std::thread::sleep(Duration::from_secs(5));
+//! client.set_bitrate(5_000_000.0)?;
+//! let report = client.wait()?;
+//! println!("{} packets sent", report.packets_sent);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`ClientBuilder::spawn_async`] and [`ServerBuilder::spawn_async`] do the
+//! same on a tokio task, with async methods. A client or server already
+//! built runs the same way with [`UdpClient::spawn`] and its siblings,
+//! which leave the channel it was built with unused.
+//!
+//! [`ClientBuilder::spawn`]: crate::ClientBuilder::spawn
+//! [`ServerBuilder::spawn`]: crate::ServerBuilder::spawn
+//! [`ClientBuilder::spawn_async`]: crate::ClientBuilder::spawn_async
+//! [`ServerBuilder::spawn_async`]: crate::ServerBuilder::spawn_async
+//! [`UdpClient::spawn`]: crate::UdpClient::spawn
+
+use std::{panic, sync::mpsc::Sender, thread::JoinHandle};
+
+use tokio::{
+    sync::{mpsc, oneshot},
+    task,
+};
+
+use crate::{
+    errors::{RunError, UdpOptError},
+    result::ClientReport,
+    utils::net_utils::{
+        ClientCommand, ClientProgress, IntervalResult, Rate, ServerCommand, ServerStatus,
+    },
+};
+
+/// Commands a spawned async test can have queued
+pub(crate) const CONTROL_CAPACITY: usize = 16;
+
+/// A client running on its own thread, see the [module docs](self).
+#[derive(Debug)]
+pub struct ClientHandle {
+    control: Sender<ClientCommand>,
+    thread: JoinHandle<Result<ClientReport, UdpOptError>>,
+}
+
+impl ClientHandle {
+    pub(crate) fn new(
+        control: Sender<ClientCommand>,
+        thread: JoinHandle<Result<ClientReport, UdpOptError>>,
+    ) -> Self {
+        Self { control, thread }
+    }
+
+    /// Starts sending.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    pub fn start(&self) -> Result<(), UdpOptError> {
+        self.send(ClientCommand::Start)
+    }
+
+    /// Stops sending, the client then sends its FIN and finishes.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    pub fn stop(&self) -> Result<(), UdpOptError> {
+        self.send(ClientCommand::Stop)
+    }
+
+    /// Sends at `bitrate_bps` bits per second from now on.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    pub fn set_bitrate(&self, bitrate_bps: f64) -> Result<(), UdpOptError> {
+        self.send(ClientCommand::SetRate(Rate::Bps(bitrate_bps)))
+    }
+
+    /// Progress of the test so far, without waiting for it to finish. The
+    /// client answers when it next checks its control channel, at the
+    /// latest after the current pacing wait.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    ///
+    /// # Panics
+    /// If called on an async runtime, which [`AsyncClientHandle`] is for.
+    pub fn status(&self) -> Result<ClientProgress, UdpOptError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(ClientCommand::Status(reply))?;
+        rx.blocking_recv().map_err(|_| UdpOptError::ChannelClosed)
+    }
+
+    /// Sends any other [`ClientCommand`].
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    pub fn send(&self, command: ClientCommand) -> Result<(), UdpOptError> {
+        self.control
+            .send(command)
+            .map_err(|_| UdpOptError::ChannelClosed)
+    }
+
+    /// Whether the client has finished, [`wait`](Self::wait) then returns at once.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the client to finish and returns what
+    /// [`UdpClient::run`](crate::UdpClient::run) returned; a panic of the
+    /// client thread is resumed here. The handle gives up the control
+    /// channel, so a client never started returns
+    /// [`UdpOptError::ChannelClosed`] and a paused one stops.
+    pub fn wait(self) -> Result<ClientReport, UdpOptError> {
+        drop(self.control);
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+    }
+}
+
+/// A server running on its own thread, see the [module docs](self).
+#[derive(Debug)]
+pub struct ServerHandle {
+    control: Sender<ServerCommand>,
+    thread: JoinHandle<Result<Vec<IntervalResult>, RunError>>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        control: Sender<ServerCommand>,
+        thread: JoinHandle<Result<Vec<IntervalResult>, RunError>>,
+    ) -> Self {
+        Self { control, thread }
+    }
+
+    /// Starts receiving.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the server has already finished.
+    pub fn start(&self) -> Result<(), UdpOptError> {
+        self.send(ServerCommand::Start)
+    }
+
+    /// Stops receiving, the server then closes the last interval and finishes.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the server has already finished.
+    pub fn stop(&self) -> Result<(), UdpOptError> {
+        self.send(ServerCommand::Stop)
+    }
+
+    /// Counters of the test so far, without waiting for it to finish.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the server has already finished.
+    ///
+    /// # Panics
+    /// If called on an async runtime, which [`AsyncServerHandle`] is for.
+    pub fn status(&self) -> Result<ServerStatus, UdpOptError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(ServerCommand::Status(reply))?;
+        rx.blocking_recv().map_err(|_| UdpOptError::ChannelClosed)
+    }
+
+    /// Sends any other [`ServerCommand`].
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the server has already finished.
+    pub fn send(&self, command: ServerCommand) -> Result<(), UdpOptError> {
+        self.control
+            .send(command)
+            .map_err(|_| UdpOptError::ChannelClosed)
+    }
+
+    /// Whether the server has finished, [`wait`](Self::wait) then returns at once.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the server to finish (a FIN, [`stop`](Self::stop) or the
+    /// idle timeout) and returns what [`UdpServer::run`](crate::UdpServer::run)
+    /// returned; a panic of the server thread is resumed here.
+    ///
+    /// The server keeps running while waited on, call [`stop`](Self::stop)
+    /// first to end it early.
+    pub fn wait(self) -> Result<Vec<IntervalResult>, RunError> {
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+    }
+}
+
+/// An async client running on a tokio task, see the [module docs](self).
+#[derive(Debug)]
+pub struct AsyncClientHandle {
+    control: mpsc::Sender<ClientCommand>,
+    task: task::JoinHandle<Result<ClientReport, UdpOptError>>,
+}
+
+impl AsyncClientHandle {
+    pub(crate) fn new(
+        control: mpsc::Sender<ClientCommand>,
+        task: task::JoinHandle<Result<ClientReport, UdpOptError>>,
+    ) -> Self {
+        Self { control, task }
+    }
+
+    /// Starts sending.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    pub async fn start(&self) -> Result<(), UdpOptError> {
+        self.send(ClientCommand::Start).await
+    }
+
+    /// Stops sending, the client then sends its FIN and finishes.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    pub async fn stop(&self) -> Result<(), UdpOptError> {
+        self.send(ClientCommand::Stop).await
+    }
+
+    /// Sends at `bitrate_bps` bits per second from now on.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    pub async fn set_bitrate(&self, bitrate_bps: f64) -> Result<(), UdpOptError> {
+        self.send(ClientCommand::SetRate(Rate::Bps(bitrate_bps)))
+            .await
+    }
+
+    /// Progress of the test so far, without waiting for it to finish.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    pub async fn status(&self) -> Result<ClientProgress, UdpOptError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(ClientCommand::Status(reply)).await?;
+        rx.await.map_err(|_| UdpOptError::ChannelClosed)
+    }
+
+    /// Sends any other [`ClientCommand`].
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the client has already finished.
+    pub async fn send(&self, command: ClientCommand) -> Result<(), UdpOptError> {
+        self.control
+            .send(command)
+            .await
+            .map_err(|_| UdpOptError::ChannelClosed)
+    }
+
+    /// Whether the client has finished, [`wait`](Self::wait) then returns at once.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits for the client to finish and returns what
+    /// [`AsyncUdpClient::run`](crate::AsyncUdpClient::run) returned; a panic
+    /// of the client task is resumed here. The handle gives up the control
+    /// channel, so a client never started returns
+    /// [`UdpOptError::ChannelClosed`] and a paused one stops.
+    pub async fn wait(self) -> Result<ClientReport, UdpOptError> {
+        drop(self.control);
+        join(self.task).await
+    }
+}
+
+/// An async server running on a tokio task, see the [module docs](self).
+#[derive(Debug)]
+pub struct AsyncServerHandle {
+    control: mpsc::Sender<ServerCommand>,
+    task: task::JoinHandle<Result<Vec<IntervalResult>, RunError>>,
+}
+
+impl AsyncServerHandle {
+    pub(crate) fn new(
+        control: mpsc::Sender<ServerCommand>,
+        task: task::JoinHandle<Result<Vec<IntervalResult>, RunError>>,
+    ) -> Self {
+        Self { control, task }
+    }
+
+    /// Starts receiving.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the server has already finished.
+    pub async fn start(&self) -> Result<(), UdpOptError> {
+        self.send(ServerCommand::Start).await
+    }
+
+    /// Stops receiving, the server then closes the last interval and finishes.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the server has already finished.
+    pub async fn stop(&self) -> Result<(), UdpOptError> {
+        self.send(ServerCommand::Stop).await
+    }
+
+    /// Counters of the test so far, without waiting for it to finish.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the server has already finished.
+    pub async fn status(&self) -> Result<ServerStatus, UdpOptError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(ServerCommand::Status(reply)).await?;
+        rx.await.map_err(|_| UdpOptError::ChannelClosed)
+    }
+
+    /// Sends any other [`ServerCommand`].
+    ///
+    /// # Errors
+    /// [`UdpOptError::ChannelClosed`] if the server has already finished.
+    pub async fn send(&self, command: ServerCommand) -> Result<(), UdpOptError> {
+        self.control
+            .send(command)
+            .await
+            .map_err(|_| UdpOptError::ChannelClosed)
+    }
+
+    /// Whether the server has finished, [`wait`](Self::wait) then returns at once.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits for the server to finish (a FIN, [`stop`](Self::stop) or the
+    /// idle timeout) and returns what
+    /// [`AsyncUdpServer::run`](crate::AsyncUdpServer::run) returned; a panic
+    /// of the server task is resumed here.
+    ///
+    /// The server keeps running while waited on, call [`stop`](Self::stop)
+    /// first to end it early.
+    pub async fn wait(self) -> Result<Vec<IntervalResult>, RunError> {
+        join(self.task).await
+    }
+}
+
+/// Output of `task`, resuming its panic
+async fn join<T>(task: task::JoinHandle<T>) -> T {
+    match task.await {
+        Ok(output) => output,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => panic::resume_unwind(panic),
+            // only aborted tasks are cancelled, and the handle never aborts
+            Err(e) => panic!("{e}"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, time::Duration};
+
+    use crate::{
+        ServerStatus,
+        builder::{ClientBuilder, ServerBuilder},
+    };
+
+    fn socket_pair() -> (UdpSocket, UdpSocket) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        (server, client)
+    }
+
+    #[test]
+    fn test_spawned_client_and_server() {
+        let (server_sock, client_sock) = socket_pair();
+        let server = ServerBuilder::new(Duration::from_millis(100)).spawn(server_sock);
+        let client = ClientBuilder::new(400_000.0, 500, None).spawn(client_sock);
+
+        server.start().unwrap();
+        client.start().unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        client.set_bitrate(800_000.0).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        client.stop().unwrap();
+
+        let report = client.wait().unwrap();
+        // 100 packets/s then 200 packets/s
+        assert!(
+            report.packets_sent > 40 && report.packets_sent < 80,
+            "{report:?}"
+        );
+        let intervals = server.wait().unwrap();
+        let received: u64 = intervals.iter().map(|i| i.received).sum();
+        assert!(received > 0);
+    }
+
+    #[test]
+    fn test_status_while_running() {
+        let (server_sock, client_sock) = socket_pair();
+        let server = ServerBuilder::new(Duration::from_millis(100)).spawn(server_sock);
+        let client = ClientBuilder::new(400_000.0, 500, None).spawn(client_sock);
+
+        // answered before the start too
+        assert_eq!(server.status().unwrap(), ServerStatus::default());
+        assert_eq!(client.status().unwrap().packets_sent, 0);
+
+        server.start().unwrap();
+        client.start().unwrap();
+        std::thread::sleep(Duration::from_millis(350));
+        let progress = client.status().unwrap();
+        let status = server.status().unwrap();
+        // 100 packets/s
+        assert!(progress.packets_sent >= 30, "{progress:?}");
+        assert!(status.received > 20 && status.intervals >= 2, "{status:?}");
+        assert!(status.peer.is_some());
+        client.stop().unwrap();
+
+        let report = client.wait().unwrap();
+        assert!(report.packets_sent >= progress.packets_sent);
+        let intervals = server.wait().unwrap();
+        assert!(intervals.iter().map(|i| i.received).sum::<u64>() >= status.received);
+    }
+
+    #[test]
+    fn test_finished_client_refuses_commands() {
+        let (_server_sock, client_sock) = socket_pair();
+        let client = ClientBuilder::new(1_000_000.0, 500, Duration::ZERO).spawn(client_sock);
+        client.start().unwrap();
+        while !client.is_finished() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(matches!(
+            client.stop(),
+            Err(crate::UdpOptError::ChannelClosed)
+        ));
+        assert!(matches!(
+            client.status(),
+            Err(crate::UdpOptError::ChannelClosed)
+        ));
+        assert!(client.wait().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_spawned_async_client_and_server() {
+        let server_sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client_sock
+            .connect(server_sock.local_addr().unwrap())
+            .await
+            .unwrap();
+        let server = ServerBuilder::new(Duration::from_millis(100)).spawn_async(server_sock);
+        let client = ClientBuilder::new(400_000.0, 500, None).spawn_async(client_sock);
+
+        server.start().await.unwrap();
+        client.start().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client.set_bitrate(800_000.0).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(client.status().await.unwrap().packets_sent > 0);
+        assert!(server.status().await.unwrap().received > 0);
+        client.stop().await.unwrap();
+
+        assert!(client.wait().await.unwrap().packets_sent > 0);
+        let intervals = server.wait().await.unwrap();
+        assert!(intervals.iter().map(|i| i.received).sum::<u64>() > 0);
+    }
+}
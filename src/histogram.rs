@@ -0,0 +1,247 @@
+//! HDR-style latency histogram.
+//!
+//! This module provides [`Histogram`] — a fixed-memory, log-linear histogram
+//! that records values (microseconds) with roughly 1.5% relative precision
+//! over the whole `u64` range. The server uses it to record per-packet transit
+//! times so [`crate::TestResult`] can report tail percentiles (p50/p90/p99/p99.9).
+
+/// Number of linear sub-buckets per power of two is `SUB_BUCKET_HALF`,
+/// values below `SUB_BUCKET_COUNT` are recorded exactly.
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKET_COUNT: u64 = 1 << SUB_BUCKET_BITS; // 128
+const SUB_BUCKET_HALF: u64 = SUB_BUCKET_COUNT / 2; // 64
+
+/// Total bucket count needed to cover the whole `u64` range.
+const BUCKET_COUNT: usize =
+    SUB_BUCKET_COUNT as usize + (64 - SUB_BUCKET_BITS as usize) * SUB_BUCKET_HALF as usize;
+
+/// One non-empty bucket of a [`Histogram`], exported for plotting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistogramBucket {
+    /// Lowest value (inclusive) that falls into this bucket, in microseconds.
+    pub low_us: u64,
+    /// Highest value (inclusive) that falls into this bucket, in microseconds.
+    pub high_us: u64,
+    /// Number of recorded values in this bucket.
+    pub count: u64,
+}
+
+/// Log-linear histogram of microsecond values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    min: u64,
+    max: u64,
+    sum: u128,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    /// Creates an empty histogram.
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; BUCKET_COUNT],
+            total: 0,
+            min: u64::MAX,
+            max: 0,
+            sum: 0,
+        }
+    }
+
+    /// Records a single value (microseconds).
+    pub fn record(&mut self, value_us: u64) {
+        self.counts[index_of(value_us)] += 1;
+        self.total += 1;
+        self.min = self.min.min(value_us);
+        self.max = self.max.max(value_us);
+        self.sum += value_us as u128;
+    }
+
+    /// Adds all values recorded in `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (c, o) in self.counts.iter_mut().zip(&other.counts) {
+            *c += o;
+        }
+        self.total += other.total;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+    }
+
+    /// Clears all recorded values.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns `true` if nothing has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Smallest recorded value, or 0 if empty.
+    pub fn min(&self) -> u64 {
+        if self.is_empty() { 0 } else { self.min }
+    }
+
+    /// Largest recorded value, or 0 if empty.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Arithmetic mean of the recorded values, or 0 if empty.
+    pub fn mean(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        self.sum as f64 / self.total as f64
+    }
+
+    /// Returns the value at the given percentile (`0.0..=100.0`).
+    ///
+    /// The result is the upper bound of the bucket holding the percentile,
+    /// clamped to the recorded maximum. Returns 0 if empty.
+    pub fn value_at_percentile(&self, percentile: f64) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let p = percentile.clamp(0.0, 100.0);
+        let rank = ((p / 100.0) * self.total as f64).ceil().max(1.0) as u64;
+
+        let mut seen = 0;
+        for (i, &c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank {
+                return highest_equivalent(i).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Exports all non-empty buckets in ascending order, e.g. for plotting.
+    pub fn buckets(&self) -> Vec<HistogramBucket> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c > 0)
+            .map(|(i, &count)| HistogramBucket {
+                low_us: lowest_equivalent(i),
+                high_us: highest_equivalent(i),
+                count,
+            })
+            .collect()
+    }
+}
+
+//helper functions
+
+#[inline]
+fn index_of(value: u64) -> usize {
+    if value < SUB_BUCKET_COUNT {
+        return value as usize;
+    }
+    // position of the most significant bit, always >= SUB_BUCKET_BITS here
+    let msb = 63 - value.leading_zeros();
+    let shift = msb - (SUB_BUCKET_BITS - 1);
+    let sub = (value >> shift) - SUB_BUCKET_HALF;
+    SUB_BUCKET_COUNT as usize + (shift as usize - 1) * SUB_BUCKET_HALF as usize + sub as usize
+}
+
+#[inline]
+fn lowest_equivalent(index: usize) -> u64 {
+    if (index as u64) < SUB_BUCKET_COUNT {
+        return index as u64;
+    }
+    let rel = index - SUB_BUCKET_COUNT as usize;
+    let shift = (rel / SUB_BUCKET_HALF as usize) as u32 + 1;
+    let sub = (rel % SUB_BUCKET_HALF as usize) as u64 + SUB_BUCKET_HALF;
+    sub << shift
+}
+
+#[inline]
+fn highest_equivalent(index: usize) -> u64 {
+    if (index as u64) < SUB_BUCKET_COUNT {
+        return index as u64;
+    }
+    let rel = index - SUB_BUCKET_COUNT as usize;
+    let shift = (rel / SUB_BUCKET_HALF as usize) as u32 + 1;
+    lowest_equivalent(index).saturating_add((1u64 << shift) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_round_trip() {
+        for v in [0u64, 1, 127, 128, 129, 255, 256, 1000, 123_456, u64::MAX] {
+            let i = index_of(v);
+            assert!(lowest_equivalent(i) <= v, "low bound for {}", v);
+            assert!(highest_equivalent(i) >= v, "high bound for {}", v);
+        }
+        assert_eq!(index_of(u64::MAX), BUCKET_COUNT - 1);
+    }
+
+    #[test]
+    fn test_small_values_are_exact() {
+        let mut h = Histogram::new();
+        for v in 1..=100 {
+            h.record(v);
+        }
+        assert_eq!(h.count(), 100);
+        assert_eq!(h.min(), 1);
+        assert_eq!(h.max(), 100);
+        assert_eq!(h.value_at_percentile(50.0), 50);
+        assert_eq!(h.value_at_percentile(90.0), 90);
+        assert_eq!(h.value_at_percentile(99.0), 99);
+        assert_eq!(h.value_at_percentile(100.0), 100);
+        assert_eq!(h.mean(), 50.5);
+    }
+
+    #[test]
+    fn test_large_values_relative_precision() {
+        let mut h = Histogram::new();
+        for v in 1..=10_000u64 {
+            h.record(v * 100);
+        }
+        let p99 = h.value_at_percentile(99.0) as f64;
+        let expected = 990_000.0;
+        assert!((p99 - expected).abs() / expected < 0.02, "p99 was {}", p99);
+    }
+
+    #[test]
+    fn test_empty_histogram() {
+        let h = Histogram::new();
+        assert!(h.is_empty());
+        assert_eq!(h.min(), 0);
+        assert_eq!(h.value_at_percentile(99.0), 0);
+        assert!(h.buckets().is_empty());
+    }
+
+    #[test]
+    fn test_buckets_and_merge() {
+        let mut a = Histogram::new();
+        let mut b = Histogram::new();
+        a.record(10);
+        a.record(10);
+        b.record(5000);
+        a.merge(&b);
+
+        let buckets = a.buckets();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].count, 2);
+        assert_eq!(buckets[0].low_us, 10);
+        assert!(buckets[1].low_us <= 5000 && buckets[1].high_us >= 5000);
+        assert_eq!(buckets.iter().map(|b| b.count).sum::<u64>(), a.count());
+    }
+}
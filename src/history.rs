@@ -0,0 +1,265 @@
+//! # Result history (feature `serde`)
+//!
+//! A [`History`] appends every [`TestReport`] to a local JSON Lines file,
+//! one line per run tagged with the name of the test, and compares a new
+//! result with the rolling baseline of the last runs of the same test, for
+//! automated regression detection:
+//!
+//! ```no_run
+//! use udpopt::{TestReport, TestResult, history::History};
+//!
+//! # fn run_test() -> TestResult { TestResult::from_intervals(&[]) }
+//! let history = History::open("udpopt-history.jsonl").window(20);
+//! let result = run_test();
+//! if let Some(check) = history.check("wan-uplink", &result, 5.0)? {
+//!     if check.is_regression() {
+//!         eprintln!("throughput {:+.1} % off the baseline", check.deviation_percent);
+//!     }
+//! }
+//! history.append("wan-uplink", &TestReport::new(result))?;
+//! # Ok::<(), udpopt::UdpOptError>(())
+//! ```
+
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::UdpOptError,
+    report::TestReport,
+    result::{TestResult, mean},
+};
+
+/// Runs the baseline is computed from by default
+pub const DEFAULT_WINDOW: usize = 10;
+
+/// A JSON Lines store of test reports, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+    window: usize,
+}
+
+/// A line of the store.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Name of the test the run belongs to.
+    pub name: String,
+    /// The run.
+    pub report: TestReport,
+}
+
+/// Rolling baseline of a test, the mean of its last runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Baseline {
+    /// Number of runs it is computed from.
+    pub runs: usize,
+    /// Mean of the runs' mean bitrate (bits/sec).
+    pub mean_bitrate: f64,
+    /// Mean of the runs' loss percentage.
+    pub loss_percent: f64,
+}
+
+/// A result compared with the [`Baseline`], returned by [`History::check`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BaselineCheck {
+    /// The baseline compared with.
+    pub baseline: Baseline,
+    /// Mean bitrate of the result (bits/sec).
+    pub bitrate: f64,
+    /// Throughput of the result relative to the baseline (%), negative when slower.
+    pub deviation_percent: f64,
+    /// The allowed deviation (%).
+    pub tolerance_percent: f64,
+}
+
+impl BaselineCheck {
+    /// Whether the throughput is within the tolerance of the baseline, either way.
+    pub fn is_within(&self) -> bool {
+        self.deviation_percent.abs() <= self.tolerance_percent
+    }
+
+    /// Whether the throughput fell below the baseline by more than the tolerance.
+    pub fn is_regression(&self) -> bool {
+        self.deviation_percent < -self.tolerance_percent
+    }
+}
+
+impl History {
+    /// The store at `path`, created by the first [`append`](Self::append).
+    pub fn open(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            window: DEFAULT_WINDOW,
+        }
+    }
+
+    /// Sets how many of the last runs make the baseline, [`DEFAULT_WINDOW`] by default.
+    pub fn window(mut self, runs: usize) -> Self {
+        self.window = runs.max(1);
+        self
+    }
+
+    /// Path of the store.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `report` as the latest run of the test `name`.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFailed`] if the store cannot be written,
+    /// [`UdpOptError::ReportFormat`] if a value has no JSON form.
+    pub fn append(&self, name: &str, report: &TestReport) -> Result<(), UdpOptError> {
+        let entry = Entry {
+            name: name.to_string(),
+            report: report.clone(),
+        };
+        let mut line =
+            serde_json::to_string(&entry).map_err(|e| UdpOptError::ReportFormat(e.to_string()))?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(UdpOptError::ReportFailed)
+    }
+
+    /// The runs of the test `name`, oldest first; none before the first append.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFailed`] if the store cannot be read,
+    /// [`UdpOptError::ReportFormat`] if a line is not an entry.
+    pub fn entries(&self, name: &str) -> Result<Vec<TestReport>, UdpOptError> {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(UdpOptError::ReportFailed(e)),
+        };
+        let mut reports = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(line)
+                .map_err(|e| UdpOptError::ReportFormat(format!("line {}: {e}", i + 1)))?;
+            if entry.name == name {
+                reports.push(entry.report);
+            }
+        }
+        Ok(reports)
+    }
+
+    /// Baseline of the test `name` over the last runs, `None` before its first run.
+    ///
+    /// # Errors
+    /// See [`entries`](Self::entries).
+    pub fn baseline(&self, name: &str) -> Result<Option<Baseline>, UdpOptError> {
+        let reports = self.entries(name)?;
+        let last = &reports[reports.len().saturating_sub(self.window)..];
+        if last.is_empty() {
+            return Ok(None);
+        }
+        let bitrates: Vec<f64> = last.iter().map(|r| r.result.mean_bitrate).collect();
+        let losses: Vec<f64> = last.iter().map(|r| r.result.loss_percent).collect();
+        Ok(Some(Baseline {
+            runs: last.len(),
+            mean_bitrate: mean(&bitrates),
+            loss_percent: mean(&losses),
+        }))
+    }
+
+    /// Compares the throughput of `result` with the baseline of the test
+    /// `name`, allowing `tolerance_percent` either way; `None` before the
+    /// first run of the test.
+    ///
+    /// # Errors
+    /// See [`entries`](Self::entries).
+    pub fn check(
+        &self,
+        name: &str,
+        result: &TestResult,
+        tolerance_percent: f64,
+    ) -> Result<Option<BaselineCheck>, UdpOptError> {
+        Ok(self.baseline(name)?.map(|baseline| {
+            let deviation_percent = if baseline.mean_bitrate == 0.0 {
+                0.0
+            } else {
+                (result.mean_bitrate - baseline.mean_bitrate) / baseline.mean_bitrate * 100.0
+            };
+            BaselineCheck {
+                baseline,
+                bitrate: result.mean_bitrate,
+                deviation_percent,
+                tolerance_percent,
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntervalResult;
+    use std::time::Duration;
+
+    fn result(bytes: usize) -> TestResult {
+        TestResult::from_intervals(&[IntervalResult {
+            time: Duration::from_secs(1),
+            received: 1000,
+            bytes,
+            ..Default::default()
+        }
+        .with_bitrate()])
+    }
+
+    #[test]
+    fn test_baseline_and_check() {
+        let path =
+            std::env::temp_dir().join(format!("udpopt-history-{}.jsonl", std::process::id()));
+        let history = History::open(&path).window(3);
+        let checks = (|| {
+            assert!(history.baseline("uplink")?.is_none());
+            // the oldest falls out of the window
+            for bytes in [100_000, 1_000_000, 1_250_000, 1_500_000] {
+                history.append("uplink", &TestReport::new(result(bytes)))?;
+            }
+            history.append("downlink", &TestReport::new(result(5_000_000)))?;
+            Ok::<_, UdpOptError>((
+                history.entries("uplink")?.len(),
+                history.baseline("uplink")?,
+                history.check("uplink", &result(1_200_000), 5.0)?,
+                history.check("uplink", &result(1_000_000), 5.0)?,
+                history.check("downlink", &result(5_000_000), 5.0)?,
+            ))
+        })();
+        std::fs::remove_file(&path).unwrap();
+        let (entries, baseline, steady, slow, other) = checks.unwrap();
+
+        assert_eq!(entries, 4);
+        let baseline = baseline.unwrap();
+        assert_eq!(baseline.runs, 3);
+        assert_eq!(baseline.mean_bitrate, 10e6);
+        let steady = steady.unwrap();
+        assert!((steady.deviation_percent + 4.0).abs() < 1e-9);
+        assert!(steady.is_within() && !steady.is_regression());
+        let slow = slow.unwrap();
+        assert!(!slow.is_within() && slow.is_regression());
+        assert_eq!(other.unwrap().deviation_percent, 0.0);
+    }
+
+    #[test]
+    fn test_malformed_line() {
+        let path =
+            std::env::temp_dir().join(format!("udpopt-history-bad-{}.jsonl", std::process::id()));
+        std::fs::write(&path, "{\"name\": \"uplink\"}\n").unwrap();
+        let entries = History::open(&path).entries("uplink");
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(entries, Err(UdpOptError::ReportFormat(e)) if e.starts_with("line 1")));
+    }
+}
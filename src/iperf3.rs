@@ -0,0 +1,455 @@
+//! iperf3 compatibility layer (feature `iperf3-compat`).
+//!
+//! Speaks enough of the iperf3 protocol to run a single-stream UDP test from
+//! [`UdpClient`] against a stock `iperf3 -s` server:
+//!
+//! - TCP control connection with the 37 byte cookie and the state machine
+//!   (`PARAM_EXCHANGE`, `CREATE_STREAMS`, `TEST_START`, ...).
+//! - JSON parameter and result exchange (4 byte big-endian length + JSON).
+//! - UDP stream setup (`UDP_CONNECT_MSG`/`UDP_CONNECT_REPLY`, host-order
+//!   ints on the wire) and the iperf3 UDP packet header
+//!   `sec | usec | pcount` with 64-bit counters.
+//!
+//! Only the client side in normal (non-reverse) mode is supported.
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream, UdpSocket},
+    time::Duration,
+};
+
+use serde_json::{Value, json};
+
+use crate::{
+    UdpClient,
+    builder::ClientConfig,
+    errors::UdpOptError,
+    result::ClientReport,
+    utils::{net_utils::IntervalResult, random_utils::RandomToSend, udp_data::WireFormat},
+};
+
+/// Length of the session cookie, including the trailing NUL
+pub(crate) const COOKIE_SIZE: usize = 37;
+/// Size of the iperf3 UDP header with 64-bit counters (sec + usec + pcount)
+pub(crate) const UDP_HEADER_SIZE: usize = 4 + 4 + 8;
+
+/// Datagram the client sends to open the UDP stream
+pub(crate) const UDP_CONNECT_MSG: u32 = 0x3637_3839;
+/// Datagram the server answers with once the UDP stream is accepted
+pub(crate) const UDP_CONNECT_REPLY: u32 = 0x3938_3736;
+/// `UDP_CONNECT_REPLY` of the iperf3 versions before 3.1
+pub(crate) const UDP_CONNECT_REPLY_LEGACY: u32 = 987_654_321;
+/// Longest JSON message accepted on the control connection
+pub(crate) const MAX_JSON_LEN: usize = 1 << 20;
+
+// iperf3 control channel states (signed char on the wire)
+pub(crate) const TEST_START: i8 = 1;
+pub(crate) const TEST_RUNNING: i8 = 2;
+pub(crate) const TEST_END: i8 = 4;
+pub(crate) const PARAM_EXCHANGE: i8 = 9;
+pub(crate) const CREATE_STREAMS: i8 = 10;
+pub(crate) const SERVER_TERMINATE: i8 = 11;
+pub(crate) const CLIENT_TERMINATE: i8 = 12;
+pub(crate) const EXCHANGE_RESULTS: i8 = 13;
+pub(crate) const DISPLAY_RESULTS: i8 = 14;
+pub(crate) const IPERF_DONE: i8 = 16;
+pub(crate) const ACCESS_DENIED: i8 = -1;
+pub(crate) const SERVER_ERROR: i8 = -2;
+
+/// Outcome of a test against an iperf3 server.
+#[derive(Debug, Clone)]
+pub struct Iperf3Report {
+    /// What the client sent.
+    pub local: ClientReport,
+    /// What the iperf3 server measured (received, lost, bytes, jitter, duration).
+    pub remote: IntervalResult,
+}
+
+impl UdpClient {
+    /// Runs a UDP test against a stock iperf3 server listening on `server`.
+    ///
+    /// - Waits for a `Start` command from the control channel.
+    /// - Opens the iperf3 TCP control connection and negotiates a single UDP stream
+    ///   using the configured bitrate, payload size and timeout.
+    /// - Sends paced packets in the iperf3 UDP format, then exchanges results.
+    ///
+    /// Returns:
+    /// - [`UdpOptError::ControlFailed`] if the TCP control connection fails.
+    /// - [`UdpOptError::ProtocolViolation`] if the server refuses the test or misbehaves.
+    /// - Any error of [`UdpClient::run`] for the UDP stream itself.
+    pub fn run_iperf3(&mut self, server: SocketAddr) -> Result<Iperf3Report, UdpOptError> {
+        self.wait_start()?;
+
+        let mut ctrl = TcpStream::connect(server).map_err(UdpOptError::ControlFailed)?;
+        ctrl.set_nodelay(true).map_err(UdpOptError::ControlFailed)?;
+        ctrl.write_all(&make_cookie()?)
+            .map_err(UdpOptError::ControlFailed)?;
+
+        let mut udp: Option<UdpSocket> = None;
+        let mut local: Option<ClientReport> = None;
+        let mut remote: Option<IntervalResult> = None;
+
+        loop {
+            match read_state(&mut ctrl)? {
+                PARAM_EXCHANGE => {
+                    let params = client_params(&self.config);
+                    write_json(&mut ctrl, &params)?;
+                }
+                CREATE_STREAMS => udp = Some(connect_udp_stream(server)?),
+                TEST_START => {}
+                TEST_RUNNING => {
+                    let sock = udp.as_ref().ok_or_else(|| {
+                        UdpOptError::ProtocolViolation("TEST_RUNNING before CREATE_STREAMS".into())
+                    })?;
+                    local = Some(self.transmit(sock, WireFormat::Iperf3)?);
+                    write_state(&mut ctrl, TEST_END)?;
+                }
+                EXCHANGE_RESULTS => {
+                    let report = local.as_ref().ok_or_else(|| {
+                        UdpOptError::ProtocolViolation("EXCHANGE_RESULTS before TEST_END".into())
+                    })?;
+                    write_json(&mut ctrl, &client_results(report))?;
+                    remote = Some(parse_server_results(&read_json(&mut ctrl)?)?);
+                }
+                DISPLAY_RESULTS => {
+                    write_state(&mut ctrl, IPERF_DONE)?;
+                    break;
+                }
+                ACCESS_DENIED => {
+                    return Err(UdpOptError::ProtocolViolation(
+                        "server is busy running another test".into(),
+                    ));
+                }
+                SERVER_ERROR | SERVER_TERMINATE | CLIENT_TERMINATE => {
+                    return Err(UdpOptError::ProtocolViolation(
+                        "server aborted the test".into(),
+                    ));
+                }
+                other => {
+                    return Err(UdpOptError::ProtocolViolation(format!(
+                        "unexpected iperf3 state {}",
+                        other
+                    )));
+                }
+            }
+        }
+
+        match (local, remote) {
+            (Some(local), Some(remote)) => Ok(Iperf3Report { local, remote }),
+            _ => Err(UdpOptError::ProtocolViolation(
+                "test ended before results were exchanged".into(),
+            )),
+        }
+    }
+}
+
+// helper functions
+
+/// Writes the iperf3 UDP header (big-endian, 64-bit packet counter)
+///
+/// # Panics
+/// Panics if the buffer length is smaller than `UDP_HEADER_SIZE`
+pub(crate) fn write_udp_header(buffer: &mut [u8], pcount: u64, sec: u64, usec: u32) {
+    assert!(buffer.len() >= UDP_HEADER_SIZE);
+    buffer[0..4].copy_from_slice(&(sec as u32).to_be_bytes());
+    buffer[4..8].copy_from_slice(&usec.to_be_bytes());
+    buffer[8..16].copy_from_slice(&pcount.to_be_bytes());
+}
+
+/// Random printable cookie identifying the test session
+fn make_cookie() -> Result<[u8; COOKIE_SIZE], UdpOptError> {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut cookie = [0u8; COOKIE_SIZE];
+    RandomToSend::new()
+        .and_then(|mut r| r.fill(&mut cookie[..COOKIE_SIZE - 1]))
+        .map_err(UdpOptError::FailToGetRandom)?;
+    for b in &mut cookie[..COOKIE_SIZE - 1] {
+        *b = ALPHABET[(*b as usize) % ALPHABET.len()];
+    }
+    Ok(cookie)
+}
+
+fn connect_udp_stream(server: SocketAddr) -> Result<UdpSocket, UdpOptError> {
+    let local: SocketAddr = if server.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let sock = UdpSocket::bind(local).map_err(|source| UdpOptError::BindFailed {
+        addr: local,
+        source,
+    })?;
+    sock.connect(server)
+        .map_err(|source| UdpOptError::ConnectFailed {
+            peer: server,
+            source,
+        })?;
+    // iperf3 writes the int in host order, little-endian as from an x86 client
+    let hello = UDP_CONNECT_MSG.to_le_bytes();
+    sock.send(&hello)
+        .map_err(UdpOptError::send_failed(Some(server), hello.len()))?;
+
+    sock.set_read_timeout(Some(Duration::from_secs(10)))
+        .map_err(|_| UdpOptError::SocketTimeout)?;
+    let mut reply = [0u8; 4];
+    let len = sock.recv(&mut reply).map_err(UdpOptError::RecvFailed)?;
+    if len != 4 || !is_connect_reply(reply) {
+        return Err(UdpOptError::ProtocolViolation(
+            "bad UDP connect reply".into(),
+        ));
+    }
+    Ok(sock)
+}
+
+/// Whether `reply` is a `UDP_CONNECT_REPLY`, in the host order of a server
+/// of either endianness
+fn is_connect_reply(reply: [u8; 4]) -> bool {
+    [u32::from_le_bytes(reply), u32::from_be_bytes(reply)]
+        .iter()
+        .any(|r| matches!(*r, UDP_CONNECT_REPLY | UDP_CONNECT_REPLY_LEGACY))
+}
+
+fn read_state(ctrl: &mut TcpStream) -> Result<i8, UdpOptError> {
+    let mut b = [0u8; 1];
+    ctrl.read_exact(&mut b)
+        .map_err(UdpOptError::ControlFailed)?;
+    Ok(b[0] as i8)
+}
+
+fn write_state(ctrl: &mut TcpStream, state: i8) -> Result<(), UdpOptError> {
+    ctrl.write_all(&[state as u8])
+        .map_err(UdpOptError::ControlFailed)
+}
+
+pub(crate) fn write_json<W: Write>(out: &mut W, value: &Value) -> Result<(), UdpOptError> {
+    let body = value.to_string();
+    out.write_all(&(body.len() as u32).to_be_bytes())
+        .and_then(|_| out.write_all(body.as_bytes()))
+        .map_err(UdpOptError::ControlFailed)
+}
+
+pub(crate) fn read_json<R: Read>(input: &mut R) -> Result<Value, UdpOptError> {
+    let mut len = [0u8; 4];
+    input
+        .read_exact(&mut len)
+        .map_err(UdpOptError::ControlFailed)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_JSON_LEN {
+        return Err(UdpOptError::ProtocolViolation(format!(
+            "JSON message of {len} bytes"
+        )));
+    }
+    let mut body = vec![0u8; len];
+    input
+        .read_exact(&mut body)
+        .map_err(UdpOptError::ControlFailed)?;
+    serde_json::from_slice(&body).map_err(|e| UdpOptError::ProtocolViolation(e.to_string()))
+}
+
+/// Test parameters sent in `PARAM_EXCHANGE`
+pub(crate) fn client_params(config: &ClientConfig) -> Value {
+    json!({
+        "udp": true,
+        "omit": 0,
+        // 0 is iperf3's unlimited duration / byte / block count
+        "time": config.timeout.map_or(0, |t| t.as_secs().max(1)),
+        "num": config.stop_after_bytes.unwrap_or(0),
+        "blockcount": config.stop_after_packets.unwrap_or(0),
+        "parallel": 1,
+        "len": config.payload_size,
+        "bandwidth": if config.unlimited() { 0 } else { config.bitrate_bps() as u64 },
+        "pacing_timer": 1000,
+        "udp_counters_64bit": 1,
+        "client_version": concat!("udpopt-", env!("CARGO_PKG_VERSION")),
+    })
+}
+
+/// Sender-side results sent in `EXCHANGE_RESULTS`
+pub(crate) fn client_results(report: &ClientReport) -> Value {
+    json!({
+        "cpu_util_total": 0,
+        "cpu_util_user": 0,
+        "cpu_util_system": 0,
+        "sender_has_retransmits": -1,
+        "streams": [{
+            "id": 1,
+            "bytes": report.bytes_sent,
+            "retransmits": -1,
+            "jitter": 0,
+            "errors": 0,
+            "omitted_errors": 0,
+            "packets": report.packets_sent,
+            "omitted_packets": 0,
+            "start_time": 0,
+            "end_time": report.duration.as_secs_f64(),
+        }],
+    })
+}
+
+/// Extracts the receiver-side measurements of the first stream
+pub(crate) fn parse_server_results(value: &Value) -> Result<IntervalResult, UdpOptError> {
+    let stream = value
+        .get("streams")
+        .and_then(|s| s.get(0))
+        .ok_or_else(|| UdpOptError::ProtocolViolation("results without streams".into()))?;
+    let num = |key: &str| stream.get(key).and_then(Value::as_f64).unwrap_or(0.0);
+
+    let time = Duration::try_from_secs_f64((num("end_time") - num("start_time")).max(0.0))
+        .map_err(|e| UdpOptError::ProtocolViolation(format!("test duration: {e}")))?;
+    // `packets` is the highest packet count seen, the lost ones included
+    let lost = num("errors") as u64;
+    Ok(IntervalResult {
+        received: (num("packets") as u64).saturating_sub(lost),
+        lost,
+        bytes: num("bytes") as usize,
+        // iperf3 reports jitter in seconds
+        jitter_ms: num("jitter") * 1000.0,
+        time,
+        ..Default::default()
+    }
+    .with_bitrate())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientCommand;
+    use std::net::TcpListener;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    /// Minimal iperf3 server side of the protocol, returning the packets it received
+    fn fake_iperf3_server(listener: TcpListener, udp: UdpSocket) -> Vec<u64> {
+        let (mut ctrl, _) = listener.accept().unwrap();
+        let mut cookie = [0u8; COOKIE_SIZE];
+        ctrl.read_exact(&mut cookie).unwrap();
+        assert_eq!(cookie[COOKIE_SIZE - 1], 0);
+
+        ctrl.write_all(&[PARAM_EXCHANGE as u8]).unwrap();
+        let params = read_json(&mut ctrl).unwrap();
+        assert_eq!(params["udp"], true);
+        assert_eq!(params["len"], 256);
+
+        ctrl.write_all(&[CREATE_STREAMS as u8]).unwrap();
+        let mut buf = [0u8; 2048];
+        let (len, peer) = udp.recv_from(&mut buf).unwrap();
+        // read and answered in host order, as iperf3 does
+        assert_eq!(len, 4);
+        assert_eq!(
+            u32::from_le_bytes(buf[..4].try_into().unwrap()),
+            UDP_CONNECT_MSG
+        );
+        udp.send_to(&UDP_CONNECT_REPLY.to_ne_bytes(), peer).unwrap();
+
+        ctrl.write_all(&[TEST_START as u8, TEST_RUNNING as u8])
+            .unwrap();
+
+        // collect datagrams until the client reports TEST_END
+        udp.set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        ctrl.set_nonblocking(true).unwrap();
+        let mut counts = Vec::new();
+        loop {
+            if let Ok(len) = udp.recv(&mut buf) {
+                assert_eq!(len, 256);
+                counts.push(u64::from_be_bytes(buf[8..16].try_into().unwrap()));
+            }
+            let mut state = [0u8; 1];
+            if ctrl.read(&mut state).is_ok() {
+                assert_eq!(state[0] as i8, TEST_END);
+                break;
+            }
+        }
+        ctrl.set_nonblocking(false).unwrap();
+
+        ctrl.write_all(&[EXCHANGE_RESULTS as u8]).unwrap();
+        let client = read_json(&mut ctrl).unwrap();
+        assert_eq!(client["streams"][0]["packets"], counts.len() as u64);
+
+        let results = json!({
+            "streams": [{
+                "id": 1, "bytes": counts.len() * 256, "jitter": 0.0005, "errors": 0,
+                "packets": counts.len(), "start_time": 0, "end_time": 0.2,
+            }],
+        });
+        write_json(&mut ctrl, &results).unwrap();
+        ctrl.write_all(&[DISPLAY_RESULTS as u8]).unwrap();
+
+        let mut done = [0u8; 1];
+        ctrl.read_exact(&mut done).unwrap();
+        assert_eq!(done[0] as i8, IPERF_DONE);
+        counts
+    }
+
+    #[test]
+    fn test_write_udp_header() {
+        let mut buf = [0u8; UDP_HEADER_SIZE];
+        write_udp_header(&mut buf, 7, 1_700_000_000, 250_000);
+        assert_eq!(
+            u32::from_be_bytes(buf[0..4].try_into().unwrap()),
+            1_700_000_000
+        );
+        assert_eq!(u32::from_be_bytes(buf[4..8].try_into().unwrap()), 250_000);
+        assert_eq!(u64::from_be_bytes(buf[8..16].try_into().unwrap()), 7);
+    }
+
+    #[test]
+    fn test_parse_server_results() {
+        let value = json!({
+            "streams": [{"bytes": 12000, "jitter": 0.002, "errors": 3,
+                         "packets": 10, "start_time": 0, "end_time": 1.5}],
+        });
+        let r = parse_server_results(&value).unwrap();
+        // the lost packets are part of the count
+        assert_eq!(r.received, 7);
+        assert_eq!(r.lost, 3);
+        assert_eq!(r.bytes, 12000);
+        assert_eq!(r.jitter_ms, 2.0);
+        assert_eq!(r.time, Duration::from_millis(1500));
+
+        assert!(parse_server_results(&json!({})).is_err());
+        let endless = json!({"streams": [{"packets": 10, "start_time": 0, "end_time": 1e300}]});
+        assert!(matches!(
+            parse_server_results(&endless),
+            Err(UdpOptError::ProtocolViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_connect_reply_in_either_order() {
+        assert!(is_connect_reply(UDP_CONNECT_REPLY.to_le_bytes()));
+        assert!(is_connect_reply(UDP_CONNECT_REPLY.to_be_bytes()));
+        assert!(is_connect_reply(UDP_CONNECT_REPLY_LEGACY.to_le_bytes()));
+        assert!(is_connect_reply(UDP_CONNECT_REPLY_LEGACY.to_be_bytes()));
+        assert!(!is_connect_reply(*b"1234"));
+    }
+
+    #[test]
+    fn test_read_json_caps_the_length() {
+        let mut huge = &u32::MAX.to_be_bytes()[..];
+        assert!(matches!(
+            read_json(&mut huge),
+            Err(UdpOptError::ProtocolViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_against_fake_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let udp = UdpSocket::bind(addr).unwrap();
+        let server = thread::spawn(move || fake_iperf3_server(listener, udp));
+
+        let (tx, rx) = channel();
+        let mut client = UdpClient::new(2_000_000.0, 256, Duration::from_millis(200), rx);
+        tx.send(ClientCommand::Start).unwrap();
+        let report = client.run_iperf3(addr).unwrap();
+
+        let counts = server.join().unwrap();
+        assert_eq!(report.local.packets_sent, counts.len() as u64);
+        // iperf3 packet counters start at 1
+        assert_eq!(counts[0], 1);
+        assert_eq!(report.remote.received, counts.len() as u64);
+        assert_eq!(report.remote.jitter_ms, 0.5);
+    }
+}
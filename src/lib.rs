@@ -14,7 +14,7 @@
 //!
 //!
 //! - Use `udpopt::UdpClient` to represent the test client
-//! ```  
+//! ```
 //! use std::net::UdpSocket;
 //! use std::sync::mpsc;
 //! use std::thread;
@@ -24,10 +24,11 @@
 //!
 //! fn main() {
 //!     
-//!    # let mut sock = UdpSocket::bind("127.0.0.1:4000").expect("failed to bind");
+//!    # let mut sock = UdpSocket::bind("127.0.0.1:0").expect("failed to bind");
+//!    # let server = UdpSocket::bind("127.0.0.1:0").expect("failed to bind");
 //!     
 //!     // Connect to server address
-//!    # sock.connect("127.0.0.1:5000").expect("failed to connect");
+//!    # sock.connect(server.local_addr().unwrap()).expect("failed to connect");
 //!
 //!     
 //!     let (tx, rx) = mpsc::channel();
@@ -55,12 +56,24 @@
 //! ```
 //! #
 //!
-//! - After the duration is done the  after 5 seconds the otput should be
+//! - The library does not print anything itself. Use [`ClientBuilder::on_progress`] and
+//!   [`ServerBuilder::on_interval`] to observe live statistics, e.g. with the helpers in [`ui`]:
 //!
-//! ```console
-//!   Client done. Sent 'Number of packets' packets (+FIN)
+//! ```
+//! use std::sync::mpsc;
+//! use std::time::Duration;
+//! use udpopt::{ClientBuilder, ServerBuilder, ui};
 //!
-//!  ```
+//! let (_client_tx, client_rx) = mpsc::channel();
+//! let client = ClientBuilder::new(1_000_000.0, 1200, Duration::from_secs(5))
+//!     .on_progress(Box::new(|p| ui::print_progress(p)))
+//!     .build(client_rx);
+//!
+//! let (_server_tx, server_rx) = mpsc::channel();
+//! let server = ServerBuilder::new(Duration::from_secs(1))
+//!     .on_interval(Box::new(|r| ui::print_result(r)))
+//!     .build(server_rx);
+//! ```
 //!
 //! - Use `udpopt::UdpServer` to represent the test server
 //!
@@ -74,7 +87,7 @@
 //!
 //! fn main()  {
 //!    # // Bind UDP socket to listen on port 5000
-//!    # let mut sock = UdpSocket::bind("127.0.0.1:5000").expect("failed to bind");
+//!    # let mut sock = UdpSocket::bind("127.0.0.1:0").expect("failed to bind");
 //!     
 //!
 //!     // Create a channel for controlling the server (start/stop)
@@ -104,9 +117,9 @@
 //! ```
 //!
 //!  - This  module defines the [`TestResult`] struct, which aggregates multiple
-//!   [`IntervalResult`] measurements that results from `UdpServer::run`
-//!   into final performance metrics — total packets, bitrate, jitter, and more.
-//!   Simulated interval results (normally collected by a UDP server)
+//!    [`IntervalResult`] measurements that results from `UdpServer::run`
+//!    into final performance metrics — total packets, bitrate, jitter, and more.
+//!    Simulated interval results (normally collected by a UDP server)
 //!
 //!
 //! ```rust
@@ -119,19 +132,47 @@
 //! #         received: 950,
 //! #         lost: 50,
 //! #         bytes: 1_200_000,
+//! #         bitrate_bps: 9_600_000.0,
 //! #         time: Duration::from_secs(1),
 //! #         jitter_ms: 0.8,
 //! #         out_of_order: 2,
+//! #         duplicates: 0,
+//! #         late: 0,
+//! #         recovered: 0,
+//! #         ce_marked: 0,
+//! #         auth_failures: 0,
+//! #         foreign: 0,
+//! #         malformed: 0,
 //! #         recommended_bitrate: 0,
+//! #         target_bitrate: 0,
+//! #         nic: None,
+//! #         socket_drops: 0,
+//! #         sent: 0,
+//! #         start_time: None,
+//! #         offset: Duration::ZERO,
 //! #     },
 //! #     IntervalResult {
 //! #         received: 970,
 //! #         lost: 30,
 //! #         bytes: 1_250_000,
+//! #         bitrate_bps: 10_000_000.0,
 //! #         time: Duration::from_secs(1),
 //! #         jitter_ms: 1.2,
 //! #         out_of_order: 1,
+//! #         duplicates: 0,
+//! #         late: 0,
+//! #         recovered: 0,
+//! #         ce_marked: 0,
+//! #         auth_failures: 0,
+//! #         foreign: 0,
+//! #         malformed: 0,
 //! #          recommended_bitrate: 0,
+//! #         target_bitrate: 0,
+//! #         nic: None,
+//! #         socket_drops: 0,
+//! #         sent: 0,
+//! #         start_time: None,
+//! #         offset: Duration::from_secs(1),
 //! #     },
 //! # ];
 //!
@@ -156,17 +197,105 @@
 //! Median jitter: 1.00 ms
 //! ```
 
+#[macro_use]
+mod log;
+pub mod access;
+pub use access::{AccessControl, IpNet};
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(feature = "auth")]
+pub use auth::AuthKey;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+pub mod buffer_pool;
+pub use buffer_pool::{BufferPool, PooledBuffer};
+mod builder;
+pub use builder::{ClientBuilder, ServerBuilder};
 mod client;
 pub use client::UdpClient;
+pub mod congestion;
+pub use congestion::CongestionController;
+#[cfg(feature = "serde")]
+pub mod config;
+#[cfg(feature = "serde")]
+pub use config::TestConfig;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+
+pub mod discovery;
+pub use discovery::{CapacitySearch, Discovery};
 
 mod errors;
-pub use errors::UdpOptError;
+pub use errors::{HeaderError, RunError, UdpOptError};
+pub mod fault;
+pub use fault::{FaultInjector, FaultStats};
+pub mod handle;
+pub use handle::{AsyncClientHandle, AsyncServerHandle, ClientHandle, ServerHandle};
+pub mod histogram;
+pub use histogram::Histogram;
+#[cfg(feature = "serde")]
+pub mod history;
+#[cfg(feature = "iperf3-compat")]
+pub mod iperf3;
+#[cfg(feature = "iperf3-compat")]
+pub use iperf3::Iperf3Report;
+pub mod nat;
+pub use nat::NatProbe;
+pub mod nic;
+pub use nic::{NicCounters, NicSampler};
+#[cfg(feature = "serde")]
+pub mod report;
+#[cfg(feature = "serde")]
+pub use report::TestReport;
 mod result;
-pub use result::TestResult;
+pub use result::{
+    BandwidthEstimate, ClientReport, DEFAULT_PERCENTILES, Fragmentation, GilbertElliott,
+    IntervalPercentile, LOSS_RUN_BUCKETS, LatencyPercentiles, LossStats, MetricDelta, Overhead,
+    REORDER_BUCKETS, RateCompliance, ReorderStats, SIZE_BUCKET_LIMITS, SIZE_BUCKETS, SendLimit,
+    Significance, SizeStats, SleepCalibration, TestComparison, TestResult, Throughput,
+    UnreachableEpisode,
+};
+pub mod payload;
+pub use payload::{PayloadSource, SizeMix};
+pub mod reverse;
+pub use reverse::{ReverseCredentials, ReverseLimits, ReversePolicy};
+pub mod runner;
+pub use runner::{RunComparison, RunSeries, TestRunner};
 mod server;
-pub use server::UdpServer;
+pub use server::{Session, UdpServer};
+pub mod socket;
+pub use socket::{DatagramSocket, Ecn, Interface, MockSocket, PortRange, RecvMeta};
+pub mod runtime;
+pub use runtime::AsyncDatagram;
+pub mod scenario;
+pub use scenario::Scenario;
+#[cfg(all(feature = "reuseport", unix))]
+pub mod sharded;
+#[cfg(all(feature = "reuseport", unix))]
+pub use sharded::ShardedServer;
+pub mod sink;
+pub use sink::{InfluxSink, ResultSink};
+pub mod step;
+pub use step::StepLoad;
+pub mod sweep;
+pub use sweep::SizeSweep;
+#[cfg(feature = "signal")]
+pub mod shutdown;
+pub mod thresholds;
+pub use thresholds::{Thresholds, Verdict};
+pub mod trace;
+pub use trace::{PacketRecord, TraceWriter};
+pub mod voip;
+pub use voip::VoipQuality;
 mod utils;
-pub use utils::net_utils::{ClientCommand, IntervalResult, ServerCommand};
+pub use utils::interval_clock::IntervalAlignment;
+pub use utils::jitter::JitterEstimator;
+pub use utils::net_utils::{
+    ClientCommand, ClientInterval, ClientProgress, IntervalCallback, IntervalResult,
+    ProgressCallback, Rate, RetryPolicy, ReverseRequest, ServerCommand, ServerStatus,
+};
+pub use utils::pacer::PacingPolicy;
 pub use utils::ui;
 
 // async part
@@ -174,3 +303,4 @@ mod async_client;
 pub use async_client::AsyncUdpClient;
 mod async_server;
 pub use async_server::AsyncUdpServer;
+pub use tokio_util::sync::CancellationToken;
@@ -0,0 +1,34 @@
+//! Logging macros that forward to `tracing` (feature `tracing`).
+//!
+//! Without the feature the events compile to nothing, so the library never
+//! writes to stdout on its own.
+
+/// Emits a `tracing` event at the given level, e.g. `event!(info, peer = %addr, "started")`.
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    }};
+}
+
+/// Records the peer address on the current span.
+macro_rules! record_peer {
+    ($peer:expr) => {{
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("peer", tracing::field::display($peer));
+        #[cfg(not(feature = "tracing"))]
+        let _ = &$peer;
+    }};
+}
+
+/// Span around one client or server run, tagged with the test id and the peer.
+#[cfg(feature = "tracing")]
+macro_rules! run_span {
+    ($name:literal, $test_id:expr) => {
+        tracing::info_span!(
+            $name,
+            test_id = $test_id.as_deref(),
+            peer = tracing::field::Empty
+        )
+    };
+}
@@ -0,0 +1,2021 @@
+//! `udpopt` command line tool (feature `cli`).
+//!
+//! ```text
+//! udpopt server [--bind 0.0.0.0:5201] [--bind-dev IFACE] [--interval 1] [--omit 2] [--json] [--one-off] [--tui]
+//! udpopt client <SERVER> [--bitrate 10M] [--payload 1200] [--duration 10]
+//!               [--interval 1] [--reverse] [--cport PORTS] [--bind-dev IFACE] [--json]
+//! udpopt discover <SERVER> [--min 1M] [--max 1G] [--max-loss 0] [--json]
+//! udpopt step-load <SERVER> [--start 10M] [--step 10M] [--every 10] [--steps 5] [--json]
+//! udpopt scenario <FILE> [--json]
+//! udpopt run <CONFIG> [--profile NAME] [--json]
+//! ```
+//!
+//! In reverse mode the client asks the server to send and measures what it receives.
+//! `discover` binary-searches the highest bitrate the path carries within the
+//! loss limit, with short tests against a `udpopt server`.
+//! `step-load` has a `udpopt server` send at a rate rising in steps and judges
+//! every step here, the last one passing being the sustainable rate.
+//! A scenario runs the flows of a TOML file at once, see `udpopt::scenario`.
+//! `run` plays one end of a test described in a TOML file, see `udpopt::config`,
+//! and exits with status 2 when the results miss its thresholds.
+//! The server only sends in reverse mode with `--allow-reverse`, to clients
+//! presenting its `--psk` or one of its `--allow-token`s, and caps what they
+//! ask for at its `--reverse-max-*` limits.
+//! Started by a systemd socket unit (Linux), the server receives on the socket
+//! of the unit instead of binding `--bind`, and refuses reverse mode.
+//! Ctrl-C stops the running test and still prints the results collected so far;
+//! a second Ctrl-C exits immediately.
+
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    process::ExitCode,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "tui")]
+use std::thread;
+
+use clap::{Args, Parser, Subcommand};
+use serde_json::{Value, json};
+use udpopt::{
+    AccessControl, AuthKey, CapacitySearch, ClientBuilder, ClientCommand, ClientReport,
+    DatagramSocket, Interface, IntervalAlignment, IntervalResult, IpNet, JitterEstimator, NatProbe,
+    Overhead, PortRange, RetryPolicy, ReverseCredentials, ReverseLimits, ReversePolicy,
+    ReverseRequest, Scenario, SendLimit, ServerBuilder, ServerCommand, SizeMix, SizeStats,
+    SizeSweep, StepLoad, TestConfig, TestReport, TestResult, Throughput, UdpOptError,
+    access::source_allowed,
+    config::Role,
+    congestion::Aimd,
+    nat,
+    report::{SocketSettings, TestParameters},
+    socket, ui,
+};
+
+/// How long the sender waits for the receiver's results after the test
+const REMOTE_RESULTS_WAIT: Duration = Duration::from_secs(2);
+
+/// How long the server waits for the NAT probe it peeked
+const NAT_PROBE_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "udpopt",
+    version,
+    about = "Test your UDP connection between two nodes"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Receive a test (or send one, when the client asks for reverse mode)
+    Server(ServerArgs),
+    /// Send a test to a server (or receive one with `--reverse`)
+    Client(ClientArgs),
+    /// Find the highest bitrate a server receives without loss
+    Discover(DiscoverArgs),
+    /// Receive a rate rising in steps from a server and judge every step
+    StepLoad(StepLoadArgs),
+    /// Test a list of packet lengths at one bitrate and compare their loss
+    Sweep(SweepArgs),
+    /// Run several flows at once, as described in a TOML file
+    Scenario(ScenarioArgs),
+    /// Run the test described in a TOML file and check its thresholds
+    Run(RunArgs),
+}
+
+#[derive(Debug, Args)]
+struct DiscoverArgs {
+    /// Address of the server
+    server: SocketAddr,
+    /// Local address to bind
+    #[arg(long, default_value = "0.0.0.0:0")]
+    bind: SocketAddr,
+    /// Source port, or a range FIRST-LAST to take the first free port of,
+    /// instead of the port of --bind
+    #[arg(long, value_name = "PORTS")]
+    cport: Option<PortRange>,
+    #[command(flatten)]
+    device: BindDevice,
+    /// Lowest bitrate of the search, with an optional K/M/G suffix
+    #[arg(long, default_value = "1M", value_parser = parse_bitrate)]
+    min: f64,
+    /// Highest bitrate of the search, with an optional K/M/G suffix
+    #[arg(long, default_value = "1G", value_parser = parse_bitrate)]
+    max: f64,
+    /// Size of each packet in bytes, header included
+    #[arg(short = 'l', long, default_value_t = 1200)]
+    payload: usize,
+    /// Duration of every trial in seconds
+    #[arg(short = 't', long, default_value = "2", value_parser = parse_secs)]
+    trial: Duration,
+    /// Highest loss (%) a trial may have and still pass
+    #[arg(long, default_value_t = 0.0)]
+    max_loss: f64,
+    /// Stop when the bounds are within this percentage of each other
+    #[arg(long, default_value_t = 5.0)]
+    precision: f64,
+    /// Stop after this many trials
+    #[arg(long, default_value_t = 12)]
+    max_trials: usize,
+    /// Print the results as JSON instead of text
+    #[arg(short = 'J', long)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct SweepArgs {
+    /// Address of the server
+    server: SocketAddr,
+    /// Local address to bind
+    #[arg(long, default_value = "0.0.0.0:0")]
+    bind: SocketAddr,
+    /// Source port, or a range FIRST-LAST to take the first free port of,
+    /// instead of the port of --bind
+    #[arg(long, value_name = "PORTS")]
+    cport: Option<PortRange>,
+    #[command(flatten)]
+    device: BindDevice,
+    /// Packet lengths to test in bytes, header included, in order
+    #[arg(
+        long,
+        value_name = "BYTES,...",
+        value_delimiter = ',',
+        default_value = "64,128,256,512,1024,1280,1400,1472,2000",
+        value_parser = parse_sweep_size
+    )]
+    sizes: Vec<usize>,
+    /// Bitrate of every length, with an optional K/M/G suffix
+    #[arg(short, long, default_value = "1M", value_parser = parse_bitrate)]
+    bitrate: f64,
+    /// Duration of every length in seconds
+    #[arg(short = 't', long, default_value = "2", value_parser = parse_secs)]
+    trial: Duration,
+    /// Print the results as JSON instead of text
+    #[arg(short = 'J', long)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct StepLoadArgs {
+    /// Address of the server
+    server: SocketAddr,
+    /// Local address to bind
+    #[arg(long, default_value = "0.0.0.0:0")]
+    bind: SocketAddr,
+    /// Source port, or a range FIRST-LAST to take the first free port of,
+    /// instead of the port of --bind
+    #[arg(long, value_name = "PORTS")]
+    cport: Option<PortRange>,
+    #[command(flatten)]
+    device: BindDevice,
+    /// Bitrate of the first step, with an optional K/M/G suffix
+    #[arg(long, default_value = "10M", value_parser = parse_bitrate)]
+    start: f64,
+    /// Bitrate added at every step, with an optional K/M/G suffix
+    #[arg(long, default_value = "10M", value_parser = parse_bitrate)]
+    step: f64,
+    /// Seconds every step lasts
+    #[arg(long, default_value = "10", value_parser = parse_secs)]
+    every: Duration,
+    /// Number of steps
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    steps: u32,
+    /// Size of each packet in bytes, header included
+    #[arg(short = 'l', long, default_value_t = 1200)]
+    payload: usize,
+    /// Highest loss (%) a step may have and still pass
+    #[arg(long, default_value_t = 0.0)]
+    max_loss: f64,
+    /// Highest interval jitter (ms) a step may have and still pass
+    #[arg(long)]
+    max_jitter: Option<f64>,
+    /// Passphrase shared with the server, signs the request
+    #[arg(long)]
+    psk: Option<String>,
+    /// Token presented to a server that only sends to known clients
+    #[arg(long)]
+    token: Option<String>,
+    /// Print the results as JSON instead of text
+    #[arg(short = 'J', long)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct RunArgs {
+    /// Test configuration file
+    config: PathBuf,
+    /// Apply this `[profiles.NAME]` table
+    #[arg(short, long)]
+    profile: Option<String>,
+    /// Play this end of the test, `client` or `server`, instead of the
+    /// `role` of the file
+    #[arg(long, value_parser = parse_role)]
+    role: Option<Role>,
+    /// Print the results as JSON instead of text
+    #[arg(short = 'J', long)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct ScenarioArgs {
+    /// Scenario file, a `duration` and `[[flow]]` tables
+    file: PathBuf,
+    /// Print the results as JSON instead of text
+    #[arg(short = 'J', long)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct ServerArgs {
+    /// Address to listen on, unless started by a systemd socket unit
+    #[arg(short, long, default_value = "0.0.0.0:5201")]
+    bind: SocketAddr,
+    #[command(flatten)]
+    device: BindDevice,
+    /// Report what this interface dropped every interval, to tell host
+    /// drops from network loss (Linux)
+    #[arg(long, value_name = "IFACE")]
+    nic: Option<Interface>,
+    /// Count the packets the socket dropped with a full receive buffer,
+    /// apart from the loss on the path (Linux)
+    #[arg(long)]
+    socket_drops: bool,
+    /// Seconds between interval reports
+    #[arg(short, long, default_value = "1", value_parser = parse_secs)]
+    interval: Duration,
+    /// Link-layer bytes per packet in the on-wire throughput, see
+    /// `udpopt client --link-overhead`
+    #[arg(long, value_name = "BYTES", default_value_t = Overhead::ETHERNET_WIRE)]
+    link_overhead: usize,
+    /// Lay the interval boundaries on the wall clock (whole seconds for 1 s
+    /// intervals) instead of the first packet
+    #[arg(long)]
+    align_wall_clock: bool,
+    /// Interval jitter estimator: rfc3550 (smoothed), ipdv (mean delay
+    /// variation, RFC 3393) or mad (median absolute deviation)
+    #[arg(long, default_value = "rfc3550")]
+    jitter: JitterEstimator,
+    /// Seconds after the first packet left out of the results (warm-up)
+    #[arg(short = 'O', long, value_parser = parse_secs)]
+    omit: Option<Duration>,
+    /// Keep receiving the tail of the test for this many seconds after the
+    /// client's FIN, so packets reordered behind it are not counted lost
+    #[arg(long, value_parser = parse_secs)]
+    fin_linger: Option<Duration>,
+    /// Recommend the rate with additive increase / multiplicative decrease,
+    /// steadier than the default loss threshold
+    #[arg(long)]
+    aimd: bool,
+    /// Print the results as JSON instead of text
+    #[arg(short = 'J', long)]
+    json: bool,
+    /// Exit after the first test instead of waiting for the next one
+    #[arg(short = '1', long)]
+    one_off: bool,
+    /// Count the packets marked ECN Congestion Experienced on the path (Linux)
+    #[arg(long)]
+    ecn: bool,
+    /// Passphrase shared with the clients, packets not signed with it are dropped
+    #[arg(long)]
+    psk: Option<String>,
+    /// Only measure clients presenting this token (repeatable), and only
+    /// send to them in reverse mode
+    #[arg(long = "allow-token", value_name = "TOKEN")]
+    allow_tokens: Vec<String>,
+    /// Send to the clients asking for reverse mode; needs --psk or
+    /// --allow-token, a spoofed request would make the server stream at
+    /// any address
+    #[arg(long)]
+    allow_reverse: bool,
+    /// Highest bitrate sent in reverse mode, with an optional K/M/G suffix
+    #[arg(long, default_value = "100M", value_parser = parse_bitrate)]
+    reverse_max_bitrate: f64,
+    /// Longest test sent in reverse mode, in seconds
+    #[arg(long, default_value = "60", value_parser = parse_secs)]
+    reverse_max_duration: Duration,
+    /// Longest packet sent in reverse mode in bytes, header included
+    #[arg(long, default_value_t = 1472)]
+    reverse_max_length: usize,
+    /// Answer the NAT probes of `udpopt client --nat-probe`; they carry no
+    /// token, so they are refused with --allow-token
+    #[arg(long)]
+    allow_nat_probe: bool,
+    /// Only measure datagrams from this prefix, e.g. 10.0.0.0/8 (repeatable);
+    /// the others are counted as foreign
+    #[arg(long = "allow-source", value_name = "PREFIX")]
+    allow_sources: Vec<IpNet>,
+    /// Write a report of the last test with its environment to FILE, TOML
+    /// for a `.toml` name, CSV for `.csv`, Prometheus gauges for `.prom`,
+    /// JSON otherwise
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+    #[command(flatten)]
+    labels: RunLabels,
+    /// Show the intervals on a live dashboard, `q` stops the test
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with = "json")]
+    tui: bool,
+}
+
+impl ServerArgs {
+    /// Whether the live dashboard was asked for
+    fn tui(&self) -> bool {
+        #[cfg(feature = "tui")]
+        return self.tui;
+        #[cfg(not(feature = "tui"))]
+        false
+    }
+}
+
+#[derive(Debug, Args)]
+struct ClientArgs {
+    /// Address of the server
+    server: SocketAddr,
+    /// Local address to bind
+    #[arg(long, default_value = "0.0.0.0:0")]
+    bind: SocketAddr,
+    /// Source port, or a range FIRST-LAST to take the first free port of,
+    /// instead of the port of --bind
+    #[arg(long, value_name = "PORTS")]
+    cport: Option<PortRange>,
+    #[command(flatten)]
+    device: BindDevice,
+    /// Target bitrate in bits per second, with an optional K/M/G suffix
+    #[arg(short, long, default_value = "1M", value_parser = parse_bitrate)]
+    bitrate: f64,
+    /// Size of each packet in bytes, header included
+    #[arg(short = 'l', long, default_value_t = 1200)]
+    payload: usize,
+    /// Test duration in seconds
+    #[arg(short = 't', long, default_value = "10", value_parser = parse_secs)]
+    duration: Duration,
+    /// Seconds between interval reports
+    #[arg(short, long, default_value = "1", value_parser = parse_secs)]
+    interval: Duration,
+    /// Let the server send and measure on this side
+    #[arg(short = 'R', long)]
+    reverse: bool,
+    /// Send the simple IMIX of 64, 576 and 1500 byte packets (7:4:1) instead
+    /// of fixed-size ones
+    #[arg(long, conflicts_with = "reverse")]
+    imix: bool,
+    /// Send the packets ECN-capable, ECT(0) (Linux)
+    #[arg(long, conflicts_with = "reverse")]
+    ecn: bool,
+    /// Mark the packets with this DiffServ code point, e.g. 46 for EF
+    /// (Linux); asks the server to mark them in reverse mode
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..64))]
+    dscp: Option<u8>,
+    /// Send non-blocking, skipping and counting the packets a full send
+    /// buffer refuses
+    #[arg(long, conflicts_with = "reverse")]
+    nonblocking: bool,
+    /// Retry a send failing with EINTR or ENOBUFS up to N times with
+    /// backoff, skipping the packet after that instead of failing the test
+    #[arg(long, value_name = "N", conflicts_with = "reverse")]
+    send_retries: Option<u32>,
+    /// Keep sending for up to this many seconds while the server's host
+    /// refuses the packets (ICMP port unreachable), e.g. while it restarts
+    #[arg(long, value_parser = parse_secs, conflicts_with = "reverse")]
+    unreachable_grace: Option<Duration>,
+    /// Link-layer bytes per packet in the on-wire throughput: 38 for
+    /// Ethernet with preamble and inter-frame gap, 14 to match interface
+    /// counters, 0 for the IP layer
+    #[arg(long, value_name = "BYTES", default_value_t = Overhead::ETHERNET_WIRE)]
+    link_overhead: usize,
+    /// Set the don't-fragment bit: packets longer than the path MTU are
+    /// refused and counted instead of fragmented (Linux)
+    #[arg(long, conflicts_with_all = ["reverse", "fragment"])]
+    dont_fragment: bool,
+    /// Clear the don't-fragment bit, the host fragments packets longer than
+    /// the path MTU (Linux)
+    #[arg(long, conflicts_with = "reverse")]
+    fragment: bool,
+    /// Passphrase shared with the server, every packet is signed with it
+    #[arg(long)]
+    psk: Option<String>,
+    /// Token presented to a server that only measures known clients, or
+    /// only sends to them in reverse mode
+    #[arg(long)]
+    token: Option<String>,
+    /// Tell the server how many packets were sent every interval, so it
+    /// counts the loss of each interval from that
+    #[arg(long, conflicts_with = "reverse")]
+    send_stats: bool,
+    /// Send a heartbeat whenever the link would stay quiet for longer than
+    /// this many seconds, so slow or bursty tests outlive the server's idle
+    /// timeout
+    #[arg(long, value_parser = parse_secs, conflicts_with = "reverse")]
+    heartbeat: Option<Duration>,
+    /// After the test, stay silent for each of these many seconds in turn
+    /// and check the NAT kept the mapping of the flow, e.g. `15,30,60,120`
+    #[arg(long, value_name = "SECS,...", value_delimiter = ',', value_parser = parse_secs)]
+    nat_probe: Vec<Duration>,
+    /// Write a report of the test with its environment to FILE, TOML for a
+    /// `.toml` name, CSV for `.csv`, Prometheus gauges for `.prom`, JSON
+    /// otherwise
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+    #[command(flatten)]
+    labels: RunLabels,
+    /// Print the results as JSON instead of text
+    #[arg(short = 'J', long)]
+    json: bool,
+}
+
+/// `--bind-dev`, shared by every command with a socket
+#[derive(Debug, Args)]
+struct BindDevice {
+    /// Bind the socket to this network interface, a name like eth1 or an
+    /// index, whatever the routing table prefers (Linux)
+    #[arg(long, value_name = "IFACE")]
+    bind_dev: Option<Interface>,
+}
+
+/// Name, description and tags of a run, kept in its JSON output and report
+#[derive(Debug, Clone, Args)]
+struct RunLabels {
+    /// Name of the run, e.g. the scenario it belongs to
+    #[arg(long)]
+    name: Option<String>,
+    /// Free text about the run
+    #[arg(long)]
+    description: Option<String>,
+    /// Label the run, e.g. `--tag site=ams1 --tag circuit=C-4711` (repeatable)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+}
+
+impl RunLabels {
+    /// `parameters` with the labels of the run
+    fn parameters(&self, parameters: TestParameters) -> TestParameters {
+        TestParameters {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            tags: self.tags.iter().cloned().collect(),
+            ..parameters
+        }
+    }
+
+    /// Adds the labels given to the JSON object `out`
+    fn add_to(&self, out: &mut Value) {
+        if let Some(name) = &self.name {
+            out["name"] = json!(name);
+        }
+        if let Some(description) = &self.description {
+            out["description"] = json!(description);
+        }
+        if !self.tags.is_empty() {
+            let tags: serde_json::Map<String, Value> = self
+                .tags
+                .iter()
+                .map(|(k, v)| (k.clone(), json!(v)))
+                .collect();
+            out["tags"] = Value::Object(tags);
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let interrupt = match Interrupt::install() {
+        Ok(interrupt) => interrupt,
+        Err(e) => {
+            eprintln!("udpopt: cannot install the Ctrl-C handler: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let res = match cli.command {
+        Command::Server(args) => run_server(&args, &interrupt),
+        Command::Client(args) => run_client(&args, &interrupt),
+        Command::Discover(args) => run_discover(&args),
+        Command::StepLoad(args) => run_step_load(&args),
+        Command::Sweep(args) => run_sweep(&args),
+        Command::Scenario(args) => run_scenario(&args),
+        Command::Run(args) => match run_config(&args) {
+            // the verdict is already printed
+            Ok(false) => return ExitCode::from(2),
+            res => res.map(drop),
+        },
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("udpopt: {}", ui::format_error(&e));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_server(args: &ServerArgs, interrupt: &Interrupt) -> Result<(), UdpOptError> {
+    // started by a systemd socket unit, receive on its socket
+    #[cfg(target_os = "linux")]
+    let inherited = udpopt::socket::systemd_sockets()
+        .map_err(UdpOptError::InheritedSocket)?
+        .into_iter()
+        .next();
+    #[cfg(not(target_os = "linux"))]
+    let inherited: Option<UdpSocket> = None;
+    let mut reverse = reverse_policy(args)?;
+    if !args.json {
+        match inherited.as_ref().and_then(|sock| sock.local_addr().ok()) {
+            Some(addr) => eprintln!("Server listening on {} (socket-activated)", addr),
+            None => eprintln!("Server listening on {}", args.bind),
+        }
+    }
+    loop {
+        let mut sock = match &inherited {
+            Some(sock) => {
+                let sock = sock.try_clone().map_err(UdpOptError::InheritedSocket)?;
+                if let Some(device) = &args.device.bind_dev {
+                    sock.bind_device(device)
+                        .map_err(UdpOptError::DeviceFailed)?;
+                }
+                sock
+            }
+            None => bind_socket(args.bind, None, args.device.bind_dev.as_ref())?,
+        };
+        let Some((request, peer)) = wait_for_peer(&sock, &args.allow_sources, interrupt)? else {
+            return Ok(());
+        };
+
+        let res = match request {
+            Request::NatProbe if !args.allow_nat_probe || !args.allow_tokens.is_empty() => {
+                if !args.json {
+                    let reason = if args.allow_nat_probe {
+                        "access control is on"
+                    } else {
+                        "start the server with --allow-nat-probe"
+                    };
+                    eprintln!("Refusing NAT probe from {}: {}", peer, reason);
+                }
+                // drop it, or it is peeked again
+                let _ = sock.recv_from(&mut [0u8; 64]);
+                continue;
+            }
+            Request::NatProbe => answer_nat_probe(&sock, peer, args.json, interrupt),
+            // connecting it would tie the socket to this client for good
+            Request::Reverse(_) if inherited.is_some() => {
+                if !args.json {
+                    eprintln!(
+                        "Refusing reverse mode from {}: the socket is inherited",
+                        peer
+                    );
+                }
+                continue;
+            }
+            Request::Reverse(buf) => {
+                let admitted = match reverse.as_mut() {
+                    Some(policy) => policy.admit(&buf, peer),
+                    None => Err(UdpOptError::ReverseRefused(
+                        "start the server with --allow-reverse".to_string(),
+                    )),
+                };
+                let req = match admitted {
+                    Ok(req) => req,
+                    Err(e) => {
+                        if !args.json {
+                            eprintln!("{} ({})", e, peer);
+                        }
+                        continue;
+                    }
+                };
+                let steps = StepLoad::from_request(&req);
+                if !args.json {
+                    match &steps {
+                        Some(plan) => eprintln!(
+                            "Sending to {} (reverse mode, step load up to {})",
+                            peer,
+                            ui::format_bitrate(plan.bitrate(plan.steps() - 1))
+                        ),
+                        None => eprintln!("Sending to {} (reverse mode)", peer),
+                    }
+                }
+                sock.connect(peer)
+                    .map_err(|source| UdpOptError::ConnectFailed { peer, source })?;
+                let (tx, rx) = mpsc::channel();
+                interrupt.arm(tx.clone(), || ClientCommand::Stop);
+                let mut builder =
+                    ClientBuilder::new(req.bitrate_bps, req.payload_size, req.duration)
+                        .remote_results(REMOTE_RESULTS_WAIT);
+                if req.dscp != 0 {
+                    builder = builder.dscp(req.dscp);
+                }
+                if let Some(psk) = &args.psk {
+                    builder = builder.auth(AuthKey::from_passphrase(psk));
+                }
+                let report = args.report.as_ref().map(|path| ReportFile {
+                    path: path.clone(),
+                    local: sock.local_addr().ok(),
+                    peer: Some(peer),
+                    socket: SocketSettings {
+                        authenticated: args.psk.is_some(),
+                        device: args.device.bind_dev.as_ref().map(ToString::to_string),
+                        ..Default::default()
+                    },
+                    parameters: args.labels.parameters(TestParameters {
+                        bitrate_bps: Some(req.bitrate_bps),
+                        packet_len: Some(req.payload_size),
+                        duration: Some(req.duration),
+                        ..Default::default()
+                    }),
+                    started_at: SystemTime::now(),
+                });
+                let client = builder.build(rx);
+                // raises the rate until the test is over
+                let _stepper = steps.map(|plan| plan.spawn_steps(tx.clone()));
+                let overhead = wire_overhead(peer, args.link_overhead);
+                send_test(
+                    client,
+                    &mut sock,
+                    &tx,
+                    overhead,
+                    args.json,
+                    report,
+                    &args.labels,
+                )
+            }
+            Request::Test => {
+                if !args.json {
+                    eprintln!("Receiving from {}", peer);
+                }
+                let (tx, rx) = mpsc::channel();
+                interrupt.arm(tx.clone(), || ServerCommand::Stop);
+                let mut builder =
+                    ServerBuilder::new(args.interval).omit(args.omit.unwrap_or_default());
+                if args.ecn {
+                    builder = builder.ecn();
+                }
+                if args.align_wall_clock {
+                    builder = builder.interval_alignment(IntervalAlignment::WallClock);
+                }
+                builder = builder.jitter_estimator(args.jitter);
+                if let Some(linger) = args.fin_linger {
+                    builder = builder.fin_linger(linger);
+                }
+                if args.aimd {
+                    builder = builder.congestion_controller(Aimd::new());
+                }
+                if let Some(nic) = &args.nic {
+                    builder = builder.nic_counters(nic.clone());
+                }
+                if args.socket_drops {
+                    builder = builder.count_socket_drops();
+                }
+                if let Some(psk) = &args.psk {
+                    builder = builder.auth(AuthKey::from_passphrase(psk));
+                }
+                if !args.allow_tokens.is_empty() {
+                    builder = builder.access_control(AccessControl::allowlist(
+                        args.allow_tokens.iter().map(String::as_str),
+                    ));
+                }
+                if !args.allow_sources.is_empty() {
+                    builder = builder.allow_sources(args.allow_sources.clone());
+                }
+                let report = args.report.as_ref().map(|path| ReportFile {
+                    path: path.clone(),
+                    local: sock.local_addr().ok(),
+                    peer: Some(peer),
+                    socket: SocketSettings {
+                        ecn: args.ecn,
+                        authenticated: args.psk.is_some(),
+                        access_control: !args.allow_tokens.is_empty(),
+                        device: args.device.bind_dev.as_ref().map(ToString::to_string),
+                        ..Default::default()
+                    },
+                    parameters: args.labels.parameters(TestParameters {
+                        receiver: true,
+                        interval: Some(args.interval),
+                        omit: args.omit,
+                        ..Default::default()
+                    }),
+                    started_at: SystemTime::now(),
+                });
+                let output = Output {
+                    json: args.json,
+                    tui: args.tui(),
+                    overhead: wire_overhead(peer, args.link_overhead),
+                    report,
+                    labels: args.labels.clone(),
+                };
+                receive_test(builder, &mut sock, rx, &tx, output)
+            }
+        };
+        interrupt.disarm();
+
+        match res {
+            Ok(()) => {}
+            Err(e) if args.one_off => return Err(e),
+            Err(e) => eprintln!("udpopt: test failed: {}", ui::format_error(&e)),
+        }
+        if args.one_off || interrupt.requested() {
+            return Ok(());
+        }
+    }
+}
+
+fn run_client(args: &ClientArgs, interrupt: &Interrupt) -> Result<(), UdpOptError> {
+    if args.nat_probe.iter().any(|idle| idle.as_millis() == 0) {
+        return Err(UdpOptError::InvalidConfig(
+            "--nat-probe silences must be at least a millisecond".to_string(),
+        ));
+    }
+    if args.nat_probe.iter().any(|idle| *idle > nat::MAX_IDLE) {
+        return Err(UdpOptError::InvalidConfig(format!(
+            "--nat-probe silences last at most {} s",
+            nat::MAX_IDLE.as_secs()
+        )));
+    }
+    let mut sock = bind_socket(args.bind, args.cport, args.device.bind_dev.as_ref())?;
+    sock.connect(args.server)
+        .map_err(|source| UdpOptError::ConnectFailed {
+            peer: args.server,
+            source,
+        })?;
+
+    let res = if args.reverse {
+        let req = ReverseRequest {
+            bitrate_bps: args.bitrate,
+            payload_size: args.payload,
+            duration: args.duration,
+            dscp: args.dscp.unwrap_or(0),
+            step_bps: 0.0,
+            step_every: Duration::ZERO,
+        };
+        let req = credentials(args.psk.as_deref(), args.token.as_deref()).encode(req);
+        sock.send(&req).map_err(|source| UdpOptError::SendFailed {
+            peer: Some(args.server),
+            bytes: req.len(),
+            source,
+        })?;
+        // give up if the server never starts sending
+        sock.set_read_timeout(Some(Duration::from_secs(5)))
+            .map_err(|_| UdpOptError::SocketTimeout)?;
+
+        let (tx, rx) = mpsc::channel();
+        interrupt.arm(tx.clone(), || ServerCommand::Stop);
+        let mut builder = ServerBuilder::new(args.interval);
+        if let Some(psk) = &args.psk {
+            builder = builder.auth(AuthKey::from_passphrase(psk));
+        }
+        let output = Output {
+            json: args.json,
+            tui: false,
+            overhead: wire_overhead(args.server, args.link_overhead),
+            report: client_report(args, &sock, true),
+            labels: args.labels.clone(),
+        };
+        receive_test(builder, &mut sock, rx, &tx, output)
+    } else {
+        let (tx, rx) = mpsc::channel();
+        interrupt.arm(tx.clone(), || ClientCommand::Stop);
+        let mut builder = ClientBuilder::new(args.bitrate, args.payload, args.duration)
+            .progress_interval(args.interval)
+            .remote_results(REMOTE_RESULTS_WAIT);
+        if args.imix {
+            builder = builder.packet_sizes(SizeMix::imix());
+        }
+        if args.ecn {
+            builder = builder.ecn();
+        }
+        if let Some(dscp) = args.dscp {
+            builder = builder.dscp(dscp);
+        }
+        if args.nonblocking {
+            builder = builder.nonblocking_send();
+        }
+        if let Some(max_retries) = args.send_retries {
+            builder = builder.send_retry(RetryPolicy {
+                max_retries,
+                ..RetryPolicy::default()
+            });
+        }
+        if let Some(grace) = args.unreachable_grace {
+            builder = builder.unreachable_grace(grace);
+        }
+        if args.dont_fragment || args.fragment {
+            builder = builder.dont_fragment(args.dont_fragment);
+        }
+        if let Some(psk) = &args.psk {
+            builder = builder.auth(AuthKey::from_passphrase(psk));
+        }
+        if let Some(token) = &args.token {
+            builder = builder.access_token(token.as_str());
+        }
+        if args.send_stats {
+            builder = builder.stats_interval(args.interval);
+        }
+        if let Some(every) = args.heartbeat {
+            builder = builder.heartbeat(every);
+        }
+        if !args.json {
+            // printed directly, `ui::print_*` log through tracing when that feature is on
+            builder = builder.on_progress(Box::new(|p| println!("{}", ui::format_progress(p))));
+        }
+        let report = client_report(args, &sock, false);
+        send_test(
+            builder.build(rx),
+            &mut sock,
+            &tx,
+            wire_overhead(args.server, args.link_overhead),
+            args.json,
+            report,
+            &args.labels,
+        )
+    };
+    res?;
+    interrupt.disarm();
+    if args.nat_probe.is_empty() || interrupt.requested() {
+        return Ok(());
+    }
+    probe_nat(&sock, &args.nat_probe, args.json)
+}
+
+/// Runs a [`NatProbe`] on the socket of the test, on the same flow, and
+/// prints its rounds
+fn probe_nat(sock: &UdpSocket, idles: &[Duration], json: bool) -> Result<(), UdpOptError> {
+    if !json {
+        let total: Duration = idles.iter().sum();
+        eprintln!(
+            "Probing the NAT mapping, {} silences over at least {:.1} s",
+            idles.len(),
+            total.as_secs_f64()
+        );
+    }
+    let report = NatProbe::new(idles.iter().copied()).run(sock)?;
+    if json {
+        let out = json!({
+            "nat_probe": {
+                "public_addr": report.public_addr.to_string(),
+                "rounds": report.rounds.iter().map(|r| json!({
+                    "idle": r.idle.as_secs_f64(),
+                    "silent": r.silent.as_secs_f64(),
+                    "inbound": r.inbound,
+                    "answered": r.answered,
+                    "mapping_kept": r.mapping_kept,
+                    "public_addr": r.public_addr.map(|addr| addr.to_string()),
+                    "survived": r.survived(),
+                })).collect::<Vec<_>>(),
+                "longest_survived": report.longest_survived().map(|d| d.as_secs_f64()),
+                "shortest_lost": report.shortest_lost().map(|d| d.as_secs_f64()),
+            }
+        });
+        println!("{:#}", out);
+        return Ok(());
+    }
+    println!("NAT probe: public address {}", report.public_addr);
+    for r in &report.rounds {
+        let outcome = match r.public_addr {
+            _ if r.survived() => "mapping kept".to_string(),
+            Some(addr) if r.mapping_kept == Some(false) => {
+                format!("mapping lost, new address {}", addr)
+            }
+            Some(_) => "mapping kept, inbound packets dropped".to_string(),
+            None => "no answer from the server".to_string(),
+        };
+        println!("Silent {:>7.1} s | {}", r.idle.as_secs_f64(), outcome);
+    }
+    match (report.longest_survived(), report.shortest_lost()) {
+        (Some(kept), Some(lost)) => println!(
+            "Mapping timeout between {:.1} s and {:.1} s",
+            kept.as_secs_f64(),
+            lost.as_secs_f64()
+        ),
+        (Some(kept), None) => println!("Mapping outlives {:.1} s of silence", kept.as_secs_f64()),
+        (None, Some(lost)) => println!("Mapping shorter than {:.1} s", lost.as_secs_f64()),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// Answers the [`NatProbe`] of `peer` and prints the rounds
+fn answer_nat_probe(
+    sock: &UdpSocket,
+    peer: SocketAddr,
+    json: bool,
+    interrupt: &Interrupt,
+) -> Result<(), UdpOptError> {
+    if !json {
+        eprintln!("Answering the NAT probe of {}", peer);
+    }
+    let seen = nat::respond_until(sock, NAT_PROBE_WAIT, || interrupt.requested())?;
+    if json {
+        let out = json!({
+            "nat_probe": seen.iter().map(|probe| json!({
+                "round": probe.round,
+                "from": probe.from.to_string(),
+                "mapping_kept": probe.mapping_kept,
+                "idle": probe.idle.as_secs_f64(),
+            })).collect::<Vec<_>>(),
+        });
+        println!("{:#}", out);
+        return Ok(());
+    }
+    for probe in &seen {
+        let kept = match probe.mapping_kept {
+            None => "",
+            Some(true) => ", same address",
+            Some(false) => ", new address",
+        };
+        println!("Probe {:>2} from {}{}", probe.round, probe.from, kept);
+    }
+    Ok(())
+}
+
+fn run_discover(args: &DiscoverArgs) -> Result<(), UdpOptError> {
+    if args.min >= args.max {
+        return Err(UdpOptError::InvalidConfig(
+            "--min must be below --max".to_string(),
+        ));
+    }
+    let mut sock = bind_socket(args.bind, args.cport, args.device.bind_dev.as_ref())?;
+    sock.connect(args.server)
+        .map_err(|source| UdpOptError::ConnectFailed {
+            peer: args.server,
+            source,
+        })?;
+    if !args.json {
+        eprintln!(
+            "Searching {} to {} on {}",
+            ui::format_bitrate(args.min),
+            ui::format_bitrate(args.max),
+            args.server
+        );
+    }
+    let found = CapacitySearch::new(args.min, args.max)
+        .payload_size(args.payload)
+        .trial_duration(args.trial)
+        .max_loss_percent(args.max_loss)
+        .precision(args.precision / 100.0)
+        .max_trials(args.max_trials)
+        .run(&mut sock)?;
+
+    if args.json {
+        let out = json!({
+            "capacity_bps": found.capacity_bps,
+            "upper_bps": found.upper_bps,
+            "throughput_bps": found.throughput_bps,
+            "saturated": found.saturated,
+            "trials": found.trials.iter().map(|t| json!({
+                "bitrate_bps": t.bitrate_bps,
+                "passed": t.passed,
+                "summary": summary_json(&t.result),
+            })).collect::<Vec<_>>(),
+        });
+        println!("{:#}", out);
+        return Ok(());
+    }
+    for trial in &found.trials {
+        println!(
+            "{:>12} | Received {} | Lost {:.2} % | {}",
+            ui::format_bitrate(trial.bitrate_bps),
+            ui::format_bitrate(trial.result.mean_bitrate),
+            trial.result.loss_percent,
+            if trial.passed { "pass" } else { "fail" }
+        );
+    }
+    if found.saturated {
+        println!(
+            "Capacity at least {}, the top of the search",
+            ui::format_bitrate(found.capacity_bps)
+        );
+    } else {
+        println!(
+            "Capacity {} (below {}), {} received",
+            ui::format_bitrate(found.capacity_bps),
+            ui::format_bitrate(found.upper_bps),
+            ui::format_bitrate(found.throughput_bps)
+        );
+    }
+    Ok(())
+}
+
+/// What reverse mode requests carry to get past the server's checks
+fn credentials(psk: Option<&str>, token: Option<&str>) -> ReverseCredentials {
+    let mut credentials = ReverseCredentials::new();
+    if let Some(psk) = psk {
+        credentials = credentials.auth(AuthKey::from_passphrase(psk));
+    }
+    if let Some(token) = token {
+        credentials = credentials.token(token);
+    }
+    credentials
+}
+
+fn run_step_load(args: &StepLoadArgs) -> Result<(), UdpOptError> {
+    let mut sock = bind_socket(args.bind, args.cport, args.device.bind_dev.as_ref())?;
+    sock.connect(args.server)
+        .map_err(|source| UdpOptError::ConnectFailed {
+            peer: args.server,
+            source,
+        })?;
+    let mut plan = StepLoad::new(args.start, args.step, args.every, args.steps as usize)
+        .payload_size(args.payload)
+        .max_loss_percent(args.max_loss)
+        .credentials(credentials(args.psk.as_deref(), args.token.as_deref()));
+    if let Some(max) = args.max_jitter {
+        plan = plan.max_jitter_ms(max);
+    }
+    if !args.json {
+        eprintln!(
+            "Receiving {} steps of {:.1} s from {}, {} to {}",
+            plan.steps(),
+            args.every.as_secs_f64(),
+            args.server,
+            ui::format_bitrate(plan.bitrate(0)),
+            ui::format_bitrate(plan.bitrate(plan.steps() - 1))
+        );
+    }
+    let report = plan.run(&mut sock)?;
+
+    if args.json {
+        let out = json!({
+            "steps": report.steps.iter().map(|s| json!({
+                "step": s.step,
+                "bitrate_bps": s.bitrate_bps,
+                "received_bps": s.received_bps,
+                "received": s.received,
+                "lost": s.lost,
+                "loss_percent": s.loss_percent,
+                "jitter_ms": s.jitter_ms,
+                "passed": s.passed,
+            })).collect::<Vec<_>>(),
+            "last_sustainable": report.last_sustainable,
+            "sustainable_bps": report.sustainable_bps,
+            "summary": summary_json(&report.result),
+        });
+        println!("{:#}", out);
+        return Ok(());
+    }
+    for s in &report.steps {
+        println!(
+            "Step {:>2} {:>12} | Received {} | Lost {:.2} % | Jitter {:.3} ms | {}",
+            s.step + 1,
+            ui::format_bitrate(s.bitrate_bps),
+            ui::format_bitrate(s.received_bps),
+            s.loss_percent,
+            s.jitter_ms,
+            if s.passed { "pass" } else { "fail" }
+        );
+    }
+    match report.last_sustainable {
+        Some(step) => println!(
+            "Sustainable up to step {}, {}",
+            step + 1,
+            ui::format_bitrate(report.sustainable_bps)
+        ),
+        None => println!("Not even the first step was sustainable"),
+    }
+    Ok(())
+}
+
+fn run_sweep(args: &SweepArgs) -> Result<(), UdpOptError> {
+    let mut sock = bind_socket(args.bind, args.cport, args.device.bind_dev.as_ref())?;
+    sock.connect(args.server)
+        .map_err(|source| UdpOptError::ConnectFailed {
+            peer: args.server,
+            source,
+        })?;
+    if !args.json {
+        eprintln!(
+            "Sweeping {} packet lengths at {} on {}",
+            args.sizes.len(),
+            ui::format_bitrate(args.bitrate),
+            args.server
+        );
+    }
+    let report = SizeSweep::new(args.sizes.iter().copied(), args.bitrate)
+        .trial_duration(args.trial)
+        .run(&mut sock)?;
+
+    if args.json {
+        let out = json!({
+            "bitrate_bps": report.bitrate_bps,
+            "sizes": report.sizes.iter().map(|s| json!({
+                "payload_size": s.payload_size,
+                "packets_sent": s.packets_sent,
+                "packets_received": s.packets_received,
+                "loss_percent": s.loss_percent,
+                "goodput_bps": s.goodput_bps,
+                "summary": s.result.as_ref().map(summary_json),
+            })).collect::<Vec<_>>(),
+            "largest_delivered": report.largest_delivered(),
+            "black_holes": report.black_holes(),
+        });
+        println!("{:#}", out);
+        return Ok(());
+    }
+    println!(
+        "{:>6} | {:>8} | {:>8} | {:>8} | Goodput",
+        "Bytes", "Sent", "Received", "Lost"
+    );
+    for s in &report.sizes {
+        println!(
+            "{:>6} | {:>8} | {:>8} | {:>6.2} % | {}",
+            s.payload_size,
+            s.packets_sent,
+            s.packets_received,
+            s.loss_percent,
+            if s.result.is_some() {
+                ui::format_bitrate(s.goodput_bps)
+            } else {
+                "no results".to_string()
+            }
+        );
+    }
+    let black_holes = report.black_holes();
+    if !black_holes.is_empty() {
+        let sizes: Vec<_> = black_holes.iter().map(ToString::to_string).collect();
+        println!(
+            "Nothing arrived of {} bytes although shorter packets did",
+            sizes.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn run_scenario(args: &ScenarioArgs) -> Result<(), UdpOptError> {
+    let toml = std::fs::read_to_string(&args.file).map_err(UdpOptError::ReportFailed)?;
+    let scenario = Scenario::from_toml(&toml)?;
+    if !args.json {
+        eprintln!(
+            "Running {} flows for {:.1} s",
+            scenario.flows.len(),
+            scenario.duration.as_secs_f64()
+        );
+    }
+    let outcome = scenario.run()?;
+    let total = &outcome.total;
+    if args.json {
+        let out = json!({
+            "flows": outcome.flows.iter().map(|flow| json!({
+                "name": flow.name,
+                "direction": flow.direction.as_str(),
+                "dscp": flow.dscp,
+                "local": flow.local.map(|addr| addr.to_string()),
+                "summary": summary_json(&flow.result),
+            })).collect::<Vec<_>>(),
+            "total": {
+                "packets": total.packets,
+                "lost": total.lost,
+                "bytes": total.bytes,
+                "bitrate_bps": total.bitrate_bps,
+                "upload_bps": total.upload_bps,
+                "download_bps": total.download_bps,
+                "loss_percent": total.loss_percent,
+                "max_jitter_ms": total.max_jitter_ms,
+            },
+        });
+        println!("{:#}", out);
+    } else {
+        for flow in &outcome.flows {
+            let result = &flow.result;
+            println!(
+                "{} ({}, DSCP {}{}): {} | Lost {:.2} % | Jitter {:.3} ms",
+                flow.name,
+                flow.direction.as_str(),
+                flow.dscp,
+                flow.local
+                    .map(|addr| format!(", port {}", addr.port()))
+                    .unwrap_or_default(),
+                ui::format_bitrate(result.mean_bitrate),
+                result.loss_percent,
+                result.mean_jitter
+            );
+        }
+        println!(
+            "Total: {} (up {}, down {}) | Lost {:.2} % | worst jitter {:.3} ms",
+            ui::format_bitrate(total.bitrate_bps),
+            ui::format_bitrate(total.upload_bps),
+            ui::format_bitrate(total.download_bps),
+            total.loss_percent,
+            total.max_jitter_ms
+        );
+    }
+    Ok(())
+}
+
+/// Runs a test configuration, returns whether it met its thresholds
+fn run_config(args: &RunArgs) -> Result<bool, UdpOptError> {
+    let config = TestConfig::from_toml(&args.config)?;
+    let role = args.role.unwrap_or(config.role);
+    let outcome = config.run_as(role, args.profile.as_deref())?;
+    if args.json {
+        let mut out = json!({
+            "profile": outcome.profile,
+            "summary": summary_json(&outcome.result),
+            "passed": outcome.verdict.passed(),
+            "failures": outcome.verdict.failures.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        });
+        let labels = RunLabels {
+            name: config.name.clone(),
+            description: config.description.clone(),
+            tags: config.tags.clone().into_iter().collect(),
+        };
+        labels.add_to(&mut out);
+        println!("{:#}", out);
+    } else {
+        ui::ReportRenderer::for_stdout().print(&[], &outcome.result);
+        println!("{}", outcome.verdict);
+    }
+    Ok(outcome.verdict.passed())
+}
+
+/// Binds a socket to `addr`, on the first free port of `ports` if given,
+/// and to the interface `device` if given
+fn bind_socket(
+    addr: SocketAddr,
+    ports: Option<PortRange>,
+    device: Option<&Interface>,
+) -> Result<UdpSocket, UdpOptError> {
+    let sock = socket::bind(addr, ports)?;
+    if let Some(device) = device {
+        sock.bind_device(device)
+            .map_err(UdpOptError::DeviceFailed)?;
+    }
+    Ok(sock)
+}
+
+/// The `--report` file of a client test, `receiver` in reverse mode
+fn client_report(args: &ClientArgs, sock: &UdpSocket, receiver: bool) -> Option<ReportFile> {
+    let path = args.report.clone()?;
+    Some(ReportFile {
+        path,
+        local: sock.local_addr().ok(),
+        peer: Some(args.server),
+        socket: SocketSettings {
+            ecn: args.ecn,
+            authenticated: args.psk.is_some(),
+            access_control: args.token.is_some(),
+            device: args.device.bind_dev.as_ref().map(ToString::to_string),
+            ..Default::default()
+        },
+        parameters: args.labels.parameters(TestParameters {
+            receiver,
+            bitrate_bps: Some(args.bitrate),
+            packet_len: Some(args.payload),
+            duration: Some(args.duration),
+            interval: Some(args.interval),
+            ..Default::default()
+        }),
+        started_at: SystemTime::now(),
+    })
+}
+
+/// How the results of a received test are shown
+struct Output {
+    json: bool,
+    tui: bool,
+    /// Overhead of the goodput and on-wire throughput
+    overhead: Overhead,
+    report: Option<ReportFile>,
+    labels: RunLabels,
+}
+
+/// Where the `--report` goes and what it records besides the results
+struct ReportFile {
+    path: PathBuf,
+    local: Option<SocketAddr>,
+    peer: Option<SocketAddr>,
+    socket: SocketSettings,
+    parameters: TestParameters,
+    started_at: SystemTime,
+}
+
+impl ReportFile {
+    /// Writes the report of `result` with its throughput over `overhead`,
+    /// in the format of the path's extension
+    fn write(self, result: &TestResult, overhead: Overhead) -> Result<(), UdpOptError> {
+        let report = TestReport::new(result.clone())
+            .with_throughput(result.throughput(overhead))
+            .with_addrs(self.local, self.peer)
+            .with_socket(self.socket)
+            .with_parameters(self.parameters)
+            .with_times(self.started_at, SystemTime::now());
+        let text = match self.path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => report.to_toml()?,
+            Some("csv") => report.to_csv(),
+            Some("prom") => report.to_prometheus(),
+            _ => report.to_json()?,
+        };
+        std::fs::write(&self.path, text).map_err(UdpOptError::ReportFailed)
+    }
+}
+
+fn receive_test(
+    mut builder: ServerBuilder,
+    sock: &mut UdpSocket,
+    rx: mpsc::Receiver<ServerCommand>,
+    tx: &mpsc::Sender<ServerCommand>,
+    output: Output,
+) -> Result<(), UdpOptError> {
+    let Output {
+        json,
+        tui,
+        overhead,
+        report,
+        labels,
+    } = output;
+    #[cfg(feature = "tui")]
+    let dashboard = if tui {
+        let (with_feed, dashboard) = spawn_dashboard(builder, tx);
+        builder = with_feed;
+        Some(dashboard)
+    } else {
+        None
+    };
+    if !json && !tui {
+        builder = builder.on_interval(Box::new(|r| println!("{}", ui::format_result(r))));
+    }
+    let mut server = builder.send_results_to_client().build(rx);
+    let _ = tx.send(ServerCommand::Start);
+    let (intervals, failure) = match server.run(sock) {
+        Ok(intervals) => (intervals, None),
+        // still report what was measured before the failure
+        Err(e) => (e.intervals, Some(e.error)),
+    };
+    if intervals.is_empty()
+        && let Some(error) = failure
+    {
+        return Err(error);
+    }
+    let summary = TestResult::from_intervals(&intervals)
+        .with_latency(server.latency_histogram())
+        .with_ipdv(server.ipdv_histogram())
+        .with_reorder(server.reorder_stats())
+        .with_sizes(server.size_stats())
+        .with_loss_pattern(server.loss_pattern())
+        .with_interarrival(server.interarrival_histogram())
+        .with_packets_sent(server.client_sent().unwrap_or(0));
+    // closes the dashboard's feed
+    drop(server);
+    #[cfg(feature = "tui")]
+    if let Some(Ok(Err(e))) = dashboard.map(thread::JoinHandle::join) {
+        eprintln!("udpopt: dashboard failed: {}", e);
+    }
+
+    if json {
+        let mut out = json!({
+            "intervals": intervals
+                .iter()
+                .map(|r| interval_json(r, overhead))
+                .collect::<Vec<_>>(),
+            "summary": summary_json(&summary),
+            "throughput": {
+                "overhead": overhead_json(overhead),
+                "received": throughput_json(&summary.throughput(overhead)),
+            },
+        });
+        labels.add_to(&mut out);
+        println!("{:#}", out);
+    } else {
+        ui::ReportRenderer::for_stdout().print(&intervals, &summary);
+        println!(
+            "{}",
+            format_throughput("Received", &summary.throughput(overhead))
+        );
+    }
+    if let Some(report) = report {
+        report.write(&summary, overhead)?;
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+/// Feeds the intervals to the live dashboard, quitting it stops the test
+#[cfg(feature = "tui")]
+fn spawn_dashboard(
+    builder: ServerBuilder,
+    tx: &mpsc::Sender<ServerCommand>,
+) -> (ServerBuilder, thread::JoinHandle<std::io::Result<bool>>) {
+    let (feed, dashboard) = udpopt::dashboard::channel();
+    let stop = tx.clone();
+    let handle = thread::spawn(move || {
+        let quit = dashboard.run()?;
+        if quit {
+            let _ = stop.send(ServerCommand::Stop);
+        }
+        Ok(quit)
+    });
+    (builder.on_interval(feed.observer()), handle)
+}
+
+fn send_test(
+    mut client: udpopt::UdpClient,
+    sock: &mut UdpSocket,
+    tx: &mpsc::Sender<ClientCommand>,
+    overhead: Overhead,
+    json: bool,
+    report_file: Option<ReportFile>,
+    labels: &RunLabels,
+) -> Result<(), UdpOptError> {
+    let _ = tx.send(ClientCommand::Start);
+    let report = client.run(sock)?;
+    if json {
+        let mut out = report_json(&report, overhead);
+        labels.add_to(&mut out);
+        println!("{:#}", out);
+    } else {
+        println!(
+            "Sent {} pkts | {} bytes | {:.2}s | {} | {:.0} pps | {} send failures",
+            report.packets_sent,
+            report.bytes_sent,
+            report.duration.as_secs_f64(),
+            ui::format_bitrate(report.bitrate_bps),
+            report.pps,
+            report.send_failures
+        );
+        println!(
+            "{}",
+            format_throughput("Sent", &report.throughput(overhead))
+        );
+        if report.send_retries > 0 {
+            println!("{} sends retried", report.send_retries);
+        }
+        if report.too_big > 0 {
+            println!(
+                "{} pkts refused as longer than the path MTU (don't-fragment set)",
+                report.too_big
+            );
+        }
+        if let Some(f) = report.fragmentation.filter(|f| f.fragmented > 0) {
+            println!(
+                "Path MTU {}: {} pkts fragmented, up to {} fragments each, {} IP packets sent",
+                f.path_mtu, f.fragmented, f.max_fragments, f.ip_packets
+            );
+        }
+        for episode in &report.unreachable {
+            println!(
+                "Peer unreachable at {:.3}s for {:.3}s, {} sends refused",
+                episode.start.as_secs_f64(),
+                episode.duration.as_secs_f64(),
+                episode.refusals
+            );
+        }
+        let compliance = &report.compliance;
+        if compliance.behind_intervals > 0 {
+            println!(
+                "Sender fell behind the target rate: {}/{} pkts sent, {} interval(s) behind, {:.3} ms drift",
+                compliance.achieved_packets,
+                compliance.intended_packets,
+                compliance.behind_intervals,
+                compliance.drift.as_secs_f64() * 1e3
+            );
+        }
+        // falling behind is reported above
+        if report.limit() == Some(SendLimit::Network) {
+            println!(
+                "Network limited: the full send buffer refused {} pkts",
+                report.would_block
+            );
+        }
+        if !report.interval_error.is_empty() {
+            let mut line = format!(
+                "Packet gap error: mean {:.3} ms | p99 {:.3} ms",
+                report.interval_error.mean() / 1e3,
+                report.interval_error.value_at_percentile(99.0) as f64 / 1e3
+            );
+            if let Some(calibration) = report.calibration {
+                line.push_str(&format!(
+                    " (host sleep overshoot {:.3} ms)",
+                    calibration.overshoot.as_secs_f64() * 1e3
+                ));
+            }
+            println!("{line}");
+        }
+        if let Some(public) = report.public_endpoint {
+            match sock.local_addr() {
+                Ok(local) if local != public => {
+                    println!("Public endpoint {} (translated from {})", public, local)
+                }
+                _ => println!("Public endpoint {}", public),
+            }
+        }
+        match &report.remote {
+            Some(remote) => {
+                ui::ReportRenderer::for_stdout().print(&[], remote);
+                println!(
+                    "{}",
+                    format_throughput("Received", &remote.throughput(overhead))
+                );
+            }
+            None => println!("The receiver did not report its results"),
+        }
+    }
+    match (report_file, &report.remote) {
+        (Some(file), Some(remote)) => file.write(remote, overhead)?,
+        (Some(_), None) => eprintln!("udpopt: no report written, the receiver sent no results"),
+        (None, _) => {}
+    }
+    Ok(())
+}
+
+/// The reverse-mode requests the server answers, `None` without `--allow-reverse`
+fn reverse_policy(args: &ServerArgs) -> Result<Option<ReversePolicy>, UdpOptError> {
+    if !args.allow_reverse {
+        return Ok(None);
+    }
+    if args.psk.is_none() && args.allow_tokens.is_empty() {
+        return Err(UdpOptError::InvalidConfig(
+            "--allow-reverse needs --psk or --allow-token".to_string(),
+        ));
+    }
+    let mut policy = ReversePolicy::new(ReverseLimits {
+        max_bitrate_bps: args.reverse_max_bitrate,
+        max_duration: args.reverse_max_duration,
+        max_payload_size: args.reverse_max_length,
+    });
+    if let Some(psk) = &args.psk {
+        policy = policy.auth(AuthKey::from_passphrase(psk));
+    }
+    if !args.allow_tokens.is_empty() {
+        policy = policy.access_control(AccessControl::allowlist(
+            args.allow_tokens.iter().map(String::as_str),
+        ));
+    }
+    Ok(Some(policy))
+}
+
+/// What a client asked the server for with its first datagram
+enum Request {
+    /// A test the client sends
+    Test,
+    /// A test the server sends, the request with its credentials
+    Reverse(Vec<u8>),
+    /// The rounds of a [`NatProbe`]
+    NatProbe,
+}
+
+/// Waits for the first datagram of a test without consuming it, unless it is a
+/// reverse request. Returns `None` when interrupted.
+fn wait_for_peer(
+    sock: &UdpSocket,
+    allow_sources: &[IpNet],
+    interrupt: &Interrupt,
+) -> Result<Option<(Request, SocketAddr)>, UdpOptError> {
+    let mut buf = [0u8; 2048];
+    sock.set_read_timeout(Some(Duration::from_millis(200)))
+        .map_err(|_| UdpOptError::SocketTimeout)?;
+    loop {
+        if interrupt.requested() {
+            return Ok(None);
+        }
+        match sock.peek_from(&mut buf) {
+            Ok((len, peer)) => {
+                if !source_allowed(allow_sources, peer.ip()) {
+                    sock.recv_from(&mut buf).map_err(UdpOptError::RecvFailed)?;
+                    continue;
+                }
+                let request = if ReverseRequest::is_request(&buf[..len]) {
+                    sock.recv_from(&mut buf).map_err(UdpOptError::RecvFailed)?;
+                    Request::Reverse(buf[..len].to_vec())
+                } else if nat::is_probe(&buf[..len]) {
+                    Request::NatProbe
+                } else {
+                    Request::Test
+                };
+                sock.set_read_timeout(None)
+                    .map_err(|_| UdpOptError::SocketTimeout)?;
+                return Ok(Some((request, peer)));
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(UdpOptError::RecvFailed(e)),
+        }
+    }
+}
+
+/// Turns Ctrl-C into a `Stop` command for the running test.
+#[derive(Default)]
+struct Interrupt {
+    requested: AtomicBool,
+    stop: Mutex<Option<Box<dyn Fn() + Send>>>,
+}
+
+impl Interrupt {
+    fn install() -> Result<Arc<Self>, ctrlc::Error> {
+        let interrupt = Arc::new(Self::default());
+        let handler = interrupt.clone();
+        ctrlc::set_handler(move || handler.trigger())?;
+        Ok(interrupt)
+    }
+
+    fn trigger(&self) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            // second Ctrl-C, give up on a clean stop
+            std::process::exit(130);
+        }
+        if let Some(stop) = self.stop.lock().unwrap().as_ref() {
+            stop();
+        }
+    }
+
+    fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Sends `cmd()` on `tx` on the next Ctrl-C (or right away if one already happened).
+    fn arm<C: Send + 'static>(&self, tx: mpsc::Sender<C>, cmd: fn() -> C) {
+        let stop = move || {
+            let _ = tx.send(cmd());
+        };
+        if self.requested() {
+            stop();
+        }
+        *self.stop.lock().unwrap() = Some(Box::new(stop));
+    }
+
+    fn disarm(&self) {
+        *self.stop.lock().unwrap() = None;
+    }
+}
+
+fn interval_json(r: &IntervalResult, overhead: Overhead) -> Value {
+    let throughput = r.throughput(overhead);
+    json!({
+        // Unix time, for lining the interval up with other logs
+        "start": r
+            .start_time
+            .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs_f64()),
+        "offset": r.offset.as_secs_f64(),
+        "seconds": r.time.as_secs_f64(),
+        "sent": r.sent,
+        "received": r.received,
+        "lost": r.lost,
+        "bytes": r.bytes,
+        "bitrate_bps": r.bitrate_bps,
+        "goodput_bps": throughput.goodput_bps,
+        "wire_bps": throughput.wire_bps,
+        "jitter_ms": r.jitter_ms,
+        "out_of_order": r.out_of_order,
+        "duplicates": r.duplicates,
+        "late": r.late,
+        "recovered": r.recovered,
+        "ce_marked": r.ce_marked,
+        "auth_failures": r.auth_failures,
+        "foreign": r.foreign,
+        "malformed": r.malformed,
+        "target_bitrate": r.target_bitrate,
+        "recommended_bitrate": r.recommended_bitrate,
+        "nic": r.nic.map(|nic| json!({
+            "rx_packets": nic.rx_packets,
+            "rx_dropped": nic.rx_dropped,
+            "rx_missed": nic.rx_missed,
+            "rx_fifo": nic.rx_fifo,
+            "rx_over": nic.rx_over,
+            "rx_errors": nic.rx_errors,
+            "drops": nic.drops(),
+        })),
+        "socket_drops": r.socket_drops,
+    })
+}
+
+fn summary_json(r: &TestResult) -> Value {
+    json!({
+        "seconds": r.total_time,
+        "packets": r.total_packets,
+        "lost": r.total_lost,
+        "socket_drops": r.total_socket_drops,
+        "path_lost": r.path_loss(),
+        "bytes": r.total_bytes,
+        "out_of_order": r.total_out_of_order,
+        "late": r.total_late,
+        "duplicates": r.total_duplicates,
+        "ooo_percent": r.ooo_percent,
+        "mean_bitrate": r.mean_bitrate,
+        "pps": r.pps(),
+        "median_bitrate": r.median_bitrate,
+        "mean_jitter_ms": r.mean_jitter,
+        "median_jitter_ms": r.median_jitter,
+        "bitrate": {
+            "min": r.min_bitrate,
+            "max": r.max_bitrate,
+            "std_dev": r.std_dev_bitrate,
+        },
+        "jitter_ms": {
+            "min": r.min_jitter,
+            "max": r.max_jitter,
+            "std_dev": r.std_dev_jitter,
+        },
+        "loss_percent": r.loss_percent,
+        "packets_sent": r.packets_sent,
+        "true_loss_percent": r.true_loss_percent(),
+        "percentiles": r.percentiles.iter().map(|p| json!({
+            "percentile": p.percentile,
+            "bitrate": p.bitrate,
+            "jitter_ms": p.jitter_ms,
+        })).collect::<Vec<_>>(),
+        "latency_ms": {
+            "p50": r.latency.p50_ms,
+            "p90": r.latency.p90_ms,
+            "p99": r.latency.p99_ms,
+            "p999": r.latency.p999_ms,
+        },
+        "ipdv_ms": {
+            "p50": r.ipdv.p50_ms,
+            "p90": r.ipdv.p90_ms,
+            "p99": r.ipdv.p99_ms,
+            "p999": r.ipdv.p999_ms,
+        },
+        "reorder": {
+            "reordered": r.reorder.reordered,
+            "percent": r.reorder.reordered_percent(),
+            "max_distance": r.reorder.max_distance,
+            "displacement": r.reorder.displacement,
+        },
+        "interarrival_us": {
+            "p50": r.interarrival_histogram().value_at_percentile(50.0),
+            "p99": r.interarrival_histogram().value_at_percentile(99.0),
+            "max": r.interarrival_histogram().max(),
+        },
+        "voip": {
+            "r_factor": r.voip_quality().r_factor,
+            "mos": r.voip_quality().mos,
+        },
+        "loss_pattern": {
+            "bursts": r.loss_pattern.bursts,
+            "max_burst": r.loss_pattern.max_burst,
+            "mean_burst": r.loss_pattern.mean_burst(),
+            "run_lengths": r.loss_pattern.run_lengths,
+            "gilbert_elliott": {
+                "p": r.loss_pattern.gilbert_elliott().p,
+                "r": r.loss_pattern.gilbert_elliott().r,
+            },
+        },
+        "sizes": (0..r.sizes.packets.len())
+            .filter(|&i| r.sizes.packets[i] > 0)
+            .map(|i| {
+                let (min, max) = SizeStats::bucket_range(i);
+                json!({
+                    "min_len": min,
+                    "max_len": max,
+                    "packets": r.sizes.packets[i],
+                    "bytes": r.sizes.bytes[i],
+                })
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// IP and UDP headers of the path to `peer` with `link` bytes of framing
+fn wire_overhead(peer: SocketAddr, link: usize) -> Overhead {
+    let overhead = if peer.ip().to_canonical().is_ipv6() {
+        Overhead::ipv6()
+    } else {
+        Overhead::ipv4()
+    };
+    overhead.link(link)
+}
+
+fn format_throughput(what: &str, t: &Throughput) -> String {
+    // the fragments' headers are in the mean when they were counted
+    format!(
+        "{} goodput {} | on-wire {} with {:.0} bytes of overhead per packet ({:.1} % efficient)",
+        what,
+        ui::format_bitrate(t.goodput_bps),
+        ui::format_bitrate(t.wire_bps),
+        t.overhead_per_packet(),
+        t.efficiency_percent()
+    )
+}
+
+fn overhead_json(overhead: Overhead) -> Value {
+    json!({
+        "header": overhead.header,
+        "ip": overhead.ip,
+        "link": overhead.link,
+    })
+}
+
+fn throughput_json(t: &Throughput) -> Value {
+    json!({
+        "goodput_bytes": t.goodput_bytes,
+        "payload_bytes": t.payload_bytes,
+        "wire_bytes": t.wire_bytes,
+        "goodput_bps": t.goodput_bps,
+        "wire_bps": t.wire_bps,
+    })
+}
+
+fn report_json(r: &ClientReport, overhead: Overhead) -> Value {
+    json!({
+        "throughput": {
+            "overhead": overhead_json(overhead),
+            "sent": throughput_json(&r.throughput(overhead)),
+            "received": r.remote.as_ref().map(|remote| throughput_json(&remote.throughput(overhead))),
+        },
+        "packets_sent": r.packets_sent,
+        "bytes_sent": r.bytes_sent,
+        "send_failures": r.send_failures,
+        "send_retries": r.send_retries,
+        "would_block": r.would_block,
+        "too_big": r.too_big,
+        "fragmentation": r.fragmentation.map(|f| json!({
+            "path_mtu": f.path_mtu,
+            "dont_fragment": f.dont_fragment,
+            "fragmented": f.fragmented,
+            "max_fragments": f.max_fragments,
+            "ip_packets": f.ip_packets,
+            "ip_bytes": f.ip_bytes,
+        })),
+        "unreachable": r
+            .unreachable
+            .iter()
+            .map(|e| {
+                json!({
+                    "start": e.start.as_secs_f64(),
+                    "seconds": e.duration.as_secs_f64(),
+                    "refusals": e.refusals,
+                })
+            })
+            .collect::<Vec<_>>(),
+        "limit": match r.limit() {
+            Some(SendLimit::Network) => "network",
+            Some(SendLimit::Sender) => "sender",
+            None => "none",
+        },
+        "seconds": r.duration.as_secs_f64(),
+        "bitrate_bps": r.bitrate_bps,
+        "pps": r.pps,
+        "remote": r.remote.as_ref().map(summary_json),
+        "public_endpoint": r.public_endpoint.map(|addr| addr.to_string()),
+        "compliance": {
+            "intended_packets": r.compliance.intended_packets,
+            "achieved_packets": r.compliance.achieved_packets,
+            "achieved_ratio": r.compliance.achieved_ratio(),
+            "drift_ms": r.compliance.drift.as_secs_f64() * 1e3,
+            "behind_intervals": r.compliance.behind_intervals,
+        },
+        "interval_error_us": {
+            "mean": r.interval_error.mean(),
+            "p99": r.interval_error.value_at_percentile(99.0),
+            "max": r.interval_error.max(),
+        },
+        "calibration": r.calibration.map(|c| json!({
+            "sleep_overshoot_us": c.overshoot.as_micros() as u64,
+            "sleep_threshold_us": c.threshold.as_micros() as u64,
+        })),
+        "intervals": r.intervals.iter().map(|i| json!({
+            "seconds": i.time.as_secs_f64(),
+            "packets_sent": i.packets_sent,
+            "intended_packets": i.intended_packets,
+            "bytes_sent": i.bytes_sent,
+            "bitrate_bps": i.bitrate_bps,
+            "pps": i.pps,
+            "drift_ms": i.drift.as_secs_f64() * 1e3,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+/// Parses a bitrate such as `500K`, `10M` or `1.5G` (decimal units).
+fn parse_bitrate(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let (num, mult) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1e3),
+        Some('M') => (&s[..s.len() - 1], 1e6),
+        Some('G') => (&s[..s.len() - 1], 1e9),
+        _ => (s, 1.0),
+    };
+    match num.parse::<f64>() {
+        Ok(v) if v > 0.0 => Ok(v * mult),
+        _ => Err(format!("invalid bitrate `{}`", s)),
+    }
+}
+
+/// Parses a `KEY=VALUE` tag, the key not empty.
+fn parse_tag(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("invalid tag `{}`, expected KEY=VALUE", s)),
+    }
+}
+
+/// Parses a positive number of seconds, fractions allowed.
+fn parse_sweep_size(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(size) if size <= SizeSweep::MAX_SIZE => Ok(size),
+        Ok(_) => Err(format!("longer than {} bytes", SizeSweep::MAX_SIZE)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_role(s: &str) -> Result<Role, String> {
+    match s {
+        "client" => Ok(Role::Client),
+        "server" => Ok(Role::Server),
+        _ => Err(format!("invalid role `{}`, client or server", s)),
+    }
+}
+
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    match s
+        .parse::<f64>()
+        .ok()
+        .and_then(|v| Duration::try_from_secs_f64(v).ok())
+    {
+        // shorter than a nanosecond is zero too
+        Some(d) if !d.is_zero() => Ok(d),
+        _ => Err(format!("invalid number of seconds `{}`", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bitrate() {
+        assert_eq!(parse_bitrate("1500").unwrap(), 1500.0);
+        assert_eq!(parse_bitrate("500k").unwrap(), 500_000.0);
+        assert_eq!(parse_bitrate("10M").unwrap(), 10_000_000.0);
+        assert_eq!(parse_bitrate("1.5G").unwrap(), 1_500_000_000.0);
+        assert!(parse_bitrate("fast").is_err());
+        assert!(parse_bitrate("0").is_err());
+    }
+
+    #[test]
+    fn test_parse_secs() {
+        assert_eq!(parse_secs("1.5").unwrap(), Duration::from_millis(1500));
+        for zero in ["0", "0.0000000001", "-1", "inf", "soon"] {
+            assert!(parse_secs(zero).is_err(), "{zero}");
+        }
+    }
+
+    #[test]
+    fn test_reverse_request_roundtrip() {
+        let req = ReverseRequest {
+            bitrate_bps: 2_000_000.0,
+            payload_size: 1200,
+            duration: Duration::from_millis(2500),
+            dscp: 46,
+            step_bps: 500_000.0,
+            step_every: Duration::from_millis(500),
+        };
+        assert_eq!(ReverseRequest::parse(&req.to_bytes()), Some(req));
+        // without the DSCP and the steps of newer clients
+        let constant = ReverseRequest {
+            step_bps: 0.0,
+            step_every: Duration::ZERO,
+            ..req
+        };
+        let legacy = ReverseRequest::parse(&req.to_bytes()[..29]).unwrap();
+        assert_eq!(legacy, constant);
+        let legacy = ReverseRequest::parse(&req.to_bytes()[..28]).unwrap();
+        assert_eq!(
+            legacy,
+            ReverseRequest {
+                dscp: 0,
+                ..constant
+            }
+        );
+        assert_eq!(ReverseRequest::parse(&req.to_bytes()[..30]), None);
+        assert_eq!(ReverseRequest::parse(&[0u8; ReverseRequest::SIZE]), None);
+        assert_eq!(ReverseRequest::parse(b"UDPOPTRV"), None);
+    }
+
+    #[test]
+    fn test_cli_parses() {
+        let cli = Cli::try_parse_from([
+            "udpopt",
+            "client",
+            "127.0.0.1:5201",
+            "-b",
+            "20M",
+            "-t",
+            "0.5",
+            "--reverse",
+            "--tag",
+            "site=ams1",
+            "--tag",
+            "ticket=NET-7=a",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Client(args) => {
+                assert_eq!(args.bitrate, 20_000_000.0);
+                assert_eq!(args.duration, Duration::from_millis(500));
+                assert!(args.reverse);
+                assert!(!args.json);
+                let params = args.labels.parameters(TestParameters::default());
+                assert_eq!(params.tags["ticket"], "NET-7=a");
+                assert_eq!(params.tags.len(), 2);
+            }
+            _ => panic!("expected the client subcommand"),
+        }
+        assert!(
+            Cli::try_parse_from(["udpopt", "server", "--tag", "=ams1"]).is_err(),
+            "a tag needs a key"
+        );
+    }
+}
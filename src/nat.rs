@@ -0,0 +1,752 @@
+//! NAT mapping probes.
+//!
+//! A NAT or stateful firewall forgets the mapping of a flow that stays
+//! silent for too long: the packets sent back to the old public address are
+//! dropped, and the next outgoing packet opens a new mapping, often on
+//! another public port. VoIP and game traffic must send keepalives more
+//! often than that, [`NatProbe`] measures how long the mapping lasts.
+//!
+//! Run after the main test on the same socket, so on the same 5-tuple, the
+//! probe goes through rounds of silence:
+//!
+//! 1. the client sends a probe announcing the next silence, the server
+//!    answers with the public address it saw;
+//! 2. the client stays silent; at the end of the silence the server sends a
+//!    ping to that address, it only arrives while the mapping lives;
+//! 3. the client resumes with the next probe, and the server tells whether
+//!    it came from the same public address.
+//!
+//! A round the mapping [`survived`](NatRound::survived) saw both the ping
+//! arrive and the address unchanged. The server side is [`respond`], which
+//! `udpopt server` runs when a probe arrives instead of a test.
+//!
+//! ```no_run
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//! use udpopt::nat::NatProbe;
+//!
+//! let sock = UdpSocket::bind("0.0.0.0:0").unwrap();
+//! sock.connect("192.0.2.10:5201").unwrap();
+//!
+//! let secs = [15, 30, 60, 120].map(Duration::from_secs);
+//! let report = NatProbe::new(secs).run(&sock).unwrap();
+//! for round in &report.rounds {
+//!     println!("{:?}: {}", round.idle, if round.survived() { "kept" } else { "lost" });
+//! }
+//! ```
+
+use std::{
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    errors::UdpOptError,
+    socket::DatagramSocket,
+    utils::{
+        net_utils::{is_transient_io_error, is_unreachable_error},
+        udp_data::new_session_id,
+    },
+};
+
+/// How often a probe is sent again until the server answers it
+const PROBE_RESEND: Duration = Duration::from_millis(250);
+
+/// Longest silence a round may announce, the server waits no longer
+pub const MAX_IDLE: Duration = Duration::from_secs(600);
+
+/// Longest grace a probe may announce
+pub const MAX_GRACE: Duration = Duration::from_secs(10);
+
+/// How often [`respond_until`] checks whether to stop
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+/// The public address the server saw a probe from, and whether it is the
+/// previous round's
+type Answer = (SocketAddr, Option<bool>);
+
+/// A NAT probe message.
+///
+/// Every message starts with the magic, its kind, the session and the round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Message {
+    /// Client to server: starts round `round`, the client then stays silent
+    /// for `idle` (0 after the last round) and resends within `grace`
+    Probe {
+        session: u32,
+        round: u32,
+        idle: Duration,
+        grace: Duration,
+    },
+    /// Server to client: the probe of `round` came from `seen`, the same
+    /// address as the previous round's or not
+    Ack {
+        session: u32,
+        round: u32,
+        seen: SocketAddr,
+        kept: Option<bool>,
+    },
+    /// Server to client: sent to the previous address at the end of the
+    /// silence before `round`
+    Ping { session: u32, round: u32 },
+}
+
+impl Message {
+    const MAGIC: &'static [u8; 8] = b"UDPOPTNT";
+    /// Magic, kind, session and round
+    const PREFIX: usize = 8 + 1 + 4 + 4;
+    const PROBE_SIZE: usize = Self::PREFIX + 8 + 8;
+    const ACK_SIZE: usize = Self::PREFIX + 1 + 16 + 2;
+    const PING_SIZE: usize = Self::PREFIX;
+    /// Longest message
+    const MAX_SIZE: usize = Self::ACK_SIZE;
+
+    fn session(&self) -> u32 {
+        match *self {
+            Self::Probe { session, .. }
+            | Self::Ack { session, .. }
+            | Self::Ping { session, .. } => session,
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let (kind, session, round) = match self {
+            Self::Probe { session, round, .. } => (0, session, round),
+            Self::Ack { session, round, .. } => (1, session, round),
+            Self::Ping { session, round } => (2, session, round),
+        };
+        let mut buf = Vec::with_capacity(Self::MAX_SIZE);
+        buf.extend_from_slice(Self::MAGIC);
+        buf.push(kind);
+        buf.extend_from_slice(&session.to_be_bytes());
+        buf.extend_from_slice(&round.to_be_bytes());
+        match self {
+            Self::Probe { idle, grace, .. } => {
+                buf.extend_from_slice(&(idle.as_millis() as u64).to_be_bytes());
+                buf.extend_from_slice(&(grace.as_millis() as u64).to_be_bytes());
+            }
+            Self::Ack { seen, kept, .. } => {
+                buf.push(match kept {
+                    Some(false) => 0,
+                    Some(true) => 1,
+                    None => 2,
+                });
+                let ip = match seen.ip() {
+                    IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                    IpAddr::V6(ip) => ip,
+                };
+                buf.extend_from_slice(&ip.octets());
+                buf.extend_from_slice(&seen.port().to_be_bytes());
+            }
+            Self::Ping { .. } => {}
+        }
+        buf
+    }
+
+    /// Decodes a message, `None` if `buf` is not one
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < Self::PREFIX || &buf[0..8] != Self::MAGIC {
+            return None;
+        }
+        let session = u32::from_be_bytes(buf[9..13].try_into().ok()?);
+        let round = u32::from_be_bytes(buf[13..17].try_into().ok()?);
+        let millis = |at: usize| {
+            Duration::from_millis(u64::from_be_bytes(buf[at..at + 8].try_into().unwrap()))
+        };
+        match (buf[8], buf.len()) {
+            (0, Self::PROBE_SIZE) => Some(Self::Probe {
+                session,
+                round,
+                idle: millis(17),
+                grace: millis(25),
+            }),
+            (1, Self::ACK_SIZE) => {
+                let kept = match buf[17] {
+                    0 => Some(false),
+                    1 => Some(true),
+                    _ => None,
+                };
+                let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&buf[18..34]).ok()?);
+                let ip = ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4);
+                let port = u16::from_be_bytes(buf[34..36].try_into().ok()?);
+                Some(Self::Ack {
+                    session,
+                    round,
+                    seen: SocketAddr::new(ip, port),
+                    kept,
+                })
+            }
+            (2, Self::PING_SIZE) => Some(Self::Ping { session, round }),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `buf` is a NAT probe, for a server telling it from a test.
+pub fn is_probe(buf: &[u8]) -> bool {
+    matches!(Message::parse(buf), Some(Message::Probe { .. }))
+}
+
+/// Whether a receive error only means nothing arrived in time; an ICMP
+/// unreachable is not final either, the server may be restarting
+fn nothing_yet(e: &io::Error) -> bool {
+    is_transient_io_error(e) || is_unreachable_error(e)
+}
+
+/// Measures how long a NAT keeps the mapping of a silent flow.
+#[derive(Debug, Clone)]
+pub struct NatProbe {
+    idles: Vec<Duration>,
+    grace: Duration,
+}
+
+impl NatProbe {
+    /// Probes the silences `idles`, in order, with a grace of 2 seconds.
+    ///
+    /// # Panics
+    /// If `idles` is empty or holds a silence under a millisecond or over
+    /// [`MAX_IDLE`].
+    pub fn new(idles: impl IntoIterator<Item = Duration>) -> Self {
+        let idles: Vec<_> = idles.into_iter().collect();
+        assert!(
+            !idles.is_empty() && idles.iter().all(|idle| idle.as_millis() > 0),
+            "a NAT probe needs silences of at least a millisecond"
+        );
+        assert!(
+            idles.iter().all(|idle| *idle <= MAX_IDLE),
+            "a NAT probe silence lasts at most {MAX_IDLE:?}"
+        );
+        Self {
+            idles,
+            grace: Duration::from_secs(2),
+        }
+    }
+
+    /// How long past a silence the client still waits for the server's ping
+    /// (default 2 seconds); also how long it waits for the answer to a probe.
+    ///
+    /// # Panics
+    /// If `grace` is over [`MAX_GRACE`].
+    pub fn grace(mut self, grace: Duration) -> Self {
+        assert!(
+            grace <= MAX_GRACE,
+            "a NAT probe grace is at most {MAX_GRACE:?}"
+        );
+        self.grace = grace;
+        self
+    }
+
+    /// Runs the rounds on the connected `sock`, which must be the socket of
+    /// the flow whose mapping is measured. Takes the sum of the silences and
+    /// a little more. The read timeout of `sock` is restored afterwards.
+    ///
+    /// # Errors
+    /// - [`UdpOptError::Timeout`] if the server never answered the first probe.
+    /// - [`UdpOptError::SendFailed`] or [`UdpOptError::RecvFailed`].
+    pub fn run<S: DatagramSocket>(&self, sock: &S) -> Result<NatReport, UdpOptError> {
+        let saved = sock
+            .read_timeout()
+            .map_err(|_| UdpOptError::SocketTimeout)?;
+        let report = self.rounds(sock, new_session_id());
+        sock.set_read_timeout(saved)
+            .map_err(|_| UdpOptError::SocketTimeout)?;
+        report
+    }
+
+    fn rounds<S: DatagramSocket>(&self, sock: &S, session: u32) -> Result<NatReport, UdpOptError> {
+        let probe = |round: usize| Message::Probe {
+            session,
+            round: round as u32,
+            idle: self.idles.get(round).copied().unwrap_or_default(),
+            grace: self.grace,
+        };
+        let (first, mut last_sent) = self.exchange(sock, probe(0))?;
+        let Some((public_addr, _)) = first else {
+            return Err(UdpOptError::Timeout(self.grace));
+        };
+        event!(info, %public_addr, "NAT probe answered");
+
+        let mut buf = [0u8; Message::MAX_SIZE];
+        let mut rounds = Vec::with_capacity(self.idles.len());
+        for (index, &idle) in self.idles.iter().enumerate() {
+            let round = index as u32 + 1;
+            // silent until the ping, or until it is overdue
+            let deadline = last_sent + idle + self.grace;
+            let mut inbound = false;
+            while let Some(message) = recv_until(sock, session, deadline, &mut buf)? {
+                if message == (Message::Ping { session, round }) {
+                    inbound = true;
+                    break;
+                }
+            }
+            let silent = last_sent.elapsed();
+            let (answer, sent) = self.exchange(sock, probe(index + 1))?;
+            last_sent = sent;
+            event!(
+                info,
+                ?idle,
+                inbound,
+                answered = answer.is_some(),
+                "NAT probe round"
+            );
+            rounds.push(NatRound {
+                idle,
+                silent,
+                inbound,
+                answered: answer.is_some(),
+                mapping_kept: answer.and_then(|(_, kept)| kept),
+                public_addr: answer.map(|(seen, _)| seen),
+            });
+        }
+        Ok(NatReport {
+            public_addr,
+            rounds,
+        })
+    }
+
+    /// Sends `probe` until the server answers it or the grace runs out,
+    /// returning the answer and when the probe was last sent
+    fn exchange<S: DatagramSocket>(
+        &self,
+        sock: &S,
+        probe: Message,
+    ) -> Result<(Option<Answer>, Instant), UdpOptError> {
+        let Message::Probe { session, round, .. } = probe else {
+            unreachable!("only probes are exchanged");
+        };
+        let bytes = probe.to_bytes();
+        let peer = sock.peer_addr().ok();
+        let give_up = Instant::now() + self.grace;
+        let mut buf = [0u8; Message::MAX_SIZE];
+        loop {
+            let sent = Instant::now();
+            match sock.send(&bytes) {
+                Ok(_) => {}
+                // refused while the server restarts, like a lost probe
+                Err(e) if nothing_yet(&e) => {}
+                Err(e) => return Err(UdpOptError::send_failed(peer, bytes.len())(e)),
+            }
+            let resend = (sent + PROBE_RESEND).min(give_up);
+            while let Some(message) = recv_until(sock, session, resend, &mut buf)? {
+                if let Message::Ack {
+                    round: acked,
+                    seen,
+                    kept,
+                    ..
+                } = message
+                    && acked == round
+                {
+                    return Ok((Some((seen, kept)), sent));
+                }
+            }
+            if Instant::now() >= give_up {
+                return Ok((None, sent));
+            }
+        }
+    }
+}
+
+/// Receives the next message of `session` on the connected `sock`, `None`
+/// once `deadline` passed
+fn recv_until<S: DatagramSocket>(
+    sock: &S,
+    session: u32,
+    deadline: Instant,
+    buf: &mut [u8],
+) -> Result<Option<Message>, UdpOptError> {
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(None);
+        }
+        sock.set_read_timeout(Some(left))
+            .map_err(|_| UdpOptError::SocketTimeout)?;
+        match sock.recv(buf) {
+            Ok(len) => match Message::parse(&buf[..len]) {
+                Some(message) if message.session() == session => return Ok(Some(message)),
+                _ => {}
+            },
+            Err(e) if nothing_yet(&e) => {}
+            Err(e) => return Err(UdpOptError::RecvFailed(e)),
+        }
+    }
+}
+
+/// One silence of a [`NatProbe`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NatRound {
+    /// Planned silence.
+    pub idle: Duration,
+    /// Actual silence, from the probe before it to the probe after it; it
+    /// runs past `idle` by the grace when the ping did not arrive.
+    pub silent: Duration,
+    /// Whether the server's ping, sent to the public address at the end of
+    /// the silence, arrived: the mapping still let packets in.
+    pub inbound: bool,
+    /// Whether the server answered the probe resuming the flow.
+    pub answered: bool,
+    /// Whether that probe came from the same public address as before, `None`
+    /// if unanswered. A new port means the NAT opened a new mapping.
+    pub mapping_kept: Option<bool>,
+    /// Public address the server saw after the silence.
+    pub public_addr: Option<SocketAddr>,
+}
+
+impl NatRound {
+    /// Whether the mapping outlasted the silence: the ping arrived and the
+    /// public address did not change.
+    pub fn survived(&self) -> bool {
+        self.inbound && self.mapping_kept != Some(false)
+    }
+}
+
+/// Outcome of a [`NatProbe`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NatReport {
+    /// Public address the server saw before the first silence.
+    pub public_addr: SocketAddr,
+    /// Every round, in the order they ran.
+    pub rounds: Vec<NatRound>,
+}
+
+impl NatReport {
+    /// Longest silence the mapping survived, `None` if it survived none.
+    pub fn longest_survived(&self) -> Option<Duration> {
+        self.rounds
+            .iter()
+            .filter(|round| round.survived())
+            .map(|round| round.idle)
+            .max()
+    }
+
+    /// Shortest silence the mapping did not survive, `None` if it survived
+    /// them all: the mapping timeout lies between the two.
+    pub fn shortest_lost(&self) -> Option<Duration> {
+        self.rounds
+            .iter()
+            .filter(|round| !round.survived())
+            .map(|round| round.idle)
+            .min()
+    }
+}
+
+/// A probe the server answered, see [`respond`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SeenProbe {
+    /// Round of the probe, 0 before the first silence.
+    pub round: u32,
+    /// Address it came from.
+    pub from: SocketAddr,
+    /// Whether that is the address of the previous round, `None` for the first.
+    pub mapping_kept: Option<bool>,
+    /// Silence announced after it, zero after the last round.
+    pub idle: Duration,
+}
+
+/// Answers the probes of one [`NatProbe`] on the unconnected `sock`, and
+/// pings the client at the end of every silence, until the last round or
+/// until the client stays silent for longer than it announced.
+///
+/// Waits up to `first_wait` for the first probe; datagrams that are not
+/// probes, and the probes of other sessions, are ignored. The silences and
+/// graces the probes announce are capped at [`MAX_IDLE`] and [`MAX_GRACE`].
+///
+/// Returns the probes answered, in order, empty if none arrived.
+///
+/// # Errors
+/// [`UdpOptError::SendFailed`] or [`UdpOptError::RecvFailed`].
+pub fn respond<S: DatagramSocket>(
+    sock: &S,
+    first_wait: Duration,
+) -> Result<Vec<SeenProbe>, UdpOptError> {
+    respond_until(sock, first_wait, || false)
+}
+
+/// [`respond`], giving up with the probes answered so far as soon as `stop`
+/// returns `true`, checked at least every 200 ms: on Ctrl-C or a command to
+/// stop.
+///
+/// # Errors
+/// [`UdpOptError::SendFailed`] or [`UdpOptError::RecvFailed`].
+pub fn respond_until<S: DatagramSocket>(
+    sock: &S,
+    first_wait: Duration,
+    mut stop: impl FnMut() -> bool,
+) -> Result<Vec<SeenProbe>, UdpOptError> {
+    let mut buf = [0u8; Message::MAX_SIZE];
+    let mut seen: Vec<SeenProbe> = Vec::new();
+    let mut session = None;
+    let mut deadline = Instant::now() + first_wait;
+    // when and where the next ping goes
+    let mut ping: Option<(Instant, SocketAddr, Message)> = None;
+    loop {
+        let now = Instant::now();
+        if let Some((at, to, message)) = ping
+            && now >= at
+        {
+            let bytes = message.to_bytes();
+            // a ping that cannot leave is a lost one
+            let _ = sock.send_to(&bytes, to);
+            ping = None;
+            continue;
+        }
+        if now >= deadline || stop() {
+            return Ok(seen);
+        }
+        let wake = ping
+            .map_or(deadline, |(at, ..)| at.min(deadline))
+            .min(now + STOP_POLL);
+        sock.set_read_timeout(Some(wake - now))
+            .map_err(|_| UdpOptError::SocketTimeout)?;
+        let (len, from) = match sock.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if nothing_yet(&e) => continue,
+            Err(e) => return Err(UdpOptError::RecvFailed(e)),
+        };
+        let Some(Message::Probe {
+            session: id,
+            round,
+            idle,
+            grace,
+        }) = Message::parse(&buf[..len])
+        else {
+            continue;
+        };
+        // the probe is not authenticated, it cannot hold the server for long
+        let (idle, grace) = (idle.min(MAX_IDLE), grace.min(MAX_GRACE));
+        if *session.get_or_insert(id) != id {
+            continue;
+        }
+        let previous = seen.last().copied();
+        // a probe sent again since the answer was lost
+        let repeated = previous.is_some_and(|p| p.round == round);
+        let kept = match previous {
+            Some(p) if repeated => p.mapping_kept,
+            Some(p) => Some(p.from == from),
+            None => None,
+        };
+        let ack = Message::Ack {
+            session: id,
+            round,
+            seen: from,
+            kept,
+        }
+        .to_bytes();
+        sock.send_to(&ack, from)
+            .map_err(UdpOptError::send_failed(Some(from), ack.len()))?;
+        if repeated {
+            continue;
+        }
+        seen.push(SeenProbe {
+            round,
+            from,
+            mapping_kept: kept,
+            idle,
+        });
+        event!(info, round, %from, ?kept, "NAT probe");
+        if idle.is_zero() {
+            // the client is done, answers to a lost last ack are not needed
+            return Ok(seen);
+        }
+        let now = Instant::now();
+        ping = Some((
+            now + idle,
+            from,
+            Message::Ping {
+                session: id,
+                round: round + 1,
+            },
+        ));
+        // the client resends within its grace past the ping
+        deadline = now + idle + grace * 2 + PROBE_RESEND;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::thread;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let messages = [
+            Message::Probe {
+                session: 7,
+                round: 2,
+                idle: Duration::from_secs(30),
+                grace: Duration::from_millis(1500),
+            },
+            Message::Ack {
+                session: 7,
+                round: 2,
+                seen: "203.0.113.5:40001".parse().unwrap(),
+                kept: Some(false),
+            },
+            Message::Ack {
+                session: 7,
+                round: 0,
+                seen: "[2001:db8::1]:5000".parse().unwrap(),
+                kept: None,
+            },
+            Message::Ping {
+                session: 7,
+                round: 3,
+            },
+        ];
+        for message in messages {
+            assert_eq!(Message::parse(&message.to_bytes()), Some(message));
+        }
+        assert!(is_probe(&messages[0].to_bytes()));
+        assert!(!is_probe(&messages[3].to_bytes()));
+        assert!(Message::parse(&messages[0].to_bytes()[..20]).is_none());
+        assert!(Message::parse(b"UDPOPTRV and more bytes").is_none());
+    }
+
+    /// A NAT in front of the client: it opens a new public port when the
+    /// client sends after `timeout` without outgoing packets, and drops what
+    /// comes back to a port silent for that long. Returns the address the
+    /// client sends to.
+    fn nat(server: SocketAddr, timeout: Duration, lifetime: Duration) -> SocketAddr {
+        let inside = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let inside_addr = inside.local_addr().unwrap();
+        inside.set_nonblocking(true).unwrap();
+        thread::spawn(move || {
+            let end = Instant::now() + lifetime;
+            let mut mapping: Option<(UdpSocket, Instant, SocketAddr)> = None;
+            let mut buf = [0u8; 256];
+            while Instant::now() < end {
+                if let Ok((len, client)) = inside.recv_from(&mut buf) {
+                    if mapping.as_ref().is_none_or(|m| m.1.elapsed() > timeout) {
+                        let outside = UdpSocket::bind("127.0.0.1:0").unwrap();
+                        outside.set_nonblocking(true).unwrap();
+                        mapping = Some((outside, Instant::now(), client));
+                    }
+                    let (outside, last_out, _) = mapping.as_mut().unwrap();
+                    outside.send_to(&buf[..len], server).unwrap();
+                    *last_out = Instant::now();
+                }
+                if let Some((outside, last_out, client)) = &mapping
+                    && let Ok(len) = outside.recv(&mut buf)
+                    && last_out.elapsed() <= timeout
+                {
+                    inside.send_to(&buf[..len], *client).unwrap();
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        inside_addr
+    }
+
+    #[test]
+    fn test_probe_through_a_nat() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let responder = thread::spawn(move || respond(&server, Duration::from_secs(2)).unwrap());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .connect(nat(
+                server_addr,
+                Duration::from_millis(200),
+                Duration::from_secs(5),
+            ))
+            .unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(7)))
+            .unwrap();
+        let idles = [50, 500].map(Duration::from_millis);
+        let report = NatProbe::new(idles)
+            .grace(Duration::from_millis(300))
+            .run(&client)
+            .unwrap();
+        let seen = responder.join().unwrap();
+
+        let (short, long) = (&report.rounds[0], &report.rounds[1]);
+        assert!(short.survived(), "{short:?}");
+        assert_eq!(short.public_addr, Some(report.public_addr));
+        assert!(short.silent >= Duration::from_millis(50));
+        // past the NAT's timeout the ping is dropped and a new port opens
+        assert!(!long.inbound && long.answered, "{long:?}");
+        assert_eq!(long.mapping_kept, Some(false));
+        assert_ne!(long.public_addr, Some(report.public_addr));
+        assert!(long.silent >= Duration::from_millis(800));
+        assert_eq!(report.longest_survived(), Some(idles[0]));
+        assert_eq!(report.shortest_lost(), Some(idles[1]));
+
+        assert_eq!(
+            seen.iter()
+                .map(|probe| probe.mapping_kept)
+                .collect::<Vec<_>>(),
+            [None, Some(true), Some(false)]
+        );
+        assert_eq!(seen[2].idle, Duration::ZERO);
+        assert_eq!(client.read_timeout().unwrap(), Some(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn test_respond_caps_and_stops() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let stopped = stop.clone();
+        let responder = thread::spawn(move || {
+            respond_until(&server, Duration::from_secs(2), || {
+                stopped.load(std::sync::atomic::Ordering::SeqCst)
+            })
+            .unwrap()
+        });
+
+        // a silence of 30 years
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let forever = Duration::from_millis(1_000_000_000_000);
+        let probe = Message::Probe {
+            session: 9,
+            round: 0,
+            idle: forever,
+            grace: forever,
+        };
+        client.send_to(&probe.to_bytes(), server_addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut buf = [0u8; Message::MAX_SIZE];
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert!(matches!(
+            Message::parse(&buf[..len]),
+            Some(Message::Ack { round: 0, .. })
+        ));
+
+        let asked = Instant::now();
+        stop.store(true, std::sync::atomic::Ordering::SeqCst);
+        let seen = responder.join().unwrap();
+        assert!(asked.elapsed() < Duration::from_secs(1));
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].idle, MAX_IDLE);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most")]
+    fn test_probe_silence_limit() {
+        NatProbe::new([MAX_IDLE + Duration::from_millis(1)]);
+    }
+
+    #[test]
+    fn test_probe_without_server() {
+        // nothing answers on the port of a closed socket
+        let gone = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = gone.local_addr().unwrap();
+        drop(gone);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(addr).unwrap();
+        let err = NatProbe::new([Duration::from_millis(10)])
+            .grace(Duration::from_millis(100))
+            .run(&client)
+            .unwrap_err();
+        assert!(matches!(err, UdpOptError::Timeout(_)), "{err:?}");
+    }
+}
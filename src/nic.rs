@@ -0,0 +1,239 @@
+//! Interface counters next to the interval results (Linux).
+//!
+//! A loss the server counts happened somewhere between the client's socket
+//! and its own: on the path, or on the receiving host, dropped by the NIC
+//! for lack of ring buffers or by the kernel with a full backlog. A
+//! [`NicSampler`] reads `/sys/class/net/<interface>/statistics` when an
+//! interval closes and attaches what the interface dropped meanwhile to
+//! [`IntervalResult::nic`](crate::IntervalResult::nic): loss with NIC drops
+//! to match is the host's, not the network's.
+//!
+//! The counters cover all the traffic of the interface, not only the test.
+//! Build the server with [`crate::ServerBuilder::nic_counters`]:
+//!
+//! ```no_run
+//! use std::net::UdpSocket;
+//! use std::sync::mpsc;
+//! use std::time::Duration;
+//! use udpopt::{ServerBuilder, ServerCommand};
+//!
+//! let mut sock = UdpSocket::bind("0.0.0.0:5201").unwrap();
+//! let (tx, rx) = mpsc::channel();
+//! let mut server = ServerBuilder::new(Duration::from_secs(1))
+//!     .nic_counters("eth0")
+//!     .build(rx);
+//! tx.send(ServerCommand::Start).unwrap();
+//! for interval in server.run(&mut sock).unwrap() {
+//!     if let Some(nic) = interval.nic {
+//!         println!("{} lost, {} dropped by eth0", interval.lost, nic.drops());
+//!     }
+//! }
+//! ```
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::socket::Interface;
+
+/// Where Linux lists the network interfaces
+const SYS_CLASS_NET: &str = "/sys/class/net";
+
+/// Receive counters of a network interface, over an interval when they
+/// come from [`NicSampler::sample`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NicCounters {
+    /// Packets received (`rx_packets`).
+    pub rx_packets: u64,
+    /// Packets received and dropped by the kernel, e.g. with a full
+    /// backlog (`rx_dropped`).
+    pub rx_dropped: u64,
+    /// Packets the NIC missed for lack of receive buffers (`rx_missed_errors`).
+    pub rx_missed: u64,
+    /// Receive FIFO overruns (`rx_fifo_errors`).
+    pub rx_fifo: u64,
+    /// Receive ring overruns (`rx_over_errors`).
+    pub rx_over: u64,
+    /// Bad packets received: CRC, length and frame errors included (`rx_errors`).
+    pub rx_errors: u64,
+}
+
+impl NicCounters {
+    /// Packets the receiving host lost before any socket saw them: dropped,
+    /// missed and overrun.
+    pub fn drops(&self) -> u64 {
+        self.rx_dropped + self.rx_missed + self.rx_fifo + self.rx_over
+    }
+
+    /// Reads the counters in the `statistics` directory `dir`, a counter
+    /// the driver does not keep reads 0
+    fn read(dir: &Path) -> io::Result<Self> {
+        let counter = |name: &str| match fs::read_to_string(dir.join(name)) {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, name.to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        };
+        // the directory itself must be there
+        fs::metadata(dir)?;
+        Ok(Self {
+            rx_packets: counter("rx_packets")?,
+            rx_dropped: counter("rx_dropped")?,
+            rx_missed: counter("rx_missed_errors")?,
+            rx_fifo: counter("rx_fifo_errors")?,
+            rx_over: counter("rx_over_errors")?,
+            rx_errors: counter("rx_errors")?,
+        })
+    }
+
+    /// Counts from `before` to `self`, 0 for a counter that went back
+    fn since(&self, before: &Self) -> Self {
+        Self {
+            rx_packets: self.rx_packets.saturating_sub(before.rx_packets),
+            rx_dropped: self.rx_dropped.saturating_sub(before.rx_dropped),
+            rx_missed: self.rx_missed.saturating_sub(before.rx_missed),
+            rx_fifo: self.rx_fifo.saturating_sub(before.rx_fifo),
+            rx_over: self.rx_over.saturating_sub(before.rx_over),
+            rx_errors: self.rx_errors.saturating_sub(before.rx_errors),
+        }
+    }
+}
+
+/// Reads the counters of one interface and tells how much they grew since
+/// the last reading.
+#[derive(Debug, Clone)]
+pub struct NicSampler {
+    /// The `statistics` directory of the interface
+    dir: PathBuf,
+    /// Counters at the last reading
+    last: NicCounters,
+}
+
+impl NicSampler {
+    /// Samples `interface`, a name like `"eth0"` or an index, taking its
+    /// counters now as the baseline.
+    ///
+    /// # Errors
+    /// If the interface does not exist, or elsewhere than on Linux
+    /// [`io::ErrorKind::NotFound`] since there is no `/sys/class/net`.
+    pub fn new(interface: &Interface) -> io::Result<Self> {
+        Self::under(Path::new(SYS_CLASS_NET), interface)
+    }
+
+    /// Samples `interface` among the interfaces listed in `root`
+    fn under(root: &Path, interface: &Interface) -> io::Result<Self> {
+        let dir = match interface {
+            Interface::Name(name) => root.join(name),
+            Interface::Index(index) => find_index(root, *index)?,
+        }
+        .join("statistics");
+        let last = NicCounters::read(&dir)?;
+        Ok(Self { dir, last })
+    }
+
+    /// Takes the counters now as the baseline of the next sample.
+    ///
+    /// # Errors
+    /// If the counters cannot be read, e.g. the interface is gone.
+    pub fn restart(&mut self) -> io::Result<()> {
+        self.last = NicCounters::read(&self.dir)?;
+        Ok(())
+    }
+
+    /// What the counters grew by since the last sample or restart.
+    ///
+    /// # Errors
+    /// If the counters cannot be read, e.g. the interface is gone.
+    pub fn sample(&mut self) -> io::Result<NicCounters> {
+        let now = NicCounters::read(&self.dir)?;
+        let grown = now.since(&self.last);
+        self.last = now;
+        Ok(grown)
+    }
+}
+
+/// Directory of the interface with `index` in `root`
+fn find_index(root: &Path, index: u32) -> io::Result<PathBuf> {
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        let found = fs::read_to_string(path.join("ifindex"))
+            .is_ok_and(|value| value.trim().parse() == Ok(index));
+        if found {
+            return Ok(path);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no interface with index {index}"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `/sys/class/net` with the interface `eth9` of index 9
+    fn fake_sys(tag: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("udpopt-nic-{tag}-{}", std::process::id()));
+        let stats = root.join("eth9").join("statistics");
+        fs::create_dir_all(&stats).unwrap();
+        fs::write(root.join("eth9").join("ifindex"), "9\n").unwrap();
+        set(&root, "rx_packets", 100);
+        set(&root, "rx_dropped", 2);
+        set(&root, "rx_missed_errors", 1);
+        root
+    }
+
+    fn set(root: &Path, counter: &str, value: u64) {
+        let path = root.join("eth9").join("statistics").join(counter);
+        fs::write(path, format!("{value}\n")).unwrap();
+    }
+
+    #[test]
+    fn test_sample_counts_the_growth() {
+        let root = fake_sys("sample");
+        let mut sampler = NicSampler::under(&root, &Interface::from("eth9")).unwrap();
+        set(&root, "rx_packets", 1100);
+        set(&root, "rx_dropped", 12);
+        set(&root, "rx_fifo_errors", 3);
+        let grown = sampler.sample().unwrap();
+        assert_eq!(
+            grown,
+            NicCounters {
+                rx_packets: 1000,
+                rx_dropped: 10,
+                rx_fifo: 3,
+                ..Default::default()
+            }
+        );
+        assert_eq!(grown.drops(), 13);
+        assert_eq!(sampler.sample().unwrap(), NicCounters::default());
+
+        // by index, and a counter reset by a driver reload
+        let mut sampler = NicSampler::under(&root, &Interface::Index(9)).unwrap();
+        set(&root, "rx_packets", 5);
+        assert_eq!(sampler.sample().unwrap().rx_packets, 0);
+        set(&root, "rx_packets", 50);
+        sampler.restart().unwrap();
+        set(&root, "rx_packets", 80);
+        assert_eq!(sampler.sample().unwrap().rx_packets, 30);
+
+        assert!(NicSampler::under(&root, &Interface::from("eth8")).is_err());
+        assert!(NicSampler::under(&root, &Interface::Index(8)).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_loopback() {
+        let Ok(mut sampler) = NicSampler::new(&Interface::from("lo")) else {
+            // no sysfs in this sandbox
+            return;
+        };
+        assert!(sampler.sample().is_ok());
+    }
+}
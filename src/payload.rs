@@ -0,0 +1,354 @@
+//! # Payload generators
+//!
+//! What the client puts after the header of every data packet. Links with
+//! compression or WAN optimizers carry compressible payloads much faster than
+//! incompressible ones, so the payload is chosen with
+//! `ClientBuilder::payload` from:
+//!
+//! - [`FastRandom`], incompressible, the default,
+//! - [`Random`], incompressible too, straight from the OS random source,
+//! - [`Zeroes`], compressible as much as possible,
+//! - [`Incrementing`], the bytes 0, 1, 2, ... 255, 0, ...,
+//! - [`UserProvided`], a pattern repeated over the payload,
+//! - [`FromFile`], the content of a file, e.g. a representative capture,
+//!
+//! or any other [`PayloadSource`].
+//!
+//! The packet length is fixed unless a [`SizeMix`] is given to
+//! `ClientBuilder::packet_sizes`, e.g. [`SizeMix::imix`].
+//!
+//! ```
+//! use std::time::Duration;
+//! use udpopt::ClientBuilder;
+//! use udpopt::payload::UserProvided;
+//!
+//! let builder = ClientBuilder::new(10e6, 1200, Duration::from_secs(10))
+//!     .payload(UserProvided::new(b"GET /index.html HTTP/1.1\r\n".to_vec()));
+//! ```
+
+use std::{io, path::Path};
+
+use crate::utils::{random_utils::RandomToSend, udp_data::HEADER_SIZE};
+
+/// Fills the payload of the data packets.
+///
+/// `fill` gets the whole packet buffer; the header is written over its start
+/// afterwards.
+pub trait PayloadSource: Send {
+    /// Fills `buf` with the next packet's payload.
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// Random bytes from the OS, a new draw for every packet.
+///
+/// `Random::default()` opens the OS source on the first packet instead.
+#[derive(Default)]
+pub struct Random(Option<RandomToSend>);
+
+impl Random {
+    /// Opens the OS random source.
+    ///
+    /// # Errors
+    /// The error opening `/dev/urandom` on Unix.
+    pub fn new() -> io::Result<Self> {
+        RandomToSend::new().map(|r| Self(Some(r)))
+    }
+}
+
+impl PayloadSource for Random {
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let random = match &mut self.0 {
+            Some(random) => random,
+            None => self.0.insert(RandomToSend::new()?),
+        };
+        random.fill(buf)
+    }
+}
+
+impl std::fmt::Debug for Random {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Random")
+    }
+}
+
+/// Pseudo-random bytes from an in-process wyrand generator, seeded once
+/// from the OS random source.
+///
+/// As incompressible as [`Random`] without a syscall per packet, so it keeps
+/// up with high packet rates. Not suitable for anything cryptographic.
+/// `FastRandom::default()` seeds on the first packet instead.
+#[derive(Debug, Clone, Default)]
+pub struct FastRandom {
+    state: Option<u64>,
+}
+
+impl FastRandom {
+    /// Seeds from the OS random source.
+    ///
+    /// # Errors
+    /// The error opening or reading `/dev/urandom` on Unix.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self::with_seed(os_seed()?))
+    }
+
+    /// Seeds with `seed`, for payloads that are the same on every run.
+    pub fn with_seed(seed: u64) -> Self {
+        Self { state: Some(seed) }
+    }
+}
+
+fn os_seed() -> io::Result<u64> {
+    let mut seed = [0u8; 8];
+    RandomToSend::new()?.fill(&mut seed)?;
+    Ok(u64::from_le_bytes(seed))
+}
+
+/// One wyrand step
+fn wyrand(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0xa076_1d64_78bd_642f);
+    let t = u128::from(*state) * u128::from(*state ^ 0xe703_7ed1_a0b4_28db);
+    ((t >> 64) as u64) ^ (t as u64)
+}
+
+impl PayloadSource for FastRandom {
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let state = match &mut self.state {
+            Some(state) => state,
+            None => self.state.insert(os_seed()?),
+        };
+        for chunk in buf.chunks_mut(8) {
+            chunk.copy_from_slice(&wyrand(state).to_le_bytes()[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+/// All zero bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Zeroes;
+
+impl PayloadSource for Zeroes {
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        buf.fill(0);
+        Ok(())
+    }
+}
+
+/// The bytes 0, 1, 2, ... wrapping after 255, from the start of every packet.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Incrementing;
+
+impl PayloadSource for Incrementing {
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        Ok(())
+    }
+}
+
+/// A byte pattern repeated over every packet.
+#[derive(Debug, Clone)]
+pub struct UserProvided {
+    pattern: Vec<u8>,
+}
+
+impl UserProvided {
+    /// Repeats `pattern`; an empty pattern gives zero bytes.
+    pub fn new(pattern: Vec<u8>) -> Self {
+        Self { pattern }
+    }
+}
+
+impl PayloadSource for UserProvided {
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if self.pattern.is_empty() {
+            buf.fill(0);
+            return Ok(());
+        }
+        for chunk in buf.chunks_mut(self.pattern.len()) {
+            chunk.copy_from_slice(&self.pattern[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+/// The content of a file, read once and streamed over the packets, starting
+/// over at its end.
+#[derive(Debug, Clone)]
+pub struct FromFile {
+    data: Vec<u8>,
+    /// Where the next packet continues in `data`
+    pos: usize,
+}
+
+impl FromFile {
+    /// Reads the whole file at `path`.
+    ///
+    /// # Errors
+    /// - the error reading the file,
+    /// - `InvalidData` if the file is empty.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let data = std::fs::read(path)?;
+        if data.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "empty payload file",
+            ));
+        }
+        Ok(Self { data, pos: 0 })
+    }
+}
+
+impl PayloadSource for FromFile {
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = (buf.len() - filled).min(self.data.len() - self.pos);
+            buf[filled..filled + n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            filled += n;
+            self.pos = (self.pos + n) % self.data.len();
+        }
+        Ok(())
+    }
+}
+
+/// Weighted mix of packet lengths (header included).
+///
+/// The lengths are sent interleaved in a fixed cycle following the weights,
+/// e.g. `[(64, 7), (576, 4), (1500, 1)]` sends 7 short, 4 medium and 1 long
+/// packet out of every 12. The bitrate is kept on average.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeMix {
+    /// One cycle of lengths
+    cycle: Vec<usize>,
+}
+
+impl SizeMix {
+    /// Mix of `(length, weight)` pairs.
+    ///
+    /// Lengths shorter than the packet header are raised to it and zero
+    /// weights are ignored.
+    ///
+    /// # Panics
+    /// Panics if no length has a weight.
+    pub fn new(weights: impl IntoIterator<Item = (usize, u32)>) -> Self {
+        let weights: Vec<(usize, i64)> = weights
+            .into_iter()
+            .filter(|&(_, w)| w > 0)
+            .map(|(len, w)| (len.max(HEADER_SIZE), i64::from(w)))
+            .collect();
+        assert!(!weights.is_empty(), "a size mix needs a weighted length");
+        let total: i64 = weights.iter().map(|&(_, w)| w).sum();
+
+        // smooth weighted round-robin, spreads every length over the cycle
+        let mut current = vec![0i64; weights.len()];
+        let cycle = (0..total)
+            .map(|_| {
+                for (c, &(_, w)) in current.iter_mut().zip(&weights) {
+                    *c += w;
+                }
+                let pick = (0..weights.len())
+                    .max_by_key(|&i| (current[i], -(i as i64)))
+                    .unwrap();
+                current[pick] -= total;
+                weights[pick].0
+            })
+            .collect();
+        Self { cycle }
+    }
+
+    /// The classic simple IMIX: 64, 576 and 1500 byte packets in a 7:4:1 ratio.
+    pub fn imix() -> Self {
+        Self::new([(64, 7), (576, 4), (1500, 1)])
+    }
+
+    /// Length of the packet number `index`.
+    pub fn len_at(&self, index: u64) -> usize {
+        self.cycle[(index % self.cycle.len() as u64) as usize]
+    }
+
+    /// Mean packet length.
+    pub fn mean_len(&self) -> f64 {
+        self.cycle.iter().sum::<usize>() as f64 / self.cycle.len() as f64
+    }
+
+    /// Longest packet length.
+    pub fn max_len(&self) -> usize {
+        self.cycle.iter().copied().max().unwrap_or(HEADER_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        let mut buf = [0xffu8; 300];
+        Zeroes.fill(&mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+
+        Incrementing.fill(&mut buf).unwrap();
+        assert_eq!((buf[0], buf[1], buf[255], buf[256]), (0, 1, 255, 0));
+
+        let mut buf = [0u8; 7];
+        UserProvided::new(b"abc".to_vec()).fill(&mut buf).unwrap();
+        assert_eq!(&buf, b"abcabca");
+    }
+
+    #[test]
+    fn test_fast_random() {
+        let mut a = [0u8; 1203];
+        let mut b = [0u8; 1203];
+        FastRandom::with_seed(7).fill(&mut a).unwrap();
+        FastRandom::with_seed(7).fill(&mut b).unwrap();
+        assert_eq!(a, b);
+
+        let mut source = FastRandom::default();
+        source.fill(&mut a).unwrap();
+        source.fill(&mut b).unwrap();
+        assert_ne!(a, b);
+        // roughly uniform: every byte value shows up in a few packets
+        let mut seen = [false; 256];
+        for _ in 0..10 {
+            source.fill(&mut a).unwrap();
+            a.iter().for_each(|&x| seen[x as usize] = true);
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn test_size_mix() {
+        let mix = SizeMix::imix();
+        let cycle: Vec<usize> = (0..12).map(|i| mix.len_at(i)).collect();
+        assert_eq!(cycle.iter().filter(|&&l| l == 64).count(), 7);
+        assert_eq!(cycle.iter().filter(|&&l| l == 576).count(), 4);
+        assert_eq!(cycle.iter().filter(|&&l| l == 1500).count(), 1);
+        // interleaved, not 7 short packets in a row
+        assert!(cycle.windows(3).all(|w| w.iter().any(|&l| l != 64)));
+        assert_eq!(mix.len_at(12), mix.len_at(0));
+        assert!((mix.mean_len() - 4_252.0 / 12.0).abs() < 1e-9);
+        assert_eq!(mix.max_len(), 1500);
+
+        let tiny = SizeMix::new([(1, 1), (100, 0)]);
+        assert_eq!((tiny.len_at(5), tiny.max_len()), (HEADER_SIZE, HEADER_SIZE));
+    }
+
+    #[test]
+    fn test_from_file_streams_over_packets() {
+        let path = std::env::temp_dir().join(format!("udpopt-payload-{}", std::process::id()));
+        std::fs::write(&path, b"0123456789").unwrap();
+        let mut source = FromFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut buf = [0u8; 4];
+        let packets: Vec<[u8; 4]> = (0..3)
+            .map(|_| {
+                source.fill(&mut buf).unwrap();
+                buf
+            })
+            .collect();
+        assert_eq!(packets, [*b"0123", *b"4567", *b"8901"]);
+    }
+}
@@ -0,0 +1,585 @@
+//! # Test reports (feature `serde`)
+//!
+//! A [`TestReport`] keeps the [`TestResult`] of a test together with what is
+//! needed to read it months later: the udpopt version, the OS, the socket
+//! settings, both addresses, the test parameters and when it ran. It
+//! serializes to JSON or TOML, and renders as a CSV row or Prometheus
+//! gauges for result pipelines.
+//!
+//! A run can be given a name, a description and key/value tags in its
+//! [`TestParameters`], to group runs by site, circuit or ticket. They are
+//! kept in every form: CSV columns, Prometheus labels.
+//!
+//! The inter-arrival histogram of the result is not stored.
+//!
+//! A bare [`TestResult`] or the intervals of a test can be kept as well, with
+//! `TestResult::save` / `TestResult::load` and `IntervalResult::save_all` /
+//! `IntervalResult::load_all`, in the same JSON form.
+//!
+//! ```
+//! use std::time::Duration;
+//! use udpopt::{IntervalResult, TestResult};
+//! use udpopt::report::{TestParameters, TestReport};
+//!
+//! let interval = IntervalResult {
+//!     time: Duration::from_secs(1),
+//!     received: 1000,
+//!     bytes: 1_200_000,
+//!     ..Default::default()
+//! }
+//! .with_bitrate();
+//! let result = TestResult::from_intervals(&[interval]);
+//! let report = TestReport::new(result).with_parameters(TestParameters {
+//!     bitrate_bps: Some(10e6),
+//!     duration: Some(Duration::from_secs(10)),
+//!     ..Default::default()
+//! });
+//! let json = report.to_json().unwrap();
+//! assert_eq!(TestReport::from_json(&json).unwrap(), report);
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    errors::UdpOptError,
+    result::{TestResult, Throughput},
+};
+
+/// A test result with its environment, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestReport {
+    /// Version of udpopt that ran the test.
+    pub crate_version: String,
+    /// Operating system, as in [`std::env::consts::OS`].
+    pub os: String,
+    /// CPU architecture, as in [`std::env::consts::ARCH`].
+    pub arch: String,
+    /// Address of this side of the test.
+    pub local_addr: Option<SocketAddr>,
+    /// Address of the other side.
+    pub peer_addr: Option<SocketAddr>,
+    /// How the socket was set up.
+    pub socket: SocketSettings,
+    /// What the test was asked to do.
+    pub parameters: TestParameters,
+    /// When the test started.
+    pub started_at: SystemTime,
+    /// When the test ended.
+    pub ended_at: SystemTime,
+    /// What was measured.
+    pub result: TestResult,
+    /// Goodput and on-wire throughput of the result, with the overhead this
+    /// side was told of; `None` when not computed.
+    #[serde(default)]
+    pub throughput: Option<Throughput>,
+}
+
+/// Socket settings of a [`TestReport`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocketSettings {
+    /// Kernel receive buffer (bytes), when known.
+    pub recv_buffer: Option<usize>,
+    /// Kernel send buffer (bytes), when known.
+    pub send_buffer: Option<usize>,
+    /// Whether the packets were sent ECN-capable or their marks counted.
+    pub ecn: bool,
+    /// Whether the packets were authenticated with a pre-shared key.
+    pub authenticated: bool,
+    /// Whether only the clients with an accepted token were measured.
+    pub access_control: bool,
+    /// Interface the socket was bound to, if any.
+    #[serde(default)]
+    pub device: Option<String>,
+}
+
+/// Test parameters of a [`TestReport`], `None` for the ones this side did not know.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TestParameters {
+    /// Identifier given with `test_id`.
+    pub test_id: Option<String>,
+    /// Name of the run, e.g. the scenario it belongs to.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Free text about the run.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Key/value labels to group runs by, e.g. `site`, `circuit`, `ticket`.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Whether this side received the test, `false` if it sent it.
+    pub receiver: bool,
+    /// Target bitrate (bits/sec).
+    pub bitrate_bps: Option<f64>,
+    /// Packet length (bytes), header included.
+    pub packet_len: Option<usize>,
+    /// Planned test duration.
+    pub duration: Option<Duration>,
+    /// Length of the result intervals.
+    pub interval: Option<Duration>,
+    /// Warm-up left out of the results.
+    pub omit: Option<Duration>,
+}
+
+impl TestReport {
+    /// A report of `result` on this build and OS, ending now.
+    ///
+    /// The start is taken `result.total_time` before, the addresses, socket
+    /// settings and parameters are left empty.
+    pub fn new(result: TestResult) -> Self {
+        let ended_at = SystemTime::now();
+        let started_at = Duration::try_from_secs_f64(result.total_time)
+            .ok()
+            .and_then(|took| ended_at.checked_sub(took))
+            .unwrap_or(ended_at);
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            local_addr: None,
+            peer_addr: None,
+            socket: SocketSettings::default(),
+            parameters: TestParameters::default(),
+            started_at,
+            ended_at,
+            result,
+            throughput: None,
+        }
+    }
+
+    /// Sets the addresses of both sides.
+    pub fn with_addrs(mut self, local: Option<SocketAddr>, peer: Option<SocketAddr>) -> Self {
+        self.local_addr = local;
+        self.peer_addr = peer;
+        self
+    }
+
+    /// Sets the socket settings.
+    pub fn with_socket(mut self, socket: SocketSettings) -> Self {
+        self.socket = socket;
+        self
+    }
+
+    /// Sets the test parameters.
+    pub fn with_parameters(mut self, parameters: TestParameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// Sets when the test started and ended.
+    pub fn with_times(mut self, started_at: SystemTime, ended_at: SystemTime) -> Self {
+        self.started_at = started_at;
+        self.ended_at = ended_at;
+        self
+    }
+
+    /// Sets the goodput and on-wire throughput, e.g. `result.throughput(overhead)`.
+    pub fn with_throughput(mut self, throughput: Throughput) -> Self {
+        self.throughput = Some(throughput);
+        self
+    }
+
+    /// Pretty-printed JSON.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFormat`] if a value has no JSON form.
+    pub fn to_json(&self) -> Result<String, UdpOptError> {
+        serde_json::to_string_pretty(self).map_err(|e| UdpOptError::ReportFormat(e.to_string()))
+    }
+
+    /// Reads a report written by [`to_json`](Self::to_json).
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFormat`] if `json` is not a report.
+    pub fn from_json(json: &str) -> Result<Self, UdpOptError> {
+        serde_json::from_str(json).map_err(|e| UdpOptError::ReportFormat(e.to_string()))
+    }
+
+    /// TOML document.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFormat`] if a value has no TOML form.
+    pub fn to_toml(&self) -> Result<String, UdpOptError> {
+        toml::to_string(self).map_err(|e| UdpOptError::ReportFormat(e.to_string()))
+    }
+
+    /// Reads a report written by [`to_toml`](Self::to_toml).
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFormat`] if `toml` is not a report.
+    pub fn from_toml(toml: &str) -> Result<Self, UdpOptError> {
+        toml::from_str(toml).map_err(|e| UdpOptError::ReportFormat(e.to_string()))
+    }
+
+    /// A header line and one row with the labels and the main results.
+    ///
+    /// The tags share one `tags` column as `key=value` pairs separated by
+    /// `;`, so rows of runs with different tags can be appended to one file.
+    /// The goodput and on-wire columns are empty without a throughput.
+    pub fn to_csv(&self) -> String {
+        let p = &self.parameters;
+        let r = &self.result;
+        let tags: Vec<String> = p.tags.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        let started = self
+            .started_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let fields = [
+            csv_field(p.name.as_deref().unwrap_or_default()),
+            csv_field(p.description.as_deref().unwrap_or_default()),
+            csv_field(p.test_id.as_deref().unwrap_or_default()),
+            csv_field(&tags.join(";")),
+            started.as_secs().to_string(),
+            r.total_time.to_string(),
+            r.total_packets.to_string(),
+            r.total_lost.to_string(),
+            r.loss_percent.to_string(),
+            r.mean_bitrate.to_string(),
+            r.mean_jitter.to_string(),
+            r.max_jitter.to_string(),
+            self.throughput
+                .map(|t| t.goodput_bps.to_string())
+                .unwrap_or_default(),
+            self.throughput
+                .map(|t| t.wire_bps.to_string())
+                .unwrap_or_default(),
+        ];
+        format!(
+            "name,description,test_id,tags,started_at,seconds,received,lost,loss_percent,bitrate_bps,jitter_ms,max_jitter_ms,goodput_bps,wire_bps\n{}\n",
+            fields.join(",")
+        )
+    }
+
+    /// The main results as Prometheus gauges in the text exposition format,
+    /// labelled with the name, the test id and the tags of the run, the
+    /// goodput and on-wire throughput among them when set.
+    ///
+    /// A tag becomes the label `tag_<key>`, so it never clashes with `name`,
+    /// `test_id` or the reserved `__` labels, with the characters of the key
+    /// that are not valid in a label name replaced by `_`; keys that end up
+    /// the same get a `_2`, `_3`... suffix. The description is left out,
+    /// labels are for grouping.
+    pub fn to_prometheus(&self) -> String {
+        let p = &self.parameters;
+        let mut labels = Vec::new();
+        if let Some(name) = &p.name {
+            labels.push(("name".to_string(), name.as_str()));
+        }
+        if let Some(id) = &p.test_id {
+            labels.push(("test_id".to_string(), id.as_str()));
+        }
+        for (key, value) in &p.tags {
+            let name = label_name(key);
+            let mut unique = name.clone();
+            let mut n = 1;
+            while labels.iter().any(|(k, _)| *k == unique) {
+                n += 1;
+                unique = format!("{name}_{n}");
+            }
+            labels.push((unique, value.as_str()));
+        }
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(k, v)| format!("{k}=\"{}\"", label_value(v)))
+            .collect();
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels.join(","))
+        };
+
+        let r = &self.result;
+        let mut gauges = vec![
+            (
+                "received_packets",
+                "Packets received",
+                r.total_packets as f64,
+            ),
+            ("lost_packets", "Packets lost", r.total_lost as f64),
+            (
+                "loss_percent",
+                "Percentage of the packets lost",
+                r.loss_percent,
+            ),
+            ("bitrate_bps", "Mean bitrate (bits/sec)", r.mean_bitrate),
+            ("jitter_ms", "Mean jitter (ms)", r.mean_jitter),
+            ("duration_seconds", "Duration of the test", r.total_time),
+        ];
+        if let Some(t) = &self.throughput {
+            gauges.push(("goodput_bps", "Application bits per second", t.goodput_bps));
+            gauges.push(("wire_bps", "Bits per second on the wire", t.wire_bps));
+        }
+        let mut out = String::new();
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP udpopt_{name} {help}.");
+            let _ = writeln!(out, "# TYPE udpopt_{name} gauge");
+            let _ = writeln!(out, "udpopt_{name}{labels} {value}");
+        }
+        out
+    }
+}
+
+/// `field` quoted if it holds a comma, a quote or a line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The tag `key` as a Prometheus label name, `tag_[a-zA-Z0-9_]*`
+fn label_name(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("tag_{key}")
+}
+
+/// `value` escaped for a Prometheus label
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A `Duration` as seconds, `duration = 2.5`, for files written by hand
+pub(crate) mod secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub(crate) fn serialize<S: Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        Duration::try_from_secs_f64(f64::deserialize(d)?).map_err(D::Error::custom)
+    }
+}
+
+/// An optional `Duration` as seconds, see [`secs`]
+pub(crate) mod opt_secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub(crate) fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => s.serialize_some(&duration.as_secs_f64()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<f64>::deserialize(d)?
+            .map(|secs| Duration::try_from_secs_f64(secs).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// Writes `value` to `path` as pretty-printed JSON
+pub(crate) fn save_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), UdpOptError> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|e| UdpOptError::ReportFormat(e.to_string()))?;
+    std::fs::write(path, json).map_err(UdpOptError::ReportFailed)
+}
+
+/// Reads a value written by [`save_json`]
+pub(crate) fn load_json<T: DeserializeOwned>(path: &Path) -> Result<T, UdpOptError> {
+    let json = std::fs::read_to_string(path).map_err(UdpOptError::ReportFailed)?;
+    serde_json::from_str(&json).map_err(|e| UdpOptError::ReportFormat(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{result::Overhead, utils::net_utils::IntervalResult};
+
+    fn report() -> TestReport {
+        let interval = IntervalResult {
+            time: Duration::from_secs(1),
+            received: 1000,
+            lost: 10,
+            bytes: 1_200_000,
+            jitter_ms: 0.5,
+            ..Default::default()
+        }
+        .with_bitrate();
+        let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_750_000_000);
+        TestReport::new(TestResult::from_intervals(&[interval, interval]))
+            .with_addrs(
+                Some("192.0.2.1:5201".parse().unwrap()),
+                Some("[2001:db8::2]:40000".parse().unwrap()),
+            )
+            .with_socket(SocketSettings {
+                recv_buffer: Some(212_992),
+                ecn: true,
+                ..Default::default()
+            })
+            .with_parameters(TestParameters {
+                test_id: Some("nightly".into()),
+                name: Some("edge uplink".into()),
+                tags: [("circuit-id", "C-42"), ("site", "ams \"1\"")]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                receiver: true,
+                interval: Some(Duration::from_secs(1)),
+                ..Default::default()
+            })
+            .with_times(started, started + Duration::from_secs(2))
+    }
+
+    #[test]
+    fn test_new_fills_the_environment() {
+        let report = TestReport::new(TestResult::from_intervals(&[]));
+        assert_eq!(report.crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(report.os, std::env::consts::OS);
+        assert!(report.started_at <= report.ended_at);
+    }
+
+    #[test]
+    fn test_json_and_toml_roundtrip() {
+        let report = report();
+        let json = report.to_json().unwrap();
+        assert!(json.contains("\"test_id\": \"nightly\""));
+        assert_eq!(TestReport::from_json(&json).unwrap(), report);
+
+        let toml = report.to_toml().unwrap();
+        assert!(toml.contains("peer_addr = \"[2001:db8::2]:40000\""));
+        assert_eq!(TestReport::from_toml(&toml).unwrap(), report);
+
+        assert!(matches!(
+            TestReport::from_json("{}"),
+            Err(UdpOptError::ReportFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_csv_and_prometheus() {
+        let report = report();
+        let csv = report.to_csv();
+        let mut lines = csv.lines();
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .starts_with("name,description,test_id,tags,")
+        );
+        let row = lines.next().unwrap();
+        assert!(row.starts_with(
+            "edge uplink,,nightly,\"circuit-id=C-42;site=ams \"\"1\"\"\",1750000000,2,2000,20,"
+        ));
+        assert!(row.ends_with(",,"));
+
+        let prom = report.to_prometheus();
+        assert!(prom.contains("# TYPE udpopt_lost_packets gauge\n"));
+        assert!(prom.contains(
+            "udpopt_lost_packets{name=\"edge uplink\",test_id=\"nightly\",tag_circuit_id=\"C-42\",tag_site=\"ams \\\"1\\\"\"} 20\n"
+        ));
+        assert!(!prom.contains("udpopt_goodput_bps"));
+
+        let bare = TestReport::new(TestResult::from_intervals(&[])).to_prometheus();
+        assert!(bare.contains("\nudpopt_lost_packets 0\n"));
+    }
+
+    #[test]
+    fn test_throughput_in_every_form() {
+        let report = report();
+        let throughput = report.result.throughput(Overhead::ipv4());
+        let report = report.with_throughput(throughput);
+        assert_eq!(
+            TestReport::from_json(&report.to_json().unwrap()).unwrap(),
+            report
+        );
+        let csv = report.to_csv();
+        assert!(csv.ends_with(&format!(
+            ",{},{}\n",
+            throughput.goodput_bps, throughput.wire_bps
+        )));
+        let prom = report.to_prometheus();
+        assert!(prom.contains("# TYPE udpopt_wire_bps gauge\n"));
+        assert!(prom.contains(&format!(
+            "udpopt_goodput_bps{{name=\"edge uplink\",test_id=\"nightly\",tag_circuit_id=\"C-42\",tag_site=\"ams \\\"1\\\"\"}} {}\n",
+            throughput.goodput_bps
+        )));
+    }
+
+    #[test]
+    fn test_prometheus_labels_stay_valid() {
+        let report =
+            TestReport::new(TestResult::from_intervals(&[])).with_parameters(TestParameters {
+                name: Some("run1".into()),
+                tags: [
+                    ("name", "dup"),
+                    ("__x", "1"),
+                    ("a-b", "2"),
+                    ("a_b", "3"),
+                    ("9", "4"),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+                ..Default::default()
+            });
+        // the tags are in key order: `9`, `__x`, `a-b`, `a_b`, `name`
+        assert!(report.to_prometheus().contains(
+            "udpopt_lost_packets{name=\"run1\",tag_9=\"4\",tag___x=\"1\",tag_a_b=\"2\",tag_a_b_2=\"3\",tag_name=\"dup\"} 0\n"
+        ));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir();
+        let result_path = dir.join(format!("udpopt-result-{}.json", std::process::id()));
+        let intervals_path = dir.join(format!("udpopt-intervals-{}.json", std::process::id()));
+        let intervals: Vec<IntervalResult> = (1..=3)
+            .map(|i| {
+                IntervalResult {
+                    time: Duration::from_millis(500),
+                    received: 100 * i,
+                    lost: i,
+                    bytes: 1200 * i as usize,
+                    jitter_ms: 0.25 * i as f64,
+                    ..Default::default()
+                }
+                .with_bitrate()
+            })
+            .collect();
+        let result = TestResult::from_intervals(&intervals);
+
+        result.save(&result_path).unwrap();
+        IntervalResult::save_all(&intervals, &intervals_path).unwrap();
+        let loaded = (
+            TestResult::load(&result_path),
+            IntervalResult::load_all(&intervals_path),
+        );
+        // a result is not a list of intervals
+        let mismatch = IntervalResult::load_all(&result_path);
+        std::fs::remove_file(&result_path).unwrap();
+        std::fs::remove_file(&intervals_path).unwrap();
+
+        assert_eq!(loaded.0.unwrap(), result);
+        assert_eq!(loaded.1.unwrap(), intervals);
+        assert!(matches!(mismatch, Err(UdpOptError::ReportFormat(_))));
+        assert!(matches!(
+            TestResult::load(&result_path),
+            Err(UdpOptError::ReportFailed(_))
+        ));
+    }
+}
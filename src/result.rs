@@ -1,31 +1,337 @@
+use std::net::SocketAddr;
+#[cfg(feature = "serde")]
+use std::path::Path;
 use std::time::Duration;
 use utils::net_utils::IntervalResult;
 
+#[cfg(feature = "serde")]
+use crate::errors::UdpOptError;
+use crate::fault::FaultStats;
+use crate::histogram::Histogram;
+use crate::thresholds::{Thresholds, Verdict};
 use crate::utils;
+use crate::utils::net_utils::ClientInterval;
+use crate::utils::udp_data::HEADER_SIZE;
+use crate::utils::ui::{loss_percent, ooo_percent};
+use crate::voip::VoipQuality;
+
+/// Per-packet delay percentiles (ms): the latency relative to the first
+/// received packet, or the delay variation between consecutive packets.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LatencyPercentiles {
+    /// 50th percentile (median) latency (ms).
+    pub p50_ms: f64,
+    /// 90th percentile latency (ms).
+    pub p90_ms: f64,
+    /// 99th percentile latency (ms).
+    pub p99_ms: f64,
+    /// 99.9th percentile latency (ms).
+    pub p999_ms: f64,
+}
+
+impl LatencyPercentiles {
+    /// Computes the percentiles from a latency [`Histogram`] recorded in microseconds.
+    pub fn from_histogram(hist: &Histogram) -> Self {
+        let ms = |p: f64| hist.value_at_percentile(p) as f64 / 1000.0;
+        Self {
+            p50_ms: ms(50.0),
+            p90_ms: ms(90.0),
+            p99_ms: ms(99.0),
+            p999_ms: ms(99.9),
+        }
+    }
+}
+
+/// Number of buckets of [`ReorderStats::displacement`]
+pub const REORDER_BUCKETS: usize = 8;
+
+/// Reordering extent in the spirit of RFC 4737.
+///
+/// A packet is reordered when it arrives after a packet with a higher sequence
+/// number; its displacement is how far behind the highest sequence number seen
+/// so far it was.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReorderStats {
+    /// Number of packets considered, duplicates excluded.
+    pub packets: u64,
+    /// Number of reordered packets.
+    pub reordered: u64,
+    /// Largest displacement seen (in sequence numbers).
+    pub max_distance: u64,
+    /// Displacement histogram: bucket `i` counts displacements in
+    /// `2^(i-1) < d <= 2^i`, the last bucket everything bigger.
+    pub displacement: [u64; REORDER_BUCKETS],
+}
+
+impl ReorderStats {
+    /// Counts a packet that arrived in order.
+    pub(crate) fn record_in_order(&mut self) {
+        self.packets += 1;
+    }
+
+    /// Counts a packet that arrived `distance` sequence numbers late.
+    pub(crate) fn record_reordered(&mut self, distance: u64) {
+        self.packets += 1;
+        self.reordered += 1;
+        self.max_distance = self.max_distance.max(distance);
+        self.displacement[log2_bucket(distance, REORDER_BUCKETS)] += 1;
+    }
+
+    /// Percentage of reordered packets.
+    pub fn reordered_percent(&self) -> f64 {
+        if self.packets == 0 {
+            0.0
+        } else {
+            self.reordered as f64 / self.packets as f64 * 100.0
+        }
+    }
+}
+
+/// Longest packet length (bytes, inclusive) of every [`SizeStats`] bucket but
+/// the last, which holds everything longer. The edges follow the RFC 2819
+/// packet size counters.
+pub const SIZE_BUCKET_LIMITS: [usize; 6] = [64, 127, 255, 511, 1023, 1518];
+
+/// Number of buckets of [`SizeStats`]
+pub const SIZE_BUCKETS: usize = SIZE_BUCKET_LIMITS.len() + 1;
+
+/// Received packets and bytes per packet length, to check how every part of
+/// a size mix (`ClientBuilder::packet_sizes`) got through.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SizeStats {
+    /// Packets per bucket, see [`SIZE_BUCKET_LIMITS`].
+    pub packets: [u64; SIZE_BUCKETS],
+    /// Bytes per bucket.
+    pub bytes: [u64; SIZE_BUCKETS],
+}
+
+impl SizeStats {
+    /// Counts a received packet of `len` bytes.
+    pub(crate) fn record(&mut self, len: usize) {
+        let bucket = Self::bucket(len);
+        self.packets[bucket] += 1;
+        self.bytes[bucket] += len as u64;
+    }
+
+    /// Index of the bucket holding `len` byte packets.
+    pub fn bucket(len: usize) -> usize {
+        SIZE_BUCKET_LIMITS
+            .iter()
+            .position(|&limit| len <= limit)
+            .unwrap_or(SIZE_BUCKET_LIMITS.len())
+    }
+
+    /// Shortest and longest length of bucket `i`, `None` for no upper limit.
+    pub fn bucket_range(i: usize) -> (usize, Option<usize>) {
+        let min = if i == 0 {
+            0
+        } else {
+            SIZE_BUCKET_LIMITS[i - 1] + 1
+        };
+        (min, SIZE_BUCKET_LIMITS.get(i).copied())
+    }
+}
+
+/// Number of buckets of [`LossStats::run_lengths`]
+pub const LOSS_RUN_BUCKETS: usize = 8;
+
+/// Loss pattern: how the lost packets are grouped into bursts.
+///
+/// Two tests with the same mean loss can sound very different on a voice
+/// call, isolated losses are concealed while long bursts are not.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LossStats {
+    /// Number of sequence numbers classified as received or lost.
+    pub packets: u64,
+    /// Number of sequence numbers never received.
+    pub lost: u64,
+    /// Number of loss bursts (runs of consecutive losses).
+    pub bursts: u64,
+    /// Longest loss burst.
+    pub max_burst: u64,
+    /// Burst length histogram: bucket `i` counts lengths in
+    /// `2^(i-1) < len <= 2^i`, the last bucket everything longer.
+    pub run_lengths: [u64; LOSS_RUN_BUCKETS],
+}
+
+/// Parameters of a two-state Gilbert-Elliott loss model.
+///
+/// Estimated from the loss runs with the classic Gilbert simplification: every
+/// packet is lost in the bad state and none in the good state.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GilbertElliott {
+    /// Probability to go from the good to the bad state.
+    pub p: f64,
+    /// Probability to go from the bad to the good state.
+    pub r: f64,
+}
+
+impl GilbertElliott {
+    /// Long-run loss probability of the model, `p / (p + r)`.
+    pub fn loss_rate(&self) -> f64 {
+        if self.p + self.r == 0.0 {
+            0.0
+        } else {
+            self.p / (self.p + self.r)
+        }
+    }
+
+    /// Expected burst length of the model, `1 / r`.
+    pub fn mean_burst(&self) -> f64 {
+        if self.r == 0.0 { 0.0 } else { 1.0 / self.r }
+    }
+
+    /// G.107 burst ratio `1 / (p + r)`: 1 for random loss, bigger for bursty loss.
+    pub fn burst_ratio(&self) -> f64 {
+        if self.p + self.r == 0.0 {
+            1.0
+        } else {
+            1.0 / (self.p + self.r)
+        }
+    }
+}
+
+impl LossStats {
+    /// Counts a finished burst of `len` consecutive losses.
+    pub(crate) fn record_burst(&mut self, len: u64) {
+        self.bursts += 1;
+        self.max_burst = self.max_burst.max(len);
+        self.run_lengths[log2_bucket(len, LOSS_RUN_BUCKETS)] += 1;
+    }
+
+    /// Average length of the loss bursts.
+    pub fn mean_burst(&self) -> f64 {
+        if self.bursts == 0 {
+            0.0
+        } else {
+            self.lost as f64 / self.bursts as f64
+        }
+    }
+
+    /// Estimates the Gilbert-Elliott transition probabilities.
+    ///
+    /// Every burst is one good-to-bad and one bad-to-good transition, so
+    /// `p = bursts / received` and `r = bursts / lost`.
+    pub fn gilbert_elliott(&self) -> GilbertElliott {
+        let received = self.packets - self.lost;
+        let ratio = |n: u64| {
+            if n == 0 {
+                0.0
+            } else {
+                self.bursts as f64 / n as f64
+            }
+        };
+        GilbertElliott {
+            p: ratio(received),
+            r: ratio(self.lost),
+        }
+    }
+}
+
+/// Percentiles of the per-interval bitrate and jitter computed by [`TestResult::from_intervals`].
+pub const DEFAULT_PERCENTILES: [f64; 3] = [90.0, 95.0, 99.0];
+
+/// A percentile of the per-interval bitrate and jitter, see
+/// [`TestResult::from_intervals_with_percentiles`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IntervalPercentile {
+    /// The percentile, between 0 and 100.
+    pub percentile: f64,
+    /// Interval bitrate at that percentile (bits/sec).
+    pub bitrate: f64,
+    /// Interval jitter at that percentile (ms).
+    pub jitter_ms: f64,
+}
 
 /// Final aggregated test statistics computed from a list of `IntervalResult`s.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TestResult {
     /// Total number of packets received across all intervals.
     pub total_packets: u64,
-    /// Total number of packets lost across all intervals.
+    /// Total number of packets lost across all intervals, less the ones
+    /// that arrived late, see [`IntervalResult::recovered`].
     pub total_lost: u64,
     /// Total number of bytes received across all intervals.
     pub total_bytes: usize,
     /// Total duration of the test (in seconds).
     pub total_time: f64,
-    /// Total duration of the test (in seconds).
+    /// Total number of out-of-order packets across all intervals.
     pub total_out_of_order: u64,
+    /// Total number of packets that arrived after their interval was closed
+    /// or after the FIN, see [`IntervalResult::late`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub total_late: u64,
+    /// Total number of extra copies of packets across all intervals, a
+    /// sign of a path problem (a looping route, a misbehaving middlebox).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub total_duplicates: u64,
 
     /// Mean bitrate over all intervals (bits/sec).
     pub mean_bitrate: f64,
     /// Median bitrate over all intervals (bits/sec).
     pub median_bitrate: f64,
+    /// Lowest interval bitrate (bits/sec).
+    pub min_bitrate: f64,
+    /// Highest interval bitrate (bits/sec).
+    pub max_bitrate: f64,
+    /// Population standard deviation of the interval bitrates (bits/sec).
+    pub std_dev_bitrate: f64,
 
     /// Mean jitter over all intervals (ms).
     pub mean_jitter: f64,
     /// Median jitter over all intervals (ms).
     pub median_jitter: f64,
+    /// Lowest interval jitter (ms).
+    pub min_jitter: f64,
+    /// Highest interval jitter (ms).
+    pub max_jitter: f64,
+    /// Population standard deviation of the interval jitters (ms).
+    pub std_dev_jitter: f64,
+
+    /// Percentage of the packets lost over the whole test.
+    pub loss_percent: f64,
+    /// Percentage of the received packets that arrived out of order.
+    pub ooo_percent: f64,
+
+    /// Tail of the interval bitrates and jitters, in the order asked for.
+    pub percentiles: Vec<IntervalPercentile>,
+
+    /// Per-packet latency percentiles, filled by [`TestResult::with_latency`].
+    pub latency: LatencyPercentiles,
+
+    /// Percentiles of the absolute delay variation between consecutive
+    /// packets (IPDV, RFC 3393), filled by [`TestResult::with_ipdv`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ipdv: LatencyPercentiles,
+
+    /// Reordering extent, filled by [`TestResult::with_reorder`].
+    pub reorder: ReorderStats,
+
+    /// Loss bursts, filled by [`TestResult::with_loss_pattern`].
+    pub loss_pattern: LossStats,
+
+    /// Packets per length bucket, filled by [`TestResult::with_sizes`].
+    pub sizes: SizeStats,
+
+    /// Packets the client says it sent, 0 when unknown; filled by
+    /// [`TestResult::with_packets_sent`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub packets_sent: u64,
+
+    /// Packets of `total_lost` the server's socket dropped with a full
+    /// receive buffer, see [`IntervalResult::socket_drops`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub total_socket_drops: u64,
+
+    /// Inter-arrival times (µs), filled by [`TestResult::with_interarrival`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    interarrival: Histogram,
 }
 
 impl TestResult {
@@ -35,22 +341,22 @@ impl TestResult {
     /// * `intervals` - A list of per-interval measurement results.
     ///
     /// # Returns
-    /// A `TestResult` containing total counts and statistical measures such as mean and median.
+    /// A `TestResult` containing total counts and statistical measures such as
+    /// mean, median, min, max, standard deviation and the [`DEFAULT_PERCENTILES`].
     pub fn from_intervals(intervals: &[IntervalResult]) -> Self {
-        if intervals.is_empty() {
-            return Self {
-                total_packets: 0,
-                total_lost: 0,
-                total_bytes: 0,
-                total_time: 0.0,
-                total_out_of_order: 0,
-                mean_bitrate: 0.0,
-                median_bitrate: 0.0,
-                mean_jitter: 0.0,
-                median_jitter: 0.0,
-            };
-        }
+        Self::from_intervals_with_percentiles(intervals, &DEFAULT_PERCENTILES)
+    }
 
+    /// Same as [`TestResult::from_intervals`] with other percentiles.
+    ///
+    /// # Arguments
+    /// * `intervals` - A list of per-interval measurement results.
+    /// * `percentiles` - The percentiles (0 to 100) of the interval bitrate and
+    ///   jitter to compute, e.g. `&[99.0, 99.9]` for an SLA on the tail.
+    pub fn from_intervals_with_percentiles(
+        intervals: &[IntervalResult],
+        percentiles: &[f64],
+    ) -> Self {
         let n = intervals.len();
         let mut bitrates = Vec::with_capacity(n);
         let mut jitters = Vec::with_capacity(n);
@@ -60,36 +366,948 @@ impl TestResult {
         let mut total_bytes = 0usize;
         let mut total_time = Duration::ZERO;
         let mut total_out_of_order = 0;
+        let mut total_late = 0;
+        let mut total_recovered = 0;
+        let mut total_duplicates = 0;
+        let mut total_socket_drops = 0;
 
         // Compute totals and collect per-interval stats in one pass
         for i in intervals {
             total_received += i.received;
             total_lost += i.lost;
             total_bytes += i.bytes;
-            total_out_of_order = i.out_of_order;
+            total_out_of_order += i.out_of_order;
+            total_late += i.late;
+            total_recovered += i.recovered;
+            total_duplicates += i.duplicates;
+            total_socket_drops += i.socket_drops;
 
-            bitrates.push((i.bytes * 8) as f64 / i.time.as_secs_f64());
+            bitrates.push(i.bitrate_bps);
             jitters.push(i.jitter_ms);
             total_time += i.time
         }
 
+        let total_lost = total_lost.saturating_sub(total_recovered);
+
         let mean_bitrate = mean(&bitrates);
         let mean_jitter = mean(&jitters);
         let median_bitrate = median_f64(&mut bitrates);
         let median_jitter = median_f64(&mut jitters);
+        let percentiles = percentiles
+            .iter()
+            .map(|&p| IntervalPercentile {
+                percentile: p,
+                bitrate: percentile_f64(&mut bitrates, p),
+                jitter_ms: percentile_f64(&mut jitters, p),
+            })
+            .collect();
 
         Self {
             total_packets: total_received,
-            total_lost: total_lost,
-            total_bytes: total_bytes,
+            total_lost,
+            total_bytes,
             total_time: total_time.as_secs_f64(),
-            total_out_of_order: total_out_of_order,
-            mean_bitrate: mean_bitrate,
-            median_bitrate: median_bitrate,
-            mean_jitter: mean_jitter,
-            median_jitter: median_jitter,
+            total_out_of_order,
+            total_late,
+            total_duplicates,
+            mean_bitrate,
+            median_bitrate,
+            // sorted by the median
+            min_bitrate: bitrates.first().copied().unwrap_or(0.0),
+            max_bitrate: bitrates.last().copied().unwrap_or(0.0),
+            std_dev_bitrate: std_dev(&bitrates),
+            mean_jitter,
+            median_jitter,
+            min_jitter: jitters.first().copied().unwrap_or(0.0),
+            max_jitter: jitters.last().copied().unwrap_or(0.0),
+            std_dev_jitter: std_dev(&jitters),
+            loss_percent: loss_percent(total_received, total_lost),
+            ooo_percent: ooo_percent(total_received, total_out_of_order),
+            percentiles,
+            latency: LatencyPercentiles::default(),
+            ipdv: LatencyPercentiles::default(),
+            reorder: ReorderStats::default(),
+            loss_pattern: LossStats::default(),
+            sizes: SizeStats::default(),
+            packets_sent: 0,
+            total_socket_drops,
+            interarrival: Histogram::new(),
+        }
+    }
+
+    /// Attaches latency percentiles computed from the server's latency histogram.
+    ///
+    /// # Arguments
+    /// * `hist` - The histogram returned by `UdpServer::latency_histogram`.
+    pub fn with_latency(mut self, hist: &Histogram) -> Self {
+        self.latency = LatencyPercentiles::from_histogram(hist);
+        self
+    }
+
+    /// Attaches the count of packets the client says it sent, 0 when unknown.
+    ///
+    /// # Arguments
+    /// * `sent` - The count returned by `UdpServer::client_sent`.
+    pub fn with_packets_sent(mut self, sent: u64) -> Self {
+        self.packets_sent = sent;
+        self
+    }
+
+    /// Packets lost against the count the client sent, `None` when unknown:
+    /// the server only takes the count from the client's FIN, a test whose
+    /// FIN was lost has none.
+    ///
+    /// Unlike `total_lost`, which the server infers from the gaps in the
+    /// sequence numbers, it compares what arrived with what the client says
+    /// left, so it holds whatever happened to the sequence numbers on the way.
+    pub fn true_loss(&self) -> Option<u64> {
+        (self.packets_sent > 0).then(|| self.packets_sent.saturating_sub(self.total_packets))
+    }
+
+    /// Packets lost on the path: `total_lost` less those the server's own
+    /// socket dropped with a full receive buffer.
+    pub fn path_loss(&self) -> u64 {
+        self.total_lost.saturating_sub(self.total_socket_drops)
+    }
+
+    /// Goodput and estimated on-wire throughput of what arrived, `overhead`
+    /// added to or taken from `total_bytes` for every packet.
+    pub fn throughput(&self, overhead: Overhead) -> Throughput {
+        Throughput::new(
+            self.total_packets,
+            self.total_bytes as u64,
+            self.total_time,
+            overhead,
+        )
+    }
+
+    /// [`TestResult::true_loss`] as a percentage of the packets sent.
+    pub fn true_loss_percent(&self) -> Option<f64> {
+        self.true_loss()
+            .map(|lost| lost as f64 / self.packets_sent as f64 * 100.0)
+    }
+
+    /// Attaches the IPDV percentiles computed from the server's histogram.
+    ///
+    /// # Arguments
+    /// * `hist` - The histogram returned by `UdpServer::ipdv_histogram`.
+    pub fn with_ipdv(mut self, hist: &Histogram) -> Self {
+        self.ipdv = LatencyPercentiles::from_histogram(hist);
+        self
+    }
+
+    /// Attaches the reordering statistics recorded by the server.
+    ///
+    /// # Arguments
+    /// * `stats` - The statistics returned by `UdpServer::reorder_stats`.
+    pub fn with_reorder(mut self, stats: &ReorderStats) -> Self {
+        self.reorder = *stats;
+        self
+    }
+
+    /// Attaches the loss pattern recorded by the server.
+    ///
+    /// # Arguments
+    /// * `stats` - The statistics returned by `UdpServer::loss_pattern`.
+    pub fn with_loss_pattern(mut self, stats: &LossStats) -> Self {
+        self.loss_pattern = *stats;
+        self
+    }
+
+    /// Attaches the packet length statistics recorded by the server.
+    ///
+    /// # Arguments
+    /// * `stats` - The statistics returned by `UdpServer::size_stats`.
+    pub fn with_sizes(mut self, stats: &SizeStats) -> Self {
+        self.sizes = *stats;
+        self
+    }
+
+    /// Attaches the inter-arrival times recorded by the server.
+    ///
+    /// # Arguments
+    /// * `hist` - The histogram returned by `UdpServer::interarrival_histogram`.
+    pub fn with_interarrival(mut self, hist: &Histogram) -> Self {
+        self.interarrival = hist.clone();
+        self
+    }
+
+    /// Time between consecutive packet arrivals at the server (µs), e.g. to
+    /// spot bursts from bufferbloat or scheduling; use [`Histogram::buckets`] to plot it.
+    ///
+    /// Empty for the results received in-band by the client, they do not carry it.
+    pub fn interarrival_histogram(&self) -> &Histogram {
+        &self.interarrival
+    }
+
+    /// Packets received per second over the whole test.
+    pub fn pps(&self) -> f64 {
+        if self.total_time > 0.0 {
+            self.total_packets as f64 / self.total_time
+        } else {
+            0.0
         }
     }
+
+    /// Estimates the VoIP call quality over the whole test.
+    ///
+    /// Clocks are not synchronized so the one-way delay is unknown, the median
+    /// latency (relative to the first packet) stands in for it when recorded.
+    pub fn voip_quality(&self) -> VoipQuality {
+        VoipQuality::estimate(
+            self.loss_percent,
+            self.mean_jitter,
+            self.latency.p50_ms,
+            self.loss_pattern.gilbert_elliott().burst_ratio(),
+        )
+    }
+
+    /// Writes the result to `path` as JSON (feature `serde`), to follow a link
+    /// over many runs.
+    ///
+    /// The inter-arrival histogram is not stored.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFailed`] if the file cannot be written,
+    /// [`UdpOptError::ReportFormat`] if a value has no JSON form.
+    #[cfg(feature = "serde")]
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), UdpOptError> {
+        crate::report::save_json(path.as_ref(), self)
+    }
+
+    /// Reads a result written by [`TestResult::save`] (feature `serde`).
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFailed`] if the file cannot be read,
+    /// [`UdpOptError::ReportFormat`] if it does not hold a result.
+    #[cfg(feature = "serde")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, UdpOptError> {
+        crate::report::load_json(path.as_ref())
+    }
+
+    /// Checks the result against the limits of a CI gate.
+    pub fn evaluate(&self, thresholds: &Thresholds) -> Verdict {
+        thresholds.evaluate(self)
+    }
+
+    /// Compares this result, the baseline, with `other`, e.g. the same test
+    /// before and after a configuration change.
+    ///
+    /// Every delta is `other` minus `self`.
+    pub fn diff(&self, other: &TestResult) -> TestComparison {
+        let throughput = MetricDelta::between(
+            self.mean_bitrate,
+            other.mean_bitrate,
+            effect_size(
+                other.mean_bitrate - self.mean_bitrate,
+                self.std_dev_bitrate,
+                other.std_dev_bitrate,
+            ),
+        );
+        let jitter = MetricDelta::between(
+            self.mean_jitter,
+            other.mean_jitter,
+            effect_size(
+                other.mean_jitter - self.mean_jitter,
+                self.std_dev_jitter,
+                other.std_dev_jitter,
+            ),
+        );
+        let loss = MetricDelta::between(
+            self.loss_percent,
+            other.loss_percent,
+            loss_significance(
+                (self.total_lost, self.total_packets + self.total_lost),
+                (other.total_lost, other.total_packets + other.total_lost),
+            ),
+        );
+        TestComparison {
+            throughput,
+            loss,
+            jitter,
+        }
+    }
+
+    /// Size of the wire encoding produced by [`TestResult::to_bytes`] without
+    /// the percentiles, which take [`PERCENTILE_SIZE`](Self::PERCENTILE_SIZE) each
+    pub(crate) const ENCODED_SIZE: usize =
+        (37 + REORDER_BUCKETS + LOSS_RUN_BUCKETS + 2 * SIZE_BUCKETS) * 8;
+
+    /// Size of one encoded percentile
+    pub(crate) const PERCENTILE_SIZE: usize = 3 * 8;
+
+    /// Most percentiles [`TestResult::to_bytes`] encodes, the others are left out
+    pub(crate) const MAX_ENCODED_PERCENTILES: usize = 64;
+
+    /// Largest wire encoding of a result
+    pub(crate) const MAX_ENCODED_SIZE: usize =
+        Self::ENCODED_SIZE + Self::MAX_ENCODED_PERCENTILES * Self::PERCENTILE_SIZE;
+
+    /// Encodes the result (big-endian) to send it back to the client
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let percentiles =
+            &self.percentiles[..self.percentiles.len().min(Self::MAX_ENCODED_PERCENTILES)];
+        let mut buf = Vec::with_capacity(Self::ENCODED_SIZE);
+        buf.extend_from_slice(&self.total_packets.to_be_bytes());
+        buf.extend_from_slice(&self.total_lost.to_be_bytes());
+        buf.extend_from_slice(&(self.total_bytes as u64).to_be_bytes());
+        buf.extend_from_slice(&self.total_out_of_order.to_be_bytes());
+        for v in [
+            self.total_time,
+            self.mean_bitrate,
+            self.median_bitrate,
+            self.mean_jitter,
+            self.median_jitter,
+            self.latency.p50_ms,
+            self.latency.p90_ms,
+            self.latency.p99_ms,
+            self.latency.p999_ms,
+        ] {
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        for v in [
+            self.reorder.packets,
+            self.reorder.reordered,
+            self.reorder.max_distance,
+        ]
+        .iter()
+        .chain(&self.reorder.displacement)
+        .chain(&[
+            self.loss_pattern.packets,
+            self.loss_pattern.lost,
+            self.loss_pattern.bursts,
+            self.loss_pattern.max_burst,
+        ])
+        .chain(&self.loss_pattern.run_lengths)
+        .chain(&self.sizes.packets)
+        .chain(&self.sizes.bytes)
+        .chain(&[percentiles.len() as u64])
+        {
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        for v in [
+            self.min_bitrate,
+            self.max_bitrate,
+            self.std_dev_bitrate,
+            self.min_jitter,
+            self.max_jitter,
+            self.std_dev_jitter,
+            self.loss_percent,
+            self.ooo_percent,
+            self.ipdv.p50_ms,
+            self.ipdv.p90_ms,
+            self.ipdv.p99_ms,
+            self.ipdv.p999_ms,
+        ] {
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        buf.extend_from_slice(&self.total_late.to_be_bytes());
+        buf.extend_from_slice(&self.total_duplicates.to_be_bytes());
+        buf.extend_from_slice(&self.packets_sent.to_be_bytes());
+        buf.extend_from_slice(&self.total_socket_drops.to_be_bytes());
+        for p in percentiles {
+            for v in [p.percentile, p.bitrate, p.jitter_ms] {
+                buf.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        buf
+    }
+
+    /// Decodes a result encoded by [`TestResult::to_bytes`]
+    pub(crate) fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() < Self::ENCODED_SIZE {
+            return None;
+        }
+        let word = |i: usize| u64::from_be_bytes(buf[i * 8..i * 8 + 8].try_into().unwrap());
+        let float = |i: usize| f64::from_bits(word(i));
+        // after the fixed-size statistics
+        let tail = 20 + REORDER_BUCKETS + LOSS_RUN_BUCKETS + 2 * SIZE_BUCKETS;
+        let count = usize::try_from(word(tail)).ok()?;
+        if count > Self::MAX_ENCODED_PERCENTILES {
+            return None;
+        }
+        let percentiles_len = count.checked_mul(Self::PERCENTILE_SIZE)?;
+        if buf.len() - Self::ENCODED_SIZE != percentiles_len {
+            return None;
+        }
+        let first = Self::ENCODED_SIZE / 8;
+        Some(Self {
+            total_packets: word(0),
+            total_lost: word(1),
+            total_bytes: word(2) as usize,
+            total_out_of_order: word(3),
+            total_late: word(tail + 13),
+            total_duplicates: word(tail + 14),
+            total_time: float(4),
+            mean_bitrate: float(5),
+            median_bitrate: float(6),
+            min_bitrate: float(tail + 1),
+            max_bitrate: float(tail + 2),
+            std_dev_bitrate: float(tail + 3),
+            mean_jitter: float(7),
+            median_jitter: float(8),
+            min_jitter: float(tail + 4),
+            max_jitter: float(tail + 5),
+            std_dev_jitter: float(tail + 6),
+            loss_percent: float(tail + 7),
+            ooo_percent: float(tail + 8),
+            percentiles: (0..count)
+                .map(|i| IntervalPercentile {
+                    percentile: float(first + 3 * i),
+                    bitrate: float(first + 3 * i + 1),
+                    jitter_ms: float(first + 3 * i + 2),
+                })
+                .collect(),
+            latency: LatencyPercentiles {
+                p50_ms: float(9),
+                p90_ms: float(10),
+                p99_ms: float(11),
+                p999_ms: float(12),
+            },
+            ipdv: LatencyPercentiles {
+                p50_ms: float(tail + 9),
+                p90_ms: float(tail + 10),
+                p99_ms: float(tail + 11),
+                p999_ms: float(tail + 12),
+            },
+            reorder: ReorderStats {
+                packets: word(13),
+                reordered: word(14),
+                max_distance: word(15),
+                displacement: std::array::from_fn(|i| word(16 + i)),
+            },
+            loss_pattern: LossStats {
+                packets: word(16 + REORDER_BUCKETS),
+                lost: word(17 + REORDER_BUCKETS),
+                bursts: word(18 + REORDER_BUCKETS),
+                max_burst: word(19 + REORDER_BUCKETS),
+                run_lengths: std::array::from_fn(|i| word(20 + REORDER_BUCKETS + i)),
+            },
+            sizes: SizeStats {
+                packets: std::array::from_fn(|i| word(20 + REORDER_BUCKETS + LOSS_RUN_BUCKETS + i)),
+                bytes: std::array::from_fn(|i| {
+                    word(20 + REORDER_BUCKETS + LOSS_RUN_BUCKETS + SIZE_BUCKETS + i)
+                }),
+            },
+            packets_sent: word(tail + 15),
+            total_socket_drops: word(tail + 16),
+            interarrival: Histogram::new(),
+        })
+    }
+}
+
+/// How much a [`MetricDelta`] stands out from the measurement noise.
+///
+/// A hint, not a proof: the throughput and jitter are judged by the effect
+/// size (the delta over the pooled standard deviation of the intervals), the
+/// loss by a two-proportion z-test on the packet counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Significance {
+    /// Smaller than the interval-to-interval variation (effect size under
+    /// 0.2, |z| under 1).
+    WithinNoise,
+    /// Could be a change or noise, repeat or lengthen the tests.
+    Inconclusive,
+    /// Very unlikely to be noise (effect size 0.8 or more, |z| 1.96 or more,
+    /// i.e. 95 % confidence).
+    Significant,
+}
+
+/// Change of one metric between two results, see [`TestResult::diff`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetricDelta {
+    /// Value in the baseline.
+    pub before: f64,
+    /// Value in the compared result.
+    pub after: f64,
+    /// `after - before`.
+    pub delta: f64,
+    /// Change relative to the baseline (%), `None` when the baseline is 0.
+    pub percent_change: Option<f64>,
+    /// Whether the change stands out from the noise.
+    pub significance: Significance,
+}
+
+impl MetricDelta {
+    fn between(before: f64, after: f64, significance: Significance) -> Self {
+        let delta = after - before;
+        Self {
+            before,
+            after,
+            delta,
+            percent_change: (before != 0.0).then(|| delta / before.abs() * 100.0),
+            significance,
+        }
+    }
+
+    /// Whether the metric significantly went up.
+    pub fn increased(&self) -> bool {
+        self.significance == Significance::Significant && self.delta > 0.0
+    }
+
+    /// Whether the metric significantly went down.
+    pub fn decreased(&self) -> bool {
+        self.significance == Significance::Significant && self.delta < 0.0
+    }
+}
+
+/// Before/after comparison of two results returned by [`TestResult::diff`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TestComparison {
+    /// Mean bitrate (bits/sec).
+    pub throughput: MetricDelta,
+    /// Loss percentage.
+    pub loss: MetricDelta,
+    /// Mean jitter (ms).
+    pub jitter: MetricDelta,
+}
+
+impl TestComparison {
+    /// Whether the compared result is significantly worse: lower throughput,
+    /// more loss or more jitter.
+    pub fn is_regression(&self) -> bool {
+        self.throughput.decreased() || self.loss.increased() || self.jitter.increased()
+    }
+
+    /// Whether the compared result is significantly better on some metric
+    /// and worse on none.
+    pub fn is_improvement(&self) -> bool {
+        !self.is_regression()
+            && (self.throughput.increased() || self.loss.decreased() || self.jitter.decreased())
+    }
+}
+
+/// Significance of a change of `delta` in a metric with the interval standard
+/// deviations `sd_before` and `sd_after`, by its effect size (Cohen's d)
+fn effect_size(delta: f64, sd_before: f64, sd_after: f64) -> Significance {
+    let pooled = ((sd_before.powi(2) + sd_after.powi(2)) / 2.0).sqrt();
+    if pooled == 0.0 {
+        // steady metrics, any change is real
+        return if delta == 0.0 {
+            Significance::WithinNoise
+        } else {
+            Significance::Significant
+        };
+    }
+    match delta.abs() / pooled {
+        d if d < 0.2 => Significance::WithinNoise,
+        d if d < 0.8 => Significance::Inconclusive,
+        _ => Significance::Significant,
+    }
+}
+
+/// Significance of a change of the loss rate from `(lost, sent)` packets
+/// before to after, by a two-proportion z-test
+fn loss_significance(before: (u64, u64), after: (u64, u64)) -> Significance {
+    let ((lost_a, sent_a), (lost_b, sent_b)) = (before, after);
+    if sent_a == 0 || sent_b == 0 {
+        return Significance::Inconclusive;
+    }
+    let (sent_a, sent_b) = (sent_a as f64, sent_b as f64);
+    let diff = lost_b as f64 / sent_b - lost_a as f64 / sent_a;
+    let pooled = (lost_a + lost_b) as f64 / (sent_a + sent_b);
+    let se = (pooled * (1.0 - pooled) * (1.0 / sent_a + 1.0 / sent_b)).sqrt();
+    if se == 0.0 {
+        // no loss at all, or nothing but loss, on both sides
+        return Significance::WithinNoise;
+    }
+    match diff.abs() / se {
+        z if z < 1.0 => Significance::WithinNoise,
+        z if z < 1.96 => Significance::Inconclusive,
+        _ => Significance::Significant,
+    }
+}
+
+/// Available-bandwidth estimate from the dispersion of packet trains.
+///
+/// Each train is sent back-to-back, so it leaves the narrowest link of the path
+/// spread out by that link's spare capacity: the bytes after the first packet
+/// divided by the time between the first and the last arrival give one rate
+/// sample per train. The median is robust against trains disturbed by bursts
+/// of cross traffic, compare it with min / max to judge how stable the path is.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BandwidthEstimate {
+    /// Number of trains with a usable dispersion.
+    pub trains: u64,
+    /// Packets per train as sent by the client.
+    pub train_len: u16,
+    /// Lowest rate sample (bits/sec).
+    pub min_bps: f64,
+    /// Median rate sample, the estimate (bits/sec).
+    pub median_bps: f64,
+    /// Highest rate sample (bits/sec).
+    pub max_bps: f64,
+}
+
+impl BandwidthEstimate {
+    /// Summarizes the per-train rate samples, `None` without any.
+    pub(crate) fn from_rates(rates: &mut [f64], train_len: u16) -> Option<Self> {
+        if rates.is_empty() {
+            return None;
+        }
+        let median_bps = median_f64(rates);
+        Some(Self {
+            trains: rates.len() as u64,
+            train_len,
+            // sorted by the median
+            min_bps: rates[0],
+            median_bps,
+            max_bps: rates[rates.len() - 1],
+        })
+    }
+}
+
+/// A stretch of the test where the peer refused the client's sends with an
+/// ICMP unreachable, part of [`ClientReport`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnreachableEpisode {
+    /// First refused send, from the start of the test.
+    pub start: Duration,
+    /// From the first to the last refused send.
+    pub duration: Duration,
+    /// Sends refused, their packets skipped and counted in `send_failures`.
+    pub refusals: u64,
+}
+
+/// How the client's datagrams fit the path MTU, part of [`ClientReport`].
+///
+/// A datagram longer than the path MTU leaves in several IP fragments when
+/// the don't-fragment bit is clear, and is lost whole if any of them is:
+/// the loss of the datagrams is then about that many times the loss of the
+/// packets on the wire. With the bit set it is refused instead, see
+/// [`ClientReport::too_big`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fragmentation {
+    /// Path MTU the host knew at the end of the test (bytes).
+    pub path_mtu: usize,
+    /// Whether the client set (`true`) or cleared the don't-fragment bit,
+    /// `None` when it left the host's default.
+    pub dont_fragment: Option<bool>,
+    /// Datagrams sent in more than one IP fragment.
+    pub fragmented: u64,
+    /// Most fragments one datagram took.
+    pub max_fragments: u64,
+    /// IP packets sent, every fragment counted, as the interface counters
+    /// see them.
+    pub ip_packets: u64,
+    /// Bytes sent at the IP layer, the IP and UDP headers of every fragment
+    /// included.
+    pub ip_bytes: u64,
+}
+
+impl Fragmentation {
+    /// IPv4 header without options
+    const IPV4_HEADER: usize = 20;
+    /// IPv6 header
+    const IPV6_HEADER: usize = 40;
+    /// IPv6 fragment extension header
+    const IPV6_FRAGMENT_HEADER: usize = 8;
+    const UDP_HEADER: usize = 8;
+
+    /// IP fragments a UDP datagram carrying `len` bytes takes over a path MTU
+    /// of `mtu` bytes, 1 when it fits.
+    pub fn fragments(len: usize, mtu: usize, ipv6: bool) -> u64 {
+        let datagram = len + Self::UDP_HEADER;
+        let (header, fragment_header) = if ipv6 {
+            (Self::IPV6_HEADER, Self::IPV6_FRAGMENT_HEADER)
+        } else {
+            (Self::IPV4_HEADER, 0)
+        };
+        if datagram + header <= mtu {
+            return 1;
+        }
+        // every fragment but the last carries a multiple of 8 bytes
+        let per_fragment = mtu.saturating_sub(header + fragment_header) & !7;
+        datagram.div_ceil(per_fragment.max(8)) as u64
+    }
+
+    /// Bytes a UDP datagram carrying `len` bytes takes at the IP layer, the
+    /// headers of its `fragments` included.
+    pub fn ip_bytes(len: usize, fragments: u64, ipv6: bool) -> u64 {
+        let per_fragment = match (ipv6, fragments > 1) {
+            (false, _) => Self::IPV4_HEADER,
+            (true, false) => Self::IPV6_HEADER,
+            (true, true) => Self::IPV6_HEADER + Self::IPV6_FRAGMENT_HEADER,
+        };
+        (len + Self::UDP_HEADER) as u64 + fragments * per_fragment as u64
+    }
+
+    /// Records a datagram carrying `len` bytes sent over the current path MTU
+    pub(crate) fn record(&mut self, len: usize, ipv6: bool) {
+        let fragments = Self::fragments(len, self.path_mtu, ipv6);
+        if fragments > 1 {
+            self.fragmented += 1;
+        }
+        self.max_fragments = self.max_fragments.max(fragments);
+        self.ip_packets += fragments;
+        self.ip_bytes += Self::ip_bytes(len, fragments, ipv6);
+    }
+}
+
+/// Bytes a packet carries or takes on the wire besides the application's
+/// data, to tell goodput from on-wire throughput.
+///
+/// `bytes_sent` and `total_bytes` count UDP payloads, udpopt's header
+/// included: neither what an application would get nor what the interface
+/// counters see. The default is IPv4 over Ethernet, preamble and
+/// inter-frame gap included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overhead {
+    /// udpopt's header at the start of every payload (bytes).
+    pub header: usize,
+    /// IP and UDP headers (bytes), 28 over IPv4, 48 over IPv6.
+    pub ip: usize,
+    /// Link-layer framing (bytes).
+    pub link: usize,
+}
+
+impl Overhead {
+    /// Ethernet on the wire: header, FCS, preamble and inter-frame gap.
+    pub const ETHERNET_WIRE: usize = 38;
+    /// The Ethernet header alone, what Linux interface counters include.
+    pub const ETHERNET_HEADER: usize = 14;
+
+    /// IPv4 over Ethernet.
+    pub fn ipv4() -> Self {
+        Self {
+            header: HEADER_SIZE,
+            ip: 28,
+            link: Self::ETHERNET_WIRE,
+        }
+    }
+
+    /// IPv6 over Ethernet.
+    pub fn ipv6() -> Self {
+        Self {
+            ip: 48,
+            ..Self::ipv4()
+        }
+    }
+
+    /// Sets the link-layer framing, e.g. [`Overhead::ETHERNET_HEADER`] to
+    /// compare with interface counters or 0 for the IP layer.
+    pub fn link(mut self, bytes: usize) -> Self {
+        self.link = bytes;
+        self
+    }
+}
+
+impl Default for Overhead {
+    fn default() -> Self {
+        Self::ipv4()
+    }
+}
+
+/// Application goodput next to on-wire throughput, see
+/// [`TestResult::throughput`] and [`ClientReport::throughput`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Throughput {
+    /// Packets the bytes were carried in.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub packets: u64,
+    /// Application bytes, udpopt's header left out of every packet.
+    pub goodput_bytes: u64,
+    /// UDP payload bytes, what `bytes_sent` and `total_bytes` count.
+    pub payload_bytes: u64,
+    /// Bytes on the wire, IP, UDP and link-layer overhead included.
+    pub wire_bytes: u64,
+    /// Application bits per second.
+    pub goodput_bps: f64,
+    /// Bits per second on the wire.
+    pub wire_bps: f64,
+}
+
+impl Throughput {
+    /// Throughput of `packets` carrying `bytes` of UDP payload in total over
+    /// `seconds`.
+    pub fn new(packets: u64, bytes: u64, seconds: f64, overhead: Overhead) -> Self {
+        let goodput_bytes = bytes.saturating_sub(packets * overhead.header as u64);
+        let wire_bytes = bytes + packets * (overhead.ip + overhead.link) as u64;
+        Self {
+            packets,
+            goodput_bytes,
+            payload_bytes: bytes,
+            wire_bytes,
+            goodput_bps: bits_per_sec(goodput_bytes, seconds),
+            wire_bps: bits_per_sec(wire_bytes, seconds),
+        }
+    }
+
+    /// Share of the bits on the wire that were application data (%).
+    pub fn efficiency_percent(&self) -> f64 {
+        if self.wire_bytes == 0 {
+            return 0.0;
+        }
+        self.goodput_bytes as f64 / self.wire_bytes as f64 * 100.0
+    }
+
+    /// Mean bytes per packet besides the application's data: udpopt's
+    /// header, the IP, UDP and link-layer headers, and those of every extra
+    /// fragment when they were counted.
+    pub fn overhead_per_packet(&self) -> f64 {
+        if self.packets == 0 {
+            return 0.0;
+        }
+        self.wire_bytes.saturating_sub(self.goodput_bytes) as f64 / self.packets as f64
+    }
+}
+
+/// `bytes` over `seconds` in bits/sec, 0 without time
+fn bits_per_sec(bytes: u64, seconds: f64) -> f64 {
+    if seconds > 0.0 {
+        bytes as f64 * 8.0 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// How closely the client kept to its configured rate, part of [`ClientReport`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateCompliance {
+    /// Packets the configured rate asked for over the test duration.
+    pub intended_packets: u64,
+    /// Packets actually sent.
+    pub achieved_packets: u64,
+    /// How far behind its pacing schedule the sender was at the end of the test.
+    pub drift: Duration,
+    /// Intervals that ended more than one pacing slot behind schedule,
+    /// i.e. the sender could not keep up (usually CPU-limited).
+    pub behind_intervals: u64,
+}
+
+impl RateCompliance {
+    /// Achieved packets as a fraction of the intended ones, 1.0 without a target.
+    pub fn achieved_ratio(&self) -> f64 {
+        if self.intended_packets == 0 {
+            return 1.0;
+        }
+        self.achieved_packets as f64 / self.intended_packets as f64
+    }
+}
+
+/// Share of the intended packets a client must send to be on target
+const ON_TARGET_RATIO: f64 = 0.99;
+
+/// What held a client below its target rate, see [`ClientReport::limit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendLimit {
+    /// The send buffer filled up: the NIC or the path below the socket
+    /// drains slower than the client sends.
+    Network,
+    /// The client could not keep to its schedule, usually CPU-limited.
+    Sender,
+}
+
+/// Sleep accuracy of the sending host, measured before the test, part of [`ClientReport`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SleepCalibration {
+    /// Worst delay past its deadline of a short sleep.
+    pub overshoot: Duration,
+    /// Time before a send the client stops sleeping and spins instead.
+    pub threshold: Duration,
+}
+
+/// Transmit statistics returned by `UdpClient::run`, the client-side counterpart of [`TestResult`].
+#[derive(Debug, Clone)]
+pub struct ClientReport {
+    /// Number of data packets sent (FIN excluded).
+    pub packets_sent: u64,
+    /// Number of bytes sent (FIN excluded).
+    pub bytes_sent: u64,
+    /// Number of sends that failed with a transient error and were skipped.
+    pub send_failures: u64,
+    /// Number of sends retried after a transient error, see
+    /// `ClientBuilder::send_retry`.
+    pub send_retries: u64,
+    /// Sends refused because the socket send buffer was full (`WouldBlock`),
+    /// counted in `send_failures` too. Only non-blocking sends see them, see
+    /// `ClientBuilder::nonblocking_send`.
+    pub would_block: u64,
+    /// Sends refused as longer than the path MTU with the don't-fragment bit
+    /// set, see `ClientBuilder::dont_fragment`, counted in `send_failures`
+    /// too.
+    pub too_big: u64,
+    /// How the datagrams fit the path MTU, `None` when the socket does not
+    /// tell it (not Linux, or not connected).
+    pub fragmentation: Option<Fragmentation>,
+    /// Total duration of the transmission, pauses excluded.
+    pub duration: Duration,
+    /// Average achieved bitrate over the whole test (bits/sec).
+    pub bitrate_bps: f64,
+    /// Average achieved packet rate over the whole test (packets/sec).
+    pub pps: f64,
+    /// Achieved bitrate per reporting interval.
+    pub intervals: Vec<ClientInterval>,
+    /// Distribution of how late each packet left compared to its pacing target (µs).
+    pub pacing_error: Histogram,
+    /// Distribution of how far each gap between two packets was from the
+    /// scheduled one (µs). Low when the host kept to the schedule, so a rate
+    /// deviation seen by the server then comes from the network.
+    pub interval_error: Histogram,
+    /// Sleep accuracy the pacing was calibrated with (sync client).
+    pub calibration: Option<SleepCalibration>,
+    /// Intended vs achieved rate.
+    pub compliance: RateCompliance,
+    /// Packets impaired on purpose by `ClientBuilder::fault_injector`.
+    pub faults: FaultStats,
+    /// When the peer was unreachable, in order. Only a client given an
+    /// `ClientBuilder::unreachable_grace` outlasts an episode; refusals
+    /// closer than the grace belong to the same one.
+    pub unreachable: Vec<UnreachableEpisode>,
+    /// Random id of the run, carried by all its packets (native protocol).
+    pub session: u32,
+    /// The server's measurements, when requested with `ClientBuilder::remote_results`.
+    pub remote: Option<TestResult>,
+    /// Address and port the server received the test from, as a NAT on the
+    /// way rewrote them, when the server sent it with its results. Differs
+    /// from the socket's local address behind a NAT.
+    pub public_endpoint: Option<SocketAddr>,
+}
+
+impl ClientReport {
+    /// What held the client below its target rate, `None` when it kept to it.
+    ///
+    /// A full send buffer only shows with non-blocking sends: a blocking
+    /// `send` waits for room instead and the run looks sender-limited.
+    /// Unpaced ([`Rate::Unlimited`](crate::Rate::Unlimited)), whatever did
+    /// not fill the send buffer was the sender.
+    pub fn limit(&self) -> Option<SendLimit> {
+        if self.would_block > 0 {
+            return Some(SendLimit::Network);
+        }
+        let compliance = &self.compliance;
+        let behind = if compliance.intended_packets == 0 {
+            self.packets_sent > 0
+        } else {
+            compliance.behind_intervals > 0 || compliance.achieved_ratio() < ON_TARGET_RATIO
+        };
+        behind.then_some(SendLimit::Sender)
+    }
+
+    /// Goodput and estimated on-wire throughput of what the client sent.
+    ///
+    /// With a known path MTU the IP packets and bytes of
+    /// [`ClientReport::fragmentation`] replace the estimate of `overhead.ip`,
+    /// every fragment counted.
+    pub fn throughput(&self, overhead: Overhead) -> Throughput {
+        let seconds = self.duration.as_secs_f64();
+        let mut throughput = Throughput::new(self.packets_sent, self.bytes_sent, seconds, overhead);
+        if let Some(f) = self.fragmentation {
+            throughput.wire_bytes = f.ip_bytes + f.ip_packets * overhead.link as u64;
+            throughput.wire_bps = bits_per_sec(throughput.wire_bytes, seconds);
+        }
+        throughput
+    }
+}
+
+/// Power-of-two bucket of `value`: 0 for 1, 1 for 2, 2 for 3..=4 and so on,
+/// capped at the last of `buckets`
+fn log2_bucket(value: u64, buckets: usize) -> usize {
+    (value.max(1).next_power_of_two().trailing_zeros() as usize).min(buckets - 1)
 }
 
 /// The mean is the sum of a collection of numbers divided by the number of numbers in the collection.
@@ -103,6 +1321,31 @@ pub fn mean(v: &[f64]) -> f64 {
     sum / v.len() as f64
 }
 
+/// The population standard deviation, the square root of the mean squared
+/// distance to the mean (reference)[https://en.wikipedia.org/wiki/Standard_deviation]
+pub fn std_dev(v: &[f64]) -> f64 {
+    if v.is_empty() {
+        return 0.0;
+    }
+
+    let mean = mean(v);
+    let variance = v.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / v.len() as f64;
+    variance.sqrt()
+}
+
+/// The value below which `p` percent of the sample falls, interpolated
+/// linearly between the two closest ranks; the 50th percentile is the median.
+pub fn percentile_f64(v: &mut [f64], p: f64) -> f64 {
+    if v.is_empty() {
+        return 0.0;
+    }
+
+    v.sort_by(f64::total_cmp);
+    let rank = p.clamp(0.0, 100.0) / 100.0 * (v.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    v[low] + (v[high] - v[low]) * (rank - low as f64)
+}
+
 // The median is the number separating the higher half of a data sample, a population, or
 /// a probability distribution, from the lower half (reference)[http://en.wikipedia.org/wiki/Median)
 pub fn median_f64(v: &mut [f64]) -> f64 {
@@ -110,7 +1353,7 @@ pub fn median_f64(v: &mut [f64]) -> f64 {
         return 0.0;
     }
 
-    v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    v.sort_by(f64::total_cmp);
     let mid = v.len() / 2;
 
     if v.len() % 2 == 1 {
@@ -142,8 +1385,23 @@ mod tests {
             time: Duration::from_millis(time_ms),
             jitter_ms,
             out_of_order,
+            duplicates: 0,
+            late: 0,
+            recovered: 0,
+            ce_marked: 0,
+            auth_failures: 0,
+            foreign: 0,
+            malformed: 0,
             recommended_bitrate: 0,
+            target_bitrate: 0,
+            nic: None,
+            socket_drops: 0,
+            sent: 0,
+            start_time: None,
+            offset: Duration::ZERO,
+            bitrate_bps: 0.0,
         }
+        .with_bitrate()
     }
 
     #[test]
@@ -160,7 +1418,9 @@ mod tests {
         assert_eq!(result.total_packets, 400);
         assert_eq!(result.total_lost, 0);
         assert_eq!(result.total_bytes, 80000);
-        assert_eq!(result.total_out_of_order, 3);
+        // 0 + 1 + 2 + 3, accumulated over the intervals
+        assert_eq!(result.total_out_of_order, 6);
+        assert_eq!(result.ooo_percent, 1.5);
 
         // Bitrates: 64000, 128000, 192000, 256000
         assert_eq!(result.mean_bitrate, 160000.0);
@@ -169,5 +1429,279 @@ mod tests {
         // Jitters: 1.0, 2.0, 3.0, 4.0
         assert_eq!(result.mean_jitter, 2.5);
         assert_eq!(result.median_jitter, 2.5);
+
+        assert_eq!(
+            (result.min_bitrate, result.max_bitrate),
+            (64000.0, 256000.0)
+        );
+        assert!((result.std_dev_bitrate - 71554.175).abs() < 0.001);
+        assert_eq!((result.min_jitter, result.max_jitter), (1.0, 4.0));
+        assert!((result.std_dev_jitter - 1.118).abs() < 0.001);
+        assert_eq!(result.loss_percent, 0.0);
+        let percentiles: Vec<f64> = result.percentiles.iter().map(|p| p.percentile).collect();
+        assert_eq!(percentiles, DEFAULT_PERCENTILES);
+    }
+
+    #[test]
+    fn test_totals_are_consistent() {
+        let intervals: Vec<IntervalResult> = (0..7u64)
+            .map(|i| IntervalResult {
+                duplicates: i % 2,
+                socket_drops: i % 3 / 2,
+                ..create_interval(50 + 13 * i, i % 3, 1200 * i as usize, 250, 0.1, i * i % 5)
+            })
+            .collect();
+        let result = TestResult::from_intervals(&intervals);
+
+        let sum = |f: fn(&IntervalResult) -> u64| intervals.iter().map(f).sum::<u64>();
+        assert_eq!(result.total_packets, sum(|i| i.received));
+        assert_eq!(result.total_lost, sum(|i| i.lost));
+        assert_eq!(result.total_out_of_order, sum(|i| i.out_of_order));
+        assert_eq!(result.total_duplicates, sum(|i| i.duplicates));
+        assert_eq!(result.total_socket_drops, sum(|i| i.socket_drops));
+        assert_eq!(
+            result.path_loss(),
+            result.total_lost - result.total_socket_drops
+        );
+        assert_eq!(result.total_bytes as u64, sum(|i| i.bytes as u64));
+        assert_eq!(result.total_time, 1.75);
+
+        let sent = result.total_packets + result.total_lost;
+        assert!(
+            (result.loss_percent / 100.0 * sent as f64 - result.total_lost as f64).abs() < 1e-9
+        );
+        assert!(
+            (result.ooo_percent / 100.0 * result.total_packets as f64
+                - result.total_out_of_order as f64)
+                .abs()
+                < 1e-9
+        );
+        // the totals' rates lie within the intervals' ones
+        let (low, high) = intervals
+            .iter()
+            .map(IntervalResult::loss_percent)
+            .fold((f64::MAX, f64::MIN), |(l, h), p| (l.min(p), h.max(p)));
+        assert!(low <= result.loss_percent && result.loss_percent <= high);
+        assert!(
+            result.min_bitrate <= result.mean_bitrate && result.mean_bitrate <= result.max_bitrate
+        );
+
+        // a single interval is its own total
+        let one = &intervals[4];
+        let single = TestResult::from_intervals(std::slice::from_ref(one));
+        assert_eq!(single.loss_percent, one.loss_percent());
+        assert_eq!(single.ooo_percent, one.ooo_percent());
+        assert_eq!(single.total_out_of_order, one.out_of_order);
+    }
+
+    #[test]
+    fn test_diff() {
+        let steady = |bytes: usize, lost, jitter| {
+            (0..10)
+                .map(|i| create_interval(1000 - lost, lost, bytes + i * 1000, 1000, jitter, 0))
+                .collect::<Vec<_>>()
+        };
+        let before = TestResult::from_intervals(&steady(1_000_000, 1, 1.0));
+
+        // same link, a bit of noise
+        let noisy = TestResult::from_intervals(&steady(1_000_100, 2, 1.0));
+        let cmp = before.diff(&noisy);
+        assert_eq!(cmp.throughput.significance, Significance::WithinNoise);
+        assert_eq!(cmp.loss.significance, Significance::Inconclusive);
+        assert_eq!(cmp.jitter.significance, Significance::WithinNoise);
+        assert!(!cmp.is_regression() && !cmp.is_improvement());
+
+        // after a bad config push
+        let worse = TestResult::from_intervals(&steady(800_000, 20, 3.0));
+        let cmp = before.diff(&worse);
+        assert_eq!(cmp.throughput.before, before.mean_bitrate);
+        assert!((cmp.throughput.percent_change.unwrap() + 19.91).abs() < 0.01);
+        assert!(cmp.throughput.decreased());
+        assert!((cmp.loss.delta - 1.9).abs() < 1e-9);
+        assert!((cmp.loss.percent_change.unwrap() - 1900.0).abs() < 1e-6);
+        assert!(cmp.loss.increased());
+        assert_eq!(cmp.jitter.percent_change, Some(200.0));
+        assert!(cmp.is_regression());
+        assert!(worse.diff(&before).is_improvement());
+
+        // nothing to be relative to
+        let from_zero = MetricDelta::between(0.0, 1.0, Significance::Significant);
+        assert_eq!(from_zero.percent_change, None);
+    }
+
+    #[test]
+    fn test_percentiles_and_loss() {
+        let intervals: Vec<IntervalResult> = (1..=10)
+            .map(|i| create_interval(90, i, i as usize * 1000, 1000, i as f64, 0))
+            .collect();
+        let result = TestResult::from_intervals_with_percentiles(&intervals, &[0.0, 50.0, 95.0]);
+
+        // 55 lost out of 955
+        assert!((result.loss_percent - 5.759).abs() < 0.001);
+        let [min, median, p95] = result.percentiles[..] else {
+            panic!("{:?}", result.percentiles);
+        };
+        assert_eq!((min.bitrate, min.jitter_ms), (8000.0, 1.0));
+        assert_eq!(median.bitrate, result.median_bitrate);
+        assert_eq!(median.jitter_ms, 5.5);
+        // between the 9th and the 10th interval
+        assert!((p95.bitrate - 76400.0).abs() < 1e-6);
+        assert!((p95.jitter_ms - 9.55).abs() < 1e-9);
+
+        let empty = TestResult::from_intervals(&[]);
+        assert_eq!(empty.max_bitrate, 0.0);
+        assert_eq!(empty.percentiles[2].jitter_ms, 0.0);
+
+        // a NaN sorts last instead of panicking
+        let mut odd = [3.0, f64::NAN, 1.0, 2.0];
+        assert_eq!(median_f64(&mut odd), 2.5);
+        assert_eq!(percentile_f64(&mut odd, 0.0), 1.0);
+        assert!(percentile_f64(&mut odd, 100.0).is_nan());
+    }
+
+    #[test]
+    fn test_with_latency() {
+        let mut hist = Histogram::new();
+        for us in 1..=1000 {
+            hist.record(us);
+        }
+
+        let result = TestResult::from_intervals(&[create_interval(100, 0, 8000, 1000, 1.0, 0)])
+            .with_latency(&hist);
+
+        assert!((result.latency.p50_ms - 0.5).abs() < 0.01);
+        assert!((result.latency.p90_ms - 0.9).abs() < 0.02);
+        assert!((result.latency.p99_ms - 0.99).abs() < 0.02);
+        assert!(result.latency.p999_ms <= 1.0);
+    }
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        let mut reorder = ReorderStats::default();
+        reorder.record_reordered(3);
+        let mut ipdv = Histogram::new();
+        for us in [120, 250, 4000] {
+            ipdv.record(us);
+        }
+        let late = IntervalResult {
+            late: 2,
+            duplicates: 1,
+            socket_drops: 1,
+            ..create_interval(90, 1, 7000, 500, 0.5, 0)
+        };
+        let result =
+            TestResult::from_intervals(&[create_interval(100, 3, 8000, 1000, 1.5, 2), late])
+                .with_reorder(&reorder)
+                .with_ipdv(&ipdv)
+                .with_loss_pattern(&LossStats {
+                    packets: 100,
+                    lost: 4,
+                    bursts: 2,
+                    max_burst: 3,
+                    run_lengths: [1, 0, 1, 0, 0, 0, 0, 0],
+                })
+                .with_sizes(&SizeStats {
+                    packets: [7, 0, 0, 0, 4, 1, 0],
+                    bytes: [448, 0, 0, 0, 2304, 1500, 0],
+                })
+                .with_packets_sent(198);
+        assert_eq!(result.total_socket_drops, 1);
+        let bytes = result.to_bytes();
+        assert_eq!(
+            bytes.len(),
+            TestResult::ENCODED_SIZE + DEFAULT_PERCENTILES.len() * TestResult::PERCENTILE_SIZE
+        );
+        assert_eq!(TestResult::from_bytes(&bytes), Some(result));
+        assert_eq!(TestResult::from_bytes(&bytes[1..]), None);
+    }
+
+    #[test]
+    fn test_size_stats() {
+        let mut stats = SizeStats::default();
+        for len in [32, 64, 65, 576, 1500, 1518, 1519, 9000] {
+            stats.record(len);
+        }
+        assert_eq!(stats.packets, [2, 1, 0, 0, 1, 2, 2]);
+        assert_eq!(stats.bytes[5], 1500 + 1518);
+        assert_eq!(SizeStats::bucket_range(0), (0, Some(64)));
+        assert_eq!(SizeStats::bucket_range(1), (65, Some(127)));
+        assert_eq!(SizeStats::bucket_range(SIZE_BUCKETS - 1), (1519, None));
+    }
+
+    #[test]
+    fn test_throughput() {
+        // 1000 packets of 1052 bytes in 2 seconds
+        let overhead = Overhead::ipv4();
+        let t = Throughput::new(1000, 1_052_000, 2.0, overhead);
+        assert_eq!(t.goodput_bytes, 1_000_000);
+        assert_eq!(t.wire_bytes, 1_052_000 + 1000 * (28 + 38));
+        assert_eq!(t.goodput_bps, 4e6);
+        assert_eq!(t.wire_bps, t.wire_bytes as f64 * 4.0);
+        assert!((t.efficiency_percent() - 89.445).abs() < 1e-3);
+        assert_eq!(t.overhead_per_packet(), (52 + 28 + 38) as f64);
+
+        // what the interface counters of an IPv6 host see
+        let counters = Overhead::ipv6().link(Overhead::ETHERNET_HEADER);
+        let t = Throughput::new(1000, 1_052_000, 2.0, counters);
+        assert_eq!(t.wire_bytes, 1_052_000 + 1000 * (48 + 14));
+
+        let mut result = TestResult::from_intervals(&[create_interval(10, 0, 600, 1000, 0.0, 0)]);
+        result.total_time = 1.0;
+        let t = result.throughput(overhead);
+        assert_eq!((t.payload_bytes, t.goodput_bytes), (600, 80));
+        let none = Throughput::new(0, 0, 0.0, overhead);
+        assert_eq!(
+            (none.efficiency_percent(), none.overhead_per_packet()),
+            (0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_reorder_stats() {
+        let mut stats = ReorderStats::default();
+        for _ in 0..6 {
+            stats.record_in_order();
+        }
+        for d in [1, 2, 3, 4, 100] {
+            stats.record_reordered(d);
+        }
+        stats.record_reordered(1_000_000);
+
+        assert_eq!(stats.packets, 12);
+        assert_eq!(stats.reordered, 6);
+        assert_eq!(stats.max_distance, 1_000_000);
+        assert_eq!(stats.displacement, [1, 1, 2, 0, 0, 0, 0, 2]);
+        assert_eq!(stats.reordered_percent(), 50.0);
+    }
+
+    #[test]
+    fn test_gilbert_elliott_estimate() {
+        let mut stats = LossStats {
+            packets: 1000,
+            lost: 20,
+            ..Default::default()
+        };
+        for len in [1, 1, 2, 16] {
+            stats.record_burst(len);
+        }
+
+        assert_eq!(stats.max_burst, 16);
+        assert_eq!(stats.run_lengths, [2, 1, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(stats.mean_burst(), 5.0);
+
+        let ge = stats.gilbert_elliott();
+        assert!((ge.p - 4.0 / 980.0).abs() < 1e-12);
+        assert!((ge.r - 0.2).abs() < 1e-12);
+        assert!((ge.loss_rate() - 0.02).abs() < 1e-12);
+        assert!((ge.mean_burst() - 5.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_voip_quality() {
+        let clean = TestResult::from_intervals(&[create_interval(1000, 0, 8000, 1000, 1.0, 0)]);
+        let lossy = TestResult::from_intervals(&[create_interval(950, 50, 8000, 1000, 1.0, 0)]);
+
+        assert!(clean.voip_quality().mos > 4.3);
+        assert!(lossy.voip_quality().mos < clean.voip_quality().mos);
     }
 }
@@ -0,0 +1,356 @@
+//! # Reverse mode admission
+//!
+//! In reverse mode the server sends to the client, at the bitrate and for
+//! the duration the client's [`ReverseRequest`] asks for. A spoofed request
+//! would turn the server into a reflector streaming at any address, so a
+//! server only answers the requests that pass its [`ReversePolicy`]:
+//!
+//! - the request carries an accepted access token (see [`crate::access`])
+//!   or is signed with the pre-shared key (see `auth`), a policy checking
+//!   neither refuses every request;
+//! - the bitrate, duration and packet length are capped at the server's
+//!   [`ReverseLimits`].
+//!
+//! The client sends its token and signature after the request with
+//! [`ReverseCredentials`].
+//!
+//! ```
+//! use std::net::SocketAddr;
+//! use std::time::Duration;
+//! use udpopt::{ReverseRequest, access::AccessControl};
+//! use udpopt::reverse::{ReverseCredentials, ReverseLimits, ReversePolicy};
+//!
+//! let request = ReverseRequest {
+//!     bitrate_bps: 1e9,
+//!     payload_size: 1200,
+//!     duration: Duration::from_secs(10),
+//!     dscp: 0,
+//!     step_bps: 0.0,
+//!     step_every: Duration::ZERO,
+//! };
+//! let sent = ReverseCredentials::new().token("lab-42").encode(request);
+//!
+//! let mut policy = ReversePolicy::new(ReverseLimits::default())
+//!     .access_control(AccessControl::allowlist(["lab-42"]));
+//! let from: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+//! let admitted = policy.admit(&sent, from).unwrap();
+//! assert_eq!(admitted.bitrate_bps, 100e6);
+//! ```
+
+use std::{net::SocketAddr, time::Duration};
+
+#[cfg(feature = "auth")]
+use crate::auth::{AuthKey, TAG_LEN};
+use crate::{
+    access::{AccessControl, MAX_TOKEN_LEN},
+    errors::UdpOptError,
+    utils::{net_utils::ReverseRequest, udp_data::HEADER_SIZE},
+};
+
+/// Most a server sends for one reverse-mode request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReverseLimits {
+    /// Highest bitrate, the last step of a step load included (bits/sec).
+    pub max_bitrate_bps: f64,
+    /// Longest test.
+    pub max_duration: Duration,
+    /// Longest packet (bytes), header included.
+    pub max_payload_size: usize,
+}
+
+impl Default for ReverseLimits {
+    /// 100 Mbit/s for a minute, in packets fitting an Ethernet frame.
+    fn default() -> Self {
+        Self {
+            max_bitrate_bps: 100e6,
+            max_duration: Duration::from_secs(60),
+            max_payload_size: 1472,
+        }
+    }
+}
+
+impl ReverseLimits {
+    /// `request` capped at the limits, `None` for a request that makes no
+    /// sense: no positive bitrate or no duration.
+    pub fn apply(&self, request: &ReverseRequest) -> Option<ReverseRequest> {
+        if !request.bitrate_bps.is_finite()
+            || request.bitrate_bps <= 0.0
+            || request.duration.is_zero()
+        {
+            return None;
+        }
+        let mut capped = ReverseRequest {
+            bitrate_bps: request.bitrate_bps.min(self.max_bitrate_bps),
+            payload_size: request
+                .payload_size
+                .clamp(HEADER_SIZE, self.max_payload_size.max(HEADER_SIZE)),
+            duration: request.duration.min(self.max_duration),
+            ..*request
+        };
+        if !capped.step_every.is_zero() {
+            // the last step must stay under the cap too
+            let steps = capped
+                .duration
+                .as_millis()
+                .div_ceil(capped.step_every.as_millis().max(1));
+            let headroom = self.max_bitrate_bps - capped.bitrate_bps;
+            let step_bps = if capped.step_bps.is_finite() {
+                capped.step_bps.max(0.0)
+            } else {
+                0.0
+            };
+            capped.step_bps = match steps {
+                0 | 1 => 0.0,
+                steps => step_bps.min(headroom / (steps - 1) as f64),
+            };
+        }
+        Some(capped)
+    }
+}
+
+/// What a client sends after its [`ReverseRequest`] to be let in by a
+/// server's [`ReversePolicy`].
+#[derive(Clone, Default, PartialEq)]
+pub struct ReverseCredentials {
+    token: Vec<u8>,
+    #[cfg(feature = "auth")]
+    key: Option<AuthKey>,
+}
+
+impl ReverseCredentials {
+    /// No token and no signature.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Presents `token` to a server with access control.
+    ///
+    /// # Panics
+    /// If the token is longer than [`MAX_TOKEN_LEN`] bytes.
+    pub fn token(mut self, token: impl Into<Vec<u8>>) -> Self {
+        let token = token.into();
+        assert!(
+            token.len() <= MAX_TOKEN_LEN,
+            "an access token is at most {MAX_TOKEN_LEN} bytes"
+        );
+        self.token = token;
+        self
+    }
+
+    /// Signs the request with `key`, for a server sharing it.
+    #[cfg(feature = "auth")]
+    pub fn auth(mut self, key: AuthKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Encodes `request` followed by the token, its length first, and the
+    /// signature of all of it, if any.
+    pub fn encode(&self, request: ReverseRequest) -> Vec<u8> {
+        let mut buf = request.to_bytes().to_vec();
+        buf.push(self.token.len() as u8);
+        buf.extend_from_slice(&self.token);
+        #[cfg(feature = "auth")]
+        if let Some(key) = &self.key {
+            buf.resize(buf.len() + TAG_LEN, 0);
+            key.sign(&mut buf);
+        }
+        buf
+    }
+}
+
+impl std::fmt::Debug for ReverseCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the token is a secret, keep it out of the logs
+        let mut debug = f.debug_struct("ReverseCredentials");
+        debug.field("token", &!self.token.is_empty());
+        #[cfg(feature = "auth")]
+        debug.field("key", &self.key);
+        debug.finish()
+    }
+}
+
+/// Which reverse-mode requests a server answers, see the [module docs](self).
+#[derive(Debug)]
+pub struct ReversePolicy {
+    limits: ReverseLimits,
+    access: Option<AccessControl>,
+    #[cfg(feature = "auth")]
+    key: Option<AuthKey>,
+}
+
+impl ReversePolicy {
+    /// Caps the requests at `limits`. Refuses them all until
+    /// [`access_control`](Self::access_control) or `auth` says which
+    /// clients to trust.
+    pub fn new(limits: ReverseLimits) -> Self {
+        Self {
+            limits,
+            access: None,
+            #[cfg(feature = "auth")]
+            key: None,
+        }
+    }
+
+    /// Only answers the requests carrying a token `access` accepts.
+    pub fn access_control(mut self, access: AccessControl) -> Self {
+        self.access = Some(access);
+        self
+    }
+
+    /// Only answers the requests signed with `key`.
+    #[cfg(feature = "auth")]
+    pub fn auth(mut self, key: AuthKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// The limits the requests are capped at.
+    pub fn limits(&self) -> &ReverseLimits {
+        &self.limits
+    }
+
+    /// The request encoded in `buf`, from `from`, capped at the limits.
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReverseRefused`] if the policy trusts no client, the
+    /// signature or the token is missing or wrong, or `buf` holds no
+    /// sensible request.
+    pub fn admit(&mut self, buf: &[u8], from: SocketAddr) -> Result<ReverseRequest, UdpOptError> {
+        let refused = |reason: &str| Err(UdpOptError::ReverseRefused(reason.to_string()));
+        #[cfg(feature = "auth")]
+        let signed = self.key.is_some();
+        #[cfg(not(feature = "auth"))]
+        let signed = false;
+        if !signed && self.access.is_none() {
+            return refused("the server checks neither a key nor a token");
+        }
+
+        #[cfg(feature = "auth")]
+        let buf = match &self.key {
+            Some(key) if key.verify_message(buf) => &buf[..buf.len() - TAG_LEN],
+            Some(_) => return refused("not signed with the server's key"),
+            None => buf,
+        };
+        let Some((request, token)) = split(buf) else {
+            return refused("malformed request");
+        };
+        if let Some(access) = &mut self.access
+            && !access.accepts(token, from)
+        {
+            return refused("token not accepted");
+        }
+        match self.limits.apply(&request) {
+            Some(request) => Ok(request),
+            None => refused("no bitrate or duration"),
+        }
+    }
+}
+
+/// The request and the token of what [`ReverseCredentials::encode`] made,
+/// the signature removed
+fn split(buf: &[u8]) -> Option<(ReverseRequest, &[u8])> {
+    let (request, token) = buf.split_at_checked(ReverseRequest::SIZE)?;
+    let (&len, token) = token.split_first()?;
+    if token.len() != usize::from(len) {
+        return None;
+    }
+    Some((ReverseRequest::parse(request)?, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(bitrate_bps: f64, secs: u64) -> ReverseRequest {
+        ReverseRequest {
+            bitrate_bps,
+            payload_size: 1200,
+            duration: Duration::from_secs(secs),
+            dscp: 0,
+            step_bps: 0.0,
+            step_every: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = ReverseLimits::default();
+        let capped = limits
+            .apply(&ReverseRequest {
+                payload_size: 9000,
+                ..request(1e12, 3600)
+            })
+            .unwrap();
+        assert_eq!(capped.bitrate_bps, 100e6);
+        assert_eq!(capped.duration, Duration::from_secs(60));
+        assert_eq!(capped.payload_size, 1472);
+        let short = ReverseRequest {
+            payload_size: 1,
+            ..request(1e6, 10)
+        };
+        assert_eq!(limits.apply(&short).unwrap().payload_size, HEADER_SIZE);
+        let within = request(10e6, 10);
+        assert_eq!(limits.apply(&within), Some(within));
+        assert_eq!(limits.apply(&request(f64::NAN, 10)), None);
+        assert_eq!(limits.apply(&request(-1.0, 10)), None);
+        assert_eq!(limits.apply(&request(1e6, 0)), None);
+
+        // 6 steps of 10 s from 50M: the last one stays at 100M
+        let steps = ReverseRequest {
+            step_bps: 50e6,
+            step_every: Duration::from_secs(10),
+            ..request(50e6, 60)
+        };
+        let capped = limits.apply(&steps).unwrap();
+        assert_eq!(capped.bitrate_bps + 5.0 * capped.step_bps, 100e6);
+    }
+
+    #[test]
+    fn test_admit_by_token() {
+        let from: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let req = request(10e6, 10);
+        let sent = ReverseCredentials::new().token("lab-42").encode(req);
+
+        let mut open = ReversePolicy::new(ReverseLimits::default());
+        assert!(matches!(
+            open.admit(&sent, from),
+            Err(UdpOptError::ReverseRefused(_))
+        ));
+
+        let mut policy = ReversePolicy::new(ReverseLimits::default())
+            .access_control(AccessControl::allowlist(["lab-42"]));
+        assert_eq!(policy.admit(&sent, from).unwrap(), req);
+        let wrong = ReverseCredentials::new().token("lab-43").encode(req);
+        assert!(policy.admit(&wrong, from).is_err());
+        let anonymous = ReverseCredentials::new().encode(req);
+        assert!(policy.admit(&anonymous, from).is_err());
+        // a request of an older client, without credentials
+        assert!(policy.admit(&req.to_bytes(), from).is_err());
+        assert!(policy.admit(&sent[..sent.len() - 1], from).is_err());
+    }
+
+    #[cfg(feature = "auth")]
+    #[test]
+    fn test_admit_by_key() {
+        let from: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let key = AuthKey::from_passphrase("secret");
+        let req = request(10e6, 10);
+        let sent = ReverseCredentials::new().auth(key.clone()).encode(req);
+
+        let mut policy = ReversePolicy::new(ReverseLimits::default()).auth(key);
+        assert_eq!(policy.admit(&sent, from).unwrap(), req);
+        let mut forged = sent.clone();
+        forged[10] ^= 1;
+        assert!(policy.admit(&forged, from).is_err());
+        let other = ReverseCredentials::new()
+            .auth(AuthKey::from_passphrase("other"))
+            .encode(req);
+        assert!(policy.admit(&other, from).is_err());
+        assert!(
+            policy
+                .admit(&ReverseCredentials::new().encode(req), from)
+                .is_err()
+        );
+    }
+}
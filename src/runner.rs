@@ -0,0 +1,240 @@
+//! Series of back-to-back tests against one server.
+//!
+//! [`TestRunner`] runs the client once per configured bitrate on the same
+//! connected socket, collects the server's [`TestResult`] of every run and
+//! compares their throughput in a [`RunComparison`].
+//!
+//! The server must answer every run: build it with
+//! [`crate::ServerBuilder::send_results_to_client`] and call `run` again after
+//! each test, like the `udpopt server` command does.
+//!
+//! ```no_run
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//! use udpopt::runner::TestRunner;
+//!
+//! let mut sock = UdpSocket::bind("0.0.0.0:0").unwrap();
+//! sock.connect("192.0.2.10:5201").unwrap();
+//!
+//! let series = TestRunner::with_bitrates([10e6, 50e6, 100e6], 1200, Duration::from_secs(5))
+//!     .run(&mut sock)
+//!     .unwrap();
+//! let cmp = series.comparison;
+//! println!("best run {} at {:.1} bps, stddev {:.1} bps", cmp.best_run, cmp.best_bps, cmp.stddev_bps);
+//! ```
+
+use std::{sync::mpsc, thread, time::Duration};
+
+use crate::{
+    builder::ClientBuilder,
+    errors::UdpOptError,
+    result::{ClientReport, TestResult, mean, std_dev},
+    socket::DatagramSocket,
+    utils::net_utils::ClientCommand,
+};
+
+/// Pause between two runs, so the server is back in `run` for the next one
+const DEFAULT_GAP: Duration = Duration::from_millis(500);
+
+/// How long every run waits for the server's results after its FIN
+const DEFAULT_RESULTS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs several client tests back to back and compares them.
+#[derive(Debug, Clone)]
+pub struct TestRunner {
+    /// Bitrate of every run (bits/sec)
+    bitrates: Vec<f64>,
+    payload_size: usize,
+    duration: Duration,
+    gap: Duration,
+    results_timeout: Duration,
+}
+
+impl TestRunner {
+    /// `runs` tests at the same `bitrate_bps`.
+    pub fn new(runs: usize, bitrate_bps: f64, payload_size: usize, duration: Duration) -> Self {
+        Self::with_bitrates(vec![bitrate_bps; runs], payload_size, duration)
+    }
+
+    /// One test per bitrate, in order.
+    pub fn with_bitrates(
+        bitrates: impl IntoIterator<Item = f64>,
+        payload_size: usize,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            bitrates: bitrates.into_iter().collect(),
+            payload_size,
+            duration,
+            gap: DEFAULT_GAP,
+            results_timeout: DEFAULT_RESULTS_TIMEOUT,
+        }
+    }
+
+    /// Sets the pause between two runs (default 500 ms).
+    pub fn gap(mut self, gap: Duration) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    /// Sets how long every run waits for the server's results (default 2 seconds).
+    pub fn results_timeout(mut self, timeout: Duration) -> Self {
+        self.results_timeout = timeout;
+        self
+    }
+
+    /// Runs all the tests on the connected `sock`.
+    ///
+    /// # Errors
+    /// - [`UdpOptError::MissingResults`] if the server did not answer a run.
+    /// - any error of [`crate::UdpClient::run`].
+    pub fn run<S: DatagramSocket>(&self, sock: &mut S) -> Result<RunSeries, UdpOptError> {
+        let mut results = Vec::with_capacity(self.bitrates.len());
+        let mut reports = Vec::with_capacity(self.bitrates.len());
+
+        for (run, &bitrate_bps) in self.bitrates.iter().enumerate() {
+            if run > 0 {
+                thread::sleep(self.gap);
+            }
+            event!(info, run, bitrate_bps, "starting run");
+            let (tx, rx) = mpsc::channel();
+            let mut client = ClientBuilder::new(bitrate_bps, self.payload_size, self.duration)
+                .remote_results(self.results_timeout)
+                .build(rx);
+            let _ = tx.send(ClientCommand::Start);
+
+            let mut report = client.run(sock)?;
+            let result = report
+                .remote
+                .take()
+                .ok_or(UdpOptError::MissingResults(run))?;
+            results.push(result);
+            reports.push(report);
+        }
+
+        Ok(RunSeries {
+            comparison: RunComparison::from_results(&results),
+            results,
+            reports,
+        })
+    }
+}
+
+/// Outcome of a [`TestRunner`] series.
+#[derive(Debug, Clone)]
+pub struct RunSeries {
+    /// The server's results, one per run.
+    pub results: Vec<TestResult>,
+    /// What the client sent in every run (`remote` moved to `results`).
+    pub reports: Vec<ClientReport>,
+    /// Throughput across the runs.
+    pub comparison: RunComparison,
+}
+
+/// Throughput (the runs' mean bitrate) compared across runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunComparison {
+    /// Number of runs.
+    pub runs: usize,
+    /// Index of the run with the highest throughput.
+    pub best_run: usize,
+    /// Highest throughput (bits/sec).
+    pub best_bps: f64,
+    /// Index of the run with the lowest throughput.
+    pub worst_run: usize,
+    /// Lowest throughput (bits/sec).
+    pub worst_bps: f64,
+    /// Mean throughput over the runs (bits/sec).
+    pub mean_bps: f64,
+    /// Population standard deviation of the throughput (bits/sec).
+    pub stddev_bps: f64,
+}
+
+impl RunComparison {
+    /// Compares the `mean_bitrate` of `results`.
+    pub fn from_results(results: &[TestResult]) -> Self {
+        let rates: Vec<f64> = results.iter().map(|r| r.mean_bitrate).collect();
+        if rates.is_empty() {
+            return Self::default();
+        }
+        let (mut best_run, mut worst_run) = (0, 0);
+        for (i, &rate) in rates.iter().enumerate() {
+            if rate > rates[best_run] {
+                best_run = i;
+            }
+            if rate < rates[worst_run] {
+                worst_run = i;
+            }
+        }
+        Self {
+            runs: rates.len(),
+            best_run,
+            best_bps: rates[best_run],
+            worst_run,
+            worst_bps: rates[worst_run],
+            mean_bps: mean(&rates),
+            stddev_bps: std_dev(&rates),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ServerBuilder, ServerCommand};
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_comparison() {
+        let results: Vec<TestResult> = [2e6, 4e6, 6e6]
+            .into_iter()
+            .map(|bps| {
+                let mut result = TestResult::from_intervals(&[]);
+                result.mean_bitrate = bps;
+                result
+            })
+            .collect();
+        let cmp = RunComparison::from_results(&results);
+        assert_eq!(cmp.runs, 3);
+        assert_eq!((cmp.best_run, cmp.best_bps), (2, 6e6));
+        assert_eq!((cmp.worst_run, cmp.worst_bps), (0, 2e6));
+        assert_eq!(cmp.mean_bps, 4e6);
+        assert!((cmp.stddev_bps - 1_632_993.16).abs() < 1.0);
+        assert_eq!(RunComparison::from_results(&[]), RunComparison::default());
+    }
+
+    #[test]
+    fn test_runs_against_one_server() {
+        let mut server_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_sock
+            .connect(server_sock.local_addr().unwrap())
+            .unwrap();
+
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(100))
+            .send_results_to_client()
+            .build(server_rx);
+        let server_thread = thread::spawn(move || {
+            for _ in 0..3 {
+                server_tx.send(ServerCommand::Start).unwrap();
+                server.run(&mut server_sock).unwrap();
+            }
+        });
+
+        let series = TestRunner::with_bitrates([1e6, 2e6, 4e6], 500, Duration::from_millis(300))
+            .gap(Duration::from_millis(100))
+            .run(&mut client_sock)
+            .unwrap();
+        server_thread.join().unwrap();
+
+        assert_eq!(series.results.len(), 3);
+        assert_eq!(series.reports.len(), 3);
+        assert!(series.reports.iter().all(|r| r.remote.is_none()));
+        let cmp = series.comparison;
+        assert_eq!(cmp.runs, 3);
+        assert_eq!(cmp.best_run, 2);
+        assert_eq!(cmp.worst_run, 0);
+        assert!(cmp.worst_bps <= cmp.mean_bps && cmp.mean_bps <= cmp.best_bps);
+    }
+}
@@ -0,0 +1,397 @@
+//! Async runtime abstraction.
+//!
+//! [`AsyncUdpClient`](crate::AsyncUdpClient) and [`AsyncUdpServer`](crate::AsyncUdpServer)
+//! only need a UDP socket and a timer, both provided by the [`AsyncDatagram`]
+//! trait. It is implemented for:
+//!
+//! - `tokio::net::UdpSocket` (always available),
+//! - `async_io::Async<std::net::UdpSocket>` (feature `async-io`), which is the
+//!   socket type of smol and async-std.
+//!
+//! The ECN methods and `bind_device` follow
+//! [`DatagramSocket`](crate::DatagramSocket)'s: both sockets support them
+//! on Linux, other implementations are `Unsupported` by default.
+//!
+//! The control and result channels are `tokio::sync::mpsc` channels, they do
+//! not need a tokio runtime. Under smol, bind an `Async<UdpSocket>`, connect
+//! the inner socket for the client and pass it to `run` like a tokio socket.
+
+use std::{
+    future::{Future, poll_fn},
+    io,
+    net::SocketAddr,
+    pin::pin,
+    task::Poll,
+    time::Duration,
+};
+
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+
+use crate::socket::{Ecn, Interface, RecvMeta};
+#[cfg(target_os = "linux")]
+use crate::utils::{device, ecn, pmtu};
+
+/// A UDP socket of an async runtime, together with that runtime's timer.
+pub trait AsyncDatagram {
+    /// Sends on a connected socket.
+    fn send(&self, buf: &[u8]) -> impl Future<Output = io::Result<usize>>;
+
+    /// Receives on a connected socket.
+    fn recv(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<usize>>;
+
+    /// Sends to `target`.
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> impl Future<Output = io::Result<usize>>;
+
+    /// Receives a datagram and its sender.
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>>;
+
+    /// Address of the connected peer.
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Waits for `duration` on the runtime the socket belongs to.
+    fn sleep(duration: Duration) -> impl Future<Output = ()>;
+
+    /// Marks the datagrams sent from now on ECN-capable, [`Ecn::Ect0`].
+    fn set_ect0(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Sets the whole TOS byte (traffic class on IPv6) of the datagrams sent
+    /// from now on, the DSCP in its six high bits and the ECN codepoint in
+    /// the two low ones.
+    fn set_tos(&self, tos: u8) -> io::Result<()> {
+        let _ = tos;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Makes [`recv_from_ecn`](Self::recv_from_ecn) report the codepoint of
+    /// the received datagrams.
+    fn enable_ecn(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Binds the socket to `interface`, see
+    /// [`DatagramSocket::bind_device`](crate::DatagramSocket::bind_device).
+    fn bind_device(&self, interface: &Interface) -> io::Result<()> {
+        let _ = interface;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Sets or clears the don't-fragment bit, see
+    /// [`DatagramSocket::set_dont_fragment`](crate::DatagramSocket::set_dont_fragment).
+    fn set_dont_fragment(&self, on: bool) -> io::Result<()> {
+        let _ = on;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Path MTU towards the connected peer, see
+    /// [`DatagramSocket::path_mtu`](crate::DatagramSocket::path_mtu).
+    fn path_mtu(&self) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Receives a datagram, its sender and its ECN codepoint, `None` when
+    /// unknown.
+    fn recv_from_ecn(
+        &self,
+        buf: &mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr, Option<Ecn>)>> {
+        async {
+            let (len, from) = self.recv_from(buf).await?;
+            Ok((len, from, None))
+        }
+    }
+
+    /// Makes [`recv_from_meta`](Self::recv_from_meta) report the datagrams
+    /// dropped with a full receive buffer, see
+    /// [`DatagramSocket::enable_drop_count`](crate::DatagramSocket::enable_drop_count).
+    fn enable_drop_count(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Receives a datagram, its sender and what the kernel told about it,
+    /// see [`RecvMeta`].
+    fn recv_from_meta(
+        &self,
+        buf: &mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr, RecvMeta)>> {
+        async {
+            let (len, from, ecn) = self.recv_from_ecn(buf).await?;
+            Ok((len, from, RecvMeta { ecn, dropped: None }))
+        }
+    }
+}
+
+impl AsyncDatagram for tokio::net::UdpSocket {
+    fn send(&self, buf: &[u8]) -> impl Future<Output = io::Result<usize>> {
+        tokio::net::UdpSocket::send(self, buf)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<usize>> {
+        tokio::net::UdpSocket::recv(self, buf)
+    }
+
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> impl Future<Output = io::Result<usize>> {
+        tokio::net::UdpSocket::send_to(self, buf, target)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>> {
+        tokio::net::UdpSocket::recv_from(self, buf)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        tokio::net::UdpSocket::peer_addr(self)
+    }
+
+    fn sleep(duration: Duration) -> impl Future<Output = ()> {
+        tokio::time::sleep(duration)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_ect0(&self) -> io::Result<()> {
+        let ipv6 = self.local_addr()?.is_ipv6();
+        ecn::set_tos(self.as_raw_fd(), ipv6, Ecn::Ect0.bits())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_tos(&self, tos: u8) -> io::Result<()> {
+        ecn::set_tos(self.as_raw_fd(), self.local_addr()?.is_ipv6(), tos)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn enable_ecn(&self) -> io::Result<()> {
+        ecn::enable_recv_tos(self.as_raw_fd(), self.local_addr()?.is_ipv6())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn bind_device(&self, interface: &Interface) -> io::Result<()> {
+        device::bind(self.as_raw_fd(), self.local_addr()?.is_ipv6(), interface)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_dont_fragment(&self, on: bool) -> io::Result<()> {
+        pmtu::set_dont_fragment(self.as_raw_fd(), self.local_addr()?.is_ipv6(), on)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn path_mtu(&self) -> io::Result<usize> {
+        pmtu::path_mtu(self.as_raw_fd(), self.local_addr()?.is_ipv6())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn recv_from_ecn(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<Ecn>)> {
+        let (len, from, tos) = self
+            .async_io(tokio::io::Interest::READABLE, || {
+                ecn::recv_from_tos(self.as_raw_fd(), buf)
+            })
+            .await?;
+        Ok((len, from, tos.map(Ecn::from_tos)))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn enable_drop_count(&self) -> io::Result<()> {
+        ecn::enable_drop_count(self.as_raw_fd())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn recv_from_meta(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, RecvMeta)> {
+        let (len, from, ancillary) = self
+            .async_io(tokio::io::Interest::READABLE, || {
+                ecn::recv_from_ancillary(self.as_raw_fd(), buf)
+            })
+            .await?;
+        Ok((len, from, RecvMeta::from(ancillary)))
+    }
+}
+
+#[cfg(feature = "async-io")]
+impl AsyncDatagram for async_io::Async<std::net::UdpSocket> {
+    fn send(&self, buf: &[u8]) -> impl Future<Output = io::Result<usize>> {
+        async_io::Async::<std::net::UdpSocket>::send(self, buf)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<usize>> {
+        async_io::Async::<std::net::UdpSocket>::recv(self, buf)
+    }
+
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> impl Future<Output = io::Result<usize>> {
+        async_io::Async::<std::net::UdpSocket>::send_to(self, buf, target)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>> {
+        async_io::Async::<std::net::UdpSocket>::recv_from(self, buf)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().peer_addr()
+    }
+
+    async fn sleep(duration: Duration) {
+        async_io::Timer::after(duration).await;
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_ect0(&self) -> io::Result<()> {
+        let ipv6 = self.get_ref().local_addr()?.is_ipv6();
+        ecn::set_tos(self.as_raw_fd(), ipv6, Ecn::Ect0.bits())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_tos(&self, tos: u8) -> io::Result<()> {
+        let ipv6 = self.get_ref().local_addr()?.is_ipv6();
+        ecn::set_tos(self.as_raw_fd(), ipv6, tos)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn enable_ecn(&self) -> io::Result<()> {
+        ecn::enable_recv_tos(self.as_raw_fd(), self.get_ref().local_addr()?.is_ipv6())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn bind_device(&self, interface: &Interface) -> io::Result<()> {
+        let ipv6 = self.get_ref().local_addr()?.is_ipv6();
+        device::bind(self.as_raw_fd(), ipv6, interface)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_dont_fragment(&self, on: bool) -> io::Result<()> {
+        let ipv6 = self.get_ref().local_addr()?.is_ipv6();
+        pmtu::set_dont_fragment(self.as_raw_fd(), ipv6, on)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn path_mtu(&self) -> io::Result<usize> {
+        pmtu::path_mtu(self.as_raw_fd(), self.get_ref().local_addr()?.is_ipv6())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn recv_from_ecn(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<Ecn>)> {
+        let (len, from, tos) = self
+            .read_with(|sock| ecn::recv_from_tos(sock.as_raw_fd(), buf))
+            .await?;
+        Ok((len, from, tos.map(Ecn::from_tos)))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn enable_drop_count(&self) -> io::Result<()> {
+        ecn::enable_drop_count(self.as_raw_fd())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn recv_from_meta(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, RecvMeta)> {
+        let (len, from, ancillary) = self
+            .read_with(|sock| ecn::recv_from_ancillary(sock.as_raw_fd(), buf))
+            .await?;
+        Ok((len, from, RecvMeta::from(ancillary)))
+    }
+}
+
+/// Runs `fut` for at most `duration`, `None` if it did not complete in time.
+pub(crate) async fn timeout<S: AsyncDatagram, F: Future>(
+    duration: Duration,
+    fut: F,
+) -> Option<F::Output> {
+    let mut fut = pin!(fut);
+    let mut sleep = pin!(S::sleep(duration));
+    poll_fn(|cx| {
+        if let Poll::Ready(out) = fut.as_mut().poll(cx) {
+            return Poll::Ready(Some(out));
+        }
+        sleep.as_mut().poll(cx).map(|_| None)
+    })
+    .await
+}
+
+/// Lets the other tasks of the executor run once.
+pub(crate) async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let slow = timeout::<tokio::net::UdpSocket, _>(
+            Duration::from_millis(20),
+            tokio::time::sleep(Duration::from_secs(5)),
+        );
+        assert_eq!(slow.await, None);
+
+        let fast = timeout::<tokio::net::UdpSocket, _>(Duration::from_secs(5), async { 7 });
+        assert_eq!(fast.await, Some(7));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_tokio_socket_ecn() {
+        let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server.local_addr().unwrap()).await.unwrap();
+        AsyncDatagram::enable_ecn(&server).unwrap();
+        AsyncDatagram::enable_drop_count(&server).unwrap();
+        AsyncDatagram::set_ect0(&client).unwrap();
+        AsyncDatagram::send(&client, b"marked").await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let (len, from, meta) = AsyncDatagram::recv_from_meta(&server, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(&buf[..len], b"marked");
+        assert_eq!(from, client.local_addr().unwrap());
+        assert_eq!(meta.ecn, Some(Ecn::Ect0));
+        assert_eq!(meta.dropped, None);
+    }
+
+    #[cfg(feature = "async-io")]
+    #[test]
+    fn test_async_io_runtime() {
+        use crate::{ClientBuilder, ClientCommand, ServerBuilder, ServerCommand};
+        use async_io::Async;
+        use std::net::UdpSocket;
+        use tokio::sync::mpsc::channel;
+
+        let mut server_sock = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+        let server_addr = server_sock.get_ref().local_addr().unwrap();
+        let mut client_sock = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+        client_sock.get_ref().connect(server_addr).unwrap();
+
+        let (server_tx, server_rx) = channel(4);
+        let mut server = ServerBuilder::new(Duration::from_secs(1))
+            .send_results_to_client()
+            .build_async(server_rx);
+        let server_handle =
+            std::thread::spawn(move || async_io::block_on(server.run(&mut server_sock)));
+
+        let (client_tx, client_rx) = channel(4);
+        let mut client = ClientBuilder::new(1_000_000.0, 500, Duration::from_millis(200))
+            .remote_results(Duration::from_secs(2))
+            .build_async(client_rx);
+
+        server_tx.try_send(ServerCommand::Start).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        client_tx.try_send(ClientCommand::Start).unwrap();
+        let report = async_io::block_on(client.run(&mut client_sock)).unwrap();
+
+        let results = server_handle.join().unwrap().unwrap();
+        assert!(report.packets_sent > 0);
+        // the first packet only starts the measurement, the FIN is counted
+        assert_eq!(
+            results.iter().map(|r| r.received).sum::<u64>(),
+            report.packets_sent
+        );
+        assert_eq!(report.remote.unwrap().total_packets, report.packets_sent);
+    }
+}
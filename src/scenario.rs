@@ -0,0 +1,723 @@
+//! # Multi-flow scenarios
+//!
+//! RRUL-style mixed traffic: several flows run at once, each with its own
+//! direction, bitrate and DSCP, e.g. a bulk upload in a low-priority class
+//! next to a voice-like download in EF, to see how the classes share the link
+//! under load.
+//!
+//! A [`Scenario`] is built in code or read from TOML with
+//! [`Scenario::from_toml`] (`serde` feature):
+//!
+//! ```toml
+//! duration = 30
+//! source_ports = "40000-40009"
+//!
+//! [[flow]]
+//! name = "bulk"
+//! server = "192.0.2.10:5201"
+//! bitrate_bps = 50e6
+//! dscp = 8
+//!
+//! [[flow]]
+//! name = "voice"
+//! server = "192.0.2.10:5202"
+//! direction = "download"
+//! bitrate_bps = 64e3
+//! payload_size = 200
+//! dscp = 46
+//! token = "lab-42"
+//! ```
+//!
+//! Every flow needs its own `udpopt server`, one per port, since a server
+//! measures one test at a time. The flows send from the address of their
+//! `bind`, any by default; with `source_ports` the flows without a `bind`
+//! port take the free ports of the range in order, so a firewall or NAT
+//! policy matching on source ports sees the ports it expects.
+//!
+//! Upload flows send from here and get the server's results back, download
+//! flows ask the server to send with a [`ReverseRequest`] and measure here.
+//! A flow's `token` and `psk` go with its packets and its request, for a
+//! server with `--allow-token` or `--psk`, which `--allow-reverse` needs.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use udpopt::scenario::{Flow, Scenario};
+//! use udpopt::sink::Direction;
+//!
+//! let server: std::net::IpAddr = "192.0.2.10".parse().unwrap();
+//! let outcome = Scenario::new(Duration::from_secs(30))
+//!     .flow(Flow::new("bulk", (server, 5201).into(), Direction::Upload, 50e6).dscp(8))
+//!     .flow(
+//!         Flow::new("voice", (server, 5202).into(), Direction::Download, 64e3)
+//!             .payload_size(200)
+//!             .dscp(46),
+//!     )
+//!     .run()
+//!     .unwrap();
+//! for flow in &outcome.flows {
+//!     let result = &flow.result;
+//!     println!("{}: {:.0} bps, {:.2} % lost", flow.name, result.mean_bitrate, result.loss_percent);
+//! }
+//! println!("total {:.0} bps", outcome.total.bitrate_bps);
+//! ```
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "auth")]
+use crate::auth::AuthKey;
+use crate::{
+    access::MAX_TOKEN_LEN,
+    builder::{ClientBuilder, ServerBuilder},
+    errors::UdpOptError,
+    result::TestResult,
+    reverse::ReverseCredentials,
+    sink::Direction,
+    socket::{self, PortRange},
+    utils::net_utils::{ClientCommand, ReverseRequest, ServerCommand},
+};
+
+/// Packet length of a flow when none is given
+const DEFAULT_PAYLOAD_SIZE: usize = 1200;
+/// How long the upload flows wait for the server's results after their FIN
+const RESULTS_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long after the planned end the download flows still wait for the
+/// servers before giving up
+const FINISH_GRACE: Duration = Duration::from_secs(10);
+
+/// One stream of a [`Scenario`].
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Flow {
+    /// Name of the flow in the results.
+    pub name: String,
+    /// Server of the flow, one per flow.
+    pub server: SocketAddr,
+    /// Upload sends from here, download asks the server to send.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub direction: Direction,
+    /// Bitrate of the flow (bits/sec).
+    pub bitrate_bps: f64,
+    /// Packet length (bytes), header included.
+    #[cfg_attr(feature = "serde", serde(default = "default_payload_size"))]
+    pub payload_size: usize,
+    /// DiffServ code point of the packets, 0 for best effort.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub dscp: u8,
+    /// Local address of the flow, a port other than 0 taking precedence
+    /// over [`Scenario::source_ports`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bind: Option<SocketAddr>,
+    /// Token presented to a server with access control (`--allow-token`).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub token: Option<String>,
+    /// Pre-shared key of a server checking signatures (`--psk`), needs the
+    /// `auth` feature.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub psk: Option<String>,
+}
+
+impl std::fmt::Debug for Flow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // token and key are secrets, keep them out of the logs
+        f.debug_struct("Flow")
+            .field("name", &self.name)
+            .field("server", &self.server)
+            .field("direction", &self.direction)
+            .field("bitrate_bps", &self.bitrate_bps)
+            .field("payload_size", &self.payload_size)
+            .field("dscp", &self.dscp)
+            .field("bind", &self.bind)
+            .field("token", &self.token.is_some())
+            .field("psk", &self.psk.is_some())
+            .finish()
+    }
+}
+
+#[cfg(feature = "serde")]
+fn default_payload_size() -> usize {
+    DEFAULT_PAYLOAD_SIZE
+}
+
+impl Flow {
+    /// A best-effort flow of 1200-byte packets.
+    pub fn new(
+        name: impl Into<String>,
+        server: SocketAddr,
+        direction: Direction,
+        bitrate_bps: f64,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            server,
+            direction,
+            bitrate_bps,
+            payload_size: DEFAULT_PAYLOAD_SIZE,
+            dscp: 0,
+            bind: None,
+            token: None,
+            psk: None,
+        }
+    }
+
+    /// Sets the packet length (bytes), header included.
+    pub fn payload_size(mut self, payload_size: usize) -> Self {
+        self.payload_size = payload_size;
+        self
+    }
+
+    /// Marks the packets with the DiffServ code point `dscp`.
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.dscp = dscp;
+        self
+    }
+
+    /// Sends from `local`, a port other than 0 taking precedence over the
+    /// source ports of the scenario.
+    pub fn bind(mut self, local: SocketAddr) -> Self {
+        self.bind = Some(local);
+        self
+    }
+
+    /// Presents `token` to a server with access control.
+    ///
+    /// # Panics
+    /// If the token is longer than [`MAX_TOKEN_LEN`] bytes.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        let token = token.into();
+        assert!(
+            token.len() <= MAX_TOKEN_LEN,
+            "an access token is at most {MAX_TOKEN_LEN} bytes"
+        );
+        self.token = Some(token);
+        self
+    }
+
+    /// Signs the packets and the reverse request with the key derived from
+    /// `psk`, for a server sharing it.
+    #[cfg(feature = "auth")]
+    pub fn psk(mut self, psk: impl Into<String>) -> Self {
+        self.psk = Some(psk.into());
+        self
+    }
+
+    /// What the flow presents to its server
+    fn credentials(&self) -> ReverseCredentials {
+        let mut credentials = ReverseCredentials::new();
+        if let Some(token) = &self.token {
+            credentials = credentials.token(token.as_str());
+        }
+        #[cfg(feature = "auth")]
+        if let Some(psk) = &self.psk {
+            credentials = credentials.auth(AuthKey::from_passphrase(psk));
+        }
+        credentials
+    }
+}
+
+/// Flows run together, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Scenario {
+    /// How long every flow runs.
+    #[cfg_attr(feature = "serde", serde(with = "crate::report::secs"))]
+    pub duration: Duration,
+    /// Length of the result intervals of the download flows.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::report::secs", default = "default_interval")
+    )]
+    pub interval: Duration,
+    /// Source ports the flows take in order, see the [module docs](self).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub source_ports: Option<PortRange>,
+    /// The flows, `[[flow]]` tables in TOML.
+    #[cfg_attr(feature = "serde", serde(rename = "flow", default))]
+    pub flows: Vec<Flow>,
+}
+
+#[cfg(feature = "serde")]
+fn default_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+impl Scenario {
+    /// A scenario of `duration` without flows, 1 second intervals.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            interval: Duration::from_secs(1),
+            source_ports: None,
+            flows: Vec::new(),
+        }
+    }
+
+    /// Sets the length of the result intervals of the download flows.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Makes the flows send from the free ports of `ports`, in order.
+    pub fn source_ports(mut self, ports: PortRange) -> Self {
+        self.source_ports = Some(ports);
+        self
+    }
+
+    /// Adds a flow.
+    pub fn flow(mut self, flow: Flow) -> Self {
+        self.flows.push(flow);
+        self
+    }
+
+    /// Reads a scenario from TOML, see the [module docs](self).
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFormat`] if `toml` is not a scenario.
+    #[cfg(feature = "serde")]
+    pub fn from_toml(toml: &str) -> Result<Self, UdpOptError> {
+        toml::from_str(toml).map_err(|e| UdpOptError::ReportFormat(e.to_string()))
+    }
+
+    /// Runs all the flows at once, each on its own thread and socket, and
+    /// waits for all of them.
+    ///
+    /// # Errors
+    /// - [`UdpOptError::InvalidScenario`] without flows, with a DSCP that
+    ///   does not fit in six bits, a token longer than [`MAX_TOKEN_LEN`], or
+    ///   a `psk` without the `auth` feature.
+    /// - [`UdpOptError::FlowFailed`] with the error of the first flow that
+    ///   failed, [`UdpOptError::BindFailed`] when its source port is taken,
+    ///   [`UdpOptError::Timeout`] for a download flow whose server never sent.
+    pub fn run(&self) -> Result<ScenarioResult, UdpOptError> {
+        if self.flows.is_empty() {
+            return Err(UdpOptError::InvalidScenario("no flows".to_string()));
+        }
+        if let Some(flow) = self.flows.iter().find(|flow| flow.dscp >= 64) {
+            return Err(UdpOptError::InvalidScenario(format!(
+                "DSCP {} of flow {} out of range",
+                flow.dscp, flow.name
+            )));
+        }
+        if let Some(flow) = self
+            .flows
+            .iter()
+            .find(|flow| flow.token.as_ref().is_some_and(|t| t.len() > MAX_TOKEN_LEN))
+        {
+            return Err(UdpOptError::InvalidScenario(format!(
+                "token of flow {} longer than {MAX_TOKEN_LEN} bytes",
+                flow.name
+            )));
+        }
+        #[cfg(not(feature = "auth"))]
+        if let Some(flow) = self.flows.iter().find(|flow| flow.psk.is_some()) {
+            return Err(UdpOptError::InvalidScenario(format!(
+                "psk of flow {} needs the auth feature",
+                flow.name
+            )));
+        }
+
+        let failed = |flow: &Flow| {
+            let name = flow.name.clone();
+            move |source| UdpOptError::FlowFailed {
+                flow: name,
+                source: Box::new(source),
+            }
+        };
+        // bound in order before any flow starts, so they take the source
+        // ports one after the other
+        let mut sockets = Vec::with_capacity(self.flows.len());
+        for flow in &self.flows {
+            sockets.push(self.connect(flow).map_err(failed(flow))?);
+        }
+        let locals: Vec<_> = sockets.iter().map(|s| s.local_addr().ok()).collect();
+
+        let deadline = self.duration + FINISH_GRACE;
+        let give_up = CancellationToken::new();
+        let outcomes = thread::scope(|s| {
+            // cancels the download flows still waiting at the deadline
+            let (done_tx, done_rx) = mpsc::channel::<()>();
+            let watchdog = give_up.clone();
+            s.spawn(move || {
+                if done_rx.recv_timeout(deadline) == Err(mpsc::RecvTimeoutError::Timeout) {
+                    watchdog.cancel();
+                }
+            });
+            let flows: Vec<_> = self
+                .flows
+                .iter()
+                .zip(sockets)
+                .enumerate()
+                .map(|(index, (flow, sock))| {
+                    let give_up = give_up.clone();
+                    s.spawn(move || self.run_flow(index, flow, sock, give_up, deadline))
+                })
+                .collect();
+            let outcomes: Vec<_> = flows
+                .into_iter()
+                .map(|flow| flow.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect();
+            drop(done_tx);
+            outcomes
+        });
+
+        let mut flows = Vec::with_capacity(self.flows.len());
+        for ((flow, outcome), local) in self.flows.iter().zip(outcomes).zip(locals) {
+            let result = outcome.map_err(failed(flow))?;
+            flows.push(FlowResult {
+                name: flow.name.clone(),
+                direction: flow.direction,
+                dscp: flow.dscp,
+                local,
+                result,
+            });
+        }
+        Ok(ScenarioResult {
+            total: ScenarioTotal::from_flows(&flows),
+            flows,
+        })
+    }
+
+    /// A socket for `flow` connected to its server, bound to its `bind`
+    /// address or on the first free source port
+    fn connect(&self, flow: &Flow) -> Result<UdpSocket, UdpOptError> {
+        let ports = match flow.bind {
+            Some(local) if local.port() != 0 => None,
+            _ => self.source_ports,
+        };
+        socket::connect(flow.bind, ports, flow.server)
+    }
+
+    /// Runs flow number `index` on `sock`, connected to its server
+    fn run_flow(
+        &self,
+        index: usize,
+        flow: &Flow,
+        mut sock: UdpSocket,
+        give_up: CancellationToken,
+        deadline: Duration,
+    ) -> Result<TestResult, UdpOptError> {
+        event!(
+            info,
+            flow = %flow.name,
+            server = %flow.server,
+            direction = flow.direction.as_str(),
+            "starting flow"
+        );
+
+        match flow.direction {
+            Direction::Upload => {
+                let (tx, rx) = mpsc::channel();
+                let mut builder =
+                    ClientBuilder::new(flow.bitrate_bps, flow.payload_size, self.duration)
+                        .remote_results(RESULTS_TIMEOUT);
+                if flow.dscp != 0 {
+                    builder = builder.dscp(flow.dscp);
+                }
+                if let Some(token) = &flow.token {
+                    builder = builder.access_token(token.as_str());
+                }
+                #[cfg(feature = "auth")]
+                if let Some(psk) = &flow.psk {
+                    builder = builder.auth(AuthKey::from_passphrase(psk));
+                }
+                let mut client = builder.build(rx);
+                let _ = tx.send(ClientCommand::Start);
+                client
+                    .run(&mut sock)?
+                    .remote
+                    .ok_or(UdpOptError::MissingResults(index))
+            }
+            Direction::Download => {
+                let request = ReverseRequest {
+                    bitrate_bps: flow.bitrate_bps,
+                    payload_size: flow.payload_size,
+                    duration: self.duration,
+                    dscp: flow.dscp,
+                    step_bps: 0.0,
+                    step_every: Duration::ZERO,
+                };
+                let request = flow.credentials().encode(request);
+                sock.send(&request)
+                    .map_err(UdpOptError::send_failed(Some(flow.server), request.len()))?;
+
+                let (tx, rx) = mpsc::channel();
+                let builder = ServerBuilder::new(self.interval)
+                    .send_results_to_client()
+                    .cancel_token(give_up);
+                #[cfg(feature = "auth")]
+                let builder = match &flow.psk {
+                    Some(psk) => builder.auth(AuthKey::from_passphrase(psk)),
+                    None => builder,
+                };
+                let mut server = builder.build(rx);
+                let _ = tx.send(ServerCommand::Start);
+                let intervals = server.run(&mut sock).map_err(|e| e.error)?;
+                if intervals.is_empty() {
+                    return Err(UdpOptError::Timeout(deadline));
+                }
+                Ok(TestResult::from_intervals(&intervals)
+                    .with_latency(server.latency_histogram())
+                    .with_ipdv(server.ipdv_histogram())
+                    .with_reorder(server.reorder_stats())
+                    .with_sizes(server.size_stats())
+                    .with_loss_pattern(server.loss_pattern())
+                    .with_packets_sent(server.client_sent().unwrap_or(0)))
+            }
+        }
+    }
+}
+
+/// Results of a flow of a [`Scenario`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlowResult {
+    /// [`Flow::name`]
+    pub name: String,
+    /// [`Flow::direction`]
+    pub direction: Direction,
+    /// [`Flow::dscp`]
+    pub dscp: u8,
+    /// Local address the flow ran from, its source port included.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub local: Option<SocketAddr>,
+    /// What the receiving side measured, the server for an upload.
+    pub result: TestResult,
+}
+
+/// The flows of a [`Scenario`] added up.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScenarioTotal {
+    /// Packets received over all flows.
+    pub packets: u64,
+    /// Packets lost over all flows.
+    pub lost: u64,
+    /// Bytes received over all flows.
+    pub bytes: usize,
+    /// Sum of the mean bitrates of the flows (bits/sec).
+    pub bitrate_bps: f64,
+    /// Sum of the mean bitrates of the upload flows (bits/sec).
+    pub upload_bps: f64,
+    /// Sum of the mean bitrates of the download flows (bits/sec).
+    pub download_bps: f64,
+    /// Percentage of the packets of all flows lost.
+    pub loss_percent: f64,
+    /// Highest mean jitter of a flow (ms).
+    pub max_jitter_ms: f64,
+}
+
+impl ScenarioTotal {
+    /// Adds up `flows`.
+    pub fn from_flows(flows: &[FlowResult]) -> Self {
+        let mut total = Self::default();
+        for flow in flows {
+            let result = &flow.result;
+            total.packets += result.total_packets;
+            total.lost += result.total_lost;
+            total.bytes += result.total_bytes;
+            total.bitrate_bps += result.mean_bitrate;
+            match flow.direction {
+                Direction::Upload => total.upload_bps += result.mean_bitrate,
+                Direction::Download => total.download_bps += result.mean_bitrate,
+            }
+            total.max_jitter_ms = total.max_jitter_ms.max(result.mean_jitter);
+        }
+        total.loss_percent = crate::utils::ui::loss_percent(total.packets, total.lost);
+        total
+    }
+}
+
+/// Outcome of [`Scenario::run`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScenarioResult {
+    /// Every flow, in the order of the scenario.
+    pub flows: Vec<FlowResult>,
+    /// All flows together.
+    pub total: ScenarioTotal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::AccessControl;
+    use crate::builder::{ClientBuilder, ServerBuilder};
+    use crate::reverse::{ReverseLimits, ReversePolicy};
+
+    #[test]
+    fn test_run_both_directions() {
+        let duration = Duration::from_millis(500);
+
+        // a server receiving the upload and sending back its results
+        let up = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let up_addr = up.local_addr().unwrap();
+        let uploads = thread::spawn(move || {
+            let mut up = up;
+            let (tx, rx) = mpsc::channel();
+            let mut server = ServerBuilder::new(Duration::from_millis(250))
+                .send_results_to_client()
+                .build(rx);
+            tx.send(ServerCommand::Start).unwrap();
+            server.run(&mut up).unwrap()
+        });
+
+        // a server answering the reverse request like `udpopt server`
+        let down = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let down_addr = down.local_addr().unwrap();
+        let downloads = thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (len, peer) = down.recv_from(&mut buf).unwrap();
+            // the request and an empty token
+            assert_eq!(len, ReverseRequest::SIZE + 1);
+            let request = ReverseRequest::parse(&buf[..ReverseRequest::SIZE]).unwrap();
+            let mut down = down;
+            down.connect(peer).unwrap();
+            let (tx, rx) = mpsc::channel();
+            let mut client =
+                ClientBuilder::new(request.bitrate_bps, request.payload_size, request.duration)
+                    .dscp(request.dscp)
+                    .remote_results(Duration::from_secs(2))
+                    .build(rx);
+            tx.send(ClientCommand::Start).unwrap();
+            (request, client.run(&mut down).unwrap())
+        });
+
+        // a port just freed starts the range
+        let first = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let ports = PortRange::new(first, first.saturating_add(50));
+        let outcome = Scenario::new(duration)
+            .interval(Duration::from_millis(250))
+            .source_ports(ports)
+            .flow(Flow::new("bulk", up_addr, Direction::Upload, 2e6))
+            .flow(
+                Flow::new("voice", down_addr, Direction::Download, 1e6)
+                    .payload_size(200)
+                    .dscp(46),
+            )
+            .run()
+            .unwrap();
+
+        assert!(!uploads.join().unwrap().is_empty());
+        let (request, sent) = downloads.join().unwrap();
+        assert_eq!((request.payload_size, request.dscp), (200, 46));
+        assert!(sent.remote.is_some());
+
+        assert_eq!(outcome.flows.len(), 2);
+        assert_eq!(outcome.flows[1].name, "voice");
+        let port = |i: usize| outcome.flows[i].local.unwrap().port();
+        assert!(ports.contains(port(0)) && ports.contains(port(1)));
+        assert!(port(0) < port(1));
+        let (bulk, voice) = (&outcome.flows[0].result, &outcome.flows[1].result);
+        assert!(bulk.total_packets > 0 && voice.total_packets > 0);
+        assert_eq!(
+            outcome.total.packets,
+            bulk.total_packets + voice.total_packets
+        );
+        assert_eq!(outcome.total.upload_bps, bulk.mean_bitrate);
+        assert_eq!(outcome.total.download_bps, voice.mean_bitrate);
+    }
+
+    #[test]
+    fn test_download_presents_its_token() {
+        let duration = Duration::from_millis(300);
+
+        // a server only answering the reverse requests carrying its token,
+        // like `udpopt server --allow-reverse --allow-token lab-42`
+        let down = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let down_addr = down.local_addr().unwrap();
+        let downloads = thread::spawn(move || {
+            let mut policy = ReversePolicy::new(ReverseLimits::default())
+                .access_control(AccessControl::allowlist(["lab-42"]));
+            let mut buf = [0u8; 512];
+            let (len, peer) = down.recv_from(&mut buf).unwrap();
+            let request = policy.admit(&buf[..len], peer).unwrap();
+            let mut down = down;
+            down.connect(peer).unwrap();
+            let (tx, rx) = mpsc::channel();
+            let mut client =
+                ClientBuilder::new(request.bitrate_bps, request.payload_size, request.duration)
+                    .remote_results(Duration::from_secs(2))
+                    .build(rx);
+            tx.send(ClientCommand::Start).unwrap();
+            client.run(&mut down).unwrap()
+        });
+
+        let outcome = Scenario::new(duration)
+            .interval(Duration::from_millis(100))
+            .flow(Flow::new("voice", down_addr, Direction::Download, 1e6).token("lab-42"))
+            .run()
+            .unwrap();
+        assert!(downloads.join().unwrap().remote.is_some());
+        assert!(outcome.flows[0].result.total_packets > 0);
+    }
+
+    #[test]
+    fn test_invalid_scenarios() {
+        let server = "127.0.0.1:9".parse().unwrap();
+        let empty = Scenario::new(Duration::from_secs(1));
+        assert!(matches!(empty.run(), Err(UdpOptError::InvalidScenario(_))));
+        let marked = empty.flow(Flow::new("x", server, Direction::Upload, 1e6).dscp(64));
+        assert!(matches!(marked.run(), Err(UdpOptError::InvalidScenario(_))));
+        let mut long = Flow::new("x", server, Direction::Download, 1e6);
+        long.token = Some("t".repeat(MAX_TOKEN_LEN + 1));
+        let long = Scenario::new(Duration::from_secs(1)).flow(long);
+        assert!(matches!(long.run(), Err(UdpOptError::InvalidScenario(_))));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_from_toml() {
+        let scenario = Scenario::from_toml(
+            r#"
+            duration = 2.5
+            source_ports = "40000-40009"
+
+            [[flow]]
+            name = "bulk"
+            server = "192.0.2.10:5201"
+            bitrate_bps = 50e6
+            dscp = 8
+            bind = "0.0.0.0:5000"
+
+            [[flow]]
+            name = "voice"
+            server = "192.0.2.10:5202"
+            direction = "download"
+            bitrate_bps = 64e3
+            payload_size = 200
+            dscp = 46
+            token = "lab-42"
+            "#,
+        )
+        .unwrap();
+        let server: std::net::IpAddr = "192.0.2.10".parse().unwrap();
+        assert_eq!(
+            scenario,
+            Scenario::new(Duration::from_millis(2500))
+                .source_ports(PortRange::new(40000, 40009))
+                .flow(
+                    Flow::new("bulk", (server, 5201).into(), Direction::Upload, 50e6)
+                        .dscp(8)
+                        .bind("0.0.0.0:5000".parse().unwrap())
+                )
+                .flow(
+                    Flow::new("voice", (server, 5202).into(), Direction::Download, 64e3)
+                        .payload_size(200)
+                        .dscp(46)
+                        .token("lab-42")
+                )
+        );
+        assert!(Scenario::from_toml("duration = 1\n[[flow]]\nname = \"x\"").is_err());
+    }
+}
@@ -4,21 +4,84 @@
 //! that can receive UDP packets, calculate bitrate periodically, and store
 //! interval-based test results.
 
-use crate::errors::UdpOptError;
-use crate::utils::net_utils::{IntervalResult, ServerCommand};
-use crate::utils::udp_data::{FLAG_FIN, HEADER_SIZE, UdpData, UdpHeader};
-use std::net::UdpSocket;
+use crate::builder::ServerConfig;
+use crate::errors::{RunError, UdpOptError};
+use crate::handle::ServerHandle;
+use crate::histogram::Histogram;
+use crate::result::{BandwidthEstimate, LossStats, ReorderStats, SizeStats, TestResult};
+use crate::socket::{DatagramSocket, RecvMeta};
+use crate::trace::TraceWriter;
+use crate::utils::interval_clock::IntervalClock;
+use crate::utils::net_utils::{
+    IntervalResult, PauseOutcome, ServerCommand, ServerStatus, discard_pending, instant_at,
+    wait_until_unless,
+};
+use crate::utils::results_exchange::send_results;
+use crate::utils::train::TrainTracker;
+use crate::utils::udp_data::{
+    FLAG_DATA, FLAG_FIN, FLAG_HEARTBEAT, FLAG_STATS, InterArrival, RECV_BUF_LEN, UdpData,
+    UdpHeader, now_micros,
+};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
 use std::sync::mpsc::{self, Receiver};
+use std::thread;
 use std::time::{Duration, Instant};
 
+/// Longest a blocking receive waits before the control channel is checked,
+/// so that `Stop` works without traffic
+const CONTROL_POLL: Duration = Duration::from_millis(10);
+
+/// A test received by [`UdpServer::serve_forever`].
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Rank of the test, from 1.
+    pub index: u64,
+    /// Id the client picked for its run, see [`ClientReport::session`](crate::ClientReport::session).
+    pub id: u32,
+    /// The client, its last address if it moved during the test.
+    pub peer: SocketAddr,
+    /// The intervals, as [`UdpServer::run`] returns them.
+    pub intervals: Vec<IntervalResult>,
+    /// Summary of the intervals and the per-run statistics.
+    pub summary: TestResult,
+}
+
+/// Why a session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SessionEnd {
+    /// FIN or idle client, the next one may come
+    Finished,
+    /// `Stop`, a cancellation or a shutdown
+    Stopped,
+}
+
 #[derive(Debug)]
 pub struct UdpServer {
-    ///Time between each result to save
-    interval: Duration,
+    /// Interval length, observers and trace output
+    config: ServerConfig,
     /// Collecting the interval results
     udp_result: Vec<IntervalResult>,
     /// Async receiver for control commands (`Start`, `Stop`) from another thread.
     control_rx: Receiver<ServerCommand>,
+    /// Per-packet latency histogram of the last run
+    latency: Histogram,
+    /// Delay variations between consecutive packets of the last run
+    ipdv: Histogram,
+    /// Reordering statistics of the last run
+    reorder: ReorderStats,
+    sizes: SizeStats,
+    /// Loss bursts of the last run
+    loss_pattern: LossStats,
+    /// Inter-arrival times of the last run
+    interarrival: Histogram,
+    /// Available bandwidth from the packet trains of the last run
+    bandwidth: Option<BandwidthEstimate>,
+    /// Packets the client of the last run says it sent
+    client_sent: Option<u64>,
+    /// Client and session id of the last test, its late packets cannot
+    /// start the next one
+    last_session: Option<(SocketAddr, u32)>,
 }
 
 impl UdpServer {
@@ -26,24 +89,56 @@ impl UdpServer {
     ///
     /// - `interval`: The duration for each result interval.
     /// - `control_rx`: A channel receiver to control start/stop commands.
-
     pub fn new(interval: Duration, control_rx: Receiver<ServerCommand>) -> Self {
+        Self::from_config(ServerConfig::new(interval), control_rx)
+    }
+
+    pub(crate) fn from_config(config: ServerConfig, control_rx: Receiver<ServerCommand>) -> Self {
         Self {
-            interval,
+            config,
             udp_result: Vec::with_capacity(100),
             control_rx,
+            latency: Histogram::new(),
+            ipdv: Histogram::new(),
+            reorder: ReorderStats::default(),
+            sizes: SizeStats::default(),
+            loss_pattern: LossStats::default(),
+            interarrival: Histogram::new(),
+            bandwidth: None,
+            client_sent: None,
+            last_session: None,
         }
     }
+
+    /// Logs every received packet (seq, timestamps, size, transit) to `writer`.
+    ///
+    /// See [`crate::trace`] for the bundled CSV and binary writers.
+    pub fn set_trace_writer<T: TraceWriter + 'static>(&mut self, writer: T) {
+        self.config.trace = Some(Box::new(writer));
+    }
     /// Runs the UDP server loop.
     ///
     /// - Waits for a `Start` command on the control channel before starting.
+    ///   `StartAt` starts at a wall-clock time instead, the datagrams that
+    ///   arrived before it are discarded.
+    /// - `Pause` freezes the interval timers until `Resume`.
+    ///
     /// The loop terminates when:
     /// - A `Stop` command is received.
     /// - A packet with the `FLAG_FIN` flag is received.
     /// - The control channel disconnects.
+    /// - Ctrl-C / SIGTERM arrives, if built with `stop_on_signal` (feature `signal`).
+    /// - No packet arrived for the idle timeout, the client is taken as gone.
+    ///
+    /// The socket need not be connected, e.g. bound to `0.0.0.0`: the test
+    /// locks onto the client of its first packet, see [`peer`](Self::peer),
+    /// `ServerBuilder::peer` and `ServerBuilder::lock_peer`.
+    ///
+    /// A link quiet for less than that only produces zero-traffic intervals.
+    /// The last, partial interval is always included in the results.
     ///
     ///
-    ///  /// # Arguments
+    /// # Arguments
     /// - `sock`: The bound UDP socket to receive packets from.
     ///
     /// Returns a slice of collected [`IntervalResult`]s.
@@ -51,90 +146,683 @@ impl UdpServer {
     ///
     /// # Errors
     ///
-    /// Returns [`UdpOptError::RecvFailed`] if a UDP receive error occurs.
-    /// Returns [`UdpOptError::SocketTimeout`] if a UDP receive error occurs.
-    /// Returns [`UdpOptError::UnexpectedCommand`] if a UDP receive error occurs.
-    /// Returns [`UdpOptError::ChannelClosed`] if a UDP receive error occurs.
-    pub fn run(&mut self, sock: &mut UdpSocket) -> Result<Vec<IntervalResult>, UdpOptError> {
-        println!("server start");
+    /// A [`RunError`] carrying the intervals completed before the failure and:
+    /// - [`UdpOptError::RecvFailed`] if a UDP receive error occurs.
+    /// - [`UdpOptError::SocketTimeout`] if the read timeout cannot be set.
+    /// - [`UdpOptError::UnexpectedCommand`] if a command other than the ones above arrives.
+    /// - [`UdpOptError::ChannelClosed`] if the control channel disconnects during a pause.
+    pub fn run<S: DatagramSocket>(
+        &mut self,
+        sock: &mut S,
+    ) -> Result<Vec<IntervalResult>, RunError> {
+        #[cfg(feature = "tracing")]
+        let _span = run_span!("udpopt_server", self.config.test_id).entered();
+        self.run_inner(sock).map_err(|error| RunError {
+            error,
+            intervals: std::mem::take(&mut self.udp_result),
+        })
+    }
+
+    /// Runs the server on its own thread, controlled through the returned
+    /// [`ServerHandle`] instead of the channel it was built with;
+    /// [`ServerBuilder::spawn`](crate::ServerBuilder::spawn) needs no channel at all.
+    pub fn spawn<S: DatagramSocket + Send + 'static>(mut self, mut sock: S) -> ServerHandle {
+        let (tx, rx) = mpsc::channel();
+        self.control_rx = rx;
+        ServerHandle::new(tx, thread::spawn(move || self.run(&mut sock)))
+    }
+
+    fn run_inner<S: DatagramSocket>(
+        &mut self,
+        sock: &mut S,
+    ) -> Result<Vec<IntervalResult>, UdpOptError> {
+        let mut buf = self.config.buffers.get(RECV_BUF_LEN);
+        if self.start(sock, &mut buf)? {
+            self.session(sock, &mut buf)?;
+        }
+        Ok(std::mem::take(&mut self.udp_result))
+    }
 
-        let mut udp_data = UdpData::new();
-        let mut buf = vec![0u8; 2048];
+    /// Serves one test after the other, for a permanent test reflector.
+    ///
+    /// Waits for `Start` (or `StartAt`) once, then receives tests like
+    /// [`run`](Self::run) back to back: after the FIN, or once the client is
+    /// idle, the state is reset, the late datagrams of the finished test are
+    /// dropped and the server waits for the next client. Every test that got
+    /// a packet is handed to `on_session` as soon as it ends, the per-run
+    /// getters such as [`latency_histogram`](Self::latency_histogram) then
+    /// describe it.
+    ///
+    /// Returns the number of sessions served once a `Stop` command, a
+    /// cancellation or a shutdown ends the loop; a test stopped midway is
+    /// still delivered.
+    ///
+    /// # Errors
+    ///
+    /// Like [`run`](Self::run), the [`RunError`] carries the intervals of the
+    /// failed session.
+    pub fn serve_forever<S: DatagramSocket>(
+        &mut self,
+        sock: &mut S,
+        mut on_session: impl FnMut(Session),
+    ) -> Result<u64, RunError> {
+        #[cfg(feature = "tracing")]
+        let _span = run_span!("udpopt_server", self.config.test_id).entered();
+        let mut buf = self.config.buffers.get(RECV_BUF_LEN);
+        let mut served = 0;
+        let res = self.start(sock, &mut buf).and_then(|started| {
+            if !started {
+                return Ok(served);
+            }
+            loop {
+                let Some(end) = self.session(sock, &mut buf)? else {
+                    break Ok(served);
+                };
+                let (peer, id) = self.last_session.expect("set by the first packet");
+                served += 1;
+                on_session(Session {
+                    index: served,
+                    id,
+                    peer,
+                    summary: self.summary(),
+                    intervals: std::mem::take(&mut self.udp_result),
+                });
+                if end == SessionEnd::Stopped {
+                    break Ok(served);
+                }
+                let _late = discard_pending(sock, &mut buf).map_err(UdpOptError::RecvFailed)?;
+                event!(debug, discarded = _late, "waiting for the next client");
+            }
+        });
+        res.map_err(|error| RunError {
+            error,
+            intervals: std::mem::take(&mut self.udp_result),
+        })
+    }
 
+    /// Waits for the start command and prepares the socket and the thread;
+    /// `false` if stopped while waiting for the time given by `StartAt`.
+    fn start<S: DatagramSocket>(
+        &mut self,
+        sock: &mut S,
+        buf: &mut [u8],
+    ) -> Result<bool, UdpOptError> {
         // wait for the start udp packet to start the test and set the buf lenght
-        match self.control_rx.recv() {
-            Ok(ServerCommand::Stop) => return Err(UdpOptError::UnexpectedCommand),
-            Ok(ServerCommand::Start) => {}
-            Err(_) => return Err(UdpOptError::ChannelClosed),
+        let start_at = loop {
+            match self.control_rx.recv() {
+                Ok(ServerCommand::Start) => break None,
+                Ok(ServerCommand::StartAt(at)) => break Some(at),
+                // nothing received yet
+                Ok(ServerCommand::Status(reply)) => {
+                    let _ = reply.send(ServerStatus::default());
+                }
+                Ok(_) => return Err(UdpOptError::UnexpectedCommand),
+                Err(_) => return Err(UdpOptError::ChannelClosed),
+            }
+        };
+
+        self.config.watch_signals()?;
+        self.config
+            .tuning
+            .apply()
+            .map_err(UdpOptError::SchedulingFailed)?;
+        if let Some(device) = &self.config.device {
+            sock.bind_device(device)
+                .map_err(UdpOptError::DeviceFailed)?;
         }
+        if self.config.ecn {
+            sock.enable_ecn().map_err(UdpOptError::EcnFailed)?;
+        }
+        if self.config.socket_drops {
+            sock.enable_drop_count()
+                .map_err(UdpOptError::DropCountFailed)?;
+        }
+        if let Some(at) = start_at {
+            event!(info, "waiting for the scheduled start");
+            if !wait_scheduled_start(&self.control_rx, &self.config, instant_at(at))? {
+                event!(info, "stopped before the scheduled start");
+                return Ok(false);
+            }
+            let _early = discard_pending(sock, buf).map_err(UdpOptError::RecvFailed)?;
+            event!(
+                debug,
+                discarded = _early,
+                "dropped datagrams that arrived early"
+            );
+        }
+        Ok(true)
+    }
 
-        // start measuring after reciving the first packt
-        let _ = sock
-            .recv(&mut buf)
-            .map_err(|e| UdpOptError::RecvFailed(e))?;
+    /// Receives one test into `udp_result`, from its first packet to its end.
+    ///
+    /// Only the packets of the session of the first one count, the packets
+    /// of another client run from the same source are ignored.
+    ///
+    /// Returns why the test ended, or `None` if it was stopped before any
+    /// packet arrived.
+    fn session<S: DatagramSocket>(
+        &mut self,
+        sock: &mut S,
+        buf: &mut [u8],
+    ) -> Result<Option<SessionEnd>, UdpOptError> {
+        let mut udp_data = UdpData::with_jitter(self.config.jitter)
+            .with_controller((self.config.congestion)())
+            .with_nic(self.config.nic_sampler()?);
+        let mut trains = TrainTracker::new();
+        let mut interarrival = InterArrival::new(self.config.interarrival_sampling);
+        event!(info, "test started, waiting for the first packet");
 
-        sock.set_read_timeout(Some(Duration::from_secs(2)))
+        // start measuring after reciving the first packt
+        let Some((mut peer, session)) = self.wait_first_packet(sock, buf)? else {
+            event!(info, "stopped before any packet arrived");
+            return Ok(None);
+        };
+        self.last_session = Some((peer, session));
+        record_peer!(peer);
+        event!(info, session, "first packet received");
+        let mut fin_received = false;
+        let mut end = SessionEnd::Finished;
+
+        sock.set_read_timeout(Some(self.config.recv_timeout.min(CONTROL_POLL)))
             .map_err(|_| UdpOptError::SocketTimeout)?;
-
-        println!("server     start");
+        let mut last_packet = Instant::now();
+        // arrivals are timed from here, the interval clock restarts
+        let epoch = Instant::now();
 
         let mut calc_instat = Instant::now();
         let calc_interval = Duration::from_millis(200);
-        let mut start = Instant::now();
-
-        println!("Collecting..");
+        let mut clock = IntervalClock::new(
+            udp_data.start_clock(),
+            self.config.interval,
+            self.config.alignment,
+        );
+        // the warm-up is received like the rest of the test and thrown away
+        let mut warming_up = !self.config.omit.is_zero();
 
         loop {
+            if self.config.shutdown_requested() {
+                event!(info, "shutdown requested, stopping");
+                end = SessionEnd::Stopped;
+                break;
+            }
+
             // Check control messages
             match self.control_rx.try_recv() {
-                Ok(ServerCommand::Stop) => break,
-                Ok(ServerCommand::Start) => return Err(UdpOptError::UnexpectedCommand),
+                Ok(ServerCommand::Stop) => {
+                    event!(info, "stop command received");
+                    end = SessionEnd::Stopped;
+                    break;
+                }
+                Ok(ServerCommand::Pause) => match self.wait_resume(ServerStatus::new(
+                    epoch.elapsed(),
+                    Some(peer),
+                    &self.udp_result,
+                    udp_data.current(),
+                ))? {
+                    PauseOutcome::Resumed(paused) => {
+                        // freeze the interval timers for the paused time
+                        clock.shift(paused);
+                        // nor does the idle timeout
+                        last_packet += paused;
+                        calc_instat += paused;
+                        interarrival.restart();
+                    }
+                    PauseOutcome::Stopped => {
+                        end = SessionEnd::Stopped;
+                        break;
+                    }
+                },
+                // the caller may have given up waiting
+                Ok(ServerCommand::Status(reply)) => {
+                    let _ = reply.send(ServerStatus::new(
+                        epoch.elapsed(),
+                        Some(peer),
+                        &self.udp_result,
+                        udp_data.current(),
+                    ));
+                }
+                Ok(_) => return Err(UdpOptError::UnexpectedCommand),
                 Err(mpsc::TryRecvError::Empty) => {}
                 Err(mpsc::TryRecvError::Disconnected) => return Err(UdpOptError::ChannelClosed),
             }
 
-            let len = sock
-                .recv(&mut buf)
-                .map_err(|e| UdpOptError::RecvFailed(e))?;
+            // before the receive, the ignored datagrams must not hold the boundaries back
+            if warming_up && clock.elapsed() >= self.config.omit {
+                event!(info, omitted = ?clock.elapsed(), "warm-up over");
+                udp_data.discard_statistics();
+                trains = TrainTracker::new();
+                interarrival = InterArrival::new(self.config.interarrival_sampling);
+                warming_up = false;
+                clock = IntervalClock::new(
+                    udp_data.start_clock(),
+                    self.config.interval,
+                    self.config.alignment,
+                );
+                calc_instat = clock.start();
+            }
+
+            // every boundary passed closes an interval, a gap in the traffic
+            // shows up as intervals with nothing received
+            while !warming_up && clock.is_due() {
+                let res = udp_data.get_interval_result(clock.advance());
+                udp_data.set_interval_start(clock.start());
+                self.config.emit_interval(&res);
+                self.config.sink_interval(peer, &res);
+                self.udp_result.push(res);
+            }
 
-            if len < HEADER_SIZE {
-                continue;
+            let received = recv_datagram(sock, buf, self.config.ancillary());
+            match received {
+                Ok((len, from, meta)) => {
+                    if self.config.socket_drops {
+                        udp_data.record_socket_drops(meta.dropped.unwrap_or(0));
+                    }
+                    if !self.config.allowed_source(from) {
+                        event!(trace, %from, "ignoring a source outside the allowed prefixes");
+                        udp_data.record_foreign();
+                        continue;
+                    }
+                    // stray datagrams from other applications must not skew the statistics
+                    let header = match UdpHeader::parse_received(buf, len) {
+                        Ok(header) => header,
+                        Err(_e) => {
+                            event!(trace, len, reason = %_e, "ignoring datagram");
+                            udp_data.record_malformed();
+                            continue;
+                        }
+                    };
+                    if !self.config.authentic(&buf[..len]) {
+                        event!(debug, %from, seq = header.seq, "authentication failed");
+                        udp_data.record_auth_failure();
+                        continue;
+                    }
+                    // e.g. the late packets of a previous run
+                    if header.session != session {
+                        event!(trace, %from, session = header.session, "ignoring another session");
+                        continue;
+                    }
+                    // only a genuine packet can move the test to another peer
+                    if from != peer {
+                        if self.config.lock_peer {
+                            event!(trace, %from, "ignoring another source, the peer is locked");
+                            continue;
+                        }
+                        if !self.config.accepts_peer(from)
+                            || !self.config.authorized(&buf[..len], from)
+                        {
+                            event!(trace, %from, "ignoring unauthorized source");
+                            continue;
+                        }
+                        event!(info, new_peer = %from, "peer changed");
+                    }
+                    peer = from;
+                    self.last_session = Some((peer, session));
+                    last_packet = Instant::now();
+
+                    match header.flags {
+                        // a report of the client, not a data packet
+                        FLAG_STATS => {
+                            event!(trace, sent = header.sent, "stats received");
+                            udp_data.record_sender_stats(&header);
+                            continue;
+                        }
+                        // only tells the client is still there
+                        FLAG_HEARTBEAT => {
+                            event!(trace, "heartbeat received");
+                            continue;
+                        }
+                        _ => {}
+                    }
+
+                    if let Some(trace) = self.config.trace.as_mut() {
+                        let (sec, usec) = now_micros();
+                        trace
+                            .write_record(&header.to_record(len, sec, usec))
+                            .map_err(UdpOptError::TraceFailed)?;
+                    }
+
+                    udp_data.process_packet(&buf[..len], &header, epoch.elapsed());
+                    udp_data.record_ecn(meta.ecn);
+                    trains.record(&header, len, last_packet);
+                    interarrival.record(last_packet);
+
+                    let time_to_calc_bitrate = calc_instat.elapsed();
+                    if time_to_calc_bitrate >= calc_interval {
+                        udp_data.calc_bitrate(time_to_calc_bitrate);
+                        calc_instat = Instant::now();
+                    }
+
+                    if header.flags == FLAG_FIN {
+                        event!(info, seq = header.seq, "FIN received");
+                        udp_data.finish();
+                        fin_received = true;
+                        break;
+                    }
+                }
+                // a quiet link is an idle interval, the control channel is
+                // checked again at the top of the loop
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if self
+                        .config
+                        .idle_timeout
+                        .is_some_and(|idle| last_packet.elapsed() >= idle)
+                    {
+                        event!(warn, idle = ?last_packet.elapsed(), "no traffic, client gone");
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(UdpOptError::RecvFailed(e)),
             }
+        }
 
-            let header = UdpHeader::read_header(&mut buf);
+        // the last interval ends with the FIN, not with the linger
+        let elapsed = clock.elapsed();
+        if fin_received && let Some(linger) = self.config.fin_linger {
+            let _tail = self.linger(sock, buf, linger, (peer, session), &mut udp_data, epoch)?;
+            event!(debug, received = _tail, "linger after the FIN over");
+        }
 
-            udp_data.process_packet(len, &header, start.elapsed());
+        // flush the last partial interval, or the only one if the interval
+        // time is bigger than the total time the client sent
+        if !warming_up && (self.udp_result.is_empty() || udp_data.has_pending()) {
+            let res = udp_data.get_interval_result(elapsed);
+            self.config.emit_interval(&res);
+            self.config.sink_interval(peer, &res);
+            self.udp_result.push(res);
+        }
 
-            let time_to_calc_bitrate = calc_instat.elapsed();
-            if time_to_calc_bitrate >= calc_interval {
-                udp_data.calc_bitrate(time_to_calc_bitrate);
-                calc_instat = Instant::now();
+        self.latency = udp_data.latency().clone();
+        self.ipdv = udp_data.ipdv().clone();
+        self.reorder = *udp_data.reorder();
+        self.sizes = *udp_data.sizes();
+        self.loss_pattern = udp_data.loss_pattern();
+        self.bandwidth = trains.estimate();
+        self.client_sent = udp_data.client_sent();
+        self.interarrival = interarrival.histogram().clone();
+        self.config.flush_outputs()?;
+
+        // the client only waits for the results after its FIN
+        if self.config.send_results && fin_received {
+            let summary = self.summary();
+            if !send_results(sock, peer, &summary)? {
+                event!(warn, "client never acknowledged the results");
             }
+        }
+        event!(info, intervals = self.udp_result.len(), "test finished");
+        Ok(Some(end))
+    }
 
-            if header.flags == FLAG_FIN {
-                break;
+    /// Summary of the intervals and statistics of the last test
+    fn summary(&self) -> TestResult {
+        TestResult::from_intervals(&self.udp_result)
+            .with_latency(&self.latency)
+            .with_ipdv(&self.ipdv)
+            .with_reorder(&self.reorder)
+            .with_sizes(&self.sizes)
+            .with_loss_pattern(&self.loss_pattern)
+            .with_interarrival(&self.interarrival)
+            .with_packets_sent(self.client_sent.unwrap_or(0))
+    }
+
+    /// Blocks until the first packet arrives.
+    ///
+    /// Returns the sender and its session id, or `None` if `Stop`, a
+    /// cancellation or a shutdown came before any packet arrived.
+    fn wait_first_packet<S: DatagramSocket>(
+        &mut self,
+        sock: &S,
+        buf: &mut [u8],
+    ) -> Result<Option<(SocketAddr, u32)>, UdpOptError> {
+        // poll so that commands, signals and cancellations are noticed without traffic
+        sock.set_read_timeout(Some(CONTROL_POLL))
+            .map_err(|_| UdpOptError::SocketTimeout)?;
+        loop {
+            if self.config.shutdown_requested() {
+                return Ok(None);
+            }
+            match self.control_rx.try_recv() {
+                Ok(ServerCommand::Stop) => return Ok(None),
+                // nothing to freeze before the measurement starts
+                Ok(ServerCommand::Pause | ServerCommand::Resume) => {}
+                Ok(ServerCommand::Status(reply)) => {
+                    let _ = reply.send(ServerStatus::default());
+                }
+                Ok(_) => return Err(UdpOptError::UnexpectedCommand),
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => return Err(UdpOptError::ChannelClosed),
+            }
+            match sock.recv_from(buf) {
+                Ok((len, from)) => {
+                    // not from a udpopt client
+                    let Ok(header) = UdpHeader::parse(&buf[..len]) else {
+                        continue;
+                    };
+                    // the results ACKs of the last test carry no session
+                    let test_packet = matches!(header.flags, FLAG_DATA | FLAG_FIN);
+                    let stale =
+                        header.session != 0 && self.last_session == Some((from, header.session));
+                    if test_packet
+                        && !stale
+                        && self.config.authentic(&buf[..len])
+                        && self.config.allowed_source(from)
+                        && self.config.accepts_peer(from)
+                        && self.config.authorized(&buf[..len], from)
+                    {
+                        return Ok(Some((from, header.session)));
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(UdpOptError::RecvFailed(e)),
             }
+        }
+    }
 
-            if start.elapsed() >= self.interval {
-                let res = udp_data.get_interval_result(start.elapsed());
-                self.udp_result.push(res);
-                start = Instant::now();
+    /// Receives the data packets of `session` from `peer` for `linger` after
+    /// the FIN, the tail of the test still in flight. `Stop` cuts it short.
+    ///
+    /// Returns how many arrived.
+    fn linger<S: DatagramSocket>(
+        &mut self,
+        sock: &S,
+        buf: &mut [u8],
+        linger: Duration,
+        (peer, session): (SocketAddr, u32),
+        udp_data: &mut UdpData,
+        epoch: Instant,
+    ) -> Result<u64, UdpOptError> {
+        let deadline = Instant::now() + linger;
+        let mut tail = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.config.shutdown_requested() {
+                return Ok(tail);
+            }
+            match self.control_rx.try_recv() {
+                Ok(ServerCommand::Stop) => return Ok(tail),
+                Ok(ServerCommand::Status(reply)) => {
+                    let _ = reply.send(ServerStatus::new(
+                        epoch.elapsed(),
+                        Some(peer),
+                        &self.udp_result,
+                        udp_data.current(),
+                    ));
+                }
+                _ => {}
+            }
+            sock.set_read_timeout(Some(remaining.min(CONTROL_POLL)))
+                .map_err(|_| UdpOptError::SocketTimeout)?;
+            let received = recv_datagram(sock, buf, self.config.ancillary());
+            match received {
+                Ok((len, from, meta)) => {
+                    if self.config.socket_drops {
+                        udp_data.record_socket_drops(meta.dropped.unwrap_or(0));
+                    }
+                    let Ok(header) = UdpHeader::parse_received(buf, len) else {
+                        continue;
+                    };
+                    if from == peer
+                        && header.session == session
+                        && header.flags == FLAG_DATA
+                        && self.config.authentic(&buf[..len])
+                    {
+                        if let Some(trace) = self.config.trace.as_mut() {
+                            let (sec, usec) = now_micros();
+                            trace
+                                .write_record(&header.to_record(len, sec, usec))
+                                .map_err(UdpOptError::TraceFailed)?;
+                        }
+                        udp_data.process_packet(&buf[..len], &header, epoch.elapsed());
+                        udp_data.record_ecn(meta.ecn);
+                        tail += 1;
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(UdpOptError::RecvFailed(e)),
             }
         }
-        
-        println!("test finished");
-        // if the interval time bigger than the total time the client send
-        if self.udp_result.len()==0{
-            self.udp_result.push(udp_data.get_interval_result(start.elapsed()));
+    }
+
+    /// Blocks until `Resume` or `Stop` arrives on the control channel,
+    /// answering `Status` with the `status` frozen by the pause.
+    fn wait_resume(&mut self, status: ServerStatus) -> Result<PauseOutcome, UdpOptError> {
+        let paused_at = Instant::now();
+        loop {
+            if self.config.shutdown_requested() {
+                return Ok(PauseOutcome::Stopped);
+            }
+            match self.control_rx.recv_timeout(CONTROL_POLL) {
+                Ok(ServerCommand::Resume) => return Ok(PauseOutcome::Resumed(paused_at.elapsed())),
+                Ok(ServerCommand::Stop) => return Ok(PauseOutcome::Stopped),
+                Ok(ServerCommand::Pause) => {}
+                Ok(ServerCommand::Status(reply)) => {
+                    let _ = reply.send(status);
+                }
+                Ok(ServerCommand::Start | ServerCommand::StartAt(_)) => {
+                    return Err(UdpOptError::UnexpectedCommand);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(UdpOptError::ChannelClosed);
+                }
+            }
         }
-        
-        Ok(std::mem::take(&mut self.udp_result))
+    }
+
+    /// Returns the client of the last [`UdpServer::run`], its last address if it
+    /// moved during the test; `None` before the first packet.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.last_session.map(|(peer, _)| peer)
+    }
+
+    /// Returns the per-packet latency histogram recorded during the last [`UdpServer::run`].
+    ///
+    /// Values are transit times in microseconds relative to the first received packet.
+    pub fn latency_histogram(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// Returns the delay variations between consecutive packets (IPDV, RFC 3393)
+    /// recorded during the last [`UdpServer::run`].
+    ///
+    /// Values are absolute differences of transit times in microseconds.
+    pub fn ipdv_histogram(&self) -> &Histogram {
+        &self.ipdv
+    }
+
+    /// Returns the reordering statistics recorded during the last [`UdpServer::run`].
+    pub fn reorder_stats(&self) -> &ReorderStats {
+        &self.reorder
+    }
+
+    /// Returns the packets and bytes per length bucket received during the last [`UdpServer::run`].
+    pub fn size_stats(&self) -> &SizeStats {
+        &self.sizes
+    }
+
+    /// Returns the loss bursts recorded during the last [`UdpServer::run`].
+    pub fn loss_pattern(&self) -> &LossStats {
+        &self.loss_pattern
+    }
+
+    /// Returns the inter-arrival times (µs) recorded during the last [`UdpServer::run`].
+    pub fn interarrival_histogram(&self) -> &Histogram {
+        &self.interarrival
+    }
+
+    /// Returns the available-bandwidth estimate of the last [`UdpServer::run`],
+    /// `None` unless the client sent packet trains (`ClientBuilder::packet_trains`).
+    pub fn bandwidth_estimate(&self) -> Option<BandwidthEstimate> {
+        self.bandwidth
+    }
+
+    /// Returns how many packets the client of the last [`UdpServer::run`] says it
+    /// sent, its FIN included, from the first packet measured (the one that
+    /// starts the test is not) or the end of the warm-up; `None` if its
+    /// packets do not carry the count.
+    ///
+    /// The final count the FIN carries, also `None` when the FIN was lost or
+    /// the test stopped before it: the count of the last packet received
+    /// would miss the packets lost after it.
+    pub fn client_sent(&self) -> Option<u64> {
+        self.client_sent
+    }
+}
+
+/// Waits for `target`, the time given by `StartAt`, answering `Status`;
+/// `false` if `Stop`, a cancellation or a signal came first
+pub(crate) fn wait_scheduled_start(
+    control_rx: &Receiver<ServerCommand>,
+    config: &ServerConfig,
+    target: Instant,
+) -> Result<bool, UdpOptError> {
+    let (mut stopped, mut unexpected) = (false, false);
+    let reached = wait_until_unless(target, || {
+        match control_rx.try_recv() {
+            Ok(ServerCommand::Stop) => stopped = true,
+            Ok(ServerCommand::Status(reply)) => {
+                let _ = reply.send(ServerStatus::default());
+            }
+            Ok(_) => unexpected = true,
+            Err(_) => {}
+        }
+        stopped || unexpected || config.shutdown_requested()
+    });
+    if unexpected {
+        return Err(UdpOptError::UnexpectedCommand);
+    }
+    Ok(reached)
+}
+
+/// Receives a datagram, with its ECN codepoint and the drop count of the
+/// socket if `ancillary` is set
+pub(crate) fn recv_datagram<S: DatagramSocket>(
+    sock: &S,
+    buf: &mut [u8],
+    ancillary: bool,
+) -> std::io::Result<(usize, SocketAddr, RecvMeta)> {
+    if ancillary {
+        sock.recv_from_meta(buf)
+    } else {
+        let (len, from) = sock.recv_from(buf)?;
+        Ok((len, from, RecvMeta::default()))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::socket::MockSocket;
+    use crate::utils::net_utils::ClientCommand;
+    use crate::utils::udp_data::HEADER_SIZE;
     use std::net::UdpSocket;
     use std::sync::mpsc::{Sender, channel};
     use std::thread;
@@ -162,12 +850,20 @@ mod tests {
     }
 
     // Helper to create a UDP packet with header
+    /// Keeps the trace records in memory
+    struct MemTrace(std::sync::Arc<std::sync::Mutex<Vec<crate::trace::PacketRecord>>>);
+
+    impl TraceWriter for MemTrace {
+        fn write_record(&mut self, record: &crate::trace::PacketRecord) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(*record);
+            Ok(())
+        }
+    }
+
     fn create_packet(seq: u64, flags: u32) -> Vec<u8> {
         let mut packet = vec![0u8; HEADER_SIZE + 100]; // Header + some payload
 
-        // Assuming UdpHeader layout (adjust based on your actual implementation)
-        packet[0..8].copy_from_slice(&seq.to_be_bytes());
-        packet[20..24].copy_from_slice(&flags.to_be_bytes());
+        UdpHeader::new(seq, 0, 0, flags).write_header(&mut packet);
 
         packet
     }
@@ -238,6 +934,57 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_stray_datagrams_are_ignored() {
+        let (mut server, tx) = create_test_server(Duration::from_secs(1));
+        let (mut server_sock, client_sock) = create_socket_pair();
+
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        // another application talking to the same port
+        client_sock.send(&[0xAB; 200]).unwrap();
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        client_sock.send(&[0xAB; 200]).unwrap();
+        client_sock.send(&create_packet(1, 0)).unwrap();
+        // a udpopt header with garbage in it
+        client_sock.send(&create_packet(9, 0x40)).unwrap();
+        client_sock.send(&create_packet(2, 1)).unwrap();
+
+        let results = handle.join().unwrap().unwrap();
+        assert_eq!(results[0].received, 2);
+        assert_eq!(results[0].bytes, 2 * (HEADER_SIZE + 100));
+        assert_eq!(results[0].lost, 0);
+        // the stray datagram before the first packet is not in a test yet
+        assert_eq!(results[0].malformed, 2);
+    }
+
+    #[test]
+    fn test_jumbo_datagrams_are_received_whole() {
+        let (mut server, tx) = create_test_server(Duration::from_secs(1));
+        let (mut server_sock, client_sock) = create_socket_pair();
+
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        for (seq, len) in [(1, 3000), (2, 9000), (3, 60000)] {
+            let mut packet = vec![0u8; len];
+            UdpHeader::new(seq, 0, 0, 0).write_header(&mut packet);
+            client_sock.send(&packet).unwrap();
+        }
+        client_sock.send(&create_packet(4, 1)).unwrap();
+
+        let results = handle.join().unwrap().unwrap();
+        assert_eq!(results[0].received, 4);
+        assert_eq!(results[0].bytes, 3000 + 9000 + 60000 + HEADER_SIZE + 100);
+        assert_eq!(results[0].malformed, 0);
+    }
+
     #[test]
     fn test_server_stops_on_fin_flag() {
         let (mut server, tx) = create_test_server(Duration::from_secs(1));
@@ -262,6 +1009,197 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_trace_writer_logs_packets() {
+        use std::sync::{Arc, Mutex};
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let (mut server, tx) = create_test_server(Duration::from_secs(1));
+        server.set_trace_writer(MemTrace(records.clone()));
+        let (mut server_sock, client_sock) = create_socket_pair();
+
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        // first packet only starts the measurement
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        client_sock.send(&create_packet(1, 0)).unwrap();
+        client_sock.send(&create_packet(2, 1)).unwrap();
+
+        assert!(handle.join().unwrap().is_ok());
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].seq, 1);
+        assert_eq!(records[0].size, HEADER_SIZE + 100);
+        assert_eq!(records[1].flags, FLAG_FIN);
+    }
+
+    #[test]
+    fn test_interval_observer_is_called() {
+        use crate::builder::ServerBuilder;
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_cb = seen.clone();
+        let (tx, rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(1))
+            .on_interval(Box::new(move |r| seen_cb.lock().unwrap().push(*r)))
+            .build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        client_sock.send(&create_packet(1, 0)).unwrap();
+        client_sock.send(&create_packet(2, 1)).unwrap();
+
+        let results = handle.join().unwrap().unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), results.len());
+        assert_eq!(seen[0].received, 2);
+    }
+
+    #[test]
+    fn test_results_are_pushed_to_sinks() {
+        use crate::builder::ServerBuilder;
+        use crate::sink::InfluxSink;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone)]
+        struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for SharedBuf {
+            fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(data)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let out = SharedBuf(Arc::new(Mutex::new(Vec::new())));
+        let (tx, rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(1))
+            .result_sink(InfluxSink::new(out.clone()).test_id("t1"))
+            .build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let client_addr = client_sock.local_addr().unwrap();
+
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        client_sock.send(&create_packet(1, 0)).unwrap();
+        client_sock.send(&create_packet(2, 1)).unwrap();
+
+        let results = handle.join().unwrap().unwrap();
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(text.lines().count(), results.len());
+        assert!(text.starts_with(&format!(
+            "udpopt,test_id=t1,peer={client_addr},direction=upload received=2i"
+        )));
+    }
+
+    #[test]
+    fn test_sink_failures_do_not_end_the_test() {
+        use crate::builder::ServerBuilder;
+        use crate::sink::ResultSink;
+        use std::sync::{Arc, Mutex};
+
+        /// Takes `delay` per result, then fails if `fail` is set
+        struct Sink {
+            delay: Duration,
+            fail: bool,
+            written: Arc<Mutex<u32>>,
+        }
+        impl ResultSink for Sink {
+            fn write_interval(&mut self, _: SocketAddr, _: &IntervalResult) -> std::io::Result<()> {
+                thread::sleep(self.delay);
+                *self.written.lock().unwrap() += 1;
+                if self.fail {
+                    return Err(std::io::ErrorKind::ConnectionRefused.into());
+                }
+                Ok(())
+            }
+        }
+
+        let failing = Arc::new(Mutex::new(0));
+        let slow = Arc::new(Mutex::new(0));
+        let (tx, rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(50))
+            .result_sink(Sink {
+                delay: Duration::ZERO,
+                fail: true,
+                written: failing.clone(),
+            })
+            .result_sink(Sink {
+                delay: Duration::from_millis(100),
+                fail: false,
+                written: slow.clone(),
+            })
+            .build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        for seq in 0..6 {
+            client_sock.send(&create_packet(seq, 0)).unwrap();
+            thread::sleep(Duration::from_millis(60));
+        }
+        client_sock.send(&create_packet(6, 1)).unwrap();
+
+        let results = handle.join().unwrap().unwrap();
+        assert_eq!(results.iter().map(|r| r.received).sum::<u64>(), 6);
+        // every interval reached both sinks before the run returned
+        let intervals = results.len() as u32;
+        assert_eq!(*failing.lock().unwrap(), intervals);
+        assert_eq!(*slow.lock().unwrap(), intervals);
+    }
+
+    #[test]
+    fn test_results_are_streamed() {
+        use crate::builder::ServerBuilder;
+
+        let (result_tx, result_rx) = channel();
+        let (tx, rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(50))
+            .stream_results(result_tx)
+            .build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_sock.send(&create_packet(0, 0)).unwrap();
+
+        // the first interval must be observable while the test is still running
+        for i in 1..=3 {
+            thread::sleep(Duration::from_millis(30));
+            client_sock.send(&create_packet(i, 0)).unwrap();
+        }
+        let first = result_rx
+            .recv_timeout(Duration::from_secs(1))
+            .expect("interval should be streamed before the test ends");
+        assert!(first.received > 0);
+
+        client_sock.send(&create_packet(4, 1)).unwrap();
+        let results = handle.join().unwrap().unwrap();
+
+        let streamed: Vec<_> = std::iter::once(first).chain(result_rx.try_iter()).collect();
+        assert_eq!(streamed.len(), results.len());
+    }
+
     #[test]
     fn test_interval_result_collection() {
         let interval = Duration::from_millis(200);
@@ -297,7 +1235,465 @@ mod tests {
         let results = handle.join().unwrap();
 
         // Should have collected at least one interval result
-        assert!(results.len() > 0);
+        assert!(!results.is_empty());
+    }
+
+    #[test]
+    fn test_pause_survives_quiet_socket() {
+        let (mut server, tx) = create_test_server(Duration::from_secs(1));
+        let (mut server_sock, client_sock) = create_socket_pair();
+
+        let handle = thread::spawn(move || {
+            let res = server.run(&mut server_sock);
+            (res, server_sock)
+        });
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        client_sock.send(&create_packet(1, 0)).unwrap();
+
+        // the sender goes quiet longer than the read timeout while paused
+        tx.send(ServerCommand::Pause).unwrap();
+        thread::sleep(Duration::from_millis(2500));
+        tx.send(ServerCommand::Resume).unwrap();
+
+        client_sock.send(&create_packet(2, 0)).unwrap();
+        client_sock.send(&create_packet(3, 1)).unwrap();
+
+        let (res, _) = handle.join().unwrap();
+        let results = res.unwrap();
+        let received: u64 = results.iter().map(|r| r.received).sum();
+        assert_eq!(received, 3);
+        assert_eq!(results[0].lost, 0);
+    }
+
+    #[test]
+    fn test_pause_longer_than_the_idle_timeout() {
+        let (tx, rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_secs(1))
+            .recv_timeout(Duration::from_millis(20))
+            .idle_timeout(Some(Duration::from_millis(300)))
+            .build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        client_sock.send(&create_packet(1, 0)).unwrap();
+        tx.send(ServerCommand::Pause).unwrap();
+        thread::sleep(Duration::from_millis(500));
+        tx.send(ServerCommand::Resume).unwrap();
+        // well within the idle timeout of the last packet, pause left out
+        thread::sleep(Duration::from_millis(100));
+        client_sock.send(&create_packet(2, 0)).unwrap();
+        client_sock.send(&create_packet(3, 1)).unwrap();
+
+        let results = handle.join().unwrap().unwrap();
+        let received: u64 = results.iter().map(|r| r.received).sum();
+        assert_eq!(received, 3);
+    }
+
+    #[test]
+    fn test_quiet_link_gives_idle_intervals() {
+        let (tx, rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100))
+            .recv_timeout(Duration::from_millis(20))
+            .idle_timeout(Some(Duration::from_millis(350)))
+            .build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        client_sock.send(&create_packet(1, 0)).unwrap();
+
+        // no FIN: the server gives up on its own instead of failing
+        let results = handle.join().unwrap().unwrap();
+        assert!(results.len() >= 2, "got {} intervals", results.len());
+        assert_eq!(results.iter().map(|r| r.received).sum::<u64>(), 1);
+        assert!(results[1..].iter().all(|r| r.received == 0 && r.lost == 0));
+    }
+
+    #[test]
+    fn test_heartbeats_outlive_the_idle_timeout() {
+        let (server_tx, server_rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100))
+            .recv_timeout(Duration::from_millis(20))
+            .idle_timeout(Some(Duration::from_millis(250)))
+            .build(server_rx);
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        let (client_tx, client_rx) = channel();
+        // a 1000 byte packet every 400 ms, longer than the idle timeout
+        let mut client = crate::ClientBuilder::new(20_000.0, 1000, Duration::from_millis(1_000))
+            .heartbeat(Duration::from_millis(100))
+            .build(client_rx);
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+
+        // the server saw the FIN instead of giving up, and the heartbeats
+        // are not data: the packets after the first and the FIN
+        let results = handle.join().unwrap().unwrap();
+        assert_eq!(report.packets_sent, 3);
+        assert_eq!(results.iter().map(|r| r.received).sum::<u64>(), 3);
+        assert!(results.iter().all(|r| r.lost == 0));
+    }
+
+    #[test]
+    fn test_traffic_gap_gives_empty_intervals() {
+        let (tx, rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100)).build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        client_sock.send(&create_packet(0, FLAG_DATA)).unwrap();
+        client_sock.send(&create_packet(1, FLAG_DATA)).unwrap();
+        // the sender stops for a while, then finishes
+        thread::sleep(Duration::from_millis(450));
+        client_sock.send(&create_packet(2, FLAG_DATA)).unwrap();
+        client_sock.send(&create_packet(3, FLAG_FIN)).unwrap();
+        let results = handle.join().unwrap().unwrap();
+
+        assert_eq!(results.iter().map(|r| r.received).sum::<u64>(), 3);
+        let empty = results.iter().filter(|r| r.received == 0).count();
+        assert!(empty >= 3, "{empty} empty intervals of {}", results.len());
+        assert!(results.iter().all(|r| r.lost == 0));
+        // no hole in the timeline
+        for pair in results.windows(2) {
+            assert_eq!(pair[1].offset, pair[0].offset + pair[0].time);
+        }
+    }
+
+    #[test]
+    fn test_intervals_carry_timestamps() {
+        let (tx, rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100))
+            .recv_timeout(Duration::from_millis(20))
+            .idle_timeout(Some(Duration::from_millis(250)))
+            .build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        let before = std::time::SystemTime::now();
+        tx.send(ServerCommand::Start).unwrap();
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        client_sock.send(&create_packet(1, 0)).unwrap();
+        let results = handle.join().unwrap().unwrap();
+        let after = std::time::SystemTime::now();
+
+        assert!(results.len() >= 2);
+        assert_eq!(results[0].offset, Duration::ZERO);
+        let first = results[0].start_time.unwrap();
+        assert!(first >= before && first <= after);
+        for pair in results.windows(2) {
+            // back to back on the timeline
+            assert!(pair[1].offset >= pair[0].offset + pair[0].time);
+            assert!(pair[1].offset - pair[0].offset - pair[0].time < Duration::from_millis(5));
+            let start = pair[1].start_time.unwrap();
+            assert_eq!(start.duration_since(first).unwrap(), pair[1].offset);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_intervals_carry_nic_counters() {
+        if !std::path::Path::new("/sys/class/net/lo").exists() {
+            return;
+        }
+        let (tx, rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100))
+            .recv_timeout(Duration::from_millis(20))
+            .idle_timeout(Some(Duration::from_millis(250)))
+            .nic_counters("lo")
+            .build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+        tx.send(ServerCommand::Start).unwrap();
+        // counted from the first packet on
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        for seq in 1..5 {
+            client_sock.send(&create_packet(seq, 0)).unwrap();
+        }
+        let results = handle.join().unwrap().unwrap();
+        let nic: Vec<_> = results.iter().map(|r| r.nic.unwrap()).collect();
+        // the test went through lo, with whatever else the host sent on it
+        assert!(nic.iter().map(|n| n.rx_packets).sum::<u64>() >= 4);
+
+        let (tx, rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100))
+            .nic_counters("udpopt-none0")
+            .build(rx);
+        tx.send(ServerCommand::Start).unwrap();
+        let err = server.run(&mut create_socket_pair().0).unwrap_err();
+        assert!(matches!(err.error, UdpOptError::NicCountersFailed(_)));
+    }
+
+    #[test]
+    fn test_intervals_on_the_wall_clock() {
+        let (tx, rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100))
+            .interval_alignment(crate::IntervalAlignment::WallClock)
+            .recv_timeout(Duration::from_millis(20))
+            .idle_timeout(Some(Duration::from_millis(450)))
+            .build(rx);
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        let results = handle.join().unwrap().unwrap();
+
+        assert!(results.len() >= 4, "got {} intervals", results.len());
+        assert!(results[0].time <= Duration::from_millis(100));
+        let last = results.len() - 1;
+        for r in &results[1..last] {
+            // noticed late on a quiet link, but laid on the grid
+            assert_eq!(r.time, Duration::from_millis(100));
+            let since_epoch = r
+                .start_time
+                .unwrap()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap();
+            let into = since_epoch.as_micros() % 100_000;
+            assert!(
+                !(1000..99_000).contains(&into),
+                "{into} µs into the interval"
+            );
+        }
+    }
+
+    #[test]
+    fn test_packet_trains_give_bandwidth_estimate() {
+        let (server_tx, server_rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_secs(1)).build(server_rx);
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || {
+            let res = server.run(&mut server_sock);
+            (server, res)
+        });
+
+        let (client_tx, client_rx) = channel();
+        let mut client = crate::ClientBuilder::new(1_000_000.0, 1000, Duration::from_millis(300))
+            .packet_trains(16, Duration::from_millis(20))
+            .build(client_rx);
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+
+        let (server, res) = handle.join().unwrap();
+        res.unwrap();
+        // 16 packets every 20 ms, whatever the bitrate says
+        assert!(
+            report.packets_sent >= 16 * 10,
+            "sent {}",
+            report.packets_sent
+        );
+        let est = server.bandwidth_estimate().unwrap();
+        assert_eq!(est.train_len, 16);
+        assert!(est.trains >= 10);
+        assert!(est.min_bps <= est.median_bps && est.median_bps <= est.max_bps);
+        // back-to-back on loopback is far faster than the configured bitrate
+        assert!(est.median_bps > 10_000_000.0, "{:?}", est);
+    }
+
+    #[test]
+    fn test_jitter_near_zero_on_loopback() {
+        let (server_tx, server_rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100)).build(server_rx);
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || {
+            let res = server.run(&mut server_sock);
+            (server, res)
+        });
+
+        let (client_tx, client_rx) = channel();
+        // 500 packets per second for 600 ms, over several intervals
+        let mut client = crate::ClientBuilder::new(4_000_000.0, 1000, Duration::from_millis(600))
+            .build(client_rx);
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_tx.send(ClientCommand::Start).unwrap();
+        client.run(&mut client_sock).unwrap();
+
+        let (server, res) = handle.join().unwrap();
+        let results = res.unwrap();
+        assert!(results.len() >= 5, "got {} intervals", results.len());
+        for r in &results {
+            assert!(r.jitter_ms < 2.0, "{} ms of jitter", r.jitter_ms);
+        }
+        // the arrivals do not restart with the intervals, their first
+        // packet does not look a whole interval late
+        let ipdv = server.ipdv_histogram();
+        assert!(ipdv.max() < 20_000, "{} µs of delay variation", ipdv.max());
+    }
+
+    #[test]
+    fn test_client_reports_sent_count_and_rate() {
+        let (server_tx, server_rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100)).build(server_rx);
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || {
+            let res = server.run(&mut server_sock);
+            (server, res)
+        });
+
+        let (client_tx, client_rx) = channel();
+        let mut client = crate::ClientBuilder::new(4_000_000.0, 1000, Duration::from_millis(300))
+            .build(client_rx);
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+
+        let (server, res) = handle.join().unwrap();
+        let results = res.unwrap();
+        // the data packets and the FIN, but the first packet only starts the test
+        assert_eq!(server.client_sent(), Some(report.packets_sent));
+        assert!(
+            results
+                .iter()
+                .filter(|r| r.received > 0)
+                .all(|r| r.target_bitrate == 4_000_000)
+        );
+        let summary =
+            TestResult::from_intervals(&results).with_packets_sent(server.client_sent().unwrap());
+        assert_eq!(summary.true_loss(), Some(0));
+        assert_eq!(summary.true_loss_percent(), Some(0.0));
+    }
+
+    #[test]
+    fn test_stats_packets_give_interval_sent() {
+        let (server_tx, server_rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100)).build(server_rx);
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        let (client_tx, client_rx) = channel();
+        let mut client = crate::ClientBuilder::new(4_000_000.0, 1000, Duration::from_millis(500))
+            .stats_interval(Duration::from_millis(100))
+            .build(client_rx);
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+
+        let results = handle.join().unwrap().unwrap();
+        assert!(results.iter().any(|r| r.sent > 0));
+        let summary = TestResult::from_intervals(&results);
+        assert_eq!(summary.total_lost, 0);
+        // the data packets and the FIN, less the first, but no STATS packet
+        assert_eq!(summary.total_packets, report.packets_sent);
+    }
+
+    #[test]
+    fn test_omit_leaves_warm_up_out() {
+        let (server_tx, server_rx) = channel();
+        let mut server = crate::ServerBuilder::new(Duration::from_millis(100))
+            .omit(Duration::from_millis(300))
+            .build(server_rx);
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        let (client_tx, client_rx) = channel();
+        // 1000 packets per second for 600 ms
+        let mut client = crate::ClientBuilder::new(8_000_000.0, 1000, Duration::from_millis(600))
+            .build(client_rx);
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+
+        let results = handle.join().unwrap().unwrap();
+        let received: u64 = results.iter().map(|r| r.received).sum();
+        let lost: u64 = results.iter().map(|r| r.lost).sum();
+        // about half of the packets fall in the warm-up
+        assert!(
+            received < report.packets_sent * 3 / 4,
+            "{received} of {} received",
+            report.packets_sent
+        );
+        assert!(received > report.packets_sent / 4);
+        // the packets of the warm-up are not counted as lost
+        assert_eq!(lost, 0);
+        assert!(results.len() <= 4, "got {} intervals", results.len());
+    }
+
+    #[test]
+    fn test_start_at_discards_early_datagrams() {
+        let (mut server, tx) = create_test_server(Duration::from_secs(1));
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        let start_at = std::time::SystemTime::now() + Duration::from_millis(200);
+        tx.send(ServerCommand::StartAt(start_at)).unwrap();
+        // sent before the start, thrown away
+        for seq in 0..5 {
+            client_sock.send(&create_packet(seq, 0)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(300));
+        client_sock.send(&create_packet(100, 0)).unwrap();
+        client_sock.send(&create_packet(101, FLAG_FIN)).unwrap();
+
+        let results = handle.join().unwrap().unwrap();
+        let received: u64 = results.iter().map(|r| r.received).sum();
+        let lost: u64 = results.iter().map(|r| r.lost).sum();
+        // the first packet after the start only starts the measurement
+        assert_eq!(received, 1);
+        // measuring from seq 0 would have counted 1..100 as lost
+        assert_eq!(lost, 0);
+    }
+
+    #[test]
+    fn test_stop_before_the_scheduled_start() {
+        let (mut server, tx) = create_test_server(Duration::from_secs(1));
+        let (mut server_sock, _client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        let start_at = std::time::SystemTime::now() + Duration::from_secs(10);
+        tx.send(ServerCommand::StartAt(start_at)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let (status_tx, status_rx) = tokio::sync::oneshot::channel();
+        tx.send(ServerCommand::Status(status_tx)).unwrap();
+        assert_eq!(status_rx.blocking_recv().unwrap(), ServerStatus::default());
+        let stopped_at = Instant::now();
+        tx.send(ServerCommand::Stop).unwrap();
+        assert!(handle.join().unwrap().unwrap().is_empty());
+        assert!(stopped_at.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_stop_without_traffic() {
+        // before the first packet
+        let (mut server, tx) = create_test_server(Duration::from_secs(1));
+        let (mut server_sock, _client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let stopped_at = Instant::now();
+        tx.send(ServerCommand::Stop).unwrap();
+        assert!(handle.join().unwrap().unwrap().is_empty());
+        assert!(stopped_at.elapsed() < Duration::from_millis(100));
+
+        // on a quiet link during the test
+        let (mut server, tx) = create_test_server(Duration::from_secs(1));
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+        tx.send(ServerCommand::Start).unwrap();
+        client_sock.send(&create_packet(0, 0)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let stopped_at = Instant::now();
+        tx.send(ServerCommand::Stop).unwrap();
+        assert_eq!(handle.join().unwrap().unwrap().len(), 1);
+        assert!(stopped_at.elapsed() < Duration::from_millis(100));
     }
 
     #[test]
@@ -336,4 +1732,305 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_error_keeps_completed_intervals() {
+        let (mut server, tx) = create_test_server(Duration::from_millis(100));
+        let (mut server_sock, client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || server.run(&mut server_sock));
+
+        tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        for seq in 0..3 {
+            client_sock.send(&create_packet(seq, 0)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(250));
+        // a second Start mid-test fails the run
+        tx.send(ServerCommand::Start).unwrap();
+
+        let err = handle.join().unwrap().unwrap_err();
+        assert!(matches!(err.error, UdpOptError::UnexpectedCommand));
+        assert!(
+            err.intervals.len() >= 2,
+            "got {} intervals",
+            err.intervals.len()
+        );
+        assert_eq!(err.intervals.iter().map(|r| r.received).sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn test_size_mix_per_bucket() {
+        let (mut server, server_tx) = create_test_server(Duration::from_secs(5));
+        let (mut server_sock, mut client_sock) = create_socket_pair();
+        let handle = thread::spawn(move || {
+            let res = server.run(&mut server_sock);
+            (server, res)
+        });
+
+        let (client_tx, client_rx) = channel();
+        let mut client = crate::ClientBuilder::new(2_000_000.0, 1200, Duration::from_secs(10))
+            .packet_sizes(crate::SizeMix::imix())
+            .stop_after_packets(120)
+            .build(client_rx);
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_tx.send(ClientCommand::Start).unwrap();
+        let report = client.run(&mut client_sock).unwrap();
+        assert_eq!(report.bytes_sent, 10 * (7 * 64 + 4 * 576 + 1500));
+
+        let (server, res) = handle.join().unwrap();
+        res.unwrap();
+        let sizes = server.size_stats();
+        // the first 64 byte packet only starts the measurement, the FIN
+        // fills the whole 1500 byte buffer
+        assert_eq!(sizes.packets, [69, 0, 0, 0, 40, 11, 0]);
+        assert_eq!(sizes.bytes[4], 40 * 576);
+    }
+
+    #[test]
+    fn test_serve_forever_takes_sequential_clients() {
+        let (mut server, tx) = create_test_server(Duration::from_secs(5));
+        let mut server_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server_sock.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut sessions = Vec::new();
+            let served = server.serve_forever(&mut server_sock, |s| sessions.push(s));
+            (served, sessions)
+        });
+        tx.send(ServerCommand::Start).unwrap();
+
+        let mut clients = Vec::new();
+        for packets in [5, 8] {
+            let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+            for seq in 0..packets {
+                client.send_to(&create_packet(seq, 0), server_addr).unwrap();
+            }
+            client
+                .send_to(&create_packet(packets, FLAG_FIN), server_addr)
+                .unwrap();
+            clients.push(client.local_addr().unwrap());
+            thread::sleep(Duration::from_millis(100));
+        }
+        tx.send(ServerCommand::Stop).unwrap();
+
+        let (served, sessions) = handle.join().unwrap();
+        assert_eq!(served.unwrap(), 2);
+        assert_eq!(sessions.len(), 2);
+        for (session, (client, received)) in sessions.iter().zip(clients.into_iter().zip([5, 8])) {
+            assert_eq!(session.peer, client);
+            // the first packet only starts the measurement, the FIN counts
+            assert_eq!(session.intervals[0].received, received);
+            assert_eq!(session.summary.total_packets, received);
+        }
+        assert_eq!((sessions[0].index, sessions[1].index), (1, 2));
+    }
+
+    #[test]
+    fn test_ignores_packets_of_other_sessions() {
+        let packet = |seq, flags, session| {
+            let mut packet = vec![0u8; HEADER_SIZE + 100];
+            UdpHeader::new(seq, 0, 0, flags)
+                .with_session(session)
+                .write_header(&mut packet);
+            packet
+        };
+        let client: SocketAddr = "192.0.2.2:40000".parse().unwrap();
+        let (mut server, tx) = create_test_server(Duration::from_secs(10));
+        let mut sock = MockSocket::new();
+        for seq in 0..5 {
+            sock.push(&packet(seq, FLAG_DATA, 1), client);
+        }
+        sock.push(&packet(5, FLAG_FIN, 1), client);
+        tx.send(ServerCommand::Start).unwrap();
+        assert_eq!(server.run(&mut sock).unwrap()[0].received, 5);
+
+        // a late packet of the first run cannot start the second one, and
+        // its stray sequence numbers are no loss for the new run
+        sock.push(&packet(3, FLAG_DATA, 1), client);
+        for seq in 0..5 {
+            sock.push(&packet(seq, FLAG_DATA, 2), client);
+            sock.push(&packet(100 + seq, FLAG_DATA, 1), client);
+        }
+        sock.push(&packet(5, FLAG_FIN, 2), client);
+        tx.send(ServerCommand::Start).unwrap();
+        let results = server.run(&mut sock).unwrap();
+        assert_eq!(results[0].received, 5);
+        assert_eq!(results[0].lost, 0);
+        assert_eq!(results[0].out_of_order, 0);
+    }
+
+    #[test]
+    fn test_peer_filter_and_lock() {
+        use crate::builder::ServerBuilder;
+
+        let packet = |seq, flags| {
+            let mut packet = vec![0u8; HEADER_SIZE + 100];
+            UdpHeader::new(seq, 0, 0, flags)
+                .with_session(7)
+                .write_header(&mut packet);
+            packet
+        };
+        let scanner: SocketAddr = "198.51.100.9:53".parse().unwrap();
+        let client: SocketAddr = "192.0.2.2:40000".parse().unwrap();
+        let rebound: SocketAddr = "192.0.2.2:40001".parse().unwrap();
+
+        // any port of the expected address
+        let (tx, rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(10))
+            .peer("192.0.2.2:0".parse().unwrap())
+            .build(rx);
+        let mut sock = MockSocket::new();
+        for seq in 0..5 {
+            sock.push(&packet(seq, FLAG_DATA), scanner);
+            sock.push(&packet(seq, FLAG_DATA), client);
+        }
+        sock.push(&packet(5, FLAG_FIN), client);
+        tx.send(ServerCommand::Start).unwrap();
+        assert!(server.peer().is_none());
+        let results = server.run(&mut sock).unwrap();
+        assert_eq!((results[0].received, results[0].lost), (5, 0));
+        assert_eq!(server.peer(), Some(client));
+
+        // the locked test does not follow the client to its new port
+        let (tx, rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(10))
+            .lock_peer()
+            .build(rx);
+        for seq in 0..3 {
+            sock.push(&packet(seq, FLAG_DATA), client);
+        }
+        for seq in 3..5 {
+            sock.push(&packet(seq, FLAG_DATA), rebound);
+        }
+        sock.push(&packet(5, FLAG_FIN), client);
+        tx.send(ServerCommand::Start).unwrap();
+        let results = server.run(&mut sock).unwrap();
+        assert_eq!((results[0].received, results[0].lost), (3, 2));
+        assert_eq!(server.peer(), Some(client));
+    }
+
+    #[test]
+    fn test_client_sent_needs_the_fin() {
+        use crate::builder::ServerBuilder;
+
+        let packet = |seq: u64, flags, sent| {
+            let mut packet = vec![0u8; HEADER_SIZE + 100];
+            UdpHeader::new(seq, 0, 0, flags)
+                .with_session(7)
+                .with_sender(sent, 0)
+                .write_header(&mut packet);
+            packet
+        };
+        let client: SocketAddr = "192.0.2.2:40000".parse().unwrap();
+        let run = |fin: bool| {
+            let (tx, rx) = channel();
+            let mut server = ServerBuilder::new(Duration::from_secs(10))
+                .recv_timeout(Duration::from_millis(10))
+                .idle_timeout(Some(Duration::from_millis(50)))
+                .build(rx);
+            let mut sock = MockSocket::new();
+            // the last 3 data packets are lost
+            for seq in 0..5 {
+                sock.push(&packet(seq, FLAG_DATA, seq + 1), client);
+            }
+            if fin {
+                sock.push(&packet(8, FLAG_FIN, 9), client);
+            }
+            tx.send(ServerCommand::Start).unwrap();
+            server.run(&mut sock).unwrap();
+            server.client_sent()
+        };
+        // from the first packet measured, the FIN included
+        assert_eq!(run(true), Some(8));
+        // the last packet received would tell 4
+        assert_eq!(run(false), None);
+    }
+
+    #[test]
+    fn test_socket_drops() {
+        use crate::builder::ServerBuilder;
+
+        let packet = |seq, flags| {
+            let mut packet = vec![0u8; HEADER_SIZE + 100];
+            UdpHeader::new(seq, 0, 0, flags)
+                .with_session(7)
+                .write_header(&mut packet);
+            packet
+        };
+        let client: SocketAddr = "192.0.2.2:40000".parse().unwrap();
+        let (tx, rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(10))
+            .count_socket_drops()
+            .build(rx);
+        let mut sock = MockSocket::new();
+        sock.push(&packet(0, FLAG_DATA), client);
+        // no count before the first drop, then 2 of them before seq 4
+        sock.push(&packet(1, FLAG_DATA), client);
+        sock.push_after_drops(&packet(4, FLAG_DATA), client, 2);
+        sock.push_after_drops(&packet(5, FLAG_DATA), client, 2);
+        sock.push_after_drops(&packet(6, FLAG_FIN), client, 2);
+        tx.send(ServerCommand::Start).unwrap();
+        let results = server.run(&mut sock).unwrap();
+        assert_eq!((results[0].lost, results[0].socket_drops), (2, 2));
+        let summary = TestResult::from_intervals(&results);
+        assert_eq!(summary.total_socket_drops, 2);
+        assert_eq!(summary.path_loss(), 0);
+    }
+
+    #[test]
+    fn test_fin_linger() {
+        use crate::builder::ServerBuilder;
+        use std::sync::{Arc, Mutex};
+
+        let packet = |seq, flags, session| {
+            let mut packet = vec![0u8; HEADER_SIZE + 100];
+            UdpHeader::new(seq, 0, 0, flags)
+                .with_session(session)
+                .write_header(&mut packet);
+            packet
+        };
+        let client: SocketAddr = "192.0.2.2:40000".parse().unwrap();
+        let linger = Duration::from_millis(100);
+        let run = |linger: Option<Duration>| {
+            let (tx, rx) = channel();
+            let mut builder = ServerBuilder::new(Duration::from_secs(10));
+            if let Some(linger) = linger {
+                builder = builder.fin_linger(linger);
+            }
+            let mut server = builder.build(rx);
+            let records = Arc::new(Mutex::new(Vec::new()));
+            server.set_trace_writer(MemTrace(records.clone()));
+            let mut sock = MockSocket::new();
+            for seq in [0, 1, 2, 4] {
+                sock.push(&packet(seq, FLAG_DATA, 7), client);
+            }
+            sock.push(&packet(5, FLAG_FIN, 7), client);
+            // reordered behind the FIN, a packet of another run and one cut
+            // short by the receive buffer
+            sock.push(&packet(3, FLAG_DATA, 7), client);
+            sock.push(&packet(6, FLAG_DATA, 8), client);
+            let mut truncated = packet(7, FLAG_DATA, 7);
+            truncated.resize(RECV_BUF_LEN + 1, 0);
+            sock.push(&truncated, client);
+            tx.send(ServerCommand::Start).unwrap();
+            let results = server.run(&mut sock).unwrap();
+            let seqs: Vec<_> = records.lock().unwrap().iter().map(|r| r.seq).collect();
+            (results, seqs)
+        };
+
+        let (results, seqs) = run(None);
+        assert_eq!((results[0].received, results[0].lost), (4, 1));
+        assert_eq!(seqs, [1, 2, 4, 5]);
+
+        let start = Instant::now();
+        let (results, seqs) = run(Some(linger));
+        assert!(start.elapsed() >= linger);
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0].received, results[0].lost), (5, 0));
+        assert_eq!(results[0].late, 1);
+        // the tail packet is traced like the others
+        assert_eq!(seqs, [1, 2, 4, 5, 3]);
+        // the interval ends with the FIN
+        assert!(results[0].time < linger);
+    }
 }
@@ -0,0 +1,810 @@
+//! Multi-threaded UDP server for 10GbE+ tests (feature `reuseport`, Unix only).
+//!
+//! This module provides [`ShardedServer`] — a server that binds `N` sockets to
+//! the same address with `SO_REUSEPORT` and receives on each of them from its
+//! own thread. The kernel spreads the flows over the sockets by their address
+//! tuple, so one client flow always lands on the same shard: use several
+//! clients (or source ports) to load more than one shard.
+//!
+//! Every shard keeps per-peer statistics and closes its intervals on a clock
+//! shared by all shards. The intervals of all shards are merged into the
+//! aggregate [`IntervalResult`]s that go to the observers, the result stream
+//! and the sinks; the per-shard results are kept in [`ShardedServer::shard_results`].
+
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+#[cfg(feature = "auth")]
+use crate::auth::AuthKey;
+use crate::{
+    access::{AccessControl, IpNet, source_allowed},
+    buffer_pool::BufferPool,
+    builder::ServerConfig,
+    errors::UdpOptError,
+    nic::NicSampler,
+    server::{recv_datagram, wait_scheduled_start},
+    socket::DatagramSocket,
+    utils::{
+        jitter::JitterEstimator,
+        net_utils::{IntervalResult, ServerCommand, ServerStatus, discard_pending, instant_at},
+        sched::ThreadTuning,
+        udp_data::{FLAG_FIN, FLAG_HEARTBEAT, FLAG_STATS, RECV_BUF_LEN, UdpData, UdpHeader},
+    },
+};
+
+/// How often an idle shard checks the clock and the stop flag
+const SHARD_POLL: Duration = Duration::from_millis(100);
+
+/// What a shard thread tells the coordinating thread
+enum ShardEvent {
+    /// First datagram of a peer
+    Peer(SocketAddr),
+    /// FIN of a peer
+    Fin(SocketAddr),
+    /// `shard` has been quiet for the idle timeout, or is no longer
+    Quiet { shard: usize, quiet: bool },
+    /// Interval `index` of `shard` is closed
+    Interval {
+        shard: usize,
+        index: u64,
+        result: IntervalResult,
+    },
+    /// The shard sent its last interval and exits
+    Done,
+    /// The shard failed and exits
+    Failed(usize, UdpOptError),
+}
+
+/// UDP server receiving on several `SO_REUSEPORT` sockets in parallel.
+///
+/// Built with [`crate::ServerBuilder::build_sharded`].
+#[derive(Debug)]
+pub struct ShardedServer {
+    /// Interval length, observers and sinks
+    config: ServerConfig,
+    /// Number of receive threads
+    shards: usize,
+    /// Control commands (`Start`, `Stop`) from another thread
+    control_rx: Receiver<ServerCommand>,
+    /// Aggregate interval results
+    udp_result: Vec<IntervalResult>,
+    /// Interval results of every shard
+    shard_results: Vec<Vec<IntervalResult>>,
+}
+
+/// Binds a UDP socket to `addr` with `SO_REUSEPORT` set.
+fn bind_reuseport(addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    let sock = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    sock.set_reuse_port(true)?;
+    sock.bind(&addr.into())?;
+    Ok(sock.into())
+}
+
+impl ShardedServer {
+    pub(crate) fn from_config(
+        config: ServerConfig,
+        shards: usize,
+        control_rx: Receiver<ServerCommand>,
+    ) -> Self {
+        let shards = shards.max(1);
+        Self {
+            config,
+            shards,
+            control_rx,
+            udp_result: Vec::with_capacity(100),
+            shard_results: vec![Vec::new(); shards],
+        }
+    }
+
+    /// Runs the test on `addr`.
+    ///
+    /// - Waits for a `Start` command, then binds one socket per shard. With
+    ///   `StartAt` the shards start at that wall-clock time, the datagrams
+    ///   that arrived before it are discarded.
+    /// - The interval clock starts with the first packet on any shard.
+    ///
+    /// The run ends when:
+    /// - A `Stop` command is received or the control channel disconnects.
+    /// - Every peer seen so far has sent its FIN.
+    /// - Ctrl-C / SIGTERM arrives, if built with `stop_on_signal` (feature `signal`).
+    ///
+    /// With port 0 all shards share the port the first one got, use
+    /// [`ShardedServer::run_reporting`] to learn which.
+    ///
+    /// Returns the aggregate [`IntervalResult`]s, or:
+    /// - [`UdpOptError::BindFailed`] if a socket cannot be bound.
+    /// - [`UdpOptError::RecvFailed`] if a shard fails to receive.
+    /// - [`UdpOptError::UnexpectedCommand`] for `Pause` / `Resume`, which are not supported.
+    pub fn run(&mut self, addr: SocketAddr) -> Result<Vec<IntervalResult>, UdpOptError> {
+        self.run_with(addr, None)
+    }
+
+    /// Like [`ShardedServer::run`] but reports the bound address on `bound_tx`
+    /// once all shards are listening.
+    pub fn run_reporting(
+        &mut self,
+        addr: SocketAddr,
+        bound_tx: Sender<SocketAddr>,
+    ) -> Result<Vec<IntervalResult>, UdpOptError> {
+        self.run_with(addr, Some(bound_tx))
+    }
+
+    fn run_with(
+        &mut self,
+        addr: SocketAddr,
+        bound_tx: Option<Sender<SocketAddr>>,
+    ) -> Result<Vec<IntervalResult>, UdpOptError> {
+        let start_at = loop {
+            match self.control_rx.recv() {
+                Ok(ServerCommand::Start) => break None,
+                Ok(ServerCommand::StartAt(at)) => break Some(at),
+                // nothing received yet
+                Ok(ServerCommand::Status(reply)) => {
+                    let _ = reply.send(ServerStatus::default());
+                }
+                Ok(_) => return Err(UdpOptError::UnexpectedCommand),
+                Err(_) => return Err(UdpOptError::ChannelClosed),
+            }
+        };
+        self.config.watch_signals()?;
+        let nic = self.config.nic_sampler()?;
+
+        let bind_failed = |source| UdpOptError::BindFailed { addr, source };
+        let first = bind_reuseport(addr).map_err(bind_failed)?;
+        // with port 0 the other shards must join the port the first one got
+        let addr = first.local_addr().map_err(bind_failed)?;
+        let bind_failed = |source| UdpOptError::BindFailed { addr, source };
+        let mut sockets = vec![first];
+        for _ in 1..self.shards {
+            sockets.push(bind_reuseport(addr).map_err(bind_failed)?);
+        }
+        if let Some(device) = &self.config.device {
+            for sock in &sockets {
+                sock.bind_device(device)
+                    .map_err(UdpOptError::DeviceFailed)?;
+            }
+        }
+        if let Some(tx) = bound_tx {
+            let _ = tx.send(addr);
+        }
+        if let Some(at) = start_at {
+            event!(info, "waiting for the scheduled start");
+            if !wait_scheduled_start(&self.control_rx, &self.config, instant_at(at))? {
+                event!(info, "stopped before the scheduled start");
+                return Ok(Vec::new());
+            }
+            let mut buf = self.config.buffers.get(RECV_BUF_LEN);
+            for sock in &sockets {
+                discard_pending(sock, &mut buf).map_err(UdpOptError::RecvFailed)?;
+            }
+        }
+        event!(info, %addr, shards = self.shards, "sharded test started");
+
+        self.udp_result.clear();
+        self.shard_results.iter_mut().for_each(Vec::clear);
+
+        let epoch = OnceLock::new();
+        let stop = AtomicBool::new(false);
+        let (event_tx, event_rx) = mpsc::channel();
+        let interval = self.config.interval;
+        let ecn = self.config.ecn;
+        let socket_drops = self.config.socket_drops;
+        let jitter = self.config.jitter;
+        let idle_timeout = self.config.idle_timeout;
+        #[cfg(feature = "auth")]
+        let auth = self.config.auth.clone();
+        let tuning = self.config.tuning;
+        let buffers = self.config.buffers.clone();
+        // shared by the shards, handed back after the run
+        let access = self.config.access.take().map(Mutex::new);
+        let allow_sources = self.config.allow_sources.clone();
+
+        let res = thread::scope(|scope| {
+            for (shard, sock) in sockets.into_iter().enumerate() {
+                let tx = event_tx.clone();
+                let (epoch, stop, buffers, access) = (&epoch, &stop, &buffers, access.as_ref());
+                let allow_sources = allow_sources.as_slice();
+                #[cfg(feature = "auth")]
+                let auth = auth.as_ref();
+                let tuning = ThreadTuning {
+                    // one core per shard
+                    core: tuning.core.map(|core| core + shard),
+                    ..tuning
+                };
+                scope.spawn(move || {
+                    let shard_run = Shard {
+                        id: shard,
+                        sock,
+                        interval,
+                        ecn,
+                        socket_drops,
+                        jitter,
+                        idle_timeout,
+                        #[cfg(feature = "auth")]
+                        auth,
+                        access,
+                        allow_sources,
+                        epoch,
+                        stop,
+                        tx: &tx,
+                    };
+                    let event = match shard_run.run(tuning, buffers) {
+                        Ok(()) => ShardEvent::Done,
+                        Err(e) => ShardEvent::Failed(shard, e),
+                    };
+                    let _ = tx.send(event);
+                });
+            }
+            drop(event_tx);
+            let res = self.coordinate(&event_rx, &stop, &epoch, nic);
+            // the shards must not outlive a failed coordinator
+            stop.store(true, Ordering::Relaxed);
+            res
+        });
+        self.config.access = access.map(|a| a.into_inner().unwrap_or_else(|e| e.into_inner()));
+        res?;
+
+        self.config.flush_outputs()?;
+        event!(info, intervals = self.udp_result.len(), "test finished");
+        Ok(std::mem::take(&mut self.udp_result))
+    }
+
+    /// Merges the shard intervals until every shard exited. The interface
+    /// counters are sampled here as each merged interval completes, the
+    /// shards all share one interface.
+    fn coordinate(
+        &mut self,
+        events: &Receiver<ShardEvent>,
+        stop: &AtomicBool,
+        epoch: &OnceLock<Instant>,
+        mut nic: Option<NicSampler>,
+    ) -> Result<(), UdpOptError> {
+        let mut peers: HashMap<SocketAddr, bool> = HashMap::new();
+        let mut first_peer = None;
+        // merged interval and the shards that reported it, by interval index
+        let mut pending: Vec<IntervalResult> = Vec::new();
+        let mut next_index = 0;
+        // last interval index reported by every shard
+        let mut reported: Vec<Option<u64>> = vec![None; self.shards];
+        // shards quiet for the idle timeout
+        let mut quiet = vec![false; self.shards];
+        let mut running = self.shards;
+        let mut failure = None;
+        let mut command_error = None;
+
+        while running > 0 {
+            if !stop.load(Ordering::Relaxed) {
+                match self.control_rx.try_recv() {
+                    Ok(ServerCommand::Stop) | Err(TryRecvError::Disconnected) => {
+                        event!(info, "stop command received");
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    // the open intervals as far as the shards reported them
+                    Ok(ServerCommand::Status(reply)) => {
+                        let open = pending
+                            .iter()
+                            .fold(IntervalResult::default(), |mut open, r| {
+                                open.merge(r);
+                                open
+                            });
+                        let _ = reply.send(ServerStatus::new(
+                            epoch.get().map_or(Duration::ZERO, Instant::elapsed),
+                            first_peer,
+                            &self.udp_result,
+                            &open,
+                        ));
+                    }
+                    Ok(_) => {
+                        command_error = Some(UdpOptError::UnexpectedCommand);
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    Err(TryRecvError::Empty) => {}
+                }
+                if self.config.shutdown_requested() {
+                    event!(info, "shutdown requested, stopping");
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+
+            let event = match events.recv_timeout(SHARD_POLL) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            match event {
+                ShardEvent::Peer(peer) => {
+                    event!(info, %peer, "new peer");
+                    if first_peer.is_none()
+                        && let Some(nic) = nic.as_mut()
+                        && let Err(_e) = nic.restart()
+                    {
+                        event!(warn, error = %_e, "interface counters unreadable");
+                    }
+                    first_peer.get_or_insert(peer);
+                    peers.entry(peer).or_insert(false);
+                }
+                ShardEvent::Fin(peer) => {
+                    event!(info, %peer, "FIN received");
+                    peers.insert(peer, true);
+                    if peers.values().all(|&fin| fin) {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                }
+                ShardEvent::Quiet {
+                    shard,
+                    quiet: is_quiet,
+                } => {
+                    quiet[shard] = is_quiet;
+                    if quiet.iter().all(|&q| q) && !stop.load(Ordering::Relaxed) {
+                        event!(warn, "no traffic on any shard, clients gone");
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                }
+                ShardEvent::Interval {
+                    shard,
+                    index,
+                    result,
+                } => {
+                    self.shard_results[shard].push(result);
+                    reported[shard] = Some(index);
+                    let slot = (index - next_index) as usize;
+                    if pending.len() <= slot {
+                        pending.resize(slot + 1, IntervalResult::default());
+                    }
+                    pending[slot].merge(&result);
+                }
+                ShardEvent::Done => running -= 1,
+                ShardEvent::Failed(_shard, e) => {
+                    event!(warn, shard = _shard, error = %crate::ui::format_error(&e), "shard failed");
+                    stop.store(true, Ordering::Relaxed);
+                    failure.get_or_insert(e);
+                    running -= 1;
+                }
+            }
+
+            // an interval is complete once every shard reported it, once
+            // stopping the rest is flushed below with the partial intervals
+            while !pending.is_empty()
+                && !stop.load(Ordering::Relaxed)
+                && reported.iter().all(|r| r.is_some_and(|r| r >= next_index))
+            {
+                let result = pending.remove(0);
+                next_index += 1;
+                self.emit(first_peer, result, &mut nic)?;
+            }
+        }
+
+        // the last, partial intervals of the shards
+        let last = pending.len();
+        for (i, result) in pending.into_iter().enumerate() {
+            let has_traffic = result.received + result.lost + result.duplicates > 0;
+            if i + 1 < last || has_traffic || self.udp_result.is_empty() {
+                self.emit(first_peer, result, &mut nic)?;
+            }
+        }
+
+        match (failure, command_error) {
+            (Some(e), _) | (None, Some(e)) => Err(e),
+            (None, None) => Ok(()),
+        }
+    }
+
+    fn emit(
+        &mut self,
+        peer: Option<SocketAddr>,
+        mut result: IntervalResult,
+        nic: &mut Option<NicSampler>,
+    ) -> Result<(), UdpOptError> {
+        result.nic = nic.as_mut().and_then(|nic| nic.sample().ok());
+        self.config.emit_interval(&result);
+        if let Some(peer) = peer {
+            self.config.sink_interval(peer, &result);
+        }
+        self.udp_result.push(result);
+        Ok(())
+    }
+
+    /// Number of receive threads.
+    pub fn shards(&self) -> usize {
+        self.shards
+    }
+
+    /// Returns the aggregate results of the last run.
+    pub fn get_results(&self) -> &[IntervalResult] {
+        &self.udp_result
+    }
+
+    /// Returns the interval results of every shard of the last run, indexed by shard.
+    pub fn shard_results(&self) -> &[Vec<IntervalResult>] {
+        &self.shard_results
+    }
+}
+
+/// One receive thread of a [`ShardedServer`]
+struct Shard<'a> {
+    id: usize,
+    sock: UdpSocket,
+    interval: Duration,
+    /// Count the CE-marked packets
+    ecn: bool,
+    /// Count the datagrams the socket dropped with its buffer full
+    socket_drops: bool,
+    /// How the jitter of the intervals is computed
+    jitter: JitterEstimator,
+    /// How long the shard may stay quiet before it tells the coordinator
+    idle_timeout: Option<Duration>,
+    /// Drop the packets not signed with this key
+    #[cfg(feature = "auth")]
+    auth: Option<&'a AuthKey>,
+    /// Which tokens may open a flow
+    access: Option<&'a Mutex<AccessControl>>,
+    /// Prefixes the datagrams must come from, any source when empty
+    allow_sources: &'a [IpNet],
+    /// Start of the interval clock, set by the first packet on any shard
+    epoch: &'a OnceLock<Instant>,
+    stop: &'a AtomicBool,
+    tx: &'a Sender<ShardEvent>,
+}
+
+impl Shard<'_> {
+    fn run(self, tuning: ThreadTuning, buffers: &BufferPool) -> Result<(), UdpOptError> {
+        tuning.apply().map_err(UdpOptError::SchedulingFailed)?;
+        self.sock
+            .set_read_timeout(Some(SHARD_POLL))
+            .map_err(|_| UdpOptError::SocketTimeout)?;
+        if self.ecn {
+            self.sock.enable_ecn().map_err(UdpOptError::EcnFailed)?;
+        }
+        if self.socket_drops {
+            self.sock
+                .enable_drop_count()
+                .map_err(UdpOptError::DropCountFailed)?;
+        }
+
+        let mut buf = buffers.get(RECV_BUF_LEN);
+        // a shard can carry several flows, each with its own sequence
+        // numbers; the session keeps a new run from a reused source port apart
+        let mut peers: HashMap<(SocketAddr, u32), UdpData> = HashMap::new();
+        let mut index = 0u64;
+        // datagrams from outside `allow_sources` and datagrams that are not
+        // udpopt packets in the current interval, and the socket's drops
+        let mut foreign = 0u64;
+        let mut malformed = 0u64;
+        let mut drops = 0u64;
+        // drop count of the socket at the last datagram; it is shared by all
+        // the flows of the shard, which would each count its growth
+        let mut dropped = None;
+        // last packet of a flow of this shard, and whether the coordinator
+        // was told the shard is quiet
+        let mut last_packet = None;
+        let mut quiet = false;
+
+        while !self.stop.load(Ordering::Relaxed) {
+            let received = recv_datagram(&self.sock, &mut buf, self.ecn || self.socket_drops);
+            match received {
+                Ok((len, from, meta)) => {
+                    if self.socket_drops {
+                        // 0 before the first drop
+                        let count = meta.dropped.unwrap_or(0);
+                        if let Some(last) = dropped {
+                            drops += u64::from(count.wrapping_sub(last));
+                        }
+                        dropped = Some(count);
+                    }
+                    if !source_allowed(self.allow_sources, from.ip()) {
+                        foreign += 1;
+                        continue;
+                    }
+                    let Ok(header) = UdpHeader::parse_received(&buf, len) else {
+                        malformed += 1;
+                        continue;
+                    };
+                    #[cfg(feature = "auth")]
+                    if self.auth.is_some_and(|key| !key.verify(&buf[..len])) {
+                        // a spoofed source must not open a flow of its own
+                        if let Some(data) = peers.get_mut(&(from, header.session)) {
+                            data.record_auth_failure();
+                        }
+                        continue;
+                    }
+                    let flow = (from, header.session);
+                    // a report of the client or a heartbeat, neither opens a flow
+                    if header.flags == FLAG_STATS {
+                        if let Some(data) = peers.get_mut(&flow) {
+                            data.record_sender_stats(&header);
+                            last_packet = Some(Instant::now());
+                        }
+                        continue;
+                    }
+                    if header.flags == FLAG_HEARTBEAT {
+                        if peers.contains_key(&flow) {
+                            last_packet = Some(Instant::now());
+                        }
+                        continue;
+                    }
+                    if !peers.contains_key(&flow) && !self.authorized(&buf[..len], from) {
+                        continue;
+                    }
+                    let epoch = *self.epoch.get_or_init(Instant::now);
+                    let data = peers.entry(flow).or_insert_with(|| {
+                        let _ = self.tx.send(ShardEvent::Peer(from));
+                        UdpData::with_jitter(self.jitter)
+                    });
+                    data.process_packet(&buf[..len], &header, epoch.elapsed());
+                    data.record_ecn(meta.ecn);
+                    last_packet = Some(Instant::now());
+                    if header.flags == FLAG_FIN {
+                        data.finish();
+                        let _ = self.tx.send(ShardEvent::Fin(from));
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(UdpOptError::RecvFailed(e)),
+            }
+
+            let Some(epoch) = self.epoch.get() else {
+                continue;
+            };
+            // close every interval boundary passed, quiet ones included
+            while epoch.elapsed() >= self.interval * (index as u32 + 1) {
+                self.close_interval(&mut peers, index, self.interval, foreign, malformed, drops);
+                foreign = 0;
+                malformed = 0;
+                drops = 0;
+                index += 1;
+            }
+            // a shard without flows is quiet from the start of the test
+            if let Some(idle) = self.idle_timeout
+                && (last_packet.unwrap_or(*epoch).elapsed() >= idle) != quiet
+            {
+                quiet = !quiet;
+                let _ = self.tx.send(ShardEvent::Quiet {
+                    shard: self.id,
+                    quiet,
+                });
+            }
+        }
+
+        if let Some(epoch) = self.epoch.get() {
+            let partial = epoch.elapsed().saturating_sub(self.interval * index as u32);
+            self.close_interval(&mut peers, index, partial, foreign, malformed, drops);
+        }
+        Ok(())
+    }
+
+    /// Whether a new flow from `from` may start, always without access control
+    fn authorized(&self, packet: &[u8], from: SocketAddr) -> bool {
+        self.access.is_none_or(|access| {
+            access
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .authorize(packet, from)
+        })
+    }
+
+    fn close_interval(
+        &self,
+        peers: &mut HashMap<(SocketAddr, u32), UdpData>,
+        index: u64,
+        time: Duration,
+        foreign: u64,
+        malformed: u64,
+        socket_drops: u64,
+    ) {
+        // the intervals are laid on the shared epoch
+        let offset = self.interval * index as u32;
+        let start_time = self.epoch.get().and_then(|epoch| {
+            SystemTime::now()
+                .checked_sub(epoch.elapsed())
+                .map(|at| at + offset)
+        });
+        let mut result = IntervalResult {
+            time,
+            foreign,
+            malformed,
+            socket_drops,
+            start_time,
+            offset,
+            ..Default::default()
+        };
+        for data in peers.values_mut() {
+            result.merge(&data.get_interval_result(time));
+        }
+        let _ = self.tx.send(ShardEvent::Interval {
+            shard: self.id,
+            index,
+            result,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientBuilder, ClientCommand, ServerBuilder, utils::udp_data::HEADER_SIZE};
+
+    #[test]
+    fn test_sharded_receive() {
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(100)).build_sharded(4, server_rx);
+        let (bound_tx, bound_rx) = mpsc::channel();
+        let server_handle = thread::spawn(move || {
+            let res = server.run_reporting("127.0.0.1:0".parse().unwrap(), bound_tx);
+            (server, res)
+        });
+        server_tx.send(ServerCommand::Start).unwrap();
+        let addr = bound_rx.recv().unwrap();
+
+        // several flows, so the kernel has something to spread
+        let clients: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+                    sock.connect(addr).unwrap();
+                    let (tx, rx) = mpsc::channel();
+                    let mut client =
+                        ClientBuilder::new(2_000_000.0, 500, Duration::from_millis(300)).build(rx);
+                    tx.send(ClientCommand::Start).unwrap();
+                    client.run(&mut sock).unwrap()
+                })
+            })
+            .collect();
+        let sent: u64 = clients
+            .into_iter()
+            .map(|c| c.join().unwrap().packets_sent)
+            .sum();
+
+        let (server, res) = server_handle.join().unwrap();
+        let results = res.unwrap();
+        let received: u64 = results.iter().map(|r| r.received).sum();
+        // every packet counts here, the FINs of the 4 flows included
+        assert_eq!(received, sent + 4);
+
+        assert_eq!(server.shard_results().len(), 4);
+        let per_shard: u64 = server
+            .shard_results()
+            .iter()
+            .flatten()
+            .map(|r| r.received)
+            .sum();
+        assert_eq!(per_shard, received);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_merged_intervals_carry_nic_counters() {
+        if !std::path::Path::new("/sys/class/net/lo").exists() {
+            return;
+        }
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(100))
+            .nic_counters("lo")
+            .build_sharded(2, server_rx);
+        let (bound_tx, bound_rx) = mpsc::channel();
+        let handle =
+            thread::spawn(move || server.run_reporting("127.0.0.1:0".parse().unwrap(), bound_tx));
+        server_tx.send(ServerCommand::Start).unwrap();
+        let addr = bound_rx.recv().unwrap();
+
+        let mut sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.connect(addr).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let mut client = ClientBuilder::new(400_000.0, 500, Duration::from_millis(250)).build(rx);
+        tx.send(ClientCommand::Start).unwrap();
+        let sent = client.run(&mut sock).unwrap().packets_sent;
+
+        let results = handle.join().unwrap().unwrap();
+        let nic: Vec<_> = results.iter().map(|r| r.nic.unwrap()).collect();
+        // the test went through lo, with whatever else the host sent on it
+        assert!(nic.iter().map(|n| n.rx_packets).sum::<u64>() >= sent);
+
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(100))
+            .nic_counters("udpopt-none0")
+            .build_sharded(2, server_rx);
+        server_tx.send(ServerCommand::Start).unwrap();
+        let err = server.run("127.0.0.1:0".parse().unwrap()).unwrap_err();
+        assert!(matches!(err, UdpOptError::NicCountersFailed(_)));
+    }
+
+    #[test]
+    fn test_quiet_shards_end_the_run() {
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(100))
+            .idle_timeout(Some(Duration::from_millis(300)))
+            .build_sharded(2, server_rx);
+        let (bound_tx, bound_rx) = mpsc::channel();
+        let handle =
+            thread::spawn(move || server.run_reporting("127.0.0.1:0".parse().unwrap(), bound_tx));
+        server_tx.send(ServerCommand::Start).unwrap();
+        let addr = bound_rx.recv().unwrap();
+
+        // two packets and no FIN: the shards give up on their own
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for seq in 0..2 {
+            let mut packet = vec![0u8; HEADER_SIZE + 100];
+            UdpHeader::new(seq, 0, 0, 0).write_header(&mut packet);
+            sock.send_to(&packet, addr).unwrap();
+        }
+        let started = Instant::now();
+        let results = handle.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(results.iter().map(|r| r.received).sum::<u64>(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "not supported by the sharded server")]
+    fn test_unsupported_options_panic() {
+        let (_tx, rx) = mpsc::channel();
+        ServerBuilder::new(Duration::from_millis(100))
+            .fin_linger(Duration::from_millis(100))
+            .build_sharded(2, rx);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_socket_drops() {
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(100))
+            .count_socket_drops()
+            .build_sharded(2, server_rx);
+        let (bound_tx, bound_rx) = mpsc::channel();
+        let handle =
+            thread::spawn(move || server.run_reporting("127.0.0.1:0".parse().unwrap(), bound_tx));
+        server_tx.send(ServerCommand::Start).unwrap();
+        let addr = bound_rx.recv().unwrap();
+
+        // a burst as fast as the socket takes it, whatever the shard misses
+        // of it shows up as its drops, counted with the FIN sent once it
+        // drained the buffer
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut packet = vec![0u8; HEADER_SIZE + 100];
+        let sent = 5000;
+        for seq in 0..=sent {
+            let flags = if seq == sent {
+                thread::sleep(Duration::from_millis(100));
+                FLAG_FIN
+            } else {
+                0
+            };
+            UdpHeader::new(seq, 0, 0, flags).write_header(&mut packet);
+            sock.send_to(&packet, addr).unwrap();
+        }
+        let results = handle.join().unwrap().unwrap();
+        let received: u64 = results.iter().map(|r| r.received).sum();
+        let drops: u64 = results.iter().map(|r| r.socket_drops).sum();
+        assert_eq!(received + drops, sent + 1, "{received} + {drops}");
+    }
+
+    #[test]
+    fn test_stop_without_traffic() {
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(100)).build_sharded(2, server_rx);
+        let handle = thread::spawn(move || server.run("127.0.0.1:0".parse().unwrap()));
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(150));
+        // answered without ending the run
+        let (reply, status) = tokio::sync::oneshot::channel();
+        server_tx.send(ServerCommand::Status(reply)).unwrap();
+        let status = status.blocking_recv().unwrap();
+        assert_eq!((status.peer, status.received), (None, 0));
+        server_tx.send(ServerCommand::Stop).unwrap();
+        assert!(handle.join().unwrap().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+//! Graceful shutdown on Ctrl-C / SIGTERM (feature `signal`).
+//!
+//! Clients and servers built with `stop_on_signal()` install a process-wide
+//! handler the first time they run. Once a signal arrives they behave as if
+//! they had received `Stop`: the client sends its FIN and returns its report,
+//! the server flushes the partial interval and returns the collected results.
+//!
+//! A run only heeds the signals arriving after it started, so a long-lived
+//! process can run again after one was stopped.
+//!
+//! ```no_run
+//! use std::net::UdpSocket;
+//! use std::sync::mpsc;
+//! use std::time::Duration;
+//! use udpopt::{ServerBuilder, ServerCommand};
+//!
+//! let mut sock = UdpSocket::bind("0.0.0.0:5201").unwrap();
+//! let (tx, rx) = mpsc::channel();
+//! let mut server = ServerBuilder::new(Duration::from_secs(1))
+//!     .stop_on_signal()
+//!     .build(rx);
+//! tx.send(ServerCommand::Start).unwrap();
+//! // returns the results so far when Ctrl-C is pressed
+//! let results = server.run(&mut sock).unwrap();
+//! ```
+
+use std::sync::{
+    OnceLock,
+    atomic::{AtomicU64, Ordering},
+};
+
+use crate::errors::UdpOptError;
+
+/// Shutdowns requested so far, runs compare it with the count they started at
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+static INSTALLED: OnceLock<Result<(), String>> = OnceLock::new();
+
+/// Installs the Ctrl-C / SIGTERM handler, once per process.
+///
+/// Returns [`UdpOptError::SignalFailed`] if another handler is already installed.
+pub fn install() -> Result<(), UdpOptError> {
+    INSTALLED
+        .get_or_init(|| ctrlc::set_handler(request).map_err(|e| e.to_string()))
+        .clone()
+        .map_err(UdpOptError::SignalFailed)
+}
+
+/// Requests a shutdown, exactly as if a signal had been received.
+pub fn request() {
+    REQUESTS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Whether a shutdown was ever requested in this process.
+pub fn is_requested() -> bool {
+    requests() > 0
+}
+
+/// Shutdowns requested so far, a run stops once it grows past the count it
+/// started at
+pub(crate) fn requests() -> u64 {
+    REQUESTS.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientBuilder, ClientCommand, ServerBuilder, ServerCommand};
+    use std::net::UdpSocket;
+    use std::sync::{Mutex, PoisonError, mpsc::channel};
+    use std::thread;
+    use std::time::Duration;
+
+    /// The signals are process-wide, the tests sending one take turns
+    static SIGNALS: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_shutdown_returns_partial_results() {
+        let _turn = SIGNALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let server_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_sock
+            .connect(server_sock.local_addr().unwrap())
+            .unwrap();
+
+        let (server_tx, server_rx) = channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(10))
+            .stop_on_signal()
+            .build(server_rx);
+        let server_handle = thread::spawn(move || {
+            let mut sock = server_sock;
+            server.run(&mut sock)
+        });
+
+        let (client_tx, client_rx) = channel();
+        let mut client = ClientBuilder::new(1_000_000.0, 500, Duration::from_secs(30))
+            .stop_on_signal()
+            .build(client_rx);
+        let client_handle = thread::spawn(move || {
+            let mut sock = client_sock;
+            client.run(&mut sock)
+        });
+
+        server_tx.send(ServerCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client_tx.send(ClientCommand::Start).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        request();
+
+        let report = client_handle.join().unwrap().unwrap();
+        assert!(report.duration < Duration::from_secs(5));
+        assert!(report.packets_sent > 0);
+
+        // the interval is 10s, so everything is in the flushed partial interval
+        let results = server_handle.join().unwrap().unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].received > 0);
+    }
+
+    #[test]
+    fn test_next_run_ignores_an_earlier_signal() {
+        let _turn = SIGNALS.lock().unwrap_or_else(PoisonError::into_inner);
+        request();
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.connect(sock.local_addr().unwrap()).unwrap();
+        let (tx, rx) = channel();
+        let mut client = ClientBuilder::new(1_000_000.0, 500, Duration::from_millis(300))
+            .stop_on_signal()
+            .build(rx);
+        tx.send(ClientCommand::Start).unwrap();
+        let mut sock = sock;
+        let report = client.run(&mut sock).unwrap();
+        // ran its whole duration
+        assert!(report.duration >= Duration::from_millis(250));
+    }
+}
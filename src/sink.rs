@@ -0,0 +1,615 @@
+//! Interval result export for monitoring systems.
+//!
+//! This module defines the [`ResultSink`] trait used by the servers to push
+//! every [`IntervalResult`] to an external system as soon as it is produced,
+//! plus [`InfluxSink`] which writes them in the InfluxDB line protocol to a
+//! file, a UDP listener ([`UdpLineWriter`]) or the HTTP write API
+//! ([`HttpLineWriter`]).
+//!
+//! The sinks of a server run on a thread of their own, so a slow or
+//! unreachable endpoint never holds up the receive loop: a failing sink is
+//! logged and the test goes on, and the results a sink is too slow to take
+//! are dropped once a few hundred are waiting.
+//!
+//! ```no_run
+//! use std::sync::mpsc;
+//! use std::time::Duration;
+//! use udpopt::ServerBuilder;
+//! use udpopt::sink::{Direction, HttpLineWriter, InfluxSink};
+//!
+//! let writer = HttpLineWriter::new(
+//!     "http://influx.example:8086/api/v2/write?org=net&bucket=udpopt&precision=ns",
+//!     Some("my-token".into()),
+//! )
+//! .unwrap();
+//! let sink = InfluxSink::new(writer)
+//!     .test_id("edge-42")
+//!     .direction(Direction::Upload);
+//!
+//! let (_tx, rx) = mpsc::channel();
+//! let server = ServerBuilder::new(Duration::from_secs(1))
+//!     .result_sink(sink)
+//!     .build(rx);
+//! ```
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::oneshot;
+
+use crate::{
+    runtime::{AsyncDatagram, timeout},
+    utils::net_utils::IntervalResult,
+};
+
+/// Results waiting for the sinks before the next ones are dropped
+const SINK_QUEUE: usize = 256;
+/// How long the end of a run waits for the sinks to catch up and flush
+const FLUSH_WAIT: Duration = Duration::from_secs(10);
+
+/// Destination for interval results.
+///
+/// Implement this to push the results anywhere (database, message queue, ...).
+pub trait ResultSink: Send {
+    /// Writes the result of one interval received from `peer`.
+    fn write_interval(&mut self, peer: SocketAddr, result: &IntervalResult) -> io::Result<()>;
+
+    /// Flushes any buffered results; called once when the test ends.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Work for the sink thread
+enum SinkJob {
+    Interval(SocketAddr, Box<IntervalResult>),
+    /// Flush every sink, then answer
+    Flush(FlushDone),
+}
+
+/// Tells the end of a run the sinks are flushed
+enum FlushDone {
+    Sync(mpsc::SyncSender<()>),
+    Async(oneshot::Sender<()>),
+}
+
+/// The sinks of a server and the thread they run on, started with the
+/// first result
+#[derive(Default)]
+pub(crate) struct SinkQueue {
+    /// Sinks not handed to the thread yet
+    sinks: Vec<Box<dyn ResultSink>>,
+    /// How many sinks there are
+    len: usize,
+    tx: Option<mpsc::SyncSender<SinkJob>>,
+}
+
+impl SinkQueue {
+    pub(crate) fn push(&mut self, sink: Box<dyn ResultSink>) {
+        self.sinks.push(sink);
+        self.len += 1;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Queues `result` for the sinks, drops it if they are too far behind
+    pub(crate) fn send(&mut self, peer: SocketAddr, result: &IntervalResult) {
+        let Some(tx) = self.sender() else {
+            return;
+        };
+        match tx.try_send(SinkJob::Interval(peer, Box::new(*result))) {
+            Ok(()) => {}
+            Err(mpsc::TrySendError::Full(_)) => {
+                event!(warn, "result sinks too slow, interval dropped");
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => self.tx = None,
+        }
+    }
+
+    /// Waits until the sinks wrote and flushed the queued results, or gave
+    /// up on them
+    pub(crate) fn flush(&mut self) {
+        let (done, flushed) = mpsc::sync_channel(1);
+        if self.request_flush(FlushDone::Sync(done)) && flushed.recv_timeout(FLUSH_WAIT).is_err() {
+            event!(warn, "result sinks not flushed in time");
+        }
+    }
+
+    /// Async version of [`flush`](Self::flush).
+    pub(crate) async fn flush_async<S: AsyncDatagram>(&mut self) {
+        let (done, flushed) = oneshot::channel();
+        if self.request_flush(FlushDone::Async(done))
+            && !matches!(timeout::<S, _>(FLUSH_WAIT, flushed).await, Some(Ok(())))
+        {
+            event!(warn, "result sinks not flushed in time");
+        }
+    }
+
+    /// Whether the sink thread took the flush request
+    fn request_flush(&mut self, done: FlushDone) -> bool {
+        let Some(tx) = self.tx.as_ref() else {
+            return false;
+        };
+        match tx.try_send(SinkJob::Flush(done)) {
+            Ok(()) => true,
+            Err(mpsc::TrySendError::Full(_)) => {
+                event!(warn, "result sinks too slow, not flushed");
+                false
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                self.tx = None;
+                false
+            }
+        }
+    }
+
+    /// The queue of the sink thread, started on the first call
+    fn sender(&mut self) -> Option<&mpsc::SyncSender<SinkJob>> {
+        if self.tx.is_none() && !self.sinks.is_empty() {
+            let (tx, rx) = mpsc::sync_channel(SINK_QUEUE);
+            let sinks = std::mem::take(&mut self.sinks);
+            match thread::Builder::new()
+                .name("udpopt-sinks".to_string())
+                .spawn(move || deliver(sinks, rx))
+            {
+                Ok(_) => self.tx = Some(tx),
+                Err(_e) => event!(warn, error = %_e, "result sinks not started"),
+            }
+        }
+        self.tx.as_ref()
+    }
+}
+
+/// Body of the sink thread, runs until the server is dropped
+fn deliver(mut sinks: Vec<Box<dyn ResultSink>>, jobs: mpsc::Receiver<SinkJob>) {
+    for job in jobs {
+        match job {
+            SinkJob::Interval(peer, result) => {
+                for sink in sinks.iter_mut() {
+                    if let Err(_e) = sink.write_interval(peer, &result) {
+                        event!(warn, error = %_e, "result sink failed");
+                    }
+                }
+            }
+            SinkJob::Flush(done) => {
+                for sink in sinks.iter_mut() {
+                    if let Err(_e) = sink.flush() {
+                        event!(warn, error = %_e, "result sink failed to flush");
+                    }
+                }
+                // the run may have given up waiting
+                match done {
+                    FlushDone::Sync(done) => {
+                        let _ = done.send(());
+                    }
+                    FlushDone::Async(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Which way the measured traffic flows, reported as the `direction` tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Direction {
+    /// Client to server.
+    #[default]
+    Upload,
+    /// Server to client (reverse mode).
+    Download,
+}
+
+impl Direction {
+    /// Tag value of the direction.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Upload => "upload",
+            Direction::Download => "download",
+        }
+    }
+}
+
+/// Writes interval results in the InfluxDB line protocol, one line per interval.
+///
+/// Each line is flushed right away so it reaches the endpoint in real time.
+/// It is timestamped with the start of its interval, or the time it is
+/// written for an interval without one.
+#[derive(Debug)]
+pub struct InfluxSink<W: Write + Send> {
+    out: W,
+    measurement: String,
+    test_id: Option<String>,
+    tags: Vec<(String, String)>,
+    direction: Direction,
+}
+
+impl<W: Write + Send> InfluxSink<W> {
+    /// Creates a sink writing to `out` with the measurement name `udpopt`.
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            measurement: "udpopt".into(),
+            test_id: None,
+            tags: Vec::new(),
+            direction: Direction::default(),
+        }
+    }
+
+    /// Sets the measurement name.
+    pub fn measurement(mut self, name: impl Into<String>) -> Self {
+        self.measurement = name.into();
+        self
+    }
+
+    /// Tags every line with `test_id`.
+    pub fn test_id(mut self, id: impl Into<String>) -> Self {
+        self.test_id = Some(id.into());
+        self
+    }
+
+    /// Tags every line with `key=value`, e.g. the site or circuit of the run.
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the `direction` tag (default [`Direction::Upload`]).
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Consumes the sink, returning the underlying output.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Formats one line, without the trailing newline.
+    pub(crate) fn line(&self, peer: SocketAddr, r: &IntervalResult, timestamp_ns: u128) -> String {
+        let mut line = escape(&self.measurement, ", ");
+        if let Some(id) = &self.test_id {
+            let _ = write!(line, ",test_id={}", escape(id, ", ="));
+        }
+        for (key, value) in &self.tags {
+            let _ = write!(line, ",{}={}", escape(key, ", ="), escape(value, ", ="));
+        }
+        let _ = write!(
+            line,
+            ",peer={},direction={} received={}i,lost={}i,bytes={}i,jitter_ms={},out_of_order={}i,duplicates={}i,bitrate_bps={},seconds={} {}",
+            escape(&peer.to_string(), ", ="),
+            self.direction.as_str(),
+            r.received,
+            r.lost,
+            r.bytes,
+            r.jitter_ms,
+            r.out_of_order,
+            r.duplicates,
+            r.bitrate_bps,
+            r.time.as_secs_f64(),
+            timestamp_ns
+        );
+        line
+    }
+}
+
+impl<W: Write + Send> ResultSink for InfluxSink<W> {
+    fn write_interval(&mut self, peer: SocketAddr, result: &IntervalResult) -> io::Result<()> {
+        let at = result
+            .start_time
+            .unwrap_or_else(SystemTime::now)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = self.line(peer, result, at.as_nanos());
+        writeln!(self.out, "{line}")?;
+        self.out.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Sends every flushed batch of lines as one datagram, for the InfluxDB
+/// (or Telegraf) UDP listener.
+#[derive(Debug)]
+pub struct UdpLineWriter {
+    sock: UdpSocket,
+    buf: Vec<u8>,
+}
+
+impl UdpLineWriter {
+    /// Creates a writer sending to `endpoint`.
+    pub fn new(endpoint: impl ToSocketAddrs) -> io::Result<Self> {
+        let addr = endpoint
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))?;
+        let local: SocketAddr = if addr.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let sock = UdpSocket::bind(local)?;
+        sock.connect(addr)?;
+        Ok(Self {
+            sock,
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl Write for UdpLineWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.sock.send(&self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+/// POSTs every flushed batch of lines to an InfluxDB HTTP write endpoint.
+///
+/// Only plain `http://` URLs are supported; put a TLS terminating proxy in
+/// front of the database for `https`.
+#[derive(Debug)]
+pub struct HttpLineWriter {
+    addr: SocketAddr,
+    host: String,
+    path: String,
+    token: Option<String>,
+    buf: Vec<u8>,
+}
+
+/// How long a write request may take before it fails
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl HttpLineWriter {
+    /// Creates a writer posting to `url`, e.g.
+    /// `http://localhost:8086/api/v2/write?org=o&bucket=b&precision=ns`.
+    ///
+    /// `token` is sent as `Authorization: Token <token>`.
+    pub fn new(url: &str, token: Option<String>) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http:// URLs are supported"))?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let addr = if host.contains(':') {
+            host.to_socket_addrs()?
+        } else {
+            (host, 80).to_socket_addrs()?
+        }
+        .next()
+        .ok_or_else(|| invalid("no address"))?;
+        Ok(Self {
+            addr,
+            host: host.to_string(),
+            path: path.to_string(),
+            token,
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl Write for HttpLineWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let mut stream = TcpStream::connect_timeout(&self.addr, HTTP_TIMEOUT)?;
+        stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+        stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host,
+            self.buf.len()
+        );
+        if let Some(token) = &self.token {
+            let _ = write!(request, "Authorization: Token {token}\r\n");
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(&self.buf)?;
+        self.buf.clear();
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "write rejected: {}",
+                status.trim_end()
+            ))),
+        }
+    }
+}
+
+// helper functions
+
+/// Backslash-escapes `special` characters as required by the line protocol
+fn escape(value: &str, special: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn sample() -> IntervalResult {
+        IntervalResult {
+            received: 100,
+            lost: 2,
+            bytes: 125_000,
+            jitter_ms: 0.5,
+            time: Duration::from_secs(1),
+            ..Default::default()
+        }
+        .with_bitrate()
+    }
+
+    #[test]
+    fn test_queue_does_not_wait_for_the_sinks() {
+        use std::sync::{Arc, Mutex};
+        use std::time::Instant;
+
+        struct Slow(Arc<Mutex<Vec<u64>>>);
+        impl ResultSink for Slow {
+            fn write_interval(&mut self, _: SocketAddr, result: &IntervalResult) -> io::Result<()> {
+                thread::sleep(Duration::from_millis(50));
+                self.0.lock().unwrap().push(result.received);
+                Ok(())
+            }
+        }
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut queue = SinkQueue::default();
+        queue.push(Box::new(Slow(written.clone())));
+        let peer = "192.0.2.1:5000".parse().unwrap();
+        let start = Instant::now();
+        for received in 0..5 {
+            queue.send(
+                peer,
+                &IntervalResult {
+                    received,
+                    ..sample()
+                },
+            );
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+        queue.flush();
+        assert_eq!(*written.lock().unwrap(), [0, 1, 2, 3, 4]);
+
+        // far behind, the sinks lose the results past the queue
+        for _ in 0..SINK_QUEUE + 10 {
+            queue.send(peer, &sample());
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_line_protocol() {
+        let sink = InfluxSink::new(Vec::new())
+            .test_id("lab run,1")
+            .tag("site", "ams=1")
+            .direction(Direction::Download);
+        let peer: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+
+        assert_eq!(
+            sink.line(peer, &sample(), 42),
+            "udpopt,test_id=lab\\ run\\,1,site=ams\\=1,peer=10.0.0.1:5000,direction=download \
+             received=100i,lost=2i,bytes=125000i,jitter_ms=0.5,out_of_order=0i,duplicates=0i,\
+             bitrate_bps=1000000,seconds=1 42"
+        );
+    }
+
+    #[test]
+    fn test_timestamp_is_the_interval_start() {
+        let peer: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let mut sink = InfluxSink::new(Vec::new());
+        let mut result = sample();
+        result.start_time = Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_250));
+        sink.write_interval(peer, &result).unwrap();
+        let out = String::from_utf8(std::mem::take(&mut sink.out)).unwrap();
+        assert!(out.ends_with(" 1700000000250000000\n"), "{out}");
+
+        // without a start, the time it is written
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        sink.write_interval(peer, &sample()).unwrap();
+        let out = String::from_utf8(sink.out).unwrap();
+        let ns: u128 = out.trim_end().rsplit(' ').next().unwrap().parse().unwrap();
+        assert!(ns >= before.as_nanos());
+    }
+
+    #[test]
+    fn test_udp_endpoint() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let writer = UdpLineWriter::new(listener.local_addr().unwrap()).unwrap();
+        let mut sink = InfluxSink::new(writer);
+        sink.write_interval("127.0.0.1:1".parse().unwrap(), &sample())
+            .unwrap();
+
+        let mut buf = [0u8; 1024];
+        let len = listener.recv(&mut buf).unwrap();
+        let line = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(line.starts_with("udpopt,peer=127.0.0.1:1,direction=upload received=100i"));
+        assert!(line.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_http_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/api/v2/write?bucket=b",
+            listener.local_addr().unwrap()
+        );
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            // read until the body (the line ends with '\n') is in
+            let complete = |r: &[u8]| {
+                r.windows(4)
+                    .position(|w| w == b"\r\n\r\n")
+                    .is_some_and(|i| r.len() > i + 4 && r.ends_with(b"\n"))
+            };
+            while !complete(&request) {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let writer = HttpLineWriter::new(&url, Some("secret".into())).unwrap();
+        let mut sink = InfluxSink::new(writer);
+        sink.write_interval("127.0.0.1:1".parse().unwrap(), &sample())
+            .unwrap();
+
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("POST /api/v2/write?bucket=b HTTP/1.1\r\n"));
+        assert!(request.contains("Authorization: Token secret\r\n"));
+        assert!(request.contains("\r\n\r\nudpopt,peer=127.0.0.1:1"));
+    }
+}
@@ -0,0 +1,1047 @@
+//! Blocking socket abstraction.
+//!
+//! [`UdpClient`](crate::UdpClient), [`UdpServer`](crate::UdpServer) and
+//! [`TestRunner`](crate::TestRunner) only use the few socket calls of the
+//! [`DatagramSocket`] trait, so they run on anything implementing it:
+//!
+//! - `std::net::UdpSocket`,
+//! - [`MockSocket`], an in-memory socket for deterministic tests without
+//!   the network.
+//!
+//! Async sockets, `tokio::net::UdpSocket` included, go through its async
+//! counterpart [`AsyncDatagram`](crate::AsyncDatagram).
+//!
+//! The ECN methods are optional: by default marking and reading the [`Ecn`]
+//! codepoint is `Unsupported`. The std and tokio sockets support it on Linux.
+//! So is [`bind_device`](DatagramSocket::bind_device), which makes a
+//! multi-homed host carry the test on a given [`Interface`], and so are
+//! [`set_dont_fragment`](DatagramSocket::set_dont_fragment) and
+//! [`path_mtu`](DatagramSocket::path_mtu), which tell how datagrams longer
+//! than the path MTU fare, and
+//! [`enable_drop_count`](DatagramSocket::enable_drop_count), which makes
+//! [`recv_from_meta`](DatagramSocket::recv_from_meta) tell the datagrams the
+//! socket dropped with a full receive buffer.
+//!
+//! A [`PortRange`] binds the first free source port of a range, so a firewall
+//! or NAT policy matching on source ports sees the ports it expects.
+//!
+//! ## Sockets configured elsewhere
+//!
+//! The client and the server never create their socket, so one set up with
+//! options udpopt does not model (`IP_FREEBIND`, `IP_TRANSPARENT`,
+//! buffer sizes, ...) works as is: build it with
+//! `socket2` and convert it with `UdpSocket::from(socket)`, take over a
+//! descriptor with [`from_fd`], or the sockets of a systemd socket unit with
+//! [`systemd_sockets`]. For the async side, make it non-blocking and pass it
+//! to `tokio::net::UdpSocket::from_std`.
+//!
+//! The client sends on the socket as connected by the caller. The crate
+//! only changes:
+//!
+//! - the read timeout, while the server waits for packets and while the
+//!   client waits for the server's results; the client restores it, the
+//!   server leaves it set;
+//! - the non-blocking mode, while the server drops the datagrams queued
+//!   before a test and while a client sends non-blocking; it is left
+//!   blocking, as the sync client and server expect it;
+//! - the TOS byte (traffic class on IPv6), when the client is given a
+//!   DSCP or asked for ECN;
+//! - the interface the socket is bound to and sends multicast through,
+//!   when the client or the server is given one with `bind_device`;
+//! - `IP_MTU_DISCOVER` / `IPV6_MTU_DISCOVER`, when the client is told to
+//!   set or clear the don't-fragment bit;
+//! - `IP_RECVTOS` / `IPV6_RECVTCLASS`, when the server counts ECN marks.
+//!
+//! Everything else, the binding, the connection and the buffers among
+//! them, stays as the caller set it.
+
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    str::FromStr,
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::errors::UdpOptError;
+#[cfg(target_os = "linux")]
+use crate::utils::{device, ecn, pmtu, sys};
+
+/// ECN codepoint of a datagram, the two low bits of its TOS byte (RFC 3168).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecn {
+    /// Not ECN-capable.
+    NotEct,
+    /// ECN-capable, ECT(1).
+    Ect1,
+    /// ECN-capable, ECT(0), what the client sends.
+    Ect0,
+    /// Congestion experienced, set by a router or AQM instead of dropping.
+    Ce,
+}
+
+impl Ecn {
+    /// Codepoint of the TOS byte `tos`.
+    pub fn from_tos(tos: u8) -> Self {
+        match tos & 0b11 {
+            0b00 => Ecn::NotEct,
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+
+    /// The codepoint as the two low bits of a TOS byte.
+    pub fn bits(self) -> u8 {
+        match self {
+            Ecn::NotEct => 0b00,
+            Ecn::Ect1 => 0b01,
+            Ecn::Ect0 => 0b10,
+            Ecn::Ce => 0b11,
+        }
+    }
+}
+
+/// Network interface a socket is bound to, see
+/// [`DatagramSocket::bind_device`].
+///
+/// Parsed from a string, digits give an index and anything else a name, so
+/// `"eth1"` and `"3"` both work on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(untagged)
+)]
+pub enum Interface {
+    /// Interface index, like the scope of an IPv6 link-local address.
+    Index(u32),
+    /// Interface name, e.g. `eth1`.
+    Name(String),
+}
+
+impl Interface {
+    /// Index of the interface, looked up by name if need be (Linux).
+    ///
+    /// # Errors
+    /// If no interface has that name, or elsewhere than on Linux
+    /// [`io::ErrorKind::Unsupported`] for a name.
+    pub fn index(&self) -> io::Result<u32> {
+        match self {
+            Self::Index(index) => Ok(*index),
+            #[cfg(target_os = "linux")]
+            Self::Name(name) => device::index_of(name),
+            #[cfg(not(target_os = "linux"))]
+            Self::Name(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
+impl From<&str> for Interface {
+    fn from(name: &str) -> Self {
+        Self::Name(name.to_string())
+    }
+}
+
+impl From<String> for Interface {
+    fn from(name: String) -> Self {
+        Self::Name(name)
+    }
+}
+
+impl From<u32> for Interface {
+    fn from(index: u32) -> Self {
+        Self::Index(index)
+    }
+}
+
+impl FromStr for Interface {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse().map_or_else(|_| Self::from(s), Self::Index))
+    }
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "{index}"),
+            Self::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Inclusive range of UDP ports, written `40000-40099`, or `40000` for one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct PortRange {
+    first: u16,
+    last: u16,
+}
+
+impl PortRange {
+    /// Ports `first` to `last`, both included.
+    ///
+    /// # Panics
+    /// If `first` is 0 or above `last`.
+    pub fn new(first: u16, last: u16) -> Self {
+        assert!(
+            0 < first && first <= last,
+            "the port range must be 0 < first <= last"
+        );
+        Self { first, last }
+    }
+
+    /// First port of the range.
+    pub fn first(&self) -> u16 {
+        self.first
+    }
+
+    /// Last port of the range.
+    pub fn last(&self) -> u16 {
+        self.last
+    }
+
+    /// Whether `port` is in the range.
+    pub fn contains(&self, port: u16) -> bool {
+        (self.first..=self.last).contains(&port)
+    }
+
+    /// Binds a socket to `ip` on the first port of the range not in use.
+    ///
+    /// Sockets bound one after the other while the earlier ones stay open
+    /// get consecutive ports.
+    ///
+    /// # Errors
+    /// [`io::ErrorKind::AddrInUse`] if every port is taken, or the first
+    /// error other than a port in use.
+    pub fn bind(&self, ip: IpAddr) -> io::Result<UdpSocket> {
+        for port in self.first..=self.last {
+            match UdpSocket::bind((ip, port)) {
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                bound => return bound,
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("no free port in {self}"),
+        ))
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = UdpOptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || UdpOptError::InvalidPortRange(s.to_string());
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        let first: u16 = first.trim().parse().map_err(|_| invalid())?;
+        let last: u16 = last.trim().parse().map_err(|_| invalid())?;
+        if first == 0 || first > last {
+            return Err(invalid());
+        }
+        Ok(Self { first, last })
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = UdpOptError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(range: PortRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == self.last {
+            return write!(f, "{}", self.first);
+        }
+        write!(f, "{}-{}", self.first, self.last)
+    }
+}
+
+/// Binds a socket to `local`, or to its address on the first free port of
+/// `ports` if given.
+///
+/// # Errors
+/// [`UdpOptError::BindFailed`] if the socket cannot be bound, with the first
+/// port of `ports` when every one is taken.
+pub fn bind(local: SocketAddr, ports: Option<PortRange>) -> Result<UdpSocket, UdpOptError> {
+    match ports {
+        Some(ports) => ports
+            .bind(local.ip())
+            .map_err(|source| UdpOptError::BindFailed {
+                addr: (local.ip(), ports.first()).into(),
+                source,
+            }),
+        None => UdpSocket::bind(local).map_err(|source| UdpOptError::BindFailed {
+            addr: local,
+            source,
+        }),
+    }
+}
+
+/// A socket bound to `local`, any address of the family of `server` when
+/// `None`, on the first free port of `ports` if given, and connected to
+/// `server`
+pub(crate) fn connect(
+    local: Option<SocketAddr>,
+    ports: Option<PortRange>,
+    server: SocketAddr,
+) -> Result<UdpSocket, UdpOptError> {
+    let unspecified = if server.is_ipv6() {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    };
+    let sock = bind(local.unwrap_or((unspecified, 0).into()), ports)?;
+    sock.connect(server)
+        .map_err(|source| UdpOptError::ConnectFailed {
+            peer: server,
+            source,
+        })?;
+    Ok(sock)
+}
+
+/// A blocking UDP socket.
+///
+/// The methods follow their `std::net::UdpSocket` namesakes: a receive that
+/// times out returns `WouldBlock` or `TimedOut`.
+pub trait DatagramSocket {
+    /// Sends on a connected socket.
+    fn send(&self, buf: &[u8]) -> io::Result<usize>;
+
+    /// Receives on a connected socket.
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Sends to `target`.
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize>;
+
+    /// Receives a datagram and its sender.
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Address of the connected peer.
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Current receive timeout, `None` blocks forever.
+    fn read_timeout(&self) -> io::Result<Option<Duration>>;
+
+    /// Sets the receive timeout.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Makes receives return `WouldBlock` at once when nothing is queued.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    /// Marks the datagrams sent from now on ECN-capable, [`Ecn::Ect0`].
+    fn set_ect0(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Sets the whole TOS byte (traffic class on IPv6) of the datagrams sent
+    /// from now on, the DSCP in its six high bits and the ECN codepoint in
+    /// the two low ones.
+    fn set_tos(&self, tos: u8) -> io::Result<()> {
+        let _ = tos;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Makes [`recv_from_ecn`](Self::recv_from_ecn) report the codepoint of
+    /// the received datagrams.
+    fn enable_ecn(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Binds the socket to `interface` (`SO_BINDTODEVICE`): it only receives
+    /// what arrived on it and sends through it, multicast included, whatever
+    /// the routing table prefers.
+    fn bind_device(&self, interface: &Interface) -> io::Result<()> {
+        let _ = interface;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Sets (`true`) or clears the don't-fragment bit of the datagrams sent
+    /// from now on. Set, a datagram longer than the path MTU is refused with
+    /// `EMSGSIZE`; cleared, the host fragments it.
+    fn set_dont_fragment(&self, on: bool) -> io::Result<()> {
+        let _ = on;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Path MTU towards the connected peer, as far as the host knows it.
+    fn path_mtu(&self) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Receives a datagram, its sender and its ECN codepoint, `None` when
+    /// unknown.
+    fn recv_from_ecn(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<Ecn>)> {
+        let (len, from) = self.recv_from(buf)?;
+        Ok((len, from, None))
+    }
+
+    /// Makes [`recv_from_meta`](Self::recv_from_meta) report how many
+    /// datagrams the socket dropped with its receive buffer full
+    /// (`SO_RXQ_OVFL`).
+    fn enable_drop_count(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Receives a datagram, its sender and what the kernel told about it,
+    /// see [`RecvMeta`].
+    fn recv_from_meta(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, RecvMeta)> {
+        let (len, from, ecn) = self.recv_from_ecn(buf)?;
+        Ok((len, from, RecvMeta { ecn, dropped: None }))
+    }
+}
+
+/// What the kernel told about a received datagram besides its sender, see
+/// [`DatagramSocket::recv_from_meta`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecvMeta {
+    /// ECN codepoint, after [`DatagramSocket::enable_ecn`].
+    pub ecn: Option<Ecn>,
+    /// Datagrams the socket dropped with its receive buffer full since it
+    /// was created, as of the arrival of this one, after
+    /// [`DatagramSocket::enable_drop_count`]; `None` before the first drop.
+    pub dropped: Option<u32>,
+}
+
+impl DatagramSocket for UdpSocket {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        UdpSocket::send(self, buf)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        UdpSocket::recv(self, buf)
+    }
+
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, target)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::peer_addr(self)
+    }
+
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        UdpSocket::read_timeout(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_read_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UdpSocket::set_nonblocking(self, nonblocking)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_ect0(&self) -> io::Result<()> {
+        let ipv6 = self.local_addr()?.is_ipv6();
+        ecn::set_tos(self.as_raw_fd(), ipv6, Ecn::Ect0.bits())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_tos(&self, tos: u8) -> io::Result<()> {
+        ecn::set_tos(self.as_raw_fd(), self.local_addr()?.is_ipv6(), tos)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn enable_ecn(&self) -> io::Result<()> {
+        ecn::enable_recv_tos(self.as_raw_fd(), self.local_addr()?.is_ipv6())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn bind_device(&self, interface: &Interface) -> io::Result<()> {
+        device::bind(self.as_raw_fd(), self.local_addr()?.is_ipv6(), interface)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_dont_fragment(&self, on: bool) -> io::Result<()> {
+        pmtu::set_dont_fragment(self.as_raw_fd(), self.local_addr()?.is_ipv6(), on)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn path_mtu(&self) -> io::Result<usize> {
+        pmtu::path_mtu(self.as_raw_fd(), self.local_addr()?.is_ipv6())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn recv_from_ecn(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<Ecn>)> {
+        let (len, from, tos) = ecn::recv_from_tos(self.as_raw_fd(), buf)?;
+        Ok((len, from, tos.map(Ecn::from_tos)))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn enable_drop_count(&self) -> io::Result<()> {
+        ecn::enable_drop_count(self.as_raw_fd())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn recv_from_meta(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, RecvMeta)> {
+        let (len, from, ancillary) = ecn::recv_from_ancillary(self.as_raw_fd(), buf)?;
+        Ok((len, from, RecvMeta::from(ancillary)))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<ecn::Ancillary> for RecvMeta {
+    fn from(ancillary: ecn::Ancillary) -> Self {
+        Self {
+            ecn: ancillary.tos.map(Ecn::from_tos),
+            dropped: ancillary.dropped,
+        }
+    }
+}
+
+/// Integer socket option `name` of `fd` at the `SOL_SOCKET` level
+#[cfg(target_os = "linux")]
+fn socket_option(fd: BorrowedFd<'_>, name: i32) -> io::Result<i32> {
+    const SOL_SOCKET: i32 = 1;
+    sys::get_int(fd.as_raw_fd(), SOL_SOCKET, name)
+}
+
+/// Whether `fd` is a UDP socket: a datagram socket of the IPv4 or IPv6
+/// family, not e.g. a Unix datagram socket
+#[cfg(target_os = "linux")]
+fn is_datagram_socket(fd: BorrowedFd<'_>) -> io::Result<bool> {
+    const SO_TYPE: i32 = 3;
+    const SO_DOMAIN: i32 = 39;
+    const SOCK_DGRAM: i32 = 2;
+    const AF_INET: i32 = 2;
+    const AF_INET6: i32 = 10;
+    Ok(socket_option(fd, SO_TYPE)? == SOCK_DGRAM
+        && matches!(socket_option(fd, SO_DOMAIN)?, AF_INET | AF_INET6))
+}
+
+/// Takes over a UDP socket set up elsewhere, e.g. inherited from a parent
+/// process, see [Sockets configured elsewhere](self#sockets-configured-elsewhere).
+/// Linux only.
+///
+/// # Errors
+/// [`io::ErrorKind::InvalidInput`] if `fd` is not an IPv4 or IPv6 datagram
+/// socket, the descriptor is closed then.
+#[cfg(target_os = "linux")]
+pub fn from_fd(fd: OwnedFd) -> io::Result<UdpSocket> {
+    if !is_datagram_socket(fd.as_fd())? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not an IPv4 or IPv6 datagram socket",
+        ));
+    }
+    Ok(UdpSocket::from(fd))
+}
+
+/// Set once the descriptors passed by systemd are taken
+#[cfg(target_os = "linux")]
+static SYSTEMD_SOCKETS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The UDP sockets systemd passed to this process (socket activation,
+/// `LISTEN_FDS`), in the order of the socket unit. Linux only.
+///
+/// Empty when the process was not socket-activated, and on every call but
+/// the first to succeed, the sockets having an owner then. Descriptors that
+/// are not IPv4 or IPv6 datagram sockets are left alone. All of them are
+/// closed on exec, as `sd_listen_fds` does.
+///
+/// # Errors
+/// If the type or the family of a descriptor cannot be read, or its
+/// close-on-exec flag set; no socket is taken then.
+#[cfg(target_os = "linux")]
+pub fn systemd_sockets() -> io::Result<Vec<UdpSocket>> {
+    /// First descriptor passed by systemd
+    const LISTEN_FDS_START: i32 = 3;
+
+    if SYSTEMD_SOCKETS_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(Vec::new());
+    }
+    let env = |name| std::env::var(name).ok().and_then(|v| v.parse::<u32>().ok());
+    // the variables may be inherited from a socket-activated parent
+    if env("LISTEN_PID") != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let count = env("LISTEN_FDS").unwrap_or(0).min(i32::MAX as u32) as i32;
+    // SAFETY: systemd hands the descriptors from 3 on over to this process,
+    // and the flag above lets them be taken only once
+    let sockets = unsafe { take_udp_fds(LISTEN_FDS_START, count) };
+    if sockets.is_err() {
+        // nothing was taken, a later call may try again
+        SYSTEMD_SOCKETS_TAKEN.store(false, Ordering::SeqCst);
+    }
+    sockets
+}
+
+/// The UDP sockets among the `count` descriptors from `start`, every one of
+/// them set to close on exec. All are checked before any is owned, so an
+/// error does not close the sockets before it.
+///
+/// # Safety
+/// The descriptors must be open and owned by no one else.
+#[cfg(target_os = "linux")]
+unsafe fn take_udp_fds(start: i32, count: i32) -> io::Result<Vec<UdpSocket>> {
+    let mut udp = Vec::new();
+    for fd in start..start.saturating_add(count) {
+        // SAFETY: open for the borrow, as the caller guarantees
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        sys::set_cloexec(fd.as_raw_fd(), true)?;
+        if is_datagram_socket(fd)? {
+            udp.push(fd.as_raw_fd());
+        }
+    }
+    Ok(udp
+        .into_iter()
+        // SAFETY: open and owned by no one else, as the caller guarantees,
+        // and each taken once
+        .map(|fd| UdpSocket::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+        .collect())
+}
+
+/// Datagram and the address it came from or goes to
+pub type Datagram = (Vec<u8>, SocketAddr);
+
+#[derive(Debug, Default)]
+struct MockState {
+    inbox: VecDeque<(Vec<u8>, SocketAddr, RecvMeta)>,
+    sent: Vec<Datagram>,
+    read_timeout: Option<Duration>,
+    nonblocking: bool,
+    ect0: bool,
+    tos: Option<u8>,
+    device: Option<Interface>,
+    dont_fragment: Option<bool>,
+    path_mtu: Option<usize>,
+    /// Errors of the next sends, one each
+    send_errors: VecDeque<io::Error>,
+}
+
+/// In-memory [`DatagramSocket`].
+///
+/// Receives return the datagrams queued with [`push`](Self::push) in order.
+/// Once the queue is empty they wait for the read timeout and return
+/// `WouldBlock`, they never block forever. Sent datagrams are kept for
+/// [`take_sent`](Self::take_sent), unless [`fail_sends`](Self::fail_sends)
+/// makes them fail.
+#[derive(Debug, Default)]
+pub struct MockSocket {
+    peer: Option<SocketAddr>,
+    state: Mutex<MockState>,
+}
+
+impl MockSocket {
+    /// An unconnected socket with nothing queued.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connects the socket to `peer`, the target of `send`.
+    pub fn connect(&mut self, peer: SocketAddr) {
+        self.peer = Some(peer);
+    }
+
+    /// Queues a datagram from `from` for the next receive.
+    pub fn push(&self, datagram: &[u8], from: SocketAddr) {
+        self.lock()
+            .inbox
+            .push_back((datagram.to_vec(), from, RecvMeta::default()));
+    }
+
+    /// Queues a datagram that arrived with the ECN codepoint `ecn`.
+    pub fn push_ecn(&self, datagram: &[u8], from: SocketAddr, ecn: Ecn) {
+        let meta = RecvMeta {
+            ecn: Some(ecn),
+            dropped: None,
+        };
+        self.lock().inbox.push_back((datagram.to_vec(), from, meta));
+    }
+
+    /// Queues a datagram that arrived once the socket had dropped `dropped`
+    /// datagrams, see [`RecvMeta::dropped`].
+    pub fn push_after_drops(&self, datagram: &[u8], from: SocketAddr, dropped: u32) {
+        let meta = RecvMeta {
+            ecn: None,
+            dropped: Some(dropped),
+        };
+        self.lock().inbox.push_back((datagram.to_vec(), from, meta));
+    }
+
+    /// Number of queued datagrams not received yet.
+    pub fn pending(&self) -> usize {
+        self.lock().inbox.len()
+    }
+
+    /// Makes the next sends fail with `errors`, one each, in order.
+    pub fn fail_sends(&self, errors: impl IntoIterator<Item = io::Error>) {
+        self.lock().send_errors.extend(errors);
+    }
+
+    /// Takes the datagrams sent so far.
+    pub fn take_sent(&self) -> Vec<Datagram> {
+        std::mem::take(&mut self.lock().sent)
+    }
+
+    /// Whether [`set_ect0`](DatagramSocket::set_ect0) was called.
+    pub fn ect0(&self) -> bool {
+        self.lock().ect0
+    }
+
+    /// The TOS byte given to [`set_tos`](DatagramSocket::set_tos), if any.
+    pub fn tos(&self) -> Option<u8> {
+        self.lock().tos
+    }
+
+    /// The interface given to [`bind_device`](DatagramSocket::bind_device),
+    /// if any.
+    pub fn device(&self) -> Option<Interface> {
+        self.lock().device.clone()
+    }
+
+    /// What [`set_dont_fragment`](DatagramSocket::set_dont_fragment) was
+    /// given last, if anything.
+    pub fn dont_fragment(&self) -> Option<bool> {
+        self.lock().dont_fragment
+    }
+
+    /// Makes [`path_mtu`](DatagramSocket::path_mtu) return `mtu`, it is
+    /// `Unsupported` until then.
+    pub fn set_path_mtu(&self, mtu: usize) {
+        self.lock().path_mtu = Some(mtu);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl DatagramSocket for MockSocket {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let peer = self.peer_addr()?;
+        self.send_to(buf, peer)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.recv_from(buf).map(|(len, _)| len)
+    }
+
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let mut state = self.lock();
+        if let Some(e) = state.send_errors.pop_front() {
+            return Err(e);
+        }
+        state.sent.push((buf.to_vec(), target));
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.recv_from_ecn(buf).map(|(len, from, _)| (len, from))
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.peer.ok_or_else(|| io::ErrorKind::NotConnected.into())
+    }
+
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.lock().read_timeout)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        self.lock().read_timeout = timeout;
+        Ok(())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.lock().nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn set_ect0(&self) -> io::Result<()> {
+        self.lock().ect0 = true;
+        Ok(())
+    }
+
+    fn set_tos(&self, tos: u8) -> io::Result<()> {
+        self.lock().tos = Some(tos);
+        Ok(())
+    }
+
+    fn enable_ecn(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn bind_device(&self, interface: &Interface) -> io::Result<()> {
+        self.lock().device = Some(interface.clone());
+        Ok(())
+    }
+
+    fn set_dont_fragment(&self, on: bool) -> io::Result<()> {
+        self.lock().dont_fragment = Some(on);
+        Ok(())
+    }
+
+    fn path_mtu(&self) -> io::Result<usize> {
+        self.lock()
+            .path_mtu
+            .ok_or_else(|| io::ErrorKind::Unsupported.into())
+    }
+
+    fn recv_from_ecn(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<Ecn>)> {
+        self.recv_from_meta(buf)
+            .map(|(len, from, meta)| (len, from, meta.ecn))
+    }
+
+    fn enable_drop_count(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn recv_from_meta(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, RecvMeta)> {
+        let mut state = self.lock();
+        if let Some((datagram, from, meta)) = state.inbox.pop_front() {
+            // like a real socket, the rest of a too long datagram is lost
+            let len = datagram.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram[..len]);
+            return Ok((len, from, meta));
+        }
+        let wait = if state.nonblocking {
+            None
+        } else {
+            state.read_timeout
+        };
+        drop(state);
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::udp_data::{FLAG_DATA, FLAG_FIN, HEADER_SIZE, UdpHeader};
+    use crate::{ClientBuilder, ClientCommand, ServerBuilder, ServerCommand};
+    use std::sync::mpsc;
+
+    fn packet(seq: u64, flags: u32) -> Vec<u8> {
+        let mut packet = vec![0u8; HEADER_SIZE + 100];
+        UdpHeader::new(seq, 0, 0, flags).write_header(&mut packet);
+        packet
+    }
+
+    #[test]
+    fn test_mock_socket() {
+        let peer: SocketAddr = "192.0.2.1:5201".parse().unwrap();
+        let mut sock = MockSocket::new();
+        assert_eq!(
+            sock.send(b"x").unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+        sock.connect(peer);
+        sock.send(b"hello").unwrap();
+        assert_eq!(sock.take_sent(), vec![(b"hello".to_vec(), peer)]);
+        assert!(sock.take_sent().is_empty());
+
+        sock.push(b"abcdef", peer);
+        let mut buf = [0u8; 4];
+        assert_eq!(sock.recv_from(&mut buf).unwrap(), (4, peer));
+        assert_eq!(&buf, b"abcd");
+        sock.set_read_timeout(Some(Duration::from_millis(1)))
+            .unwrap();
+        assert_eq!(
+            sock.recv(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn test_server_over_mock_socket() {
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let mut sock = MockSocket::new();
+        // seq 2 is lost, 4 arrives before 3
+        for seq in [0, 1, 4, 3] {
+            sock.push(&packet(seq, FLAG_DATA), peer);
+        }
+        sock.push(&packet(5, FLAG_FIN), peer);
+
+        let (tx, rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(1)).build(rx);
+        tx.send(ServerCommand::Start).unwrap();
+        let results = server.run(&mut sock).unwrap();
+
+        assert_eq!(sock.pending(), 0);
+        assert_eq!(results.len(), 1);
+        // the first packet only starts the measurement
+        assert_eq!(results[0].received, 4);
+        assert_eq!(results[0].lost, 1);
+        assert_eq!(results[0].out_of_order, 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_from_fd() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+        let taken = from_fd(OwnedFd::from(sock)).unwrap();
+        assert_eq!(taken.local_addr().unwrap(), addr);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let err = from_fd(OwnedFd::from(listener)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // a datagram socket, but not a UDP one
+        let (unix, _peer) = std::os::unix::net::UnixDatagram::pair().unwrap();
+        let err = from_fd(OwnedFd::from(unix)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // the tests are not socket-activated
+        assert!(systemd_sockets().unwrap().is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_take_udp_fds() {
+        use std::os::fd::IntoRawFd;
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+        let fd = sock.into_raw_fd();
+        // inherited without the flag, as systemd passes them
+        sys::set_cloexec(fd, false).unwrap();
+        // SAFETY: released by `sock` above, owned by no one else
+        let taken = unsafe { take_udp_fds(fd, 1) }.unwrap();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].local_addr().unwrap(), addr);
+        assert!(sys::cloexec(taken[0].as_raw_fd()).unwrap());
+    }
+
+    #[test]
+    fn test_ecn_over_mock_socket() {
+        let (tx, rx) = mpsc::channel();
+        let mut client = ClientBuilder::new(1e6, 100, Duration::from_secs(1))
+            .stop_after_packets(1)
+            .ecn()
+            .build(rx);
+        let mut sock = MockSocket::new();
+        sock.connect("192.0.2.1:5201".parse().unwrap());
+        tx.send(ClientCommand::Start).unwrap();
+        client.run(&mut sock).unwrap();
+        assert!(sock.ect0());
+
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let mut sock = MockSocket::new();
+        sock.push_ecn(&packet(0, FLAG_DATA), peer, Ecn::Ect0);
+        for (seq, ecn) in [(1, Ecn::Ce), (2, Ecn::Ect0), (3, Ecn::Ce)] {
+            sock.push_ecn(&packet(seq, FLAG_DATA), peer, ecn);
+        }
+        sock.push(&packet(4, FLAG_FIN), peer);
+
+        let (tx, rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(1)).ecn().build(rx);
+        tx.send(ServerCommand::Start).unwrap();
+        let results = server.run(&mut sock).unwrap();
+        assert_eq!(results[0].ce_marked, 2);
+        assert_eq!(Ecn::from_tos(0xb8 | Ecn::Ce.bits()), Ecn::Ce);
+    }
+
+    #[test]
+    fn test_dscp_over_mock_socket() {
+        let run = |builder: ClientBuilder| {
+            let (tx, rx) = mpsc::channel();
+            let mut client = builder.stop_after_packets(1).build(rx);
+            let mut sock = MockSocket::new();
+            sock.connect("192.0.2.1:5201".parse().unwrap());
+            tx.send(ClientCommand::Start).unwrap();
+            client.run(&mut sock).unwrap();
+            (sock.tos(), sock.ect0())
+        };
+        let builder = || ClientBuilder::new(1e6, 100, Duration::from_secs(1));
+
+        // EF in the six high bits
+        assert_eq!(run(builder().dscp(46)), (Some(0xb8), false));
+        // ECT(0) in the low bits of the same byte
+        assert_eq!(run(builder().dscp(46).ecn()), (Some(0xba), false));
+        assert_eq!(run(builder()), (None, false));
+    }
+
+    #[test]
+    fn test_bind_device_over_mock_socket() {
+        let (tx, rx) = mpsc::channel();
+        let mut client = ClientBuilder::new(1e6, 100, Duration::from_secs(1))
+            .stop_after_packets(1)
+            .bind_device("eth1")
+            .build(rx);
+        let mut sock = MockSocket::new();
+        sock.connect("192.0.2.1:5201".parse().unwrap());
+        tx.send(ClientCommand::Start).unwrap();
+        client.run(&mut sock).unwrap();
+        assert_eq!(sock.device(), Some(Interface::Name("eth1".to_string())));
+
+        let mut sock = MockSocket::new();
+        sock.push(&packet(0, FLAG_FIN), "192.0.2.1:40000".parse().unwrap());
+        let (tx, rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_secs(1))
+            .bind_device(3)
+            .build(rx);
+        tx.send(ServerCommand::Start).unwrap();
+        server.run(&mut sock).unwrap();
+        assert_eq!(sock.device(), Some(Interface::Index(3)));
+
+        assert_eq!("3".parse(), Ok(Interface::Index(3)));
+        assert_eq!("eth1".parse(), Ok(Interface::from("eth1")));
+        assert_eq!(Interface::from("eth1").to_string(), "eth1");
+        assert_eq!(Interface::Index(7).index().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_port_range() {
+        let range: PortRange = "40000-40002".parse().unwrap();
+        assert_eq!((range.first(), range.last()), (40000, 40002));
+        assert!(range.contains(40001) && !range.contains(40003));
+        assert_eq!(range.to_string(), "40000-40002");
+        assert_eq!(
+            "5201".parse::<PortRange>().unwrap(),
+            PortRange::new(5201, 5201)
+        );
+        assert_eq!(PortRange::new(5201, 5201).to_string(), "5201");
+        for bad in ["", "0-10", "10-9", "1-70000", "a-b"] {
+            assert!(bad.parse::<PortRange>().is_err(), "{bad}");
+        }
+
+        // a range above a port just taken, the sockets get its free ports in order
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let taken = UdpSocket::bind((ip, 0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let range = PortRange::new(port, port.saturating_add(2));
+        let port_of = |sock: &UdpSocket| sock.local_addr().unwrap().port();
+        let (a, b) = (range.bind(ip), range.bind(ip));
+        if let (Ok(a), Ok(b)) = (&a, &b) {
+            assert!(port_of(a) > port && port_of(b) > port_of(a));
+            let full = range.bind(ip).unwrap_err();
+            assert_eq!(full.kind(), io::ErrorKind::AddrInUse);
+        }
+    }
+}
@@ -0,0 +1,446 @@
+//! Step-load capacity tests.
+//!
+//! A [`StepLoad`] raises the bitrate in fixed steps during one continuous
+//! test, e.g. from 50 Mbps by 50 Mbps every 10 seconds, and gives every step
+//! a verdict against the loss and jitter limits. The last sustainable step is
+//! the highest one before the first failure. Where
+//! [`CapacitySearch`](crate::CapacitySearch) runs separate trials, a step
+//! load shows how the path behaves as the load builds up without pauses.
+//!
+//! The receiving end judges the steps, from its intervals:
+//!
+//! - [`StepLoad::run`] asks a `udpopt server` to send the steps back (reverse
+//!   mode) and measures them here,
+//! - [`StepLoad::spawn_steps`] raises the rate of any running client, and
+//!   [`StepLoad::evaluate`] judges the intervals of the server that received it.
+//!
+//! ```no_run
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//! use udpopt::step::StepLoad;
+//!
+//! let mut sock = UdpSocket::bind("0.0.0.0:0").unwrap();
+//! sock.connect("192.0.2.10:5201").unwrap();
+//!
+//! let report = StepLoad::new(50e6, 50e6, Duration::from_secs(10), 6)
+//!     .max_loss_percent(0.5)
+//!     .max_jitter_ms(2.0)
+//!     .run(&mut sock)
+//!     .unwrap();
+//! for step in &report.steps {
+//!     println!("{:.0} bps: {}", step.bitrate_bps, if step.passed { "pass" } else { "fail" });
+//! }
+//! println!("sustainable up to {:.0} bps", report.sustainable_bps);
+//! ```
+
+use std::{
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    builder::ServerBuilder,
+    errors::UdpOptError,
+    result::TestResult,
+    reverse::ReverseCredentials,
+    socket::DatagramSocket,
+    utils::{
+        net_utils::{ClientCommand, IntervalResult, Rate, ReverseRequest, ServerCommand},
+        ui::loss_percent,
+    },
+};
+
+/// How long after the planned end a run gives up on the server's packets
+const FINISH_GRACE: Duration = Duration::from_secs(10);
+
+/// A bitrate raised in steps during one test, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepLoad {
+    start_bps: f64,
+    step_bps: f64,
+    step_duration: Duration,
+    steps: usize,
+    payload_size: usize,
+    interval: Duration,
+    max_loss_percent: f64,
+    max_jitter_ms: Option<f64>,
+    credentials: Option<ReverseCredentials>,
+}
+
+impl StepLoad {
+    /// `steps` steps of `step_duration`, the first at `start_bps` and each
+    /// next one `step_bps` faster, with 1200 byte packets, no loss allowed
+    /// and no jitter limit.
+    ///
+    /// # Panics
+    /// If `start_bps` is not positive, `step_bps` is negative, `steps` is 0
+    /// or a step is shorter than a millisecond.
+    pub fn new(start_bps: f64, step_bps: f64, step_duration: Duration, steps: usize) -> Self {
+        assert!(start_bps > 0.0, "the first step needs a positive bitrate");
+        assert!(step_bps >= 0.0, "the steps cannot lower the bitrate");
+        assert!(steps > 0, "a step load needs at least one step");
+        assert!(
+            step_duration >= Duration::from_millis(1),
+            "a step lasts at least 1 ms"
+        );
+        Self {
+            start_bps,
+            step_bps,
+            step_duration,
+            steps,
+            payload_size: 1200,
+            interval: step_duration.min(Duration::from_secs(1)),
+            max_loss_percent: 0.0,
+            max_jitter_ms: None,
+            credentials: None,
+        }
+    }
+
+    /// The plan of a reverse-mode request, `None` for a constant rate or a
+    /// request that makes no sense.
+    pub fn from_request(request: &ReverseRequest) -> Option<Self> {
+        if request.step_every.is_zero()
+            || !request.bitrate_bps.is_finite()
+            || request.bitrate_bps <= 0.0
+        {
+            return None;
+        }
+        let steps = request
+            .duration
+            .as_millis()
+            .div_ceil(request.step_every.as_millis())
+            .max(1);
+        let steps = u32::try_from(steps).ok()?;
+        Some(
+            Self::new(
+                request.bitrate_bps,
+                request.step_bps.max(0.0),
+                request.step_every,
+                steps as usize,
+            )
+            .payload_size(request.payload_size),
+        )
+    }
+
+    /// Sets the packet length (bytes), header included.
+    pub fn payload_size(mut self, payload_size: usize) -> Self {
+        self.payload_size = payload_size;
+        self
+    }
+
+    /// Sets the length of the intervals [`run`](Self::run) measures, the
+    /// step or 1 second by default, whichever is shorter. Best a divisor of
+    /// the step: an interval counts in the step its middle falls in.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Highest loss (%) a step may have and still pass.
+    pub fn max_loss_percent(mut self, percent: f64) -> Self {
+        self.max_loss_percent = percent;
+        self
+    }
+
+    /// Highest interval jitter (ms) a step may have and still pass.
+    pub fn max_jitter_ms(mut self, ms: f64) -> Self {
+        self.max_jitter_ms = Some(ms);
+        self
+    }
+
+    /// Sends the request of [`run`](Self::run) with `credentials`, for a
+    /// server that only sends to known clients, see [`crate::reverse`].
+    pub fn credentials(mut self, credentials: ReverseCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Bitrate of step `step`, counted from 0 (bits/sec).
+    pub fn bitrate(&self, step: usize) -> f64 {
+        self.start_bps + step as f64 * self.step_bps
+    }
+
+    /// Number of steps.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Length of the whole test.
+    pub fn duration(&self) -> Duration {
+        self.step_duration * self.steps as u32
+    }
+
+    /// The request asking a `udpopt server` to send the steps.
+    pub fn request(&self) -> ReverseRequest {
+        ReverseRequest {
+            bitrate_bps: self.start_bps,
+            payload_size: self.payload_size,
+            duration: self.duration(),
+            dscp: 0,
+            step_bps: self.step_bps,
+            step_every: self.step_duration,
+        }
+    }
+
+    /// Raises the rate of a client started at [`bitrate(0)`](Self::bitrate)
+    /// for the whole [`duration`](Self::duration), sending `SetRate` on `tx`
+    /// at every step. Call it as the client is started; the thread ends
+    /// after the last step or once the client is gone.
+    pub fn spawn_steps(&self, tx: mpsc::Sender<ClientCommand>) -> thread::JoinHandle<()> {
+        let plan = self.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            for step in 1..plan.steps {
+                let at = start + plan.step_duration * step as u32;
+                thread::sleep(at.saturating_duration_since(Instant::now()));
+                event!(info, step, bitrate_bps = plan.bitrate(step), "next step");
+                if tx
+                    .send(ClientCommand::SetRate(Rate::Bps(plan.bitrate(step))))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        })
+    }
+
+    /// Judges every step from the `intervals` of the receiving end.
+    pub fn evaluate(&self, intervals: &[IntervalResult]) -> StepReport {
+        let mut steps: Vec<StepResult> = (0..self.steps)
+            .map(|step| StepResult {
+                step,
+                bitrate_bps: self.bitrate(step),
+                ..Default::default()
+            })
+            .collect();
+        let mut times = vec![Duration::ZERO; self.steps];
+        let mut bytes = vec![0usize; self.steps];
+        for interval in intervals {
+            let middle = interval.offset + interval.time / 2;
+            let step =
+                ((middle.as_nanos() / self.step_duration.as_nanos()) as usize).min(self.steps - 1);
+            let result = &mut steps[step];
+            result.received += interval.received;
+            result.lost += interval.lost;
+            result.jitter_ms = result.jitter_ms.max(interval.jitter_ms);
+            times[step] += interval.time;
+            bytes[step] += interval.bytes;
+        }
+        for (result, (time, bytes)) in steps.iter_mut().zip(times.into_iter().zip(bytes)) {
+            result.loss_percent = loss_percent(result.received, result.lost);
+            if !time.is_zero() {
+                result.received_bps = bytes as f64 * 8.0 / time.as_secs_f64();
+            }
+            result.passed = result.received > 0
+                && result.loss_percent <= self.max_loss_percent
+                && self.max_jitter_ms.is_none_or(|max| result.jitter_ms <= max);
+        }
+
+        let last_sustainable = steps.iter().take_while(|s| s.passed).count().checked_sub(1);
+        StepReport {
+            sustainable_bps: last_sustainable.map_or(0.0, |step| self.bitrate(step)),
+            last_sustainable,
+            result: TestResult::from_intervals(intervals),
+            steps,
+        }
+    }
+
+    /// Asks the `udpopt server` `sock` is connected to for the steps and
+    /// judges what arrives.
+    ///
+    /// # Errors
+    /// - [`UdpOptError::SendFailed`] if the request cannot be sent.
+    /// - [`UdpOptError::Timeout`] if nothing arrived by the planned end and
+    ///   a grace period of 10 seconds.
+    /// - any error of [`crate::UdpServer::run`].
+    pub fn run<S: DatagramSocket>(&self, sock: &mut S) -> Result<StepReport, UdpOptError> {
+        let request = match &self.credentials {
+            Some(credentials) => credentials.encode(self.request()),
+            None => self.request().to_bytes().to_vec(),
+        };
+        sock.send(&request).map_err(UdpOptError::send_failed(
+            sock.peer_addr().ok(),
+            request.len(),
+        ))?;
+
+        let deadline = self.duration() + FINISH_GRACE;
+        let give_up = CancellationToken::new();
+        let (tx, rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(self.interval)
+            .send_results_to_client()
+            .cancel_token(give_up.clone())
+            .build(rx);
+        let _ = tx.send(ServerCommand::Start);
+        let intervals = thread::scope(|s| {
+            // cancels the run still waiting at the deadline
+            let (done_tx, done_rx) = mpsc::channel::<()>();
+            s.spawn(move || {
+                if done_rx.recv_timeout(deadline) == Err(mpsc::RecvTimeoutError::Timeout) {
+                    give_up.cancel();
+                }
+            });
+            let intervals = server.run(sock).map_err(|e| e.error);
+            drop(done_tx);
+            intervals
+        })?;
+        if intervals.is_empty() {
+            return Err(UdpOptError::Timeout(deadline));
+        }
+        Ok(self.evaluate(&intervals))
+    }
+}
+
+/// Verdict of one step of a [`StepLoad`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StepResult {
+    /// Index of the step, from 0.
+    pub step: usize,
+    /// Bitrate sent (bits/sec).
+    pub bitrate_bps: f64,
+    /// Bitrate received (bits/sec).
+    pub received_bps: f64,
+    /// Packets received.
+    pub received: u64,
+    /// Packets lost.
+    pub lost: u64,
+    /// Percentage of the packets lost.
+    pub loss_percent: f64,
+    /// Highest interval jitter of the step (ms).
+    pub jitter_ms: f64,
+    /// Whether something arrived within the loss and jitter limits.
+    pub passed: bool,
+}
+
+/// Outcome of a [`StepLoad`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepReport {
+    /// Every step, in order.
+    pub steps: Vec<StepResult>,
+    /// Highest step passing with all the steps before it, `None` if the
+    /// first one failed.
+    pub last_sustainable: Option<usize>,
+    /// Bitrate of the last sustainable step (bits/sec), 0 if none.
+    pub sustainable_bps: f64,
+    /// The whole test.
+    pub result: TestResult,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientBuilder;
+    use std::net::UdpSocket;
+
+    fn interval(offset_ms: u64, received: u64, lost: u64, jitter_ms: f64) -> IntervalResult {
+        IntervalResult {
+            offset: Duration::from_millis(offset_ms),
+            time: Duration::from_millis(500),
+            received,
+            lost,
+            bytes: received as usize * 1000,
+            jitter_ms,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_evaluate_steps() {
+        let plan = StepLoad::new(1e6, 1e6, Duration::from_secs(1), 4)
+            .max_loss_percent(1.0)
+            .max_jitter_ms(5.0);
+        assert_eq!(plan.duration(), Duration::from_secs(4));
+        assert_eq!(plan.bitrate(3), 4e6);
+
+        let intervals = [
+            interval(0, 100, 0, 0.5),
+            interval(500, 100, 0, 0.5),
+            interval(1000, 200, 1, 0.5),
+            // too jittery
+            interval(1500, 200, 0, 9.0),
+            // passes again, the first failure already ended the climb
+            interval(2000, 300, 0, 0.5),
+            // the last step gets the intervals running past the end
+            interval(3000, 400, 40, 0.5),
+            interval(4000, 10, 0, 0.5),
+        ];
+        let report = plan.evaluate(&intervals);
+        let passed: Vec<bool> = report.steps.iter().map(|s| s.passed).collect();
+        assert_eq!(passed, [true, false, true, false]);
+        assert_eq!(report.last_sustainable, Some(0));
+        assert_eq!(report.sustainable_bps, 1e6);
+
+        let first = report.steps[0];
+        assert_eq!((first.received, first.lost), (200, 0));
+        assert_eq!(first.received_bps, 1.6e6);
+        assert_eq!(report.steps[1].jitter_ms, 9.0);
+        assert_eq!(report.steps[3].received, 410);
+        assert_eq!(report.result.total_packets, 1310);
+
+        // a step nothing reached fails
+        let report = plan.evaluate(&intervals[..2]);
+        assert!(!report.steps[1].passed);
+        assert_eq!(plan.evaluate(&[]).last_sustainable, None);
+    }
+
+    #[test]
+    fn test_request_roundtrip() {
+        let plan = StepLoad::new(2e6, 5e5, Duration::from_millis(1500), 3).payload_size(800);
+        let request = plan.request();
+        assert_eq!(request.duration, Duration::from_millis(4500));
+        let back = StepLoad::from_request(&ReverseRequest::parse(&request.to_bytes()).unwrap());
+        assert_eq!(back.as_ref(), Some(&plan));
+
+        let constant = ReverseRequest {
+            step_every: Duration::ZERO,
+            ..request
+        };
+        assert_eq!(StepLoad::from_request(&constant), None);
+    }
+
+    #[test]
+    fn test_run_against_a_stepping_sender() {
+        let peer_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.connect(peer_sock.local_addr().unwrap()).unwrap();
+
+        // what `udpopt server` does with a step-load request
+        let sender = thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (len, from) = peer_sock.recv_from(&mut buf).unwrap();
+            let request = ReverseRequest::parse(&buf[..len]).unwrap();
+            let plan = StepLoad::from_request(&request).unwrap();
+            let mut peer_sock = peer_sock;
+            peer_sock.connect(from).unwrap();
+            let (tx, rx) = mpsc::channel();
+            let mut client =
+                ClientBuilder::new(request.bitrate_bps, request.payload_size, request.duration)
+                    .remote_results(Duration::from_secs(2))
+                    .build(rx);
+            tx.send(ClientCommand::Start).unwrap();
+            let steps = plan.spawn_steps(tx);
+            let report = client.run(&mut peer_sock).unwrap();
+            drop(client);
+            steps.join().unwrap();
+            report
+        });
+
+        let report = StepLoad::new(200e3, 200e3, Duration::from_millis(400), 3)
+            .payload_size(500)
+            .interval(Duration::from_millis(200))
+            .max_loss_percent(5.0)
+            .run(&mut sock)
+            .unwrap();
+        let sent = sender.join().unwrap();
+
+        assert_eq!(report.steps.len(), 3);
+        assert!(sent.remote.is_some());
+        // loopback keeps up: every step passes at its own rate
+        assert_eq!(report.last_sustainable, Some(2), "{report:?}");
+        assert_eq!(report.sustainable_bps, 600e3);
+        let rates: Vec<f64> = report.steps.iter().map(|s| s.received_bps).collect();
+        assert!(rates[0] < rates[1] && rates[1] < rates[2], "{rates:?}");
+    }
+}
@@ -0,0 +1,331 @@
+//! Packet-size sweeps.
+//!
+//! A [`SizeSweep`] runs one short test per packet length, e.g. 64, 128, ...,
+//! 1472 and 2000 bytes, all at the same bitrate, and puts the loss and the
+//! goodput of every length side by side in a [`SweepReport`]. Paths that
+//! mishandle large datagrams show at a glance:
+//!
+//! - an MTU black hole delivers nothing from some length on, while the
+//!   shorter packets pass,
+//! - fragmentation shows as loss that grows with the length past the MTU,
+//!   every lost fragment losing its whole datagram,
+//! - per-packet limits (small packets at a high packet rate) show as loss at
+//!   the short end.
+//!
+//! Like [`CapacitySearch`](crate::CapacitySearch), the server must answer
+//! every trial: build it with [`crate::ServerBuilder::send_results_to_client`]
+//! and call `run` again after each test, like the `udpopt server` command
+//! does. A length that never reaches the server gets no answer, the sweep
+//! records it as lost rather than failing.
+//!
+//! ```no_run
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//! use udpopt::sweep::SizeSweep;
+//!
+//! let mut sock = UdpSocket::bind("0.0.0.0:0").unwrap();
+//! sock.connect("192.0.2.10:5201").unwrap();
+//!
+//! let sizes = [64, 128, 256, 512, 1024, 1472, 2000];
+//! let report = SizeSweep::new(sizes, 10e6)
+//!     .trial_duration(Duration::from_secs(2))
+//!     .run(&mut sock)
+//!     .unwrap();
+//! for size in &report.sizes {
+//!     println!("{} bytes: {:.2} % lost", size.payload_size, size.loss_percent);
+//! }
+//! ```
+
+use std::{sync::mpsc, thread, time::Duration};
+
+use crate::{
+    builder::ClientBuilder,
+    errors::UdpOptError,
+    result::TestResult,
+    socket::DatagramSocket,
+    utils::{net_utils::ClientCommand, udp_data::MAX_DATAGRAM_LEN},
+};
+
+/// Pause between two trials, so the server is back in `run` for the next one
+const DEFAULT_GAP: Duration = Duration::from_millis(500);
+
+/// How long every trial waits for the server's results after its FIN
+const DEFAULT_RESULTS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Tests a list of packet lengths one after the other at a fixed bitrate.
+#[derive(Debug, Clone)]
+pub struct SizeSweep {
+    sizes: Vec<usize>,
+    bitrate_bps: f64,
+    trial: Duration,
+    gap: Duration,
+    results_timeout: Duration,
+}
+
+impl SizeSweep {
+    /// Longest packet length a sweep tests, the longest UDP payload over IPv4.
+    pub const MAX_SIZE: usize = MAX_DATAGRAM_LEN;
+
+    /// Tests the packet lengths `sizes` (bytes, header included), in order,
+    /// at `bitrate_bps` for 2 seconds each.
+    ///
+    /// # Panics
+    /// If `sizes` is empty, has a length over [`MAX_SIZE`](Self::MAX_SIZE)
+    /// or the bitrate is not positive.
+    pub fn new(sizes: impl IntoIterator<Item = usize>, bitrate_bps: f64) -> Self {
+        let sizes: Vec<_> = sizes.into_iter().collect();
+        assert!(
+            !sizes.is_empty(),
+            "a sweep needs at least one packet length"
+        );
+        assert!(
+            sizes.iter().all(|&size| size <= Self::MAX_SIZE),
+            "a packet length is over {} bytes",
+            Self::MAX_SIZE
+        );
+        assert!(bitrate_bps > 0.0, "the sweep bitrate must be positive");
+        Self {
+            sizes,
+            bitrate_bps,
+            trial: Duration::from_secs(2),
+            gap: DEFAULT_GAP,
+            results_timeout: DEFAULT_RESULTS_TIMEOUT,
+        }
+    }
+
+    /// Sets how long every length is sent.
+    pub fn trial_duration(mut self, trial: Duration) -> Self {
+        self.trial = trial;
+        self
+    }
+
+    /// Sets the pause between two trials (default 500 ms).
+    pub fn gap(mut self, gap: Duration) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    /// Sets how long every trial waits for the server's results (default 2 seconds).
+    pub fn results_timeout(mut self, timeout: Duration) -> Self {
+        self.results_timeout = timeout;
+        self
+    }
+
+    /// Runs the sweep on the connected `sock`.
+    ///
+    /// # Errors
+    /// Any error of [`crate::UdpClient::run`], e.g. a length the socket
+    /// refuses to send.
+    pub fn run<S: DatagramSocket>(&self, sock: &mut S) -> Result<SweepReport, UdpOptError> {
+        self.sweep(|trial, payload_size| {
+            if trial > 0 {
+                thread::sleep(self.gap);
+            }
+            let (tx, rx) = mpsc::channel();
+            let mut client = ClientBuilder::new(self.bitrate_bps, payload_size, self.trial)
+                .remote_results(self.results_timeout)
+                .build(rx);
+            let _ = tx.send(ClientCommand::Start);
+            let report = client.run(sock)?;
+            Ok((report.packets_sent, report.remote))
+        })
+    }
+
+    /// The sweep, `run_trial` sending one length and returning the packets
+    /// sent and the server's results
+    fn sweep(
+        &self,
+        mut run_trial: impl FnMut(usize, usize) -> Result<(u64, Option<TestResult>), UdpOptError>,
+    ) -> Result<SweepReport, UdpOptError> {
+        let mut sizes = Vec::with_capacity(self.sizes.len());
+        for (trial, &payload_size) in self.sizes.iter().enumerate() {
+            event!(info, trial, payload_size, "starting trial");
+            let (packets_sent, result) = run_trial(trial, payload_size)?;
+            let received = result.as_ref().map_or(0, |r| r.total_packets);
+            let loss_percent = if packets_sent == 0 {
+                0.0
+            } else {
+                packets_sent.saturating_sub(received) as f64 / packets_sent as f64 * 100.0
+            };
+            sizes.push(SizeResult {
+                payload_size,
+                packets_sent,
+                packets_received: received,
+                loss_percent,
+                goodput_bps: result.as_ref().map_or(0.0, |r| r.mean_bitrate),
+                result,
+            });
+        }
+        Ok(SweepReport {
+            bitrate_bps: self.bitrate_bps,
+            sizes,
+        })
+    }
+}
+
+/// One packet length of a [`SizeSweep`].
+#[derive(Debug, Clone)]
+pub struct SizeResult {
+    /// Packet length (bytes, header included).
+    pub payload_size: usize,
+    /// Packets the client sent.
+    pub packets_sent: u64,
+    /// Packets the server received, 0 without results.
+    pub packets_received: u64,
+    /// Share of the packets sent that did not arrive (%), 100 when nothing did.
+    pub loss_percent: f64,
+    /// What the server received (bits/sec).
+    pub goodput_bps: f64,
+    /// The server's results, `None` when it sent none: nothing of this
+    /// length arrived, or the results were lost.
+    pub result: Option<TestResult>,
+}
+
+impl SizeResult {
+    /// Whether any packet of this length arrived.
+    pub fn delivered(&self) -> bool {
+        self.packets_received > 0
+    }
+}
+
+/// Outcome of a [`SizeSweep`], the loss and goodput of every length.
+#[derive(Debug, Clone)]
+pub struct SweepReport {
+    /// Bitrate every length was sent at (bits/sec).
+    pub bitrate_bps: f64,
+    /// Every length, in the order they ran.
+    pub sizes: Vec<SizeResult>,
+}
+
+impl SweepReport {
+    /// Longest length of which some packets arrived, `None` if none did.
+    pub fn largest_delivered(&self) -> Option<usize> {
+        self.sizes
+            .iter()
+            .filter(|size| size.delivered())
+            .map(|size| size.payload_size)
+            .max()
+    }
+
+    /// Lengths of which nothing arrived although a shorter one did: past a
+    /// black hole, or above the path's largest datagram.
+    pub fn black_holes(&self) -> Vec<usize> {
+        let Some(smallest) = self
+            .sizes
+            .iter()
+            .filter(|size| size.delivered())
+            .map(|size| size.payload_size)
+            .min()
+        else {
+            return Vec::new();
+        };
+        self.sizes
+            .iter()
+            .filter(|size| !size.delivered() && size.payload_size > smallest)
+            .map(|size| size.payload_size)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ServerBuilder, ServerCommand};
+    use std::net::UdpSocket;
+
+    /// A path delivering packets up to `mtu` bytes and losing `loss` of
+    /// every fragment of the longer ones, or all of them when `loss` is 1
+    fn path(
+        mtu: usize,
+        loss: f64,
+    ) -> impl FnMut(usize, usize) -> Result<(u64, Option<TestResult>), UdpOptError> {
+        move |_, payload_size| {
+            let sent = 1000;
+            let fragments = payload_size.div_ceil(mtu) as i32;
+            let received = (sent as f64 * (1.0 - loss).powi(fragments - 1)) as u64;
+            if received == 0 {
+                return Ok((sent, None));
+            }
+            let mut result = TestResult::from_intervals(&[]);
+            result.total_packets = received;
+            result.mean_bitrate = (received * payload_size as u64 * 8) as f64 / 2.0;
+            Ok((sent, Some(result)))
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "a packet length is over 65507 bytes")]
+    fn test_sizes_beyond_udp() {
+        SizeSweep::new([1200, 70000], 1e6);
+    }
+
+    #[test]
+    fn test_sweep_finds_a_black_hole() {
+        let sweep = SizeSweep::new([64, 512, 1400, 1472, 2000], 1e6);
+        let report = sweep.sweep(path(1400, 1.0)).unwrap();
+        let loss: Vec<_> = report.sizes.iter().map(|s| s.loss_percent).collect();
+        assert_eq!(loss, [0.0, 0.0, 0.0, 100.0, 100.0]);
+        assert_eq!(report.largest_delivered(), Some(1400));
+        assert_eq!(report.black_holes(), [1472, 2000]);
+        assert!(report.sizes[3].result.is_none());
+        assert_eq!(report.sizes[3].goodput_bps, 0.0);
+        assert_eq!(report.sizes[1].goodput_bps, 1000.0 * 512.0 * 8.0 / 2.0);
+    }
+
+    #[test]
+    fn test_sweep_sees_fragment_loss_grow() {
+        // 10 % of the fragments after the first lost
+        let report = SizeSweep::new([1000, 2000, 3000], 1e6)
+            .sweep(path(1000, 0.1))
+            .unwrap();
+        let loss: Vec<_> = report
+            .sizes
+            .iter()
+            .map(|s| s.loss_percent.round())
+            .collect();
+        assert_eq!(loss, [0.0, 10.0, 19.0]);
+        assert!(report.black_holes().is_empty());
+        assert!(report.sizes.iter().all(SizeResult::delivered));
+
+        let nothing = SizeSweep::new([64], 1e6).sweep(path(32, 1.0));
+        assert!(nothing.is_ok_and(|r| r.largest_delivered().is_none()));
+    }
+
+    #[test]
+    fn test_run_against_one_server() {
+        let mut server_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_sock
+            .connect(server_sock.local_addr().unwrap())
+            .unwrap();
+
+        let sizes = [100, 1400];
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut server = ServerBuilder::new(Duration::from_millis(100))
+            .send_results_to_client()
+            .build(server_rx);
+        let server_thread = thread::spawn(move || {
+            for _ in sizes {
+                server_tx.send(ServerCommand::Start).unwrap();
+                server.run(&mut server_sock).unwrap();
+            }
+        });
+
+        let report = SizeSweep::new(sizes, 500e3)
+            .trial_duration(Duration::from_millis(300))
+            .gap(Duration::from_millis(100))
+            .run(&mut client_sock)
+            .unwrap();
+        server_thread.join().unwrap();
+
+        assert_eq!(report.sizes.len(), 2);
+        for (size, expected) in report.sizes.iter().zip(sizes) {
+            assert_eq!(size.payload_size, expected);
+            assert!(size.delivered() && size.goodput_bps > 0.0, "{size:?}");
+            assert!(size.loss_percent < 5.0, "{size:?}");
+        }
+        // the same bitrate takes fewer long packets
+        assert!(report.sizes[0].packets_sent > report.sizes[1].packets_sent);
+        assert_eq!(report.largest_delivered(), Some(1400));
+    }
+}
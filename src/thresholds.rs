@@ -0,0 +1,244 @@
+//! Pass/fail thresholds for CI network gates.
+//!
+//! A pipeline states the link quality a deploy needs in [`Thresholds`] and
+//! gates on the [`Verdict`] of [`TestResult::evaluate`], which lists every
+//! criterion the test missed:
+//!
+//! ```
+//! use std::time::Duration;
+//! use udpopt::{IntervalResult, TestResult, Thresholds};
+//!
+//! let result = TestResult::from_intervals(&[IntervalResult {
+//!     time: Duration::from_secs(1),
+//!     received: 980,
+//!     lost: 20,
+//!     bytes: 1_225_000,
+//!     jitter_ms: 0.4,
+//!     ..Default::default()
+//! }
+//! .with_bitrate()]);
+//! let verdict = result.evaluate(&Thresholds {
+//!     max_loss_percent: Some(1.0),
+//!     min_throughput_bps: Some(5e6),
+//!     ..Default::default()
+//! });
+//! assert!(!verdict.passed());
+//! for failure in &verdict.failures {
+//!     eprintln!("link check failed: {failure}");
+//! }
+//! ```
+
+use std::fmt;
+
+use crate::{result::TestResult, utils::ui::format_bitrate};
+
+/// Limits a test must stay within, `None` for the ones not checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Thresholds {
+    /// Highest acceptable packet loss (%).
+    pub max_loss_percent: Option<f64>,
+    /// Lowest acceptable mean throughput (bits/sec).
+    pub min_throughput_bps: Option<f64>,
+    /// Highest acceptable 99th percentile of the interval jitter (ms).
+    pub max_p99_jitter_ms: Option<f64>,
+}
+
+/// A criterion of [`Thresholds`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Criterion {
+    /// [`Thresholds::max_loss_percent`]
+    Loss,
+    /// [`Thresholds::min_throughput_bps`]
+    Throughput,
+    /// [`Thresholds::max_p99_jitter_ms`]
+    Jitter,
+}
+
+/// A missed criterion, with the limit and what was measured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Failure {
+    /// The criterion missed.
+    pub criterion: Criterion,
+    /// The limit of [`Thresholds`].
+    pub limit: f64,
+    /// The measured value, in the unit of the limit.
+    pub measured: f64,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.criterion {
+            Criterion::Loss => write!(
+                f,
+                "loss {:.2} % above the {:.2} % limit",
+                self.measured, self.limit
+            ),
+            Criterion::Throughput => write!(
+                f,
+                "throughput {} below the {} limit",
+                format_bitrate(self.measured),
+                format_bitrate(self.limit)
+            ),
+            Criterion::Jitter => write!(
+                f,
+                "p99 jitter {:.3} ms above the {:.3} ms limit",
+                self.measured, self.limit
+            ),
+        }
+    }
+}
+
+/// Outcome of [`TestResult::evaluate`].
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Verdict {
+    /// The missed criteria, empty when the test passed.
+    pub failures: Vec<Failure>,
+}
+
+impl Verdict {
+    /// Whether every criterion was met.
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Whether `criterion` was missed.
+    pub fn failed(&self, criterion: Criterion) -> bool {
+        self.failures.iter().any(|f| f.criterion == criterion)
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.passed() {
+            return f.write_str("PASS");
+        }
+        f.write_str("FAIL")?;
+        for (i, failure) in self.failures.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { ", " };
+            write!(f, "{sep}{failure}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Thresholds {
+    /// Checks `result` against every limit set.
+    pub(crate) fn evaluate(&self, result: &TestResult) -> Verdict {
+        let checks = [
+            (Criterion::Loss, self.max_loss_percent, result.loss_percent),
+            (
+                Criterion::Throughput,
+                self.min_throughput_bps,
+                result.mean_bitrate,
+            ),
+            (
+                Criterion::Jitter,
+                self.max_p99_jitter_ms,
+                p99_jitter(result),
+            ),
+        ];
+        let failures = checks
+            .into_iter()
+            .filter_map(|(criterion, limit, measured)| {
+                let limit = limit?;
+                let missed = match criterion {
+                    Criterion::Throughput => measured < limit,
+                    Criterion::Loss | Criterion::Jitter => measured > limit,
+                };
+                missed.then_some(Failure {
+                    criterion,
+                    limit,
+                    measured,
+                })
+            })
+            .collect();
+        Verdict { failures }
+    }
+}
+
+/// The 99th percentile of the interval jitter, or the highest interval jitter
+/// when the result was computed without it
+fn p99_jitter(result: &TestResult) -> f64 {
+    result
+        .percentiles
+        .iter()
+        .find(|p| p.percentile == 99.0)
+        .map_or(result.max_jitter, |p| p.jitter_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntervalResult;
+    use std::time::Duration;
+
+    fn result(lost: u64, jitter_ms: f64) -> TestResult {
+        let intervals: Vec<IntervalResult> = (0..100)
+            .map(|i| {
+                IntervalResult {
+                    time: Duration::from_secs(1),
+                    received: 1000 - lost,
+                    lost,
+                    bytes: 1_250_000,
+                    // one bad interval in a hundred
+                    jitter_ms: if i == 0 { jitter_ms * 10.0 } else { jitter_ms },
+                    ..Default::default()
+                }
+                .with_bitrate()
+            })
+            .collect();
+        TestResult::from_intervals(&intervals)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let thresholds = Thresholds {
+            max_loss_percent: Some(0.5),
+            min_throughput_bps: Some(9e6),
+            max_p99_jitter_ms: Some(1.0),
+        };
+        let good = result(1, 0.1).evaluate(&thresholds);
+        assert!(good.passed());
+        assert_eq!(good.to_string(), "PASS");
+
+        let bad = result(10, 1.0).evaluate(&thresholds);
+        assert!(!bad.passed());
+        assert!(bad.failed(Criterion::Loss) && bad.failed(Criterion::Jitter));
+        assert!(!bad.failed(Criterion::Throughput));
+        assert_eq!(
+            bad.to_string(),
+            "FAIL: loss 1.00 % above the 0.50 % limit, p99 jitter 1.090 ms above the 1.000 ms limit"
+        );
+
+        // nothing to check
+        assert!(result(500, 50.0).evaluate(&Thresholds::default()).passed());
+        let slow = result(0, 0.1).evaluate(&Thresholds {
+            min_throughput_bps: Some(20e6),
+            ..Default::default()
+        });
+        assert_eq!(
+            slow.failures,
+            [Failure {
+                criterion: Criterion::Throughput,
+                limit: 20e6,
+                measured: 10e6,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_p99_jitter_without_percentile() {
+        let intervals = [IntervalResult {
+            time: Duration::from_secs(1),
+            received: 10,
+            jitter_ms: 3.0,
+            ..Default::default()
+        }];
+        let result = TestResult::from_intervals_with_percentiles(&intervals, &[50.0]);
+        assert_eq!(p99_jitter(&result), 3.0);
+    }
+}
@@ -0,0 +1,205 @@
+//! Per-packet trace export.
+//!
+//! This module defines the [`TraceWriter`] trait used by the servers to log every
+//! received packet for offline analysis (micro-bursts, reordering, ...), plus two
+//! ready-made writers: [`CsvTraceWriter`] and [`BinaryTraceWriter`].
+
+use std::io::{self, Write};
+
+/// A single received packet as seen by the server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacketRecord {
+    /// Sequence number from the packet header.
+    pub seq: u64,
+    /// Header flags (`0` = data, `1` = FIN).
+    pub flags: u32,
+    /// Send time (seconds since UNIX_EPOCH, sender clock).
+    pub send_sec: u64,
+    /// Send time microseconds part.
+    pub send_usec: u32,
+    /// Arrival time (seconds since UNIX_EPOCH, receiver clock).
+    pub recv_sec: u64,
+    /// Arrival time microseconds part.
+    pub recv_usec: u32,
+    /// Datagram size in bytes, including the header.
+    pub size: usize,
+    /// Arrival minus send time in milliseconds; only meaningful with synchronized clocks.
+    pub transit_ms: f64,
+}
+
+impl PacketRecord {
+    /// Size of one record in the [`BinaryTraceWriter`] format.
+    pub const BINARY_SIZE: usize = 8 + 4 + 8 + 4 + 8 + 4 + 4; // 40 bytes
+
+    /// Encodes the record in the fixed-size big-endian binary layout
+    /// `seq | flags | send_sec | send_usec | recv_sec | recv_usec | size`.
+    pub fn to_bytes(&self) -> [u8; Self::BINARY_SIZE] {
+        let mut b = [0u8; Self::BINARY_SIZE];
+        b[0..8].copy_from_slice(&self.seq.to_be_bytes());
+        b[8..12].copy_from_slice(&self.flags.to_be_bytes());
+        b[12..20].copy_from_slice(&self.send_sec.to_be_bytes());
+        b[20..24].copy_from_slice(&self.send_usec.to_be_bytes());
+        b[24..32].copy_from_slice(&self.recv_sec.to_be_bytes());
+        b[32..36].copy_from_slice(&self.recv_usec.to_be_bytes());
+        b[36..40].copy_from_slice(&(self.size as u32).to_be_bytes());
+        b
+    }
+
+    /// Decodes a record written by [`PacketRecord::to_bytes`].
+    ///
+    /// The transit time is recomputed from the timestamps.
+    pub fn from_bytes(b: &[u8; Self::BINARY_SIZE]) -> Self {
+        let seq = u64::from_be_bytes(b[0..8].try_into().unwrap());
+        let flags = u32::from_be_bytes(b[8..12].try_into().unwrap());
+        let send_sec = u64::from_be_bytes(b[12..20].try_into().unwrap());
+        let send_usec = u32::from_be_bytes(b[20..24].try_into().unwrap());
+        let recv_sec = u64::from_be_bytes(b[24..32].try_into().unwrap());
+        let recv_usec = u32::from_be_bytes(b[32..36].try_into().unwrap());
+        let size = u32::from_be_bytes(b[36..40].try_into().unwrap()) as usize;
+        Self {
+            seq,
+            flags,
+            send_sec,
+            send_usec,
+            recv_sec,
+            recv_usec,
+            size,
+            transit_ms: transit_ms(send_sec, send_usec, recv_sec, recv_usec),
+        }
+    }
+}
+
+/// Destination for per-packet trace records.
+///
+/// Implement this to send the packet log anywhere (file, memory, socket).
+pub trait TraceWriter: Send {
+    /// Writes one packet record.
+    fn write_record(&mut self, record: &PacketRecord) -> io::Result<()>;
+
+    /// Flushes any buffered records; called once when the test ends.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes records as CSV with a header line.
+#[derive(Debug)]
+pub struct CsvTraceWriter<W: Write + Send> {
+    out: W,
+    header_written: bool,
+}
+
+impl<W: Write + Send> CsvTraceWriter<W> {
+    /// Creates a CSV writer; wrap files in a `BufWriter` for high packet rates.
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            header_written: false,
+        }
+    }
+
+    /// Consumes the writer, returning the underlying output.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Send> TraceWriter for CsvTraceWriter<W> {
+    fn write_record(&mut self, r: &PacketRecord) -> io::Result<()> {
+        if !self.header_written {
+            writeln!(
+                self.out,
+                "seq,flags,send_sec,send_usec,recv_sec,recv_usec,size,transit_ms"
+            )?;
+            self.header_written = true;
+        }
+        writeln!(
+            self.out,
+            "{},{},{},{},{},{},{},{:.3}",
+            r.seq, r.flags, r.send_sec, r.send_usec, r.recv_sec, r.recv_usec, r.size, r.transit_ms
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Writes records in the compact binary layout of [`PacketRecord::to_bytes`].
+#[derive(Debug)]
+pub struct BinaryTraceWriter<W: Write + Send> {
+    out: W,
+}
+
+impl<W: Write + Send> BinaryTraceWriter<W> {
+    /// Creates a binary writer; wrap files in a `BufWriter` for high packet rates.
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    /// Consumes the writer, returning the underlying output.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Send> TraceWriter for BinaryTraceWriter<W> {
+    fn write_record(&mut self, record: &PacketRecord) -> io::Result<()> {
+        self.out.write_all(&record.to_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// helper functions
+
+pub(crate) fn transit_ms(send_sec: u64, send_usec: u32, recv_sec: u64, recv_usec: u32) -> f64 {
+    let send_ms = send_sec as f64 * 1000.0 + send_usec as f64 / 1000.0;
+    let recv_ms = recv_sec as f64 * 1000.0 + recv_usec as f64 / 1000.0;
+    recv_ms - send_ms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(seq: u64) -> PacketRecord {
+        PacketRecord {
+            seq,
+            flags: 0,
+            send_sec: 100,
+            send_usec: 250_000,
+            recv_sec: 100,
+            recv_usec: 251_500,
+            size: 1200,
+            transit_ms: transit_ms(100, 250_000, 100, 251_500),
+        }
+    }
+
+    #[test]
+    fn test_csv_writer() {
+        let mut w = CsvTraceWriter::new(Vec::new());
+        w.write_record(&sample(1)).unwrap();
+        w.write_record(&sample(2)).unwrap();
+        w.flush().unwrap();
+
+        let text = String::from_utf8(w.into_inner()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("seq,"));
+        assert_eq!(lines[1], "1,0,100,250000,100,251500,1200,1.500");
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let mut w = BinaryTraceWriter::new(Vec::new());
+        w.write_record(&sample(7)).unwrap();
+        let bytes = w.into_inner();
+        assert_eq!(bytes.len(), PacketRecord::BINARY_SIZE);
+
+        let decoded = PacketRecord::from_bytes(bytes[..].try_into().unwrap());
+        assert_eq!(decoded, sample(7));
+    }
+}
@@ -0,0 +1,107 @@
+//! # Interface binding
+//!
+//! Binds a socket to a network interface with `SO_BINDTOIFINDEX`, the
+//! index flavour of `SO_BINDTODEVICE`: the socket only receives the
+//! datagrams that arrived on the interface and sends through it, whatever
+//! the routing table prefers. The multicast interface (`IP_MULTICAST_IF` /
+//! `IPV6_MULTICAST_IF`) follows, so multicast tests leave through it too.
+//! Linux only, on other systems the socket traits' `bind_device` returns
+//! [`io::ErrorKind::Unsupported`].
+
+use std::{ffi::CString, io};
+
+use super::sys::{self, get_int, set_option};
+use crate::socket::Interface;
+
+const SOL_SOCKET: i32 = 1;
+const SO_BINDTOIFINDEX: i32 = 62;
+const IPPROTO_IP: i32 = 0;
+const IP_MULTICAST_IF: i32 = 32;
+const IPPROTO_IPV6: i32 = 41;
+const IPV6_MULTICAST_IF: i32 = 17;
+
+/// `struct ip_mreqn`, which `IP_MULTICAST_IF` takes to pick the interface
+/// by index
+#[repr(C)]
+struct IpMreqn {
+    imr_multiaddr: u32,
+    imr_address: u32,
+    imr_ifindex: i32,
+}
+
+/// Index of the interface called `name`.
+///
+/// # Errors
+/// The OS error, `ENODEV` when there is no such interface, or
+/// [`io::ErrorKind::InvalidInput`] for a name with a NUL byte.
+pub(crate) fn index_of(name: &str) -> io::Result<u32> {
+    let name = CString::new(name).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    sys::interface_index(&name)
+}
+
+/// Index of the interface `fd` is bound to, 0 for none
+fn bound_index(fd: i32) -> io::Result<u32> {
+    Ok(get_int(fd, SOL_SOCKET, SO_BINDTOIFINDEX)? as u32)
+}
+
+/// Binds `fd` to `interface` and sends its multicast datagrams through it.
+///
+/// Binding again to the same interface is a no-op: changing the interface
+/// of a bound socket needs `CAP_NET_RAW`, the first binding does not
+/// (Linux 5.7 on).
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn bind(fd: i32, ipv6: bool, interface: &Interface) -> io::Result<()> {
+    let index = interface.index()?;
+    if bound_index(fd)? != index {
+        set_option(fd, SOL_SOCKET, SO_BINDTOIFINDEX, &(index as i32))?;
+    }
+    let mreqn = IpMreqn {
+        imr_multiaddr: 0,
+        imr_address: 0,
+        imr_ifindex: index as i32,
+    };
+    if ipv6 {
+        set_option(fd, IPPROTO_IPV6, IPV6_MULTICAST_IF, &(index as i32))?;
+        // a v6-only socket refuses it, it sends no IPv4 multicast then
+        let _ = set_option(fd, IPPROTO_IP, IP_MULTICAST_IF, &mreqn);
+        return Ok(());
+    }
+    set_option(fd, IPPROTO_IP, IP_MULTICAST_IF, &mreqn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn test_bind_to_loopback() {
+        let lo = index_of("lo").unwrap();
+        assert!(index_of("no-such-if0").is_err());
+        assert!(index_of("l\0o").is_err());
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(bound_index(client.as_raw_fd()).unwrap(), 0);
+        for interface in [Interface::from("lo"), Interface::Index(lo)] {
+            bind(client.as_raw_fd(), false, &interface).unwrap();
+            assert_eq!(bound_index(client.as_raw_fd()).unwrap(), lo);
+        }
+
+        // loopback traffic still flows through lo
+        client
+            .send_to(b"ping", server.local_addr().unwrap())
+            .unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(server.recv(&mut buf).unwrap(), 4);
+
+        // hosts without IPv6 skip the rest
+        if let Ok(v6) = UdpSocket::bind("[::1]:0") {
+            bind(v6.as_raw_fd(), true, &Interface::Index(lo)).unwrap();
+            assert_eq!(bound_index(v6.as_raw_fd()).unwrap(), lo);
+        }
+    }
+}
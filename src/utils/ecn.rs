@@ -0,0 +1,216 @@
+//! # ECN and receive-queue socket options
+//!
+//! Marks outgoing datagrams ECN-capable through the TOS byte (traffic class
+//! on IPv6) and reads the TOS byte of incoming datagrams from the
+//! `IP_TOS`/`IPV6_TCLASS` control message of `recvmsg`, along with the
+//! count of datagrams the socket dropped with a full receive buffer from
+//! the `SO_RXQ_OVFL` one.
+//! Linux only, on other systems the socket traits' ECN and drop count
+//! methods return [`io::ErrorKind::Unsupported`].
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+};
+
+use super::sys::{self, set_int};
+
+const IPPROTO_IP: i32 = 0;
+const IP_TOS: i32 = 1;
+const IP_RECVTOS: i32 = 13;
+const IPPROTO_IPV6: i32 = 41;
+const IPV6_RECVTCLASS: i32 = 66;
+const IPV6_TCLASS: i32 = 67;
+const SOL_SOCKET: i32 = 1;
+const SO_RXQ_OVFL: i32 = 40;
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+
+/// Sets the TOS byte (traffic class on IPv6) of the datagrams sent on `fd`.
+///
+/// An IPv6 socket also sets it for the IPv4 datagrams it sends to mapped
+/// addresses.
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn set_tos(fd: i32, ipv6: bool, tos: u8) -> io::Result<()> {
+    if ipv6 {
+        set_int(fd, IPPROTO_IPV6, IPV6_TCLASS, i32::from(tos))?;
+        // a v6-only socket refuses it, nothing to mark then
+        let _ = set_int(fd, IPPROTO_IP, IP_TOS, i32::from(tos));
+        return Ok(());
+    }
+    set_int(fd, IPPROTO_IP, IP_TOS, i32::from(tos))
+}
+
+/// Asks for the TOS byte of every datagram received on `fd`.
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn enable_recv_tos(fd: i32, ipv6: bool) -> io::Result<()> {
+    if ipv6 {
+        set_int(fd, IPPROTO_IPV6, IPV6_RECVTCLASS, 1)?;
+        let _ = set_int(fd, IPPROTO_IP, IP_RECVTOS, 1);
+        return Ok(());
+    }
+    set_int(fd, IPPROTO_IP, IP_RECVTOS, 1)
+}
+
+/// Asks for the count of datagrams `fd` dropped with a full receive buffer
+/// along with every datagram received.
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn enable_drop_count(fd: i32) -> io::Result<()> {
+    set_int(fd, SOL_SOCKET, SO_RXQ_OVFL, 1)
+}
+
+/// What the kernel attached to a received datagram
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Ancillary {
+    /// TOS byte, with [`enable_recv_tos`]
+    pub(crate) tos: Option<u8>,
+    /// Datagrams dropped so far, with [`enable_drop_count`]; the kernel
+    /// attaches nothing before the first drop
+    pub(crate) dropped: Option<u32>,
+}
+
+/// Receives a datagram, its sender and its TOS byte, `None` if the kernel
+/// did not attach it (see [`enable_recv_tos`]).
+///
+/// # Errors
+/// The OS error, `WouldBlock` when a timeout or non-blocking receive finds
+/// nothing.
+pub(crate) fn recv_from_tos(
+    fd: i32,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<u8>)> {
+    let (len, from, ancillary) = recv_from_ancillary(fd, buf)?;
+    Ok((len, from, ancillary.tos))
+}
+
+/// Receives a datagram, its sender and the control messages the kernel
+/// attached to it.
+///
+/// # Errors
+/// The OS error, `WouldBlock` when a timeout or non-blocking receive finds
+/// nothing.
+pub(crate) fn recv_from_ancillary(
+    fd: i32,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Ancillary)> {
+    // room for a sockaddr_storage, aligned like it
+    let mut name = [0u64; 16];
+    // room for one or two small control messages
+    let mut control = [0u64; 8];
+    let (len, name, control) = sys::recv_msg(fd, buf, &mut name, &mut control)?;
+    Ok((len, parse_sockaddr(name)?, parse_control(control)))
+}
+
+/// Decodes a `sockaddr_in` or `sockaddr_in6`
+fn parse_sockaddr(name: &[u8]) -> io::Result<SocketAddr> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "unknown sender address");
+    let family = u16::from_ne_bytes(name.get(..2).ok_or_else(invalid)?.try_into().unwrap());
+    match family {
+        AF_INET if name.len() >= 8 => {
+            let port = u16::from_be_bytes([name[2], name[3]]);
+            let ip = Ipv4Addr::new(name[4], name[5], name[6], name[7]);
+            Ok(SocketAddr::from((ip, port)))
+        }
+        AF_INET6 if name.len() >= 28 => {
+            let port = u16::from_be_bytes([name[2], name[3]]);
+            let flowinfo = u32::from_be_bytes(name[4..8].try_into().unwrap());
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&name[8..24]).unwrap());
+            let scope_id = u32::from_ne_bytes(name[24..28].try_into().unwrap());
+            Ok(SocketAddrV6::new(ip, port, flowinfo, scope_id).into())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Finds the TOS byte and the drop count in the control messages
+fn parse_control(control: &[u8]) -> Ancillary {
+    let align = |len: usize| len.next_multiple_of(size_of::<usize>());
+    // struct cmsghdr { size_t cmsg_len; int cmsg_level; int cmsg_type; }
+    let header = align(size_of::<usize>() + 8);
+    let mut ancillary = Ancillary::default();
+    let mut at = 0;
+    while at + header <= control.len() {
+        let word = |i: usize| &control[at + i..];
+        let cmsg_len = usize::from_ne_bytes(word(0)[..size_of::<usize>()].try_into().unwrap());
+        let level = i32::from_ne_bytes(word(size_of::<usize>())[..4].try_into().unwrap());
+        let kind = i32::from_ne_bytes(word(size_of::<usize>() + 4)[..4].try_into().unwrap());
+        if cmsg_len < header || at + cmsg_len > control.len() {
+            break;
+        }
+        let data = &control[at + header..at + cmsg_len];
+        match (level, kind) {
+            // IPv4 passes the byte itself
+            (IPPROTO_IP, IP_TOS) if !data.is_empty() => ancillary.tos = Some(data[0]),
+            // IPv6 passes an int
+            (IPPROTO_IPV6, IPV6_TCLASS) if data.len() >= 4 => {
+                ancillary.tos = Some(i32::from_ne_bytes(data[..4].try_into().unwrap()) as u8);
+            }
+            (SOL_SOCKET, SO_RXQ_OVFL) if data.len() >= 4 => {
+                ancillary.dropped = Some(u32::from_ne_bytes(data[..4].try_into().unwrap()));
+            }
+            _ => {}
+        }
+        at += align(cmsg_len);
+    }
+    ancillary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::UdpSocket, os::fd::AsRawFd};
+
+    #[test]
+    fn test_tos_roundtrip() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        enable_recv_tos(server.as_raw_fd(), false).unwrap();
+        // CE, as a router under congestion would rewrite it
+        set_tos(client.as_raw_fd(), false, 0b11).unwrap();
+        client
+            .send_to(b"marked", server.local_addr().unwrap())
+            .unwrap();
+
+        let mut buf = [0u8; 16];
+        let (len, from, tos) = recv_from_tos(server.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"marked");
+        assert_eq!(from, client.local_addr().unwrap());
+        assert_eq!(tos.map(|t| t & 0b11), Some(0b11));
+    }
+
+    #[test]
+    fn test_drop_count() {
+        const SO_RCVBUF: i32 = 8;
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        // the smallest buffer the kernel allows, a few datagrams
+        set_int(server.as_raw_fd(), SOL_SOCKET, SO_RCVBUF, 1).unwrap();
+        enable_drop_count(server.as_raw_fd()).unwrap();
+        let to = server.local_addr().unwrap();
+        client.send_to(b"first", to).unwrap();
+
+        let mut buf = [0u8; 1024];
+        let (_, _, ancillary) = recv_from_ancillary(server.as_raw_fd(), &mut buf).unwrap();
+        // nothing attached before the first drop
+        assert_eq!(ancillary.dropped, None);
+        for _ in 0..100 {
+            client.send_to(&[0u8; 1000], to).unwrap();
+        }
+        // the count is the one when the datagram was queued, drain what
+        // was queued before the drops
+        server.set_nonblocking(true).unwrap();
+        while recv_from_ancillary(server.as_raw_fd(), &mut buf).is_ok() {}
+        client.send_to(b"last", to).unwrap();
+        server.set_nonblocking(false).unwrap();
+        let (len, _, ancillary) = recv_from_ancillary(server.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"last");
+        assert!(ancillary.dropped.is_some_and(|n| n > 0), "{ancillary:?}");
+        assert_eq!(ancillary.tos, None);
+    }
+}
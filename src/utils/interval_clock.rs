@@ -0,0 +1,127 @@
+//! # Interval boundaries
+//!
+//! The server closes its intervals on a fixed grid laid when the measurement
+//! starts, not a full interval after the last one was closed: a boundary
+//! noticed late makes that interval a little longer and the next one
+//! shorter, the error does not add up over the test. With
+//! [`IntervalAlignment::WallClock`] the grid sits on whole multiples of the
+//! interval since the Unix epoch (every whole second for 1 s intervals), so
+//! the intervals of several servers or runs cover the same time slots.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Where the server lays the boundaries of its reporting intervals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IntervalAlignment {
+    /// Every interval after the start of the measurement. The default.
+    #[default]
+    TestStart,
+    /// On whole multiples of the interval of the wall clock, the first
+    /// interval is shorter.
+    WallClock,
+}
+
+/// The boundaries of the reporting intervals of a measurement
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IntervalClock {
+    interval: Duration,
+    /// Start of the current interval
+    start: Instant,
+    /// Next boundary
+    end: Instant,
+}
+
+impl IntervalClock {
+    /// Intervals of `interval` from `start`, aligned as `alignment` says.
+    pub(crate) fn new(start: Instant, interval: Duration, alignment: IntervalAlignment) -> Self {
+        let interval = interval.max(Duration::from_millis(1));
+        let first = match alignment {
+            IntervalAlignment::TestStart => interval,
+            IntervalAlignment::WallClock => {
+                let since_epoch = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let into = since_epoch.as_nanos() % interval.as_nanos();
+                interval - Duration::from_nanos(into as u64)
+            }
+        };
+        Self {
+            interval,
+            start,
+            end: start + first,
+        }
+    }
+
+    /// Start of the current interval.
+    pub(crate) fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// Time spent in the current interval.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Time left before the next boundary, zero once it has passed.
+    pub(crate) fn remaining(&self) -> Duration {
+        self.end.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether the current interval is over.
+    pub(crate) fn is_due(&self) -> bool {
+        Instant::now() >= self.end
+    }
+
+    /// Moves to the next interval and returns the length of the one just
+    /// over.
+    pub(crate) fn advance(&mut self) -> Duration {
+        let length = self.end - self.start;
+        self.start = self.end;
+        self.end += self.interval;
+        length
+    }
+
+    /// Freezes the grid for the `paused` time.
+    pub(crate) fn shift(&mut self, paused: Duration) {
+        self.start += paused;
+        self.end += paused;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundaries_do_not_drift() {
+        let ms = Duration::from_millis;
+        let start = Instant::now() - ms(1050);
+        let mut clock = IntervalClock::new(start, ms(100), IntervalAlignment::TestStart);
+        // the boundaries are noticed late, the grid stays on the start
+        let mut lengths = Vec::new();
+        while clock.is_due() {
+            lengths.push(clock.advance());
+        }
+        assert_eq!(lengths, vec![ms(100); 10]);
+        assert_eq!(clock.start(), start + ms(1000));
+        assert!(clock.remaining() <= ms(50));
+
+        clock.shift(ms(500));
+        assert_eq!(clock.start(), start + ms(1500));
+        assert!(!clock.is_due());
+    }
+
+    #[test]
+    fn test_wall_clock_alignment() {
+        let interval = Duration::from_millis(250);
+        let now = Instant::now();
+        let mut clock = IntervalClock::new(now, interval, IntervalAlignment::WallClock);
+        let first = clock.advance();
+        assert!(first > Duration::ZERO && first <= interval);
+        // the first boundary is a whole multiple of the interval
+        let at = SystemTime::now() + (clock.start() - Instant::now());
+        let into = at.duration_since(UNIX_EPOCH).unwrap().as_millis() % 250;
+        assert!(!(5..245).contains(&into), "{into} ms into the interval");
+        assert_eq!(clock.advance(), interval);
+    }
+}
@@ -0,0 +1,196 @@
+//! # Jitter estimators
+//!
+//! The jitter of an interval comes from the transit times of its packets,
+//! arrival minus send time. The clocks of the client and the server are not
+//! synchronized, but every estimator works on differences of transit times,
+//! so the offset between them cancels out.
+//!
+//! Whatever the estimator, the delay variation between consecutive packets
+//! (IPDV, RFC 3393) is recorded over the whole test for its percentiles.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::UdpOptError;
+use crate::histogram::Histogram;
+use crate::result::median_f64;
+
+/// How the server turns the transit times of an interval into its
+/// [`jitter_ms`](crate::IntervalResult::jitter_ms).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum JitterEstimator {
+    /// Smoothed interarrival jitter of RFC 3550 (RTP), the gain of 1/16
+    /// following the recent packets. The default.
+    #[default]
+    Rfc3550,
+    /// Mean absolute delay variation between consecutive packets
+    /// (IPDV, RFC 3393), unsmoothed.
+    Ipdv,
+    /// Median absolute deviation of the transit times from their median,
+    /// robust against a few delay spikes. Keeps the transit times of the
+    /// interval in memory.
+    Mad,
+}
+
+impl FromStr for JitterEstimator {
+    type Err = UdpOptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rfc3550" => Ok(Self::Rfc3550),
+            "ipdv" => Ok(Self::Ipdv),
+            "mad" => Ok(Self::Mad),
+            _ => Err(UdpOptError::UnknownJitterEstimator(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for JitterEstimator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Rfc3550 => "rfc3550",
+            Self::Ipdv => "ipdv",
+            Self::Mad => "mad",
+        })
+    }
+}
+
+/// Jitter state of a flow
+#[derive(Debug, Clone)]
+pub(crate) struct Jitter {
+    estimator: JitterEstimator,
+    /// Delay variations in the current interval, for the running mean
+    count: u64,
+    /// Transit times of the current interval (ms), kept for [`JitterEstimator::Mad`]
+    transits: Vec<f64>,
+    /// Absolute delay variations over the whole test (µs)
+    ipdv: Histogram,
+}
+
+impl Jitter {
+    pub(crate) fn new(estimator: JitterEstimator) -> Self {
+        Self {
+            estimator,
+            count: 0,
+            transits: Vec::new(),
+            ipdv: Histogram::new(),
+        }
+    }
+
+    /// Records a packet with the transit time `transit_ms`, the previous
+    /// packet's being `prev_ms`, and updates the `jitter_ms` of the interval
+    pub(crate) fn record(&mut self, prev_ms: Option<f64>, transit_ms: f64, jitter_ms: &mut f64) {
+        if self.estimator == JitterEstimator::Mad {
+            self.transits.push(transit_ms);
+        }
+        let Some(prev) = prev_ms else {
+            return;
+        };
+        let d = (transit_ms - prev).abs();
+        self.ipdv.record((d * 1000.0) as u64);
+        self.count += 1;
+        match self.estimator {
+            JitterEstimator::Rfc3550 => *jitter_ms += (d - *jitter_ms) / 16.0,
+            JitterEstimator::Ipdv => *jitter_ms += (d - *jitter_ms) / self.count as f64,
+            // computed when the interval closes
+            JitterEstimator::Mad => {}
+        }
+    }
+
+    /// Finishes the interval, setting its `jitter_ms` if the estimator
+    /// needs all of its packets
+    pub(crate) fn close_interval(&mut self, jitter_ms: &mut f64) {
+        if self.estimator == JitterEstimator::Mad {
+            *jitter_ms = median_absolute_deviation(&mut self.transits);
+            self.transits.clear();
+        }
+        self.count = 0;
+    }
+
+    /// Forgets the delay variations recorded so far
+    pub(crate) fn clear(&mut self) {
+        self.ipdv = Histogram::new();
+        self.transits.clear();
+        self.count = 0;
+    }
+
+    /// Absolute delay variations between consecutive packets (µs)
+    pub(crate) fn ipdv(&self) -> &Histogram {
+        &self.ipdv
+    }
+}
+
+/// Median of the absolute deviations of `values` from their median, 0 for
+/// fewer than two values
+fn median_absolute_deviation(values: &mut [f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let center = median_f64(values);
+    for v in values.iter_mut() {
+        *v = (*v - center).abs();
+    }
+    median_f64(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(estimator: JitterEstimator, transits: &[f64]) -> (f64, Jitter) {
+        let mut jitter = Jitter::new(estimator);
+        let mut jitter_ms = 0.0;
+        let mut prev = None;
+        for &t in transits {
+            jitter.record(prev, t, &mut jitter_ms);
+            prev = Some(t);
+        }
+        jitter.close_interval(&mut jitter_ms);
+        (jitter_ms, jitter)
+    }
+
+    #[test]
+    fn test_estimators() {
+        // steady 10 ms with one 50 ms spike
+        let transits = [10.0, 11.0, 10.0, 11.0, 60.0, 10.0, 11.0, 10.0, 11.0];
+
+        let (rfc, _) = run(JitterEstimator::Rfc3550, &transits);
+        let (ipdv, jitter) = run(JitterEstimator::Ipdv, &transits);
+        let (mad, _) = run(JitterEstimator::Mad, &transits);
+
+        // 6 variations of 1 ms and 2 of about 50 ms
+        assert!((ipdv - (6.0 + 49.0 + 50.0) / 8.0).abs() < 1e-9);
+        assert!(rfc > 0.0 && rfc < ipdv);
+        // the spike barely moves the median
+        assert!((mad - 1.0).abs() < 1e-9, "{mad}");
+
+        let hist = jitter.ipdv();
+        assert_eq!(hist.count(), 8);
+        assert!((900..1100).contains(&hist.value_at_percentile(50.0)));
+        assert!(hist.max() >= 49_000);
+    }
+
+    #[test]
+    fn test_parse_estimator() {
+        for estimator in [
+            JitterEstimator::Rfc3550,
+            JitterEstimator::Ipdv,
+            JitterEstimator::Mad,
+        ] {
+            assert_eq!(
+                estimator.to_string().parse::<JitterEstimator>().unwrap(),
+                estimator
+            );
+        }
+        assert_eq!(
+            "IPDV".parse::<JitterEstimator>().unwrap(),
+            JitterEstimator::Ipdv
+        );
+        assert!("smoothed".parse::<JitterEstimator>().is_err());
+    }
+}
@@ -1,4 +1,19 @@
+#[cfg(target_os = "linux")]
+pub(crate) mod device;
+#[cfg(target_os = "linux")]
+pub(crate) mod ecn;
+pub(crate) mod interval_clock;
+pub(crate) mod jitter;
 pub mod net_utils;
+pub(crate) mod pacer;
+#[cfg(target_os = "linux")]
+pub(crate) mod pmtu;
 pub(crate) mod random_utils;
+pub(crate) mod results_exchange;
+pub(crate) mod sched;
+pub(crate) mod send_data;
+#[cfg(target_os = "linux")]
+pub(crate) mod sys;
+pub(crate) mod train;
 pub mod udp_data;
 pub mod ui;
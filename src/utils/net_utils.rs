@@ -1,41 +1,674 @@
-use std::time::Duration;
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use tokio::sync::oneshot::Sender;
+
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+#[cfg(feature = "serde")]
+use crate::errors::UdpOptError;
+use crate::{
+    nic::NicCounters,
+    result::{Overhead, Throughput},
+    socket::DatagramSocket,
+    utils::ui,
+    voip::VoipQuality,
+};
 
 /// Statistics for a given interval
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IntervalResult {
-    /// Number of packets received
+    /// Number of packets received, duplicates excluded
     pub received: u64,
     /// Number of packets lost
     pub lost: u64,
     /// Total bytes received
     pub bytes: usize,
+    /// Throughput of the interval (bits/sec), `bytes` over `time`, set once
+    /// when the interval closes
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bitrate_bps: f64,
     /// Jitter in milliseconds
     pub jitter_ms: f64,
     /// Number of out-of-order packets
     pub out_of_order: u64,
-    /// Recommended bitrate (packets per second)
+    /// Number of packets received more than once (extra copies)
+    pub duplicates: u64,
+    /// Number of packets that arrived after their interval was closed, not
+    /// received in this one (see `recovered`), or after the FIN (then
+    /// received, see `ServerBuilder::fin_linger`)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub late: u64,
+    /// Number of the `late` packets a closed interval counted lost: its
+    /// `lost` stays as reported, the loss of the test is the sum of `lost`
+    /// less the sum of these
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub recovered: u64,
+    /// Number of packets marked Congestion Experienced (ECN) on the path,
+    /// counted when the server reads ECN
+    pub ce_marked: u64,
+    /// Number of packets rejected by the pre-shared key check (feature
+    /// `auth`), neither received nor lost
+    pub auth_failures: u64,
+    /// Number of datagrams from sources outside `ServerBuilder::allow_sources`,
+    /// neither received nor lost
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub foreign: u64,
+    /// Number of datagrams that are not valid udpopt packets (see
+    /// [`HeaderError`](crate::HeaderError)), neither received nor lost
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub malformed: u64,
+    /// Bitrate the server recommends from the loss of the interval (bits
+    /// per second), its packet rate recommendation times the mean size of
+    /// the packets received; 0 without a recommendation
     pub recommended_bitrate: u64,
+    /// Packets the client reported sending during the interval in its STATS
+    /// packets (`ClientBuilder::stats_interval`), 0 without them; `lost` is
+    /// then these minus the packets received
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sent: u64,
+    /// Bitrate the client was set to (bits/sec) by the last packet of the
+    /// interval, 0 when unlimited or unknown
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub target_bitrate: u64,
+    /// What the receiving interface counted during the interval, its drops
+    /// telling host losses from network ones; `None` unless the server
+    /// samples it (`ServerBuilder::nic_counters`)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub nic: Option<NicCounters>,
+    /// Number of datagrams the server's socket dropped with its receive
+    /// buffer full, counted in `lost` although the path delivered them;
+    /// counted when the server asks for it (`ServerBuilder::count_socket_drops`)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub socket_drops: u64,
     pub time: Duration,
+    /// Wall-clock time the interval started at, to line it up with external
+    /// logs; `None` for intervals not measured by a server
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub start_time: Option<SystemTime>,
+    /// Start of the interval from the start of the measurement, on the
+    /// monotonic clock and paused time included
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub offset: Duration,
 }
 
-/// Commands that control the UDP server behavior.
+impl IntervalResult {
+    /// Percentage of the packets of this interval that were lost.
+    pub fn loss_percent(&self) -> f64 {
+        ui::loss_percent(self.received, self.lost)
+    }
+
+    /// Percentage of the received packets of this interval that arrived out of order.
+    pub fn ooo_percent(&self) -> f64 {
+        ui::ooo_percent(self.received, self.out_of_order)
+    }
+
+    /// Packets received per second in this interval.
+    pub fn pps(&self) -> f64 {
+        packet_rate(self.received, self.time)
+    }
+
+    /// Sets [`bitrate_bps`](Self::bitrate_bps) from `bytes` and `time`, for
+    /// an interval built by hand; the measured ones come with it.
+    pub fn with_bitrate(mut self) -> Self {
+        self.bitrate_bps = ui::bitrate(self.bytes, self.time.as_secs_f64());
+        self
+    }
+
+    /// Goodput and estimated on-wire throughput of what arrived in this
+    /// interval, see [`TestResult::throughput`](crate::TestResult::throughput).
+    pub fn throughput(&self, overhead: Overhead) -> Throughput {
+        Throughput::new(
+            self.received,
+            self.bytes as u64,
+            self.time.as_secs_f64(),
+            overhead,
+        )
+    }
+
+    /// Estimates the VoIP call quality of this interval from its loss and jitter.
+    pub fn voip_quality(&self) -> VoipQuality {
+        VoipQuality::estimate(self.loss_percent(), self.jitter_ms, 0.0, 1.0)
+    }
+
+    /// Adds the counters of `other`, an interval of the same length measured
+    /// on another socket; the jitter is averaged weighted by the received packets.
+    pub fn merge(&mut self, other: &IntervalResult) {
+        let received = self.received + other.received;
+        if received > 0 {
+            self.jitter_ms = (self.jitter_ms * self.received as f64
+                + other.jitter_ms * other.received as f64)
+                / received as f64;
+        }
+        self.received = received;
+        self.lost += other.lost;
+        self.bytes += other.bytes;
+        self.bitrate_bps += other.bitrate_bps;
+        self.out_of_order += other.out_of_order;
+        self.duplicates += other.duplicates;
+        self.late += other.late;
+        self.recovered += other.recovered;
+        self.ce_marked += other.ce_marked;
+        self.auth_failures += other.auth_failures;
+        self.foreign += other.foreign;
+        self.malformed += other.malformed;
+        self.recommended_bitrate += other.recommended_bitrate;
+        self.target_bitrate += other.target_bitrate;
+        self.sent += other.sent;
+        self.socket_drops += other.socket_drops;
+        self.time = self.time.max(other.time);
+        // the same interface, not summed
+        if self.nic.is_none() {
+            self.nic = other.nic;
+        }
+        if self.start_time.is_none() {
+            self.start_time = other.start_time;
+            self.offset = other.offset;
+        }
+    }
+
+    /// Writes `intervals` to `path` as a JSON array (feature `serde`).
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFailed`] if the file cannot be written,
+    /// [`UdpOptError::ReportFormat`] if a value has no JSON form.
+    #[cfg(feature = "serde")]
+    pub fn save_all(
+        intervals: &[IntervalResult],
+        path: impl AsRef<Path>,
+    ) -> Result<(), UdpOptError> {
+        crate::report::save_json(path.as_ref(), intervals)
+    }
+
+    /// Reads intervals written by [`IntervalResult::save_all`] (feature `serde`).
+    ///
+    /// # Errors
+    /// [`UdpOptError::ReportFailed`] if the file cannot be read,
+    /// [`UdpOptError::ReportFormat`] if it does not hold intervals.
+    #[cfg(feature = "serde")]
+    pub fn load_all(path: impl AsRef<Path>) -> Result<Vec<IntervalResult>, UdpOptError> {
+        let intervals: Vec<IntervalResult> = crate::report::load_json(path.as_ref())?;
+        // saved before the bitrate was kept
+        Ok(intervals
+            .into_iter()
+            .map(|i| {
+                if i.bitrate_bps == 0.0 {
+                    i.with_bitrate()
+                } else {
+                    i
+                }
+            })
+            .collect())
+    }
+}
+
+/// Transmit progress reported by the client while sending.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientProgress {
+    /// Time since the test started
+    pub elapsed: Duration,
+    /// Number of packets sent so far
+    pub packets_sent: u64,
+    /// Number of bytes sent so far
+    pub bytes_sent: u64,
+    /// Average sending bitrate since the start (bits per second)
+    pub bitrate_bps: f64,
+}
+
+impl ClientProgress {
+    pub(crate) fn new(elapsed: Duration, packets_sent: u64, bytes_sent: u64) -> Self {
+        let secs = elapsed.as_secs_f64();
+        let bitrate_bps = if secs > 0.0 {
+            (bytes_sent * 8) as f64 / secs
+        } else {
+            0.0
+        };
+        Self {
+            elapsed,
+            packets_sent,
+            bytes_sent,
+            bitrate_bps,
+        }
+    }
+}
+
+/// Counters of a running server, the reply to [`ServerCommand::Status`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ServerStatus {
+    /// Time since the measurement started, zero before the first packet
+    pub elapsed: Duration,
+    /// The client being measured, `None` before the first packet
+    pub peer: Option<SocketAddr>,
+    /// Number of intervals closed so far
+    pub intervals: usize,
+    /// Number of packets received so far, the open interval included
+    pub received: u64,
+    /// Number of packets lost so far, the open interval included
+    pub lost: u64,
+    /// Number of bytes received so far, the open interval included
+    pub bytes: usize,
+}
+
+impl ServerStatus {
+    pub(crate) fn new(
+        elapsed: Duration,
+        peer: Option<SocketAddr>,
+        closed: &[IntervalResult],
+        open: &IntervalResult,
+    ) -> Self {
+        let mut status = Self {
+            elapsed,
+            peer,
+            intervals: closed.len(),
+            received: open.received,
+            lost: open.lost,
+            bytes: open.bytes,
+        };
+        let mut recovered = open.recovered;
+        for r in closed {
+            status.received += r.received;
+            status.lost += r.lost;
+            status.bytes += r.bytes;
+            recovered += r.recovered;
+        }
+        status.lost = status.lost.saturating_sub(recovered);
+        status
+    }
+}
+
+/// Transmit statistics of the client for one interval.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClientInterval {
+    /// Packets sent in this interval
+    pub packets_sent: u64,
+    /// Bytes sent in this interval
+    pub bytes_sent: u64,
+    /// Length of the interval
+    pub time: Duration,
+    /// Achieved sending bitrate in this interval (bits per second)
+    pub bitrate_bps: f64,
+    /// Achieved sending rate in this interval (packets per second)
+    pub pps: f64,
+    /// Packets the configured rate asked for in this interval
+    pub intended_packets: u64,
+    /// How far behind its pacing schedule the sender was at the end of the interval
+    pub drift: Duration,
+}
+
+impl ClientInterval {
+    pub(crate) fn new(packets_sent: u64, bytes_sent: u64, time: Duration) -> Self {
+        let secs = time.as_secs_f64();
+        Self {
+            packets_sent,
+            bytes_sent,
+            time,
+            bitrate_bps: if secs > 0.0 {
+                (bytes_sent * 8) as f64 / secs
+            } else {
+                0.0
+            },
+            pps: packet_rate(packets_sent, time),
+            intended_packets: 0,
+            drift: Duration::ZERO,
+        }
+    }
+}
+
+/// Observer called by the server with every [`IntervalResult`].
+pub type IntervalCallback = Box<dyn FnMut(&IntervalResult) + Send>;
+
+/// Observer called by the client with its [`ClientProgress`].
+pub type ProgressCallback = Box<dyn FnMut(&ClientProgress) + Send>;
 
-#[derive(Debug, Clone)]
+/// Commands that control the UDP server behavior.
+#[derive(Debug)]
 pub enum ServerCommand {
     Start,
+    /// Start at a wall-clock time; datagrams that arrive earlier are discarded
+    StartAt(SystemTime),
     Stop,
+    /// Freeze the interval timers until `Resume`
+    Pause,
+    /// Continue a paused test
+    Resume,
+    /// Reply with the counters so far on the given channel, the test goes on.
+    /// A `oneshot` channel, so async callers await the reply and sync ones
+    /// take it with `blocking_recv`
+    Status(Sender<ServerStatus>),
 }
 
 /// Commands that control the UDP client behavior.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum ClientCommand {
     Start,
+    /// Start sending at a wall-clock time, so clients on several machines
+    /// (with synchronized clocks) begin together
+    StartAt(SystemTime),
     Stop,
+    /// Stop sending and freeze the test timeline until `Resume`
+    Pause,
+    /// Continue a paused test with the next sequence number
+    Resume,
+    /// Send at this rate from now on, ignored in probing mode
+    SetRate(Rate),
+    /// Reply with the progress so far on the given channel, the test goes on
+    Status(Sender<ClientProgress>),
+}
+
+/// Datagram a client sends to ask the server to transmit the test instead
+/// (reverse mode), as the `udpopt` command line tool does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReverseRequest {
+    /// Bitrate to send at (bits/sec)
+    pub bitrate_bps: f64,
+    /// Packet length (bytes), header included
+    pub payload_size: usize,
+    /// How long to send
+    pub duration: Duration,
+    /// DSCP to mark the packets with, 0 for best effort
+    pub dscp: u8,
+    /// Bitrate added every `step_every` (bits/sec), for a step-load test
+    pub step_bps: f64,
+    /// How often the bitrate rises by `step_bps`, zero for a constant rate
+    pub step_every: Duration,
 }
 
-pub(crate) fn interval_per_packet(paylod: usize, bitrate: f64) -> Duration {
-    let bits_per_packet = (paylod * 8) as f64;
-    let packet_per_second = (bitrate / bits_per_packet).max(1.0);
+impl ReverseRequest {
+    const MAGIC: &'static [u8; 8] = b"UDPOPTRV";
+    /// Length of the encoded request
+    pub const SIZE: usize = 8 + 8 + 4 + 8 + 1 + 8 + 8;
+    /// Length of the requests of older clients, without the DSCP or the steps
+    const LEGACY_SIZES: [usize; 2] = [28, 29];
+
+    /// Encodes the request.
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        buf[0..8].copy_from_slice(Self::MAGIC);
+        buf[8..16].copy_from_slice(&self.bitrate_bps.to_be_bytes());
+        buf[16..20].copy_from_slice(&(self.payload_size as u32).to_be_bytes());
+        buf[20..28].copy_from_slice(&(self.duration.as_millis() as u64).to_be_bytes());
+        buf[28] = self.dscp;
+        buf[29..37].copy_from_slice(&self.step_bps.to_be_bytes());
+        buf[37..45].copy_from_slice(&(self.step_every.as_millis() as u64).to_be_bytes());
+        buf
+    }
+
+    /// Whether `buf` starts like a request, credentials may follow it, see
+    /// [`crate::reverse`].
+    pub fn is_request(buf: &[u8]) -> bool {
+        buf.starts_with(Self::MAGIC)
+    }
+
+    /// Decodes a request, `None` if `buf` is not one. A request of older
+    /// clients, without the DSCP or the steps, asks for best effort at a
+    /// constant rate.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if !(buf.len() == Self::SIZE || Self::LEGACY_SIZES.contains(&buf.len()))
+            || &buf[0..8] != Self::MAGIC
+        {
+            return None;
+        }
+        let steps = buf.get(29..Self::SIZE);
+        Some(Self {
+            bitrate_bps: f64::from_be_bytes(buf[8..16].try_into().ok()?),
+            payload_size: u32::from_be_bytes(buf[16..20].try_into().ok()?) as usize,
+            duration: Duration::from_millis(u64::from_be_bytes(buf[20..28].try_into().ok()?)),
+            dscp: buf.get(28).map_or(0, |dscp| dscp & 0x3f),
+            step_bps: steps.map_or(0.0, |s| f64::from_be_bytes(s[0..8].try_into().unwrap())),
+            step_every: steps.map_or(Duration::ZERO, |s| {
+                Duration::from_millis(u64::from_be_bytes(s[8..16].try_into().unwrap()))
+            }),
+        })
+    }
+}
+
+/// Outcome of waiting on the control channel while paused.
+pub(crate) enum PauseOutcome {
+    /// `Resume` arrived after being paused for the given duration
+    Resumed(Duration),
+    /// `Stop` arrived while paused
+    Stopped,
+}
+
+/// Target sending rate of the client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rate {
+    /// Bits per second, the packet header included
+    Bps(f64),
+    /// Packets per second, whatever their length
+    Pps(f64),
+    /// As fast as the socket takes them, without pacing, to find the
+    /// ceiling of the host and the NIC. The sync client sends non-blocking
+    /// and counts the sends refused by a full send buffer.
+    Unlimited,
+}
+
+impl Rate {
+    /// Packets per second with packets of `packet_len` bytes on average
+    pub fn pps(&self, packet_len: f64) -> f64 {
+        match *self {
+            Rate::Bps(bps) => bps / (packet_len * 8.0),
+            Rate::Pps(pps) => pps,
+            Rate::Unlimited => f64::INFINITY,
+        }
+    }
+
+    /// Bits per second with packets of `packet_len` bytes on average
+    pub fn bitrate_bps(&self, packet_len: f64) -> f64 {
+        match *self {
+            Rate::Bps(bps) => bps,
+            Rate::Pps(pps) => pps * packet_len * 8.0,
+            Rate::Unlimited => f64::INFINITY,
+        }
+    }
+}
+
+/// Time between two packets, zero for [`Rate::Unlimited`]
+pub(crate) fn interval_per_packet(paylod: f64, rate: Rate) -> Duration {
+    let packet_per_second = rate.pps(paylod).max(1.0);
 
     Duration::from_secs_f64(1.0 / packet_per_second)
 }
+
+/// Packets per second of `packets` over `time`, 0 over no time
+pub(crate) fn packet_rate(packets: u64, time: Duration) -> f64 {
+    let secs = time.as_secs_f64();
+    if secs > 0.0 {
+        packets as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+/// Back-to-back packet trains sent in probing mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TrainConfig {
+    /// Packets per train
+    pub(crate) length: u16,
+    /// Time between the starts of two trains
+    pub(crate) spacing: Duration,
+}
+
+/// Instant at which the packet in pacing slot `tick` should leave
+#[inline]
+pub(crate) fn pacing_target(tick: u64, ipp: Duration, start: Instant) -> Instant {
+    start + Duration::from_secs_f64(tick as f64 * ipp.as_secs_f64())
+}
+
+/// Monotonic instant matching the wall-clock time `at`, now if `at` has passed
+pub(crate) fn instant_at(at: SystemTime) -> Instant {
+    let now = Instant::now();
+    now + at.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+/// Blocks until `target`, sleeping first and spinning the last few hundred µs
+pub(crate) fn wait_until(target: Instant) {
+    loop {
+        let now = Instant::now();
+        if now >= target {
+            break;
+        }
+
+        let remaining = target - now;
+
+        if remaining > Duration::from_micros(200) {
+            // coarse sleep; subtract a small delta to avoid oversleep
+            std::thread::sleep(remaining - Duration::from_micros(100));
+        } else {
+            // using spin here is more acurate but is uses more cpu
+            // short spin / yield
+            std::thread::yield_now();
+        }
+    }
+}
+
+/// Longest sleep of a scheduled start between two looks at the control
+/// channel and the shutdown flag
+pub(crate) const START_POLL: Duration = Duration::from_millis(50);
+
+/// Blocks until `target` like [`wait_until`], asking `stopped` every
+/// [`START_POLL`] whether to give up; returns whether `target` was reached
+pub(crate) fn wait_until_unless(target: Instant, mut stopped: impl FnMut() -> bool) -> bool {
+    loop {
+        if stopped() {
+            return false;
+        }
+        let remaining = target.saturating_duration_since(Instant::now());
+        if remaining <= START_POLL {
+            wait_until(target);
+            return true;
+        }
+        // the last slice is left to `wait_until`, for its precision
+        std::thread::sleep((remaining - START_POLL).min(START_POLL));
+    }
+}
+
+/// Drops the datagrams already queued on `sock`, returns how many there were
+pub(crate) fn discard_pending<S: DatagramSocket>(sock: &S, buf: &mut [u8]) -> io::Result<u64> {
+    sock.set_nonblocking(true)?;
+    let mut discarded = 0;
+    let res = loop {
+        match sock.recv_from(buf) {
+            Ok(_) => discarded += 1,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(discarded),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => break Err(e),
+        }
+    };
+    sock.set_nonblocking(false)?;
+    res
+}
+
+/// Keeps a socket non-blocking until dropped
+pub(crate) struct NonBlocking<'a, S: DatagramSocket>(&'a S);
+
+impl<'a, S: DatagramSocket> NonBlocking<'a, S> {
+    pub(crate) fn new(sock: &'a S) -> io::Result<Self> {
+        sock.set_nonblocking(true)?;
+        Ok(Self(sock))
+    }
+}
+
+impl<S: DatagramSocket> Drop for NonBlocking<'_, S> {
+    fn drop(&mut self) {
+        let _ = self.0.set_nonblocking(false);
+    }
+}
+
+/// Send errors that only skip the current packet instead of aborting the test
+pub(crate) fn is_transient_send_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
+
+/// ENOBUFS, the kernel is out of buffer space for the datagram
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const ENOBUFS: i32 = 105;
+/// WSAENOBUFS
+#[cfg(windows)]
+const ENOBUFS: i32 = 10055;
+/// ENOBUFS of macOS and the BSDs
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+const ENOBUFS: i32 = 55;
+
+/// I/O errors that may go away by themselves: an interrupted call, a full
+/// socket buffer or a timeout, unlike e.g. a refused connection
+pub(crate) fn is_transient_io_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut
+    ) || e.raw_os_error() == Some(ENOBUFS)
+}
+
+/// EMSGSIZE, the datagram is longer than the socket may send
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const EMSGSIZE: i32 = 90;
+/// WSAEMSGSIZE
+#[cfg(windows)]
+const EMSGSIZE: i32 = 10040;
+/// EMSGSIZE of macOS and the BSDs
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+const EMSGSIZE: i32 = 40;
+
+/// Send errors refusing a datagram as too long: longer than the path MTU with
+/// the don't-fragment bit set, or than any UDP datagram
+pub(crate) fn is_too_big_error(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(EMSGSIZE)
+}
+
+/// Send errors carrying an ICMP unreachable: `ECONNREFUSED` on a connected
+/// socket when nothing listens on the peer's port, or no route to it
+pub(crate) fn is_unreachable_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+    )
+}
+
+/// How the client retries a send that failed with a transient error, e.g.
+/// `EINTR` or `ENOBUFS`, see `ClientBuilder::send_retry`. Other errors, e.g.
+/// `ECONNREFUSED`, still end the test, see `ClientBuilder::unreachable_grace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries of one packet, it is then skipped and counted as a send failure
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every next one
+    pub backoff: Duration,
+    /// Longest wait before a retry
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// 3 retries after 100 µs, 200 µs and 400 µs
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff: Duration::from_micros(100),
+            max_backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether the failed send is worth retrying; a full send buffer of a
+    /// non-blocking send is counted instead
+    pub(crate) fn retries(&self, e: &io::Error) -> bool {
+        is_transient_io_error(e) && e.kind() != io::ErrorKind::WouldBlock
+    }
+
+    /// Wait before the retry `attempt`, 0 for the first
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff)
+    }
+}
@@ -0,0 +1,403 @@
+//! # Packet pacing
+//!
+//! Waits for the send time of the next packet. By default
+//! ([`PacingPolicy::Hybrid`]) it sleeps while the send time is further
+//! away than the sleep threshold, then yields until it is reached. The
+//! threshold is how late a short sleep can wake up on this host, measured
+//! once per process, so the sleeps never overshoot the target: about 100 µs
+//! on Linux, a whole timer tick (15.6 ms by default) on Windows. The
+//! measurement is returned in [`ClientReport::calibration`].
+//!
+//! On Windows the pacer also raises the timer resolution to 1 ms
+//! (`timeBeginPeriod`) while it lives and sleeps on a high-resolution
+//! waitable timer (Windows 10 1803 and later), which brings the threshold
+//! down to a few hundred µs; without it the packets would leave in bursts
+//! of one timer tick.
+//!
+//! [`ClientReport::calibration`]: crate::ClientReport::calibration
+
+use std::{
+    sync::{
+        OnceLock,
+        mpsc::{Receiver, RecvTimeoutError, TryRecvError},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::result::SleepCalibration;
+
+/// Sleeps timed by the calibration
+const CALIBRATION_SAMPLES: u32 = 8;
+/// Length of every calibration sleep
+const CALIBRATION_SLEEP: Duration = Duration::from_micros(100);
+/// Lowest sleep threshold, what was used before the calibration
+const MIN_THRESHOLD: Duration = Duration::from_micros(200);
+/// Highest sleep threshold, above any timer tick
+const MAX_THRESHOLD: Duration = Duration::from_millis(50);
+
+/// Longest sleep left to the pacer's timer while waiting on a channel, the
+/// wait above it on the channel's coarser timeout; above the 15.6 ms tick of
+/// Windows so that timeout overshooting it still leaves the timer the end
+const TIMER_SLICE: Duration = Duration::from_millis(20);
+
+/// Calibration of the first pacer of the process
+static CALIBRATION: OnceLock<SleepCalibration> = OnceLock::new();
+
+/// How the client waits for the send time of the next packet, trading CPU
+/// for timing accuracy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacingPolicy {
+    /// Busy-waits on the clock: the most accurate, keeps a core at 100 %.
+    /// The async client does not give the executor back while it waits.
+    PureSpin,
+    /// Sleeps until `threshold` before the send time, then yields until it.
+    /// `None` takes the threshold calibrated on the host for the sync client
+    /// and 200 µs for the async one. The default.
+    Hybrid {
+        /// How long before the send time the client stops sleeping.
+        threshold: Option<Duration>,
+    },
+    /// Sleeps until the send time: the least CPU, but the packets leave as
+    /// late as the timer wakes up, in bursts on coarse timers.
+    SleepOnly,
+    /// Yields to the scheduler (or the executor) until the send time: close
+    /// to spinning when the core is idle, fair to other work when it is not.
+    Yield,
+}
+
+impl Default for PacingPolicy {
+    fn default() -> Self {
+        Self::Hybrid { threshold: None }
+    }
+}
+
+/// What to do while waiting for a send time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Wait {
+    Sleep(Duration),
+    Yield,
+    Spin,
+}
+
+impl PacingPolicy {
+    /// Next wait with `remaining` time to go, `default_threshold` standing
+    /// in for a `Hybrid` one left unset
+    pub(crate) fn next_wait(&self, remaining: Duration, default_threshold: Duration) -> Wait {
+        match *self {
+            Self::PureSpin => Wait::Spin,
+            Self::Yield => Wait::Yield,
+            Self::SleepOnly => Wait::Sleep(remaining),
+            Self::Hybrid { threshold } => {
+                let threshold = threshold.unwrap_or(default_threshold);
+                if remaining > threshold {
+                    // wakes up by the target at the latest
+                    Wait::Sleep(remaining - threshold)
+                } else {
+                    Wait::Yield
+                }
+            }
+        }
+    }
+}
+
+/// Waits for pacing targets with a [`PacingPolicy`], see the [module docs](self).
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    policy: PacingPolicy,
+    calibration: SleepCalibration,
+    #[cfg(windows)]
+    timer: windows::HighResTimer,
+}
+
+impl Pacer {
+    /// A pacer, calibrating the sleep threshold on the first call.
+    pub(crate) fn new(policy: PacingPolicy) -> Self {
+        #[cfg(windows)]
+        let mut pacer = Self {
+            policy,
+            calibration: SleepCalibration::default(),
+            timer: windows::HighResTimer::new(),
+        };
+        #[cfg(not(windows))]
+        let mut pacer = Self {
+            policy,
+            calibration: SleepCalibration::default(),
+        };
+        pacer.calibration = *CALIBRATION.get_or_init(|| calibrate(|d| pacer.sleep(d)));
+        pacer
+    }
+
+    /// The sleep accuracy measured on this host.
+    pub(crate) fn calibration(&self) -> SleepCalibration {
+        self.calibration
+    }
+
+    /// Blocks until `target`.
+    pub(crate) fn wait_until(&self, target: Instant) {
+        loop {
+            let now = Instant::now();
+            if now >= target {
+                break;
+            }
+
+            match self
+                .policy
+                .next_wait(target - now, self.calibration.threshold)
+            {
+                Wait::Sleep(duration) => self.sleep(duration),
+                Wait::Yield => std::thread::yield_now(),
+                Wait::Spin => std::hint::spin_loop(),
+            }
+        }
+    }
+
+    /// Blocks until `target` like [`wait_until`](Self::wait_until), waiting
+    /// on `rx` for all but the last [`TIMER_SLICE`] of the long sleeps and
+    /// checking it between the short ones; returns what arrived before
+    /// `target`.
+    pub(crate) fn wait_until_recv<T>(&self, target: Instant, rx: &Receiver<T>) -> Option<T> {
+        loop {
+            let now = Instant::now();
+            if now >= target {
+                return None;
+            }
+
+            match self
+                .policy
+                .next_wait(target - now, self.calibration.threshold)
+            {
+                Wait::Sleep(duration) if duration > TIMER_SLICE => {
+                    match rx.recv_timeout(duration - TIMER_SLICE) {
+                        Ok(value) => return Some(value),
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        // nothing more can arrive
+                        Err(RecvTimeoutError::Disconnected) => {
+                            self.wait_until(target);
+                            return None;
+                        }
+                    }
+                }
+                Wait::Sleep(duration) => self.sleep(duration),
+                Wait::Yield => std::thread::yield_now(),
+                Wait::Spin => std::hint::spin_loop(),
+            }
+            match rx.try_recv() {
+                Ok(value) => return Some(value),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    self.wait_until(target);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn sleep(&self, duration: Duration) {
+        #[cfg(windows)]
+        self.timer.sleep(duration);
+        #[cfg(not(windows))]
+        std::thread::sleep(duration);
+    }
+}
+
+/// Times a few short sleeps of `sleep`; the threshold is the worst overshoot
+/// plus half of it for the ones not seen
+fn calibrate(sleep: impl Fn(Duration)) -> SleepCalibration {
+    let worst = (0..CALIBRATION_SAMPLES)
+        .map(|_| {
+            let started = Instant::now();
+            sleep(CALIBRATION_SLEEP);
+            started.elapsed().saturating_sub(CALIBRATION_SLEEP)
+        })
+        .max()
+        .unwrap_or_default();
+    SleepCalibration {
+        overshoot: worst,
+        threshold: (worst + worst / 2).clamp(MIN_THRESHOLD, MAX_THRESHOLD),
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{ffi::c_void, ptr, time::Duration};
+
+    type Handle = *mut c_void;
+
+    const CREATE_WAITABLE_TIMER_HIGH_RESOLUTION: u32 = 0x0000_0002;
+    const TIMER_ALL_ACCESS: u32 = 0x001F_0003;
+    const INFINITE: u32 = 0xFFFF_FFFF;
+    const TIMERR_NOERROR: u32 = 0;
+
+    #[link(name = "winmm")]
+    unsafe extern "system" {
+        fn timeBeginPeriod(uPeriod: u32) -> u32;
+        fn timeEndPeriod(uPeriod: u32) -> u32;
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn CreateWaitableTimerExW(
+            lpTimerAttributes: *const c_void,
+            lpTimerName: *const u16,
+            dwFlags: u32,
+            dwDesiredAccess: u32,
+        ) -> Handle;
+        fn SetWaitableTimer(
+            hTimer: Handle,
+            lpDueTime: *const i64,
+            lPeriod: i32,
+            pfnCompletionRoutine: *const c_void,
+            lpArgToCompletionRoutine: *const c_void,
+            fResume: i32,
+        ) -> i32;
+        fn WaitForSingleObject(hHandle: Handle, dwMilliseconds: u32) -> u32;
+        fn CloseHandle(hObject: Handle) -> i32;
+    }
+
+    /// A 1 ms system timer period and a high-resolution waitable timer, both
+    /// given back on drop
+    #[derive(Debug)]
+    pub(super) struct HighResTimer {
+        /// Null before Windows 10 1803, `thread::sleep` is used then
+        handle: Handle,
+        period_raised: bool,
+    }
+
+    // SAFETY: the handle is owned by the timer and a kernel object handle
+    // may be used and closed from any thread. Not `Sync`: two threads
+    // sleeping on it at once would re-arm each other's timer
+    unsafe impl Send for HighResTimer {}
+
+    impl HighResTimer {
+        pub(super) fn new() -> Self {
+            // SAFETY: no pointers; matched by `timeEndPeriod` on drop when
+            // it succeeded
+            let period_raised = unsafe { timeBeginPeriod(1) } == TIMERR_NOERROR;
+            // SAFETY: null attributes and name are allowed, for default
+            // security and an unnamed timer; null on failure, checked
+            // before every use
+            let handle = unsafe {
+                CreateWaitableTimerExW(
+                    ptr::null(),
+                    ptr::null(),
+                    CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+                    TIMER_ALL_ACCESS,
+                )
+            };
+            Self {
+                handle,
+                period_raised,
+            }
+        }
+
+        pub(super) fn sleep(&self, duration: Duration) {
+            if self.handle.is_null() {
+                return std::thread::sleep(duration);
+            }
+            // negative: relative to now, in 100 ns units
+            let due = -i64::try_from(duration.as_nanos() / 100).unwrap_or(i64::MAX);
+            // SAFETY: the handle is a live timer owned by `self`, `due` a
+            // live local read during the call, no completion routine
+            let armed =
+                unsafe { SetWaitableTimer(self.handle, &due, 0, ptr::null(), ptr::null(), 0) != 0 };
+            if armed {
+                // SAFETY: the handle is a live timer, armed just above, so
+                // the wait ends when it fires
+                unsafe { WaitForSingleObject(self.handle, INFINITE) };
+            } else {
+                std::thread::sleep(duration);
+            }
+        }
+    }
+
+    impl Drop for HighResTimer {
+        fn drop(&mut self) {
+            if !self.handle.is_null() {
+                // SAFETY: a live handle owned by `self`, closed only here
+                unsafe { CloseHandle(self.handle) };
+            }
+            if self.period_raised {
+                // SAFETY: no pointers; undoes the `timeBeginPeriod` of `new`
+                unsafe { timeEndPeriod(1) };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibrate() {
+        // a timer that always wakes up 2 ms late
+        let coarse = calibrate(|d| std::thread::sleep(d + Duration::from_millis(2)));
+        assert!(coarse.overshoot >= Duration::from_millis(2), "{coarse:?}");
+        assert!(coarse.threshold >= Duration::from_millis(3), "{coarse:?}");
+        // a perfect one keeps the lowest threshold
+        let perfect = calibrate(|_| {});
+        assert_eq!(perfect.overshoot, Duration::ZERO);
+        assert_eq!(perfect.threshold, MIN_THRESHOLD);
+    }
+
+    #[test]
+    fn test_next_wait() {
+        let ms = Duration::from_millis;
+        let hybrid = PacingPolicy::default();
+        assert_eq!(hybrid.next_wait(ms(10), ms(1)), Wait::Sleep(ms(9)));
+        assert_eq!(hybrid.next_wait(ms(1), ms(1)), Wait::Yield);
+        let fixed = PacingPolicy::Hybrid {
+            threshold: Some(ms(4)),
+        };
+        assert_eq!(fixed.next_wait(ms(10), ms(1)), Wait::Sleep(ms(6)));
+        assert_eq!(fixed.next_wait(ms(3), ms(1)), Wait::Yield);
+        assert_eq!(
+            PacingPolicy::SleepOnly.next_wait(ms(10), ms(1)),
+            Wait::Sleep(ms(10))
+        );
+        assert_eq!(PacingPolicy::Yield.next_wait(ms(10), ms(1)), Wait::Yield);
+        assert_eq!(PacingPolicy::PureSpin.next_wait(ms(10), ms(1)), Wait::Spin);
+    }
+
+    #[test]
+    fn test_wait_until() {
+        for policy in [
+            PacingPolicy::default(),
+            PacingPolicy::PureSpin,
+            PacingPolicy::SleepOnly,
+            PacingPolicy::Yield,
+        ] {
+            let pacer = Pacer::new(policy);
+            assert!(pacer.calibration().threshold >= MIN_THRESHOLD);
+            for ms in [0, 1, 5] {
+                let target = Instant::now() + Duration::from_millis(ms);
+                pacer.wait_until(target);
+                let now = Instant::now();
+                assert!(now >= target);
+                // loose, the test machine may be busy
+                assert!(now - target < Duration::from_millis(50));
+            }
+        }
+    }
+
+    #[test]
+    fn test_wait_until_recv() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        for policy in [PacingPolicy::default(), PacingPolicy::SleepOnly] {
+            let pacer = Pacer::new(policy);
+            // past the slice left to the timer, and within it
+            for ms in [1, 40] {
+                let target = Instant::now() + Duration::from_millis(ms);
+                assert_eq!(pacer.wait_until_recv(target, &rx), None::<u8>);
+                let now = Instant::now();
+                assert!(now >= target);
+                assert!(now - target < Duration::from_millis(50));
+            }
+            // a message cuts the wait short
+            tx.send(7).unwrap();
+            let target = Instant::now() + Duration::from_secs(5);
+            assert_eq!(pacer.wait_until_recv(target, &rx), Some(7));
+            assert!(Instant::now() < target);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! # Path MTU discovery options
+//!
+//! Sets the don't-fragment bit of outgoing datagrams through
+//! `IP_MTU_DISCOVER` / `IPV6_MTU_DISCOVER` and reads the path MTU the kernel
+//! knows for a connected socket with `IP_MTU` / `IPV6_MTU`. With the bit set
+//! a datagram longer than the path MTU is refused with `EMSGSIZE` instead of
+//! being fragmented.
+//! Linux only, on other systems the socket traits' `set_dont_fragment` and
+//! `path_mtu` return [`io::ErrorKind::Unsupported`].
+
+use std::io;
+
+use super::sys::{get_int, set_int};
+
+const IPPROTO_IP: i32 = 0;
+const IP_MTU_DISCOVER: i32 = 10;
+const IP_MTU: i32 = 14;
+const IPPROTO_IPV6: i32 = 41;
+const IPV6_MTU_DISCOVER: i32 = 23;
+const IPV6_MTU: i32 = 24;
+/// Never set the bit, fragment locally (`IP_PMTUDISC_DONT`, the IPv6 value
+/// is the same)
+const PMTUDISC_DONT: i32 = 0;
+/// Always set the bit, refuse what does not fit (`IP_PMTUDISC_DO`)
+const PMTUDISC_DO: i32 = 2;
+
+/// Sets (`on`) or clears the don't-fragment bit of the datagrams sent on `fd`.
+///
+/// An IPv6 socket also sets it for the IPv4 datagrams it sends to mapped
+/// addresses.
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn set_dont_fragment(fd: i32, ipv6: bool, on: bool) -> io::Result<()> {
+    let mode = if on { PMTUDISC_DO } else { PMTUDISC_DONT };
+    if ipv6 {
+        set_int(fd, IPPROTO_IPV6, IPV6_MTU_DISCOVER, mode)?;
+        // a v6-only socket refuses it, it sends no IPv4 then
+        let _ = set_int(fd, IPPROTO_IP, IP_MTU_DISCOVER, mode);
+        return Ok(());
+    }
+    set_int(fd, IPPROTO_IP, IP_MTU_DISCOVER, mode)
+}
+
+/// Path MTU the kernel knows towards the peer of the connected `fd`: the
+/// MTU of the route, or less once an ICMP "fragmentation needed" arrived.
+///
+/// # Errors
+/// The OS error, `ENOTCONN` for an unconnected socket.
+pub(crate) fn path_mtu(fd: i32, ipv6: bool) -> io::Result<usize> {
+    let (level, name) = if ipv6 {
+        (IPPROTO_IPV6, IPV6_MTU)
+    } else {
+        (IPPROTO_IP, IP_MTU)
+    };
+    Ok(get_int(fd, level, name)? as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn test_dont_fragment_on_loopback() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(path_mtu(client.as_raw_fd(), false).is_err());
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let mtu = path_mtu(client.as_raw_fd(), false).unwrap();
+        assert!(mtu >= 1280, "loopback MTU {mtu}");
+
+        set_dont_fragment(client.as_raw_fd(), false, true).unwrap();
+        // 28 bytes of IP and UDP header, a 64 KiB loopback MTU fits any datagram
+        let fits = (mtu - 28).min(65507);
+        let too_long = vec![0u8; fits + 1];
+        assert!(client.send(&too_long[..fits]).is_ok());
+        let err = client.send(&too_long).unwrap_err();
+        assert!(crate::utils::net_utils::is_too_big_error(&err), "{err}");
+        if fits == 65507 {
+            return;
+        }
+
+        // cleared, the kernel fragments it
+        set_dont_fragment(client.as_raw_fd(), false, false).unwrap();
+        assert_eq!(client.send(&too_long).unwrap(), fits + 1);
+    }
+}
@@ -1,27 +1,20 @@
 //! # Cross-Platform Random Number Generator
 //!
-//! Provides sync and async  random number generator for filling buffers with random bytes,
+//! Provides a random number generator for filling buffers with random bytes,
 //! compatible with both Unix-like systems and Windows.  
 //! On Unix, it uses `/dev/urandom`.  
-//! On Windows, it uses the system-preferred RNG via `BCryptGenRandom`.
+//! On Windows, it uses the system-preferred RNG via `BCryptGenRandom`.  
+//! With the `getrandom` feature, it uses the `getrandom` crate everywhere,
+//! for targets where neither is available (UWP, MinGW cross-builds...).
 
 use std::io;
 
-#[cfg(windows)]
-/// Flag to use the system-preferred RNG on Windows without opening an algorithm handle
-const BCRYPT_USE_SYSTEM_PREFERRED_RNG: u32 = 0x00000002;
-#[cfg(windows)]
-#[link(name = "bcrypt")]
-
-unsafe extern "system" {
-    /// External Windows API function for random bytes
-    fn BCryptGenRandom(hAlgorithm: usize, pbBuffer: *mut u8, cbBuffer: u32, dwFlags: u32) -> i32;
-}
+use crate::payload::PayloadSource;
 
 /// Cross-platform random number generator
 pub struct RandomToSend {
     /// File handle for Unix systems (`/dev/urandom`)
-    #[cfg(unix)]
+    #[cfg(all(unix, not(feature = "getrandom")))]
     file: std::fs::File,
 }
 
@@ -30,139 +23,119 @@ impl RandomToSend {
     ///
     /// # Errors
     /// - Unix: if opening `/dev/urandom` fails
-    /// - Windows: never fails on creation  
+    /// - Windows, `getrandom`: never fails on creation  
     pub fn new() -> io::Result<Self> {
-        #[cfg(unix)]
+        #[cfg(all(unix, not(feature = "getrandom")))]
         {
             let file = std::fs::File::open("/dev/urandom")?;
             Ok(Self { file })
         }
 
-        #[cfg(windows)]
+        #[cfg(any(windows, feature = "getrandom"))]
         {
             Ok(Self {})
         }
     }
+
     /// Fills the provided buffer with random bytes
     ///
     /// # Parameters
     /// - `buffer`: the mutable slice to fill with random data
     ///
     /// # Errors
-    /// - Unix: if reading from `/dev/urandom` fails
+    /// - Unix: if reading from `/dev/urandom` fails or comes up short
     /// - Windows: if `BCryptGenRandom` fails
-
+    /// - `getrandom`: if the OS source fails
     pub fn fill(&mut self, buffer: &mut [u8]) -> io::Result<()> {
-        #[cfg(unix)]
+        #[cfg(feature = "getrandom")]
+        {
+            getrandom::fill(buffer).map_err(io::Error::from)
+        }
+
+        #[cfg(all(unix, not(feature = "getrandom")))]
         {
             use std::io::Read;
 
-            let mut total_read = 0;
-            while total_read < buffer.len() {
-                match self.file.read(&mut buffer[total_read..])? {
-                    0 => break, // EOF
-                    n => total_read += n,
-                }
-            }
-            Ok(())
+            self.file.read_exact(buffer)
         }
 
-        #[cfg(windows)]
+        #[cfg(all(windows, not(feature = "getrandom")))]
         {
+            bcrypt::fill(buffer)
+        }
+    }
+}
+
+impl PayloadSource for RandomToSend {
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        RandomToSend::fill(self, buf)
+    }
+}
+
+/// The one call into bcrypt, behind a safe function
+#[cfg(all(windows, not(feature = "getrandom")))]
+mod bcrypt {
+    use std::io;
+
+    /// Flag to use the system-preferred RNG on Windows without opening an algorithm handle
+    const BCRYPT_USE_SYSTEM_PREFERRED_RNG: u32 = 0x00000002;
+
+    #[link(name = "bcrypt")]
+    unsafe extern "system" {
+        /// External Windows API function for random bytes
+        fn BCryptGenRandom(
+            hAlgorithm: usize,
+            pbBuffer: *mut u8,
+            cbBuffer: u32,
+            dwFlags: u32,
+        ) -> i32;
+    }
+
+    /// Fills `buffer` from the system-preferred RNG, in chunks whose length
+    /// fits the `u32` of the API
+    pub(super) fn fill(buffer: &mut [u8]) -> io::Result<()> {
+        for chunk in buffer.chunks_mut(u32::MAX as usize) {
+            // SAFETY: the pointer and length describe `chunk`, writable for
+            // the whole call, and the flag stands for the algorithm handle
             let status = unsafe {
                 BCryptGenRandom(
                     0,
-                    buffer.as_mut_ptr(),
-                    buffer.len() as u32,
+                    chunk.as_mut_ptr(),
+                    chunk.len() as u32,
                     BCRYPT_USE_SYSTEM_PREFERRED_RNG,
                 )
             };
-
             if status != 0 {
-                Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("BCryptGenRandom failed {:#x}", status),
-                ))
-            } else {
-                Ok(())
+                return Err(io::Error::other(format!(
+                    "BCryptGenRandom failed {status:#x}"
+                )));
             }
         }
+        Ok(())
     }
 }
 
-pub struct AsyncRandomToSend {
-    #[cfg(unix)]
-    file: tokio::fs::File,
-}
-
-impl AsyncRandomToSend {
-    /// Creates a new `AsyncRandomToSend`.
-    ///
-    /// - On Unix, opens `/dev/urandom` asynchronously.
-    /// - On Windows, no actual I/O is required, so this is a fast operation.
-    ///
-    /// # Errors
-    ///
-    /// - Returns an `io::Error` if `/dev/urandom` cannot be opened on Unix.
-    /// - On Windows, this function always succeeds.
-    pub async fn new() -> io::Result<Self> {
-        #[cfg(unix)]
-        {
-            let file = tokio::fs::File::open("/dev/urandom").await?;
-            Ok(Self { file })
-        }
-
-        #[cfg(windows)]
-        {
-            Ok(Self {})
-        }
-    }
-
-    /// Asynchronously fills the given buffer with cryptographically secure random bytes.
-    ///
-    /// # Parameters
-    ///
-    /// - `buffer`: A mutable byte slice that will be filled with random data.
-    ///
-    /// # Errors
-    ///
-    /// - On Unix, returns any `tokio::io::Error` encountered while reading.
-    /// - On Windows, returns an error if `BCryptGenRandom` fails.
-    ///
-
-    pub async fn fill(&mut self, buffer: &mut [u8]) -> io::Result<()> {
-        #[cfg(unix)]
-        {
-            use tokio::io::AsyncReadExt;
-            let mut total = 0;
-            while total < buffer.len() {
-                let n = self.file.read(&mut buffer[total..]).await?;
-                if n == 0 {
-                    break;
-                }
-                total += n;
-            }
-            Ok(())
-        }
-
-        // note that this functin in non-bloking in  nature
-        #[cfg(windows)]
-        unsafe {
-            let status = BCryptGenRandom(
-                0,
-                buffer.as_mut_ptr(),
-                buffer.len() as u32,
-                BCRYPT_USE_SYSTEM_PREFERRED_RNG,
-            );
-
-            if status != 0 {
-                Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("BCryptGenRandom failed {:#x}", status),
-                ))
-            } else {
-                Ok(())
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_lengths() {
+        let mut random = RandomToSend::new().unwrap();
+        let source: &mut dyn PayloadSource = &mut random;
+
+        source.fill(&mut []).unwrap();
+        let mut one = [0u8; 1];
+        source.fill(&mut one).unwrap();
+
+        // past the 64 KiB a single read or call may stop at
+        let mut first = vec![0u8; 100_000];
+        let mut second = vec![0u8; 100_000];
+        source.fill(&mut first).unwrap();
+        source.fill(&mut second).unwrap();
+        assert_ne!(first, second);
+        // filled to the end
+        assert!(first[first.len() - 64..].iter().any(|&b| b != 0));
+        assert!(second[second.len() - 64..].iter().any(|&b| b != 0));
     }
 }
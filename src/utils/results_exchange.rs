@@ -0,0 +1,386 @@
+//! # In-band result exchange
+//!
+//! Once the server has received the client's FIN it can send its final
+//! [`TestResult`] back over the test socket, so the client gets both sides of
+//! the measurement without any extra channel.
+//!
+//! The encoded result is split into chunks, each one a [`UdpHeader`] with
+//! `FLAG_RESULT` (the sequence number is the chunk index), the chunk count and
+//! the data. The server resends all chunks until the client answers with a
+//! `FLAG_RESULT_ACK` header or the retries run out.
+//!
+//! Every round of chunks starts with a `FLAG_ENDPOINT` header with the address
+//! the server received the test from, which tells the client how a NAT on
+//! the way rewrote its address, like a STUN binding response.
+
+use std::{
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    errors::UdpOptError,
+    result::TestResult,
+    runtime::{AsyncDatagram, timeout},
+    socket::DatagramSocket,
+    utils::udp_data::{
+        FLAG_ENDPOINT, FLAG_RESULT, FLAG_RESULT_ACK, HEADER_SIZE, UdpHeader, now_micros,
+    },
+};
+
+/// Maximum number of result bytes per datagram
+pub(crate) const CHUNK_SIZE: usize = 1024;
+/// How many times the server sends the chunks without getting an ACK
+const RESULT_RETRIES: u32 = 5;
+/// How long the server waits for the ACK after each round
+const ACK_WAIT: Duration = Duration::from_millis(200);
+/// Size of the chunk count that follows the header
+const COUNT_SIZE: usize = 4;
+/// Most chunks a result takes, a header announcing more is forged or broken
+const MAX_CHUNKS: usize = TestResult::MAX_ENCODED_SIZE.div_ceil(CHUNK_SIZE);
+/// Size of the address that follows the header of an endpoint echo: the IP,
+/// IPv4 mapped to IPv6, and the port
+const ENDPOINT_SIZE: usize = 16 + 2;
+
+/// Splits `payload` into result datagrams of at most `chunk_size` data bytes
+pub(crate) fn make_chunks(payload: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+    let count = payload.len().div_ceil(chunk_size).max(1);
+    (0..count)
+        .map(|i| {
+            let data = &payload
+                [(i * chunk_size).min(payload.len())..((i + 1) * chunk_size).min(payload.len())];
+            let mut packet = vec![0u8; HEADER_SIZE + COUNT_SIZE + data.len()];
+            let (sec, usec) = now_micros();
+            UdpHeader::new(i as u64, sec, usec, FLAG_RESULT).write_header(&mut packet);
+            packet[HEADER_SIZE..HEADER_SIZE + COUNT_SIZE]
+                .copy_from_slice(&(count as u32).to_be_bytes());
+            packet[HEADER_SIZE + COUNT_SIZE..].copy_from_slice(data);
+            packet
+        })
+        .collect()
+}
+
+/// Collects result chunks until the whole payload is there
+#[derive(Debug, Default)]
+pub(crate) struct ChunkAssembler {
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+impl ChunkAssembler {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a received datagram, returns the payload once all chunks arrived.
+    ///
+    /// Datagrams that are not result chunks, or announce more chunks than
+    /// the largest result takes, are ignored.
+    pub(crate) fn push(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        if packet.len() < HEADER_SIZE + COUNT_SIZE {
+            return None;
+        }
+        let header = UdpHeader::parse(packet).ok()?;
+        if header.flags != FLAG_RESULT {
+            return None;
+        }
+        let count = u32::from_be_bytes(
+            packet[HEADER_SIZE..HEADER_SIZE + COUNT_SIZE]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        if count > MAX_CHUNKS {
+            return None;
+        }
+        if self.chunks.len() != count {
+            // first chunk, or chunks of another result
+            self.chunks = vec![None; count];
+        }
+        let slot = self.chunks.get_mut(header.seq as usize)?;
+        *slot = Some(packet[HEADER_SIZE + COUNT_SIZE..].to_vec());
+
+        if self.chunks.iter().all(Option::is_some) {
+            Some(self.chunks.iter().flatten().flatten().copied().collect())
+        } else {
+            None
+        }
+    }
+}
+
+/// The echo of the address `peer` the test came from
+fn endpoint_packet(peer: SocketAddr) -> [u8; HEADER_SIZE + ENDPOINT_SIZE] {
+    let mut packet = [0u8; HEADER_SIZE + ENDPOINT_SIZE];
+    let (sec, usec) = now_micros();
+    UdpHeader::new(0, sec, usec, FLAG_ENDPOINT).write_header(&mut packet);
+    let ip = match peer.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    packet[HEADER_SIZE..HEADER_SIZE + 16].copy_from_slice(&ip.octets());
+    packet[HEADER_SIZE + 16..].copy_from_slice(&peer.port().to_be_bytes());
+    packet
+}
+
+/// The address echoed by `packet`, `None` if it is not an endpoint echo
+fn parse_endpoint(packet: &[u8]) -> Option<SocketAddr> {
+    if packet.len() != HEADER_SIZE + ENDPOINT_SIZE
+        || UdpHeader::parse(packet).ok()?.flags != FLAG_ENDPOINT
+    {
+        return None;
+    }
+    let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[HEADER_SIZE..HEADER_SIZE + 16]).ok()?);
+    let port = u16::from_be_bytes(packet[HEADER_SIZE + 16..].try_into().ok()?);
+    let ip = ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4);
+    Some(SocketAddr::new(ip, port))
+}
+
+fn ack_packet() -> [u8; HEADER_SIZE] {
+    let mut ack = [0u8; HEADER_SIZE];
+    let (sec, usec) = now_micros();
+    UdpHeader::new(0, sec, usec, FLAG_RESULT_ACK).write_header(&mut ack);
+    ack
+}
+
+fn is_ack(packet: &[u8]) -> bool {
+    UdpHeader::parse(packet).is_ok_and(|h| h.flags == FLAG_RESULT_ACK)
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+fn decode(payload: &[u8]) -> Result<TestResult, UdpOptError> {
+    TestResult::from_bytes(payload)
+        .ok_or_else(|| UdpOptError::ProtocolViolation("malformed remote results".into()))
+}
+
+/// Sends `result` to `peer` until it is acknowledged.
+///
+/// Returns `false` if the client never acknowledged the results.
+pub(crate) fn send_results<S: DatagramSocket>(
+    sock: &S,
+    peer: SocketAddr,
+    result: &TestResult,
+) -> Result<bool, UdpOptError> {
+    let chunks = make_chunks(&result.to_bytes(), CHUNK_SIZE);
+    let endpoint = endpoint_packet(peer);
+    let mut buf = [0u8; 2048];
+    sock.set_read_timeout(Some(ACK_WAIT))
+        .map_err(|_| UdpOptError::SocketTimeout)?;
+
+    for _ in 0..RESULT_RETRIES {
+        for packet in std::iter::once(&endpoint[..]).chain(chunks.iter().map(Vec::as_slice)) {
+            sock.send_to(packet, peer)
+                .map_err(UdpOptError::send_failed(Some(peer), packet.len()))?;
+        }
+        let deadline = Instant::now() + ACK_WAIT;
+        while Instant::now() < deadline {
+            match sock.recv_from(&mut buf) {
+                Ok((len, from)) if from == peer && is_ack(&buf[..len]) => return Ok(true),
+                // late data packets or other peers
+                Ok(_) => {}
+                Err(e) if is_timeout(&e) || e.kind() == ErrorKind::Interrupted => break,
+                Err(e) => return Err(UdpOptError::RecvFailed(e)),
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Async version of [`send_results`].
+pub(crate) async fn send_results_async<S: AsyncDatagram>(
+    sock: &S,
+    peer: SocketAddr,
+    result: &TestResult,
+) -> Result<bool, UdpOptError> {
+    let chunks = make_chunks(&result.to_bytes(), CHUNK_SIZE);
+    let endpoint = endpoint_packet(peer);
+    let mut buf = [0u8; 2048];
+
+    for _ in 0..RESULT_RETRIES {
+        for packet in std::iter::once(&endpoint[..]).chain(chunks.iter().map(Vec::as_slice)) {
+            sock.send_to(packet, peer)
+                .await
+                .map_err(UdpOptError::send_failed(Some(peer), packet.len()))?;
+        }
+        let deadline = Instant::now() + ACK_WAIT;
+        while let Some(res) = timeout::<S, _>(
+            deadline.saturating_duration_since(Instant::now()),
+            sock.recv_from(&mut buf),
+        )
+        .await
+        {
+            match res {
+                Ok((len, from)) if from == peer && is_ack(&buf[..len]) => return Ok(true),
+                Ok(_) => {}
+                Err(e) => return Err(UdpOptError::RecvFailed(e)),
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Waits up to `timeout` for the server's results on the connected `sock`.
+///
+/// Returns the results, `None` if they did not arrive in time, and the
+/// address the server echoed, `None` if it sent none.
+pub(crate) fn recv_results<S: DatagramSocket>(
+    sock: &S,
+    timeout: Duration,
+) -> Result<(Option<TestResult>, Option<SocketAddr>), UdpOptError> {
+    let previous = sock
+        .read_timeout()
+        .map_err(|_| UdpOptError::SocketTimeout)?;
+    let mut assembler = ChunkAssembler::new();
+    let mut endpoint = None;
+    let mut buf = [0u8; 2048];
+    let deadline = Instant::now() + timeout;
+
+    let res = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break Ok(None);
+        }
+        sock.set_read_timeout(Some(remaining))
+            .map_err(|_| UdpOptError::SocketTimeout)?;
+        match sock.recv(&mut buf) {
+            Ok(len) => {
+                if let Some(addr) = parse_endpoint(&buf[..len]) {
+                    endpoint = Some(addr);
+                } else if let Some(payload) = assembler.push(&buf[..len]) {
+                    // the ACK may get lost, the server gives up after its retries anyway
+                    for _ in 0..3 {
+                        let _ = sock.send(&ack_packet());
+                    }
+                    break decode(&payload).map(Some);
+                }
+            }
+            Err(e) if is_timeout(&e) => break Ok(None),
+            // nobody is listening on the server side
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => break Ok(None),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => break Err(UdpOptError::RecvFailed(e)),
+        }
+    };
+    sock.set_read_timeout(previous)
+        .map_err(|_| UdpOptError::SocketTimeout)?;
+    Ok((res?, endpoint))
+}
+
+/// Async version of [`recv_results`].
+pub(crate) async fn recv_results_async<S: AsyncDatagram>(
+    sock: &S,
+    wait: Duration,
+) -> Result<(Option<TestResult>, Option<SocketAddr>), UdpOptError> {
+    let mut assembler = ChunkAssembler::new();
+    let mut endpoint = None;
+    let mut buf = [0u8; 2048];
+    let deadline = Instant::now() + wait;
+
+    while let Some(res) = timeout::<S, _>(
+        deadline.saturating_duration_since(Instant::now()),
+        sock.recv(&mut buf),
+    )
+    .await
+    {
+        match res {
+            Ok(len) => {
+                if let Some(addr) = parse_endpoint(&buf[..len]) {
+                    endpoint = Some(addr);
+                } else if let Some(payload) = assembler.push(&buf[..len]) {
+                    for _ in 0..3 {
+                        let _ = sock.send(&ack_packet()).await;
+                    }
+                    return Ok((Some(decode(&payload)?), endpoint));
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => return Ok((None, endpoint)),
+            Err(e) => return Err(UdpOptError::RecvFailed(e)),
+        }
+    }
+    Ok((None, endpoint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_chunks_reassemble_out_of_order() {
+        let payload: Vec<u8> = (0..250u32).map(|i| i as u8).collect();
+        let chunks = make_chunks(&payload, 100);
+        assert_eq!(chunks.len(), 3);
+
+        let mut assembler = ChunkAssembler::new();
+        assert_eq!(assembler.push(&chunks[2]), None);
+        assert_eq!(assembler.push(&chunks[0]), None);
+        // duplicates are harmless
+        assert_eq!(assembler.push(&chunks[0]), None);
+        assert_eq!(assembler.push(&chunks[1]), Some(payload));
+    }
+
+    #[test]
+    fn test_assembler_ignores_other_packets() {
+        let mut assembler = ChunkAssembler::new();
+        let data = vec![0u8; HEADER_SIZE + 100];
+        assert_eq!(assembler.push(&data), None);
+        assert!(is_ack(&ack_packet()));
+        assert!(!is_ack(&data));
+    }
+
+    #[test]
+    fn test_assembler_ignores_forged_counts() {
+        let mut assembler = ChunkAssembler::new();
+        let mut chunk = make_chunks(&[1, 2, 3], 100).remove(0);
+        chunk[HEADER_SIZE..HEADER_SIZE + COUNT_SIZE].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(assembler.push(&chunk), None);
+        assert!(assembler.chunks.is_empty());
+
+        // the largest result still gets through
+        let mut result = TestResult::from_intervals(&[]);
+        result.percentiles = vec![Default::default(); TestResult::MAX_ENCODED_PERCENTILES + 10];
+        let payload = result.to_bytes();
+        assert_eq!(payload.len(), TestResult::MAX_ENCODED_SIZE);
+        let chunks = make_chunks(&payload, CHUNK_SIZE);
+        assert_eq!(chunks.len(), MAX_CHUNKS);
+        let received = chunks.iter().find_map(|c| assembler.push(c));
+        assert_eq!(received, Some(payload));
+    }
+
+    #[test]
+    fn test_send_and_receive_results() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let peer = client.local_addr().unwrap();
+
+        let result = TestResult::from_intervals(&[crate::IntervalResult {
+            received: 10,
+            bytes: 1000,
+            time: Duration::from_secs(1),
+            ..Default::default()
+        }
+        .with_bitrate()]);
+        let sent = result.clone();
+        let handle = std::thread::spawn(move || send_results(&server, peer, &sent).unwrap());
+
+        let (received, endpoint) = recv_results(&client, Duration::from_secs(2)).unwrap();
+        assert_eq!(received, Some(result));
+        assert_eq!(endpoint, Some(peer));
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn test_endpoint_roundtrip() {
+        for addr in ["198.51.100.7:40000", "[2001:db8::5]:1"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            assert_eq!(parse_endpoint(&endpoint_packet(addr)), Some(addr));
+        }
+        // a result chunk or an ACK is no echo
+        assert_eq!(parse_endpoint(&ack_packet()), None);
+        let chunk = &make_chunks(&[0u8; ENDPOINT_SIZE - COUNT_SIZE], 100)[0];
+        assert_eq!(chunk.len(), HEADER_SIZE + ENDPOINT_SIZE);
+        assert_eq!(parse_endpoint(chunk), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! # Thread scheduling
+//!
+//! Pins the thread running a test to one CPU core and/or moves it to the
+//! `SCHED_FIFO` realtime class, so the OS scheduler does not add its own
+//! jitter to the pacing and arrival timestamps on busy hosts.
+//! Only Linux is supported, other systems return [`io::ErrorKind::Unsupported`].
+
+use std::io;
+
+/// `SCHED_FIFO` priority of the test thread, in the middle of the 1..=99 range
+/// so kernel threads with higher priorities still run
+#[cfg(target_os = "linux")]
+const FIFO_PRIORITY: i32 = 50;
+
+/// Number of cores a `cpu_set_t` can hold
+#[cfg(target_os = "linux")]
+const CPU_SETSIZE: usize = 1024;
+
+#[cfg(target_os = "linux")]
+const SCHED_FIFO: i32 = 1;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct SchedParam {
+    sched_priority: i32,
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" {
+    /// pid 0 is the calling thread
+    fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const u64) -> i32;
+    fn sched_setscheduler(pid: i32, policy: i32, param: *const SchedParam) -> i32;
+}
+
+/// Restricts the calling thread to CPU `core`.
+///
+/// # Errors
+/// - `InvalidInput` if `core` does not exist in a `cpu_set_t`
+/// - the OS error if the core is offline or not allowed for this process
+pub(crate) fn pin_current_thread(core: usize) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        if core >= CPU_SETSIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("core {core} out of range"),
+            ));
+        }
+        let mut mask = [0u64; CPU_SETSIZE / 64];
+        mask[core / 64] |= 1 << (core % 64);
+        // SAFETY: `mask` is a live array of the size given, only read
+        let rc = unsafe { sched_setaffinity(0, std::mem::size_of_val(&mask), mask.as_ptr()) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = core;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Moves the calling thread to the `SCHED_FIFO` realtime class.
+///
+/// # Errors
+/// The OS error, usually `PermissionDenied` without `CAP_SYS_NICE` or an
+/// `RLIMIT_RTPRIO` limit.
+pub(crate) fn set_realtime_priority() -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let param = SchedParam {
+            sched_priority: FIFO_PRIORITY,
+        };
+        // SAFETY: `param` is a live `struct sched_param`, only read
+        if unsafe { sched_setscheduler(0, SCHED_FIFO, &param) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Scheduling requested for the thread running a test
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ThreadTuning {
+    /// Core to pin the thread to
+    pub(crate) core: Option<usize>,
+    /// Run under `SCHED_FIFO`
+    pub(crate) realtime: bool,
+}
+
+impl ThreadTuning {
+    /// Applies the settings to the calling thread.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        if let Some(core) = self.core {
+            pin_current_thread(core)?;
+        }
+        if self.realtime {
+            set_realtime_priority()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// First core this process may run on, from `/proc/self/status`
+    fn allowed_core() -> usize {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let list = status
+            .lines()
+            .find_map(|l| l.strip_prefix("Cpus_allowed_list:"))
+            .unwrap();
+        list.trim()
+            .split([',', '-'])
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pin_current_thread() {
+        let core = allowed_core();
+        std::thread::spawn(move || pin_current_thread(core))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_pin_out_of_range() {
+        let err = pin_current_thread(CPU_SETSIZE).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_default_tuning_is_a_no_op() {
+        ThreadTuning::default().apply().unwrap();
+    }
+}
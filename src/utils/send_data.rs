@@ -0,0 +1,490 @@
+//! # Client transmit statistics
+//!
+//! [`SendData`] is the client-side counterpart of [`super::udp_data::UdpData`]:
+//! it tracks what the client actually sent, per interval and in total, along
+//! with pacing accuracy, and turns it into a [`ClientReport`].
+
+use std::time::Duration;
+
+use crate::fault::FaultStats;
+use crate::histogram::Histogram;
+use crate::result::{
+    ClientReport, Fragmentation, RateCompliance, SleepCalibration, UnreachableEpisode,
+};
+use crate::utils::net_utils::{ClientInterval, ClientProgress, packet_rate};
+
+/// Tracks the client's transmit statistics for one test
+#[derive(Debug, Clone)]
+pub(crate) struct SendData {
+    /// Packets sent since the test started (FIN excluded)
+    packets_sent: u64,
+    /// Bytes sent since the test started (FIN excluded)
+    bytes_sent: u64,
+    /// Sends that failed with a transient error
+    send_failures: u64,
+    /// Sends refused by a full send buffer, part of `send_failures`
+    would_block: u64,
+    /// Sends refused as longer than the path MTU, part of `send_failures`
+    too_big: u64,
+    /// IP fragments of the packets sent, while the path MTU is known
+    fragmentation: Option<Fragmentation>,
+    /// Whether the packets go out over IPv6, for their header sizes
+    ipv6: bool,
+    /// Sends retried after a transient error
+    send_retries: u64,
+    /// Episodes of sends refused by an ICMP unreachable
+    unreachable: Vec<UnreachableEpisode>,
+    /// Packets sent in the current interval
+    interval_packets: u64,
+    /// Bytes sent in the current interval
+    interval_bytes: u64,
+    /// Completed intervals
+    intervals: Vec<ClientInterval>,
+    /// How late each packet left compared to its pacing target (µs)
+    pacing_error: Histogram,
+    /// How far each gap between two packets was from the scheduled one (µs)
+    interval_error: Histogram,
+    /// How late the previous packet left, `None` before the first one
+    last_error: Option<Duration>,
+    /// Sleep accuracy the pacer was calibrated with
+    calibration: Option<SleepCalibration>,
+    /// Length of a pacing slot, zero without a rate target
+    slot: Duration,
+    /// Packets sent in every slot
+    per_slot: u64,
+    /// Packets intended under the earlier rates of the test
+    intended_before: u64,
+    /// When the current rate was set, from the start of the test
+    paced_since: Duration,
+    /// How late the last packet left, the sender's lag behind its schedule
+    drift: Duration,
+    /// Intervals closed more than one slot behind schedule
+    behind_intervals: u64,
+}
+
+impl SendData {
+    /// Creates a new `SendData` instance
+    pub(crate) fn new() -> Self {
+        Self {
+            packets_sent: 0,
+            bytes_sent: 0,
+            send_failures: 0,
+            would_block: 0,
+            too_big: 0,
+            fragmentation: None,
+            ipv6: false,
+            send_retries: 0,
+            unreachable: Vec::new(),
+            interval_packets: 0,
+            interval_bytes: 0,
+            intervals: Vec::new(),
+            pacing_error: Histogram::new(),
+            interval_error: Histogram::new(),
+            last_error: None,
+            calibration: None,
+            slot: Duration::ZERO,
+            per_slot: 1,
+            intended_before: 0,
+            paced_since: Duration::ZERO,
+            drift: Duration::ZERO,
+            behind_intervals: 0,
+        }
+    }
+
+    /// Sets the pacing schedule the intended packet counts are derived from
+    ///
+    /// # Parameters
+    /// - `slot`: length of a pacing slot
+    /// - `per_slot`: packets sent in every slot
+    pub(crate) fn with_pacing(mut self, slot: Duration, per_slot: u64) -> Self {
+        self.slot = slot;
+        self.per_slot = per_slot;
+        self
+    }
+
+    /// Sets the sleep accuracy the pacer was calibrated with
+    pub(crate) fn with_calibration(mut self, calibration: SleepCalibration) -> Self {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    /// Counts the IP fragments of the packets sent over a path MTU of `mtu`,
+    /// to a peer reached over IPv6 or not
+    pub(crate) fn with_path_mtu(mut self, mtu: usize, ipv6: bool) -> Self {
+        self.fragmentation = Some(Fragmentation {
+            path_mtu: mtu,
+            ..Fragmentation::default()
+        });
+        self.ipv6 = ipv6;
+        self
+    }
+
+    /// Counts the fragments of the next packets over a path MTU of `mtu`,
+    /// once the host learned a new one
+    pub(crate) fn set_path_mtu(&mut self, mtu: usize) {
+        if let Some(fragmentation) = self.fragmentation.as_mut() {
+            fragmentation.path_mtu = mtu;
+        }
+    }
+
+    /// Switches to a new schedule `elapsed` into the test, after a rate change
+    pub(crate) fn set_pacing(&mut self, slot: Duration, per_slot: u64, elapsed: Duration) {
+        self.intended_before += self.intended_packets(elapsed.saturating_sub(self.paced_since));
+        self.paced_since = elapsed;
+        self.slot = slot;
+        self.per_slot = per_slot;
+    }
+
+    /// Packets the schedule asks for in `time`, 0 without a rate target
+    fn intended_packets(&self, time: Duration) -> u64 {
+        if self.slot.is_zero() {
+            return 0;
+        }
+        (time.as_secs_f64() / self.slot.as_secs_f64() * self.per_slot as f64).round() as u64
+    }
+
+    /// Records a successfully sent packet
+    ///
+    /// # Parameters
+    /// - `len`: number of bytes sent
+    /// - `pacing_error`: how late the packet left compared to its target time
+    pub(crate) fn record_sent(&mut self, len: usize, pacing_error: Duration) {
+        self.packets_sent += 1;
+        self.bytes_sent += len as u64;
+        self.interval_packets += 1;
+        self.interval_bytes += len as u64;
+        self.pacing_error.record(pacing_error.as_micros() as u64);
+        // both are late against their own target, the difference is the
+        // gap error whatever the schedule
+        if let Some(last) = self.last_error {
+            let error = pacing_error.abs_diff(last);
+            self.interval_error.record(error.as_micros() as u64);
+        }
+        self.last_error = Some(pacing_error);
+        self.drift = pacing_error;
+        if let Some(fragmentation) = self.fragmentation.as_mut() {
+            fragmentation.record(len, self.ipv6);
+        }
+    }
+
+    /// Packets sent since the test started
+    pub(crate) fn packets_sent(&self) -> u64 {
+        self.packets_sent
+    }
+
+    /// Bytes sent since the test started
+    pub(crate) fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Records a send retried after a transient error
+    pub(crate) fn record_retry(&mut self) {
+        self.send_retries += 1;
+    }
+
+    /// Records a send that failed with a transient error
+    pub(crate) fn record_failure(&mut self) {
+        self.send_failures += 1;
+    }
+
+    /// Records a send refused because the send buffer was full
+    pub(crate) fn record_would_block(&mut self) {
+        self.record_failure();
+        self.would_block += 1;
+    }
+
+    /// Records a send refused as longer than the path MTU
+    pub(crate) fn record_too_big(&mut self) {
+        self.record_failure();
+        self.too_big += 1;
+    }
+
+    /// Records a send refused by an ICMP unreachable `at` this time into the
+    /// test, part of the last episode if it is less than `grace` old. Returns
+    /// whether the episode is still shorter than `grace`.
+    pub(crate) fn record_unreachable(&mut self, at: Duration, grace: Duration) -> bool {
+        self.record_failure();
+        match self.unreachable.last_mut() {
+            Some(episode) if at.saturating_sub(episode.start + episode.duration) < grace => {
+                episode.duration = at.saturating_sub(episode.start);
+                episode.refusals += 1;
+                episode.duration < grace
+            }
+            _ => {
+                self.unreachable.push(UnreachableEpisode {
+                    start: at,
+                    duration: Duration::ZERO,
+                    refusals: 1,
+                });
+                !grace.is_zero()
+            }
+        }
+    }
+
+    /// Closes the current interval and returns it
+    pub(crate) fn close_interval(&mut self, time: Duration) -> ClientInterval {
+        let mut interval = ClientInterval::new(self.interval_packets, self.interval_bytes, time);
+        interval.intended_packets = self.intended_packets(time);
+        interval.drift = self.drift;
+        if !self.slot.is_zero() && self.drift > self.slot {
+            self.behind_intervals += 1;
+        }
+        self.intervals.push(interval);
+        self.interval_packets = 0;
+        self.interval_bytes = 0;
+        interval
+    }
+
+    /// Cumulative progress since the start of the test
+    pub(crate) fn progress(&self, elapsed: Duration) -> ClientProgress {
+        ClientProgress::new(elapsed, self.packets_sent, self.bytes_sent)
+    }
+
+    /// Finishes the test, closing the last partial interval
+    ///
+    /// # Parameters
+    /// - `elapsed`: total test duration
+    /// - `last_interval`: duration of the last, partial interval
+    pub(crate) fn into_report(
+        mut self,
+        elapsed: Duration,
+        last_interval: Duration,
+    ) -> ClientReport {
+        if self.interval_packets > 0 {
+            self.close_interval(last_interval);
+        }
+        let secs = elapsed.as_secs_f64();
+        ClientReport {
+            packets_sent: self.packets_sent,
+            bytes_sent: self.bytes_sent,
+            send_failures: self.send_failures,
+            send_retries: self.send_retries,
+            would_block: self.would_block,
+            too_big: self.too_big,
+            fragmentation: self.fragmentation,
+            duration: elapsed,
+            bitrate_bps: if secs > 0.0 {
+                (self.bytes_sent * 8) as f64 / secs
+            } else {
+                0.0
+            },
+            pps: packet_rate(self.packets_sent, elapsed),
+            compliance: RateCompliance {
+                intended_packets: self.intended_before
+                    + self.intended_packets(elapsed.saturating_sub(self.paced_since)),
+                achieved_packets: self.packets_sent,
+                drift: self.drift,
+                behind_intervals: self.behind_intervals,
+            },
+            faults: FaultStats::default(),
+            unreachable: self.unreachable,
+            session: 0,
+            intervals: self.intervals,
+            pacing_error: self.pacing_error,
+            interval_error: self.interval_error,
+            calibration: self.calibration,
+            remote: None,
+            public_endpoint: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result::Overhead;
+
+    #[test]
+    fn test_send_data_report() {
+        let mut data = SendData::new();
+        for _ in 0..10 {
+            data.record_sent(1000, Duration::from_micros(50));
+        }
+        data.record_failure();
+        data.record_would_block();
+        let first = data.close_interval(Duration::from_secs(1));
+        assert_eq!(first.packets_sent, 10);
+        assert_eq!(first.bitrate_bps, 80_000.0);
+
+        for _ in 0..5 {
+            data.record_sent(1000, Duration::from_micros(150));
+        }
+        assert_eq!(data.progress(Duration::from_secs(2)).packets_sent, 15);
+
+        let report = data.into_report(Duration::from_millis(1500), Duration::from_millis(500));
+        assert_eq!(report.packets_sent, 15);
+        assert_eq!(report.bytes_sent, 15_000);
+        assert_eq!(report.send_failures, 2);
+        assert_eq!(report.would_block, 1);
+        assert_eq!(report.intervals.len(), 2);
+        assert_eq!(report.intervals[1].bitrate_bps, 80_000.0);
+        assert_eq!(report.bitrate_bps, 80_000.0);
+        assert_eq!(report.pps, 10.0);
+        assert_eq!(report.pacing_error.count(), 15);
+        assert_eq!(report.pacing_error.max(), 150);
+        // no pacing schedule, no target
+        assert_eq!(report.compliance.intended_packets, 0);
+        assert_eq!(report.compliance.achieved_ratio(), 1.0);
+        // the socket told no path MTU
+        assert!(report.fragmentation.is_none());
+    }
+
+    #[test]
+    fn test_fragment_accounting() {
+        let mut data = SendData::new().with_path_mtu(1500, false);
+        // 1472 bytes fill a 1500 byte packet with the IPv4 and UDP headers
+        data.record_sent(1472, Duration::ZERO);
+        data.record_sent(2000, Duration::ZERO);
+        // an ICMP "fragmentation needed" lowered it
+        data.set_path_mtu(1280);
+        data.record_sent(1472, Duration::ZERO);
+        data.record_too_big();
+        let report = data.into_report(Duration::from_secs(1), Duration::from_secs(1));
+
+        let fragmentation = report.fragmentation.unwrap();
+        assert_eq!(fragmentation.path_mtu, 1280);
+        assert_eq!(fragmentation.fragmented, 2);
+        assert_eq!(fragmentation.max_fragments, 2);
+        assert_eq!(fragmentation.ip_packets, 5);
+        assert_eq!(
+            fragmentation.ip_bytes,
+            (1472 + 2000 + 1472 + 3 * 8) as u64 + 5 * 20
+        );
+        assert_eq!((report.too_big, report.send_failures), (1, 1));
+        // every fragment on the wire
+        let throughput = report.throughput(Overhead::ipv4().link(0));
+        assert_eq!(throughput.wire_bytes, fragmentation.ip_bytes);
+        assert_eq!(throughput.goodput_bytes, (1472 + 2000 + 1472) - 3 * 52);
+        // the headers of the two extra fragments included
+        assert_eq!(
+            throughput.overhead_per_packet(),
+            (3 * 52 + 5 * 20 + 3 * 8) as f64 / 3.0
+        );
+
+        // IPv6 takes 48 bytes of header, 8 more in every fragment
+        assert_eq!(Fragmentation::fragments(1452, 1500, true), 1);
+        assert_eq!(Fragmentation::fragments(1453, 1500, true), 2);
+        assert_eq!(Fragmentation::fragments(8972, 9000, false), 1);
+        assert_eq!(Fragmentation::fragments(65_000, 1500, false), 44);
+        assert_eq!(Fragmentation::ip_bytes(2000, 2, true), 2008 + 2 * 48);
+    }
+
+    #[test]
+    fn test_interval_error() {
+        let mut data = SendData::new().with_calibration(SleepCalibration {
+            overshoot: Duration::from_micros(80),
+            threshold: Duration::from_micros(200),
+        });
+        // steadily late keeps the gaps on schedule, a jump shows in two gaps
+        for late in [100, 100, 100, 400, 100] {
+            data.record_sent(100, Duration::from_micros(late));
+        }
+        let report = data.into_report(Duration::from_secs(1), Duration::ZERO);
+        assert_eq!(report.interval_error.count(), 4);
+        assert_eq!(report.interval_error.max(), 300);
+        assert_eq!(report.interval_error.min(), 0);
+        assert_eq!(
+            report.calibration.unwrap().overshoot,
+            Duration::from_micros(80)
+        );
+    }
+
+    #[test]
+    fn test_unreachable_episodes() {
+        let ms = Duration::from_millis;
+        let grace = ms(100);
+        let mut data = SendData::new();
+        // a restarting server: refusals 50 ms apart, then silence
+        for at in [10, 60, 100] {
+            assert!(data.record_unreachable(ms(at), grace));
+        }
+        // a new episode, outlasting the grace
+        assert!(data.record_unreachable(ms(500), grace));
+        assert!(data.record_unreachable(ms(590), grace));
+        assert!(!data.record_unreachable(ms(680), grace));
+
+        // no grace at all
+        assert!(!SendData::new().record_unreachable(ms(10), Duration::ZERO));
+
+        let report = data.into_report(ms(700), Duration::ZERO);
+        assert_eq!(report.send_failures, 6);
+        assert_eq!(
+            report.unreachable,
+            [
+                UnreachableEpisode {
+                    start: ms(10),
+                    duration: ms(90),
+                    refusals: 3,
+                },
+                UnreachableEpisode {
+                    start: ms(500),
+                    duration: ms(180),
+                    refusals: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_change() {
+        // 10 ms slots for 1 s, then 1 ms slots for 2 s
+        let mut data = SendData::new().with_pacing(Duration::from_millis(10), 1);
+        data.set_pacing(Duration::from_millis(1), 1, Duration::from_secs(1));
+        let report = data.into_report(Duration::from_secs(3), Duration::ZERO);
+        assert_eq!(report.compliance.intended_packets, 100 + 2000);
+    }
+
+    #[test]
+    fn test_send_limit() {
+        use crate::result::SendLimit;
+
+        let run = |sent: u64, would_block: u64| {
+            let mut data = SendData::new().with_pacing(Duration::from_millis(10), 1);
+            for _ in 0..sent {
+                data.record_sent(100, Duration::ZERO);
+            }
+            for _ in 0..would_block {
+                data.record_would_block();
+            }
+            data.into_report(Duration::from_secs(1), Duration::ZERO)
+                .limit()
+        };
+        assert_eq!(run(100, 0), None);
+        assert_eq!(run(60, 0), Some(SendLimit::Sender));
+        assert_eq!(run(60, 40), Some(SendLimit::Network));
+
+        // unpaced, nothing but the sender held it
+        let mut unpaced = SendData::new();
+        unpaced.record_sent(100, Duration::ZERO);
+        let report = unpaced.into_report(Duration::from_secs(1), Duration::ZERO);
+        assert_eq!(report.limit(), Some(SendLimit::Sender));
+    }
+
+    #[test]
+    fn test_rate_compliance() {
+        // 1 ms slots: 1000 packets intended per second
+        let mut data = SendData::new().with_pacing(Duration::from_millis(1), 1);
+        for _ in 0..1000 {
+            data.record_sent(100, Duration::from_micros(20));
+        }
+        let on_time = data.close_interval(Duration::from_secs(1));
+        assert_eq!(on_time.intended_packets, 1000);
+        assert_eq!(on_time.drift, Duration::from_micros(20));
+
+        // the sender only manages half the rate and falls 500 ms behind
+        for i in 1..=500 {
+            data.record_sent(100, Duration::from_millis(i));
+        }
+        let behind = data.close_interval(Duration::from_secs(1));
+        assert_eq!(behind.intended_packets, 1000);
+        assert_eq!(behind.packets_sent, 500);
+
+        let report = data.into_report(Duration::from_secs(2), Duration::ZERO);
+        let compliance = report.compliance;
+        assert_eq!(compliance.intended_packets, 2000);
+        assert_eq!(compliance.achieved_packets, 1500);
+        assert_eq!(compliance.drift, Duration::from_millis(500));
+        assert_eq!(compliance.behind_intervals, 1);
+        assert_eq!(compliance.achieved_ratio(), 0.75);
+    }
+}
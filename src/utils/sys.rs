@@ -0,0 +1,198 @@
+//! # Socket system calls
+//!
+//! The libc calls the crate makes on sockets and descriptors, declared once
+//! here behind safe wrappers: socket options, `recvmsg` with its control
+//! messages, the close-on-exec flag and interface indexes. The wrappers take
+//! raw descriptors, a closed or foreign one fails with `EBADF` or
+//! `ENOTSOCK` like any other OS error.
+//! Linux only.
+
+use std::{
+    ffi::{CStr, c_char, c_void},
+    io,
+};
+
+const F_GETFD: i32 = 1;
+const F_SETFD: i32 = 2;
+const FD_CLOEXEC: i32 = 1;
+
+#[repr(C)]
+struct IoVec {
+    iov_base: *mut c_void,
+    iov_len: usize,
+}
+
+#[repr(C)]
+struct MsgHdr {
+    msg_name: *mut c_void,
+    msg_namelen: u32,
+    msg_iov: *mut IoVec,
+    msg_iovlen: usize,
+    msg_control: *mut c_void,
+    msg_controllen: usize,
+    msg_flags: i32,
+}
+
+unsafe extern "C" {
+    fn setsockopt(fd: i32, level: i32, name: i32, value: *const c_void, len: u32) -> i32;
+    fn getsockopt(fd: i32, level: i32, name: i32, value: *mut c_void, len: *mut u32) -> i32;
+    fn recvmsg(fd: i32, msg: *mut MsgHdr, flags: i32) -> isize;
+    fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+    fn if_nametoindex(name: *const c_char) -> u32;
+}
+
+/// Sets a socket option to `value`.
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn set_option<T>(fd: i32, level: i32, name: i32, value: &T) -> io::Result<()> {
+    // SAFETY: `value` points to a live `T` of the length given, which the
+    // kernel only reads
+    let rc = unsafe {
+        setsockopt(
+            fd,
+            level,
+            name,
+            (value as *const T).cast(),
+            size_of::<T>() as u32,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sets an `int` socket option.
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn set_int(fd: i32, level: i32, name: i32, value: i32) -> io::Result<()> {
+    set_option(fd, level, name, &value)
+}
+
+/// Reads an `int` socket option.
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn get_int(fd: i32, level: i32, name: i32) -> io::Result<i32> {
+    let mut value = 0i32;
+    let mut len = size_of::<i32>() as u32;
+    // SAFETY: `value` and `len` are live locals, `len` holding the size of
+    // `value`, the most the kernel writes to it
+    let rc = unsafe { getsockopt(fd, level, name, (&mut value as *mut i32).cast(), &mut len) };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Receives a datagram into `buf`, its source address into `name` and the
+/// control messages the kernel attached to it into `control`; returns the
+/// length of the datagram and the parts of `name` and `control` written.
+///
+/// The buffers are words so the kernel fills them aligned like a
+/// `sockaddr_storage` and a `cmsghdr`.
+///
+/// # Errors
+/// The OS error, `WouldBlock` when a timeout or non-blocking receive finds
+/// nothing.
+pub(crate) fn recv_msg<'a>(
+    fd: i32,
+    buf: &mut [u8],
+    name: &'a mut [u64],
+    control: &'a mut [u64],
+) -> io::Result<(usize, &'a [u8], &'a [u8])> {
+    let mut iov = IoVec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut msg = MsgHdr {
+        msg_name: name.as_mut_ptr().cast(),
+        msg_namelen: size_of_val(name) as u32,
+        msg_iov: &mut iov,
+        msg_iovlen: 1,
+        msg_control: control.as_mut_ptr().cast(),
+        msg_controllen: size_of_val(control),
+        msg_flags: 0,
+    };
+    // SAFETY: every pointer of `msg` points to a live buffer of the length
+    // next to it, borrowed mutably for the call
+    let len = unsafe { recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let name_len = (msg.msg_namelen as usize).min(size_of_val(name));
+    let control_len = msg.msg_controllen.min(size_of_val(control));
+    // SAFETY: the bytes of the words, which any bit pattern is, up to their
+    // size; the borrows keep them alive and unchanged
+    let (name, control) = unsafe {
+        (
+            std::slice::from_raw_parts(name.as_ptr().cast(), name_len),
+            std::slice::from_raw_parts(control.as_ptr().cast(), control_len),
+        )
+    };
+    Ok((len as usize, name, control))
+}
+
+/// Whether `fd` is closed on exec.
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn cloexec(fd: i32) -> io::Result<bool> {
+    // SAFETY: F_GETFD takes no argument and touches no memory
+    let flags = unsafe { fcntl(fd, F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags & FD_CLOEXEC != 0)
+}
+
+/// Sets (`on`) or clears the close-on-exec flag of `fd`, so a child process
+/// does not inherit it.
+///
+/// # Errors
+/// The OS error.
+pub(crate) fn set_cloexec(fd: i32, on: bool) -> io::Result<()> {
+    if cloexec(fd)? == on {
+        return Ok(());
+    }
+    // FD_CLOEXEC is the only descriptor flag
+    let flags = if on { FD_CLOEXEC } else { 0 };
+    // SAFETY: F_SETFD takes an int and touches no memory
+    if unsafe { fcntl(fd, F_SETFD, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Index of the interface called `name`.
+///
+/// # Errors
+/// The OS error, `ENODEV` when there is no such interface.
+pub(crate) fn interface_index(name: &CStr) -> io::Result<u32> {
+    // SAFETY: `name` is a NUL-terminated string, alive for the call
+    match unsafe { if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::UdpSocket, os::fd::AsRawFd};
+
+    #[test]
+    fn test_cloexec() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let fd = sock.as_raw_fd();
+        // std opens its sockets close-on-exec
+        assert!(cloexec(fd).unwrap());
+        set_cloexec(fd, false).unwrap();
+        assert!(!cloexec(fd).unwrap());
+        set_cloexec(fd, true).unwrap();
+        assert!(cloexec(fd).unwrap());
+        assert!(cloexec(-1).is_err());
+    }
+}
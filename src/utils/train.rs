@@ -0,0 +1,133 @@
+//! # Packet-train dispersion
+//!
+//! In probing mode (`ClientBuilder::packet_trains`) the client sends its
+//! packets in back-to-back trains and writes the train length into the header.
+//! [`TrainTracker`] groups the arrivals by train (`seq / train_len`) and turns
+//! the spread of every train into a rate sample for [`BandwidthEstimate`].
+
+use std::time::{Duration, Instant};
+
+use crate::result::BandwidthEstimate;
+use crate::utils::udp_data::{FLAG_DATA, UdpHeader};
+
+/// Shortest dispersion trusted, below it the clock resolution dominates
+const MIN_DISPERSION: Duration = Duration::from_micros(1);
+
+/// Arrivals of the train being received
+#[derive(Debug, Clone, Copy)]
+struct Train {
+    id: u64,
+    first_seq: u64,
+    first_arrival: Instant,
+    last_seq: u64,
+    last_arrival: Instant,
+    /// Bytes received after the first packet
+    bytes: u64,
+}
+
+/// Collects one rate sample per received train
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrainTracker {
+    train_len: u16,
+    current: Option<Train>,
+    /// Rate of every closed train (bits/sec)
+    rates: Vec<f64>,
+}
+
+impl TrainTracker {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a packet that arrived at `arrival`; packets outside trains are ignored.
+    pub(crate) fn record(&mut self, h: &UdpHeader, packet_len: usize, arrival: Instant) {
+        if h.train_len == 0 || h.flags != FLAG_DATA {
+            return;
+        }
+        self.train_len = h.train_len;
+        let id = h.seq / h.train_len as u64;
+        match self.current.as_mut() {
+            Some(train) if train.id == id => {
+                // a reordered packet does not extend the train
+                if h.seq > train.last_seq {
+                    train.last_seq = h.seq;
+                    train.last_arrival = arrival;
+                }
+                train.bytes += packet_len as u64;
+            }
+            // late packets of an already closed train are ignored
+            Some(train) if id < train.id => {}
+            _ => {
+                self.close();
+                self.current = Some(Train {
+                    id,
+                    first_seq: h.seq,
+                    first_arrival: arrival,
+                    last_seq: h.seq,
+                    last_arrival: arrival,
+                    bytes: 0,
+                });
+            }
+        }
+    }
+
+    /// Closes the train being received and keeps its rate
+    fn close(&mut self) {
+        let Some(train) = self.current.take() else {
+            return;
+        };
+        let dispersion = train.last_arrival - train.first_arrival;
+        if train.last_seq > train.first_seq && dispersion >= MIN_DISPERSION {
+            self.rates
+                .push(train.bytes as f64 * 8.0 / dispersion.as_secs_f64());
+        }
+    }
+
+    /// Estimate over all trains so far, `None` if no train had two packets
+    pub(crate) fn estimate(&self) -> Option<BandwidthEstimate> {
+        let mut tracker = self.clone();
+        tracker.close();
+        BandwidthEstimate::from_rates(&mut tracker.rates, tracker.train_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn train_header(seq: u64) -> UdpHeader {
+        UdpHeader::new(seq, 0, 0, FLAG_DATA).with_train(4)
+    }
+
+    #[test]
+    fn test_dispersion_rate() {
+        let mut tracker = TrainTracker::new();
+        let t0 = Instant::now();
+        // two trains of 4 x 1000 bytes, spread over 3 ms and 6 ms
+        for (train, gap_us) in [(0u64, 1000u64), (1, 2000)] {
+            let start = t0 + Duration::from_millis(100 * train);
+            for i in 0..4 {
+                let arrival = start + Duration::from_micros(gap_us * i);
+                tracker.record(&train_header(train * 4 + i), 1000, arrival);
+            }
+        }
+
+        let est = tracker.estimate().unwrap();
+        assert_eq!(est.trains, 2);
+        assert_eq!(est.train_len, 4);
+        assert!((est.max_bps - 8_000_000.0).abs() < 1.0);
+        assert!((est.min_bps - 4_000_000.0).abs() < 1.0);
+        assert!((est.median_bps - 6_000_000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_plain_packets_are_ignored() {
+        let mut tracker = TrainTracker::new();
+        let now = Instant::now();
+        tracker.record(&UdpHeader::new(0, 0, 0, FLAG_DATA), 1000, now);
+        tracker.record(&UdpHeader::new(1, 0, 0, FLAG_DATA), 1000, now);
+        // a train with a single surviving packet gives no sample either
+        tracker.record(&train_header(4), 1000, now);
+        assert_eq!(tracker.estimate(), None);
+    }
+}
@@ -7,29 +7,69 @@
 //! It is used by the UDP client and server to process incoming/outgoing packets
 //! and generate per-interval statistics.
 //!
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::congestion::{CongestionController, LossThreshold};
+use crate::errors::HeaderError;
+use crate::histogram::Histogram;
+use crate::nic::NicSampler;
+use crate::result::{LossStats, ReorderStats, SizeStats};
+use crate::socket::Ecn;
+use crate::trace::{PacketRecord, transit_ms};
+use crate::utils::jitter::{Jitter, JitterEstimator};
 use crate::utils::net_utils::IntervalResult;
+use crate::utils::random_utils::RandomToSend;
+use crate::utils::ui::bitrate;
+
+/// Size of the UDP header in bytes (magic + version + train length + reserved + seq + sec + usec + flags + session
+/// + sent count + target rate)
+pub(crate) const HEADER_SIZE: usize = 4 + 1 + 2 + 1 + 8 + 8 + 4 + 4 + 4 + 8 + 8; // 52 bytes
+
+/// Longest UDP payload over IPv4, the longest packet a test can send
+pub(crate) const MAX_DATAGRAM_LEN: usize = 65507;
 
-/// Size of the UDP header in bytes (seq + sec + usec + flags)
-pub(crate) const HEADER_SIZE: usize = 8 + 8 + 4 + 4; // 24 bytes
+/// Receive buffer of the servers, longer than any UDP payload: a datagram
+/// filling it was truncated
+pub(crate) const RECV_BUF_LEN: usize = 1 << 16;
+
+/// Marks the datagrams of this crate ("UDPO"), anything else on the port is ignored
+pub(crate) const MAGIC: u32 = 0x5544_504F;
+/// Version of the packet format, bumped on incompatible changes
+pub(crate) const PROTOCOL_VERSION: u8 = 3;
 
 /// Flag indicating a data packet
 pub(crate) const FLAG_DATA: u32 = 0;
 /// Flag indicating the end of a test (FIN)
 pub(crate) const FLAG_FIN: u32 = 1;
+/// Flag of a chunk of the server's final results sent back to the client
+pub(crate) const FLAG_RESULT: u32 = 2;
+/// Flag of the client's acknowledgement of the final results
+pub(crate) const FLAG_RESULT_ACK: u32 = 3;
+/// Flag of the client's periodic report of the packets it sent, not a data packet
+pub(crate) const FLAG_STATS: u32 = 4;
+/// Flag of a packet the client sends through a gap in its traffic so the
+/// server does not take it as gone, not a data packet
+pub(crate) const FLAG_HEARTBEAT: u32 = 5;
+/// Flag of the server's echo of the address it received the test from, sent
+/// with the final results
+pub(crate) const FLAG_ENDPOINT: u32 = 6;
 
 /// Represents the header of a UDP packet
 pub(crate) struct UdpHeader {
-    seq: u64,       // sequence number
-    sec: u64,       // seconds since UNIX_EPOCH
-    usec: u32,      // microseconds part (0..999_999)
-    pub flags: u32, // 0 = data, 1 = FIN (end of test)
+    pub(crate) seq: u64, // sequence number
+    sec: u64,            // seconds since UNIX_EPOCH
+    usec: u32,           // microseconds part (0..999_999)
+    pub flags: u32,      // 0 = data, 1 = FIN (end of test)
+    /// Packets per train in probing mode, 0 for paced packets
+    pub(crate) train_len: u16,
+    /// Random id of the client run, the same on all its packets, 0 for none
+    pub(crate) session: u32,
+    /// Packets the client sent so far, this one included, 0 when unknown
+    pub(crate) sent: u64,
+    /// Bitrate the client is set to (bits/sec), 0 when unlimited or unknown
+    pub(crate) rate_bps: u64,
 }
 
-const ACCEPTABLE: u32 = 99;
-const ACCEPTABLEDECIMAL: u32 = 98;
-
 impl UdpHeader {
     /// Creates a new `UdpHeader`
     ///
@@ -40,13 +80,36 @@ impl UdpHeader {
     /// - `flag`: packet type (`FLAG_DATA` or `FLAG_FIN`)   
     pub(crate) fn new(seq: u64, sec: u64, usec: u32, flag: u32) -> Self {
         Self {
-            seq: seq,
-            sec: sec,
-            usec: usec,
+            seq,
+            sec,
+            usec,
             flags: flag,
+            train_len: 0,
+            session: 0,
+            sent: 0,
+            rate_bps: 0,
         }
     }
 
+    /// Marks the packet as sent by the client run `session`
+    pub(crate) fn with_session(mut self, session: u32) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Carries the client's count of packets sent so far and its target bitrate
+    pub(crate) fn with_sender(mut self, sent: u64, rate_bps: u64) -> Self {
+        self.sent = sent;
+        self.rate_bps = rate_bps;
+        self
+    }
+
+    /// Marks the packet as part of a train of `train_len` packets
+    pub(crate) fn with_train(mut self, train_len: u16) -> Self {
+        self.train_len = train_len;
+        self
+    }
+
     /// Writes the header into a buffer (big-endian)
     ///
     /// # Panics
@@ -54,166 +117,779 @@ impl UdpHeader {
     pub(crate) fn write_header(&mut self, buffer: &mut [u8]) {
         assert!(buffer.len() >= HEADER_SIZE);
 
-        buffer[0..8].copy_from_slice(&self.seq.to_be_bytes());
-        buffer[8..16].copy_from_slice(&self.sec.to_be_bytes());
-        buffer[16..20].copy_from_slice(&self.usec.to_be_bytes());
-        buffer[20..24].copy_from_slice(&self.flags.to_be_bytes());
+        buffer[0..4].copy_from_slice(&MAGIC.to_be_bytes());
+        buffer[4] = PROTOCOL_VERSION;
+        buffer[5..7].copy_from_slice(&self.train_len.to_be_bytes());
+        buffer[7] = 0;
+        buffer[8..16].copy_from_slice(&self.seq.to_be_bytes());
+        buffer[16..24].copy_from_slice(&self.sec.to_be_bytes());
+        buffer[24..28].copy_from_slice(&self.usec.to_be_bytes());
+        buffer[28..32].copy_from_slice(&self.flags.to_be_bytes());
+        buffer[32..36].copy_from_slice(&self.session.to_be_bytes());
+        buffer[36..44].copy_from_slice(&self.sent.to_be_bytes());
+        buffer[44..52].copy_from_slice(&self.rate_bps.to_be_bytes());
     }
 
-    /// Reads a `UdpHeader` from a buffer (big-endian)
+    /// Parses the first `len` bytes received into `buf`.
     ///
-    /// # Panics
-    /// Panics if the buffer is smaller than `HEADER_SIZE`.
-    pub(crate) fn read_header(buffer: &mut [u8]) -> Self {
-        let seq = u64::from_be_bytes(buffer[0..8].try_into().unwrap());
-        let sec = u64::from_be_bytes(buffer[8..16].try_into().unwrap());
-        let usec = u32::from_be_bytes(buffer[16..20].try_into().unwrap());
-        let flags = u32::from_be_bytes(buffer[20..24].try_into().unwrap());
-        Self {
+    /// # Errors
+    /// [`HeaderError::Truncated`] if the datagram filled `buf`, the receive
+    /// cut it short; otherwise as [`parse`](Self::parse).
+    pub(crate) fn parse_received(buf: &[u8], len: usize) -> Result<Self, HeaderError> {
+        if len >= buf.len() {
+            return Err(HeaderError::Truncated(buf.len()));
+        }
+        Self::parse(&buf[..len])
+    }
+
+    /// Parses a `UdpHeader` from the start of a datagram (big-endian)
+    ///
+    /// # Errors
+    /// A [`HeaderError`] if the datagram is smaller than `HEADER_SIZE`, does
+    /// not start with [`MAGIC`], has another protocol version, unknown flags
+    /// or a timestamp with a million microseconds or more.
+    pub(crate) fn parse(buffer: &[u8]) -> Result<Self, HeaderError> {
+        if buffer.len() < HEADER_SIZE {
+            return Err(HeaderError::TooShort(buffer.len()));
+        }
+        if u32::from_be_bytes(buffer[0..4].try_into().unwrap()) != MAGIC {
+            return Err(HeaderError::BadMagic);
+        }
+        if buffer[4] != PROTOCOL_VERSION {
+            return Err(HeaderError::UnsupportedVersion(buffer[4]));
+        }
+        let seq = u64::from_be_bytes(buffer[8..16].try_into().unwrap());
+        let sec = u64::from_be_bytes(buffer[16..24].try_into().unwrap());
+        let usec = u32::from_be_bytes(buffer[24..28].try_into().unwrap());
+        if usec >= 1_000_000 {
+            return Err(HeaderError::BadTimestamp(usec));
+        }
+        let flags = u32::from_be_bytes(buffer[28..32].try_into().unwrap());
+        if !matches!(
+            flags,
+            FLAG_DATA
+                | FLAG_FIN
+                | FLAG_RESULT
+                | FLAG_RESULT_ACK
+                | FLAG_STATS
+                | FLAG_HEARTBEAT
+                | FLAG_ENDPOINT
+        ) {
+            return Err(HeaderError::UnknownFlags(flags));
+        }
+        let train_len = u16::from_be_bytes(buffer[5..7].try_into().unwrap());
+        let session = u32::from_be_bytes(buffer[32..36].try_into().unwrap());
+        let sent = u64::from_be_bytes(buffer[36..44].try_into().unwrap());
+        let rate_bps = u64::from_be_bytes(buffer[44..52].try_into().unwrap());
+        Ok(Self {
             seq,
             sec,
             usec,
             flags,
+            train_len,
+            session,
+            sent,
+            rate_bps,
+        })
+    }
+
+    /// Builds a trace [`PacketRecord`] for this header
+    ///
+    /// # Parameters
+    /// - `packet_len`: length of the packet in bytes
+    /// - `recv_sec`, `recv_usec`: arrival time since UNIX_EPOCH
+    pub(crate) fn to_record(
+        &self,
+        packet_len: usize,
+        recv_sec: u64,
+        recv_usec: u32,
+    ) -> PacketRecord {
+        PacketRecord {
+            seq: self.seq,
+            flags: self.flags,
+            send_sec: self.sec,
+            send_usec: self.usec,
+            recv_sec,
+            recv_usec,
+            size: packet_len,
+            transit_ms: transit_ms(self.sec, self.usec, recv_sec, recv_usec),
+        }
+    }
+}
+
+/// On-the-wire layout of the test packets sent by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WireFormat {
+    /// This crate's [`UdpHeader`], ending with a FIN packet
+    Native,
+    /// iperf3 UDP header (`sec | usec | 64-bit pcount`), no FIN
+    #[cfg(feature = "iperf3-compat")]
+    Iperf3,
+}
+
+impl WireFormat {
+    /// Writes `header` at the start of `buffer`
+    ///
+    /// The train length, the flags and the session are only carried by the
+    /// native format.
+    pub(crate) fn write_header(&self, buffer: &mut [u8], mut header: UdpHeader) {
+        match self {
+            WireFormat::Native => header.write_header(buffer),
+            #[cfg(feature = "iperf3-compat")]
+            WireFormat::Iperf3 => {
+                crate::iperf3::write_udp_header(buffer, header.seq + 1, header.sec, header.usec)
+            }
+        }
+    }
+
+    /// Whether the end of the test is signalled in-band with a FIN packet
+    pub(crate) fn has_fin(&self) -> bool {
+        matches!(self, WireFormat::Native)
+    }
+}
+
+/// How far back (in sequence numbers) duplicates are detected; a bigger step
+/// back is taken as a client restarting its sequence numbers
+///
+/// A client run is told apart by its session id first. Only the packets
+/// without one (session 0, e.g. the iperf3 wire format) fall back on the
+/// step back, so such a sender restarting before it got `SEQ_WINDOW` packets
+/// ahead has its new packets counted as duplicates.
+const SEQ_WINDOW: u64 = 1024;
+
+/// Which of the last `SEQ_WINDOW` sequence numbers were received.
+///
+/// Sequence numbers leaving the window are final, they feed the loss pattern.
+#[derive(Debug, Clone)]
+struct SeqWindow {
+    bits: [u64; (SEQ_WINDOW / 64) as usize],
+    /// Oldest sequence number not yet classified as received or lost
+    next: u64,
+    /// Length of the loss burst in progress
+    run: u64,
+    loss: LossStats,
+}
+
+impl SeqWindow {
+    fn new() -> Self {
+        Self {
+            bits: [0; (SEQ_WINDOW / 64) as usize],
+            next: 0,
+            run: 0,
+            loss: LossStats::default(),
         }
     }
+
+    /// Starts tracking from `seq`, the first sequence number of a client
+    fn start(&mut self, seq: u64) {
+        self.bits = [0; (SEQ_WINDOW / 64) as usize];
+        self.next = seq;
+        self.mark(seq);
+    }
+
+    /// Closes the window up to `highest` and starts over from `seq` after a client restart
+    fn restart(&mut self, highest: u64, seq: u64) {
+        self.classify(highest, highest);
+        self.start(seq);
+    }
+
+    /// Forgets the loss pattern so far, sequence tracking goes on
+    fn clear_losses(&mut self) {
+        self.run = 0;
+        self.loss = LossStats::default();
+    }
+
+    fn is_marked(&self, seq: u64) -> bool {
+        let slot = (seq % SEQ_WINDOW) as usize;
+        self.bits[slot / 64] & (1 << (slot % 64)) != 0
+    }
+
+    /// Marks `seq` as received, returns whether it already was
+    fn mark(&mut self, seq: u64) -> bool {
+        let seen = self.is_marked(seq);
+        let slot = (seq % SEQ_WINDOW) as usize;
+        self.bits[slot / 64] |= 1 << (slot % 64);
+        seen
+    }
+
+    /// Moves the window from the highest sequence `from` to `to`
+    fn advance(&mut self, from: u64, to: u64) {
+        // the slots of the sequence numbers leaving the window get reused
+        self.classify(to.wrapping_sub(SEQ_WINDOW), from);
+
+        let steps = to.wrapping_sub(from);
+        if steps >= SEQ_WINDOW {
+            self.bits = [0; (SEQ_WINDOW / 64) as usize];
+            return;
+        }
+        for k in 1..=steps {
+            let slot = (from.wrapping_add(k) % SEQ_WINDOW) as usize;
+            self.bits[slot / 64] &= !(1 << (slot % 64));
+        }
+    }
+
+    /// Classifies the sequence numbers from `next` up to `until` (inclusive),
+    /// `highest` being the highest sequence number in the window
+    fn classify(&mut self, until: u64, highest: u64) {
+        loop {
+            let remaining = until.wrapping_sub(self.next) as i64;
+            if remaining < 0 {
+                break;
+            }
+            if (self.next.wrapping_sub(highest) as i64) > 0 {
+                // skipped over without ever entering the window
+                self.lose(remaining as u64 + 1);
+                self.next = until.wrapping_add(1);
+                break;
+            }
+            if self.is_marked(self.next) {
+                self.loss.packets += 1;
+                if self.run > 0 {
+                    self.loss.record_burst(self.run);
+                    self.run = 0;
+                }
+            } else {
+                self.lose(1);
+            }
+            self.next = self.next.wrapping_add(1);
+        }
+    }
+
+    fn lose(&mut self, count: u64) {
+        self.loss.packets += count;
+        self.loss.lost += count;
+        self.run += count;
+    }
+
+    /// Loss pattern so far, counting the sequence numbers still in the window
+    fn loss_stats(&self, highest: u64) -> LossStats {
+        let mut window = self.clone();
+        window.classify(highest, highest);
+        window.loss
+    }
 }
 
 /// Tracks UDP statistics and state for a connection
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug)]
 pub(crate) struct UdpData {
-    /// Last received sequence number
+    /// Highest received sequence number
     last_seq: Option<u64>,
+    /// Session id of the client run `last_seq` belongs to, 0 for none
+    session: u32,
+    /// Recently received sequence numbers, for duplicate detection
+    seen: SeqWindow,
     /// Interval statistics
     interval_result: IntervalResult,
+    /// Send time (µs since UNIX_EPOCH, sender's clock) and arrival of the
+    /// first packet, the reference of the transit times
+    reference: Option<(u64, Duration)>,
     /// Previous packet transit time (ms)
     prev_transit_ms: Option<f64>,
+    /// Jitter of the interval and delay variations of the test
+    jitter: Jitter,
     /// Recommended packets per second
     pub recommend_pps: f64,
+    /// Decides `recommend_pps` from the loss
+    controller: Box<dyn CongestionController>,
+    /// Packets received and lost in the interval at the last rate decision
+    calc_mark: (u64, u64),
+    /// Transit time of the first packet (ms), used as the latency reference
+    base_transit_ms: Option<f64>,
+    /// Per-packet transit times relative to the first packet (µs)
+    latency: Histogram,
+    /// Reordering extent over the whole test
+    reorder: ReorderStats,
+    /// Packets per length bucket over the whole test
+    sizes: SizeStats,
+    /// Start of the measurement on the monotonic and the wall clock
+    origin: (Instant, SystemTime),
+    /// Highest count of packets sent the client reported
+    client_sent: u64,
+    /// Packets the client had sent when the statistics were last discarded
+    client_sent_before: u64,
+    /// Count of the last STATS packet of the interval
+    reported_sent: Option<u64>,
+    /// Packets the client had sent at the last reported interval close
+    sent_mark: Option<u64>,
+    /// Packets received and counted lost in the intervals closed without a
+    /// report since
+    unreported: (u64, u64),
+    /// Highest sequence number when the last interval closed, the packets
+    /// up to it belong to a closed interval
+    closed_up_to: Option<u64>,
+    /// Highest sequence number when the statistics were last discarded,
+    /// the loss of the packets up to it is not counted anywhere
+    discarded_up_to: Option<u64>,
+    /// Whether the FIN arrived, the packets after it are late
+    fin: bool,
+    /// Counters of the receiving interface, sampled at every interval close
+    nic: Option<NicSampler>,
+    /// Drop count of the socket as of the last datagram received
+    socket_dropped: Option<u32>,
 }
 
 impl UdpData {
     /// Creates a new `UdpData` instance
-
     pub(crate) fn new() -> Self {
         Self {
             last_seq: None,
+            session: 0,
+            seen: SeqWindow::new(),
             interval_result: IntervalResult::default(),
+            reference: None,
             prev_transit_ms: None,
+            jitter: Jitter::new(JitterEstimator::default()),
             recommend_pps: 0.0,
+            controller: Box::new(LossThreshold),
+            calc_mark: (0, 0),
+            base_transit_ms: None,
+            latency: Histogram::new(),
+            reorder: ReorderStats::default(),
+            sizes: SizeStats::default(),
+            origin: (Instant::now(), SystemTime::now()),
+            client_sent: 0,
+            client_sent_before: 0,
+            reported_sent: None,
+            sent_mark: None,
+            unreported: (0, 0),
+            closed_up_to: None,
+            discarded_up_to: None,
+            fin: false,
+            nic: None,
+            socket_dropped: None,
+        }
+    }
+
+    /// Creates a new `UdpData` computing the interval jitter with `estimator`
+    pub(crate) fn with_jitter(estimator: JitterEstimator) -> Self {
+        Self {
+            jitter: Jitter::new(estimator),
+            ..Self::new()
         }
     }
 
+    /// Recommends the rate with `controller` instead of [`LossThreshold`]
+    pub(crate) fn with_controller(self, controller: Box<dyn CongestionController>) -> Self {
+        Self { controller, ..self }
+    }
+
+    /// Attaches what the interface of `nic` counted to every interval
+    pub(crate) fn with_nic(self, nic: Option<NicSampler>) -> Self {
+        Self { nic, ..self }
+    }
+
+    /// Starts the interval timeline of the measurement now and returns its start
+    pub(crate) fn start_clock(&mut self) -> Instant {
+        if let Some(nic) = self.nic.as_mut()
+            && let Err(_e) = nic.restart()
+        {
+            event!(warn, error = %_e, "interface counters unreadable");
+        }
+        self.origin = (Instant::now(), SystemTime::now());
+        self.interval_result.offset = Duration::ZERO;
+        self.interval_result.start_time = Some(self.origin.1);
+        self.origin.0
+    }
+
+    /// Places the current interval at `start` on the timeline
+    pub(crate) fn set_interval_start(&mut self, start: Instant) {
+        let offset = start.saturating_duration_since(self.origin.0);
+        self.interval_result.offset = offset;
+        self.interval_result.start_time = Some(self.origin.1 + offset);
+    }
+
+    /// Stamps the interval starting now with its place on the timeline; the
+    /// wall-clock time follows the monotonic clock, a clock step during the
+    /// test does not move it
+    fn stamp_interval(&mut self) {
+        let offset = self.origin.0.elapsed();
+        self.interval_result.offset = offset;
+        self.interval_result.start_time = Some(self.origin.1 + offset);
+    }
+
     /// Processes a received packet, updates statistics and jitter
     ///
     /// # Parameters
-    /// - `packet_len`: length of the packet in bytes
+    /// - `packet`: the received datagram, borrowed from the receive buffer
     /// - `h`: reference to the packet header
-    /// - `now_since_start`: elapsed time since server start
-    pub(crate) fn process_packet(
-        &mut self,
-        packet_len: usize,
-        h: &UdpHeader,
-        now_since_start: Duration,
-    ) {
-        self.interval_result.received += 1;
-        self.interval_result.bytes += packet_len;
-        //  determine losses ,out of order
+    /// - `arrival`: arrival time, elapsed since an instant fixed for the
+    ///   whole test (not the start of the interval)
+    pub(crate) fn process_packet(&mut self, packet: &[u8], h: &UdpHeader, arrival: Duration) {
+        self.interval_result.bytes += packet.len();
+        self.sizes.record(packet.len());
+        if self.client_sent == 0 {
+            // counted from the first packet measured, like the sequence numbers
+            self.client_sent_before = h.sent.saturating_sub(1);
+        }
+        if self.sent_mark.is_none() && h.sent > 0 {
+            self.sent_mark = Some(h.sent - 1);
+        }
+        self.client_sent = self.client_sent.max(h.sent);
+        if h.rate_bps > 0 {
+            self.interval_result.target_bitrate = h.rate_bps;
+        }
+        // after the FIN, or too late for the interval it belongs to
+        let mut late = self.fin;
+        //  determine losses ,out of order, duplicates
         match self.last_seq {
-            None => self.last_seq = Some(h.seq),
+            None => {
+                self.last_seq = Some(h.seq);
+                self.session = h.session;
+                self.seen.start(h.seq);
+                self.interval_result.received += 1;
+                self.reorder.record_in_order();
+            }
 
             Some(prev) => {
-                if h.seq == prev {
-                    //duplicate packet
-                } else if h.seq == prev + 1 {
-                    //set the last accepted sequence to be packet sequnce
+                // signed distance, so that wrapping around u64::MAX is a step forward
+                let distance = h.seq.wrapping_sub(prev) as i64;
+                // another run of the client, or far behind anything recent
+                // without a session to tell: its sequence numbers started over
+                let restarted = h.session != self.session
+                    || (distance <= 0 && distance.unsigned_abs() >= SEQ_WINDOW);
+                if restarted {
+                    // start over instead of counting it as reordering or copies
+                    self.seen.restart(prev, h.seq);
+                    self.session = h.session;
+                    self.closed_up_to = None;
+                    self.discarded_up_to = None;
                     self.last_seq = Some(h.seq);
-                } else if h.seq > (prev + 1) {
-                    // when the header sequence is bigger than the previous sequence +1
-                    self.interval_result.lost = h.seq - (prev + 1);
-
+                    self.interval_result.received += 1;
+                    self.reorder.record_in_order();
+                } else if distance > 0 {
+                    if distance > 1 {
+                        // every sequence skipped over counts as lost until it shows up late
+                        self.interval_result.lost += (distance - 1) as u64;
+                    }
+                    self.seen.advance(prev, h.seq);
+                    self.seen.mark(h.seq);
+                    //set the last accepted sequence to be packet sequnce
                     self.last_seq = Some(h.seq);
+                    self.interval_result.received += 1;
+                    self.reorder.record_in_order();
+                } else if self.seen.mark(h.seq) {
+                    // an extra copy says nothing new about timing either
+                    self.interval_result.duplicates += 1;
+                    return;
+                } else if self
+                    .closed_up_to
+                    .is_some_and(|closed| closed.wrapping_sub(h.seq) as i64 >= 0)
+                {
+                    // its interval is closed with the packet counted lost,
+                    // counting it in this one would inflate its bitrate
+                    self.interval_result.bytes -= packet.len();
+                    self.reorder.record_reordered(distance.unsigned_abs());
+                    late = true;
+                    if self
+                        .discarded_up_to
+                        .is_none_or(|discarded| (discarded.wrapping_sub(h.seq) as i64) < 0)
+                    {
+                        self.interval_result.recovered += 1;
+                    }
                 } else {
-                    // out of order happend when h.seq<prev
+                    // out of order happend when h.seq<prev, the packet was counted
+                    // lost when the gap opened. A gap from an earlier interval stays
+                    // in that interval's count
                     self.interval_result.out_of_order += 1;
+                    self.interval_result.lost = self.interval_result.lost.saturating_sub(1);
+                    self.interval_result.received += 1;
+                    self.reorder.record_reordered(distance.unsigned_abs());
                 }
             }
         }
 
+        if late {
+            self.interval_result.late += 1;
+        }
+
         //proccess jitter
-        // Jitter per RFC3550 (in milliseconds)
         // And read https://support.spirent.com/s/article/FAQ13756
-        // Not that  send_ms uses sender's clock (may differ from server), but jitter is based on differences
-        // There is no need for NTP
-
-        let send_ms = (h.sec as f64) * 1000.0 + (h.usec as f64) / 1000.0;
-        let arrival_ms = now_since_start.as_secs_f64() * 1000.0; // relative to server start
+        // The send time comes from the sender's clock and the arrival from the
+        // server's, so both are taken relative to the first packet: the transit
+        // time is how much longer than the first packet this one took, the
+        // offset between the clocks cancels out. There is no need for NTP
+        let send_us = h
+            .sec
+            .wrapping_mul(1_000_000)
+            .wrapping_add(u64::from(h.usec));
+        let (send0, arrival0) = *self.reference.get_or_insert((send_us, arrival));
+        let send_ms = send_us.wrapping_sub(send0) as i64 as f64 / 1000.0;
+        let arrival_ms = (arrival.as_secs_f64() - arrival0.as_secs_f64()) * 1000.0;
         let transit = arrival_ms - send_ms;
-        if let Some(prev_t) = self.prev_transit_ms {
-            let d = (transit - prev_t).abs();
-            self.interval_result.jitter_ms += (d - self.interval_result.jitter_ms) / 16.0;
-        }
+        self.jitter.record(
+            self.prev_transit_ms,
+            transit,
+            &mut self.interval_result.jitter_ms,
+        );
         self.prev_transit_ms = Some(transit);
+
+        // sender and receiver clocks are not synchronized, so latency is recorded
+        // relative to the first packet's transit time
+        let base = *self.base_transit_ms.get_or_insert(transit);
+        let relative_us = ((transit - base) * 1000.0).max(0.0);
+        self.latency.record(relative_us as u64);
+    }
+
+    /// Packets the client says it sent from the first packet measured or the
+    /// end of the warm-up, `None` if it does not report it
+    ///
+    /// Taken from the final count the FIN carries, `None` when the FIN was
+    /// lost: the count of the last packet received misses the packets lost
+    /// after it.
+    pub(crate) fn client_sent(&self) -> Option<u64> {
+        (self.fin && self.client_sent > 0).then(|| self.client_sent - self.client_sent_before)
+    }
+
+    /// Returns the per-packet latency histogram collected so far
+    pub(crate) fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// Counts the packet just processed if it arrived marked CE
+    pub(crate) fn record_ecn(&mut self, ecn: Option<Ecn>) {
+        if ecn == Some(Ecn::Ce) {
+            self.interval_result.ce_marked += 1;
+        }
+    }
+
+    /// Takes the drop count of the socket when a datagram was queued, 0
+    /// before its first drop: its growth since the previous datagram went
+    /// to a full receive buffer
+    pub(crate) fn record_socket_drops(&mut self, dropped: u32) {
+        if let Some(last) = self.socket_dropped {
+            self.interval_result.socket_drops += u64::from(dropped.wrapping_sub(last));
+        }
+        self.socket_dropped = Some(dropped);
+    }
+
+    /// Counts a packet that failed the pre-shared key check
+    pub(crate) fn record_auth_failure(&mut self) {
+        self.interval_result.auth_failures += 1;
+    }
+
+    /// Takes the count of packets sent from a STATS packet of the client
+    pub(crate) fn record_sender_stats(&mut self, h: &UdpHeader) {
+        self.client_sent = self.client_sent.max(h.sent);
+        self.reported_sent = Some(h.sent);
+    }
+
+    /// Counts a datagram that is not a valid udpopt packet
+    pub(crate) fn record_malformed(&mut self) {
+        self.interval_result.malformed += 1;
+    }
+
+    /// Counts a datagram from a source that is not allowed
+    pub(crate) fn record_foreign(&mut self) {
+        self.interval_result.foreign += 1;
+    }
+
+    /// Returns the delay variations between consecutive packets (µs) collected so far
+    pub(crate) fn ipdv(&self) -> &Histogram {
+        self.jitter.ipdv()
+    }
+
+    /// Returns the reordering statistics collected so far
+    pub(crate) fn reorder(&self) -> &ReorderStats {
+        &self.reorder
+    }
+
+    /// Returns the packets per length bucket
+    pub(crate) fn sizes(&self) -> &SizeStats {
+        &self.sizes
+    }
+
+    /// Returns the loss pattern of the sequence numbers up to the highest one received
+    pub(crate) fn loss_pattern(&self) -> LossStats {
+        match self.last_seq {
+            Some(highest) => self.seen.loss_stats(highest),
+            None => LossStats::default(),
+        }
     }
 
     // custom conjection control
 
-    /// Calculates recommended bitrate based on packet loss and interval duration
+    /// Calculates recommended bitrate based on packet loss and interval
+    /// duration, with the [`CongestionController`] of the session
     ///
     /// # Parameters
-    /// - `time`: duration of the measurement period
+    /// - `time`: duration of the measurement period, since the previous call
+    ///   or the start of the interval; only the packets of that period count
     pub(crate) fn calc_bitrate(&mut self, time: Duration) {
-        let received = self.interval_result.received;
-        let lost = self.interval_result.lost;
+        let (received_before, lost_before) = self.calc_mark;
+        let received = self
+            .interval_result
+            .received
+            .saturating_sub(received_before);
+        let lost = self.interval_result.lost.saturating_sub(lost_before);
+        self.calc_mark = (self.interval_result.received, self.interval_result.lost);
         // Reset early if no packets to avoid div-by-zero
         if received == 0 {
             self.recommend_pps = 0.0;
             return;
         }
-        // Packets per second (pps)
-        let act_pps = received as f64 / time.as_secs_f64();
-        // Reset early if no packets lost
-        if lost == 0 {
+        if time.as_secs_f64() <= f64::EPSILON {
             return;
         }
 
-        let interval_secs = time.as_secs_f64();
-        if interval_secs <= f64::EPSILON {
-            return;
+        if let Some(recommended) = self.controller.recommend(received, lost, time) {
+            self.recommend_pps = recommended.max(0.0); // never negative
         }
+    }
+
+    /// [`recommend_pps`](Self::recommend_pps) in bits per second, from the
+    /// mean size of the packets received in the interval; 0 without a
+    /// recommendation or without packets
+    fn recommended_bps(&self) -> u64 {
+        let received = self.interval_result.received;
+        if received == 0 || self.recommend_pps <= 0.0 {
+            return 0;
+        }
+        let mean_len = self.interval_result.bytes as f64 / received as f64;
+        (self.recommend_pps * mean_len * 8.0).round() as u64
+    }
 
-        // Compute received ratio once
-        let received_ratio = ((received - lost) as f64 / received as f64) * 100.0;
+    /// Drops everything measured so far but keeps tracking the sequence
+    /// numbers, so the end of a warm-up period does not show up as loss
+    pub(crate) fn discard_statistics(&mut self) {
+        self.interval_result = IntervalResult::default();
+        self.calc_mark = (0, 0);
+        self.base_transit_ms = None;
+        self.latency = Histogram::new();
+        self.jitter.clear();
+        self.client_sent_before = self.client_sent;
+        self.sent_mark = None;
+        self.reported_sent = None;
+        self.unreported = (0, 0);
+        self.reorder = ReorderStats::default();
+        self.sizes = SizeStats::default();
+        self.seen.clear_losses();
+        self.closed_up_to = self.last_seq;
+        self.discarded_up_to = self.last_seq;
+    }
 
-        // Split into integer + decimal parts
-        let int_part = received_ratio as u32; // truncates
+    /// Marks the FIN received, the packets processed after it are late
+    pub(crate) fn finish(&mut self) {
+        self.fin = true;
+    }
 
-        let decimal_part = ((received_ratio * 10000.0) as u32) % 100;
+    /// Counters of the interval still open
+    pub(crate) fn current(&self) -> &IntervalResult {
+        &self.interval_result
+    }
 
-        // Decide recommended adjustment
-        let recommended = if int_part < ACCEPTABLE {
-            act_pps * 0.95 // reduce rate by 5%
-        } else if decimal_part >= ACCEPTABLEDECIMAL {
-            act_pps + 5.0 // small increase
-        } else {
-            act_pps - 10.0 // bigger decrease
-        };
+    /// Whether packets were received since the last interval result
+    pub(crate) fn has_pending(&self) -> bool {
+        self.interval_result.received > 0
+    }
 
-        self.recommend_pps = recommended.max(0.0); // never negative
+    /// Takes the loss of the interval from what the client reported sending,
+    /// when it did, instead of the gaps in the sequence numbers
+    fn settle_loss(&mut self) {
+        let (received, lost) = self.unreported;
+        let received = received + self.interval_result.received;
+        match (self.reported_sent.take(), self.sent_mark) {
+            (Some(reported), Some(mark)) => {
+                let sent = reported.saturating_sub(mark);
+                self.interval_result.sent = sent;
+                // less what the intervals without a report already counted
+                self.interval_result.lost = sent.saturating_sub(received).saturating_sub(lost);
+                self.sent_mark = Some(reported.max(mark));
+                self.unreported = (0, 0);
+            }
+            // the next report covers this interval too
+            (None, Some(_)) => self.unreported = (received, lost + self.interval_result.lost),
+            _ => {}
+        }
     }
 
     /// Returns interval statistics and resets them
-
     pub(crate) fn get_interval_result(&mut self, iterval_time: Duration) -> IntervalResult {
         self.interval_result.time = iterval_time;
-        let r = std::mem::take(&mut self.interval_result);
-        r
+        self.interval_result.bitrate_bps =
+            bitrate(self.interval_result.bytes, iterval_time.as_secs_f64());
+        self.jitter
+            .close_interval(&mut self.interval_result.jitter_ms);
+        self.settle_loss();
+        if let Some(recommended) = self.controller.close_interval() {
+            self.recommend_pps = recommended.max(0.0);
+        }
+        self.interval_result.recommended_bitrate = self.recommended_bps();
+        self.calc_mark = (0, 0);
+        // a gone interface leaves the interval without counters
+        self.interval_result.nic = self.nic.as_mut().and_then(|nic| nic.sample().ok());
+
+        let result = std::mem::take(&mut self.interval_result);
+        self.closed_up_to = self.last_seq;
+        self.stamp_interval();
+        result
+    }
+}
+
+/// Time between consecutive packet arrivals at the server
+#[derive(Debug, Clone)]
+pub(crate) struct InterArrival {
+    /// Arrival of the previous packet, `None` after a pause
+    last: Option<Instant>,
+    /// Only every `sample_every`-th delta is recorded
+    sample_every: u32,
+    /// Deltas skipped since the last recorded one
+    skipped: u32,
+    /// Recorded deltas (µs)
+    hist: Histogram,
+}
+
+impl InterArrival {
+    pub(crate) fn new(sample_every: u32) -> Self {
+        Self {
+            last: None,
+            sample_every: sample_every.max(1),
+            skipped: 0,
+            hist: Histogram::new(),
+        }
+    }
+
+    /// Records the packet that arrived at `arrival`
+    pub(crate) fn record(&mut self, arrival: Instant) {
+        if let Some(last) = self.last {
+            self.skipped += 1;
+            if self.skipped >= self.sample_every {
+                self.hist
+                    .record(arrival.saturating_duration_since(last).as_micros() as u64);
+                self.skipped = 0;
+            }
+        }
+        self.last = Some(arrival);
+    }
+
+    /// Forgets the previous arrival, so the gap of a pause is not recorded
+    pub(crate) fn restart(&mut self) {
+        self.last = None;
+    }
+
+    pub(crate) fn histogram(&self) -> &Histogram {
+        &self.hist
     }
 }
 
 // helper functions
 
-/// Returns the current system time as seconds + microseconds since UNIX_EPOCH
+/// A random session id for a new client run, from the clock if the OS
+/// random source fails
+///
+/// Never 0, the id of the packets that carry no session.
+pub(crate) fn new_session_id() -> u32 {
+    let mut id = [0u8; 4];
+    let id = match RandomToSend::new().and_then(|mut random| random.fill(&mut id)) {
+        Ok(()) => u32::from_be_bytes(id),
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos(),
+    };
+    id.max(1)
+}
 
+/// Returns the current system time as seconds + microseconds since UNIX_EPOCH
 pub fn now_micros() -> (u64, u32) {
     let d = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
     (d.as_secs(), d.subsec_micros())
@@ -222,8 +898,11 @@ pub fn now_micros() -> (u64, u32) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::TestResult;
     use std::time::Duration;
 
+    const PACKET: [u8; 1500] = [0; 1500];
+
     #[test]
     fn test_udp_header_new() {
         let header = UdpHeader::new(12345, 1000000, 500000, FLAG_DATA);
@@ -237,18 +916,81 @@ mod tests {
     #[test]
     fn test_udp_header_write_and_read() {
         let mut buffer = vec![0u8; HEADER_SIZE];
-        let mut original = UdpHeader::new(42, 1234567890, 999999, FLAG_FIN);
+        let mut original = UdpHeader::new(42, 1234567890, 999999, FLAG_FIN).with_session(0xC0FFEE);
 
         // Write header to buffer
         original.write_header(&mut buffer);
 
         // Read it back
-        let read_header = UdpHeader::read_header(&mut buffer);
+        let read_header = UdpHeader::parse(&buffer).unwrap();
 
         assert_eq!(read_header.seq, 42);
         assert_eq!(read_header.sec, 1234567890);
         assert_eq!(read_header.usec, 999999);
         assert_eq!(read_header.flags, FLAG_FIN);
+        assert_eq!(read_header.session, 0xC0FFEE);
+    }
+
+    #[test]
+    fn test_udp_header_rejects_foreign_packets() {
+        let mut buffer = vec![0u8; HEADER_SIZE];
+        UdpHeader::new(7, 1, 2, FLAG_DATA).write_header(&mut buffer);
+        assert!(UdpHeader::parse(&buffer).is_ok());
+
+        assert!(matches!(
+            UdpHeader::parse(&buffer[..HEADER_SIZE - 1]),
+            Err(HeaderError::TooShort(51))
+        ));
+        assert!(matches!(
+            UdpHeader::parse(&[]),
+            Err(HeaderError::TooShort(0))
+        ));
+
+        let mut other_version = buffer.clone();
+        other_version[4] = PROTOCOL_VERSION + 1;
+        assert!(matches!(
+            UdpHeader::parse(&other_version),
+            Err(HeaderError::UnsupportedVersion(v)) if v == PROTOCOL_VERSION + 1
+        ));
+
+        // e.g. a DNS reply landing on the test port
+        let stray = [0xABu8; 64];
+        assert!(matches!(
+            UdpHeader::parse(&stray),
+            Err(HeaderError::BadMagic)
+        ));
+
+        // a datagram filling the receive buffer was cut short
+        assert!(UdpHeader::parse_received(&buffer, HEADER_SIZE - 1).is_err());
+        let mut received = buffer.clone();
+        received.push(0);
+        assert!(UdpHeader::parse_received(&received, HEADER_SIZE).is_ok());
+        assert!(matches!(
+            UdpHeader::parse_received(&received, HEADER_SIZE + 1),
+            Err(HeaderError::Truncated(len)) if len == HEADER_SIZE + 1
+        ));
+    }
+
+    #[test]
+    fn test_udp_header_rejects_garbage_fields() {
+        let mut buffer = vec![0u8; HEADER_SIZE];
+        UdpHeader::new(7, 1, 2, 9).write_header(&mut buffer);
+        assert_eq!(
+            UdpHeader::parse(&buffer).err(),
+            Some(HeaderError::UnknownFlags(9))
+        );
+
+        UdpHeader::new(7, 1, 1_000_000, FLAG_DATA).write_header(&mut buffer);
+        assert_eq!(
+            UdpHeader::parse(&buffer).err(),
+            Some(HeaderError::BadTimestamp(1_000_000))
+        );
+
+        let error = crate::UdpOptError::from(HeaderError::BadMagic);
+        assert!(matches!(
+            error,
+            crate::UdpOptError::MalformedPacket(HeaderError::BadMagic)
+        ));
     }
 
     #[test]
@@ -283,25 +1025,108 @@ mod tests {
         assert_eq!(data.recommend_pps, 0.0);
     }
 
+    #[test]
+    fn test_stats_packets_settle_the_interval_loss() {
+        let packet = |seq: u64| UdpHeader::new(seq, 0, 0, FLAG_DATA).with_sender(seq + 1, 0);
+        let stats = |sent: u64| UdpHeader::new(sent, 0, 0, FLAG_STATS).with_sender(sent, 0);
+        let interval = Duration::from_secs(1);
+        let mut data = UdpData::new();
+
+        // 10 sent, the last two lost: no gap in the sequence numbers yet
+        for seq in 0..8 {
+            data.process_packet(&PACKET, &packet(seq), Duration::ZERO);
+        }
+        data.record_sender_stats(&stats(10));
+        let first = data.get_interval_result(interval);
+        assert_eq!((first.sent, first.received, first.lost), (10, 8, 2));
+
+        // the gap shows in this interval, the report keeps it in the first
+        for seq in 10..20 {
+            data.process_packet(&PACKET, &packet(seq), Duration::ZERO);
+        }
+        data.record_sender_stats(&stats(20));
+        let second = data.get_interval_result(interval);
+        assert_eq!((second.sent, second.received, second.lost), (10, 10, 0));
+
+        // an interval without a report keeps the sequence loss, the next
+        // report covers both
+        for seq in (20..30).filter(|&seq| seq != 25) {
+            data.process_packet(&PACKET, &packet(seq), Duration::ZERO);
+        }
+        let third = data.get_interval_result(interval);
+        assert_eq!((third.sent, third.received, third.lost), (0, 9, 1));
+        for seq in 30..40 {
+            data.process_packet(&PACKET, &packet(seq), Duration::ZERO);
+        }
+        data.record_sender_stats(&stats(40));
+        let fourth = data.get_interval_result(interval);
+        // not counted twice
+        assert_eq!((fourth.sent, fourth.received, fourth.lost), (20, 10, 0));
+    }
+
+    #[test]
+    fn test_transit_relative_to_first_packet() {
+        let mut data = UdpData::new();
+        // sender clock at the real time, the arrivals since the server start:
+        // the transit times stay small and a steady flow has no jitter
+        let sent = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        for seq in 0..20u64 {
+            let at = sent + Duration::from_millis(10 * seq);
+            let h = UdpHeader::new(seq, at.as_secs(), at.subsec_micros(), FLAG_DATA);
+            let arrival = Duration::from_millis(5_000 + 10 * seq);
+            data.process_packet(&PACKET, &h, arrival);
+            assert!(data.prev_transit_ms.unwrap().abs() < 1e-6);
+            if seq == 9 {
+                data.get_interval_result(Duration::from_millis(100));
+            }
+        }
+        assert!(data.interval_result.jitter_ms < 1e-6);
+        assert_eq!(data.latency().max(), 0);
+
+        // one packet 3 ms late
+        let at = sent + Duration::from_millis(200);
+        let h = UdpHeader::new(20, at.as_secs(), at.subsec_micros(), FLAG_DATA);
+        data.process_packet(&PACKET, &h, Duration::from_millis(5_203));
+        assert!((data.prev_transit_ms.unwrap() - 3.0).abs() < 1e-6);
+        assert!((data.interval_result.jitter_ms - 3.0 / 16.0).abs() < 1e-6);
+    }
+
     #[test]
     fn test_process_packet_jitter_calculation() {
         let mut data = UdpData::new();
 
         // First packet - establishes baseline
         let h1 = UdpHeader::new(0, 1000, 0, FLAG_DATA);
-        data.process_packet(1500, &h1, Duration::from_millis(100));
+        data.process_packet(&PACKET, &h1, Duration::from_millis(100));
 
         assert!(data.prev_transit_ms.is_some());
         assert_eq!(data.interval_result.jitter_ms, 0.0); // No jitter yet
 
         // Second packet - should calculate jitter
         let h2 = UdpHeader::new(1, 1000, 50000, FLAG_DATA);
-        data.process_packet(1500, &h2, Duration::from_millis(200));
+        data.process_packet(&PACKET, &h2, Duration::from_millis(200));
 
         // Jitter should be non-zero now
         assert!(data.interval_result.jitter_ms > 0.0);
     }
 
+    #[test]
+    fn test_process_packet_records_latency() {
+        let mut data = UdpData::new();
+
+        // second packet arrives 5 ms later than the first relative to its send time
+        let h1 = UdpHeader::new(0, 1000, 0, FLAG_DATA);
+        data.process_packet(&PACKET, &h1, Duration::from_millis(100));
+        let h2 = UdpHeader::new(1, 1000, 10_000, FLAG_DATA);
+        data.process_packet(&PACKET, &h2, Duration::from_millis(115));
+
+        let latency = data.latency();
+        assert_eq!(latency.count(), 2);
+        assert_eq!(latency.min(), 0);
+        let max = latency.max();
+        assert!((4_990..=5_010).contains(&max), "max was {}", max);
+    }
+
     #[test]
     fn test_process_multiple_packets() {
         let mut data = UdpData::new();
@@ -320,7 +1145,7 @@ mod tests {
                 i
             }; // 4 and 5 swapped
             let header = UdpHeader::new(seq, 1000 + i, (i * 1000) as u32, FLAG_DATA);
-            data.process_packet(1500, &header, Duration::from_millis(i * 100));
+            data.process_packet(&PACKET, &header, Duration::from_millis(i * 100));
         }
 
         assert_eq!(data.interval_result.received, 9); // Received 9 out of 10
@@ -329,21 +1154,306 @@ mod tests {
         assert_eq!(data.interval_result.out_of_order, 1); // One out-of-order
     }
 
+    #[test]
+    fn test_loss_accumulates_over_gaps() {
+        let mut data = UdpData::new();
+
+        // three gaps in one interval: 2, 5..=7 and 9
+        for seq in [0, 1, 3, 4, 8, 10] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+
+        assert_eq!(data.interval_result.lost, 5);
+        assert_eq!(data.interval_result.received, 6);
+    }
+
+    #[test]
+    fn test_late_arrivals_are_credited() {
+        let mut data = UdpData::new();
+
+        // 2 and 6 arrive late, 4 never does
+        for seq in [0, 1, 3, 2, 5, 7, 6, 8] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+
+        assert_eq!(data.interval_result.out_of_order, 2);
+        assert_eq!(data.interval_result.lost, 1);
+        assert_eq!(data.interval_result.received, 8);
+    }
+
+    #[test]
+    fn test_reorder_displacement() {
+        let mut data = UdpData::new();
+
+        // 1 is 3 behind, 5 is 1 behind, the duplicate is not counted
+        for seq in [0, 2, 3, 4, 1, 6, 5, 5, 7] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+
+        let reorder = data.reorder();
+        assert_eq!(reorder.packets, 8);
+        assert_eq!(reorder.reordered, 2);
+        assert_eq!(reorder.max_distance, 3);
+        assert_eq!(reorder.displacement[0], 1);
+        assert_eq!(reorder.displacement[2], 1);
+    }
+
+    #[test]
+    fn test_loss_pattern() {
+        let mut data = UdpData::new();
+
+        // bursts: 1, then 3..=5, then 8 arrives late and is not a loss
+        for seq in [0, 2, 6, 7, 9, 8, 10] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+
+        let loss = data.loss_pattern();
+        assert_eq!(loss.packets, 11);
+        assert_eq!(loss.lost, 4);
+        assert_eq!(loss.bursts, 2);
+        assert_eq!(loss.max_burst, 3);
+        assert_eq!(loss.run_lengths[0], 1);
+        assert_eq!(loss.run_lengths[2], 1);
+    }
+
+    #[test]
+    fn test_loss_pattern_across_window() {
+        let mut data = UdpData::new();
+
+        // a gap bigger than the window, then enough packets to push it out
+        let seqs = (0..10).chain(3000..3000 + 2 * SEQ_WINDOW);
+        for seq in seqs {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+
+        let loss = data.loss_pattern();
+        assert_eq!(loss.lost, 2990);
+        assert_eq!(loss.bursts, 1);
+        assert_eq!(loss.max_burst, 2990);
+        assert_eq!(loss.packets, 3000 + 2 * SEQ_WINDOW);
+    }
+
+    #[test]
+    fn test_late_arrival_after_interval_reset() {
+        let mut data = UdpData::new();
+
+        for seq in [0, 2, 5] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+        let closed = data.get_interval_result(Duration::from_secs(1));
+        assert_eq!(closed.lost, 3);
+
+        // the loss stays in the closed interval, the new one counts it late
+        // without receiving it, and recovered for the total
+        let h = UdpHeader::new(1, 1000, 0, FLAG_DATA);
+        data.process_packet(&PACKET, &h, Duration::from_secs(2));
+        assert_eq!(data.interval_result.lost, 0);
+        assert_eq!(data.interval_result.late, 1);
+        assert_eq!(data.interval_result.recovered, 1);
+        assert_eq!(data.interval_result.received, 0);
+        assert_eq!(data.interval_result.bytes, 0);
+        assert_eq!(data.interval_result.out_of_order, 0);
+        assert_eq!(data.reorder().reordered, 1);
+
+        // reordered within the interval, not late
+        for seq in [7, 6] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(2));
+        }
+        assert_eq!(data.interval_result.out_of_order, 1);
+        assert_eq!(data.interval_result.late, 1);
+
+        // the tail behind the FIN is received and late, never counted lost
+        data.finish();
+        let h = UdpHeader::new(8, 1000, 0, FLAG_DATA);
+        data.process_packet(&PACKET, &h, Duration::from_secs(2));
+        assert_eq!(data.interval_result.late, 2);
+        assert_eq!(data.interval_result.recovered, 1);
+        assert_eq!(data.interval_result.received, 3);
+
+        // the total agrees with the loss pattern: 3 and 4 never arrived
+        let open = data.get_interval_result(Duration::from_secs(1));
+        let total = TestResult::from_intervals(&[closed, open]);
+        assert_eq!(total.total_lost, 2);
+        assert_eq!(data.loss_pattern().lost, 2);
+    }
+
+    #[test]
+    fn test_late_arrival_from_the_warm_up() {
+        let mut data = UdpData::new();
+        for seq in [0, 2] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+        // its loss went with the warm-up, there is nothing to take back
+        data.discard_statistics();
+        let h = UdpHeader::new(1, 1000, 0, FLAG_DATA);
+        data.process_packet(&PACKET, &h, Duration::from_secs(2));
+        assert_eq!(data.interval_result.late, 1);
+        assert_eq!(data.interval_result.recovered, 0);
+    }
+
     #[test]
     fn test_large_sequence_numbers() {
         let mut data = UdpData::new();
 
         let large_seq = u64::MAX - 10;
         let h1 = UdpHeader::new(large_seq, 1000, 0, FLAG_DATA);
-        data.process_packet(1500, &h1, Duration::from_secs(1));
+        data.process_packet(&PACKET, &h1, Duration::from_secs(1));
 
         let h2 = UdpHeader::new(large_seq + 1, 1000, 1000, FLAG_DATA);
-        data.process_packet(1500, &h2, Duration::from_secs(1));
+        data.process_packet(&PACKET, &h2, Duration::from_secs(1));
 
         assert_eq!(data.last_seq, Some(large_seq + 1));
         assert_eq!(data.interval_result.lost, 0);
     }
 
+    #[test]
+    fn test_duplicates_are_counted() {
+        let mut data = UdpData::new();
+
+        for seq in [0, 1, 1, 2, 3, 0, 2] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+
+        assert_eq!(data.interval_result.duplicates, 3);
+        assert_eq!(data.interval_result.received, 4);
+        assert_eq!(data.interval_result.out_of_order, 0);
+        assert_eq!(data.interval_result.lost, 0);
+    }
+
+    #[test]
+    fn test_late_packet_is_not_a_duplicate() {
+        let mut data = UdpData::new();
+
+        for seq in [0, 2, 1, 1] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+
+        assert_eq!(data.interval_result.out_of_order, 1);
+        assert_eq!(data.interval_result.duplicates, 1);
+    }
+
+    #[test]
+    fn test_client_restart_resets_sequence() {
+        let mut data = UdpData::new();
+
+        for seq in (0..5000).chain(0..100) {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+
+        assert_eq!(data.last_seq, Some(99));
+        assert_eq!(data.interval_result.received, 5100);
+        assert_eq!(data.interval_result.out_of_order, 0);
+        assert_eq!(data.interval_result.duplicates, 0);
+        assert_eq!(data.interval_result.lost, 0);
+    }
+
+    #[test]
+    fn test_short_restart_is_told_by_the_session() {
+        let mut data = UdpData::new();
+        let packet = |seq, session| UdpHeader::new(seq, 1000, 0, FLAG_DATA).with_session(session);
+        for (seq, session) in (0..10)
+            .map(|seq| (seq, 7))
+            .chain((0..10).map(|seq| (seq, 8)))
+        {
+            data.process_packet(&PACKET, &packet(seq, session), Duration::from_secs(1));
+        }
+        assert_eq!(data.interval_result.received, 20);
+        assert_eq!(data.interval_result.duplicates, 0);
+
+        // without a session only a step back of a whole window tells
+        let mut data = UdpData::new();
+        for seq in (0..10).chain(0..10) {
+            data.process_packet(&PACKET, &packet(seq, 0), Duration::from_secs(1));
+        }
+        assert_eq!(data.interval_result.received, 10);
+        assert_eq!(data.interval_result.duplicates, 10);
+    }
+
+    #[test]
+    fn test_sequence_wraparound() {
+        let mut data = UdpData::new();
+
+        for seq in [u64::MAX - 1, u64::MAX, 0, 1, u64::MAX] {
+            let h = UdpHeader::new(seq, 1000, 0, FLAG_DATA);
+            data.process_packet(&PACKET, &h, Duration::from_secs(1));
+        }
+
+        assert_eq!(data.last_seq, Some(1));
+        assert_eq!(data.interval_result.lost, 0);
+        assert_eq!(data.interval_result.out_of_order, 0);
+        assert_eq!(data.interval_result.duplicates, 1);
+    }
+
+    #[test]
+    fn test_interval_recommended_bitrate() {
+        let mut data = UdpData::new();
+        data.interval_result.received = 1000;
+        data.interval_result.lost = 100;
+        data.interval_result.bytes = 1000 * 1200;
+        data.calc_bitrate(Duration::from_secs(1));
+
+        // 950 pps of 1200-byte packets
+        let result = data.get_interval_result(Duration::from_secs(1));
+        assert_eq!(result.recommended_bitrate, 950 * 1200 * 8);
+
+        // nothing received, nothing to recommend
+        data.calc_bitrate(Duration::from_secs(1));
+        let result = data.get_interval_result(Duration::from_secs(1));
+        assert_eq!(result.recommended_bitrate, 0);
+    }
+
+    #[test]
+    fn test_calc_bitrate_with_controller() {
+        use crate::congestion::Aimd;
+
+        let mut data = UdpData::new().with_controller(Box::new(Aimd::new().hysteresis(2)));
+        // one lossy interval is not enough for a change, however many
+        // periods it has
+        for expected in [1000.0, 900.0] {
+            for _ in 0..5 {
+                data.interval_result.received += 200;
+                data.interval_result.lost += 20;
+                data.calc_bitrate(Duration::from_millis(200));
+            }
+            data.get_interval_result(Duration::from_secs(1));
+            assert_eq!(data.recommend_pps, expected);
+        }
+    }
+
+    #[test]
+    fn test_calc_bitrate_over_periods_of_the_interval() {
+        let mut data = UdpData::new();
+        // 1000 pps of 1200-byte packets with 10 % loss, decided every 200 ms
+        for _ in 0..5 {
+            data.interval_result.received += 180;
+            data.interval_result.lost += 20;
+            data.interval_result.bytes += 180 * 1200;
+            data.calc_bitrate(Duration::from_millis(200));
+            assert_eq!(data.recommend_pps, 900.0 * 0.95);
+        }
+
+        // 95 % of the 900 pps received, not of the whole interval over 200 ms
+        let result = data.get_interval_result(Duration::from_secs(1));
+        assert_eq!(result.recommended_bitrate, 855 * 1200 * 8);
+
+        // the next interval starts counting afresh
+        data.interval_result.received = 90;
+        data.interval_result.lost = 10;
+        data.calc_bitrate(Duration::from_millis(200));
+        assert_eq!(data.recommend_pps, 450.0 * 0.95);
+    }
+
     #[test]
     fn test_calc_bitrate_high_loss() {
         let mut data = UdpData::new();
@@ -426,6 +1536,7 @@ mod tests {
         assert_eq!(result.lost, 5);
         assert_eq!(result.jitter_ms, 2.5);
         assert_eq!(result.out_of_order, 3);
+        assert_eq!(result.bitrate_bps, 1_200_000.0);
 
         // Check that original is reset
         assert_eq!(data.interval_result.received, 0);
@@ -433,5 +1544,30 @@ mod tests {
         assert_eq!(data.interval_result.lost, 0);
         assert_eq!(data.interval_result.jitter_ms, 0.0);
         assert_eq!(data.interval_result.out_of_order, 0);
+
+        // an empty interval of no length has no rate, not NaN
+        assert_eq!(data.get_interval_result(Duration::ZERO).bitrate_bps, 0.0);
+    }
+
+    #[test]
+    fn test_interarrival_sampling() {
+        let t0 = Instant::now();
+        let mut every = InterArrival::new(1);
+        let mut sampled = InterArrival::new(3);
+        for i in 0..10 {
+            let arrival = t0 + Duration::from_millis(i * i);
+            every.record(arrival);
+            sampled.record(arrival);
+        }
+        // 9 deltas, every third one kept
+        assert_eq!(every.histogram().count(), 9);
+        assert_eq!(sampled.histogram().count(), 3);
+        assert_eq!(every.histogram().min(), 1000);
+        assert_eq!(every.histogram().max(), 17000);
+
+        // no delta spans a pause
+        every.restart();
+        every.record(t0 + Duration::from_secs(60));
+        assert_eq!(every.histogram().count(), 9);
     }
 }
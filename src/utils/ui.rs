@@ -1,57 +1,444 @@
+use std::fmt::Write;
+use std::io::IsTerminal;
 use std::time::Instant;
 
-use crate::utils::net_utils::IntervalResult;
+use crate::result::{LatencyPercentiles, TestResult};
+use crate::utils::net_utils::{ClientProgress, IntervalResult};
 
-pub fn print_result(test_result: &IntervalResult) {
-    let elapsed = test_result.time.as_secs_f64();
-    let mbps = if elapsed > 0.0 {
-        (test_result.bytes as f64 * 8.0) / elapsed / 1_000_000.0
+/// Formats a bitrate with the largest fitting unit, e.g. `12.50 Mbps`.
+pub fn format_bitrate(bps: f64) -> String {
+    const UNITS: [(f64, &str); 3] = [(1e9, "Gbps"), (1e6, "Mbps"), (1e3, "Kbps")];
+    match UNITS.iter().find(|&&(scale, _)| bps >= scale) {
+        Some((scale, unit)) => format!("{:.2} {}", bps / scale, unit),
+        None => format!("{:.0} bps", bps),
+    }
+}
+
+/// Formats an error with its chain of causes on one line, e.g.
+/// `Failed to send 1200 bytes to 192.0.2.1:5201: Connection refused`.
+pub fn format_error(error: &dyn std::error::Error) -> String {
+    let mut line = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let _ = write!(line, ": {cause}");
+        source = cause.source();
+    }
+    line
+}
+
+/// Bitrate of `bytes` received in `secs`, 0 for an empty interval
+pub(crate) fn bitrate(bytes: usize, secs: f64) -> f64 {
+    if secs > 0.0 {
+        bytes as f64 * 8.0 / secs
     } else {
         0.0
-    };
-    println!(
-        " Elapsed {:.2}s | Recv {} pkts | Lost {} | OOO {} | Jitter {:.3} ms | Rate {:.3} Mbps",
-        elapsed,
+    }
+}
+
+/// Percentage of the packets sent that were lost
+pub(crate) fn loss_percent(received: u64, lost: u64) -> f64 {
+    let sent = received + lost;
+    if sent > 0 {
+        lost as f64 / sent as f64 * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// Share of the `received` packets that arrived out of order (%)
+pub(crate) fn ooo_percent(received: u64, out_of_order: u64) -> f64 {
+    if received > 0 {
+        out_of_order as f64 / received as f64 * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// Formats an interval result as one human readable line.
+pub fn format_result(test_result: &IntervalResult) -> String {
+    let mut line = format!(
+        " Elapsed {:.2}s | Recv {} pkts | Lost {} | OOO {} | Jitter {:.3} ms | Rate {}",
+        test_result.time.as_secs_f64(),
         test_result.received,
         test_result.lost,
         test_result.out_of_order,
         test_result.jitter_ms,
-        mbps
+        format_bitrate(test_result.bitrate_bps)
     );
+    // the loss is then from what the client reported sending
+    if test_result.sent > 0 {
+        line.push_str(&format!(" | Sent {}", test_result.sent));
+    }
+    // intended vs achieved, when the client tells its rate
+    if test_result.target_bitrate > 0 {
+        line.push_str(&format!(
+            " (target {})",
+            format_bitrate(test_result.target_bitrate as f64)
+        ));
+    }
+    // what the loss of the interval suggests sending at instead
+    if test_result.recommended_bitrate > 0 {
+        line.push_str(&format!(
+            " | Recommended {}",
+            format_bitrate(test_result.recommended_bitrate as f64)
+        ));
+    }
+    // a looping route or a misbehaving middlebox
+    if test_result.duplicates > 0 {
+        line.push_str(&format!(" | Dup {}", test_result.duplicates));
+    }
+    // reported on their own, not in the bitrate of this interval
+    if test_result.late > 0 {
+        line.push_str(&format!(" | Late {}", test_result.late));
+    }
+    // only counted when the server reads ECN / checks a key
+    if test_result.ce_marked > 0 {
+        line.push_str(&format!(" | CE {}", test_result.ce_marked));
+    }
+    if test_result.auth_failures > 0 {
+        line.push_str(&format!(" | Auth failures {}", test_result.auth_failures));
+    }
+    if test_result.foreign > 0 {
+        line.push_str(&format!(" | Foreign {}", test_result.foreign));
+    }
+    if test_result.malformed > 0 {
+        line.push_str(&format!(" | Malformed {}", test_result.malformed));
+    }
+    // lost on this host rather than on the path
+    if let Some(nic) = test_result.nic.filter(|nic| nic.drops() > 0) {
+        line.push_str(&format!(" | NIC drops {}", nic.drops()));
+    }
+    if test_result.socket_drops > 0 {
+        line.push_str(&format!(" | Socket drops {}", test_result.socket_drops));
+    }
+    // a gap in the traffic, not a slow interval
+    if test_result.received == 0 && test_result.lost == 0 {
+        line.push_str(" | no traffic");
+    }
+    line
 }
 
-// pub fn final_report(test_result:TestResult) {
-//     let elapsed = test_result.time.as_secs_f64();
-//     let mbps = if elapsed > 0.0 {
-//         (test_result.result.bytes as f64 * 8.0) / elapsed / 1_000_000.0
-//     } else {
-//         0.0
-//     };
-
-//     let loss_pct = if total_sent > 0 {
-//         (intervatest_result.result.l_result.lost as f64) / (total_sent as f64) * 100.0
-//     } else {
-//         0.0
-//     };
-//     println!(
-//         "FINAL   Duration: {:.2}s\n  Bytes: {}\n  Throughput: {:.3} Mbps\n  Received: {} pkts\n  Lost: {} pkts ({:.2}%)\n  Out-of-order: {}\n  Jitter: {:.3} ms",
-//         elapsed,
-//         intervatest_result.result.l_result.bytes,
-//         mbps,
-//         intervatest_result.result.l_result.received,
-//         intervatest_result.result.l_result.lost,
-//         loss_pct,
-//         intervatest_result.result.l_result.out_of_order,
-//         intervatest_result.result.l_result.jitter_ms
-//     );
-// }
+/// Prints an interval result, or logs it as a `tracing` event with the `tracing` feature.
+pub fn print_result(test_result: &IntervalResult) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        received = test_result.received,
+        lost = test_result.lost,
+        out_of_order = test_result.out_of_order,
+        duplicates = test_result.duplicates,
+        recommended_bitrate = test_result.recommended_bitrate,
+        jitter_ms = test_result.jitter_ms,
+        "{}",
+        format_result(test_result).trim_start()
+    );
+    #[cfg(not(feature = "tracing"))]
+    println!("{}", format_result(test_result));
+}
 
 pub fn client_period_report(start: Instant, payload: usize, seq: usize) {
     let elapsed = start.elapsed().as_secs_f64();
-    let sent_bytes = (seq as usize * payload) as f64;
+    let sent_bytes = (seq * payload) as f64;
     let mbps = (sent_bytes * 8.0) / elapsed / 1_000_000.0;
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        "Elapsed {:.2}s | Sent {} pkts | est rate {:.3} Mbps",
+        elapsed,
+        seq,
+        mbps
+    );
+    #[cfg(not(feature = "tracing"))]
     println!(
         "Elapsed {:.2}s | Sent {} pkts | est rate {:.3} Mbps",
         elapsed, seq, mbps
     );
 }
+
+/// Formats the client progress as one human readable line.
+pub fn format_progress(progress: &ClientProgress) -> String {
+    format!(
+        "Elapsed {:.2}s | Sent {} pkts | est rate {}",
+        progress.elapsed.as_secs_f64(),
+        progress.packets_sent,
+        format_bitrate(progress.bitrate_bps)
+    )
+}
+
+/// Prints the client progress, or logs it as a `tracing` event with the `tracing` feature.
+pub fn print_progress(progress: &ClientProgress) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        packets_sent = progress.packets_sent,
+        bitrate_bps = progress.bitrate_bps,
+        "{}",
+        format_progress(progress)
+    );
+    #[cfg(not(feature = "tracing"))]
+    println!("{}", format_progress(progress));
+}
+
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders the final report of a test as a table: one row per interval, a
+/// total row with the loss, throughput and jitter of the whole test, then
+/// the latency percentiles and, when recorded, the IPDV percentiles.
+///
+/// ```
+/// use udpopt::{IntervalResult, TestResult, ui::ReportRenderer};
+///
+/// let intervals = vec![IntervalResult::default()];
+/// let summary = TestResult::from_intervals(&intervals);
+/// let table = ReportRenderer::new().render(&intervals, &summary);
+/// assert!(table.contains("Total"));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReportRenderer {
+    color: bool,
+}
+
+impl ReportRenderer {
+    /// A renderer without colors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A renderer with colors when stdout is a terminal and `NO_COLOR` is not set.
+    pub fn for_stdout() -> Self {
+        Self::new().color(std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none())
+    }
+
+    /// Highlights the header and colors the loss: green without loss, yellow
+    /// under 1 %, red from 1 %.
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Renders `intervals`, possibly none, and `summary`.
+    pub fn render(&self, intervals: &[IntervalResult], summary: &TestResult) -> String {
+        let mut out = String::new();
+        let header = format!(
+            "{:<16} {:>10} {:>8} {:>8} {:>8} {:>10} {:>14}",
+            "Interval", "Received", "Lost", "Loss", "OOO", "Jitter", "Throughput"
+        );
+        let _ = writeln!(out, "{}", self.paint(&header, BOLD));
+
+        let mut from = 0.0;
+        for r in intervals {
+            let secs = r.time.as_secs_f64();
+            let span = format!("{:.2}-{:.2} s", from, from + secs);
+            from += secs;
+            self.row(
+                &mut out,
+                &span,
+                r.received,
+                r.lost,
+                r.out_of_order,
+                r.jitter_ms,
+            );
+            let _ = writeln!(out, " {:>14}", format_bitrate(r.bitrate_bps));
+        }
+
+        let _ = writeln!(out, "{}", "-".repeat(header.len()));
+        let span = format!("Total {:.2} s", summary.total_time);
+        self.row(
+            &mut out,
+            &span,
+            summary.total_packets,
+            summary.total_lost,
+            summary.total_out_of_order,
+            summary.mean_jitter,
+        );
+        let _ = writeln!(out, " {:>14}", format_bitrate(summary.mean_bitrate));
+        let _ = writeln!(
+            out,
+            "Throughput min {} | max {} | std dev {} | {:.0} pps",
+            format_bitrate(summary.min_bitrate),
+            format_bitrate(summary.max_bitrate),
+            format_bitrate(summary.std_dev_bitrate),
+            summary.pps()
+        );
+        if let (Some(lost), Some(percent)) = (summary.true_loss(), summary.true_loss_percent()) {
+            let _ = writeln!(
+                out,
+                "Sent by the client {} | lost {} ({:.2} %)",
+                summary.packets_sent, lost, percent
+            );
+        }
+        if summary.total_duplicates > 0 || summary.total_late > 0 {
+            let _ = writeln!(
+                out,
+                "Duplicates {} | late {}",
+                summary.total_duplicates, summary.total_late
+            );
+        }
+        if summary.total_socket_drops > 0 {
+            let _ = writeln!(
+                out,
+                "Dropped by the socket {} | lost on the path {}",
+                summary.total_socket_drops,
+                summary.path_loss()
+            );
+        }
+        let mut jitter = format!(
+            "Jitter min {:.3} ms | max {:.3} ms | std dev {:.3} ms",
+            summary.min_jitter, summary.max_jitter, summary.std_dev_jitter
+        );
+        for p in &summary.percentiles {
+            let _ = write!(jitter, " | p{} {:.3} ms", p.percentile, p.jitter_ms);
+        }
+        let _ = writeln!(out, "{jitter}");
+        let latency = &summary.latency;
+        let _ = writeln!(
+            out,
+            "Latency p50 {:.3} ms | p90 {:.3} ms | p99 {:.3} ms | p99.9 {:.3} ms | MOS {:.2}",
+            latency.p50_ms,
+            latency.p90_ms,
+            latency.p99_ms,
+            latency.p999_ms,
+            summary.voip_quality().mos
+        );
+        let ipdv = &summary.ipdv;
+        if *ipdv != LatencyPercentiles::default() {
+            let _ = writeln!(
+                out,
+                "IPDV p50 {:.3} ms | p90 {:.3} ms | p99 {:.3} ms | p99.9 {:.3} ms",
+                ipdv.p50_ms, ipdv.p90_ms, ipdv.p99_ms, ipdv.p999_ms
+            );
+        }
+        out
+    }
+
+    /// Prints the report on stdout.
+    pub fn print(&self, intervals: &[IntervalResult], summary: &TestResult) {
+        print!("{}", self.render(intervals, summary));
+    }
+
+    /// The columns up to the jitter, the throughput is left to the caller
+    fn row(&self, out: &mut String, span: &str, received: u64, lost: u64, ooo: u64, jitter: f64) {
+        let loss = loss_percent(received, lost);
+        let color = if loss >= 1.0 {
+            RED
+        } else if loss > 0.0 {
+            YELLOW
+        } else {
+            GREEN
+        };
+        // padded before painting, the escape codes have no width
+        let loss = self.paint(&format!("{:>6.2} %", loss), color);
+        let _ = write!(
+            out,
+            "{:<16} {:>10} {:>8} {} {:>8} {:>7.3} ms",
+            span, received, lost, loss, ooo, jitter
+        );
+    }
+
+    fn paint(&self, text: &str, style: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_bitrate() {
+        assert_eq!(format_bitrate(950.0), "950 bps");
+        assert_eq!(format_bitrate(64_000.0), "64.00 Kbps");
+        assert_eq!(format_bitrate(12_500_000.0), "12.50 Mbps");
+        assert_eq!(format_bitrate(2.5e9), "2.50 Gbps");
+    }
+
+    #[test]
+    fn test_format_error_chain() {
+        use crate::{RunError, UdpOptError};
+
+        let refused = || std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        let peer = "192.0.2.1:5201".parse().unwrap();
+        let send = UdpOptError::SendFailed {
+            peer: Some(peer),
+            bytes: 1200,
+            source: refused(),
+        };
+        assert_eq!(send.peer(), Some(peer));
+        assert_eq!(
+            format_error(&send),
+            "Failed to send 1200 bytes to 192.0.2.1:5201: refused"
+        );
+        // a run error is its error, not one more link of the chain
+        let run = RunError {
+            error: UdpOptError::RecvFailed(refused()),
+            intervals: Vec::new(),
+        };
+        assert_eq!(format_error(&run), "Failed to receive a datagram: refused");
+        let unknown = UdpOptError::SendFailed {
+            peer: None,
+            bytes: 64,
+            source: refused(),
+        };
+        assert_eq!(unknown.to_string(), "Failed to send 64 bytes");
+    }
+
+    #[test]
+    fn test_report_table() {
+        let interval = |lost| {
+            IntervalResult {
+                time: Duration::from_secs(1),
+                received: 99,
+                lost,
+                bytes: 125_000,
+                ..Default::default()
+            }
+            .with_bitrate()
+        };
+        let intervals = [interval(0), interval(1)];
+        let summary = TestResult::from_intervals(&intervals);
+
+        let table = ReportRenderer::new().render(&intervals, &summary);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[1].starts_with("0.00-1.00 s"));
+        assert!(lines[2].starts_with("1.00-2.00 s") && lines[2].contains("1.00 %"));
+        assert!(lines[1].ends_with("1.00 Mbps"));
+        assert!(lines[4].starts_with("Total 2.00 s"));
+        assert_eq!(
+            lines[5],
+            "Throughput min 1.00 Mbps | max 1.00 Mbps | std dev 0 bps | 99 pps"
+        );
+        assert!(lines[6].ends_with("| p99 0.000 ms"));
+        // all rows line up with the header
+        assert!(lines[..5].iter().all(|l| l.len() == lines[0].len()));
+        assert!(!table.contains('\x1b'));
+
+        let colored = ReportRenderer::new()
+            .color(true)
+            .render(&intervals, &summary);
+        assert!(colored.contains(&format!("{RED}  1.00 %{RESET}")));
+        assert!(colored.contains(&format!("{GREEN}  0.00 %{RESET}")));
+
+        let duplicated = [IntervalResult {
+            duplicates: 3,
+            ..interval(0)
+        }];
+        let summary = TestResult::from_intervals(&duplicated);
+        let table = ReportRenderer::new().render(&duplicated, &summary);
+        assert!(table.lines().any(|l| l == "Duplicates 3 | late 0"));
+        assert!(format_result(&duplicated[0]).contains(" | Dup 3"));
+
+        assert!(!format_result(&interval(0)).contains("Recommended"));
+        let recommended = IntervalResult {
+            recommended_bitrate: 9_120_000,
+            ..interval(0)
+        };
+        assert!(format_result(&recommended).contains(" | Recommended 9.12 Mbps"));
+    }
+}
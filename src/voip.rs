@@ -0,0 +1,103 @@
+//! VoIP suitability score.
+//!
+//! This module provides [`VoipQuality`] — an R-factor and MOS estimate from the
+//! simplified ITU-T G.107 E-model (as popularised by Cole & Rosenbluth), for a
+//! G.711 call with packet loss concealment. It turns loss, jitter and delay into
+//! a single number a VoIP engineer can act on:
+//!
+//! | MOS       | R-factor | User satisfaction |
+//! |-----------|----------|-------------------|
+//! | 4.3 - 4.5 | 90 - 100 | Very satisfied    |
+//! | 4.0 - 4.3 | 80 - 90  | Satisfied         |
+//! | 3.6 - 4.0 | 70 - 80  | Some dissatisfied |
+//! | 3.1 - 3.6 | 60 - 70  | Many dissatisfied |
+//! | < 3.1     | < 60     | Not recommended   |
+
+/// Default transmission rating of the E-model without impairments.
+const R0: f64 = 93.2;
+/// Packet-loss robustness of G.711 with packet loss concealment.
+const BPL: f64 = 25.1;
+/// Codec and jitter buffer delay added to the measured delay (ms).
+const CODEC_DELAY_MS: f64 = 10.0;
+
+/// E-model R-factor and the matching Mean Opinion Score.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VoipQuality {
+    /// Transmission rating, 0 (unusable) to 93.2 (best possible).
+    pub r_factor: f64,
+    /// Mean Opinion Score, 1.0 (bad) to 4.5 (excellent).
+    pub mos: f64,
+}
+
+impl VoipQuality {
+    /// Computes the score of a G.711 call over the measured path.
+    ///
+    /// # Parameters
+    /// - `loss_percent`: packet loss (0..=100).
+    /// - `jitter_ms`: mean jitter in milliseconds.
+    /// - `delay_ms`: one-way delay in milliseconds, 0 when unknown.
+    /// - `burst_ratio`: 1 for random loss, bigger when losses come in bursts
+    ///   (see [`crate::GilbertElliott::burst_ratio`]).
+    pub fn estimate(loss_percent: f64, jitter_ms: f64, delay_ms: f64, burst_ratio: f64) -> Self {
+        // the jitter buffer adds about twice the jitter
+        let effective_delay = delay_ms + 2.0 * jitter_ms + CODEC_DELAY_MS;
+        let delay_impairment = if effective_delay < 160.0 {
+            effective_delay / 40.0
+        } else {
+            (effective_delay - 120.0) / 10.0
+        };
+
+        let loss = loss_percent.clamp(0.0, 100.0);
+        let burst_ratio = burst_ratio.max(1.0);
+        let loss_impairment = 95.0 * loss / (loss / burst_ratio + BPL);
+
+        let r_factor = (R0 - delay_impairment - loss_impairment).clamp(0.0, R0);
+        Self {
+            r_factor,
+            mos: mos_from_r(r_factor),
+        }
+    }
+}
+
+/// G.107 mapping from R-factor to MOS
+fn mos_from_r(r: f64) -> f64 {
+    if r <= 0.0 {
+        1.0
+    } else if r >= 100.0 {
+        4.5
+    } else {
+        1.0 + 0.035 * r + 7.0e-6 * r * (r - 60.0) * (100.0 - r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perfect_path() {
+        let q = VoipQuality::estimate(0.0, 0.0, 0.0, 1.0);
+        assert!((q.r_factor - 92.95).abs() < 1e-9);
+        assert!(q.mos > 4.4 && q.mos <= 4.5, "mos was {}", q.mos);
+    }
+
+    #[test]
+    fn test_quality_degrades() {
+        let good = VoipQuality::estimate(0.5, 2.0, 20.0, 1.0);
+        let lossy = VoipQuality::estimate(5.0, 2.0, 20.0, 1.0);
+        let bursty = VoipQuality::estimate(5.0, 2.0, 20.0, 4.0);
+        let slow = VoipQuality::estimate(0.5, 2.0, 300.0, 1.0);
+
+        assert!(lossy.r_factor < good.r_factor);
+        assert!(bursty.r_factor < lossy.r_factor);
+        assert!(slow.r_factor < good.r_factor);
+        assert!(lossy.mos < good.mos);
+    }
+
+    #[test]
+    fn test_unusable_path_is_clamped() {
+        let q = VoipQuality::estimate(100.0, 500.0, 1000.0, 1.0);
+        assert_eq!(q.r_factor, 0.0);
+        assert_eq!(q.mos, 1.0);
+    }
+}